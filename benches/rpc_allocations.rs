@@ -0,0 +1,106 @@
+// Allocation benchmark for typical RPC payloads
+//
+// Counts heap allocations and measures the time per operation for encoding
+// and decoding small request/response objects, which is where the per-container
+// allocations of `HtlvItem` trees dominate. Run with
+// `cargo bench --bench rpc_allocations`.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::hint::black_box;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Instant;
+
+use bytes::Bytes;
+use tonitru::codec::decode::{decode_item, decode_item_ref};
+use tonitru::codec::encode::encode_item;
+use tonitru::codec::types::{HtlvItem, HtlvValue};
+
+/// Global allocator counting allocations and reallocations
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.realloc(ptr, layout, new_size)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+const ITERATIONS: usize = 20_000;
+
+/// A request with a method name, an ID and a small parameter object
+fn request() -> HtlvItem {
+    HtlvItem::new(1, HtlvValue::Object(vec![
+        HtlvItem::new(1, HtlvValue::String(Bytes::from_static(b"account.get"))),
+        HtlvItem::new(2, HtlvValue::U64(981_234)),
+        HtlvItem::new(3, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String(Bytes::from_static(b"acct-0042"))),
+            HtlvItem::new(2, HtlvValue::Bool(true)),
+            HtlvItem::new(3, HtlvValue::U8(3)),
+        ])),
+    ]))
+}
+
+/// A response with a status, a record of six fields and a short list of tags
+fn response() -> HtlvItem {
+    HtlvItem::new(1, HtlvValue::Object(vec![
+        HtlvItem::new(1, HtlvValue::U8(0)),
+        HtlvItem::new(2, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String(Bytes::from_static(b"acct-0042"))),
+            HtlvItem::new(2, HtlvValue::String(Bytes::from_static(b"Ada Lovelace"))),
+            HtlvItem::new(3, HtlvValue::I64(-1_250)),
+            HtlvItem::new(4, HtlvValue::Bool(false)),
+            HtlvItem::new(5, HtlvValue::U32(1_700_000_000)),
+            HtlvItem::new(6, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::String(Bytes::from_static(b"gold"))),
+                HtlvItem::new(0, HtlvValue::String(Bytes::from_static(b"eu"))),
+            ])),
+        ])),
+    ]))
+}
+
+/// Runs `op` repeatedly and prints allocations and nanoseconds per call
+fn bench(name: &str, mut op: impl FnMut()) {
+    op(); // Warm up
+    let allocations_before = ALLOCATIONS.load(Ordering::Relaxed);
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        op();
+    }
+    let elapsed = start.elapsed();
+    let allocations = ALLOCATIONS.load(Ordering::Relaxed) - allocations_before;
+    println!(
+        "{:<24} {:>8.1} allocations/op {:>10.0} ns/op",
+        name,
+        allocations as f64 / ITERATIONS as f64,
+        elapsed.as_nanos() as f64 / ITERATIONS as f64
+    );
+}
+
+fn main() {
+    for (name, item) in [("request", request()), ("response", response())] {
+        let encoded = encode_item(&item).unwrap();
+        bench(&format!("encode_item {}", name), || {
+            black_box(encode_item(black_box(&item)).unwrap());
+        });
+        bench(&format!("decode_item {}", name), || {
+            black_box(decode_item(black_box(&encoded)).unwrap());
+        });
+        bench(&format!("decode_item_ref {}", name), || {
+            black_box(decode_item_ref(black_box(&encoded)).unwrap());
+        });
+    }
+}
@@ -0,0 +1,654 @@
+// Interop protocol shared by the interop_server and interop_client examples
+//
+// The QUIC transport is not implemented yet, so the examples run the transport
+// stack over TCP: frames are written and read with `PacketWriter` and
+// `PacketReader`, schemas are exchanged with the schema synchronization control
+// packets, and data packets are sealed with a `PipelineBuilder`. A connection
+// goes through:
+//
+//   Hello        client -> server: X25519 public key, and the id of a session
+//                to resume (0 for a new session)
+//   Welcome      server -> client: X25519 public key and session id, and
+//                whether the session was resumed
+//   schema sync  the client advertises and the server requests and receives
+//                what it lacks, then the other way around
+//   data         Echo messages compressed with zstd and encrypted with
+//                AES-256-GCM under the session key, which the server returns
+//                resealed; the client closes the stream when done
+//
+// An Echo whose payload exceeds `FRAGMENT_SIZE` is split into Fragment
+// messages, each sealed on its own, and reassembled by the receiver. Frames
+// are limited to `MAX_FRAME_SIZE`, so large payloads only get through
+// fragmented. Fragments are no larger than the large field threshold: the
+// encoder shards longer Bytes values, and a sharded field nested in a message
+// object decodes as its header followed by its shards.
+//
+// The server keeps the key of every session. A client reconnecting with the id
+// of its session resumes it: the server skips the key exchange, and as it kept
+// the schemas received before, synchronization transfers no definitions.
+
+#![allow(dead_code)] // Each example uses its own side of the protocol
+
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use hkdf::Hkdf;
+use rand_core::RngCore;
+use sha2::Sha256;
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::{TcpListener, TcpStream};
+use x25519_dalek::{EphemeralSecret, PublicKey};
+
+use tonitru::codec::decode::decode_item;
+use tonitru::codec::encode::encode_item;
+use tonitru::codec::types::{HtlvItem, HtlvValue};
+use tonitru::codec::varint::encode_varint_into;
+use tonitru::compress::CompressionStrategy;
+use tonitru::config::DEFAULT_LARGE_FIELD_THRESHOLD;
+use tonitru::encrypt::aes_gcm::AesGcmEncryptor;
+use tonitru::encrypt::rng::SecureRng;
+use tonitru::encrypt::EncryptionStrategy;
+use tonitru::internal::cursor::WireCursor;
+use tonitru::internal::error::{Error, Result};
+use tonitru::internal::framing::{FramingConfig, PacketReader, PacketWriter};
+use tonitru::internal::packet::{DataBody, MetadataHeader, Packet};
+use tonitru::pipeline::PipelineBuilder;
+use tonitru::protocol::schema_sync::{SchemaSet, SchemaSync, SchemaSyncMessage};
+
+/// Address the examples use when none is given
+pub const DEFAULT_ADDR: &str = "127.0.0.1:7461";
+
+/// Schema ID of the handshake control packets
+pub const HANDSHAKE_SCHEMA_ID: u64 = 0x4845_4C4F; // "HELO"
+/// Schema ID of the sealed data packets
+pub const DATA_SCHEMA_ID: u64 = 0x4543_484F; // "ECHO"
+
+/// Largest frame either side accepts
+pub const MAX_FRAME_SIZE: usize = 4 * 1024;
+/// Largest payload sent without fragmentation, and size of the fragments
+pub const FRAGMENT_SIZE: usize = DEFAULT_LARGE_FIELD_THRESHOLD;
+/// Largest payload reassembled from fragments
+pub const MAX_PAYLOAD_SIZE: usize = 4 * 1024 * 1024;
+
+/// HKDF salt of the session key
+const SESSION_KEY_SALT: &[u8] = b"Tonitru interop v1";
+
+// Message kinds
+const KIND_HELLO: u8 = 0;
+const KIND_WELCOME: u8 = 1;
+const KIND_ECHO: u8 = 2;
+const KIND_FRAGMENT: u8 = 3;
+
+// Tags used for the fields of a message object
+const TAG_KIND: u64 = 1;
+const TAG_FIELDS: u64 = 2;
+const TAG_DATA: u64 = 3;
+// Root tag of a message object
+const TAG_MESSAGE: u64 = 1;
+
+/// A message of the interop protocol
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    /// Opens a connection, resuming the session `resume` if not 0
+    Hello { public_key: [u8; 32], resume: u64 },
+    /// Accepts a connection into a new or resumed session
+    Welcome { public_key: [u8; 32], session: u64, resumed: bool },
+    /// Data the server returns to the client
+    Echo { sequence: u64, payload: Bytes },
+    /// Part `index` of the `count` parts of the payload of Echo `sequence`
+    Fragment { sequence: u64, index: u64, count: u64, chunk: Bytes },
+}
+
+impl Message {
+    /// Converts the message into an HTLV object item.
+    pub fn to_htlv_item(&self) -> HtlvItem {
+        let mut fields = Vec::new();
+        let (kind, data) = match self {
+            Message::Hello { public_key, resume } => {
+                encode_varint_into(*resume, &mut fields);
+                (KIND_HELLO, Bytes::copy_from_slice(public_key))
+            }
+            Message::Welcome { public_key, session, resumed } => {
+                encode_varint_into(*session, &mut fields);
+                encode_varint_into(*resumed as u64, &mut fields);
+                (KIND_WELCOME, Bytes::copy_from_slice(public_key))
+            }
+            Message::Echo { sequence, payload } => {
+                encode_varint_into(*sequence, &mut fields);
+                (KIND_ECHO, payload.clone())
+            }
+            Message::Fragment { sequence, index, count, chunk } => {
+                encode_varint_into(*sequence, &mut fields);
+                encode_varint_into(*index, &mut fields);
+                encode_varint_into(*count, &mut fields);
+                (KIND_FRAGMENT, chunk.clone())
+            }
+        };
+        HtlvItem::new(TAG_MESSAGE, HtlvValue::Object(vec![
+            HtlvItem::new(TAG_KIND, HtlvValue::U8(kind)),
+            HtlvItem::new(TAG_FIELDS, HtlvValue::Bytes(Bytes::from(fields))),
+            HtlvItem::new(TAG_DATA, HtlvValue::Bytes(data)),
+        ]))
+    }
+
+    /// Parses a message from an HTLV object item produced by `to_htlv_item`.
+    pub fn from_htlv_item(item: &HtlvItem) -> Result<Self> {
+        let HtlvValue::Object(fields) = &item.value else {
+            return Err(Error::ProtocolError(format!(
+                "Interop message must be an Object, got {:?}", item.value.value_type()
+            )));
+        };
+        let field = |tag: u64| {
+            fields.iter().find(|field| field.tag == tag).map(|field| &field.value).ok_or_else(|| {
+                Error::ProtocolError(format!("Interop message missing field tag {}", tag))
+            })
+        };
+        let (HtlvValue::U8(kind), HtlvValue::Bytes(numbers), HtlvValue::Bytes(data)) =
+            (field(TAG_KIND)?, field(TAG_FIELDS)?, field(TAG_DATA)?)
+        else {
+            return Err(Error::ProtocolError("Interop message fields have the wrong types".to_string()));
+        };
+        let mut cursor = WireCursor::new(numbers);
+        let public_key = || {
+            <[u8; 32]>::try_from(data.as_ref())
+                .map_err(|_| Error::ProtocolError(format!("Invalid public key of {} bytes", data.len())))
+        };
+
+        let message = match *kind {
+            KIND_HELLO => Message::Hello { public_key: public_key()?, resume: cursor.read_varint("Session")? },
+            KIND_WELCOME => Message::Welcome {
+                public_key: public_key()?,
+                session: cursor.read_varint("Session")?,
+                resumed: cursor.read_varint("Resumed flag")? != 0,
+            },
+            KIND_ECHO => Message::Echo { sequence: cursor.read_varint("Sequence")?, payload: data.clone() },
+            KIND_FRAGMENT => Message::Fragment {
+                sequence: cursor.read_varint("Sequence")?,
+                index: cursor.read_varint("Fragment index")?,
+                count: cursor.read_varint("Fragment count")?,
+                chunk: data.clone(),
+            },
+            kind => return Err(Error::ProtocolError(format!("Unknown interop message kind: {}", kind))),
+        };
+        if !cursor.is_empty() {
+            return Err(Error::ProtocolError(format!("{} unexpected bytes in interop message", cursor.remaining())));
+        }
+        Ok(message)
+    }
+}
+
+/// What a connection has carried, for the conformance checks
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ConnectionStats {
+    /// Data packets sent
+    pub packets_sent: u64,
+    /// Data packets received
+    pub packets_received: u64,
+    /// Fragments received
+    pub fragments_received: u64,
+    /// Data packets received with a compressed body
+    pub compressed_received: u64,
+}
+
+/// One end of a connection
+pub struct Connection {
+    reader: PacketReader<OwnedReadHalf>,
+    writer: PacketWriter<OwnedWriteHalf>,
+    /// Seals and opens data packets once the session key is known
+    pipeline: Option<PipelineBuilder>,
+    /// Fragments received by Echo sequence, until all parts arrived
+    partial: HashMap<u64, Vec<Option<Bytes>>>,
+    stats: ConnectionStats,
+}
+
+impl Connection {
+    /// Wraps a connected stream.
+    pub fn new(stream: TcpStream) -> Self {
+        let config = FramingConfig { max_frame_size: MAX_FRAME_SIZE, ..FramingConfig::default() };
+        let (read, write) = stream.into_split();
+        Connection {
+            reader: PacketReader::with_config(read, config.clone()),
+            writer: PacketWriter::with_config(write, config),
+            pipeline: None,
+            partial: HashMap::new(),
+            stats: ConnectionStats::default(),
+        }
+    }
+
+    /// Returns what the connection has carried so far.
+    pub fn stats(&self) -> ConnectionStats {
+        self.stats
+    }
+
+    /// Seals data packets with zstd and AES-256-GCM under the session key.
+    pub fn set_session_key(&mut self, key: &[u8; 32]) -> Result<()> {
+        let pipeline = PipelineBuilder::new(DATA_SCHEMA_ID)
+            .compress(CompressionStrategy::Zstd)
+            .encrypt(AesGcmEncryptor::with_key(key)?, None);
+        self.pipeline = Some(pipeline);
+        Ok(())
+    }
+
+    /// Sends a handshake message in the clear.
+    pub async fn send_handshake(&mut self, message: &Message) -> Result<()> {
+        let header = MetadataHeader {
+            schema_id: HANDSHAKE_SCHEMA_ID,
+            timestamp: 0,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: 0, // Will be set by build_packet
+            key_id: None,
+        };
+        let packet = Packet::build_packet(header, DataBody::Raw(encode_item(&message.to_htlv_item())?))?;
+        self.write(&packet).await
+    }
+
+    /// Receives a handshake message, or `None` if the peer closed the stream.
+    pub async fn receive_handshake(&mut self) -> Result<Option<Message>> {
+        let Some(packet) = self.reader.read_packet().await? else {
+            return Ok(None);
+        };
+        let DataBody::Raw(data) = &packet.body else {
+            return Err(Error::ProtocolError("Handshake packet body must be Raw".to_string()));
+        };
+        if packet.header.schema_id != HANDSHAKE_SCHEMA_ID {
+            return Err(Error::ProtocolError(format!(
+                "Expected a handshake packet, got schema_id {}", packet.header.schema_id
+            )));
+        }
+        Message::from_htlv_item(&decode_item(data)?.0).map(Some)
+    }
+
+    /// Sends a schema synchronization message.
+    pub async fn send_sync(&mut self, message: &SchemaSyncMessage) -> Result<()> {
+        self.write(&message.to_control_packet(0)?).await
+    }
+
+    /// Receives a schema synchronization message.
+    pub async fn receive_sync(&mut self) -> Result<SchemaSyncMessage> {
+        let packet = self.reader.read_packet().await?.ok_or_else(|| closed("schema synchronization"))?;
+        SchemaSyncMessage::from_control_packet(&packet)
+    }
+
+    /// Seals a message into data packets, fragmenting an Echo with a large
+    /// payload.
+    pub async fn send(&mut self, message: &Message) -> Result<()> {
+        let Message::Echo { sequence, payload } = message else {
+            return self.send_sealed(message).await;
+        };
+        if payload.len() <= FRAGMENT_SIZE {
+            return self.send_sealed(message).await;
+        }
+        let count = payload.len().div_ceil(FRAGMENT_SIZE) as u64;
+        for (index, chunk) in payload.chunks(FRAGMENT_SIZE).enumerate() {
+            let chunk = payload.slice_ref(chunk);
+            self.send_sealed(&Message::Fragment { sequence: *sequence, index: index as u64, count, chunk }).await?;
+        }
+        Ok(())
+    }
+
+    /// Receives the next message from data packets, reassembling fragmented
+    /// ones, or `None` if the peer closed the stream.
+    pub async fn receive(&mut self) -> Result<Option<Message>> {
+        loop {
+            let Some(packet) = self.reader.read_packet().await? else {
+                if !self.partial.is_empty() {
+                    return Err(Error::ProtocolError("Stream ended with fragmented messages incomplete".to_string()));
+                }
+                return Ok(None);
+            };
+            if packet.header.get_encryption_strategy()? != EncryptionStrategy::AesGcm {
+                return Err(Error::ProtocolError("Data packet is not encrypted with AES-GCM".to_string()));
+            }
+            let item = self.pipeline()?.open_packet(&packet)?;
+            self.stats.packets_received += 1;
+            if packet.header.get_compression_strategy()? != CompressionStrategy::NoCompression {
+                self.stats.compressed_received += 1;
+            }
+
+            match Message::from_htlv_item(&item)? {
+                Message::Fragment { sequence, index, count, chunk } => {
+                    self.stats.fragments_received += 1;
+                    if let Some(message) = self.reassemble(sequence, index, count, chunk)? {
+                        return Ok(Some(message));
+                    }
+                }
+                message => return Ok(Some(message)),
+            }
+        }
+    }
+
+    /// Flushes and closes the sending side of the stream.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.writer.shutdown().await
+    }
+
+    /// Adds a fragment, returning the Echo once all of its parts arrived.
+    fn reassemble(&mut self, sequence: u64, index: u64, count: u64, chunk: Bytes) -> Result<Option<Message>> {
+        let max_count = MAX_PAYLOAD_SIZE.div_ceil(FRAGMENT_SIZE) as u64;
+        if count == 0 || count > max_count || index >= count || chunk.len() > FRAGMENT_SIZE {
+            return Err(Error::ProtocolError(format!("Invalid fragment {} of {} of Echo {}", index, count, sequence)));
+        }
+        let parts = self.partial.entry(sequence).or_insert_with(|| vec![None; count as usize]);
+        if parts.len() as u64 != count {
+            return Err(Error::ProtocolError(format!("Fragments of Echo {} disagree on their count", sequence)));
+        }
+        parts[index as usize] = Some(chunk);
+        if parts.iter().any(Option::is_none) {
+            return Ok(None);
+        }
+
+        let parts = self.partial.remove(&sequence).unwrap_or_default();
+        let payload: Vec<u8> = parts.into_iter().flatten().flat_map(|part| part.to_vec()).collect();
+        Ok(Some(Message::Echo { sequence, payload: Bytes::from(payload) }))
+    }
+
+    async fn send_sealed(&mut self, message: &Message) -> Result<()> {
+        let packet = self.pipeline()?.seal(&message.to_htlv_item())?;
+        self.write(&packet).await?;
+        self.stats.packets_sent += 1;
+        Ok(())
+    }
+
+    async fn write(&mut self, packet: &Packet) -> Result<()> {
+        self.writer.write_packet(packet).await?;
+        self.writer.flush().await
+    }
+
+    fn pipeline(&self) -> Result<&PipelineBuilder> {
+        self.pipeline
+            .as_ref()
+            .ok_or_else(|| Error::ProtocolError("Data packet before the handshake completed".to_string()))
+    }
+}
+
+/// Ephemeral X25519 key pair of one handshake
+pub struct KeyExchange {
+    secret: EphemeralSecret,
+    public_key: [u8; 32],
+}
+
+impl KeyExchange {
+    /// Generates a key pair.
+    pub fn new() -> Self {
+        let secret = EphemeralSecret::random_from_rng(SecureRng);
+        let public_key = PublicKey::from(&secret).to_bytes();
+        KeyExchange { secret, public_key }
+    }
+
+    /// Returns the public key to send to the peer.
+    pub fn public_key(&self) -> [u8; 32] {
+        self.public_key
+    }
+
+    /// Derives the key of the session from the peer's public key.
+    pub fn session_key(self, peer: [u8; 32], session: u64) -> Result<[u8; 32]> {
+        let shared = self.secret.diffie_hellman(&PublicKey::from(peer));
+        if !shared.was_contributory() {
+            return Err(Error::ProtocolError("Peer sent a low order public key".to_string()));
+        }
+        let mut key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(SESSION_KEY_SALT), shared.as_bytes())
+            .expand(&session.to_le_bytes(), &mut key)
+            .map_err(|_| Error::EncryptionError("Failed to derive the session key".to_string()))?;
+        Ok(key)
+    }
+}
+
+/// Serves interop clients, keeping their sessions and schemas across
+/// connections.
+pub struct InteropServer {
+    listener: TcpListener,
+    /// Session keys by session id
+    sessions: HashMap<u64, [u8; 32]>,
+    schemas: SchemaSet,
+}
+
+impl InteropServer {
+    /// Creates a server offering the schemas on the listener.
+    pub fn new(listener: TcpListener, schemas: SchemaSet) -> Self {
+        InteropServer { listener, sessions: HashMap::new(), schemas }
+    }
+
+    /// Returns the schemas the server holds, including those received.
+    pub fn schemas(&self) -> &SchemaSet {
+        &self.schemas
+    }
+
+    /// Serves connections one after another, stopping after `connections` of
+    /// them if given. A failed connection is reported and does not stop the
+    /// server.
+    pub async fn serve(&mut self, connections: Option<usize>) -> Result<()> {
+        let mut served = 0;
+        while connections.is_none_or(|limit| served < limit) {
+            let (stream, peer) = self.listener.accept().await?;
+            served += 1;
+            match self.handle(Connection::new(stream)).await {
+                Ok(stats) => println!("{}: closed after echoing {} packets", peer, stats.packets_sent),
+                Err(e) => eprintln!("{}: {}", peer, e),
+            }
+        }
+        Ok(())
+    }
+
+    /// Runs the server side of the protocol on one connection.
+    async fn handle(&mut self, mut connection: Connection) -> Result<ConnectionStats> {
+        // Handshake
+        let Some(Message::Hello { public_key: client_key, resume }) = connection.receive_handshake().await? else {
+            return Err(Error::ProtocolError("Expected a Hello".to_string()));
+        };
+        let exchange = KeyExchange::new();
+        let welcome = match self.sessions.get(&resume) {
+            Some(key) => {
+                connection.set_session_key(key)?;
+                Message::Welcome { public_key: exchange.public_key(), session: resume, resumed: true }
+            }
+            None => {
+                let session = SecureRng.next_u64().max(1);
+                let public_key = exchange.public_key();
+                let key = exchange.session_key(client_key, session)?;
+                connection.set_session_key(&key)?;
+                self.sessions.insert(session, key);
+                Message::Welcome { public_key, session, resumed: false }
+            }
+        };
+        connection.send_handshake(&welcome).await?;
+
+        // Schema synchronization: the client advertises first
+        let mut sync = SchemaSync::new(self.schemas.clone());
+        let advertisement = connection.receive_sync().await?;
+        let request = sync.handle(&advertisement)?.unwrap_or(SchemaSyncMessage::Request(Vec::new()));
+        connection.send_sync(&request).await?;
+        sync.handle(&connection.receive_sync().await?)?;
+        connection.send_sync(&sync.advertise()).await?;
+        let request = connection.receive_sync().await?;
+        let definitions = sync.handle(&request)?.unwrap_or(SchemaSyncMessage::Definitions(Vec::new()));
+        connection.send_sync(&definitions).await?;
+        self.schemas = sync.into_local();
+
+        // Echo data until the client closes the stream
+        while let Some(message) = connection.receive().await? {
+            let Message::Echo { .. } = message else {
+                return Err(Error::ProtocolError(format!("Expected an Echo, got {:?}", message)));
+            };
+            connection.send(&message).await?;
+        }
+        connection.shutdown().await?;
+        Ok(connection.stats())
+    }
+}
+
+/// Returns the JSON document of a schema with one string field.
+pub fn schema_document(id: &str, field: &str) -> Vec<u8> {
+    format!(
+        r#"{{"id": "{}", "name": "{}", "version": "1.0.0", "type": "object",
+            "properties": {{"{}": {{"type": "string", "tag": 1}}}}}}"#,
+        id, id, field
+    )
+    .into_bytes()
+}
+
+/// Returns the schemas the server offers.
+pub fn server_schemas() -> Result<SchemaSet> {
+    let mut schemas = SchemaSet::new();
+    schemas.insert(schema_document("interop.echo", "payload"))?;
+    schemas.insert(schema_document("interop.telemetry", "reading"))?;
+    Ok(schemas)
+}
+
+/// Returns the schemas the client offers.
+pub fn client_schemas() -> Result<SchemaSet> {
+    let mut schemas = SchemaSet::new();
+    schemas.insert(schema_document("interop.echo", "payload"))?;
+    schemas.insert(schema_document("interop.commands", "command"))?;
+    Ok(schemas)
+}
+
+/// Runs the client side of the conformance suite against a server, printing
+/// the result of each check. Fails with the first check that does not pass.
+pub async fn run_conformance(addr: &str) -> Result<()> {
+    // Handshake, schema exchange and data on a new session
+    let mut connection = Connection::new(TcpStream::connect(addr).await?);
+    let session = check("handshake", handshake(&mut connection, None).await.and_then(|(session, resumed)| {
+        expect(!resumed, "the server resumed a session that was not asked for")?;
+        Ok(session)
+    }))?;
+
+    let mut schemas = SchemaSync::new(client_schemas()?);
+    check("schema exchange", async {
+        let received = synchronize(&mut connection, &mut schemas).await?;
+        expect_schemas(&schemas, received, 1)
+    }.await)?;
+    check("compressed+encrypted packets", echo_compressible(&mut connection).await)?;
+    check("fragmentation", echo_fragmented(&mut connection).await)?;
+    connection.shutdown().await?;
+    expect_closed(&mut connection).await?;
+
+    // Reconnect and resume the session: the schemas are known on both sides
+    let mut connection = Connection::new(TcpStream::connect(addr).await?);
+    check("reconnect", async {
+        let (resumed_session, resumed) = handshake(&mut connection, Some(session)).await?;
+        expect(resumed && resumed_session.id == session.id, "the server did not resume the session")?;
+        let received = synchronize(&mut connection, &mut schemas).await?;
+        expect_schemas(&schemas, received, 0)?;
+        echo_compressible(&mut connection).await
+    }.await)?;
+    connection.shutdown().await?;
+    expect_closed(&mut connection).await
+}
+
+/// Prints the outcome of a check, naming it in the error on failure.
+fn check<T>(name: &str, result: Result<T>) -> Result<T> {
+    match result {
+        Ok(value) => {
+            println!("ok    {}", name);
+            Ok(value)
+        }
+        Err(e) => {
+            println!("FAIL  {}: {}", name, e);
+            Err(Error::ProtocolError(format!("Conformance check '{}' failed: {}", name, e)))
+        }
+    }
+}
+
+fn expect(condition: bool, message: &str) -> Result<()> {
+    if condition {
+        Ok(())
+    } else {
+        Err(Error::ProtocolError(message.to_string()))
+    }
+}
+
+fn closed(stage: &str) -> Error {
+    Error::ProtocolError(format!("Peer closed the stream during {}", stage))
+}
+
+/// Id and key of a session, as kept by the client to resume it
+#[derive(Clone, Copy)]
+struct Session {
+    id: u64,
+    key: [u8; 32],
+}
+
+/// Opens a session, or resumes the given one, returning the session and
+/// whether it was resumed.
+async fn handshake(connection: &mut Connection, resume: Option<Session>) -> Result<(Session, bool)> {
+    let exchange = KeyExchange::new();
+    let hello = Message::Hello { public_key: exchange.public_key(), resume: resume.map_or(0, |session| session.id) };
+    connection.send_handshake(&hello).await?;
+    let Some(Message::Welcome { public_key, session: id, resumed }) = connection.receive_handshake().await? else {
+        return Err(Error::ProtocolError("Expected a Welcome".to_string()));
+    };
+    let session = match resume {
+        Some(session) if resumed => session,
+        _ if resumed => return Err(Error::ProtocolError(format!("Server resumed unknown session {}", id))),
+        _ => Session { id, key: exchange.session_key(public_key, id)? },
+    };
+    connection.set_session_key(&session.key)?;
+    Ok((session, resumed))
+}
+
+/// Runs the client side of schema synchronization, returning the number of
+/// definitions received.
+async fn synchronize(connection: &mut Connection, sync: &mut SchemaSync) -> Result<usize> {
+    connection.send_sync(&sync.advertise()).await?;
+    let request = connection.receive_sync().await?;
+    let definitions = sync.handle(&request)?.unwrap_or(SchemaSyncMessage::Definitions(Vec::new()));
+    connection.send_sync(&definitions).await?;
+
+    let advertisement = connection.receive_sync().await?;
+    let request = sync.handle(&advertisement)?.unwrap_or(SchemaSyncMessage::Request(Vec::new()));
+    connection.send_sync(&request).await?;
+    let definitions = connection.receive_sync().await?;
+    let SchemaSyncMessage::Definitions(documents) = &definitions else {
+        return Err(Error::ProtocolError("Expected schema definitions".to_string()));
+    };
+    let received = documents.len();
+    sync.handle(&definitions)?;
+    Ok(received)
+}
+
+fn expect_schemas(sync: &SchemaSync, received: usize, expected: usize) -> Result<()> {
+    expect(received == expected, &format!("received {} schema definitions, expected {}", received, expected))?;
+    expect(sync.pending().is_empty() && sync.conflicts().is_empty(), "schemas are pending or conflicting")?;
+    let ids: Vec<_> = sync.local().infos().into_iter().map(|info| info.id).collect();
+    expect(
+        ["interop.commands", "interop.echo", "interop.telemetry"].iter().all(|id| ids.iter().any(|held| held == id)),
+        &format!("holds schemas {:?} instead of the union of both peers", ids),
+    )
+}
+
+/// Sends a compressible payload and checks that it comes back intact in a
+/// compressed and encrypted packet.
+async fn echo_compressible(connection: &mut Connection) -> Result<()> {
+    let payload = Bytes::from("sensor=42 status=nominal ".repeat(40));
+    let before = connection.stats();
+    echo(connection, Message::Echo { sequence: before.packets_sent, payload }).await?;
+    let after = connection.stats();
+    expect(after.compressed_received > before.compressed_received, "the echo was not compressed")
+}
+
+/// Sends a payload too large for one frame and checks that it travels in
+/// fragments both ways.
+async fn echo_fragmented(connection: &mut Connection) -> Result<()> {
+    // Random, so that compression cannot shrink it into a single frame
+    let mut payload = vec![0u8; 3 * MAX_FRAME_SIZE + 100];
+    SecureRng.fill_bytes(&mut payload);
+    let before = connection.stats();
+    echo(connection, Message::Echo { sequence: before.packets_sent, payload: Bytes::from(payload) }).await?;
+    let fragments = connection.stats().fragments_received - before.fragments_received;
+    expect(fragments > 1, &format!("the echo arrived in {} fragments", fragments))
+}
+
+async fn echo(connection: &mut Connection, message: Message) -> Result<()> {
+    connection.send(&message).await?;
+    let reply = connection.receive().await?.ok_or_else(|| closed("the echo"))?;
+    expect(reply == message, "the echo differs from the message sent")
+}
+
+async fn expect_closed(connection: &mut Connection) -> Result<()> {
+    match connection.receive().await? {
+        None => Ok(()),
+        Some(message) => Err(Error::ProtocolError(format!("Unexpected message after closing: {:?}", message))),
+    }
+}
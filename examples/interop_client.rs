@@ -0,0 +1,21 @@
+// Interop test client
+//
+// Runs the conformance suite of `interop/mod.rs` against an interop server on
+// the given address (default 127.0.0.1:7461): handshake, schema exchange,
+// compressed and encrypted packets, fragmentation, and reconnecting to resume
+// the session. Each check is printed as it completes, and the client exits
+// with an error at the first one that fails. Run with
+// `cargo run --example interop_client -- [ADDR]` against a running
+// `interop_server`.
+
+mod interop;
+
+use tonitru::internal::error::Result;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let addr = std::env::args().nth(1).unwrap_or_else(|| interop::DEFAULT_ADDR.to_string());
+    interop::run_conformance(&addr).await?;
+    println!("all conformance checks passed against {}", addr);
+    Ok(())
+}
@@ -0,0 +1,29 @@
+// Interop test server
+//
+// Accepts interop clients on the given address (default 127.0.0.1:7461) and
+// runs the server side of the protocol in `interop/mod.rs` with each: it
+// completes the handshake, exchanges schemas and echoes data packets back.
+// Sessions and the schemas received are kept across connections, so clients
+// can reconnect and resume. Run with
+// `cargo run --example interop_server -- [ADDR] [CONNECTIONS]`, the server
+// stopping after CONNECTIONS connections if given.
+
+mod interop;
+
+use tokio::net::TcpListener;
+use tonitru::internal::error::{Error, Result};
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> Result<()> {
+    let mut args = std::env::args().skip(1);
+    let addr = args.next().unwrap_or_else(|| interop::DEFAULT_ADDR.to_string());
+    let connections = match args.next() {
+        Some(count) => Some(count.parse().map_err(|_| Error::ConfigError(format!("Invalid connection count: {}", count)))?),
+        None => None,
+    };
+
+    let listener = TcpListener::bind(&addr).await?;
+    println!("interop server listening on {}", listener.local_addr()?);
+    let mut server = interop::InteropServer::new(listener, interop::server_schemas()?);
+    server.serve(connections).await
+}
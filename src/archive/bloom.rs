@@ -0,0 +1,110 @@
+// Bloom filters for archive indexes
+//
+// Each archive segment carries a bloom filter over the values of its indexed
+// fields, so a reader can skip segments that cannot hold a value without
+// reading them. Bit positions come from a BLAKE3 hash of the key with double
+// hashing, which keeps filters deterministic and portable between writers.
+
+use crate::internal::error::{Error, Result};
+use crate::archive::ByteReader;
+use crate::codec::varint::encode_varint;
+
+/// Largest number of hash functions a filter may use
+const MAX_HASHES: u32 = 16;
+
+/// A bloom filter over byte keys
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    bits: Vec<u8>,
+    num_hashes: u32,
+}
+
+impl BloomFilter {
+    /// Creates a filter sized for `expected_items` keys at the given false
+    /// positive rate
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let items = expected_items.max(1) as f64;
+        let rate = false_positive_rate.clamp(1e-9, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+
+        let num_bits = (-items * rate.ln() / (ln2 * ln2)).ceil().max(8.0) as usize;
+        let num_hashes = ((num_bits as f64 / items) * ln2).round().clamp(1.0, MAX_HASHES as f64) as u32;
+
+        Self {
+            bits: vec![0; num_bits.div_ceil(8)],
+            num_hashes,
+        }
+    }
+
+    /// Adds a key to the filter
+    pub fn insert(&mut self, key: &[u8]) {
+        for bit in self.bit_positions(key) {
+            self.bits[bit / 8] |= 1 << (bit % 8);
+        }
+    }
+
+    /// Returns false if the key was certainly never inserted
+    pub fn may_contain(&self, key: &[u8]) -> bool {
+        self.bit_positions(key)
+            .all(|bit| self.bits[bit / 8] & (1 << (bit % 8)) != 0)
+    }
+
+    /// Returns the size of the filter in bytes
+    pub fn size(&self) -> usize {
+        self.bits.len()
+    }
+
+    fn bit_positions(&self, key: &[u8]) -> impl Iterator<Item = usize> {
+        let hash = blake3::hash(key);
+        let bytes = hash.as_bytes();
+        let h1 = u64::from_le_bytes(bytes[0..8].try_into().unwrap());
+        // An odd step visits distinct positions for every hash function
+        let h2 = u64::from_le_bytes(bytes[8..16].try_into().unwrap()) | 1;
+        let num_bits = (self.bits.len() * 8) as u64;
+
+        (0..self.num_hashes as u64).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % num_bits) as usize)
+    }
+
+    /// Appends the encoded filter to `out`
+    pub(crate) fn encode(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&encode_varint(self.num_hashes as u64));
+        out.extend_from_slice(&encode_varint(self.bits.len() as u64));
+        out.extend_from_slice(&self.bits);
+    }
+
+    /// Decodes a filter written by `encode`
+    pub(crate) fn decode(reader: &mut ByteReader<'_>) -> Result<Self> {
+        let num_hashes = reader.read_varint()?;
+        if num_hashes == 0 || num_hashes > MAX_HASHES as u64 {
+            return Err(Error::CodecError(format!("Invalid bloom filter hash count: {}", num_hashes)));
+        }
+        let bits = reader.read_bytes()?.to_vec();
+        if bits.is_empty() {
+            return Err(Error::CodecError("Empty bloom filter".to_string()));
+        }
+        Ok(Self { bits, num_hashes: num_hashes as u32 })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(100, 0.01);
+        for i in 0..100u32 {
+            filter.insert(&i.to_le_bytes());
+        }
+        for i in 0..100u32 {
+            assert!(filter.may_contain(&i.to_le_bytes()));
+        }
+        let false_positives = (100..10_100u32).filter(|i| filter.may_contain(&i.to_le_bytes())).count();
+        assert!(false_positives < 300, "{} false positives", false_positives);
+
+        let mut encoded = Vec::new();
+        filter.encode(&mut encoded);
+        let decoded = BloomFilter::decode(&mut ByteReader::new(&encoded)).unwrap();
+        assert_eq!(decoded, filter);
+    }
+}
@@ -0,0 +1,413 @@
+// Consumer groups over a packet log
+//
+// A `LogConsumer` reads the closed partitions of a `PacketLog` in partition
+// order and remembers, per partition file, how many packets its group has
+// consumed. Reading only advances the consumer's position; `commit` makes the
+// position durable and `rollback` returns to the last commit, so a processor
+// that commits after handling a batch resumes after it.
+//
+// Commits are HTLV control records appended to `consumers/<group>.offsets` in
+// the log directory, each one a length-prefixed packet carrying the complete
+// offsets of the group. The last record whose checksum verifies wins, so a
+// commit torn by a crash falls back to the previous one. The file is rewritten
+// with only the latest record when it has grown long or has a torn tail.
+//
+// For exactly-once processing, store `position()` atomically with the output
+// of a batch and restore it with `seek` after a restart, instead of relying on
+// a separate commit.
+//
+// Compaction merges partitions into new files, which a group has not read;
+// compact only partitions every group has consumed.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use bytes::Bytes;
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::archive::{ArchiveReader, ByteReader, PacketLog};
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::varint::{decode_varint, encode_varint};
+use crate::protocol::control::{self, read_bytes, read_string, read_u64, Fields, TAG_MESSAGE};
+
+/// Schema ID used in the metadata header of consumer offset records
+pub const CONSUMER_OFFSETS_SCHEMA_ID: u64 = 0x4F46_4653; // "OFFS"
+
+/// Directory of the offset files, inside the log directory
+const CONSUMERS_DIR: &str = "consumers";
+
+/// Records an offset file may hold before it is rewritten
+const MAX_RECORDS: usize = 256;
+
+// Tags used for the fields of an offset record
+const TAG_GROUP: u64 = 1;
+const TAG_GENERATION: u64 = 2;
+const TAG_PARTITIONS: u64 = 3;
+
+/// Describes offset records in errors
+const MESSAGE_NAME: &str = "consumer offsets";
+
+/// Packets consumed by a group, per partition file
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ConsumerOffsets {
+    /// Number of packets consumed, keyed by partition file name
+    pub partitions: BTreeMap<String, u64>,
+}
+
+impl ConsumerOffsets {
+    /// Returns the number of packets consumed from the given partition file
+    pub fn consumed(&self, file_name: &str) -> u64 {
+        self.partitions.get(file_name).copied().unwrap_or(0)
+    }
+
+    /// Converts the offsets into an HTLV object item for the given group and
+    /// commit generation
+    pub fn to_htlv_item(&self, group: &str, generation: u64) -> HtlvItem {
+        let mut partitions = Vec::new();
+        for (name, consumed) in &self.partitions {
+            partitions.extend_from_slice(&encode_varint(name.len() as u64));
+            partitions.extend_from_slice(name.as_bytes());
+            partitions.extend_from_slice(&encode_varint(*consumed));
+        }
+        HtlvItem::new(TAG_MESSAGE, HtlvValue::Object(vec![
+            HtlvItem::new(TAG_GROUP, HtlvValue::String(Bytes::from(group.to_string()))),
+            HtlvItem::new(TAG_GENERATION, HtlvValue::U64(generation)),
+            HtlvItem::new(TAG_PARTITIONS, HtlvValue::Bytes(Bytes::from(partitions))),
+        ]))
+    }
+
+    /// Parses offsets from an HTLV object item produced by `to_htlv_item`.
+    /// Returns the group, the commit generation and the offsets.
+    pub fn from_htlv_item(item: &HtlvItem) -> Result<(String, u64, Self)> {
+        let fields = Fields::of(item, MESSAGE_NAME)?;
+
+        let (mut group, mut generation, mut offsets) = (None, 0, Self::default());
+        for field in fields.all() {
+            match field.tag {
+                TAG_GROUP => group = Some(read_string(field)?),
+                TAG_GENERATION => generation = read_u64(field)?,
+                TAG_PARTITIONS => {
+                    let mut reader = ByteReader::new(read_bytes(field)?);
+                    while !reader.is_empty() {
+                        let name = String::from_utf8(reader.read_bytes()?.to_vec())
+                            .map_err(|_| Error::ProtocolError("Invalid partition name in consumer offsets".to_string()))?;
+                        offsets.partitions.insert(name, reader.read_varint()?);
+                    }
+                }
+                // Ignore unknown fields for forward compatibility
+                _ => {}
+            }
+        }
+        let group = group.ok_or_else(|| Error::ProtocolError("Consumer offsets missing group".to_string()))?;
+        Ok((group, generation, offsets))
+    }
+
+    /// Builds an HTLV control packet carrying these offsets
+    pub fn to_control_packet(&self, group: &str, generation: u64, timestamp: u64) -> Result<Packet> {
+        control::to_control_packet(CONSUMER_OFFSETS_SCHEMA_ID, timestamp, &self.to_htlv_item(group, generation))
+    }
+
+    /// Parses offsets from a control packet built by `to_control_packet`.
+    /// Returns the group, the commit generation and the offsets.
+    pub fn from_control_packet(packet: &Packet) -> Result<(String, u64, Self)> {
+        Self::from_htlv_item(&control::from_control_packet(packet, CONSUMER_OFFSETS_SCHEMA_ID, MESSAGE_NAME)?)
+    }
+}
+
+/// A member of a consumer group reading a packet log
+#[derive(Debug)]
+pub struct LogConsumer {
+    group: String,
+    path: PathBuf,
+    generation: u64,
+    records: usize,
+    committed: ConsumerOffsets,
+    position: ConsumerOffsets,
+}
+
+impl LogConsumer {
+    /// Opens the consumer of the given group over the log in `dir`, resuming
+    /// from the group's last commit
+    pub fn open(dir: impl AsRef<Path>, group: &str) -> Result<Self> {
+        if group.is_empty() || !group.chars().all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c)) || group.starts_with('.') {
+            return Err(Error::CodecError(format!("Invalid consumer group name '{}'", group)));
+        }
+        let consumers = dir.as_ref().join(CONSUMERS_DIR);
+        fs::create_dir_all(&consumers)?;
+        let path = consumers.join(format!("{}.offsets", group));
+
+        let mut consumer = Self {
+            group: group.to_string(),
+            path,
+            generation: 0,
+            records: 0,
+            committed: ConsumerOffsets::default(),
+            position: ConsumerOffsets::default(),
+        };
+        if consumer.path.exists() {
+            let data = fs::read(&consumer.path)?;
+            let (latest, records, intact) = read_records(&data);
+            if let Some((generation, offsets)) = latest {
+                consumer.generation = generation;
+                consumer.committed = offsets.clone();
+                consumer.position = offsets;
+            }
+            consumer.records = records;
+            if !intact || records > MAX_RECORDS {
+                let record = consumer.record(&consumer.committed, consumer.generation)?;
+                consumer.rewrite(&record)?;
+            }
+        }
+        Ok(consumer)
+    }
+
+    /// Returns the name of the group
+    pub fn group(&self) -> &str {
+        &self.group
+    }
+
+    /// Returns the offsets read up to, including uncommitted reads
+    pub fn position(&self) -> &ConsumerOffsets {
+        &self.position
+    }
+
+    /// Returns the offsets of the last commit
+    pub fn committed(&self) -> &ConsumerOffsets {
+        &self.committed
+    }
+
+    /// Reads up to `max` packets following the current position from the
+    /// closed partitions of `log`
+    ///
+    /// Offsets of partition files that no longer exist, e.g. after retention,
+    /// are dropped.
+    pub fn poll(&mut self, log: &PacketLog, max: usize) -> Result<Vec<Packet>> {
+        let partitions = log.partitions()?;
+        let names: Vec<String> = partitions.iter().map(|p| file_name(&p.path)).collect();
+        self.position.partitions.retain(|name, _| names.contains(name));
+
+        let mut packets = Vec::new();
+        for (partition, name) in partitions.iter().zip(names) {
+            if packets.len() >= max {
+                break;
+            }
+            let consumed = self.position.consumed(&name);
+            let mut reader = ArchiveReader::open(File::open(&partition.path)?)?;
+            if consumed >= reader.packet_count() {
+                continue;
+            }
+
+            let mut skip = consumed;
+            let mut taken = 0;
+            for segment in 0..reader.segments().len() {
+                let (count, shredded) = (reader.segments()[segment].packet_count, reader.segments()[segment].is_shredded());
+                if skip >= count {
+                    skip -= count;
+                    continue;
+                }
+                // Packets of shredded segments are gone; count them as consumed
+                if shredded {
+                    taken += count - skip;
+                    skip = 0;
+                    continue;
+                }
+                let segment_packets = reader.read_segment(segment)?;
+                let remaining = &segment_packets[skip as usize..];
+                let count = remaining.len().min(max - packets.len());
+                packets.extend_from_slice(&remaining[..count]);
+                taken += count as u64;
+                skip = 0;
+                if packets.len() >= max {
+                    break;
+                }
+            }
+            self.position.partitions.insert(name, consumed + taken);
+        }
+        Ok(packets)
+    }
+
+    /// Makes the current position durable
+    pub fn commit(&mut self) -> Result<()> {
+        // The generation and the committed offsets only move once the record
+        // is durable
+        let generation = self.generation + 1;
+        let record = self.record(&self.position, generation)?;
+        if self.records >= MAX_RECORDS {
+            self.rewrite(&record)?;
+        } else {
+            let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+            file.write_all(&record)?;
+            file.sync_all()?;
+            self.records += 1;
+        }
+        self.generation = generation;
+        self.committed = self.position.clone();
+        Ok(())
+    }
+
+    /// Returns to the position of the last commit; packets read since are
+    /// read again
+    pub fn rollback(&mut self) {
+        self.position = self.committed.clone();
+    }
+
+    /// Moves to the given position, e.g. one stored along with the output of
+    /// a processor; the position is not committed
+    pub fn seek(&mut self, position: ConsumerOffsets) {
+        self.position = position;
+    }
+
+    /// Encodes the offset record of the given offsets and commit generation
+    fn record(&self, offsets: &ConsumerOffsets, generation: u64) -> Result<Vec<u8>> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+        let packet = offsets.to_control_packet(&self.group, generation, timestamp)?.encode_packet()?;
+        let mut record = encode_varint(packet.len() as u64);
+        record.extend_from_slice(&packet);
+        Ok(record)
+    }
+
+    /// Replaces the offset file with one holding only the given record
+    fn rewrite(&mut self, record: &[u8]) -> Result<()> {
+        let temporary = self.path.with_extension("tmp");
+        let mut file = File::create(&temporary)?;
+        file.write_all(record)?;
+        file.sync_all()?;
+        fs::rename(&temporary, &self.path)?;
+        self.records = 1;
+        Ok(())
+    }
+}
+
+/// Reads the records of an offset file; returns the generation and offsets of
+/// the latest valid record, the number of valid records and whether the file
+/// ended cleanly
+fn read_records(data: &[u8]) -> (Option<(u64, ConsumerOffsets)>, usize, bool) {
+    let (mut latest, mut records, mut position) = (None, 0, 0);
+    while position < data.len() {
+        let record = decode_varint(&data[position..]).ok().and_then(|(length, prefix)| {
+            let end = prefix.checked_add(usize::try_from(length).ok()?)?;
+            let packet = Packet::parse_packet(data[position..].get(prefix..end)?).ok()?;
+            let (_, generation, offsets) = ConsumerOffsets::from_control_packet(&packet).ok()?;
+            Some((generation, offsets, end))
+        });
+        let Some((generation, offsets, length)) = record else {
+            return (latest, records, false);
+        };
+        latest = Some((generation, offsets));
+        records += 1;
+        position += length;
+    }
+    (latest, records, true)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::packet::{DataBody, MetadataHeader};
+    use crate::archive::LogConfig;
+
+    fn packet(timestamp: u64) -> Packet {
+        let header = MetadataHeader { schema_id: 1, timestamp, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(vec![0x02, 0x01, 0x01, 0x05])).unwrap()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tonitru-consumer-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn timestamps(packets: &[Packet]) -> Vec<u64> {
+        packets.iter().map(|p| p.header.timestamp).collect()
+    }
+
+    #[test]
+    fn test_consumer_commit_and_rollback() {
+        let dir = test_dir("commit");
+        let mut log = PacketLog::open(&dir, LogConfig::default()).unwrap();
+        for timestamp in [10, 20, 30, 3600, 3610] {
+            log.append(&packet(timestamp)).unwrap();
+        }
+        log.flush().unwrap();
+
+        let mut consumer = LogConsumer::open(&dir, "billing").unwrap();
+        assert_eq!(timestamps(&consumer.poll(&log, 2).unwrap()), vec![10, 20]);
+        consumer.commit().unwrap();
+        assert_eq!(timestamps(&consumer.poll(&log, 2).unwrap()), vec![30, 3600]);
+        consumer.rollback();
+        assert_eq!(timestamps(&consumer.poll(&log, 10).unwrap()), vec![30, 3600, 3610]);
+
+        // A restarted consumer resumes from the last commit
+        let mut consumer = LogConsumer::open(&dir, "billing").unwrap();
+        assert_eq!(timestamps(&consumer.poll(&log, 10).unwrap()), vec![30, 3600, 3610]);
+        consumer.commit().unwrap();
+        assert!(consumer.poll(&log, 10).unwrap().is_empty());
+
+        // New packets, including a late one for a closed partition
+        log.append(&packet(40)).unwrap();
+        log.append(&packet(7200)).unwrap();
+        log.flush().unwrap();
+        assert_eq!(timestamps(&consumer.poll(&log, 10).unwrap()), vec![40, 7200]);
+
+        // Groups are independent
+        let mut other = LogConsumer::open(&dir, "audit").unwrap();
+        assert_eq!(other.poll(&log, 10).unwrap().len(), 7);
+        assert!(LogConsumer::open(&dir, "../escape").is_err());
+
+        drop(log);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_consumer_offsets_survive_torn_commit() {
+        let dir = test_dir("torn");
+        let mut log = PacketLog::open(&dir, LogConfig::default()).unwrap();
+        for timestamp in 0..4 {
+            log.append(&packet(timestamp)).unwrap();
+        }
+        log.flush().unwrap();
+
+        let mut consumer = LogConsumer::open(&dir, "etl").unwrap();
+        consumer.poll(&log, 1).unwrap();
+        consumer.commit().unwrap();
+        consumer.poll(&log, 1).unwrap();
+        consumer.commit().unwrap();
+        let committed = consumer.committed().clone();
+        assert_eq!(committed.partitions.values().copied().collect::<Vec<_>>(), vec![2]);
+
+        // A crash in the middle of a commit leaves half a record behind
+        consumer.poll(&log, 1).unwrap();
+        let record = consumer.record(consumer.position(), consumer.generation + 1).unwrap();
+        let mut file = OpenOptions::new().append(true).open(&consumer.path).unwrap();
+        file.write_all(&record[..record.len() / 2]).unwrap();
+
+        let mut consumer = LogConsumer::open(&dir, "etl").unwrap();
+        assert_eq!(consumer.committed(), &committed);
+        assert_eq!(consumer.records, 1);
+        assert_eq!(timestamps(&consumer.poll(&log, 10).unwrap()), vec![2, 3]);
+        consumer.commit().unwrap();
+        assert_eq!(LogConsumer::open(&dir, "etl").unwrap().committed(), consumer.committed());
+
+        // A commit that cannot be written leaves the last one in place
+        let (generation, last) = (consumer.generation, consumer.committed().clone());
+        consumer.seek(ConsumerOffsets::default());
+        fs::remove_file(&consumer.path).unwrap();
+        fs::create_dir(&consumer.path).unwrap();
+        assert!(consumer.commit().is_err());
+        assert_eq!((consumer.generation, consumer.committed()), (generation, &last));
+
+        let packet = committed.to_control_packet("etl", 7, 0).unwrap();
+        assert_eq!(ConsumerOffsets::from_control_packet(&packet).unwrap(), ("etl".to_string(), 7, committed));
+
+        drop(log);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,263 @@
+// Envelope encryption of archive segments
+//
+// An encrypted archive seals each segment with its own random data key
+// (AES-256-GCM). The data key is wrapped by a master key held by a
+// `KeyManager`, and the index entry of the segment records the wrapped key and
+// the ID of the master key. Rotating the master key and shredding segments
+// therefore only rewrite the index: a shredded segment loses its wrapped key,
+// which leaves its data unreadable (crypto-erase).
+//
+// Index entries themselves are not encrypted. Timestamp ranges, schema IDs
+// and bloom filters of encrypted segments stay readable, so archives whose
+// field values must not leak should be written without indexed tags.
+
+use std::fs::{self, File};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::Arc;
+
+use rand_core::RngCore;
+use crate::encrypt::rng::SecureRng;
+
+use crate::internal::error::{Error, Result};
+use crate::archive::{ArchiveIndex, ArchiveReader, BloomFilter, FOOTER_MAGIC};
+use crate::encrypt::aes_gcm::AesGcmEncryptor;
+use crate::encrypt::key_management::{KeyManager, KeyType};
+use crate::encrypt::Encryptor;
+
+/// Size of segment data keys in bytes
+const DATA_KEY_SIZE: usize = 32;
+
+/// Key of an encrypted segment, as recorded in the archive index
+#[derive(Debug, Clone, PartialEq)]
+pub enum SegmentKey {
+    /// Data key wrapped by a master key
+    Wrapped {
+        /// ID of the master key in the key manager
+        master_key_id: String,
+        /// Data key encrypted with the master key
+        wrapped_key: Vec<u8>,
+    },
+    /// The data key has been destroyed; the segment can no longer be read
+    Shredded,
+}
+
+/// Master key used by an archive writer to wrap segment data keys
+#[derive(Debug, Clone)]
+pub(crate) struct SegmentEncryption {
+    keys: Arc<KeyManager>,
+    master_key_id: String,
+}
+
+impl SegmentEncryption {
+    /// Fails if the master key does not exist or cannot wrap data keys
+    pub fn new(keys: Arc<KeyManager>, master_key_id: &str) -> Result<Self> {
+        check_master_key(&keys, master_key_id)?;
+        Ok(Self { keys, master_key_id: master_key_id.to_string() })
+    }
+
+    /// Encrypts segment data under a fresh data key; returns the sealed data
+    /// and the wrapped data key
+    pub fn seal(&self, data: &[u8]) -> Result<(Vec<u8>, SegmentKey)> {
+        let mut data_key = [0u8; DATA_KEY_SIZE];
+        SecureRng.fill_bytes(&mut data_key);
+        let sealed = AesGcmEncryptor::with_key(&data_key)?.encrypt(data, None)?;
+        let wrapped_key = self.keys.wrap_key(&self.master_key_id, &data_key)?;
+        Ok((sealed, SegmentKey::Wrapped { master_key_id: self.master_key_id.clone(), wrapped_key }))
+    }
+}
+
+/// Checks that a master key exists and can wrap data keys
+fn check_master_key(keys: &KeyManager, master_key_id: &str) -> Result<()> {
+    let metadata = keys.get_key(master_key_id)?;
+    if !matches!(metadata.key_type, KeyType::AesGcm | KeyType::ChaCha20Poly1305) {
+        return Err(Error::EncryptionError(format!(
+            "Master key '{}' is a {:?} key; archives need a symmetric master key",
+            master_key_id, metadata.key_type
+        )));
+    }
+    Ok(())
+}
+
+/// Decrypts the data of a segment sealed with the given key
+pub(crate) fn open_segment(keys: &KeyManager, key: &SegmentKey, sealed: &[u8]) -> Result<Vec<u8>> {
+    match key {
+        SegmentKey::Wrapped { master_key_id, wrapped_key } => {
+            let data_key = keys.unwrap_key(master_key_id, wrapped_key)?;
+            AesGcmEncryptor::with_key(&data_key)?.decrypt(sealed, None)
+        }
+        SegmentKey::Shredded => Err(Error::EncryptionError("Archive segment has been shredded".to_string())),
+    }
+}
+
+/// Re-wraps the data keys of all readable encrypted segments of an archive
+/// with a new master key; returns the number of segments re-wrapped
+///
+/// The key manager must hold both the old and the new master keys. Once this
+/// returns, the old master keys are no longer needed to read the archive.
+pub fn rewrap_keys(path: impl AsRef<Path>, keys: &KeyManager, master_key_id: &str) -> Result<usize> {
+    check_master_key(keys, master_key_id)?;
+    rewrite_index(path.as_ref(), |index| {
+        let mut rewrapped = 0;
+        for segment in &mut index.segments {
+            if let Some(SegmentKey::Wrapped { master_key_id: old_key_id, wrapped_key }) = &mut segment.key {
+                if old_key_id == master_key_id {
+                    continue;
+                }
+                let data_key = keys.unwrap_key(old_key_id, wrapped_key)?;
+                *wrapped_key = keys.wrap_key(master_key_id, &data_key)?;
+                *old_key_id = master_key_id.to_string();
+                rewrapped += 1;
+            }
+        }
+        Ok(rewrapped)
+    })
+}
+
+/// Destroys the data keys of the given segments of an archive, making their
+/// data unreadable; returns the number of segments shredded
+///
+/// The bloom filters of shredded segments are cleared as well, so lookups skip
+/// them. Segments already shredded are left as they are; unencrypted segments
+/// cannot be shredded.
+pub fn shred_segments(path: impl AsRef<Path>, segments: &[usize]) -> Result<usize> {
+    rewrite_index(path.as_ref(), |index| {
+        let mut shredded = 0;
+        for &number in segments {
+            let segment = index.segments.get_mut(number)
+                .ok_or_else(|| Error::CodecError(format!("Archive has no segment {}", number)))?;
+            match segment.key {
+                Some(SegmentKey::Wrapped { .. }) => {
+                    segment.key = Some(SegmentKey::Shredded);
+                    // An empty filter rules out every value
+                    segment.bloom = BloomFilter::new(0, 0.5);
+                    segment.fully_indexed = true;
+                    shredded += 1;
+                }
+                Some(SegmentKey::Shredded) => {}
+                None => return Err(Error::EncryptionError(format!("Archive segment {} is not encrypted", number))),
+            }
+        }
+        Ok(shredded)
+    })
+}
+
+/// Applies `update` to the index of an archive file and, if it reports
+/// changes, replaces the index and footer
+///
+/// The segments are copied as they are into a temporary file, which then
+/// takes the place of the archive, so an interrupted rewrite leaves the old
+/// archive intact. The old index is not kept in a copy, so wrapped keys
+/// dropped from it are gone once the old file is.
+fn rewrite_index(path: &Path, update: impl FnOnce(&mut ArchiveIndex) -> Result<usize>) -> Result<usize> {
+    let mut file = File::open(path)?;
+    let (mut index, index_offset) = ArchiveReader::open(&mut file)?.into_index();
+    let changed = update(&mut index)?;
+    if changed == 0 {
+        return Ok(0);
+    }
+
+    let mut tail = index.encode();
+    tail.extend_from_slice(&index_offset.to_le_bytes());
+    tail.extend_from_slice(FOOTER_MAGIC);
+    let temporary = path.with_extension("tmp");
+    let mut rewritten = File::create(&temporary)?;
+    file.seek(SeekFrom::Start(0))?;
+    io::copy(&mut file.take(index_offset), &mut rewritten)?;
+    rewritten.write_all(&tail)?;
+    rewritten.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(changed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{ArchiveConfig, ArchiveWriter};
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::{HtlvItem, HtlvValue};
+    use crate::internal::packet::{DataBody, MetadataHeader, Packet};
+    use std::fs;
+    use std::io::Cursor;
+
+    fn packet(timestamp: u64) -> Packet {
+        let item = HtlvItem::new(1, HtlvValue::U64(timestamp));
+        let header = MetadataHeader { schema_id: 1, timestamp, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(encode_item(&item).unwrap())).unwrap()
+    }
+
+    fn write_encrypted(keys: &Arc<KeyManager>, master_key_id: &str, packets: &[Packet]) -> Vec<u8> {
+        let config = ArchiveConfig { segment_packets: 2, indexed_tags: vec![1], ..ArchiveConfig::default() };
+        let mut writer = ArchiveWriter::with_config(Vec::new(), config);
+        writer.set_encryption(keys.clone(), master_key_id).unwrap();
+        for packet in packets {
+            writer.append(packet).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_encrypted_archive_roundtrip() {
+        let keys = Arc::new(KeyManager::new());
+        let master = keys.generate_key(KeyType::AesGcm, true).unwrap();
+        let packets: Vec<Packet> = (0..5).map(packet).collect();
+        let data = write_encrypted(&keys, &master, &packets);
+
+        // Packet bodies do not appear in the clear
+        let body = packets[3].encode_packet().unwrap();
+        assert!(!data.windows(body.len()).any(|window| window == body.as_slice()));
+
+        let mut reader = ArchiveReader::open(Cursor::new(data.clone())).unwrap();
+        assert!(reader.segments().iter().all(|segment| segment.is_encrypted()));
+        assert!(reader.read_segment(0).is_err());
+        reader.set_key_manager(keys.clone());
+        assert_eq!(reader.read_all().unwrap(), packets);
+        assert_eq!(reader.find(1, &HtlvValue::U64(4)).unwrap(), vec![packets[4].clone()]);
+
+        // Each segment has its own data key
+        let wrapped: Vec<&SegmentKey> = reader.segments().iter().filter_map(|segment| segment.key.as_ref()).collect();
+        assert_ne!(wrapped[0], wrapped[1]);
+
+        // Another key manager cannot read the archive
+        let other = Arc::new(KeyManager::new());
+        let mut reader = ArchiveReader::open(Cursor::new(data)).unwrap();
+        reader.set_key_manager(other);
+        assert!(reader.read_segment(0).is_err());
+
+        let x25519 = keys.generate_key(KeyType::X25519, false).unwrap();
+        assert!(ArchiveWriter::new(Vec::new()).set_encryption(keys.clone(), &x25519).is_err());
+    }
+
+    #[test]
+    fn test_rewrap_and_shred() {
+        let path = std::env::temp_dir().join(format!("tonitru-crypto-{}.tna", std::process::id()));
+        let keys = Arc::new(KeyManager::new());
+        let old_master = keys.generate_key(KeyType::AesGcm, true).unwrap();
+        let new_master = keys.generate_key(KeyType::ChaCha20Poly1305, true).unwrap();
+        let packets: Vec<Packet> = (0..6).map(packet).collect();
+        fs::write(&path, write_encrypted(&keys, &old_master, &packets)).unwrap();
+
+        assert_eq!(rewrap_keys(&path, &keys, &new_master).unwrap(), 3);
+        assert_eq!(rewrap_keys(&path, &keys, &new_master).unwrap(), 0);
+        let mut reader = ArchiveReader::open(fs::File::open(&path).unwrap()).unwrap();
+        reader.set_key_manager(keys.clone());
+        assert!(reader.segments().iter().all(|segment| matches!(
+            &segment.key,
+            Some(SegmentKey::Wrapped { master_key_id, .. }) if *master_key_id == new_master
+        )));
+        assert_eq!(reader.read_all().unwrap(), packets);
+
+        assert_eq!(shred_segments(&path, &[1]).unwrap(), 1);
+        let mut reader = ArchiveReader::open(fs::File::open(&path).unwrap()).unwrap();
+        reader.set_key_manager(keys.clone());
+        assert!(reader.segments()[1].is_shredded());
+        assert!(reader.read_segment(1).is_err());
+        let remaining: Vec<Packet> = packets.iter().filter(|p| !(2..4).contains(&p.header.timestamp)).cloned().collect();
+        assert_eq!(reader.read_all().unwrap(), remaining);
+        assert!(reader.find(1, &HtlvValue::U64(2)).unwrap().is_empty());
+        assert_eq!(reader.segments_in_range(0, 10), vec![0, 2]);
+
+        assert!(shred_segments(&path, &[7]).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
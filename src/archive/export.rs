@@ -0,0 +1,208 @@
+// Conversion between archives and JSON Lines
+//
+// Each packet becomes one JSON record holding its header fields and its item
+// as JSON. When the archive carries the schema of a packet, object fields are
+// named after the schema and the record can be imported back; otherwise the
+// item is converted without a schema, keyed by tag numbers.
+
+use std::collections::HashMap;
+use std::io::{BufRead, Read, Seek, Write};
+
+use serde_json::{json, Value};
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::{DataBody, MetadataHeader, Packet};
+use crate::archive::{packet_item, ArchiveReader, ArchiveSchema, ArchiveWriter};
+use crate::codec::encode::encode_item;
+use crate::codec::types::HtlvItem;
+use crate::schema::mapper::{value_to_json, SchemaMapper};
+use crate::schema::types::Schema;
+
+/// Outcome of an export
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExportReport {
+    /// Packets written as records
+    pub exported: usize,
+    /// Packets skipped because their body cannot be read, e.g. encrypted ones,
+    /// or because their segment has been shredded
+    pub skipped: usize,
+}
+
+/// Converts packets to JSON records and back, using the schemas of an archive
+#[derive(Debug, Default)]
+pub struct RecordConverter {
+    schemas: HashMap<u64, (Schema, SchemaMapper)>,
+}
+
+impl RecordConverter {
+    /// Creates a converter for the given schemas; schemas that do not parse
+    /// are ignored, and their packets are converted without a schema
+    pub fn new(schemas: &[ArchiveSchema]) -> Self {
+        let mut converter = Self::default();
+        for schema in schemas {
+            if let Ok(parsed) = schema.parse() {
+                converter.add_schema(schema.schema_id, parsed);
+            }
+        }
+        converter
+    }
+
+    /// Adds the schema of a schema ID
+    pub fn add_schema(&mut self, schema_id: u64, schema: Schema) {
+        let mut mapper = SchemaMapper::new();
+        mapper.set_definitions(&schema);
+        self.schemas.insert(schema_id, (schema, mapper));
+    }
+
+    /// Returns the schema of a schema ID
+    pub fn schema(&self, schema_id: u64) -> Option<&Schema> {
+        self.schemas.get(&schema_id).map(|(schema, _)| schema)
+    }
+
+    /// Converts a packet to a record, or returns `None` if its body cannot be
+    /// read
+    pub fn to_record(&self, packet: &Packet) -> Result<Option<Value>> {
+        let Some(item) = packet_item(packet) else {
+            return Ok(None);
+        };
+        let data = match self.schemas.get(&packet.header.schema_id) {
+            Some((schema, mapper)) => mapper.htlv_to_json(&schema.root_type, &item.value)?,
+            None => value_to_json(&item.value),
+        };
+        Ok(Some(json!({
+            "schema_id": packet.header.schema_id,
+            "timestamp": packet.header.timestamp,
+            "shard_id": packet.header.shard_id,
+            "data": data,
+        })))
+    }
+
+    /// Converts a record back to a packet with a raw body; the schema of the
+    /// record's schema ID must be known
+    pub fn from_record(&self, record: &Value) -> Result<Packet> {
+        let field = |name: &str| {
+            record.get(name).and_then(Value::as_u64).ok_or_else(|| {
+                Error::SchemaError(format!("Record field '{}' is missing or not an unsigned integer", name))
+            })
+        };
+        let schema_id = field("schema_id")?;
+        let header = MetadataHeader {
+            schema_id,
+            timestamp: field("timestamp")?,
+            shard_id: field("shard_id")?,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        let data = record.get("data")
+            .ok_or_else(|| Error::SchemaError("Record field 'data' is missing".to_string()))?;
+
+        let (schema, mapper) = self.schemas.get(&schema_id)
+            .ok_or_else(|| Error::SchemaError(format!("No schema for schema ID {}", schema_id)))?;
+        let value = mapper.json_to_htlv(&schema.root_type, data)?;
+        let body = encode_item(&HtlvItem::new(0, value))?;
+        Packet::build_packet(header, DataBody::Raw(body))
+    }
+}
+
+/// Writes the packets of an archive as JSON Lines
+pub fn export_jsonl<R: Read + Seek, W: Write>(reader: &mut ArchiveReader<R>, mut out: W) -> Result<ExportReport> {
+    let converter = RecordConverter::new(reader.schemas());
+    let mut report = ExportReport::default();
+    for segment in 0..reader.segments().len() {
+        let entry = &reader.segments()[segment];
+        if entry.is_shredded() {
+            report.skipped += entry.packet_count as usize;
+            continue;
+        }
+        for packet in reader.read_segment(segment)? {
+            match converter.to_record(&packet)? {
+                Some(record) => {
+                    writeln!(out, "{}", record)?;
+                    report.exported += 1;
+                }
+                None => report.skipped += 1,
+            }
+        }
+    }
+    out.flush()?;
+    Ok(report)
+}
+
+/// Appends the records of a JSON Lines input to an archive; returns the number
+/// of packets written
+///
+/// Blank lines are skipped. Records are converted with the schemas of the
+/// converter, which should also be added to the archive.
+pub fn import_jsonl<B: BufRead, W: Write>(input: B, converter: &RecordConverter, writer: &mut ArchiveWriter<W>) -> Result<usize> {
+    let mut imported = 0;
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let record: Value = serde_json::from_str(&line)
+            .map_err(|e| Error::SchemaError(format!("Invalid JSON on line {}: {}", number + 1, e)))?;
+        let packet = converter.from_record(&record)
+            .map_err(|e| Error::SchemaError(format!("Invalid record on line {}: {}", number + 1, e)))?;
+        writer.append(&packet)?;
+        imported += 1;
+    }
+    Ok(imported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const SCHEMA: &str = r#"{"id": "events", "name": "Events", "version": "1.0.0", "type": "object",
+        "properties": {"user": {"type": "integer"}, "action": {"type": "string"}}, "required": ["user"]}"#;
+
+    #[test]
+    fn test_jsonl_roundtrip() {
+        let input = concat!(
+            r#"{"schema_id": 7, "timestamp": 100, "shard_id": 1, "data": {"user": 42, "action": "click"}}"#, "\n",
+            "\n",
+            r#"{"schema_id": 7, "timestamp": 200, "shard_id": 2, "data": {"user": -3}}"#, "\n",
+        );
+        let schemas = [ArchiveSchema { schema_id: 7, document: SCHEMA.as_bytes().to_vec() }];
+        let converter = RecordConverter::new(&schemas);
+
+        let mut writer = ArchiveWriter::new(Vec::new());
+        writer.add_schema(7, SCHEMA.as_bytes().to_vec()).unwrap();
+        assert_eq!(import_jsonl(input.as_bytes(), &converter, &mut writer).unwrap(), 2);
+        let archive = writer.finish().unwrap();
+
+        let mut reader = ArchiveReader::open(Cursor::new(archive)).unwrap();
+        let mut out = Vec::new();
+        let report = export_jsonl(&mut reader, &mut out).unwrap();
+        assert_eq!(report, ExportReport { exported: 2, skipped: 0 });
+
+        let records: Vec<Value> = String::from_utf8(out).unwrap().lines().map(|l| serde_json::from_str(l).unwrap()).collect();
+        assert_eq!(records[0], json!({"schema_id": 7, "timestamp": 100, "shard_id": 1, "data": {"user": 42, "action": "click"}}));
+        assert_eq!(records[1]["data"], json!({"user": -3}));
+    }
+
+    #[test]
+    fn test_jsonl_import_errors() {
+        let converter = RecordConverter::default();
+        let mut writer = ArchiveWriter::new(Vec::new());
+        let missing_schema = r#"{"schema_id": 1, "timestamp": 0, "shard_id": 0, "data": {}}"#;
+        assert!(import_jsonl(missing_schema.as_bytes(), &converter, &mut writer).is_err());
+        assert!(import_jsonl("not json".as_bytes(), &converter, &mut writer).is_err());
+    }
+
+    #[test]
+    fn test_record_without_schema() {
+        let item = HtlvItem::new(0, crate::codec::types::HtlvValue::Bool(true));
+        let header = MetadataHeader { schema_id: 9, timestamp: 5, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        let packet = Packet::build_packet(header, DataBody::Raw(encode_item(&item).unwrap())).unwrap();
+        let record = RecordConverter::default().to_record(&packet).unwrap().unwrap();
+        assert_eq!(record["data"], json!(true));
+
+        let header = MetadataHeader { schema_id: 9, timestamp: 5, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        let encrypted = Packet::build_packet(header, DataBody::Encrypted(vec![1, 2, 3])).unwrap();
+        assert_eq!(RecordConverter::default().to_record(&encrypted).unwrap(), None);
+    }
+}
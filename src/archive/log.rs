@@ -0,0 +1,377 @@
+// Time-partitioned packet log
+//
+// A `PacketLog` keeps packets in a directory of archives, one per time
+// partition. Files are named `<start>-<end>.tna` after the half-open range of
+// header timestamps (in seconds) they cover, zero-padded so that names sort by
+// time; packets arriving for a partition that was already closed go to an
+// extra file `<start>-<end>.<n>.tna`. Queries prune partitions by name and
+// segments by their timestamp range before reading any packet, and a retention
+// policy deletes or compacts partitions past a maximum age.
+
+use std::collections::BTreeMap;
+use std::fs::{self, File};
+use std::io::BufWriter;
+use std::path::{Path, PathBuf};
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::archive::{ArchiveConfig, ArchiveReader, ArchiveSchema, ArchiveWriter};
+
+/// Seconds in a day
+const DAY_SECS: u64 = 86_400;
+
+/// File extension of partition files
+const EXTENSION: &str = "tna";
+
+/// Configuration of a packet log
+#[derive(Debug, Clone)]
+pub struct LogConfig {
+    /// Width of a partition in seconds
+    pub partition_secs: u64,
+
+    /// Configuration of the partition archives
+    pub archive: ArchiveConfig,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            partition_secs: 3600, // One partition per hour
+            archive: ArchiveConfig::default(),
+        }
+    }
+}
+
+/// A partition file of a packet log
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partition {
+    /// Path of the archive
+    pub path: PathBuf,
+    /// First timestamp covered by the partition
+    pub start: u64,
+    /// First timestamp after the partition
+    pub end: u64,
+    /// Number of the file among the files of the same range, from 0
+    pub sequence: u32,
+}
+
+impl Partition {
+    /// Returns true if the partition may hold packets with timestamps in
+    /// `from..=to`
+    pub fn overlaps(&self, from: u64, to: u64) -> bool {
+        self.start <= to && from < self.end
+    }
+
+    /// Parses a partition file name, returning `None` for other files
+    fn from_path(path: PathBuf) -> Option<Self> {
+        let name = path.file_name()?.to_str()?.strip_suffix(EXTENSION)?.strip_suffix('.')?;
+        let (range, sequence) = match name.split_once('.') {
+            Some((range, sequence)) => (range, sequence.parse().ok()?),
+            None => (name, 0),
+        };
+        let (start, end) = range.split_once('-')?;
+        let (start, end) = (start.parse().ok()?, end.parse().ok()?);
+        (start < end).then_some(Self { path, start, end, sequence })
+    }
+}
+
+/// What to do with partitions past the maximum age
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RetentionAction {
+    /// Delete the partitions
+    Delete,
+    /// Merge the partitions of each day into a single archive
+    Compact,
+}
+
+/// Retention policy of a packet log
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    /// Partitions ending more than this many days ago are expired
+    pub max_age_days: u64,
+    /// What to do with expired partitions
+    pub action: RetentionAction,
+}
+
+/// Outcome of applying a retention policy
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RetentionReport {
+    /// Partition files deleted, including the inputs of compactions
+    pub deleted: usize,
+    /// Day archives written by compaction
+    pub compacted: usize,
+}
+
+/// The partition currently being written
+#[derive(Debug)]
+struct OpenPartition {
+    partition: Partition,
+    writer: ArchiveWriter<BufWriter<File>>,
+}
+
+/// A directory of time-partitioned archives
+///
+/// Packets of the open partition become visible to queries once the partition
+/// is closed, by `flush`, by a packet for another partition, or when the log
+/// is dropped.
+#[derive(Debug)]
+pub struct PacketLog {
+    dir: PathBuf,
+    config: LogConfig,
+    schemas: Vec<ArchiveSchema>,
+    current: Option<OpenPartition>,
+}
+
+impl PacketLog {
+    /// Opens the log in the given directory, creating the directory if needed
+    pub fn open(dir: impl Into<PathBuf>, config: LogConfig) -> Result<Self> {
+        if config.partition_secs == 0 {
+            return Err(Error::CodecError("Log partitions must be at least one second wide".to_string()));
+        }
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, config, schemas: Vec::new(), current: None })
+    }
+
+    /// Adds a schema to the archives of partitions opened from now on
+    pub fn add_schema(&mut self, schema_id: u64, document: Vec<u8>) {
+        self.schemas.retain(|schema| schema.schema_id != schema_id);
+        self.schemas.push(ArchiveSchema { schema_id, document });
+    }
+
+    /// Appends a packet to the partition of its header timestamp
+    pub fn append(&mut self, packet: &Packet) -> Result<()> {
+        let start = packet.header.timestamp - packet.header.timestamp % self.config.partition_secs;
+        let is_current = self.current.as_ref().is_some_and(|open| open.partition.start == start);
+        if !is_current {
+            self.flush()?;
+            let end = start.saturating_add(self.config.partition_secs);
+            self.current = Some(self.create_partition(start, end)?);
+        }
+        self.current.as_mut().unwrap().writer.append(packet)
+    }
+
+    /// Closes the open partition, making its packets visible to queries
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(open) = self.current.take() {
+            open.writer.finish()?;
+        }
+        Ok(())
+    }
+
+    /// Returns the closed partitions, sorted by start time
+    pub fn partitions(&self) -> Result<Vec<Partition>> {
+        let open_path = self.current.as_ref().map(|open| &open.partition.path);
+        let mut partitions = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            if let Some(partition) = Partition::from_path(entry?.path()) {
+                if Some(&partition.path) != open_path {
+                    partitions.push(partition);
+                }
+            }
+        }
+        partitions.sort_by_key(|p| (p.start, p.end, p.sequence));
+        Ok(partitions)
+    }
+
+    /// Returns the packets with timestamps in `from..=to`, in partition order
+    ///
+    /// Only partitions and segments whose time range overlaps the query are
+    /// read.
+    pub fn query(&self, from: u64, to: u64) -> Result<Vec<Packet>> {
+        let mut packets = Vec::new();
+        for partition in self.partitions()?.into_iter().filter(|p| p.overlaps(from, to)) {
+            let mut reader = ArchiveReader::open(File::open(&partition.path)?)?;
+            for segment in reader.segments_in_range(from, to) {
+                packets.extend(
+                    reader.read_segment(segment)?
+                        .into_iter()
+                        .filter(|packet| (from..=to).contains(&packet.header.timestamp)),
+                );
+            }
+        }
+        Ok(packets)
+    }
+
+    /// Deletes or compacts the partitions that ended more than
+    /// `policy.max_age_days` days before `now`
+    pub fn apply_retention(&mut self, policy: &RetentionPolicy, now: u64) -> Result<RetentionReport> {
+        let cutoff = now.saturating_sub(policy.max_age_days.saturating_mul(DAY_SECS));
+        if self.current.as_ref().is_some_and(|open| open.partition.end <= cutoff) {
+            self.flush()?;
+        }
+        let expired: Vec<Partition> = self.partitions()?.into_iter().filter(|p| p.end <= cutoff).collect();
+
+        let mut report = RetentionReport::default();
+        match policy.action {
+            RetentionAction::Delete => {
+                for partition in &expired {
+                    fs::remove_file(&partition.path)?;
+                    report.deleted += 1;
+                }
+            }
+            RetentionAction::Compact => {
+                let mut days: BTreeMap<u64, Vec<Partition>> = BTreeMap::new();
+                for partition in expired {
+                    // Partitions spanning several days are already compact
+                    let day = partition.start / DAY_SECS;
+                    if partition.end.div_ceil(DAY_SECS) == day + 1 {
+                        days.entry(day).or_default().push(partition);
+                    }
+                }
+                for (day, partitions) in days {
+                    let (start, end) = (day * DAY_SECS, (day + 1) * DAY_SECS);
+                    if let [partition] = partitions.as_slice() {
+                        if (partition.start, partition.end) == (start, end) {
+                            continue;
+                        }
+                    }
+                    self.compact(start, end, &partitions)?;
+                    report.deleted += partitions.len();
+                    report.compacted += 1;
+                }
+            }
+        }
+        Ok(report)
+    }
+
+    /// Merges partitions into one archive covering `start..end`
+    fn compact(&self, start: u64, end: u64, partitions: &[Partition]) -> Result<()> {
+        let mut schemas: BTreeMap<u64, Vec<u8>> = BTreeMap::new();
+        let mut packets = Vec::new();
+        for partition in partitions {
+            let mut reader = ArchiveReader::open(File::open(&partition.path)?)?;
+            for schema in reader.schemas() {
+                schemas.entry(schema.schema_id).or_insert_with(|| schema.document.clone());
+            }
+            packets.extend(reader.read_all()?);
+        }
+        packets.sort_by_key(|packet| packet.header.timestamp);
+
+        // Write under a temporary name so a crash never leaves a partial
+        // archive that looks like a partition
+        let target = self.partition_path(start, end, 0);
+        let temporary = target.with_extension("tmp");
+        let mut writer = ArchiveWriter::with_config(BufWriter::new(File::create(&temporary)?), self.config.archive.clone());
+        for (schema_id, document) in schemas {
+            writer.add_schema(schema_id, document)?;
+        }
+        for packet in &packets {
+            writer.append(packet)?;
+        }
+        writer.finish()?.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+
+        // Move the merged archive into place before removing its inputs, so a
+        // crash in between duplicates packets instead of losing them. The
+        // first input may have the target's name and is replaced by the rename.
+        fs::rename(&temporary, &target)?;
+        for partition in partitions.iter().filter(|partition| partition.path != target) {
+            fs::remove_file(&partition.path)?;
+        }
+        Ok(())
+    }
+
+    /// Creates the archive of a partition, picking a free file name
+    fn create_partition(&self, start: u64, end: u64) -> Result<OpenPartition> {
+        let mut sequence = 0;
+        let mut path = self.partition_path(start, end, sequence);
+        while path.exists() {
+            sequence += 1;
+            path = self.partition_path(start, end, sequence);
+        }
+
+        let mut writer = ArchiveWriter::with_config(BufWriter::new(File::create(&path)?), self.config.archive.clone());
+        for schema in &self.schemas {
+            writer.add_schema(schema.schema_id, schema.document.clone())?;
+        }
+        Ok(OpenPartition { partition: Partition { path, start, end, sequence }, writer })
+    }
+
+    fn partition_path(&self, start: u64, end: u64, sequence: u32) -> PathBuf {
+        let name = match sequence {
+            0 => format!("{:020}-{:020}.{}", start, end, EXTENSION),
+            n => format!("{:020}-{:020}.{}.{}", start, end, n, EXTENSION),
+        };
+        self.dir.join(name)
+    }
+
+    /// Returns the directory of the log
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+}
+
+impl Drop for PacketLog {
+    fn drop(&mut self) {
+        // Errors cannot be reported from drop; call `flush` to observe them
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::packet::{DataBody, MetadataHeader};
+
+    fn packet(timestamp: u64) -> Packet {
+        let header = MetadataHeader { schema_id: 1, timestamp, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(vec![0x02, 0x01, 0x01, 0x05])).unwrap()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tonitru-log-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_log_partitions_and_query() {
+        let dir = test_dir("query");
+        let mut log = PacketLog::open(&dir, LogConfig::default()).unwrap();
+        for timestamp in [10, 20, 3600, 3700, 7300] {
+            log.append(&packet(timestamp)).unwrap();
+        }
+        // Late packet for a closed partition
+        log.append(&packet(30)).unwrap();
+        log.flush().unwrap();
+
+        let partitions = log.partitions().unwrap();
+        let ranges: Vec<_> = partitions.iter().map(|p| (p.start, p.end)).collect();
+        assert_eq!(ranges, vec![(0, 3600), (0, 3600), (3600, 7200), (7200, 10800)]);
+
+        let timestamps = |packets: Vec<Packet>| packets.iter().map(|p| p.header.timestamp).collect::<Vec<_>>();
+        assert_eq!(timestamps(log.query(15, 3650).unwrap()), vec![20, 30, 3600]);
+        assert_eq!(timestamps(log.query(7000, 8000).unwrap()), vec![7300]);
+        assert!(log.query(20_000, 30_000).unwrap().is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_log_retention() {
+        let dir = test_dir("retention");
+        let mut log = PacketLog::open(&dir, LogConfig::default()).unwrap();
+        // Two partitions on day 0, one on day 1, one on day 3
+        for timestamp in [100, 5000, DAY_SECS + 100, 3 * DAY_SECS] {
+            log.append(&packet(timestamp)).unwrap();
+        }
+        log.flush().unwrap();
+
+        let now = 3 * DAY_SECS + 100;
+        let compact = RetentionPolicy { max_age_days: 1, action: RetentionAction::Compact };
+        let report = log.apply_retention(&compact, now).unwrap();
+        assert_eq!(report, RetentionReport { deleted: 3, compacted: 2 });
+        let ranges: Vec<_> = log.partitions().unwrap().iter().map(|p| (p.start, p.end)).collect();
+        assert_eq!(ranges, vec![(0, DAY_SECS), (DAY_SECS, 2 * DAY_SECS), (3 * DAY_SECS, 3 * DAY_SECS + 3600)]);
+        assert_eq!(log.query(0, now).unwrap().len(), 4);
+
+        // Compacted days are left alone
+        assert_eq!(log.apply_retention(&compact, now).unwrap(), RetentionReport::default());
+
+        let delete = RetentionPolicy { max_age_days: 1, action: RetentionAction::Delete };
+        assert_eq!(log.apply_retention(&delete, now).unwrap().deleted, 2);
+        assert_eq!(log.query(0, now).unwrap().len(), 1);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,421 @@
+// Archive container for Tonitru data at rest
+//
+// A `.tna` archive bundles packets with the schemas they refer to, so one file
+// can be stored, shipped and read back without a schema registry. Layout:
+//
+//   header    magic "TNAR" and the format version
+//   schemas   schema count, then schema ID and JSON document of each schema
+//   segments  packets, each prefixed with its length; in encrypted archives
+//             each segment is sealed with its own data key (see `crypto`)
+//   index     indexed tags, then one entry per segment: offset, length,
+//             packet count, timestamp range, schema IDs, flags, the wrapped
+//             data key of encrypted segments and a bloom filter over the
+//             values of the indexed fields
+//   footer    index offset (u64 little endian) and the magic "TNAX"
+//
+// Integers are varints unless noted. The index trails the segments so that a
+// writer can stream packets without knowing how many will follow; a reader
+// starts from the footer and only loads the segments it needs.
+
+pub mod anonymize;
+pub mod bloom;
+pub mod consumer;
+pub mod crypto;
+pub mod export;
+pub mod log;
+pub mod reader;
+pub mod repair;
+pub mod tail;
+pub mod writer;
+
+pub use anonymize::{anonymize_export, AnonymizeConfig, AnonymizeReport, Synthesis};
+pub use bloom::BloomFilter;
+pub use consumer::{ConsumerOffsets, LogConsumer};
+pub use crypto::{rewrap_keys, shred_segments, SegmentKey};
+pub use export::{export_jsonl, import_jsonl, ExportReport, RecordConverter};
+pub use log::{LogConfig, PacketLog, Partition, RetentionAction, RetentionPolicy, RetentionReport};
+pub use reader::{ArchiveReader, LookupStats};
+pub use repair::{repair, repair_file, DamagedRegion, RepairReport};
+pub use tail::{Backpressure, Subscription, TailConfig, TailFilter, TailReader, TailRecord};
+pub use writer::{ArchiveConfig, ArchiveWriter};
+
+use std::borrow::Cow;
+
+use crate::internal::error::{Error, Result};
+use crate::internal::cursor::WireCursor;
+use crate::internal::packet::{DataBody, Packet};
+use crate::codec::encode::encode_item;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::varint::encode_varint;
+use crate::codec::wire::decode_item_with_format;
+use crate::compress::get_compressor;
+use crate::schema::parser::SchemaParser;
+use crate::schema::types::Schema;
+
+/// Magic number at the start of an archive
+pub const ARCHIVE_MAGIC: &[u8; 4] = b"TNAR";
+
+/// Magic number at the end of an archive
+pub const FOOTER_MAGIC: &[u8; 4] = b"TNAX";
+
+/// Version of the archive format written by this library
+pub const ARCHIVE_VERSION: u8 = 1;
+
+/// Size of the footer: index offset and magic number
+pub(crate) const FOOTER_SIZE: usize = 12;
+
+/// Segment flag: every packet of the segment is in the bloom filter
+const SEGMENT_FULLY_INDEXED: u8 = 0x01;
+
+/// Segment flag: the segment is encrypted and its wrapped key follows
+const SEGMENT_ENCRYPTED: u8 = 0x02;
+
+/// Segment flag: the segment is encrypted and its key has been destroyed
+const SEGMENT_SHREDDED: u8 = 0x04;
+
+/// A schema stored in an archive
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArchiveSchema {
+    /// Schema ID as carried in packet headers
+    pub schema_id: u64,
+    /// JSON document of the schema
+    pub document: Vec<u8>,
+}
+
+impl ArchiveSchema {
+    /// Parses the JSON document of the schema
+    pub fn parse(&self) -> Result<Schema> {
+        let json: serde_json::Value = serde_json::from_slice(&self.document)
+            .map_err(|e| Error::SchemaError(format!("Invalid schema document {}: {}", self.schema_id, e)))?;
+        SchemaParser::new().parse_schema(&json)
+    }
+}
+
+/// Table of contents entry of a packet segment
+#[derive(Debug, Clone, PartialEq)]
+pub struct SegmentEntry {
+    /// Offset of the first packet of the segment from the start of the archive
+    pub offset: u64,
+    /// Length of the segment in bytes
+    pub length: u64,
+    /// Number of packets in the segment
+    pub packet_count: u64,
+    /// Smallest packet timestamp in the segment
+    pub min_timestamp: u64,
+    /// Largest packet timestamp in the segment
+    pub max_timestamp: u64,
+    /// Schema IDs of the packets in the segment, sorted
+    pub schema_ids: Vec<u64>,
+    /// False if some packets could not be indexed, e.g. encrypted ones; such
+    /// segments may hold any value
+    pub fully_indexed: bool,
+    /// Key of the segment data, if the segment is encrypted
+    pub key: Option<SegmentKey>,
+    /// Bloom filter over the values of the indexed fields
+    pub bloom: BloomFilter,
+}
+
+impl SegmentEntry {
+    /// Returns false if the segment certainly holds no packet with the given
+    /// value in the field with the given tag
+    ///
+    /// Only tags the archive was written with are indexed; for other tags the
+    /// answer is meaningless.
+    pub fn may_contain(&self, tag: u64, value: &HtlvValue) -> bool {
+        if !self.fully_indexed {
+            return true;
+        }
+        match index_key(tag, value) {
+            Some(key) => self.bloom.may_contain(&key),
+            None => true,
+        }
+    }
+
+    /// Returns true if the segment may hold packets with timestamps in
+    /// `from..=to`
+    pub fn overlaps(&self, from: u64, to: u64) -> bool {
+        self.min_timestamp <= to && from <= self.max_timestamp
+    }
+
+    /// Returns true if the segment data is encrypted
+    pub fn is_encrypted(&self) -> bool {
+        self.key.is_some()
+    }
+
+    /// Returns true if the key of the segment has been destroyed
+    pub fn is_shredded(&self) -> bool {
+        self.key == Some(SegmentKey::Shredded)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        for value in [self.offset, self.length, self.packet_count, self.min_timestamp, self.max_timestamp] {
+            out.extend_from_slice(&encode_varint(value));
+        }
+        out.extend_from_slice(&encode_varint(self.schema_ids.len() as u64));
+        for schema_id in &self.schema_ids {
+            out.extend_from_slice(&encode_varint(*schema_id));
+        }
+        let mut flags = 0;
+        if self.fully_indexed {
+            flags |= SEGMENT_FULLY_INDEXED;
+        }
+        match &self.key {
+            None => out.push(flags),
+            Some(SegmentKey::Shredded) => out.push(flags | SEGMENT_SHREDDED),
+            Some(SegmentKey::Wrapped { master_key_id, wrapped_key }) => {
+                out.push(flags | SEGMENT_ENCRYPTED);
+                out.extend_from_slice(&encode_varint(master_key_id.len() as u64));
+                out.extend_from_slice(master_key_id.as_bytes());
+                out.extend_from_slice(&encode_varint(wrapped_key.len() as u64));
+                out.extend_from_slice(wrapped_key);
+            }
+        }
+        self.bloom.encode(out);
+    }
+
+    fn decode(reader: &mut ByteReader<'_>) -> Result<Self> {
+        let offset = reader.read_varint()?;
+        let length = reader.read_varint()?;
+        let packet_count = reader.read_varint()?;
+        let min_timestamp = reader.read_varint()?;
+        let max_timestamp = reader.read_varint()?;
+        let schema_count = reader.read_count()?;
+        let schema_ids = (0..schema_count).map(|_| reader.read_varint()).collect::<Result<Vec<_>>>()?;
+        let flags = reader.read_u8()?;
+        let key = match flags & !SEGMENT_FULLY_INDEXED {
+            0 => None,
+            SEGMENT_SHREDDED => Some(SegmentKey::Shredded),
+            SEGMENT_ENCRYPTED => {
+                let master_key_id = String::from_utf8(reader.read_bytes()?.to_vec())
+                    .map_err(|_| Error::CodecError("Invalid master key ID in archive index".to_string()))?;
+                let wrapped_key = reader.read_bytes()?.to_vec();
+                Some(SegmentKey::Wrapped { master_key_id, wrapped_key })
+            }
+            _ => return Err(Error::CodecError(format!("Invalid segment index flags: {:#04x}", flags))),
+        };
+        let fully_indexed = flags & SEGMENT_FULLY_INDEXED != 0;
+        let bloom = BloomFilter::decode(reader)?;
+
+        Ok(Self { offset, length, packet_count, min_timestamp, max_timestamp, schema_ids, fully_indexed, key, bloom })
+    }
+}
+
+/// The trailing index of an archive
+#[derive(Debug, Clone, PartialEq, Default)]
+pub(crate) struct ArchiveIndex {
+    pub indexed_tags: Vec<u64>,
+    pub segments: Vec<SegmentEntry>,
+}
+
+impl ArchiveIndex {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&encode_varint(self.indexed_tags.len() as u64));
+        for tag in &self.indexed_tags {
+            out.extend_from_slice(&encode_varint(*tag));
+        }
+        out.extend_from_slice(&encode_varint(self.segments.len() as u64));
+        for segment in &self.segments {
+            segment.encode(&mut out);
+        }
+        out
+    }
+
+    pub fn decode(data: &[u8]) -> Result<Self> {
+        let mut reader = ByteReader::new(data);
+        let tag_count = reader.read_count()?;
+        let indexed_tags = (0..tag_count).map(|_| reader.read_varint()).collect::<Result<Vec<_>>>()?;
+        let segment_count = reader.read_count()?;
+        let segments = (0..segment_count).map(|_| SegmentEntry::decode(&mut reader)).collect::<Result<Vec<_>>>()?;
+        if !reader.is_empty() {
+            return Err(Error::CodecError("Trailing bytes after archive index".to_string()));
+        }
+        Ok(Self { indexed_tags, segments })
+    }
+}
+
+/// Decodes the item in the body of a packet, or returns `None` if the body
+/// cannot be read, e.g. because it is encrypted
+pub(crate) fn packet_item(packet: &Packet) -> Option<HtlvItem> {
+    if packet.header.has_tag_table() {
+        return None;
+    }
+    let body = match &packet.body {
+        DataBody::Raw(data) => Cow::Borrowed(data),
+        DataBody::Compressed(data) => {
+            let compressor = get_compressor(packet.header.get_compression_strategy().ok()?).ok()?;
+            Cow::Owned(compressor.decompress(data).ok()?)
+        }
+        DataBody::Encrypted(_) => return None,
+    };
+    let format = packet.header.get_wire_format().ok()?;
+    decode_item_with_format(&body, format).ok().map(|(item, _)| item)
+}
+
+/// Collects the scalar values of the fields with the given tags, anywhere in
+/// the item; the elements of an array are collected under the array's tag
+pub(crate) fn indexed_values<'a>(item: &'a HtlvItem, tags: &[u64], values: &mut Vec<(u64, &'a HtlvValue)>) {
+    let indexed = tags.contains(&item.tag);
+    match &item.value {
+        HtlvValue::Object(fields) => {
+            for field in fields {
+                indexed_values(field, tags, values);
+            }
+        }
+        HtlvValue::Array(elements) => {
+            for element in elements {
+                if indexed && index_key(item.tag, &element.value).is_some() {
+                    values.push((item.tag, &element.value));
+                }
+                indexed_values(element, tags, values);
+            }
+        }
+        value => {
+            if indexed {
+                values.push((item.tag, value));
+            }
+        }
+    }
+}
+
+/// Returns the bloom filter key of a field value, or `None` for complex values
+pub(crate) fn index_key(tag: u64, value: &HtlvValue) -> Option<Vec<u8>> {
+    match value {
+        HtlvValue::Array(_) | HtlvValue::Object(_) => None,
+        _ => encode_item(&HtlvItem::new(tag, value.clone())).ok(),
+    }
+}
+
+/// Reads varints and length-prefixed byte strings from a slice
+#[derive(Debug)]
+pub(crate) struct ByteReader<'a> {
+    cursor: WireCursor<'a>,
+}
+
+impl<'a> ByteReader<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Self { cursor: WireCursor::new(data) }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cursor.is_empty()
+    }
+
+    /// Returns the number of bytes read so far
+    pub fn position(&self) -> usize {
+        self.cursor.position()
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        self.cursor.read_u8("archive data")
+    }
+
+    pub fn read_varint(&mut self) -> Result<u64> {
+        self.cursor.read_varint("archive")
+    }
+
+    /// Reads an element count, rejecting counts larger than the remaining data
+    /// could hold
+    pub fn read_count(&mut self) -> Result<usize> {
+        let count = self.read_varint()?;
+        if count > self.cursor.remaining() as u64 {
+            return Err(Error::CodecError(format!("Invalid element count in archive data: {}", count)));
+        }
+        Ok(count as usize)
+    }
+
+    /// Reads a varint length followed by that many bytes
+    pub fn read_bytes(&mut self) -> Result<&'a [u8]> {
+        let length = self.read_count()?;
+        self.cursor.take(length as u64, "archive data")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::packet::MetadataHeader;
+    use std::io::Cursor;
+
+    const SCHEMA: &str = r#"{"id": "events", "name": "Events", "version": "1.0.0", "type": "object", "properties": {"user": {"type": "integer"}}}"#;
+
+    fn event_packet(timestamp: u64, user: u32) -> Packet {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U32(user)),
+            HtlvItem::new(3, HtlvValue::String("click".into())),
+        ]));
+        let header = MetadataHeader { schema_id: 7, timestamp, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(encode_item(&item).unwrap())).unwrap()
+    }
+
+    fn write_archive(packets: &[Packet]) -> Vec<u8> {
+        let config = ArchiveConfig { segment_packets: 4, indexed_tags: vec![2], ..ArchiveConfig::default() };
+        let mut writer = ArchiveWriter::with_config(Vec::new(), config);
+        writer.add_schema(7, SCHEMA.as_bytes().to_vec()).unwrap();
+        for packet in packets {
+            writer.append(packet).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn test_archive_roundtrip() {
+        let packets: Vec<Packet> = (0..10).map(|i| event_packet(1000 + i, i as u32)).collect();
+        let data = write_archive(&packets);
+        assert!(data.starts_with(ARCHIVE_MAGIC));
+        assert!(data.ends_with(FOOTER_MAGIC));
+
+        let mut reader = ArchiveReader::open(Cursor::new(data)).unwrap();
+        assert_eq!(reader.schemas().len(), 1);
+        assert_eq!(reader.schemas()[0].parse().unwrap().id, "events");
+        assert_eq!(reader.indexed_tags(), &[2]);
+
+        let segments = reader.segments().to_vec();
+        assert_eq!(segments.len(), 3);
+        assert_eq!(segments.iter().map(|s| s.packet_count).collect::<Vec<_>>(), vec![4, 4, 2]);
+        assert_eq!((segments[1].min_timestamp, segments[1].max_timestamp), (1004, 1007));
+        assert_eq!(segments[2].schema_ids, vec![7]);
+
+        assert_eq!(reader.read_all().unwrap(), packets);
+        assert_eq!(reader.read_segment(1).unwrap(), packets[4..8].to_vec());
+        assert!(reader.read_segment(3).is_err());
+    }
+
+    #[test]
+    fn test_archive_lookup() {
+        let packets: Vec<Packet> = (0..10).map(|i| event_packet(1000 + i, i as u32)).collect();
+        let mut reader = ArchiveReader::open(Cursor::new(write_archive(&packets))).unwrap();
+
+        // Bloom filters never miss a value that is present
+        assert!(reader.segments()[2].may_contain(2, &HtlvValue::U32(9)));
+        assert_eq!(reader.find(2, &HtlvValue::U32(5)).unwrap(), vec![packets[5].clone()]);
+        assert!(reader.find(2, &HtlvValue::U32(99)).unwrap().is_empty());
+
+        // Only the segment holding the value is read
+        let (found, stats) = reader.find_with_stats(2, &HtlvValue::U32(6)).unwrap();
+        assert_eq!(found, vec![packets[6].clone()]);
+        assert_eq!(reader.candidate_segments(2, &HtlvValue::U32(6)).unwrap(), vec![1]);
+        assert_eq!((stats.segments_total, stats.segments_read, stats.segments_skipped()), (3, 1, 2));
+        assert_eq!(stats.packets_scanned, 4);
+        assert_eq!(stats.false_positives, 3);
+        assert!(reader.candidate_segments(3, &HtlvValue::U32(6)).is_err());
+
+        assert_eq!(reader.segments_in_range(1005, 1006), vec![1]);
+        assert_eq!(reader.segments_in_range(0, 999), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn test_archive_rejects_corruption() {
+        let data = write_archive(&[event_packet(1, 1)]);
+        assert!(ArchiveReader::open(Cursor::new(data[..data.len() - 1].to_vec())).is_err());
+
+        let mut bad_magic = data.clone();
+        bad_magic[0] = b'X';
+        assert!(ArchiveReader::open(Cursor::new(bad_magic)).is_err());
+
+        let mut bad_offset = data.clone();
+        let footer = bad_offset.len() - FOOTER_SIZE;
+        bad_offset[footer..footer + 8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert!(ArchiveReader::open(Cursor::new(bad_offset)).is_err());
+    }
+}
@@ -0,0 +1,259 @@
+// Archive reader
+//
+// `ArchiveReader` loads the footer, index and schema section of an archive up
+// front and reads packet segments on demand, so lookups by timestamp or by
+// indexed field value only touch the segments that may match. Encrypted
+// segments are decrypted with the key manager set on the reader.
+
+use std::io::{Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::archive::crypto::open_segment;
+use crate::archive::{indexed_values, packet_item, ArchiveIndex, ArchiveSchema, ByteReader, SegmentEntry, ARCHIVE_MAGIC, ARCHIVE_VERSION, FOOTER_MAGIC, FOOTER_SIZE};
+use crate::codec::types::HtlvValue;
+use crate::encrypt::key_management::KeyManager;
+
+/// How much of an archive a lookup read
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LookupStats {
+    /// Segments in the archive
+    pub segments_total: usize,
+    /// Segments read because their bloom filter may contain the value
+    pub segments_read: usize,
+    /// Packets decoded in the segments read
+    pub packets_scanned: usize,
+    /// Packets scanned that did not hold the value
+    pub false_positives: usize,
+}
+
+impl LookupStats {
+    /// Returns the number of segments the bloom filters ruled out
+    pub fn segments_skipped(&self) -> usize {
+        self.segments_total - self.segments_read
+    }
+}
+
+/// Reads packets from an archive
+#[derive(Debug)]
+pub struct ArchiveReader<R: Read + Seek> {
+    reader: R,
+    schemas: Vec<ArchiveSchema>,
+    index: ArchiveIndex,
+    index_offset: u64,
+    keys: Option<Arc<KeyManager>>,
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    /// Opens an archive, reading its schemas and index
+    pub fn open(mut reader: R) -> Result<Self> {
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+        if archive_len < (ARCHIVE_MAGIC.len() + 1 + FOOTER_SIZE) as u64 {
+            return Err(Error::CodecError("Data too short for an archive".to_string()));
+        }
+
+        let mut footer = [0u8; FOOTER_SIZE];
+        reader.seek(SeekFrom::End(-(FOOTER_SIZE as i64)))?;
+        reader.read_exact(&mut footer)?;
+        if &footer[8..] != FOOTER_MAGIC {
+            return Err(Error::CodecError("Missing archive footer".to_string()));
+        }
+        let index_offset = u64::from_le_bytes(footer[..8].try_into().unwrap());
+        let index_end = archive_len - FOOTER_SIZE as u64;
+        if index_offset > index_end {
+            return Err(Error::CodecError(format!("Archive index offset out of range: {}", index_offset)));
+        }
+
+        let index = ArchiveIndex::decode(&read_range(&mut reader, index_offset, index_end - index_offset)?)?;
+        let data_start = index.segments.first().map_or(index_offset, |segment| segment.offset);
+        let mut expected_offset = data_start;
+        for segment in &index.segments {
+            let end = segment.offset.checked_add(segment.length);
+            if segment.offset != expected_offset || end.is_none_or(|end| end > index_offset) {
+                return Err(Error::CodecError(format!("Archive segment out of range at offset {}", segment.offset)));
+            }
+            expected_offset = segment.offset + segment.length;
+        }
+        if expected_offset != index_offset {
+            return Err(Error::CodecError("Archive segments do not reach the index".to_string()));
+        }
+
+        let header = read_range(&mut reader, 0, data_start)?;
+        let schemas = parse_header(&header)?;
+
+        Ok(Self { reader, schemas, index, index_offset, keys: None })
+    }
+
+    /// Sets the key manager holding the master keys of encrypted segments
+    pub fn set_key_manager(&mut self, keys: Arc<KeyManager>) {
+        self.keys = Some(keys);
+    }
+
+    /// Returns the schemas stored in the archive
+    pub fn schemas(&self) -> &[ArchiveSchema] {
+        &self.schemas
+    }
+
+    /// Returns the schema with the given ID
+    pub fn schema(&self, schema_id: u64) -> Option<&ArchiveSchema> {
+        self.schemas.iter().find(|schema| schema.schema_id == schema_id)
+    }
+
+    /// Returns the tags of the fields indexed in the bloom filters
+    pub fn indexed_tags(&self) -> &[u64] {
+        &self.index.indexed_tags
+    }
+
+    /// Returns the table of contents
+    pub fn segments(&self) -> &[SegmentEntry] {
+        &self.index.segments
+    }
+
+    /// Returns the total number of packets in the archive, including those
+    /// in shredded segments
+    pub fn packet_count(&self) -> u64 {
+        self.index.segments.iter().map(|segment| segment.packet_count).sum()
+    }
+
+    /// Reads the packets of a segment
+    ///
+    /// Fails for encrypted segments if no key manager holding their master
+    /// key is set, and for shredded segments.
+    pub fn read_segment(&mut self, segment: usize) -> Result<Vec<Packet>> {
+        let entry = self.index.segments.get(segment)
+            .ok_or_else(|| Error::CodecError(format!("Archive has no segment {}", segment)))?;
+        let (offset, length, packet_count) = (entry.offset, entry.length, entry.packet_count);
+
+        let mut data = read_range(&mut self.reader, offset, length)?;
+        if let Some(key) = &entry.key {
+            let keys = self.keys.as_deref().ok_or_else(|| {
+                Error::EncryptionError(format!("Archive segment {} is encrypted and no key manager is set", segment))
+            })?;
+            data = open_segment(keys, key, &data)
+                .map_err(|e| Error::EncryptionError(format!("Cannot decrypt archive segment {}: {}", segment, e)))?;
+        }
+        let mut reader = ByteReader::new(&data);
+        let mut packets = Vec::new();
+        while !reader.is_empty() {
+            packets.push(Packet::parse_packet(reader.read_bytes()?)?);
+        }
+        if packets.len() as u64 != packet_count {
+            return Err(Error::CodecError(format!(
+                "Archive segment {} holds {} packets, index says {}",
+                segment,
+                packets.len(),
+                packet_count
+            )));
+        }
+        Ok(packets)
+    }
+
+    /// Reads all packets of the archive, skipping shredded segments
+    pub fn read_all(&mut self) -> Result<Vec<Packet>> {
+        let mut packets = Vec::new();
+        for segment in 0..self.index.segments.len() {
+            if !self.index.segments[segment].is_shredded() {
+                packets.extend(self.read_segment(segment)?);
+            }
+        }
+        Ok(packets)
+    }
+
+    /// Returns the segments that may hold packets with timestamps in
+    /// `from..=to`; shredded segments are left out
+    pub fn segments_in_range(&self, from: u64, to: u64) -> Vec<usize> {
+        (0..self.index.segments.len())
+            .filter(|&segment| {
+                let entry = &self.index.segments[segment];
+                entry.overlaps(from, to) && !entry.is_shredded()
+            })
+            .collect()
+    }
+
+    /// Returns the segments whose bloom filter may contain the given value in
+    /// a field with the given tag
+    ///
+    /// Fails if the tag is not indexed in the archive.
+    pub fn candidate_segments(&self, tag: u64, value: &HtlvValue) -> Result<Vec<usize>> {
+        if !self.index.indexed_tags.contains(&tag) {
+            return Err(Error::IndexError(format!("Tag {} is not indexed in the archive", tag)));
+        }
+        Ok((0..self.index.segments.len())
+            .filter(|&segment| self.index.segments[segment].may_contain(tag, value))
+            .collect())
+    }
+
+    /// Returns the packets holding the given value in a field with the given
+    /// tag, reading only the candidate segments
+    ///
+    /// Fails if the tag is not indexed in the archive.
+    pub fn find(&mut self, tag: u64, value: &HtlvValue) -> Result<Vec<Packet>> {
+        self.find_with_stats(tag, value).map(|(packets, _)| packets)
+    }
+
+    /// Like `find`, also returning how much of the archive the lookup read
+    pub fn find_with_stats(&mut self, tag: u64, value: &HtlvValue) -> Result<(Vec<Packet>, LookupStats)> {
+        let candidates = self.candidate_segments(tag, value)?;
+        let mut stats = LookupStats {
+            segments_total: self.index.segments.len(),
+            segments_read: candidates.len(),
+            ..LookupStats::default()
+        };
+
+        let mut found = Vec::new();
+        for segment in candidates {
+            for packet in self.read_segment(segment)? {
+                stats.packets_scanned += 1;
+                let matches = packet_item(&packet).is_some_and(|item| {
+                    let mut values = Vec::new();
+                    indexed_values(&item, &[tag], &mut values);
+                    values.iter().any(|(_, candidate)| *candidate == value)
+                });
+                if matches {
+                    found.push(packet);
+                } else {
+                    stats.false_positives += 1;
+                }
+            }
+        }
+        Ok((found, stats))
+    }
+
+    /// Returns the index and its offset in the archive
+    pub(crate) fn into_index(self) -> (ArchiveIndex, u64) {
+        (self.index, self.index_offset)
+    }
+}
+
+/// Reads `length` bytes at `offset`
+fn read_range<R: Read + Seek>(reader: &mut R, offset: u64, length: u64) -> Result<Vec<u8>> {
+    let mut data = vec![0; length as usize];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Parses the header and schema section
+fn parse_header(data: &[u8]) -> Result<Vec<ArchiveSchema>> {
+    if !data.starts_with(ARCHIVE_MAGIC) {
+        return Err(Error::CodecError("Missing archive magic number".to_string()));
+    }
+    let mut reader = ByteReader::new(&data[ARCHIVE_MAGIC.len()..]);
+    let version = reader.read_u8()?;
+    if version != ARCHIVE_VERSION {
+        return Err(Error::CodecError(format!("Unsupported archive version: {}", version)));
+    }
+
+    let schema_count = reader.read_count()?;
+    let mut schemas = Vec::with_capacity(schema_count);
+    for _ in 0..schema_count {
+        let schema_id = reader.read_varint()?;
+        let document = reader.read_bytes()?.to_vec();
+        schemas.push(ArchiveSchema { schema_id, document });
+    }
+    if !reader.is_empty() {
+        return Err(Error::CodecError("Unexpected data between schemas and packets".to_string()));
+    }
+    Ok(schemas)
+}
@@ -0,0 +1,256 @@
+// Recovery of truncated or corrupted archives
+//
+// Packets carry no frame magic of their own, but every packet record in an
+// archive is a length prefix followed by a packet whose BLAKE3 checksum covers
+// its header and body. `repair` walks the data section record by record; when
+// a record does not check out it resynchronizes by retrying at each following
+// byte until a length prefix frames a packet with a valid checksum again. The
+// recovered packets are written to a new archive with a rebuilt index, and
+// the byte ranges that yielded nothing are reported.
+//
+// Encrypted segments are sealed as a whole, so their packets cannot be found
+// this way; their bytes are reported as unrecoverable.
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use crate::internal::error::Result;
+use crate::internal::packet::Packet;
+use crate::archive::{ArchiveConfig, ArchiveIndex, ArchiveSchema, ArchiveWriter, ByteReader, ARCHIVE_MAGIC, ARCHIVE_VERSION, FOOTER_MAGIC, FOOTER_SIZE};
+use crate::codec::varint::decode_varint;
+
+/// A byte range of a damaged archive from which nothing could be recovered
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DamagedRegion {
+    /// Offset of the range from the start of the file
+    pub offset: u64,
+    /// Length of the range in bytes
+    pub length: u64,
+}
+
+/// Outcome of a repair
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RepairReport {
+    /// Packets copied to the repaired archive
+    pub packets_recovered: usize,
+    /// Schemas copied to the repaired archive
+    pub schemas_recovered: usize,
+    /// Whether the footer and index of the damaged archive could be read;
+    /// if so, its indexed tags are kept
+    pub index_intact: bool,
+    /// Ranges of the header and data sections that held no readable packets
+    pub unrecoverable: Vec<DamagedRegion>,
+}
+
+impl RepairReport {
+    /// Returns the number of bytes that could not be recovered
+    pub fn bytes_lost(&self) -> u64 {
+        self.unrecoverable.iter().map(|region| region.length).sum()
+    }
+}
+
+/// Recovers the schemas and packets of a damaged archive and writes them to a
+/// new archive; returns the underlying writer and a report
+///
+/// `config` sets up the new archive. If the index of the damaged archive can
+/// still be read, its indexed tags replace those of `config`.
+pub fn repair<W: Write>(data: &[u8], out: W, mut config: ArchiveConfig) -> Result<(W, RepairReport)> {
+    let mut report = RepairReport::default();
+
+    let (schemas, data_start) = recover_schemas(data);
+    let data_end = match recover_index(data) {
+        Some((index, index_offset)) => {
+            report.index_intact = true;
+            config.indexed_tags = index.indexed_tags;
+            index_offset
+        }
+        None => data.len(),
+    };
+
+    let mut writer = ArchiveWriter::with_config(out, config);
+    for schema in schemas {
+        writer.add_schema(schema.schema_id, schema.document)?;
+        report.schemas_recovered += 1;
+    }
+
+    let mut position = data_start;
+    let mut damaged_from = None;
+    while position < data_end {
+        match read_record(&data[position..data_end]) {
+            Some((packet, length)) => {
+                if let Some(start) = damaged_from.take() {
+                    report.unrecoverable.push(region(start, position));
+                }
+                writer.append(&packet)?;
+                report.packets_recovered += 1;
+                position += length;
+            }
+            None => {
+                damaged_from.get_or_insert(position);
+                position += 1;
+            }
+        }
+    }
+    if let Some(start) = damaged_from {
+        report.unrecoverable.push(region(start, data_end));
+    }
+
+    Ok((writer.finish()?, report))
+}
+
+/// Repairs the archive file at `input` into a new file at `output`, with the
+/// default archive configuration
+pub fn repair_file(input: impl AsRef<Path>, output: impl AsRef<Path>) -> Result<RepairReport> {
+    let data = fs::read(input)?;
+    let out = BufWriter::new(File::create(output)?);
+    let (out, report) = repair(&data, out, ArchiveConfig::default())?;
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+    Ok(report)
+}
+
+/// Reads the schemas that are still intact; returns them and the offset where
+/// the search for packets starts, which is 0 if the header is damaged
+fn recover_schemas(data: &[u8]) -> (Vec<ArchiveSchema>, usize) {
+    let header_len = ARCHIVE_MAGIC.len() + 1;
+    if !data.starts_with(ARCHIVE_MAGIC) || data.get(ARCHIVE_MAGIC.len()) != Some(&ARCHIVE_VERSION) {
+        return (Vec::new(), 0);
+    }
+
+    let mut schemas = Vec::new();
+    let mut reader = ByteReader::new(&data[header_len..]);
+    let Ok(schema_count) = reader.read_count() else {
+        return (schemas, header_len);
+    };
+    let mut intact_end = header_len + reader.position();
+    for _ in 0..schema_count {
+        let Ok(schema_id) = reader.read_varint() else { break };
+        let Ok(document) = reader.read_bytes() else { break };
+        // A damaged length can swallow packets; only keep documents that parse
+        if serde_json::from_slice::<serde_json::Value>(document).is_err() {
+            break;
+        }
+        schemas.push(ArchiveSchema { schema_id, document: document.to_vec() });
+        intact_end = header_len + reader.position();
+    }
+    (schemas, intact_end)
+}
+
+/// Reads the footer and index, if intact; returns the index and its offset
+fn recover_index(data: &[u8]) -> Option<(ArchiveIndex, usize)> {
+    let footer_start = data.len().checked_sub(FOOTER_SIZE)?;
+    let footer = &data[footer_start..];
+    if &footer[8..] != FOOTER_MAGIC {
+        return None;
+    }
+    let index_offset = usize::try_from(u64::from_le_bytes(footer[..8].try_into().unwrap())).ok()?;
+    if index_offset > footer_start {
+        return None;
+    }
+    let index = ArchiveIndex::decode(&data[index_offset..footer_start]).ok()?;
+    Some((index, index_offset))
+}
+
+/// Reads a length-prefixed packet at the start of `data`; returns the packet
+/// and the length of the record, or `None` if the record is damaged
+fn read_record(data: &[u8]) -> Option<(Packet, usize)> {
+    let (length, prefix) = decode_varint(data).ok()?;
+    let end = prefix.checked_add(usize::try_from(length).ok()?)?;
+    let packet = Packet::parse_packet(data.get(prefix..end)?).ok()?;
+    Some((packet, end))
+}
+
+fn region(start: usize, end: usize) -> DamagedRegion {
+    DamagedRegion { offset: start as u64, length: (end - start) as u64 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchiveReader;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::{HtlvItem, HtlvValue};
+    use crate::internal::packet::{DataBody, MetadataHeader};
+    use std::io::Cursor;
+
+    const SCHEMA: &str = r#"{"id": "events", "name": "Events", "version": "1.0.0", "type": "object", "properties": {"user": {"type": "integer"}}}"#;
+
+    fn packet(timestamp: u64) -> Packet {
+        let item = HtlvItem::new(1, HtlvValue::String(format!("event {}", timestamp).into()));
+        let header = MetadataHeader { schema_id: 7, timestamp, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(encode_item(&item).unwrap())).unwrap()
+    }
+
+    fn archive(packets: &[Packet]) -> Vec<u8> {
+        let config = ArchiveConfig { segment_packets: 3, indexed_tags: vec![1], ..ArchiveConfig::default() };
+        let mut writer = ArchiveWriter::with_config(Vec::new(), config);
+        writer.add_schema(7, SCHEMA.as_bytes().to_vec()).unwrap();
+        for packet in packets {
+            writer.append(packet).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn record_offsets(data: &[u8]) -> Vec<usize> {
+        let reader = ArchiveReader::open(Cursor::new(data.to_vec())).unwrap();
+        let mut offsets = Vec::new();
+        for segment in reader.segments() {
+            let mut position = segment.offset as usize;
+            while position < (segment.offset + segment.length) as usize {
+                offsets.push(position);
+                position += read_record(&data[position..]).unwrap().1;
+            }
+        }
+        offsets
+    }
+
+    #[test]
+    fn test_repair_intact_archive() {
+        let packets: Vec<Packet> = (0..7).map(packet).collect();
+        let (repaired, report) = repair(&archive(&packets), Vec::new(), ArchiveConfig::default()).unwrap();
+        assert!(report.index_intact);
+        assert_eq!((report.packets_recovered, report.schemas_recovered, report.bytes_lost()), (7, 1, 0));
+
+        let mut reader = ArchiveReader::open(Cursor::new(repaired)).unwrap();
+        assert_eq!(reader.indexed_tags(), &[1]);
+        assert_eq!(reader.read_all().unwrap(), packets);
+    }
+
+    #[test]
+    fn test_repair_corrupted_and_truncated() {
+        let packets: Vec<Packet> = (0..7).map(packet).collect();
+        let mut data = archive(&packets);
+        let offsets = record_offsets(&data);
+
+        // Damage the body of the third packet, then cut the file in the middle
+        // of the sixth
+        data[offsets[2] + 10] ^= 0xFF;
+        data.truncate(offsets[5] + 5);
+
+        let (repaired, report) = repair(&data, Vec::new(), ArchiveConfig::default()).unwrap();
+        assert!(!report.index_intact);
+        assert_eq!(report.packets_recovered, 4);
+        assert_eq!(report.unrecoverable, vec![
+            DamagedRegion { offset: offsets[2] as u64, length: (offsets[3] - offsets[2]) as u64 },
+            DamagedRegion { offset: offsets[5] as u64, length: 5 },
+        ]);
+
+        let mut reader = ArchiveReader::open(Cursor::new(repaired)).unwrap();
+        assert_eq!(reader.schemas().len(), 1);
+        let expected: Vec<Packet> = [0, 1, 3, 4].iter().map(|&i| packets[i].clone()).collect();
+        assert_eq!(reader.read_all().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_repair_damaged_header() {
+        let packets: Vec<Packet> = (0..2).map(packet).collect();
+        let mut data = archive(&packets);
+        let first = record_offsets(&data)[0];
+        data[0] = b'X';
+
+        let (repaired, report) = repair(&data, Vec::new(), ArchiveConfig::default()).unwrap();
+        assert_eq!((report.packets_recovered, report.schemas_recovered), (2, 0));
+        assert_eq!(report.unrecoverable, vec![DamagedRegion { offset: 0, length: first as u64 }]);
+        assert_eq!(ArchiveReader::open(Cursor::new(repaired)).unwrap().read_all().unwrap(), packets);
+    }
+}
@@ -0,0 +1,342 @@
+// Live subscriptions to a packet log
+//
+// A `TailReader` wraps a `PacketLog`: every packet appended through it is
+// stored in the log and then offered to the subscribers. Each subscriber has a
+// filter; the header conditions (time range, schema IDs) are checked first, so
+// a packet is only decoded if some subscriber may want it, and it is decoded
+// once however many subscribers match. Matching items are collected into
+// batches and sent over a bounded channel per subscriber. When a channel is
+// full the reader either waits for the subscriber or drops the batch,
+// depending on the configured backpressure.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::internal::error::Result;
+use crate::internal::packet::{MetadataHeader, Packet};
+use crate::archive::{indexed_values, packet_item, PacketLog};
+use crate::codec::types::{HtlvItem, HtlvValue};
+
+/// Conditions a packet must meet to be delivered to a subscriber
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailFilter {
+    /// Smallest header timestamp delivered
+    pub from: u64,
+    /// Largest header timestamp delivered
+    pub to: u64,
+    /// Schema IDs delivered; empty for all schemas
+    pub schema_ids: Vec<u64>,
+    /// Field values the item must hold, as pairs of tag and value; a field
+    /// matches anywhere in the item, and every pair must match
+    pub fields: Vec<(u64, HtlvValue)>,
+}
+
+impl Default for TailFilter {
+    fn default() -> Self {
+        Self {
+            from: 0,
+            to: u64::MAX,
+            schema_ids: Vec::new(),
+            fields: Vec::new(),
+        }
+    }
+}
+
+impl TailFilter {
+    /// Returns true if a packet with the given header may be delivered; the
+    /// field conditions are not checked
+    pub fn matches_header(&self, header: &MetadataHeader) -> bool {
+        (self.from..=self.to).contains(&header.timestamp)
+            && (self.schema_ids.is_empty() || self.schema_ids.contains(&header.schema_id))
+    }
+
+    /// Returns true if a decoded item meets the field conditions
+    pub fn matches_item(&self, item: &HtlvItem) -> bool {
+        self.fields.iter().all(|(tag, value)| {
+            let mut values = Vec::new();
+            indexed_values(item, &[*tag], &mut values);
+            values.iter().any(|(_, candidate)| *candidate == value)
+        })
+    }
+}
+
+/// What a tail reader does when a subscriber's channel is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backpressure {
+    /// Wait until the subscriber takes a batch; appends block meanwhile
+    Block,
+    /// Drop the batch and count it as dropped
+    DropNewest,
+}
+
+/// Configuration of a tail reader
+#[derive(Debug, Clone)]
+pub struct TailConfig {
+    /// Items collected before a batch is sent
+    pub batch_size: usize,
+
+    /// Batches a subscriber's channel holds before backpressure applies
+    pub channel_capacity: usize,
+
+    /// What to do when a channel is full
+    pub backpressure: Backpressure,
+}
+
+impl Default for TailConfig {
+    fn default() -> Self {
+        Self {
+            batch_size: 64,
+            channel_capacity: 16,
+            backpressure: Backpressure::Block,
+        }
+    }
+}
+
+/// An item delivered to a subscriber, with the header of its packet
+#[derive(Debug, Clone, PartialEq)]
+pub struct TailRecord {
+    /// Header of the packet the item was decoded from
+    pub header: MetadataHeader,
+    /// The decoded item
+    pub item: HtlvItem,
+}
+
+/// Receiving end of a subscription
+///
+/// Dropping the subscription unsubscribes; the tail reader notices on its
+/// next delivery.
+#[derive(Debug)]
+pub struct Subscription {
+    receiver: Receiver<Vec<TailRecord>>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// Waits for the next batch; returns `None` once the tail reader is gone
+    /// and every batch has been received
+    pub fn recv(&self) -> Option<Vec<TailRecord>> {
+        self.receiver.recv().ok()
+    }
+
+    /// Returns the next batch if one is waiting
+    pub fn try_recv(&self) -> Option<Vec<TailRecord>> {
+        self.receiver.try_recv().ok()
+    }
+
+    /// Waits at most `timeout` for the next batch
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<Vec<TailRecord>> {
+        self.receiver.recv_timeout(timeout).ok()
+    }
+
+    /// Returns the number of batches dropped because the channel was full
+    pub fn dropped_batches(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug)]
+struct Subscriber {
+    filter: TailFilter,
+    sender: SyncSender<Vec<TailRecord>>,
+    pending: Vec<TailRecord>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl Subscriber {
+    /// Sends the pending batch; returns false if the subscription is gone
+    fn deliver(&mut self, backpressure: Backpressure) -> bool {
+        if self.pending.is_empty() {
+            return true;
+        }
+        let batch = std::mem::take(&mut self.pending);
+        match backpressure {
+            Backpressure::Block => self.sender.send(batch).is_ok(),
+            Backpressure::DropNewest => match self.sender.try_send(batch) {
+                Ok(()) => true,
+                Err(TrySendError::Full(_)) => {
+                    self.dropped.fetch_add(1, Ordering::Relaxed);
+                    true
+                }
+                Err(TrySendError::Disconnected(_)) => false,
+            },
+        }
+    }
+}
+
+/// A packet log that delivers appended packets to subscribers
+///
+/// Packets whose body cannot be decoded, e.g. encrypted ones, are stored but
+/// not delivered. With `Backpressure::Block`, subscribers must be drained from
+/// another thread than the one appending.
+#[derive(Debug)]
+pub struct TailReader {
+    log: PacketLog,
+    config: TailConfig,
+    subscribers: Vec<Subscriber>,
+}
+
+impl TailReader {
+    /// Creates a tail reader over the given log with the default configuration
+    pub fn new(log: PacketLog) -> Self {
+        Self::with_config(log, TailConfig::default())
+    }
+
+    /// Creates a tail reader over the given log with a custom configuration
+    pub fn with_config(log: PacketLog, config: TailConfig) -> Self {
+        Self { log, config, subscribers: Vec::new() }
+    }
+
+    /// Subscribes to the packets appended from now on that match `filter`
+    pub fn subscribe(&mut self, filter: TailFilter) -> Subscription {
+        let (sender, receiver) = mpsc::sync_channel(self.config.channel_capacity.max(1));
+        let dropped = Arc::new(AtomicU64::new(0));
+        self.subscribers.push(Subscriber { filter, sender, pending: Vec::new(), dropped: dropped.clone() });
+        Subscription { receiver, dropped }
+    }
+
+    /// Returns the number of live subscriptions
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers.len()
+    }
+
+    /// Appends a packet to the log and offers it to the subscribers
+    pub fn append(&mut self, packet: &Packet) -> Result<()> {
+        self.log.append(packet)?;
+
+        let header = &packet.header;
+        if !self.subscribers.iter().any(|subscriber| subscriber.filter.matches_header(header)) {
+            return Ok(());
+        }
+        let Some(item) = packet_item(packet) else {
+            return Ok(());
+        };
+
+        let (batch_size, backpressure) = (self.config.batch_size.max(1), self.config.backpressure);
+        self.subscribers.retain_mut(|subscriber| {
+            if !subscriber.filter.matches_header(header) || !subscriber.filter.matches_item(&item) {
+                return true;
+            }
+            subscriber.pending.push(TailRecord { header: header.clone(), item: item.clone() });
+            subscriber.pending.len() < batch_size || subscriber.deliver(backpressure)
+        });
+        Ok(())
+    }
+
+    /// Sends the partial batches of all subscribers and flushes the log
+    pub fn flush(&mut self) -> Result<()> {
+        let backpressure = self.config.backpressure;
+        self.subscribers.retain_mut(|subscriber| subscriber.deliver(backpressure));
+        self.log.flush()
+    }
+
+    /// Returns the underlying log, e.g. to query past packets
+    pub fn log(&self) -> &PacketLog {
+        &self.log
+    }
+
+    /// Sends the partial batches and returns the underlying log; the
+    /// subscriptions end
+    pub fn into_log(mut self) -> Result<PacketLog> {
+        self.flush()?;
+        Ok(self.log)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::LogConfig;
+    use crate::codec::encode::encode_item;
+    use crate::internal::packet::DataBody;
+    use std::fs;
+    use std::path::PathBuf;
+
+    fn packet(schema_id: u64, timestamp: u64, user: u32) -> Packet {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![HtlvItem::new(2, HtlvValue::U32(user))]));
+        let header = MetadataHeader { schema_id, timestamp, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(encode_item(&item).unwrap())).unwrap()
+    }
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tonitru-tail-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn timestamps(batch: &[TailRecord]) -> Vec<u64> {
+        batch.iter().map(|record| record.header.timestamp).collect()
+    }
+
+    #[test]
+    fn test_tail_filters_and_batches() {
+        let dir = test_dir("filters");
+        let config = TailConfig { batch_size: 2, ..TailConfig::default() };
+        let mut tail = TailReader::with_config(PacketLog::open(&dir, LogConfig::default()).unwrap(), config);
+
+        let all = tail.subscribe(TailFilter::default());
+        let user = tail.subscribe(TailFilter { fields: vec![(2, HtlvValue::U32(7))], ..TailFilter::default() });
+        let schema = tail.subscribe(TailFilter { schema_ids: vec![2], from: 20, ..TailFilter::default() });
+
+        for (schema_id, timestamp, user_id) in [(1, 10, 7), (2, 20, 3), (2, 30, 7), (1, 40, 5), (2, 50, 7)] {
+            tail.append(&packet(schema_id, timestamp, user_id)).unwrap();
+        }
+        assert_eq!(timestamps(&all.try_recv().unwrap()), vec![10, 20]);
+        assert_eq!(timestamps(&all.try_recv().unwrap()), vec![30, 40]);
+        assert!(all.try_recv().is_none());
+        assert_eq!(timestamps(&user.try_recv().unwrap()), vec![10, 30]);
+        assert_eq!(timestamps(&schema.try_recv().unwrap()), vec![20, 30]);
+
+        // Partial batches go out on flush
+        tail.flush().unwrap();
+        assert_eq!(timestamps(&all.try_recv().unwrap()), vec![50]);
+        let batch = user.try_recv().unwrap();
+        assert_eq!((timestamps(&batch), batch[0].item.tag), (vec![50], 1));
+        assert_eq!(timestamps(&schema.try_recv().unwrap()), vec![50]);
+
+        // Everything reached the log as well
+        assert_eq!(tail.log().query(0, 100).unwrap().len(), 5);
+
+        drop(user);
+        tail.append(&packet(1, 60, 7)).unwrap();
+        tail.flush().unwrap();
+        assert_eq!(tail.subscriber_count(), 2);
+
+        drop(tail);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_tail_backpressure() {
+        let dir = test_dir("backpressure");
+        let config = TailConfig { batch_size: 1, channel_capacity: 2, backpressure: Backpressure::DropNewest };
+        let mut tail = TailReader::with_config(PacketLog::open(&dir, LogConfig::default()).unwrap(), config);
+        let slow = tail.subscribe(TailFilter::default());
+        for timestamp in 0..5 {
+            tail.append(&packet(1, timestamp, 1)).unwrap();
+        }
+        assert_eq!(slow.dropped_batches(), 3);
+        assert_eq!(timestamps(&slow.try_recv().unwrap()), vec![0]);
+        assert_eq!(timestamps(&slow.try_recv().unwrap()), vec![1]);
+        assert!(slow.try_recv().is_none());
+
+        // A blocking reader waits for a subscriber on another thread
+        let config = TailConfig { batch_size: 1, channel_capacity: 1, backpressure: Backpressure::Block };
+        let mut tail = TailReader::with_config(tail.into_log().unwrap(), config);
+        let subscription = tail.subscribe(TailFilter::default());
+        let consumer = std::thread::spawn(move || {
+            let mut received = Vec::new();
+            while let Some(batch) = subscription.recv() {
+                received.extend(timestamps(&batch));
+            }
+            received
+        });
+        for timestamp in 10..20 {
+            tail.append(&packet(1, timestamp, 1)).unwrap();
+        }
+        drop(tail);
+        assert_eq!(consumer.join().unwrap(), (10..20).collect::<Vec<_>>());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,257 @@
+// Streaming archive writer
+//
+// `ArchiveWriter` writes packets straight to the underlying writer and keeps
+// only the table of contents in memory: per segment, the timestamp range, the
+// schema IDs and the bloom filter keys of the indexed fields. The index and
+// footer are written by `finish`. Encrypted segments are sealed as a whole, so
+// with encryption the packets of the open segment are buffered until it
+// closes.
+
+use std::collections::{BTreeSet, HashSet};
+use std::io::Write;
+use std::sync::Arc;
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::archive::crypto::SegmentEncryption;
+use crate::archive::{index_key, indexed_values, packet_item, ArchiveIndex, ArchiveSchema, BloomFilter, SegmentEntry, SegmentKey, ARCHIVE_MAGIC, ARCHIVE_VERSION, FOOTER_MAGIC};
+use crate::codec::varint::encode_varint;
+use crate::encrypt::key_management::KeyManager;
+
+/// Configuration for writing archives
+#[derive(Debug, Clone)]
+pub struct ArchiveConfig {
+    /// Number of packets per segment
+    pub segment_packets: usize,
+
+    /// Tags of the fields whose values are indexed in bloom filters
+    pub indexed_tags: Vec<u64>,
+
+    /// Target false positive rate of the bloom filters
+    pub false_positive_rate: f64,
+}
+
+impl Default for ArchiveConfig {
+    fn default() -> Self {
+        Self {
+            segment_packets: 1024,
+            indexed_tags: Vec::new(),
+            false_positive_rate: 0.01,
+        }
+    }
+}
+
+/// A segment that is still being written
+#[derive(Debug)]
+struct OpenSegment {
+    offset: u64,
+    length: u64,
+    packet_count: u64,
+    min_timestamp: u64,
+    max_timestamp: u64,
+    schema_ids: BTreeSet<u64>,
+    keys: HashSet<Vec<u8>>,
+    fully_indexed: bool,
+    /// Packet records not written yet, for encrypted segments
+    buffer: Vec<u8>,
+}
+
+impl OpenSegment {
+    fn new(offset: u64) -> Self {
+        Self {
+            offset,
+            length: 0,
+            packet_count: 0,
+            min_timestamp: u64::MAX,
+            max_timestamp: 0,
+            schema_ids: BTreeSet::new(),
+            keys: HashSet::new(),
+            fully_indexed: true,
+            buffer: Vec::new(),
+        }
+    }
+
+    fn close(self, false_positive_rate: f64, key: Option<SegmentKey>) -> SegmentEntry {
+        let mut bloom = BloomFilter::new(self.keys.len(), false_positive_rate);
+        for key in &self.keys {
+            bloom.insert(key);
+        }
+        SegmentEntry {
+            offset: self.offset,
+            length: self.length,
+            packet_count: self.packet_count,
+            min_timestamp: self.min_timestamp,
+            max_timestamp: self.max_timestamp,
+            schema_ids: self.schema_ids.into_iter().collect(),
+            fully_indexed: self.fully_indexed,
+            key,
+            bloom,
+        }
+    }
+}
+
+/// Writes packets into an archive
+#[derive(Debug)]
+pub struct ArchiveWriter<W: Write> {
+    writer: W,
+    config: ArchiveConfig,
+    schemas: Vec<ArchiveSchema>,
+    /// Whether the header and schema section have been written
+    started: bool,
+    /// Bytes written so far
+    offset: u64,
+    segments: Vec<SegmentEntry>,
+    current: Option<OpenSegment>,
+    encryption: Option<SegmentEncryption>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    /// Creates a writer with the default configuration
+    pub fn new(writer: W) -> Self {
+        Self::with_config(writer, ArchiveConfig::default())
+    }
+
+    /// Creates a writer with a custom configuration
+    pub fn with_config(writer: W, config: ArchiveConfig) -> Self {
+        Self {
+            writer,
+            config,
+            schemas: Vec::new(),
+            started: false,
+            offset: 0,
+            segments: Vec::new(),
+            current: None,
+            encryption: None,
+        }
+    }
+
+    /// Encrypts the segments of the archive, each with a fresh data key
+    /// wrapped by the given master key
+    ///
+    /// The master key must be a symmetric key held by `keys`. Like schemas,
+    /// encryption must be set up before the first packet.
+    pub fn set_encryption(&mut self, keys: Arc<KeyManager>, master_key_id: &str) -> Result<()> {
+        if self.started {
+            return Err(Error::EncryptionError("Encryption must be set up before the first packet".to_string()));
+        }
+        self.encryption = Some(SegmentEncryption::new(keys, master_key_id)?);
+        Ok(())
+    }
+
+    /// Adds a schema to the archive
+    ///
+    /// Schemas precede the packets in the archive, so they must be added
+    /// before the first packet.
+    pub fn add_schema(&mut self, schema_id: u64, document: Vec<u8>) -> Result<()> {
+        if self.started {
+            return Err(Error::CodecError("Schemas must be added before the first packet".to_string()));
+        }
+        if self.schemas.iter().any(|schema| schema.schema_id == schema_id) {
+            return Err(Error::CodecError(format!("Duplicate schema in archive: {}", schema_id)));
+        }
+        self.schemas.push(ArchiveSchema { schema_id, document });
+        Ok(())
+    }
+
+    /// Appends a packet to the current segment, starting a new segment when
+    /// the current one is full
+    pub fn append(&mut self, packet: &Packet) -> Result<()> {
+        self.start()?;
+
+        let encoded = packet.encode_packet()?;
+        let mut record = encode_varint(encoded.len() as u64);
+        record.extend_from_slice(&encoded);
+
+        let keys = self.index_keys(packet);
+        let offset = self.offset;
+        let segment = self.current.get_or_insert_with(|| OpenSegment::new(offset));
+        if self.encryption.is_some() {
+            segment.buffer.extend_from_slice(&record);
+        } else {
+            self.writer.write_all(&record)?;
+            segment.length += record.len() as u64;
+            self.offset += record.len() as u64;
+        }
+        segment.packet_count += 1;
+        segment.min_timestamp = segment.min_timestamp.min(packet.header.timestamp);
+        segment.max_timestamp = segment.max_timestamp.max(packet.header.timestamp);
+        segment.schema_ids.insert(packet.header.schema_id);
+        match keys {
+            Some(keys) => segment.keys.extend(keys),
+            None => segment.fully_indexed = false,
+        }
+        let full = segment.packet_count as usize >= self.config.segment_packets.max(1);
+
+        if full {
+            self.close_segment()?;
+        }
+        Ok(())
+    }
+
+    /// Writes the index and footer and returns the underlying writer
+    pub fn finish(mut self) -> Result<W> {
+        self.start()?;
+        self.close_segment()?;
+
+        let index = ArchiveIndex {
+            indexed_tags: self.config.indexed_tags.clone(),
+            segments: std::mem::take(&mut self.segments),
+        };
+        self.writer.write_all(&index.encode())?;
+        self.writer.write_all(&self.offset.to_le_bytes())?;
+        self.writer.write_all(FOOTER_MAGIC)?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Writes the header and schema section if not done yet
+    fn start(&mut self) -> Result<()> {
+        if self.started {
+            return Ok(());
+        }
+        self.started = true;
+
+        let mut header = ARCHIVE_MAGIC.to_vec();
+        header.push(ARCHIVE_VERSION);
+        header.extend_from_slice(&encode_varint(self.schemas.len() as u64));
+        for schema in &self.schemas {
+            header.extend_from_slice(&encode_varint(schema.schema_id));
+            header.extend_from_slice(&encode_varint(schema.document.len() as u64));
+            header.extend_from_slice(&schema.document);
+        }
+        self.writer.write_all(&header)?;
+        self.offset = header.len() as u64;
+        Ok(())
+    }
+
+    fn close_segment(&mut self) -> Result<()> {
+        let Some(mut segment) = self.current.take() else {
+            return Ok(());
+        };
+        let key = match &self.encryption {
+            Some(encryption) => {
+                let (sealed, key) = encryption.seal(&segment.buffer)?;
+                self.writer.write_all(&sealed)?;
+                segment.offset = self.offset;
+                segment.length = sealed.len() as u64;
+                self.offset += segment.length;
+                Some(key)
+            }
+            None => None,
+        };
+        self.segments.push(segment.close(self.config.false_positive_rate, key));
+        Ok(())
+    }
+
+    /// Returns the bloom filter keys of the indexed fields of a packet, or
+    /// `None` if its body cannot be read
+    fn index_keys(&self, packet: &Packet) -> Option<Vec<Vec<u8>>> {
+        if self.config.indexed_tags.is_empty() {
+            return Some(Vec::new());
+        }
+        let item = packet_item(packet)?;
+        let mut values = Vec::new();
+        indexed_values(&item, &self.config.indexed_tags, &mut values);
+        Some(values.into_iter().filter_map(|(tag, value)| index_key(tag, value)).collect())
+    }
+}
@@ -0,0 +1,291 @@
+// Conversions between Rust values and HtlvValue
+//
+// `ToHtlvValue` and `FromHtlvValue` are what `#[derive(Encode, Decode)]` from
+// the `derive` feature builds on: a derived struct converts each field with
+// them and becomes an Object whose items are tagged by the `#[htlv(tag = N)]`
+// attributes. They are implemented for the primitive types, strings, bytes,
+// vectors and options, and by the derive for the structs themselves, so
+// derived structs nest.
+
+use bytes::Bytes;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::internal::error::{Error, Result};
+
+/// Converts a value into an `HtlvValue`.
+pub trait ToHtlvValue {
+    /// Returns the value as an `HtlvValue`.
+    fn to_htlv_value(&self) -> Result<HtlvValue>;
+}
+
+/// Converts an `HtlvValue` back into a value.
+pub trait FromHtlvValue: Sized {
+    /// Reads the value from an `HtlvValue`.
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self>;
+
+    /// Returns the value of a struct field whose tag is absent; an error unless overridden.
+    fn from_missing(field: &str) -> Result<Self> {
+        Err(Error::CodecError(format!("Missing field '{}'", field)))
+    }
+}
+
+fn type_mismatch(expected: &str, value: &HtlvValue) -> Error {
+    Error::CodecError(format!("Expected {}, found {:?}", expected, value.value_type()))
+}
+
+/// Returns the items of an Object.
+pub fn object_items<'a>(value: &'a HtlvValue, name: &str) -> Result<&'a [HtlvItem]> {
+    match value {
+        HtlvValue::Object(items) => Ok(items),
+        other => Err(type_mismatch(&format!("an Object for {}", name), other)),
+    }
+}
+
+/// Reads the field with the given tag from the items of an Object.
+pub fn field<T: FromHtlvValue>(items: &[HtlvItem], tag: u64, name: &str) -> Result<T> {
+    match items.iter().find(|item| item.tag == tag) {
+        Some(item) => T::from_htlv_value(&item.value)
+            .map_err(|e| Error::CodecError(format!("Field '{}' (tag {}): {}", name, tag, e))),
+        None => T::from_missing(name),
+    }
+}
+
+macro_rules! integer_conversions {
+    ($($ty:ty => $variant:ident),*) => {
+        $(
+            impl ToHtlvValue for $ty {
+                fn to_htlv_value(&self) -> Result<HtlvValue> {
+                    Ok(HtlvValue::$variant(*self))
+                }
+            }
+
+            impl FromHtlvValue for $ty {
+                fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+                    let wide: i128 = match value.as_scalar() {
+                        HtlvValue::U8(v) => *v as i128,
+                        HtlvValue::U16(v) => *v as i128,
+                        HtlvValue::U32(v) => *v as i128,
+                        HtlvValue::U64(v) => *v as i128,
+                        HtlvValue::I8(v) => *v as i128,
+                        HtlvValue::I16(v) => *v as i128,
+                        HtlvValue::I32(v) => *v as i128,
+                        HtlvValue::I64(v) => *v as i128,
+                        other => return Err(type_mismatch(stringify!($ty), other)),
+                    };
+                    <$ty>::try_from(wide)
+                        .map_err(|_| Error::CodecError(format!("Value {} out of range for {}", wide, stringify!($ty))))
+                }
+            }
+        )*
+    };
+}
+
+integer_conversions!(
+    u8 => U8, u16 => U16, u32 => U32, u64 => U64,
+    i8 => I8, i16 => I16, i32 => I32, i64 => I64
+);
+
+impl ToHtlvValue for f32 {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        Ok(HtlvValue::F32(*self))
+    }
+}
+
+impl FromHtlvValue for f32 {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        match value.as_scalar() {
+            HtlvValue::F32(v) => Ok(*v),
+            other => Err(type_mismatch("f32", other)),
+        }
+    }
+}
+
+impl ToHtlvValue for f64 {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        Ok(HtlvValue::F64(*self))
+    }
+}
+
+impl FromHtlvValue for f64 {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        match value.as_scalar() {
+            HtlvValue::F64(v) => Ok(*v),
+            HtlvValue::F32(v) => Ok(*v as f64),
+            other => Err(type_mismatch("f64", other)),
+        }
+    }
+}
+
+impl ToHtlvValue for bool {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Bool(*self))
+    }
+}
+
+impl FromHtlvValue for bool {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        match value {
+            HtlvValue::Bool(v) => Ok(*v),
+            other => Err(type_mismatch("bool", other)),
+        }
+    }
+}
+
+impl ToHtlvValue for String {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        Ok(HtlvValue::String(Bytes::copy_from_slice(self.as_bytes())))
+    }
+}
+
+impl FromHtlvValue for String {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        match value {
+            HtlvValue::String(v) => String::from_utf8(v.to_vec())
+                .map_err(|e| Error::CodecError(format!("Invalid UTF-8 sequence for String value: {}", e))),
+            other => Err(type_mismatch("String", other)),
+        }
+    }
+}
+
+impl ToHtlvValue for Bytes {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Bytes(self.clone()))
+    }
+}
+
+impl FromHtlvValue for Bytes {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        match value {
+            HtlvValue::Bytes(v) => Ok(v.clone()),
+            other => Err(type_mismatch("Bytes", other)),
+        }
+    }
+}
+
+impl ToHtlvValue for HtlvValue {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        Ok(self.clone())
+    }
+}
+
+impl FromHtlvValue for HtlvValue {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        Ok(value.clone())
+    }
+}
+
+impl<T: ToHtlvValue> ToHtlvValue for Vec<T> {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        let items = self.iter()
+            .map(|element| element.to_htlv_value().map(|value| HtlvItem::new(0, value)))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(HtlvValue::Array(items))
+    }
+}
+
+impl<T: FromHtlvValue> FromHtlvValue for Vec<T> {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        match value {
+            HtlvValue::Array(items) => items.iter().map(|item| T::from_htlv_value(&item.value)).collect(),
+            other => Err(type_mismatch("an Array", other)),
+        }
+    }
+}
+
+impl<T: ToHtlvValue> ToHtlvValue for Option<T> {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        match self {
+            Some(value) => value.to_htlv_value(),
+            None => Ok(HtlvValue::Null),
+        }
+    }
+}
+
+impl<T: FromHtlvValue> FromHtlvValue for Option<T> {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        match value {
+            HtlvValue::Null => Ok(None),
+            other => T::from_htlv_value(other).map(Some),
+        }
+    }
+
+    fn from_missing(_field: &str) -> Result<Self> {
+        Ok(None)
+    }
+}
+
+impl<T: ToHtlvValue + ?Sized> ToHtlvValue for Box<T> {
+    fn to_htlv_value(&self) -> Result<HtlvValue> {
+        (**self).to_htlv_value()
+    }
+}
+
+impl<T: FromHtlvValue> FromHtlvValue for Box<T> {
+    fn from_htlv_value(value: &HtlvValue) -> Result<Self> {
+        T::from_htlv_value(value).map(Box::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{Decode, Encode};
+    use bytes::BytesMut;
+
+    #[derive(Debug, PartialEq, tonitru_derive::Encode, tonitru_derive::Decode)]
+    struct Address {
+        #[htlv(tag = 1)]
+        city: String,
+        #[htlv(tag = 2)]
+        zip: Option<u32>,
+    }
+
+    #[derive(Debug, PartialEq, tonitru_derive::Encode, tonitru_derive::Decode)]
+    struct User {
+        #[htlv(tag = 1)]
+        id: u64,
+        #[htlv(tag = 5)]
+        name: String,
+        #[htlv(tag = 3)]
+        scores: Vec<i16>,
+        #[htlv(tag = 4)]
+        address: Address,
+        #[htlv(tag = 6)]
+        avatar: Option<Bytes>,
+    }
+
+    fn user() -> User {
+        User {
+            id: 7,
+            name: "Ada".to_string(),
+            scores: vec![-3, 12],
+            address: Address { city: "London".to_string(), zip: Some(1815) },
+            avatar: None,
+        }
+    }
+
+    #[test]
+    fn test_derived_struct_maps_fields_to_tags() {
+        let value = user().to_htlv_value().unwrap();
+        let items = object_items(&value, "User").unwrap();
+        assert_eq!(items.iter().map(|item| item.tag).collect::<Vec<_>>(), vec![1, 5, 3, 4, 6]);
+        assert_eq!(items[1].value, HtlvValue::String(Bytes::from_static(b"Ada")));
+        assert_eq!(User::from_htlv_value(&value).unwrap(), user());
+    }
+
+    #[test]
+    fn test_derived_encode_decode_round_trip() {
+        let mut buf = BytesMut::new();
+        user().encode(&mut buf).unwrap();
+        let (decoded, bytes_read) = User::decode(&buf).unwrap();
+        assert_eq!(decoded, user());
+        assert_eq!(bytes_read, buf.len());
+
+        // Absent Option fields decode as None, other absent fields fail
+        let partial = HtlvValue::Object(vec![HtlvItem::new(1, HtlvValue::String(Bytes::from_static(b"Oslo")))]);
+        assert_eq!(Address::from_htlv_value(&partial).unwrap(), Address { city: "Oslo".to_string(), zip: None });
+        let err = User::from_htlv_value(&HtlvValue::Object(vec![HtlvItem::new(1, HtlvValue::U64(1))])).unwrap_err();
+        assert!(err.to_string().contains("Missing field 'name'"));
+        assert!(u8::from_htlv_value(&HtlvValue::U16(256)).is_err());
+    }
+}
@@ -3,12 +3,14 @@
 // This module contains functions for decoding basic HTLV values.
 
 use crate::codec::types::{HtlvValue, HtlvValueType};
+use crate::codec::decode::type_table::{self, ValueKind};
 use crate::internal::error::{Error, Result};
 // Removed unused import: use crate::codec::types::HtlvItem; // Import HtlvItem for tests
 
 /// Decodes a basic HTLV value from a byte slice.
 ///
-/// This function handles the decoding of single basic types (excluding batch decodable types).
+/// This function handles the decoding of single basic types (excluding batch decodable types),
+/// dispatching through the type table.
 ///
 /// Arguments:
 /// * `value_type`: The `HtlvValueType` of the value to decode.
@@ -18,58 +20,18 @@ use crate::internal::error::{Error, Result};
 /// Returns:
 /// A `Result` containing the decoded `HtlvValue` or an `Error` if decoding fails.
 pub fn decode_basic_value(value_type: HtlvValueType, length: u64, data: &[u8]) -> Result<HtlvValue> {
-    match value_type {
-        HtlvValueType::Null => {
-            if length != 0 {
-                return Err(Error::CodecError(format!("Invalid length for Null value: {}", length)));
-            }
-            Ok(HtlvValue::Null)
-        }
-        HtlvValueType::Bool => {
-            if length != 1 {
-                return Err(Error::CodecError(format!("Invalid length for Bool value: {}", length)));
-            }
-            if data.is_empty() {
-                return Err(Error::CodecError("Incomplete data for Bool value".to_string()));
-            }
-            Ok(HtlvValue::Bool(data[0] != 0))
-        }
-        HtlvValueType::U8 => {
-            if length != 1 {
-                return Err(Error::CodecError(format!("Invalid length for U8 value: {}", length)));
-            }
-            if data.is_empty() {
-                return Err(Error::CodecError("Incomplete data for U8 value".to_string()));
-            }
-            Ok(HtlvValue::U8(data[0]))
-        }
-        HtlvValueType::I8 => {
-            if length != 1 {
-                return Err(Error::CodecError(format!("Invalid length for I8 value: {}", length)));
-            }
-            if data.is_empty() {
-                return Err(Error::CodecError("Incomplete data for I8 value".to_string()));
-            }
-            Ok(HtlvValue::I8(data[0] as i8))
-        }
-        HtlvValueType::Bytes => {
-            // Bytes type can have any length
-            Ok(HtlvValue::Bytes(bytes::Bytes::copy_from_slice(data)))
-        }
-        HtlvValueType::String => {
-            // String type can have any length
-            let s = String::from_utf8(data.to_vec())
-                .map_err(|e| Error::CodecError(format!("Invalid UTF-8 sequence for String value: {}", e)))?;
-            Ok(HtlvValue::String(bytes::Bytes::from(s)))
+    let handler = type_table::handler(value_type);
+    match (handler.kind, handler.decode) {
+        (ValueKind::Basic, Some(decode)) => {
+            (handler.validate)(value_type, length)?;
+            decode(data)
         }
         // Batch decodable types are handled in batch_value_decoder
-        HtlvValueType::U16 | HtlvValueType::U32 | HtlvValueType::U64 |
-        HtlvValueType::I16 | HtlvValueType::I32 | HtlvValueType::I64 |
-        HtlvValueType::F32 | HtlvValueType::F64 => {
+        (ValueKind::Batch, _) => {
              Err(Error::CodecError(format!("Batch decodable type {:?} should be handled by batch_value_decoder", value_type)))
         }
         // Complex types are handled elsewhere
-        HtlvValueType::Array | HtlvValueType::Object => {
+        _ => {
             Err(Error::CodecError(format!("Complex type {:?} should be handled by complex_value_handler", value_type)))
         }
         // Note: Large field types are handled by large_field_handler.rs
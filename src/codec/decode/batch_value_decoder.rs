@@ -3,6 +3,10 @@
 use crate::internal::error::Result;
 use crate::codec::types::{HtlvValueType, HtlvValue};
 use crate::codec::decode::pipeline_processor;
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "simd"))]
+use crate::codec::decode::simd_optimizations::BatchResult;
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "simd"))]
+use crate::codec::types::HtlvItem;
 
 /// Decodes a batch of HTLV values based on the element type, total length, and raw data.
 /// This function encapsulates the decoding logic for batch decodable basic types.
@@ -18,15 +22,85 @@ use crate::codec::decode::pipeline_processor;
 /// - SIMD acceleration when available
 /// - Better error detection and reporting
 /// - More efficient memory usage
+///
+/// With the `simd` feature, u32, u64, f32 and f64 batches skip the pipeline and
+/// are decoded by the SIMD kernels: on x86_64 the widest of AVX-512, AVX2 and
+/// SSE4.1 that the CPU supports, on aarch64 NEON.
 pub fn decode_batch_value(
     element_type: HtlvValueType,
     length: u64,
     raw_value_slice: &[u8],
 ) -> Result<HtlvValue> {
+    #[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "simd"))]
+    if let Some(value) = decode_batch_value_simd(element_type, raw_value_slice)? {
+        return Ok(value);
+    }
+
     // Use the pipeline processor to handle the batch decoding
     pipeline_processor::process_batch_value(element_type, length, raw_value_slice)
 }
 
+/// Decodes u32, u64, f32 and f64 batches with the kernels of the instruction
+/// set reported by `get_simd_instruction_set`: the widest of AVX-512, AVX2 and
+/// SSE4.1 on x86_64, NEON on aarch64. Returns None for the batches left to the
+/// pipeline: other element types, SSE4.1 batches of 8-byte elements, or SIMD
+/// turned off.
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "simd"))]
+fn decode_batch_value_simd(element_type: HtlvValueType, data: &[u8]) -> Result<Option<HtlvValue>> {
+    use crate::codec::decode::simd_optimizations::get_simd_instruction_set;
+    #[cfg(target_arch = "aarch64")]
+    use crate::codec::decode::simd_optimizations::aarch64::neon;
+    #[cfg(target_arch = "x86_64")]
+    use crate::codec::decode::simd_optimizations::x86_64::{avx2, avx512, sse41};
+    use HtlvValueType as T;
+
+    let Some(instruction_set) = get_simd_instruction_set() else {
+        return Ok(None);
+    };
+    match (instruction_set, element_type) {
+        #[cfg(target_arch = "x86_64")]
+        ("avx512f", T::U32) => batch_array(avx512::decode_u32_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("avx512f", T::U64) => batch_array(avx512::decode_u64_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("avx512f", T::F32) => batch_array(avx512::decode_f32_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("avx512f", T::F64) => batch_array(avx512::decode_f64_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("avx2", T::U32) => batch_array(avx2::decode_u32_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("avx2", T::U64) => batch_array(avx2::decode_u64_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("avx2", T::F32) => batch_array(avx2::decode_f32_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("avx2", T::F64) => batch_array(avx2::decode_f64_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("sse4.1", T::U32) => batch_array(sse41::decode_u32_batch_simd(data)),
+        #[cfg(target_arch = "x86_64")]
+        ("sse4.1", T::F32) => batch_array(sse41::decode_f32_batch_simd(data)),
+        #[cfg(target_arch = "aarch64")]
+        ("neon", T::U32) => batch_array(neon::decode_u32_batch_simd(data)),
+        #[cfg(target_arch = "aarch64")]
+        ("neon", T::U64) => batch_array(neon::decode_u64_batch_simd(data)),
+        #[cfg(target_arch = "aarch64")]
+        ("neon", T::F32) => batch_array(neon::decode_f32_batch_simd(data)),
+        #[cfg(target_arch = "aarch64")]
+        ("neon", T::F64) => batch_array(neon::decode_f64_batch_simd(data)),
+        _ => Ok(None),
+    }
+}
+
+/// Turns the output of a SIMD kernel into an Array of tag-0 items.
+#[cfg(all(any(target_arch = "x86_64", target_arch = "aarch64"), feature = "simd"))]
+fn batch_array<T>(decoded: Result<(BatchResult<'_, T>, usize)>) -> Result<Option<HtlvValue>>
+where
+    T: pipeline_processor::PipelineProcessor<DecodedType = T>,
+{
+    let (values, _) = decoded?;
+    let items = T::dispatch(&values).into_iter().map(|value| HtlvItem::new(0, value)).collect();
+    Ok(Some(HtlvValue::Array(items)))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,307 @@
+// Zero-copy decoding into borrowed views
+//
+// `decode_item_ref` builds an `HtlvItemRef` tree whose Bytes, String and
+// numeric batch values point into the input buffer instead of being copied.
+// Only the child lists of arrays and objects are allocated. The tree is built
+// from the events of the pull decoder, so it shares its bounds and depth
+// checks. Items appear as they are on the wire: the header and shards of a
+// large field are separate items, as with `decode_item`.
+
+use crate::internal::error::{Error, Result};
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use super::pull::{PullDecoder, PullEvent};
+use super::type_table;
+use bytes::Bytes;
+
+/// An HTLV item borrowing its values from the buffer it was decoded from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HtlvItemRef<'a> {
+    pub tag: u64,
+    pub value: HtlvValueRef<'a>,
+}
+
+/// The value of an `HtlvItemRef`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HtlvValueRef<'a> {
+    Null,
+    Bool(bool),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    F32(f32),
+    F64(f64),
+    Bytes(&'a [u8]),
+    String(&'a str),
+    /// Several numeric values of the same type stored in one item
+    Batch(BatchRef<'a>),
+    Array(Vec<HtlvItemRef<'a>>),
+    Object(Vec<HtlvItemRef<'a>>),
+}
+
+/// A batch of little-endian numeric values borrowed from the input.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BatchRef<'a> {
+    element_type: HtlvValueType,
+    data: &'a [u8],
+}
+
+impl<'a> BatchRef<'a> {
+    /// Returns the type of the elements.
+    pub fn element_type(&self) -> HtlvValueType {
+        self.element_type
+    }
+
+    /// Returns the raw little-endian bytes of the batch.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.data
+    }
+
+    /// Returns the number of elements.
+    pub fn len(&self) -> usize {
+        self.data.len() / element_width(self.element_type)
+    }
+
+    /// Returns true if the batch has no elements.
+    pub fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    /// Returns the element at `index`.
+    pub fn get(&self, index: usize) -> Option<HtlvValueRef<'a>> {
+        let width = element_width(self.element_type);
+        let start = index.checked_mul(width)?;
+        let bytes = self.data.get(start..start + width)?;
+        scalar_value(self.element_type, bytes)
+    }
+
+    /// Returns an iterator over the elements.
+    pub fn iter(&self) -> impl Iterator<Item = HtlvValueRef<'a>> + 'a {
+        let element_type = self.element_type;
+        self.data
+            .chunks_exact(element_width(element_type))
+            .filter_map(move |bytes| scalar_value(element_type, bytes))
+    }
+}
+
+impl<'a> HtlvValueRef<'a> {
+    /// Returns the corresponding HtlvValueType; for batches, the element type.
+    pub fn value_type(&self) -> HtlvValueType {
+        match self {
+            HtlvValueRef::Null => HtlvValueType::Null,
+            HtlvValueRef::Bool(_) => HtlvValueType::Bool,
+            HtlvValueRef::U8(_) => HtlvValueType::U8,
+            HtlvValueRef::U16(_) => HtlvValueType::U16,
+            HtlvValueRef::U32(_) => HtlvValueType::U32,
+            HtlvValueRef::U64(_) => HtlvValueType::U64,
+            HtlvValueRef::I8(_) => HtlvValueType::I8,
+            HtlvValueRef::I16(_) => HtlvValueType::I16,
+            HtlvValueRef::I32(_) => HtlvValueType::I32,
+            HtlvValueRef::I64(_) => HtlvValueType::I64,
+            HtlvValueRef::F32(_) => HtlvValueType::F32,
+            HtlvValueRef::F64(_) => HtlvValueType::F64,
+            HtlvValueRef::Bytes(_) => HtlvValueType::Bytes,
+            HtlvValueRef::String(_) => HtlvValueType::String,
+            HtlvValueRef::Batch(batch) => batch.element_type,
+            HtlvValueRef::Array(_) => HtlvValueType::Array,
+            HtlvValueRef::Object(_) => HtlvValueType::Object,
+        }
+    }
+
+    /// Copies the value into an owned `HtlvValue`. Batches become arrays of
+    /// items with tag 0, the form in which they were encoded.
+    pub fn to_owned_value(&self) -> HtlvValue {
+        match self {
+            HtlvValueRef::Null => HtlvValue::Null,
+            HtlvValueRef::Bool(v) => HtlvValue::Bool(*v),
+            HtlvValueRef::U8(v) => HtlvValue::U8(*v),
+            HtlvValueRef::U16(v) => HtlvValue::U16(*v),
+            HtlvValueRef::U32(v) => HtlvValue::U32(*v),
+            HtlvValueRef::U64(v) => HtlvValue::U64(*v),
+            HtlvValueRef::I8(v) => HtlvValue::I8(*v),
+            HtlvValueRef::I16(v) => HtlvValue::I16(*v),
+            HtlvValueRef::I32(v) => HtlvValue::I32(*v),
+            HtlvValueRef::I64(v) => HtlvValue::I64(*v),
+            HtlvValueRef::F32(v) => HtlvValue::F32(*v),
+            HtlvValueRef::F64(v) => HtlvValue::F64(*v),
+            HtlvValueRef::Bytes(v) => HtlvValue::Bytes(Bytes::copy_from_slice(v)),
+            HtlvValueRef::String(v) => HtlvValue::String(Bytes::copy_from_slice(v.as_bytes())),
+            HtlvValueRef::Batch(batch) => {
+                HtlvValue::Array(batch.iter().map(|value| HtlvItem::new(0, value.to_owned_value())).collect())
+            }
+            HtlvValueRef::Array(items) => HtlvValue::Array(items.iter().map(HtlvItemRef::to_owned_item).collect()),
+            HtlvValueRef::Object(items) => HtlvValue::Object(items.iter().map(HtlvItemRef::to_owned_item).collect()),
+        }
+    }
+}
+
+impl<'a> HtlvItemRef<'a> {
+    /// Copies the item into an owned `HtlvItem`.
+    pub fn to_owned_item(&self) -> HtlvItem {
+        HtlvItem::new(self.tag, self.value.to_owned_value())
+    }
+
+    /// Returns the field with the given tag if this item is an object.
+    pub fn field(&self, tag: u64) -> Option<&HtlvItemRef<'a>> {
+        match &self.value {
+            HtlvValueRef::Object(fields) => fields.iter().find(|field| field.tag == tag),
+            _ => None,
+        }
+    }
+}
+
+/// An array or object whose children are still being decoded.
+struct OpenContainer<'a> {
+    tag: u64,
+    is_array: bool,
+    items: Vec<HtlvItemRef<'a>>,
+}
+
+/// Decodes a single HTLV item into a view borrowing from `data`.
+/// Returns the item and the number of bytes read for it.
+pub fn decode_item_ref(data: &[u8]) -> Result<(HtlvItemRef<'_>, usize)> {
+    let mut decoder = PullDecoder::new(data);
+    let mut stack: Vec<OpenContainer<'_>> = Vec::new();
+
+    while let Some(event) = decoder.next_event()? {
+        let item = match event {
+            PullEvent::BeginObject(tag) | PullEvent::BeginArray(tag) => {
+                let is_array = matches!(event, PullEvent::BeginArray(_));
+                stack.push(OpenContainer { tag, is_array, items: Vec::new() });
+                continue;
+            }
+            PullEvent::EndObject | PullEvent::EndArray => {
+                let container = stack.pop().ok_or_else(|| Error::CodecError("Unbalanced container end".to_string()))?;
+                let value = if container.is_array {
+                    HtlvValueRef::Array(container.items)
+                } else {
+                    HtlvValueRef::Object(container.items)
+                };
+                HtlvItemRef { tag: container.tag, value }
+            }
+            PullEvent::Field(tag, value_type, value) => HtlvItemRef { tag, value: field_value(value_type, value)? },
+        };
+
+        match stack.last_mut() {
+            Some(parent) => parent.items.push(item),
+            None => return Ok((item, decoder.offset())),
+        }
+    }
+
+    Err(Error::CodecError("Decoding failed: No root item decoded".to_string()))
+}
+
+/// Converts the raw bytes of a basic field into a borrowed value.
+fn field_value(value_type: HtlvValueType, value: &[u8]) -> Result<HtlvValueRef<'_>> {
+    let invalid = || {
+        Error::CodecError(format!("Invalid {} byte value for type {:?}", value.len(), value_type))
+    };
+    match value_type {
+        HtlvValueType::Bytes => Ok(HtlvValueRef::Bytes(value)),
+        HtlvValueType::String => std::str::from_utf8(value)
+            .map(HtlvValueRef::String)
+            .map_err(|e| Error::CodecError(format!("Invalid UTF-8 in String value: {}", e))),
+        HtlvValueType::Null => if value.is_empty() { Ok(HtlvValueRef::Null) } else { Err(invalid()) },
+        HtlvValueType::Bool | HtlvValueType::U8 | HtlvValueType::I8 => scalar_value(value_type, value).ok_or_else(invalid),
+        HtlvValueType::Array | HtlvValueType::Object => Err(invalid()),
+        HtlvValueType::Extension | HtlvValueType::ExternalRef => Err(Error::CodecError(format!(
+            "{:?} values are not supported by the borrowed decoder",
+            value_type
+        ))),
+        _ => {
+            let width = element_width(value_type);
+            if value.len() == width {
+                scalar_value(value_type, value).ok_or_else(invalid)
+            } else if value.len().is_multiple_of(width) {
+                Ok(HtlvValueRef::Batch(BatchRef { element_type: value_type, data: value }))
+            } else {
+                Err(invalid())
+            }
+        }
+    }
+}
+
+/// Returns the size of one element of a numeric type.
+fn element_width(value_type: HtlvValueType) -> usize {
+    type_table::handler(value_type).element_size.unwrap_or(1)
+}
+
+/// Decodes a single fixed-size scalar.
+fn scalar_value(value_type: HtlvValueType, bytes: &[u8]) -> Option<HtlvValueRef<'static>> {
+    Some(match value_type {
+        HtlvValueType::Bool => match bytes {
+            [byte] => HtlvValueRef::Bool(*byte != 0),
+            _ => return None,
+        },
+        HtlvValueType::U8 => HtlvValueRef::U8(u8::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::U16 => HtlvValueRef::U16(u16::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::U32 => HtlvValueRef::U32(u32::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::U64 => HtlvValueRef::U64(u64::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::I8 => HtlvValueRef::I8(i8::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::I16 => HtlvValueRef::I16(i16::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::I32 => HtlvValueRef::I32(i32::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::I64 => HtlvValueRef::I64(i64::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::F32 => HtlvValueRef::F32(f32::from_le_bytes(bytes.try_into().ok()?)),
+        HtlvValueType::F64 => HtlvValueRef::F64(f64::from_le_bytes(bytes.try_into().ok()?)),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::encode_item;
+
+    #[test]
+    fn test_decode_item_ref_borrows_from_input() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U32(70000)),
+            HtlvItem::new(3, HtlvValue::Bytes(Bytes::from_static(b"\x00\x01\x02"))),
+            HtlvItem::new(4, HtlvValue::String(Bytes::from_static(b"sensor"))),
+            HtlvItem::new(5, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::Bool(true)),
+                HtlvItem::new(0, HtlvValue::Null),
+            ])),
+        ]));
+        let encoded = encode_item(&item).unwrap();
+
+        let (view, read) = decode_item_ref(&encoded).unwrap();
+        assert_eq!(read, encoded.len());
+        assert_eq!(view.field(2).unwrap().value, HtlvValueRef::U32(70000));
+        match view.field(4).unwrap().value {
+            HtlvValueRef::String(text) => {
+                assert_eq!(text, "sensor");
+                assert!(encoded.as_ptr_range().contains(&text.as_ptr()));
+            }
+            ref other => panic!("Expected String, got {:?}", other),
+        }
+        assert_eq!(view.to_owned_item(), item);
+    }
+
+    #[test]
+    fn test_batches_and_errors() {
+        // Three u16 values stored in one item
+        let mut encoded = vec![0x07, HtlvValueType::U16 as u8, 0x06];
+        for value in [1u16, 2, 65535] {
+            encoded.extend_from_slice(&value.to_le_bytes());
+        }
+        let (view, _) = decode_item_ref(&encoded).unwrap();
+        match view.value {
+            HtlvValueRef::Batch(batch) => {
+                assert_eq!(batch.len(), 3);
+                assert_eq!(batch.get(2), Some(HtlvValueRef::U16(65535)));
+                assert_eq!(batch.get(3), None);
+                assert_eq!(batch.iter().count(), 3);
+            }
+            other => panic!("Expected batch, got {:?}", other),
+        }
+
+        assert!(decode_item_ref(&[0x01, HtlvValueType::U32 as u8, 0x03, 1, 2, 3]).is_err());
+        assert!(decode_item_ref(&[0x01, HtlvValueType::String as u8, 0x01, 0xFF]).is_err());
+        assert!(decode_item_ref(&[]).is_err());
+    }
+}
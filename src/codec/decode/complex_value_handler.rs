@@ -2,7 +2,12 @@
 
 use crate::internal::error::{Error, Result};
 use crate::codec::types::{HtlvItem, HtlvValueType, HtlvValue};
-use crate::codec::decode::decoder_state_machine::{DecodeContext, DecodeState, ComplexDecodeContext, MAX_NESTING_DEPTH};
+use crate::codec::decode::type_table;
+use crate::codec::decode::decoder_state_machine::{DecodeContext, DecodeState, ComplexDecodeContext};
+
+// Children reserved up front for a complex value. Most objects have fewer
+// fields, so they are decoded with a single allocation instead of growing.
+const RESERVED_CHILDREN: usize = 8;
 
 /// Handles the logic for decoding complex HTLV values (Array and Object).
 pub struct ComplexValueHandler;
@@ -17,17 +22,24 @@ impl ComplexValueHandler {
         value_end: usize,
     ) -> Result<()> {
         let next_depth = ctx.complex_stack.len() + 1;
-        if next_depth > MAX_NESTING_DEPTH {
-            return Err(Error::CodecError(format!("Maximum nesting depth ({}) exceeded", MAX_NESTING_DEPTH)));
+        if next_depth > ctx.limits.nesting_depth() {
+            return Err(Error::DepthExceeded { offset: ctx.current_offset, limit: ctx.limits.nesting_depth() });
         }
 
+        // The value length bounds the number of children, so small and empty
+        // values reserve no more than they can hold
+        let value_length = value_end.saturating_sub(ctx.current_offset) as u64;
+        let max_children = (type_table::handler(value_type).size_hint)(value_length);
         ctx.complex_stack.push(ComplexDecodeContext {
             tag,
             value_type, // This will be Array or Object
             end_offset: value_end, // End of the complex value in the original data
-            items: Vec::new(),
+            items: Vec::with_capacity(max_children.min(RESERVED_CHILDREN)),
             depth: next_depth, // Set the current depth
         });
+        if ctx.header_check.is_some() {
+            ctx.check_stack.push(ctx.current_item_check_state);
+        }
         ctx.current_offset = ctx.current_offset; // current_offset remains at value_start to process nested items
         ctx.state = DecodeState::Scan; // Start decoding items within this complex type
         // println!("decode_item state transition: PrepareValue -> Scan (Complex Array/Object)"); // Debug print
@@ -39,6 +51,11 @@ impl ComplexValueHandler {
     pub fn handle_process_complex_state(ctx: &mut DecodeContext) -> Result<()> {
         // A complex item on top of the stack is finished processing its children.
         let decoded_complex_context = ctx.complex_stack.pop().unwrap();
+        ctx.check_stack.pop();
+        // The children were charged as they were decoded, which covers the
+        // slots they fill; the item itself and the spare slots are left
+        let spare = decoded_complex_context.items.capacity() - decoded_complex_context.items.len();
+        ctx.charge((1 + spare) * std::mem::size_of::<HtlvItem>())?;
         let complex_value = match decoded_complex_context.value_type {
             HtlvValueType::Array => HtlvValue::Array(decoded_complex_context.items),
             HtlvValueType::Object => HtlvValue::Object(decoded_complex_context.items),
@@ -47,6 +64,7 @@ impl ComplexValueHandler {
 
         // Update current_offset to the end of the processed complex value
         ctx.current_offset = decoded_complex_context.end_offset;
+        ctx.item_decoded();
         // println!("decode_item: Updated current_offset to end_offset = {}", ctx.current_offset); // Debug print
 
 
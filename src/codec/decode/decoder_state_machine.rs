@@ -1,14 +1,24 @@
 // State machine and context for the decoding pipeline
 
 use crate::internal::error::{Error, Result};
-use crate::codec::varint; // Import varint for decoding tag and length
-use crate::codec::types::{HtlvItem, HtlvValueType};
-use bytes::BytesMut;
+use crate::internal::cursor::WireCursor; // Checked reading of tags, lengths and offsets
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use bytes::{Bytes, BytesMut};
 // Removed unused import: use bytes::Bytes; // Import Bytes for batch decoding alignment
 use crate::codec::decode::basic_value_decoder; // Import the new basic value decoder module
 use crate::codec::decode::batch_value_decoder; // Import the batch value decoder module
+use crate::codec::decode::type_table::{self, ValueKind}; // Per-type dispatch table
 use crate::codec::decode::complex_value_handler::ComplexValueHandler; // Import the new complex value handler
 use crate::codec::decode::large_field_handler::{LargeFieldHandler, LargeFieldProcessingResult}; // Import the new large field handler and its result enum
+use crate::codec::decode::header_check::{HeaderCheck, UNCHECKED}; // Optional decode-time validation
+use crate::codec::decode::limits::DecodeConfig; // Limits on untrusted input
+use crate::internal::diagnostics::{DiagnosticKind, DiagnosticSource, Diagnostics};
+use crate::internal::progress::{ProgressReporter, ProgressStage};
+use crate::internal::metrics::{MetricsRecorder, PipelineStage};
+use crate::internal::cancel::CancellationToken;
+use crate::internal::deadline::Deadline;
+use crate::internal::memory::{MemoryBudget, MemoryReservation};
+use std::sync::Arc;
 // Removed unused import: use std::mem; // Import std::mem
 
 
@@ -26,6 +36,18 @@ pub enum DecodeState {
     Done, // Decoding is complete
 }
 
+impl DecodeState {
+    /// Returns the stage the time spent in this state is recorded under.
+    pub fn pipeline_stage(&self) -> PipelineStage {
+        match self {
+            DecodeState::Scan | DecodeState::PrepareValue | DecodeState::Done => PipelineStage::Scan,
+            DecodeState::DecodeValue => PipelineStage::ValueDecode,
+            DecodeState::DecodeBatchValue => PipelineStage::BatchDecode,
+            DecodeState::ProcessComplex => PipelineStage::ComplexAssembly,
+        }
+    }
+}
+
 /// Represents a complex item being decoded on the stack.
 #[derive(Debug)]
 pub struct ComplexDecodeContext {
@@ -36,6 +58,31 @@ pub struct ComplexDecodeContext {
     pub depth: usize, // Current nesting depth
 }
 
+/// Observers and controls of a decode. The default sets none of them and
+/// applies the default limits.
+#[derive(Debug, Clone, Default)]
+pub struct DecodeOptions {
+    /// Sink for non-fatal conditions such as unaligned batch data
+    pub diagnostics: Option<Diagnostics>,
+    /// Observer of the bytes processed and items decoded, told after every item
+    pub progress: Option<ProgressReporter>,
+    /// Recorder of the time spent scanning headers, decoding values and
+    /// assembling containers
+    pub metrics: Option<MetricsRecorder>,
+    /// Token failing the decode with `Error::Cancelled` at the next state
+    /// transition once it is cancelled
+    pub cancellation: Option<CancellationToken>,
+    /// Time budget failing the decode with `Error::DeadlineExceeded` at the
+    /// next state transition once it passes
+    pub deadline: Option<Deadline>,
+    /// Budget the decode buffer and the decoded items are accounted against
+    pub budget: Option<MemoryBudget>,
+    /// Check consulted for every item header before its value is decoded
+    pub header_check: Option<Arc<dyn HeaderCheck>>,
+    /// Limits on nesting, item lengths and item counts
+    pub limits: DecodeConfig,
+}
+
 /// Represents the context and state of the decoding process.
 #[derive(Debug)]
 pub struct DecodeContext {
@@ -49,6 +96,7 @@ pub struct DecodeContext {
     // Information about the current item being processed
     pub current_item_tag: u64,
     pub current_item_type: Option<HtlvValueType>,
+    pub current_item_type_byte: u8, // Raw type byte, which identifies extension types
     pub current_item_length: u64, // Store the length of the current item (shard or regular)
 
     // State for decoding large fields
@@ -57,6 +105,23 @@ pub struct DecodeContext {
     pub large_field_value_type: Option<HtlvValueType>,
     pub large_field_total_length: u64,
     pub large_field_buffer: BytesMut,
+
+    // Optional check of item headers before their values are decoded
+    pub header_check: Option<Arc<dyn HeaderCheck>>,
+    pub current_item_check_state: usize, // State returned by the check for the current item
+    pub check_stack: Vec<usize>, // Check states of the complex items on `complex_stack`
+
+    // Limits on nesting, item lengths and item counts, and the number of
+    // item headers read so far
+    pub limits: DecodeConfig,
+    pub items_scanned: usize,
+
+    // Observers and controls of the decode, and the number of items decoded
+    pub options: DecodeOptions,
+    pub items_decoded: u64,
+
+    // Memory charged for the input buffer and the items decoded so far
+    pub memory: Option<MemoryReservation>,
 }
 
 impl DecodeContext {
@@ -71,12 +136,94 @@ impl DecodeContext {
             bytes_read_for_root_item: 0,
             current_item_tag: 0, // Initialize new field
             current_item_type: None, // Initialize new field
+            current_item_type_byte: 0,
             current_item_length: 0,
             decoding_large_field: false,
             large_field_tag: 0,
             large_field_value_type: None,
             large_field_total_length: 0,
             large_field_buffer: BytesMut::new(),
+            header_check: None,
+            current_item_check_state: UNCHECKED,
+            check_stack: Vec::new(),
+            limits: DecodeConfig::default(),
+            items_scanned: 0,
+            options: DecodeOptions::default(),
+            items_decoded: 0,
+            memory: None,
+        }
+    }
+
+    /// Creates a decoding context with the given observers and controls.
+    pub fn with_options(data: &[u8], options: &DecodeOptions) -> Self {
+        let mut ctx = Self::new(data);
+        ctx.header_check = options.header_check.clone();
+        ctx.limits = options.limits;
+        ctx.options = options.clone();
+        ctx
+    }
+
+    /// Prepares the context for decoding other data. The capacity of its
+    /// buffers and stacks is kept, and so are its limits, checks and sinks.
+    pub fn reset(&mut self, data: &[u8]) {
+        self.data.clear();
+        self.data.extend_from_slice(data);
+        self.restart_at(0);
+    }
+
+    /// Prepares the context for decoding the item at `offset` of the data it
+    /// holds. Offsets in errors and the bytes read stay relative to the data.
+    pub(crate) fn restart_at(&mut self, offset: usize) {
+        self.current_offset = offset;
+        self.state = DecodeState::Scan;
+        self.complex_stack.clear();
+        self.root_item = None;
+        self.bytes_read_for_root_item = 0;
+        self.current_item_tag = 0;
+        self.current_item_type = None;
+        self.current_item_type_byte = 0;
+        self.current_item_length = 0;
+        self.decoding_large_field = false;
+        self.large_field_tag = 0;
+        self.large_field_value_type = None;
+        self.large_field_total_length = 0;
+        self.large_field_buffer.clear();
+        self.current_item_check_state = UNCHECKED;
+        self.check_stack.clear();
+        self.items_decoded = 0;
+        self.items_scanned = 0;
+    }
+
+    /// Returns the end offset of the current item's value; the Scan state
+    /// checked that it lies within the data.
+    fn current_value_end(&self) -> Result<usize> {
+        WireCursor::at(&self.data, self.current_offset).end_of(self.current_item_length, "Value")
+    }
+
+    /// Returns the error for data ending before all shards of the large field
+    /// being decoded were read.
+    pub(crate) fn large_field_truncated(&self) -> Error {
+        Error::UnexpectedEof {
+            what: format!("Large field with tag {}", self.large_field_tag),
+            offset: self.current_offset,
+            needed: self.large_field_total_length,
+            got: self.large_field_buffer.len(),
+        }
+    }
+
+    /// Counts an item whose value has been decoded and reports the progress.
+    pub(crate) fn item_decoded(&mut self) {
+        self.items_decoded += 1;
+        if let Some(progress) = &self.options.progress {
+            progress.report(ProgressStage::Decode, self.current_offset as u64, self.data.len() as u64, self.items_decoded);
+        }
+    }
+
+    /// Charges memory allocated for decoded items to the reservation, if any.
+    pub(crate) fn charge(&mut self, bytes: usize) -> Result<()> {
+        match &mut self.memory {
+            Some(reservation) => reservation.resize(reservation.size() + bytes),
+            None => Ok(()),
         }
     }
 
@@ -95,36 +242,44 @@ impl DecodeContext {
         // If stack is empty or current complex item is not done, scan for the next item header.
         if self.current_offset < self.data.len() {
             // --- Stage 1: Type Identification & Tag/Length Extraction ---
-            // Decode Tag
-            let (tag, tag_bytes) = varint::decode_varint(&self.data[self.current_offset..])
-                .map_err(|e| Error::CodecError(format!("Failed to decode item Tag varint: {}", e)))?;
-            let offset_after_tag = self.current_offset + tag_bytes;
+            let mut cursor = WireCursor::at(&self.data, self.current_offset);
 
-            // Ensure there's enough data for the Type byte
-            if self.data.len() < offset_after_tag + 1 {
-                 return Err(Error::CodecError("Incomplete data for Type byte".to_string()));
-            }
+            // Decode Tag
+            let tag = cursor.read_varint("item Tag")?;
 
             // Decode Type
-            let value_type_byte = self.data[offset_after_tag];
-            let offset_after_type = offset_after_tag + 1;
-
-            let value_type = HtlvValueType::from_byte(value_type_byte)
-                .ok_or_else(|| Error::CodecError(format!("Unknown value type tag: {}", value_type_byte)))?;
+            let type_offset = cursor.position();
+            let value_type_byte = cursor.read_u8("Type byte")?;
+            let value_type = type_table::handler_for_byte(value_type_byte)
+                .map(|handler| handler.value_type)
+                .ok_or(Error::UnknownType { type_byte: value_type_byte, offset: type_offset })?;
 
             // Decode Length
-            let (length, length_bytes) = varint::decode_varint(&self.data[offset_after_type..])
-                .map_err(|e| Error::CodecError(format!("Failed to decode Length varint: {}", e)))?;
-            let offset_after_length = offset_after_type + length_bytes;
+            let length = cursor.read_varint("Length")?;
+            let offset_after_length = cursor.position();
+
+            // Apply the limits before anything is done with the value
+            self.items_scanned += 1;
+            self.limits.check_item_count(self.items_scanned)?;
+            self.limits.check_item_length(length, self.current_offset as u64)?;
+
+            // Ensure there's enough data for the Value, without overflowing the
+            // offset, and that it ends within the enclosing container
+            let value_end = cursor.end_of(length, "Value")?;
+            if self.complex_stack.last().is_some_and(|parent| value_end > parent.end_offset) {
+                return Err(Error::ExceedsContainer { what: "Item".to_string(), offset: self.current_offset });
+            }
 
-            // Ensure there's enough data for the Value
-            if self.data.len() < offset_after_length + length as usize {
-                 return Err(Error::CodecError(format!("Incomplete data for Value (expected {} bytes)", length)));
+            // Reject the item before its value is decoded if the header check fails
+            if let Some(header_check) = &self.header_check {
+                let parent = if self.complex_stack.is_empty() { None } else { self.check_stack.last().copied() };
+                self.current_item_check_state = header_check.check_header(parent, tag, value_type)?;
             }
 
             // Store extracted info and transition to PrepareValue
             self.current_item_tag = tag; // Store the tag
             self.current_item_type = Some(value_type); // Store the type
+            self.current_item_type_byte = value_type_byte;
             self.current_item_length = length; // Store the length
             self.current_offset = offset_after_length; // Advance offset past header
             self.state = DecodeState::PrepareValue; // Transition to prepare for value decoding
@@ -137,7 +292,7 @@ impl DecodeContext {
                  // println!("decode_item state transition: Scan -> Done (stack empty)"); // Debug print
             } else if self.decoding_large_field {
                  // If we are at the end of the data but still decoding a large field, it's incomplete.
-                 return Err(Error::CodecError(format!("Incomplete large field data. Expected {} bytes, got {}", self.large_field_total_length, self.large_field_buffer.len())));
+                 return Err(self.large_field_truncated());
             }
             else {
                 // If we are at the end of the data but the stack is not empty, it means
@@ -153,12 +308,12 @@ impl DecodeContext {
         // --- Stage 2: Prepare for Value Decoding ---
         let tag = self.current_item_tag;
         let value_type = self.current_item_type.unwrap();
-        let length = self.current_item_length;
         let value_start = self.current_offset;
-        let value_end = value_start + length as usize;
+        let value_end = self.current_value_end()?;
         let raw_value_slice = &self.data[value_start..value_end];
 
         if self.decoding_large_field {
+            self.limits.check_large_field_size(self.large_field_total_length, self.large_field_tag)?;
             // If decoding a large field, use the large field handler.
             let result = LargeFieldHandler::process_shard(
                 self.large_field_tag,
@@ -200,23 +355,21 @@ impl DecodeContext {
             }
 
         } else {
-            // Not decoding a large field, determine how to decode the value
-            match value_type {
-                HtlvValueType::Array | HtlvValueType::Object => {
+            // Not decoding a large field, the type table tells how to decode the value
+            match type_table::handler(value_type).kind {
+                ValueKind::Complex => {
                     // It's a complex type, use the complex value handler
                     ComplexValueHandler::handle_prepare_complex_value(self, tag, value_type, value_end)?;
                     self.state = DecodeState::Scan; // Transition to scan for nested items
                     // println!("decode_item state transition: PrepareValue -> Scan (Complex)"); // Debug print
                 }
-                HtlvValueType::U16 | HtlvValueType::U32 | HtlvValueType::U64 |
-                HtlvValueType::I16 | HtlvValueType::I32 | HtlvValueType::I64 |
-                HtlvValueType::F32 | HtlvValueType::F64 => {
+                ValueKind::Batch => {
                     // It's a batch decodable basic type
                     // self.current_offset = value_end; // Removed incorrect offset advance
                     self.state = DecodeState::DecodeBatchValue; // Transition to decode batch value
                     // println!("decode_item state transition: PrepareValue -> DecodeBatchValue (Batch)"); // Debug print
                 }
-                _ => {
+                ValueKind::Basic | ValueKind::Extension => {
                     // It's a single basic type
                     // self.current_offset = value_end; // Removed incorrect offset advance
                     self.state = DecodeState::DecodeValue; // Transition to decode the single value
@@ -234,13 +387,19 @@ impl DecodeContext {
         let value_type = self.current_item_type.unwrap();
         let length = self.current_item_length;
         let value_start = self.current_offset; // Corrected value_start calculation
-        let value_end = value_start + length as usize;
+        let value_end = self.current_value_end()?;
         let raw_value_slice = &self.data[value_start..value_end];
 
-        // Use the new basic_value_decoder function
-        let decoded_value = basic_value_decoder::decode_basic_value(value_type, length, raw_value_slice)?;
+        // Extension payloads are kept as they are, other types use the basic_value_decoder function
+        let decoded_value = if value_type == HtlvValueType::Extension {
+            HtlvValue::Extension(self.current_item_type_byte, Bytes::copy_from_slice(raw_value_slice))
+        } else {
+            basic_value_decoder::decode_basic_value(value_type, length, raw_value_slice)?
+        };
+        self.charge(std::mem::size_of::<HtlvItem>() + decoded_value.heap_size(true))?;
 
         self.current_offset = value_end; // Advance offset past the basic value
+        self.item_decoded();
 
         if self.complex_stack.is_empty() {
             // This is the root item and it's basic
@@ -265,13 +424,29 @@ impl DecodeContext {
         let value_type = self.current_item_type.unwrap(); // This is the element type (e.g., U32)
         let length = self.current_item_length; // This is the total length of the batch value
         let value_start = self.current_offset; // Corrected value_start calculation
-        let value_end = value_start + length as usize;
+        let value_end = self.current_value_end()?;
         let raw_value_slice = &self.data[value_start..value_end]; // Slice for the entire batch value
 
+        let handler = type_table::handler(value_type);
+        (handler.validate)(value_type, length)?;
+
+        if let Some(diagnostics) = &self.options.diagnostics {
+            let alignment = handler.alignment;
+            if !(raw_value_slice.as_ptr() as usize).is_multiple_of(alignment) {
+                diagnostics.report(
+                    DiagnosticSource::Decode,
+                    DiagnosticKind::UnalignedFallback { length: raw_value_slice.len() },
+                    format!("Batch of {:?} with tag {} is unaligned and was copied before decoding", value_type, tag),
+                );
+            }
+        }
+
         // Use the new batch_value_decoder function
         let decoded_value = batch_value_decoder::decode_batch_value(value_type, length, raw_value_slice)?;
+        self.charge(std::mem::size_of::<HtlvItem>() + decoded_value.heap_size(true))?;
 
         self.current_offset = value_end; // Advance offset past the batch value
+        self.item_decoded();
 
         if self.complex_stack.is_empty() {
             // This is the root item and it's a batch
@@ -0,0 +1,176 @@
+// Decoding of buffers holding consecutive top-level items
+//
+// Files and message bodies often hold several items one after another, with
+// nothing but their own lengths to tell where one ends. `HtlvIter` yields the
+// items of such a buffer lazily, restarting one decode context at the end of
+// each, so offsets in errors are offsets into the whole buffer; `decode_all`
+// collects them. Items come out as `decode_item` returns them, so a sharded
+// large field yields its header item and then its shards. The buffer is taken
+// to be complete: an item cut short by the end of the buffer is trailing
+// garbage, reported as `Error::TrailingData` at the offset where the last
+// complete item ended, and any other error is corruption returned as it is.
+// The iterator ends after either.
+
+use crate::codec::decode::decoder_state_machine::DecodeContext;
+use crate::codec::decode::limits::DecodeConfig;
+use crate::codec::decode::run_decode_in;
+use crate::codec::types::HtlvItem;
+use crate::internal::error::{Error, Result};
+
+/// Decodes all the consecutive top-level items of a buffer.
+pub fn decode_all(data: &[u8]) -> Result<Vec<HtlvItem>> {
+    HtlvIter::new(data).collect()
+}
+
+/// An iterator over the consecutive top-level items of a buffer.
+#[derive(Debug)]
+pub struct HtlvIter<'a> {
+    data: &'a [u8],
+    limits: DecodeConfig,
+    /// Created on the first call to `next`
+    ctx: Option<DecodeContext>,
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> HtlvIter<'a> {
+    /// Creates an iterator with the default limits.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::with_config(data, &DecodeConfig::default())
+    }
+
+    /// Creates an iterator applying the given limits to every item; `strict`
+    /// is ignored, as the items are followed by the next ones.
+    pub fn with_config(data: &'a [u8], config: &DecodeConfig) -> Self {
+        HtlvIter {
+            data,
+            limits: DecodeConfig { strict: false, ..*config },
+            ctx: None,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Returns the offset of the next item, which is the number of bytes
+    /// consumed by the items yielded so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the bytes not consumed by the items yielded so far.
+    pub fn remaining(&self) -> &'a [u8] {
+        &self.data[self.offset..]
+    }
+}
+
+impl Iterator for HtlvIter<'_> {
+    type Item = Result<HtlvItem>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done || self.offset >= self.data.len() {
+            return None;
+        }
+
+        let limits = self.limits;
+        let data = self.data;
+        let ctx = self.ctx.get_or_insert_with(|| {
+            let mut ctx = DecodeContext::new(data);
+            ctx.limits = limits;
+            ctx
+        });
+        ctx.restart_at(self.offset);
+        match run_decode_in(ctx) {
+            Ok((item, end)) => {
+                self.offset = end;
+                Some(Ok(item))
+            }
+            Err(e) => {
+                self.done = true;
+                if e.is_truncation() {
+                    Some(Err(Error::TrailingData {
+                        offset: self.offset,
+                        remaining: self.data.len() - self.offset,
+                    }))
+                } else {
+                    Some(Err(e))
+                }
+            }
+        }
+    }
+}
+
+impl std::iter::FusedIterator for HtlvIter<'_> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::{encode_item, LARGE_FIELD_THRESHOLD};
+    use crate::codec::types::HtlvValue;
+    use bytes::Bytes;
+
+    fn document(items: &[HtlvItem]) -> Vec<u8> {
+        let mut data = Vec::new();
+        for item in items {
+            data.extend_from_slice(&encode_item(item).unwrap());
+        }
+        data
+    }
+
+    #[test]
+    fn test_decode_all_items() {
+        let items = vec![
+            HtlvItem::new(1, HtlvValue::Object(vec![
+                HtlvItem::new(2, HtlvValue::U8(7)),
+                HtlvItem::new(3, HtlvValue::String(Bytes::from("second"))),
+            ])),
+            HtlvItem::new(4, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::I64(-1))])),
+            HtlvItem::new(5, HtlvValue::Bool(true)),
+        ];
+        let data = document(&items);
+        assert_eq!(decode_all(&data).unwrap(), items);
+        assert!(decode_all(&[]).unwrap().is_empty());
+
+        let mut iter = HtlvIter::new(&data);
+        assert_eq!(iter.next().unwrap().unwrap(), items[0]);
+        assert_eq!(iter.offset(), encode_item(&items[0]).unwrap().len());
+        assert_eq!(iter.by_ref().count(), 2);
+        assert!(iter.remaining().is_empty());
+
+        // A sharded field comes out as decode_item returns it
+        let large = document(&[HtlvItem::new(6, HtlvValue::Bytes(Bytes::from(vec![1u8; LARGE_FIELD_THRESHOLD + 1])))]);
+        let mut expected = Vec::new();
+        let mut offset = 0;
+        while offset < large.len() {
+            let (item, length) = crate::codec::decode::decode_item(&large[offset..]).unwrap();
+            expected.push(item);
+            offset += length;
+        }
+        assert_eq!(decode_all(&large).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_trailing_garbage_and_corruption() {
+        let items = vec![HtlvItem::new(1, HtlvValue::U8(1)), HtlvItem::new(2, HtlvValue::String(Bytes::from("two")))];
+        let mut data = document(&items);
+        let end = data.len();
+
+        // An item cut short after the last complete one is trailing garbage
+        data.extend_from_slice(&encode_item(&items[1]).unwrap()[..2]);
+        let mut iter = HtlvIter::new(&data);
+        assert_eq!(iter.by_ref().take(2).collect::<Result<Vec<_>>>().unwrap(), items);
+        assert!(matches!(iter.next(), Some(Err(Error::TrailingData { offset, remaining: 2 })) if offset == end));
+        assert!(iter.next().is_none());
+        assert!(matches!(decode_all(&data), Err(Error::TrailingData { .. })));
+
+        // An unknown type byte is reported where it is
+        data.truncate(end);
+        data.extend_from_slice(&[3, 0x20, 0]);
+        assert!(matches!(decode_all(&data), Err(Error::UnknownType { offset, .. }) if offset == end + 1));
+
+        // Limits apply to every item
+        let limits = DecodeConfig { max_item_length: 1, ..DecodeConfig::default() };
+        let mut iter = HtlvIter::with_config(&data[..end], &limits);
+        assert_eq!(iter.next().unwrap().unwrap(), items[0]);
+        assert!(matches!(iter.next(), Some(Err(Error::CodecError(_)))));
+    }
+}
@@ -0,0 +1,26 @@
+// Decode-time header checks
+//
+// A `HeaderCheck` is consulted by the decoder for every item header, before
+// the value of the item is decoded. It sees the tag and value type of the item
+// and the state it returned for the enclosing complex item, so it can follow a
+// schema through the nesting and reject unknown tags or mismatching types
+// without materializing any value. `CompiledSchema` implements it.
+
+use std::fmt::Debug;
+
+use crate::internal::error::Result;
+use crate::codec::types::HtlvValueType;
+
+/// State meaning "no checks below this item", e.g. for fields a schema allows
+/// without describing them
+pub const UNCHECKED: usize = usize::MAX;
+
+/// Checks item headers while decoding
+pub trait HeaderCheck: Debug + Send + Sync {
+    /// Checks the header of an item
+    ///
+    /// `parent` is the state returned for the enclosing complex item, or
+    /// `None` for the root item. The returned state is passed back as `parent`
+    /// for the items nested in this one; return `UNCHECKED` to skip them.
+    fn check_header(&self, parent: Option<usize>, tag: u64, value_type: HtlvValueType) -> Result<usize>;
+}
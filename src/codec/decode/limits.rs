@@ -0,0 +1,119 @@
+// Resource limits of the decoder
+//
+// Lengths and counts on the wire are chosen by whoever produced the data. A
+// service decoding untrusted input bounds what a crafted message can make it
+// spend with a `DecodeConfig`:
+//
+// - `max_nesting_depth`: depth of nested arrays and objects, at most
+//   `MAX_NESTING_DEPTH`
+// - `max_item_length`: length of the value of any one item, as announced by its
+//   header; containers included, so it also bounds the size of a message
+// - `max_total_items`: number of items in a message, nested ones included
+// - `max_large_field_size`: total length of a sharded Bytes or String field, as
+//   announced by its header, checked before any shard is read
+//
+// Items are checked as their headers are read, before anything is allocated
+// for their values. The default only limits the nesting depth, as the decoder
+// always did.
+//
+// With `strict`, the input must hold exactly one item: bytes left after it fail
+// with `Error::TrailingData` instead of being left to the caller. Fuzzers
+// checking that whatever decodes re-encodes to the same bytes want this, as do
+// services that receive one message per buffer.
+
+use crate::internal::error::{Error, Result};
+use super::decoder_state_machine::MAX_NESTING_DEPTH;
+
+/// Limits applied by the decoder to untrusted input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecodeConfig {
+    /// Maximum nesting depth of arrays and objects, at most `MAX_NESTING_DEPTH`
+    pub max_nesting_depth: usize,
+    /// Maximum length of the value of an item, in bytes
+    pub max_item_length: usize,
+    /// Maximum number of items in a message, nested ones included
+    pub max_total_items: usize,
+    /// Maximum total length of a sharded large field, in bytes
+    pub max_large_field_size: usize,
+    /// Whether bytes after the decoded item are an error
+    pub strict: bool,
+}
+
+impl Default for DecodeConfig {
+    fn default() -> Self {
+        DecodeConfig {
+            max_nesting_depth: MAX_NESTING_DEPTH,
+            max_item_length: usize::MAX,
+            max_total_items: usize::MAX,
+            max_large_field_size: usize::MAX,
+            strict: false,
+        }
+    }
+}
+
+impl DecodeConfig {
+    /// Returns limits suited to messages from untrusted peers: 16 levels of
+    /// nesting, items of up to 16 MiB, a million items per message and large
+    /// fields of up to 256 MiB
+    pub fn untrusted() -> Self {
+        DecodeConfig {
+            max_nesting_depth: 16,
+            max_item_length: 16 * 1024 * 1024,
+            max_total_items: 1 << 20,
+            max_large_field_size: 256 * 1024 * 1024,
+            strict: false,
+        }
+    }
+
+    /// Returns the limits of `untrusted`, rejecting bytes after the decoded
+    /// item as well
+    pub fn strict() -> Self {
+        DecodeConfig { strict: true, ..Self::untrusted() }
+    }
+
+    /// Returns the nesting depth limit, capped at `MAX_NESTING_DEPTH`
+    pub fn nesting_depth(&self) -> usize {
+        self.max_nesting_depth.min(MAX_NESTING_DEPTH)
+    }
+
+    /// Fails if the value of the item at `offset` is longer than allowed
+    pub(crate) fn check_item_length(&self, length: u64, offset: u64) -> Result<()> {
+        if length > self.max_item_length as u64 {
+            return Err(Error::CodecError(format!(
+                "Item at offset {} has a value of {} bytes, more than the limit of {}",
+                offset, length, self.max_item_length
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fails if a message holds more than the allowed number of items, given
+    /// the number read so far
+    pub(crate) fn check_item_count(&self, count: usize) -> Result<()> {
+        if count > self.max_total_items {
+            return Err(Error::CodecError(format!(
+                "Message holds more than the limit of {} items", self.max_total_items
+            )));
+        }
+        Ok(())
+    }
+
+    /// Fails in strict mode if bytes are left after the item ending at `end`
+    pub(crate) fn check_trailing_data(&self, end: usize, length: usize) -> Result<()> {
+        if self.strict && end < length {
+            return Err(Error::TrailingData { offset: end, remaining: length - end });
+        }
+        Ok(())
+    }
+
+    /// Fails if a sharded field announces a total length above the limit
+    pub(crate) fn check_large_field_size(&self, total_length: u64, tag: u64) -> Result<()> {
+        if total_length > self.max_large_field_size as u64 {
+            return Err(Error::CodecError(format!(
+                "Large field with tag {} announces {} bytes, more than the limit of {}",
+                tag, total_length, self.max_large_field_size
+            )));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,87 @@
+// Reusable decoder for thread-per-core runtimes
+//
+// The `decode_item*` functions build a fresh decode context for every call:
+// they copy the input into a new buffer and grow new stacks for nested values.
+// A `LocalDecoder` owns one context and resets it between calls, so a core
+// decoding a stream of packets allocates only when an item is larger or deeper
+// than any before it. It takes no checks or sinks, which are shared through
+// `Arc`s, and holds no locks. It is deliberately neither `Send` nor `Sync`:
+// each core of a thread-per-core runtime (glommio, monoio) keeps its own
+// decoder in task-local state, and later versions can add `Rc`-based state
+// without a breaking change.
+
+use std::marker::PhantomData;
+use std::rc::Rc;
+
+use crate::codec::decode::decoder_state_machine::DecodeContext;
+use crate::codec::decode::run_decode_in;
+use crate::codec::types::HtlvItem;
+use crate::config::TonitruConfig;
+use crate::internal::error::Result;
+
+/// A single-threaded decoder reusing its buffers across items.
+///
+/// It cannot be moved to another thread:
+///
+/// ```compile_fail
+/// use tonitru::codec::decode::local::LocalDecoder;
+/// let decoder = LocalDecoder::new();
+/// std::thread::spawn(move || drop(decoder));
+/// ```
+#[derive(Debug)]
+pub struct LocalDecoder {
+    ctx: DecodeContext,
+    _not_send: PhantomData<Rc<()>>,
+}
+
+impl Default for LocalDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl LocalDecoder {
+    /// Creates a decoder with the default limits.
+    pub fn new() -> Self {
+        LocalDecoder { ctx: DecodeContext::new(&[]), _not_send: PhantomData }
+    }
+
+    /// Creates a decoder applying the decoder limits of the configuration.
+    pub fn with_config(config: &TonitruConfig) -> Self {
+        let mut decoder = Self::new();
+        decoder.ctx.limits = config.decode_config();
+        decoder
+    }
+
+    /// Decodes bytes into a single logical HTLV item like `decode_item`.
+    /// Returns the decoded item and the number of bytes read.
+    pub fn decode(&mut self, data: &[u8]) -> Result<(HtlvItem, usize)> {
+        self.ctx.reset(data);
+        run_decode_in(&mut self.ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode::decode_item;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::HtlvValue;
+
+    #[test]
+    fn test_decoder_is_reusable_after_errors() {
+        let nested = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::Bool(true))])),
+            HtlvItem::new(3, HtlvValue::String(bytes::Bytes::from("local"))),
+        ]));
+        let encoded = encode_item(&nested).unwrap();
+        let mut decoder = LocalDecoder::new();
+
+        assert_eq!(decoder.decode(&encoded).unwrap(), decode_item(&encoded).unwrap());
+        // A failed decode leaves no state behind for the next one
+        assert!(decoder.decode(&encoded[..encoded.len() - 2]).is_err());
+        let small = encode_item(&HtlvItem::new(9, HtlvValue::Null)).unwrap();
+        assert_eq!(decoder.decode(&small).unwrap(), (HtlvItem::new(9, HtlvValue::Null), small.len()));
+        assert_eq!(decoder.decode(&encoded).unwrap().0, nested);
+    }
+}
@@ -16,11 +16,32 @@ pub mod complex_value_handler;
 pub mod large_field_handler;
 pub mod simd_optimizations;
 pub mod pipeline_processor;
+pub mod pull; // Allocation-free pull decoder
+pub mod header_check; // Decode-time header checks
+pub mod borrowed; // Zero-copy decoding into borrowed views
+pub mod type_table; // Per-type decode/validate/size-hint dispatch
+pub mod streaming; // Decoding from a reader, streaming large fields into a sink
+pub mod local; // Reusable decoder for thread-per-core runtimes
+pub mod limits; // Resource limits for untrusted input
+pub mod push; // Incremental decoding of partial network buffers
+pub mod document; // Decoding consecutive top-level items
 
 
 use crate::internal::error::{Error, Result};
+use crate::internal::memory::{MemoryBudget, MemorySubsystem};
+use crate::internal::cancel;
+use crate::internal::deadline;
 use crate::codec::types::HtlvItem;
 use decoder_state_machine::{DecodeContext, DecodeState}; // Import from the new state machine module
+use header_check::HeaderCheck;
+use std::sync::Arc;
+use std::time::Instant;
+
+pub use borrowed::{decode_item_ref, HtlvItemRef, HtlvValueRef};
+pub use decoder_state_machine::DecodeOptions;
+pub use document::{decode_all, HtlvIter};
+pub use limits::DecodeConfig;
+pub use push::{DecodeProgress, PushDecoder};
 
 
 // Fixed length for the total length encoded in the large field header item value (size of u64)
@@ -34,28 +55,96 @@ const TOTAL_LENGTH_HEADER_LEN: u64 = 8;
 /// Returns the decoded HtlvItem and the number of bytes read for this logical item.
 /// Note: For large fields, this function will consume multiple underlying HTLV items (header + shards).
 pub fn decode_item(data: &[u8]) -> Result<(HtlvItem, usize)> {
-    let mut ctx = DecodeContext::new(data);
+    run_decode(DecodeContext::new(data))
+}
 
+/// Decodes bytes into a single logical HTLV item like `decode_item`, consulting the
+/// header check for every item before its value is decoded.
+///
+/// Validation and decoding happen in one pass: an item rejected by the check fails
+/// decoding before any of its values are materialized.
+pub fn decode_item_checked(data: &[u8], header_check: Arc<dyn HeaderCheck>) -> Result<(HtlvItem, usize)> {
+    decode_item_with_options(data, &DecodeOptions { header_check: Some(header_check), ..DecodeOptions::default() })
+}
+
+/// Runs the decoding state machine to completion.
+fn run_decode(mut ctx: DecodeContext) -> Result<(HtlvItem, usize)> {
+    run_decode_in(&mut ctx)
+}
+
+/// Runs the decoding state machine of a context to completion, leaving the
+/// context to be reset and reused.
+pub(crate) fn run_decode_in(ctx: &mut DecodeContext) -> Result<(HtlvItem, usize)> {
     while ctx.state != DecodeState::Done {
+        cancel::check(&ctx.options.cancellation, "Decoding")?;
+        deadline::check(&ctx.options.deadline, "Decoding")?;
         // println!("decode_item loop: current_offset = {}, state = {:?}", ctx.current_offset, ctx.state); // Debug print
-        match ctx.state {
-            DecodeState::Scan => ctx.handle_scan_state()?,
-            DecodeState::PrepareValue => ctx.handle_prepare_value_state()?,
-            DecodeState::DecodeValue => ctx.handle_decode_value_state()?,
-            DecodeState::DecodeBatchValue => ctx.handle_decode_batch_value_state()?,
-            DecodeState::ProcessComplex => ctx.handle_process_complex_state()?,
+        let started = ctx.options.metrics.as_ref().map(|_| (ctx.state.pipeline_stage(), Instant::now()));
+        let step = match ctx.state {
+            DecodeState::Scan => ctx.handle_scan_state(),
+            DecodeState::PrepareValue => ctx.handle_prepare_value_state(),
+            DecodeState::DecodeValue => ctx.handle_decode_value_state(),
+            DecodeState::DecodeBatchValue => ctx.handle_decode_batch_value_state(),
+            DecodeState::ProcessComplex => ctx.handle_process_complex_state(),
             DecodeState::Done => break, // Should exit the loop here
+        };
+        if let (Some(metrics), Some((stage, started))) = (&ctx.options.metrics, started) {
+            metrics.record(stage, started.elapsed());
+        }
+        // The values of the open containers were checked to be complete, so
+        // data ending within them is corrupt rather than cut short
+        if ctx.complex_stack.is_empty() {
+            step?;
+        } else {
+            step.map_err(Error::within_container)?;
         }
     }
 
     // If we exit the loop while still decoding a large field, it's an error
     if ctx.decoding_large_field {
-         return Err(Error::CodecError(format!("Incomplete large field data at end of stream. Expected total length {}, got {}", ctx.large_field_total_length, ctx.large_field_buffer.len())));
+         return Err(ctx.large_field_truncated());
     }
 
 
-    ctx.root_item.ok_or_else(|| Error::CodecError("Decoding failed: No root item decoded".to_string()))
-        .map(|item| (item, ctx.bytes_read_for_root_item)) // Return bytes read for the root item
+    // Without a root item, the data was empty
+    let item = ctx.root_item.take().ok_or_else(|| Error::UnexpectedEof {
+        what: "Item".to_string(),
+        offset: ctx.current_offset,
+        needed: 1,
+        got: 0,
+    })?;
+    ctx.limits.check_trailing_data(ctx.bytes_read_for_root_item, ctx.data.len())?;
+    Ok((item, ctx.bytes_read_for_root_item)) // Return bytes read for the root item
+}
+
+/// Decodes bytes into a single logical HTLV item like `decode_item`, accounting the
+/// decode buffer and the decoded items against the given memory budget.
+///
+/// Fails with `Error::MemoryLimitExceeded` before decoding if the budget cannot hold
+/// the buffer, and while decoding once it cannot hold the items decoded so far. The
+/// reservation is released once decoding finishes.
+pub fn decode_item_with_budget(data: &[u8], budget: &MemoryBudget) -> Result<(HtlvItem, usize)> {
+    decode_item_with_options(data, &DecodeOptions { budget: Some(budget.clone()), ..DecodeOptions::default() })
+}
+
+/// Decodes bytes into a single logical HTLV item like `decode_item`, applying the
+/// given decoder limits; `TonitruConfig::decode_config` provides the configured ones.
+pub fn decode_item_with_config(data: &[u8], config: &DecodeConfig) -> Result<(HtlvItem, usize)> {
+    decode_item_with_options(data, &DecodeOptions { limits: *config, ..DecodeOptions::default() })
+}
+
+/// Decodes bytes into a single logical HTLV item like `decode_item`, with the
+/// given observers and controls, e.g. a deadline and a metrics recorder. The
+/// other `decode_item_with_*` functions and `decode_item_checked` set one of
+/// them and decode through this function.
+pub fn decode_item_with_options(data: &[u8], options: &DecodeOptions) -> Result<(HtlvItem, usize)> {
+    let mut ctx = DecodeContext::with_options(data, options);
+    if let Some(budget) = &options.budget {
+        // The decode context copies the input into its own buffer, and the
+        // items are charged as they are decoded
+        ctx.memory = Some(budget.try_reserve(MemorySubsystem::DecodeBuffers, data.len())?);
+    }
+    run_decode(ctx)
 }
 
 #[cfg(test)]
@@ -66,6 +155,10 @@ mod tests {
     use decoder_state_machine::MAX_NESTING_DEPTH; // Import MAX_NESTING_DEPTH for tests
     use bytes::BytesMut;
     use crate::codec::types::{HtlvValue, HtlvValueType};
+    use crate::internal::cancel::CancellationToken;
+    use crate::internal::deadline::Deadline;
+    use crate::internal::diagnostics::Diagnostics;
+    use crate::internal::progress::ProgressReporter;
 
     #[test]
     fn test_decode_nested_depth_limit() {
@@ -116,13 +209,176 @@ mod tests {
 
         // Now decode and expect a depth limit error
         let result = decode_item(&raw_data);
-        assert!(result.is_err());
-        assert_eq!(
-            result.unwrap_err().to_string(),
-            format!("Codec Error: Maximum nesting depth ({}) exceeded", MAX_NESTING_DEPTH)
+        assert!(
+            matches!(result, Err(Error::DepthExceeded { limit: MAX_NESTING_DEPTH, .. })),
+            "{:?}", result
         );
     }
 
+    #[test]
+    fn test_decode_item_with_budget() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U8(7)),
+            HtlvItem::new(3, HtlvValue::String(bytes::Bytes::from("budget"))),
+            HtlvItem::new(4, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::U32(1)), HtlvItem::new(0, HtlvValue::U32(2))])),
+        ]));
+        let raw_data = encode_item(&item).unwrap();
+
+        // The input buffer and the decoded items are charged
+        let (decoded_item, _) = decode_item(&raw_data).unwrap();
+        let budget = MemoryBudget::with_limit(raw_data.len() + decoded_item.deep_size());
+        let (decoded_item, _) = decode_item_with_budget(&raw_data, &budget).unwrap();
+        assert_eq!(decoded_item, item);
+        assert_eq!(budget.used(), 0); // Released after decoding
+
+        let small_budget = MemoryBudget::with_limit(raw_data.len() + decoded_item.deep_size() - 1);
+        let result = decode_item_with_budget(&raw_data, &small_budget);
+        assert!(matches!(result, Err(Error::MemoryLimitExceeded { .. })));
+        assert_eq!(small_budget.used(), 0);
+
+        let small_budget = MemoryBudget::with_limit(raw_data.len() - 1);
+        let result = decode_item_with_budget(&raw_data, &small_budget);
+        assert!(matches!(result, Err(Error::MemoryLimitExceeded { .. })));
+    }
+
+    #[test]
+    fn test_decode_rejects_overflowing_length() {
+        // A Bytes item claiming u64::MAX bytes must not wrap the value offset
+        let mut raw_data = vec![0x01, HtlvValueType::Bytes as u8];
+        raw_data.extend_from_slice(&varint::encode_varint(u64::MAX));
+        raw_data.extend_from_slice(b"abc");
+        let result = decode_item(&raw_data);
+        assert!(matches!(result, Err(Error::LengthOverflow { length: u64::MAX, offset: 12, .. })), "{:?}", result);
+    }
+
+    #[test]
+    fn test_decode_item_with_config_limits() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::String(bytes::Bytes::from("x".repeat(40)))),
+            HtlvItem::new(3, HtlvValue::Bool(true)),
+        ]));
+        let raw_data = encode_item(&item).unwrap();
+        let decode = |config: DecodeConfig| decode_item_with_config(&raw_data, &config);
+        assert_eq!(decode(DecodeConfig::default()).unwrap().0, item);
+        assert!(decode(DecodeConfig::untrusted()).is_ok());
+        assert!(decode(DecodeConfig { max_total_items: 3, max_item_length: raw_data.len(), ..DecodeConfig::default() }).is_ok());
+        assert!(decode(DecodeConfig { max_total_items: 2, ..DecodeConfig::default() }).is_err());
+        assert!(decode(DecodeConfig { max_item_length: 39, ..DecodeConfig::default() }).is_err());
+        assert!(decode(DecodeConfig { max_nesting_depth: 0, ..DecodeConfig::default() }).is_err());
+
+        // A length varint announcing far more than allowed fails on the limit
+        let mut crafted = vec![0x01, HtlvValueType::Bytes as u8];
+        crafted.extend_from_slice(&varint::encode_varint(1 << 40));
+        let limited = DecodeConfig { max_item_length: 1 << 20, ..DecodeConfig::default() };
+        let error = decode_item_with_config(&crafted, &limited).unwrap_err().to_string();
+        assert!(error.contains("more than the limit"), "{}", error);
+    }
+
+    #[test]
+    fn test_structured_errors_tell_truncation_from_corruption() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U8(7)),
+            HtlvItem::new(3, HtlvValue::String(bytes::Bytes::from("abc"))),
+        ]));
+        let raw_data = encode_item(&item).unwrap();
+
+        // Every prefix is cut short, and could be completed by more bytes
+        for end in 0..raw_data.len() {
+            let err = decode_item(&raw_data[..end]).unwrap_err();
+            assert!(err.is_truncation(), "prefix of {} bytes: {:?}", end, err);
+        }
+
+        // Object header is 3 bytes: the type byte of the first field is at 4
+        let mut unknown_type = raw_data.clone();
+        unknown_type[4] = 0x20;
+        let err = decode_item(&unknown_type).unwrap_err();
+        assert!(matches!(err, Error::UnknownType { type_byte: 0x20, offset: 4 }), "{:?}", err);
+        assert!(!err.is_truncation());
+
+        // A field longer than the object holding it is corrupt, even when
+        // bytes follow the object; the String field starts at 7
+        let mut overrun = raw_data.clone();
+        overrun[9] = 10;
+        overrun.extend_from_slice(&[0; 16]);
+        let err = decode_item(&overrun).unwrap_err();
+        assert!(matches!(err, Error::ExceedsContainer { offset: 7, .. }), "{:?}", err);
+        assert_eq!(err.offset(), Some(7));
+
+        // Strict decoding rejects what follows the item
+        let mut trailing = raw_data.clone();
+        trailing.push(0);
+        assert_eq!(decode_item(&trailing).unwrap().1, raw_data.len());
+        let err = decode_item_with_config(&trailing, &DecodeConfig::strict()).unwrap_err();
+        assert!(matches!(err, Error::TrailingData { remaining: 1, .. }), "{:?}", err);
+        assert_eq!(err.offset(), Some(raw_data.len()));
+        assert!(decode_item_with_config(&raw_data, &DecodeConfig::strict()).is_ok());
+    }
+
+    /// Rejects one tag and only allows items up to a nesting depth
+    #[derive(Debug)]
+    struct TestCheck {
+        rejected_tag: u64,
+        max_depth: usize,
+    }
+
+    impl HeaderCheck for TestCheck {
+        fn check_header(&self, parent: Option<usize>, tag: u64, _value_type: HtlvValueType) -> Result<usize> {
+            let depth = parent.map_or(0, |depth| depth + 1);
+            if tag == self.rejected_tag || depth > self.max_depth {
+                return Err(Error::SchemaError(format!("Rejected tag {} at depth {}", tag, depth)));
+            }
+            Ok(depth)
+        }
+    }
+
+    #[test]
+    fn test_decode_item_checked() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U8(1)),
+            HtlvItem::new(3, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::Bool(true))])),
+        ]));
+        let raw_data = encode_item(&item).unwrap();
+
+        let check = Arc::new(TestCheck { rejected_tag: 9, max_depth: 2 });
+        let (decoded_item, bytes_read) = decode_item_checked(&raw_data, check).unwrap();
+        assert_eq!(decoded_item, item);
+        assert_eq!(bytes_read, raw_data.len());
+
+        let check = Arc::new(TestCheck { rejected_tag: 3, max_depth: 2 });
+        let result = decode_item_checked(&raw_data, check);
+        assert_eq!(result.unwrap_err().to_string(), "Schema Error: Rejected tag 3 at depth 1");
+
+        let check = Arc::new(TestCheck { rejected_tag: 9, max_depth: 1 });
+        let result = decode_item_checked(&raw_data, check);
+        assert_eq!(result.unwrap_err().to_string(), "Schema Error: Rejected tag 0 at depth 2");
+    }
+
+    #[test]
+    fn test_decode_item_with_combined_options() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U8(1)),
+            HtlvItem::new(3, HtlvValue::String(bytes::Bytes::from("combined"))),
+        ]));
+        let raw_data = encode_item(&item).unwrap();
+        let budget = MemoryBudget::with_limit(1 << 20);
+        let options = DecodeOptions {
+            header_check: Some(Arc::new(TestCheck { rejected_tag: 9, max_depth: 2 })),
+            limits: DecodeConfig { max_total_items: 3, ..DecodeConfig::default() },
+            budget: Some(budget.clone()),
+            ..DecodeOptions::default()
+        };
+        assert_eq!(decode_item_with_options(&raw_data, &options).unwrap().0, item);
+        assert_eq!(budget.used(), 0);
+
+        // Each of them still applies when set together
+        let limited = DecodeOptions { limits: DecodeConfig { max_total_items: 2, ..DecodeConfig::default() }, ..options.clone() };
+        assert!(decode_item_with_options(&raw_data, &limited).is_err());
+        let checked = DecodeOptions { header_check: Some(Arc::new(TestCheck { rejected_tag: 3, max_depth: 2 })), ..options.clone() };
+        assert!(matches!(decode_item_with_options(&raw_data, &checked), Err(Error::SchemaError(_))));
+        let small = DecodeOptions { budget: Some(MemoryBudget::with_limit(raw_data.len())), ..options };
+        assert!(matches!(decode_item_with_options(&raw_data, &small), Err(Error::MemoryLimitExceeded { .. })));
+    }
+
     #[test]
     fn test_decode_array_batch_u8() {
         // Test decoding an Array containing a batch of U8 values
@@ -147,4 +403,67 @@ mod tests {
         assert_eq!(bytes_read, raw_data.len());
         assert_eq!(decoded_item, expected_item);
     }
+
+    #[test]
+    fn test_decode_item_with_diagnostics() {
+        use crate::internal::diagnostics::DiagnosticKind;
+
+        let diagnostics = Diagnostics::new();
+        let options = DecodeOptions { diagnostics: Some(diagnostics.clone()), ..DecodeOptions::default() };
+        // The value of a single-byte tag starts at offset 3, which is never aligned for u32
+        let raw_data = encode_item(&HtlvItem::new(1, HtlvValue::U32(7))).unwrap();
+        decode_item_with_options(&raw_data, &options).unwrap();
+        assert_eq!(diagnostics.entries()[0].kind, DiagnosticKind::UnalignedFallback { length: 4 });
+
+        let raw_data = encode_item(&HtlvItem::new(1, HtlvValue::U8(7))).unwrap();
+        diagnostics.take();
+        decode_item_with_options(&raw_data, &options).unwrap();
+        assert!(diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_decode_item_with_progress() {
+        use crate::internal::progress::{Progress, ProgressStage};
+        use std::sync::{Arc, Mutex};
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&reports);
+        let progress = ProgressReporter::new(move |progress: &Progress| seen.lock().unwrap().push(*progress));
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U8(1)),
+            HtlvItem::new(3, HtlvValue::Null),
+        ]));
+        let raw_data = encode_item(&item).unwrap();
+        decode_item_with_options(&raw_data, &DecodeOptions { progress: Some(progress), ..DecodeOptions::default() }).unwrap();
+
+        // One report per item, the enclosing Object last
+        let reports = reports.lock().unwrap();
+        assert_eq!(reports.iter().map(|p| p.items_decoded).collect::<Vec<_>>(), vec![1, 2, 3]);
+        assert!(reports.iter().all(|p| p.stage == ProgressStage::Decode && p.total_bytes == raw_data.len() as u64));
+        assert_eq!(reports[2].bytes_processed, raw_data.len() as u64);
+    }
+
+    #[test]
+    fn test_decode_item_with_cancellation() {
+        let raw_data = encode_item(&HtlvItem::new(1, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::Null)]))).unwrap();
+        let token = CancellationToken::new();
+        let options = DecodeOptions { cancellation: Some(token.clone()), ..DecodeOptions::default() };
+        assert!(decode_item_with_options(&raw_data, &options).is_ok());
+
+        token.cancel();
+        let result = decode_item_with_options(&raw_data, &options);
+        assert!(matches!(result, Err(Error::Cancelled(_))), "{:?}", result);
+    }
+
+    #[test]
+    fn test_decode_item_with_deadline() {
+        use std::time::Duration;
+
+        let raw_data = encode_item(&HtlvItem::new(1, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::Null)]))).unwrap();
+        let options = |deadline| DecodeOptions { deadline: Some(deadline), ..DecodeOptions::default() };
+        assert!(decode_item_with_options(&raw_data, &options(Deadline::after(Duration::from_secs(3600)))).is_ok());
+
+        let result = decode_item_with_options(&raw_data, &options(Deadline::after(Duration::ZERO)));
+        assert!(matches!(&result, Err(Error::DeadlineExceeded { stage, .. }) if stage == "Decoding"), "{:?}", result);
+    }
 }
@@ -1,7 +1,8 @@
 // Batch processing functions for the pipeline processor
 
 use crate::internal::error::{Error, Result};
-use crate::codec::types::{HtlvValueType, HtlvValue, HtlvItem};
+use crate::codec::types::{HtlvValueType, HtlvValue};
+use crate::codec::decode::type_table;
 
 // Import PipelineProcessor trait and related types
 use super::{PipelineProcessor, prepare_aligned_batch};
@@ -33,8 +34,8 @@ pub fn process_batch_generic<T: PipelineProcessor>(raw_data: &[u8]) -> Result<(V
 
 /// Process batch values using the pipeline processor
 ///
-/// This function selects the appropriate pipeline processor based on the element type
-/// and processes the raw data through the four-stage pipeline:
+/// This function selects the pipeline processor of the element type from the type
+/// table and processes the raw data through the four-stage pipeline:
 /// 1. Prefetch: Prepare data for efficient processing by ensuring proper alignment
 /// 2. Decode: Convert raw bytes to typed values using the aligned data
 /// 3. Dispatch: Process decoded values
@@ -46,26 +47,10 @@ pub fn process_batch_value(
     _length: u64,
     raw_value_slice: &[u8],
 ) -> Result<HtlvValue> {
-    let (htlv_values, _) = match element_type {
-        HtlvValueType::U8 => process_batch_generic::<u8>(raw_value_slice)?,
-        HtlvValueType::U16 => process_batch_generic::<u16>(raw_value_slice)?,
-        HtlvValueType::U32 => process_batch_generic::<u32>(raw_value_slice)?,
-        HtlvValueType::U64 => process_batch_generic::<u64>(raw_value_slice)?,
-        HtlvValueType::I8 => process_batch_generic::<i8>(raw_value_slice)?,
-        HtlvValueType::I16 => process_batch_generic::<i16>(raw_value_slice)?,
-        HtlvValueType::I32 => process_batch_generic::<i32>(raw_value_slice)?,
-        HtlvValueType::I64 => process_batch_generic::<i64>(raw_value_slice)?,
-        HtlvValueType::F32 => process_batch_generic::<f32>(raw_value_slice)?,
-        HtlvValueType::F64 => process_batch_generic::<f64>(raw_value_slice)?,
-        _ => return Err(Error::CodecError(format!("Unsupported type for batch processing: {:?}", element_type))),
-    };
-
-    // Convert to HtlvItems and wrap in an Array
-    let items: Vec<HtlvItem> = htlv_values.into_iter()
-        .map(|v| HtlvItem::new(0, v))
-        .collect();
-
-    Ok(HtlvValue::Array(items))
+    match type_table::handler(element_type).batch_decode {
+        Some(batch_decode) => batch_decode(raw_value_slice),
+        None => Err(Error::CodecError(format!("Unsupported type for batch processing: {:?}", element_type))),
+    }
 }
 
 #[cfg(test)]
@@ -0,0 +1,361 @@
+// Allocation-free pull decoder for HTLV data
+//
+// `PullDecoder` walks an encoded buffer and yields events that borrow from it,
+// without building `HtlvItem` trees. It keeps its nesting stack in a fixed-size
+// array and reports errors with the `Copy` type `PullError`, so it only depends
+// on `core` and never touches the heap. This makes it suitable for
+// microcontroller gateways that parse Tonitru frames from a static buffer.
+
+use core::fmt;
+use crate::codec::types::HtlvValueType;
+use crate::internal::error::Error;
+use super::decoder_state_machine::MAX_NESTING_DEPTH;
+
+/// An event produced by the pull decoder.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PullEvent<'a> {
+    /// Start of an object with the given tag; its fields follow.
+    BeginObject(u64),
+    /// End of the innermost open object.
+    EndObject,
+    /// Start of an array with the given tag; its elements follow.
+    BeginArray(u64),
+    /// End of the innermost open array.
+    EndArray,
+    /// A basic value: tag, type and the raw value bytes borrowed from the input.
+    ///
+    /// Large Bytes or String values are sharded by the encoder; they appear as a
+    /// header field (holding the total length) followed by one field per shard,
+    /// all with the same tag.
+    Field(u64, HtlvValueType, &'a [u8]),
+}
+
+/// Errors reported by the pull decoder. These never allocate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PullError {
+    /// The input ended in the middle of an item at the given offset.
+    Truncated { offset: usize },
+    /// A varint at the given offset does not fit in a u64.
+    VarintOverflow { offset: usize },
+    /// The type byte at the given offset is not a known value type.
+    UnknownType { offset: usize, byte: u8 },
+    /// The item at the given offset extends past its enclosing array or object.
+    LengthOutOfBounds { offset: usize },
+    /// Objects and arrays are nested deeper than `MAX_NESTING_DEPTH`.
+    DepthExceeded,
+    /// A scalar value has the wrong size or type for the requested conversion.
+    InvalidScalar,
+}
+
+impl fmt::Display for PullError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PullError::Truncated { offset } => write!(f, "Incomplete item at offset {}", offset),
+            PullError::VarintOverflow { offset } => write!(f, "Varint value too large at offset {}", offset),
+            PullError::UnknownType { offset, byte } => write!(f, "Unknown value type tag {} at offset {}", byte, offset),
+            PullError::LengthOutOfBounds { offset } => write!(f, "Item at offset {} exceeds its container", offset),
+            PullError::DepthExceeded => write!(f, "Maximum nesting depth ({}) exceeded", MAX_NESTING_DEPTH),
+            PullError::InvalidScalar => write!(f, "Invalid scalar value"),
+        }
+    }
+}
+
+impl From<PullError> for Error {
+    fn from(err: PullError) -> Self {
+        Error::CodecError(err.to_string())
+    }
+}
+
+/// An open object or array on the decoder stack.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    /// Offset at which the container's value ends
+    end: usize,
+    /// Whether the container is an array (otherwise an object)
+    is_array: bool,
+}
+
+/// A pull decoder yielding `PullEvent`s over a borrowed buffer without allocating.
+///
+/// The decoder is an iterator; it stops after the first error.
+#[derive(Debug)]
+pub struct PullDecoder<'a> {
+    data: &'a [u8],
+    offset: usize,
+    stack: [Frame; MAX_NESTING_DEPTH],
+    depth: usize,
+    failed: bool,
+}
+
+impl<'a> PullDecoder<'a> {
+    /// Creates a pull decoder over a buffer holding one or more encoded items.
+    pub fn new(data: &'a [u8]) -> Self {
+        PullDecoder {
+            data,
+            offset: 0,
+            stack: [Frame { end: 0, is_array: false }; MAX_NESTING_DEPTH],
+            depth: 0,
+            failed: false,
+        }
+    }
+
+    /// Returns the number of bytes consumed so far.
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Returns the current nesting depth.
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Returns the next event, `Ok(None)` at the end of the input.
+    pub fn next_event(&mut self) -> Result<Option<PullEvent<'a>>, PullError> {
+        if self.failed {
+            return Ok(None);
+        }
+        let result = self.advance();
+        if result.is_err() {
+            self.failed = true;
+        }
+        result
+    }
+
+    fn advance(&mut self) -> Result<Option<PullEvent<'a>>, PullError> {
+        // Close the innermost container once its value is fully consumed
+        let limit = match self.depth.checked_sub(1).map(|top| self.stack[top]) {
+            Some(frame) if self.offset >= frame.end => {
+                self.depth -= 1;
+                return Ok(Some(if frame.is_array { PullEvent::EndArray } else { PullEvent::EndObject }));
+            }
+            Some(frame) => frame.end,
+            None if self.offset >= self.data.len() => return Ok(None),
+            None => self.data.len(),
+        };
+
+        let item_offset = self.offset;
+        let (tag, cursor) = self.read_varint(item_offset)?;
+        let type_byte = *self.data.get(cursor).ok_or(PullError::Truncated { offset: item_offset })?;
+        let value_type = HtlvValueType::from_byte(type_byte)
+            .ok_or(PullError::UnknownType { offset: cursor, byte: type_byte })?;
+        let (length, value_start) = self.read_varint(cursor + 1)?;
+
+        let value_end = usize::try_from(length)
+            .ok()
+            .and_then(|length| value_start.checked_add(length))
+            .ok_or(PullError::LengthOutOfBounds { offset: item_offset })?;
+        if value_end > self.data.len() {
+            return Err(PullError::Truncated { offset: item_offset });
+        }
+        if value_end > limit {
+            return Err(PullError::LengthOutOfBounds { offset: item_offset });
+        }
+
+        match value_type {
+            HtlvValueType::Array | HtlvValueType::Object => {
+                if self.depth == MAX_NESTING_DEPTH {
+                    return Err(PullError::DepthExceeded);
+                }
+                let is_array = value_type == HtlvValueType::Array;
+                self.stack[self.depth] = Frame { end: value_end, is_array };
+                self.depth += 1;
+                self.offset = value_start;
+                Ok(Some(if is_array { PullEvent::BeginArray(tag) } else { PullEvent::BeginObject(tag) }))
+            }
+            _ => {
+                self.offset = value_end;
+                Ok(Some(PullEvent::Field(tag, value_type, &self.data[value_start..value_end])))
+            }
+        }
+    }
+
+    /// Reads a varint at `offset`, returning the value and the offset after it.
+    fn read_varint(&self, offset: usize) -> Result<(u64, usize), PullError> {
+        let mut value = 0u64;
+        let mut shift = 0;
+        for (i, &byte) in self.data.get(offset..).unwrap_or(&[]).iter().enumerate() {
+            // The tenth byte only holds the last bit of a u64 and ends the varint
+            if shift == 63 && byte > 1 {
+                return Err(PullError::VarintOverflow { offset });
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok((value, offset + i + 1));
+            }
+            shift += 7;
+        }
+        Err(PullError::Truncated { offset })
+    }
+}
+
+impl<'a> Iterator for PullDecoder<'a> {
+    type Item = Result<PullEvent<'a>, PullError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+/// Reads a Bool field value.
+pub fn read_bool(value_type: HtlvValueType, value: &[u8]) -> Result<bool, PullError> {
+    match (value_type, value) {
+        (HtlvValueType::Bool, [byte]) => Ok(*byte != 0),
+        _ => Err(PullError::InvalidScalar),
+    }
+}
+
+/// Reads an unsigned integer field value (U8 to U64), widened to u64.
+pub fn read_u64(value_type: HtlvValueType, value: &[u8]) -> Result<u64, PullError> {
+    let width = match value_type {
+        HtlvValueType::U8 => 1,
+        HtlvValueType::U16 => 2,
+        HtlvValueType::U32 => 4,
+        HtlvValueType::U64 => 8,
+        _ => return Err(PullError::InvalidScalar),
+    };
+    let mut bytes = [0u8; 8];
+    le_bytes(value, width, &mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Reads a signed integer field value (I8 to I64), sign-extended to i64.
+pub fn read_i64(value_type: HtlvValueType, value: &[u8]) -> Result<i64, PullError> {
+    let width = match value_type {
+        HtlvValueType::I8 => 1,
+        HtlvValueType::I16 => 2,
+        HtlvValueType::I32 => 4,
+        HtlvValueType::I64 => 8,
+        _ => return Err(PullError::InvalidScalar),
+    };
+    let mut bytes = [0u8; 8];
+    le_bytes(value, width, &mut bytes)?;
+    // Shift the value to the top and back to sign-extend it
+    let unused_bits = (8 - width) as u32 * 8;
+    Ok((i64::from_le_bytes(bytes) << unused_bits) >> unused_bits)
+}
+
+/// Reads a floating point field value (F32 or F64), widened to f64.
+pub fn read_f64(value_type: HtlvValueType, value: &[u8]) -> Result<f64, PullError> {
+    match value_type {
+        HtlvValueType::F32 => {
+            let mut bytes = [0u8; 4];
+            le_bytes(value, 4, &mut bytes)?;
+            Ok(f32::from_le_bytes(bytes) as f64)
+        }
+        HtlvValueType::F64 => {
+            let mut bytes = [0u8; 8];
+            le_bytes(value, 8, &mut bytes)?;
+            Ok(f64::from_le_bytes(bytes))
+        }
+        _ => Err(PullError::InvalidScalar),
+    }
+}
+
+/// Reads a String field value as UTF-8 text borrowed from the input.
+pub fn read_str(value_type: HtlvValueType, value: &[u8]) -> Result<&str, PullError> {
+    match value_type {
+        HtlvValueType::String => core::str::from_utf8(value).map_err(|_| PullError::InvalidScalar),
+        _ => Err(PullError::InvalidScalar),
+    }
+}
+
+/// Copies exactly `width` little-endian bytes into the start of `out`.
+fn le_bytes(value: &[u8], width: usize, out: &mut [u8]) -> Result<(), PullError> {
+    if value.len() != width {
+        return Err(PullError::InvalidScalar);
+    }
+    out[..width].copy_from_slice(value);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::{HtlvItem, HtlvValue};
+    use bytes::Bytes;
+
+    #[test]
+    fn test_pull_events_for_nested_object() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U16(513)),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::I8(-5)),
+            ])),
+            HtlvItem::new(4, HtlvValue::String(Bytes::from_static(b"gateway"))),
+        ]));
+        let encoded = encode_item(&item).unwrap();
+
+        let mut decoder = PullDecoder::new(&encoded);
+        assert_eq!(decoder.next_event(), Ok(Some(PullEvent::BeginObject(1))));
+        assert_eq!(decoder.next_event(), Ok(Some(PullEvent::Field(2, HtlvValueType::U16, &[0x01, 0x02][..]))));
+        assert_eq!(decoder.next_event(), Ok(Some(PullEvent::BeginArray(3))));
+        match decoder.next_event() {
+            Ok(Some(PullEvent::Field(0, value_type, value))) => assert_eq!(read_i64(value_type, value), Ok(-5)),
+            other => panic!("Expected I8 field, got {:?}", other),
+        }
+        assert_eq!(decoder.next_event(), Ok(Some(PullEvent::EndArray)));
+        match decoder.next_event() {
+            Ok(Some(PullEvent::Field(4, value_type, value))) => assert_eq!(read_str(value_type, value), Ok("gateway")),
+            other => panic!("Expected String field, got {:?}", other),
+        }
+        assert_eq!(decoder.next_event(), Ok(Some(PullEvent::EndObject)));
+        assert_eq!(decoder.next_event(), Ok(None));
+        assert_eq!(decoder.offset(), encoded.len());
+    }
+
+    #[test]
+    fn test_scalar_readers() {
+        assert_eq!(read_bool(HtlvValueType::Bool, &[1]), Ok(true));
+        assert_eq!(read_u64(HtlvValueType::U32, &7u32.to_le_bytes()), Ok(7));
+        assert_eq!(read_i64(HtlvValueType::I16, &(-300i16).to_le_bytes()), Ok(-300));
+        assert_eq!(read_f64(HtlvValueType::F32, &1.5f32.to_le_bytes()), Ok(1.5));
+        assert_eq!(read_u64(HtlvValueType::U32, &[1, 2]), Err(PullError::InvalidScalar));
+        assert_eq!(read_u64(HtlvValueType::I32, &[0; 4]), Err(PullError::InvalidScalar));
+    }
+
+    #[test]
+    fn test_truncated_input() {
+        let encoded = encode_item(&HtlvItem::new(1, HtlvValue::U64(42))).unwrap();
+        let mut decoder = PullDecoder::new(&encoded[..encoded.len() - 1]);
+        assert_eq!(decoder.next_event(), Err(PullError::Truncated { offset: 0 }));
+        // The decoder stops after an error
+        assert_eq!(decoder.next(), None);
+    }
+
+    #[test]
+    fn test_varint_overflow() {
+        // A tag of 2^63 takes all ten bytes, the last one holding a single bit
+        let mut encoded = vec![0x80; 9];
+        encoded.extend_from_slice(&[0x01, HtlvValueType::Null as u8, 0x00]);
+        assert_eq!(PullDecoder::new(&encoded).next_event(), Ok(Some(PullEvent::Field(1 << 63, HtlvValueType::Null, &[][..]))));
+
+        // Higher bits in the tenth byte do not fit a u64
+        encoded[9] = 0x02;
+        assert_eq!(PullDecoder::new(&encoded).next_event(), Err(PullError::VarintOverflow { offset: 0 }));
+        encoded[9] = 0x81;
+        assert_eq!(PullDecoder::new(&encoded).next_event(), Err(PullError::VarintOverflow { offset: 0 }));
+    }
+
+    #[test]
+    fn test_item_exceeding_container() {
+        // Object of length 3 containing a U8 item of length 1 (4 bytes)
+        let data = [0x01, HtlvValueType::Object as u8, 0x03, 0x02, HtlvValueType::U8 as u8, 0x01, 0x07];
+        let events: Vec<_> = PullDecoder::new(&data).collect();
+        assert_eq!(events, vec![
+            Ok(PullEvent::BeginObject(1)),
+            Err(PullError::LengthOutOfBounds { offset: 3 }),
+        ]);
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let mut item = HtlvItem::new(0, HtlvValue::Null);
+        for _ in 0..=MAX_NESTING_DEPTH {
+            item = HtlvItem::new(0, HtlvValue::Array(vec![item]));
+        }
+        let encoded = encode_item(&item).unwrap();
+        let result: Result<Vec<_>, _> = PullDecoder::new(&encoded).collect();
+        assert_eq!(result, Err(PullError::DepthExceeded));
+    }
+}
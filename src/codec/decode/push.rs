@@ -0,0 +1,189 @@
+// Incremental decoder for partial network buffers
+//
+// `decode_item` needs the whole item in one slice. A non-blocking socket hands
+// over whatever has arrived, which may end in the middle of an item. A
+// `PushDecoder` is fed these chunks as they come and answers with either the
+// next complete item or the number of bytes it needs at least before trying
+// again, so the caller knows when another read is worth the decode attempt.
+//
+// Chunks are appended to an internal buffer and the item at its front is
+// decoded with a decode context kept across calls. A truncated item fails with
+// `Error::UnexpectedEof`, whose missing byte count becomes the hint; no
+// decoding is attempted until that many bytes have been fed. As the length of
+// an array or object is read first, a large nested item is decoded once, after
+// its last byte arrives. Items come out as `decode_item` returns them from the
+// whole stream, so a sharded large field yields its header item and then its
+// shards. Any other error means the stream is corrupt: it is
+// returned on every call until `clear`, and the connection should be dropped.
+//
+// The buffer holds the items that follow, so strict decoding does not apply.
+
+use bytes::{Buf, BytesMut};
+
+use crate::codec::decode::decoder_state_machine::DecodeContext;
+use crate::codec::decode::limits::DecodeConfig;
+use crate::codec::decode::run_decode_in;
+use crate::codec::types::HtlvItem;
+use crate::config::TonitruConfig;
+use crate::internal::error::{Error, Result};
+
+/// Outcome of a decode attempt on the buffered data.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecodeProgress {
+    /// The item is incomplete; at least this many more bytes are needed.
+    NeedMoreData(usize),
+    /// The next item, whose bytes have been consumed.
+    Item(HtlvItem),
+}
+
+/// A decoder accepting data in arbitrary chunks.
+#[derive(Debug)]
+pub struct PushDecoder {
+    ctx: DecodeContext,
+    buffer: BytesMut,
+    /// Buffered length below which the item at the front is known incomplete
+    wanted: usize,
+    /// Message of the error that left the stream undecodable
+    failed: Option<String>,
+}
+
+impl Default for PushDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl PushDecoder {
+    /// Creates a decoder with the default limits.
+    pub fn new() -> Self {
+        Self::with_limits(DecodeConfig::default())
+    }
+
+    /// Creates a decoder applying the decoder limits of the configuration.
+    pub fn with_config(config: &TonitruConfig) -> Self {
+        Self::with_limits(config.decode_config())
+    }
+
+    /// Creates a decoder applying the given limits; `strict` is ignored.
+    pub fn with_limits(limits: DecodeConfig) -> Self {
+        let mut ctx = DecodeContext::new(&[]);
+        ctx.limits = DecodeConfig { strict: false, ..limits };
+        PushDecoder { ctx, buffer: BytesMut::new(), wanted: 1, failed: None }
+    }
+
+    /// Returns the number of bytes fed but not consumed by a decoded item.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Appends a chunk and attempts to decode the next item.
+    pub fn feed(&mut self, chunk: &[u8]) -> Result<DecodeProgress> {
+        self.buffer.extend_from_slice(chunk);
+        self.poll()
+    }
+
+    /// Attempts to decode the next item from the data fed so far. Call it again
+    /// after an `Item` to drain items arriving in the same chunk.
+    pub fn poll(&mut self) -> Result<DecodeProgress> {
+        if let Some(message) = &self.failed {
+            return Err(Error::CodecError(format!("Stream is corrupt: {}", message)));
+        }
+        if self.buffer.len() < self.wanted {
+            return Ok(DecodeProgress::NeedMoreData(self.wanted - self.buffer.len()));
+        }
+
+        self.ctx.reset(&self.buffer);
+        match run_decode_in(&mut self.ctx) {
+            Ok((item, length)) => {
+                self.buffer.advance(length);
+                self.wanted = 1;
+                Ok(DecodeProgress::Item(item))
+            }
+            Err(Error::UnexpectedEof { needed, got, .. }) => {
+                let missing = usize::try_from(needed.saturating_sub(got as u64)).unwrap_or(usize::MAX).max(1);
+                self.wanted = self.buffer.len().saturating_add(missing);
+                Ok(DecodeProgress::NeedMoreData(missing))
+            }
+            Err(e) => {
+                self.failed = Some(e.to_string());
+                Err(e)
+            }
+        }
+    }
+
+    /// Drops the buffered data and any error, to decode a new stream.
+    pub fn clear(&mut self) {
+        self.buffer.clear();
+        self.wanted = 1;
+        self.failed = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::{encode_item, LARGE_FIELD_THRESHOLD};
+    use crate::codec::types::HtlvValue;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_items_split_across_chunks() {
+        let items = vec![
+            HtlvItem::new(1, HtlvValue::Object(vec![
+                HtlvItem::new(2, HtlvValue::U8(7)),
+                HtlvItem::new(3, HtlvValue::String(Bytes::from("pushed"))),
+            ])),
+            HtlvItem::new(4, HtlvValue::Bytes(Bytes::from(vec![9u8; LARGE_FIELD_THRESHOLD + 100]))),
+            HtlvItem::new(5, HtlvValue::Bool(true)),
+        ];
+        let mut stream = Vec::new();
+        for item in &items {
+            stream.extend_from_slice(&encode_item(item).unwrap());
+        }
+        // The items decode_item reads one after another from the whole stream
+        let mut expected = Vec::new();
+        let mut offset = 0;
+        while offset < stream.len() {
+            let (item, length) = crate::codec::decode::decode_item(&stream[offset..]).unwrap();
+            expected.push(item);
+            offset += length;
+        }
+
+        for chunk_size in [1, 7, 4096, stream.len()] {
+            let mut decoder = PushDecoder::new();
+            let mut decoded = Vec::new();
+            for chunk in stream.chunks(chunk_size) {
+                let mut progress = decoder.feed(chunk).unwrap();
+                while let DecodeProgress::Item(item) = progress {
+                    decoded.push(item);
+                    progress = decoder.poll().unwrap();
+                }
+            }
+            assert_eq!(decoded, expected, "chunks of {} bytes", chunk_size);
+            assert_eq!(decoder.buffered(), 0);
+        }
+        assert_eq!(expected[0], items[0]);
+        assert_eq!(expected.last(), items.last());
+    }
+
+    #[test]
+    fn test_hints_and_corruption() {
+        let encoded = encode_item(&HtlvItem::new(1, HtlvValue::Bytes(Bytes::from(vec![1u8; 100])))).unwrap();
+        let mut decoder = PushDecoder::new();
+        // Once the header is in, the hint is the rest of the value
+        assert_eq!(decoder.feed(&encoded[..3]).unwrap(), DecodeProgress::NeedMoreData(100));
+        assert_eq!(decoder.feed(&encoded[3..50]).unwrap(), DecodeProgress::NeedMoreData(53));
+        assert!(matches!(decoder.feed(&encoded[50..]).unwrap(), DecodeProgress::Item(_)));
+        assert_eq!(decoder.poll().unwrap(), DecodeProgress::NeedMoreData(1));
+
+        // An unknown type byte is fatal until the decoder is cleared
+        assert!(decoder.feed(&[1, 0x20, 0]).is_err());
+        assert!(decoder.feed(&encoded).is_err());
+        decoder.clear();
+        assert!(matches!(decoder.feed(&encoded).unwrap(), DecodeProgress::Item(_)));
+
+        // A Bytes value as long as a large field header comes out as it is
+        let eight = HtlvItem::new(2, HtlvValue::Bytes(Bytes::from(vec![3u8; 8])));
+        assert_eq!(decoder.feed(&encode_item(&eight).unwrap()).unwrap(), DecodeProgress::Item(eight));
+    }
+}
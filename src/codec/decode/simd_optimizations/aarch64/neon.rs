@@ -1,124 +1,64 @@
 // NEON optimizations for aarch64 architecture
 //
-// This module contains SIMD-optimized implementations using NEON instructions.
+// This module contains the batch decode kernels for aarch64, offering the same
+// functions as the x86_64 kernels so that ARM servers such as Graviton take the
+// same dispatch paths. NEON is part of every aarch64 target, so unlike the
+// x86_64 kernels these need no runtime check. Aligned input is borrowed in
+// place; unaligned input is moved into an aligned buffer 16 bytes at a time
+// with `vld1q_u8`/`vst1q_u8`, which accept any alignment. aarch64 targets are
+// little-endian, like the wire format, so no byte swapping is needed.
 
-use crate::internal::error::{Error, Result};
-use std::mem;
-use std::slice;
+use crate::internal::error::Result;
+use super::super::BatchResult;
+use std::arch::aarch64::{vld1q_u8, vst1q_u8};
 
-#[cfg(target_arch = "aarch64")]
-use std::arch::aarch64::*;
+/// Bytes moved by one 128-bit load and store
+const REGISTER_BYTES: usize = 16;
 
-/// Decodes a batch of u32 values using NEON SIMD instructions.
-/// Returns a slice of the decoded elements and the number of bytes read.
-#[cfg(target_arch = "aarch64")]
-pub fn decode_u32_batch_simd(data: &[u8]) -> Result<(&[u32], usize)> {
-    let size = mem::size_of::<u32>();
-    if data.len() % size != 0 {
-        return Err(Error::CodecError(format!(
-            "Invalid data length for U32 batch decoding. Length ({}) must be a multiple of {}",
-            data.len(),
-            size
-        )));
-    }
+/// Decodes a batch of u32 values on aarch64.
+/// Returns a BatchResult containing the decoded elements and the number of bytes read.
+pub fn decode_u32_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, u32>, usize)> {
+    BatchResult::from_le_bytes(data, "U32", copy_neon)
+}
 
-    let count = data.len() / size;
-    if count == 0 {
-        return Ok((&[], 0));
-    }
+/// Decodes a batch of u64 values on aarch64.
+pub fn decode_u64_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, u64>, usize)> {
+    BatchResult::from_le_bytes(data, "U64", copy_neon)
+}
 
-    // Check alignment for NEON operations
-    let aligned = (data.as_ptr() as usize) % mem::align_of::<u32>() == 0;
-    
-    if aligned {
-        // For aligned data, we can simply reinterpret the slice
-        // This is safe because we've already checked size and alignment
-        let decoded_slice = unsafe {
-            slice::from_raw_parts(data.as_ptr() as *const u32, count)
-        };
-        
-        Ok((decoded_slice, data.len()))
-    } else {
-        // For unaligned data, we need to use unaligned loads
-        // This is more complex and would require manual SIMD implementation
-        // For now, we'll use a scalar fallback for unaligned data
-        let mut values = Vec::with_capacity(count);
-        for i in 0..count {
-            let offset = i * size;
-            let value = u32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]);
-            values.push(value);
-        }
-        
-        // Return a reference to the vector's data
-        let slice = unsafe {
-            slice::from_raw_parts(values.as_ptr(), values.len())
-        };
-        
-        // We need to leak the vector to ensure the data remains valid
-        std::mem::forget(values);
-        
-        Ok((slice, data.len()))
-    }
+/// Decodes a batch of f32 values on aarch64.
+pub fn decode_f32_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, f32>, usize)> {
+    BatchResult::from_le_bytes(data, "F32", copy_neon)
 }
 
-/// Decodes a batch of f32 values using NEON SIMD instructions.
-/// Returns a slice of the decoded elements and the number of bytes read.
-#[cfg(target_arch = "aarch64")]
-pub fn decode_f32_batch_simd(data: &[u8]) -> Result<(&[f32], usize)> {
-    let size = mem::size_of::<f32>();
-    if data.len() % size != 0 {
-        return Err(Error::CodecError(format!(
-            "Invalid data length for F32 batch decoding. Length ({}) must be a multiple of {}",
-            data.len(),
-            size
-        )));
-    }
+/// Decodes a batch of f64 values on aarch64.
+pub fn decode_f64_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, f64>, usize)> {
+    BatchResult::from_le_bytes(data, "F64", copy_neon)
+}
 
-    let count = data.len() / size;
-    if count == 0 {
-        return Ok((&[], 0));
-    }
+/// Decodes a batch of u8 values. For u8 no conversion is needed; it is
+/// included for API consistency with the x86_64 kernels.
+pub fn decode_u8_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, u8>, usize)> {
+    Ok((BatchResult::borrowed(data), data.len()))
+}
+
+/// Decodes a batch of i8 values.
+pub fn decode_i8_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, i8>, usize)> {
+    // i8 and u8 have the same memory layout
+    Ok((BatchResult::borrowed(bytemuck::cast_slice(data)), data.len()))
+}
 
-    // Check alignment for NEON operations
-    let aligned = (data.as_ptr() as usize) % mem::align_of::<f32>() == 0;
-    
-    if aligned {
-        // For aligned data, we can simply reinterpret the slice
-        // This is safe because we've already checked size and alignment
-        let decoded_slice = unsafe {
-            slice::from_raw_parts(data.as_ptr() as *const f32, count)
-        };
-        
-        Ok((decoded_slice, data.len()))
-    } else {
-        // For unaligned data, we need to use unaligned loads
-        // This is more complex and would require manual SIMD implementation
-        // For now, we'll use a scalar fallback for unaligned data
-        let mut values = Vec::with_capacity(count);
-        for i in 0..count {
-            let offset = i * size;
-            let value = f32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]);
-            values.push(value);
+/// Copies `src` into `dst` with 128-bit loads and stores.
+fn copy_neon(src: &[u8], dst: &mut [u8]) {
+    assert_eq!(src.len(), dst.len());
+    let full = src.len() - src.len() % REGISTER_BYTES;
+    for offset in (0..full).step_by(REGISTER_BYTES) {
+        // SAFETY: NEON is always available on aarch64, both ranges are in
+        // bounds, and the loads and stores need no alignment
+        unsafe {
+            let register = vld1q_u8(src.as_ptr().add(offset));
+            vst1q_u8(dst.as_mut_ptr().add(offset), register);
         }
-        
-        // Return a reference to the vector's data
-        let slice = unsafe {
-            slice::from_raw_parts(values.as_ptr(), values.len())
-        };
-        
-        // We need to leak the vector to ensure the data remains valid
-        std::mem::forget(values);
-        
-        Ok((slice, data.len()))
     }
+    dst[full..].copy_from_slice(&src[full..]);
 }
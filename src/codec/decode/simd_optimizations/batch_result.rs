@@ -5,6 +5,8 @@
 
 use std::ops::Deref;
 
+use crate::internal::error::{Error, Result};
+
 /// A batch of decoded values, either borrowed or owned.
 /// This enum allows us to return either a borrowed slice or an owned vector,
 /// depending on whether the data was aligned or not.
@@ -98,3 +100,29 @@ impl<'a, T> BatchResult<'a, T> {
         BatchResult::Owned(vec)
     }
 }
+
+impl<'a, T: bytemuck::Pod> BatchResult<'a, T> {
+    /// Decodes a batch of fixed-size little-endian values, borrowing `data`
+    /// when it is aligned for `T` and copying it into an aligned buffer with
+    /// `copy` otherwise, which is given the input and an output of the same
+    /// length. Returns the batch and the number of bytes read. The targets with
+    /// batch kernels are little-endian, like the wire format, so no byte
+    /// swapping is needed.
+    pub(crate) fn from_le_bytes(data: &'a [u8], type_name: &str, copy: fn(&[u8], &mut [u8])) -> Result<(Self, usize)> {
+        let size = std::mem::size_of::<T>();
+        if !data.len().is_multiple_of(size) {
+            return Err(Error::CodecError(format!(
+                "Invalid data length for {} batch decoding. Length ({}) must be a multiple of {}",
+                type_name,
+                data.len(),
+                size
+            )));
+        }
+        if let Ok(values) = bytemuck::try_cast_slice(data) {
+            return Ok((BatchResult::borrowed(values), data.len()));
+        }
+        let mut values = vec![T::zeroed(); data.len() / size];
+        copy(data, bytemuck::cast_slice_mut::<T, u8>(&mut values));
+        Ok((BatchResult::owned(values), data.len()))
+    }
+}
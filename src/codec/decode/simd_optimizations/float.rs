@@ -11,12 +11,19 @@ pub fn decode_f32_batch_simd(data: &[u8]) -> Result<(super::BatchResult<f32>, us
 
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
-        if std::is_x86_feature_detected!("sse4.1") {
+        if super::simd_enabled() && std::is_x86_feature_detected!("sse4.1") {
             // Use the re-exported function from the main module
             return super::x86_64::sse41::decode_f32_batch_simd(data);
         }
     }
 
+    #[cfg(all(target_arch = "aarch64", feature = "simd"))]
+    {
+        if super::simd_enabled() {
+            return super::aarch64::neon::decode_f32_batch_simd(data);
+        }
+    }
+
     // Fallback to non-SIMD implementation
     let size = std::mem::size_of::<f32>();
     if data.len() % size != 0 {
@@ -66,6 +73,13 @@ pub fn decode_f32_batch_simd(data: &[u8]) -> Result<(super::BatchResult<f32>, us
 pub fn decode_f64_batch_simd(data: &[u8]) -> Result<(super::BatchResult<f64>, usize)> {
     use super::BatchResult;
 
+    #[cfg(all(target_arch = "aarch64", feature = "simd"))]
+    {
+        if super::simd_enabled() {
+            return super::aarch64::neon::decode_f64_batch_simd(data);
+        }
+    }
+
     // Check if data length is valid
     let size = std::mem::size_of::<f64>();
     if data.len() % size != 0 {
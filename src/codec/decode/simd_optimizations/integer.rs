@@ -25,12 +25,19 @@ pub fn decode_u32_batch_simd(data: &[u8]) -> Result<(super::BatchResult<u32>, us
 
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
-        if std::is_x86_feature_detected!("sse4.1") {
+        if super::simd_enabled() && std::is_x86_feature_detected!("sse4.1") {
             // Use the re-exported function from the main module
             return super::x86_64::sse41::decode_u32_batch_simd(data);
         }
     }
 
+    #[cfg(all(target_arch = "aarch64", feature = "simd"))]
+    {
+        if super::simd_enabled() {
+            return super::aarch64::neon::decode_u32_batch_simd(data);
+        }
+    }
+
     // Check alignment
     let aligned = (data.as_ptr() as usize) % std::mem::align_of::<u32>() == 0;
 
@@ -68,11 +75,18 @@ pub fn decode_u8_batch_simd(data: &[u8]) -> Result<(super::BatchResult<u8>, usiz
 
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
-        if std::is_x86_feature_detected!("sse4.1") {
+        if super::simd_enabled() && std::is_x86_feature_detected!("sse4.1") {
             return super::x86_64::sse41::decode_u8_batch_simd(data);
         }
     }
 
+    #[cfg(all(target_arch = "aarch64", feature = "simd"))]
+    {
+        if super::simd_enabled() {
+            return super::aarch64::neon::decode_u8_batch_simd(data);
+        }
+    }
+
     // Fallback to non-SIMD implementation
     Ok((BatchResult::borrowed(data), data.len()))
 }
@@ -83,11 +97,18 @@ pub fn decode_i8_batch_simd(data: &[u8]) -> Result<(super::BatchResult<i8>, usiz
 
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
-        if std::is_x86_feature_detected!("sse4.1") {
+        if super::simd_enabled() && std::is_x86_feature_detected!("sse4.1") {
             return super::x86_64::sse41::decode_i8_batch_simd(data);
         }
     }
 
+    #[cfg(all(target_arch = "aarch64", feature = "simd"))]
+    {
+        if super::simd_enabled() {
+            return super::aarch64::neon::decode_i8_batch_simd(data);
+        }
+    }
+
     // For i8, we can simply reinterpret the slice
     // This is safe because i8 and u8 have the same memory layout
     let decoded_slice = unsafe {
@@ -10,6 +10,7 @@
 // Import error types
 #[allow(unused_imports)]
 use crate::internal::error::Result;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // Re-export architecture-specific modules
 #[cfg(target_arch = "x86_64")]
@@ -45,8 +46,28 @@ pub use x86_64::sse41::decode_u8_batch_simd;
 #[cfg(target_arch = "x86_64")]
 pub use x86_64::sse41::decode_i8_batch_simd;
 
+#[cfg(target_arch = "aarch64")]
+pub use aarch64::neon::{decode_f32_batch_simd, decode_i8_batch_simd, decode_u32_batch_simd, decode_u8_batch_simd};
+
+// Process-wide switch that lets configuration turn the SIMD paths off
+static SIMD_ENABLED: AtomicBool = AtomicBool::new(true);
+
+/// Enables or disables the SIMD decoding paths for the whole process.
+/// Disabling them forces the scalar fallbacks even where SIMD is supported.
+pub fn set_simd_enabled(enabled: bool) {
+    SIMD_ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+/// Returns whether the SIMD decoding paths are enabled.
+pub fn simd_enabled() -> bool {
+    SIMD_ENABLED.load(Ordering::Relaxed)
+}
+
 // Helper function to check if SIMD is available for the current platform
 pub fn is_simd_available() -> bool {
+    if !simd_enabled() {
+        return false;
+    }
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
         std::is_x86_feature_detected!("sse4.1")
@@ -66,8 +87,13 @@ pub fn is_simd_available() -> bool {
     }
 }
 
-// Helper function to get the best available SIMD instruction set for the current platform
+// Helper function to get the best available SIMD instruction set for the current platform,
+// or None when the SIMD paths are disabled
 pub fn get_simd_instruction_set() -> Option<&'static str> {
+    if !simd_enabled() {
+        return None;
+    }
+
     #[cfg(all(target_arch = "x86_64", feature = "simd"))]
     {
         if std::is_x86_feature_detected!("avx512f") {
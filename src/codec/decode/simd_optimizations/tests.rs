@@ -70,6 +70,105 @@ mod tests {
             assert_eq!(batch_result.as_slice(), expected);
             assert_eq!(bytes_consumed, data.len());
         }
+
+        #[test]
+        fn test_decode_unaligned_batches_avx2_and_avx512() {
+            // 37 elements leave a tail after the last full register
+            let values: Vec<u64> = (0..37).map(|i| i * 0x0101_0101_0101).collect();
+            let floats: Vec<f32> = (0..37).map(|i| i as f32 * 0.25).collect();
+            let mut data = vec![0u8];
+            data.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+            let mut float_data = vec![0u8];
+            float_data.extend(floats.iter().flat_map(|value| value.to_le_bytes()));
+
+            if std::is_x86_feature_detected!("avx2") {
+                let (batch_result, bytes_consumed) = x86_64::avx2::decode_u64_batch_simd(&data[1..]).unwrap();
+                assert!(matches!(batch_result, BatchResult::Owned(_)));
+                assert_eq!(batch_result.as_slice(), values.as_slice());
+                assert_eq!(bytes_consumed, data.len() - 1);
+                let (batch_result, _) = x86_64::avx2::decode_f32_batch_simd(&float_data[1..]).unwrap();
+                assert_eq!(batch_result.as_slice(), floats.as_slice());
+                assert!(x86_64::avx2::decode_u32_batch_simd(&data[1..4]).is_err());
+            }
+            if std::is_x86_feature_detected!("avx512f") {
+                let (batch_result, _) = x86_64::avx512::decode_u64_batch_simd(&data[1..]).unwrap();
+                assert_eq!(batch_result.as_slice(), values.as_slice());
+                let (batch_result, _) = x86_64::avx512::decode_f32_batch_simd(&float_data[1..]).unwrap();
+                assert_eq!(batch_result.as_slice(), floats.as_slice());
+                let aligned: &[u8] = unsafe {
+                    slice::from_raw_parts(values.as_ptr() as *const u8, values.len() * mem::size_of::<u64>())
+                };
+                let (batch_result, _) = x86_64::avx512::decode_f64_batch_simd(aligned).unwrap();
+                assert!(matches!(batch_result, BatchResult::Borrowed(_)));
+            }
+        }
+
+        #[test]
+        fn test_avx_kernels_match_scalar_decoding() {
+            // Every length up to a few registers past the widest, at every misalignment
+            let bytes: Vec<u8> = (0..8 + 3 * 64 + 8).map(|i| (i * 37 + 11) as u8).collect();
+            for start in 0..8 {
+                for length in (0..=3 * 64 + 8).step_by(8) {
+                    let data = &bytes[start..start + length];
+                    let scalar_u32: Vec<u32> = data.chunks_exact(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+                    let scalar_u64: Vec<u64> = data.chunks_exact(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect();
+
+                    if std::is_x86_feature_detected!("avx2") {
+                        assert_eq!(x86_64::avx2::decode_u32_batch_simd(data).unwrap().0.as_slice(), scalar_u32.as_slice());
+                        assert_eq!(x86_64::avx2::decode_u64_batch_simd(data).unwrap().0.as_slice(), scalar_u64.as_slice());
+                        let (floats, _) = x86_64::avx2::decode_f64_batch_simd(data).unwrap();
+                        assert_eq!(floats.iter().map(|f| f.to_bits()).collect::<Vec<_>>(), scalar_u64);
+                    }
+                    if std::is_x86_feature_detected!("avx512f") {
+                        assert_eq!(x86_64::avx512::decode_u32_batch_simd(data).unwrap().0.as_slice(), scalar_u32.as_slice());
+                        assert_eq!(x86_64::avx512::decode_u64_batch_simd(data).unwrap().0.as_slice(), scalar_u64.as_slice());
+                        let (floats, _) = x86_64::avx512::decode_f64_batch_simd(data).unwrap();
+                        assert_eq!(floats.iter().map(|f| f.to_bits()).collect::<Vec<_>>(), scalar_u64);
+                    }
+                }
+            }
+        }
+    }
+
+    #[cfg(all(target_arch = "aarch64", feature = "simd"))]
+    mod aarch64_tests {
+        use super::super::super::{aarch64, BatchResult};
+
+        #[test]
+        fn test_decode_unaligned_batches_neon() {
+            // 37 elements leave a tail after the last full block
+            let values: Vec<u64> = (0..37).map(|i| i * 0x0101_0101_0101).collect();
+            let floats: Vec<f32> = (0..37).map(|i| i as f32 * 0.25).collect();
+            let mut data = vec![0u8];
+            data.extend(values.iter().flat_map(|value| value.to_le_bytes()));
+            let mut float_data = vec![0u8];
+            float_data.extend(floats.iter().flat_map(|value| value.to_le_bytes()));
+
+            let (batch_result, bytes_consumed) = aarch64::neon::decode_u64_batch_simd(&data[1..]).unwrap();
+            assert!(matches!(batch_result, BatchResult::Owned(_)));
+            assert_eq!(batch_result.as_slice(), values.as_slice());
+            assert_eq!(bytes_consumed, data.len() - 1);
+            let (batch_result, _) = aarch64::neon::decode_f32_batch_simd(&float_data[1..]).unwrap();
+            assert_eq!(batch_result.as_slice(), floats.as_slice());
+            assert!(aarch64::neon::decode_u32_batch_simd(&data[1..4]).is_err());
+        }
+
+        #[test]
+        fn test_neon_kernels_match_scalar_decoding() {
+            // Every length up to a few registers, at every misalignment
+            let bytes: Vec<u8> = (0..8 + 4 * 16 + 8).map(|i| (i * 37 + 11) as u8).collect();
+            for start in 0..8 {
+                for length in (0..=4 * 16 + 8).step_by(8) {
+                    let data = &bytes[start..start + length];
+                    let scalar_u32: Vec<u32> = data.chunks_exact(4).map(|c| u32::from_le_bytes(c.try_into().unwrap())).collect();
+                    let scalar_u64: Vec<u64> = data.chunks_exact(8).map(|c| u64::from_le_bytes(c.try_into().unwrap())).collect();
+                    assert_eq!(aarch64::neon::decode_u32_batch_simd(data).unwrap().0.as_slice(), scalar_u32.as_slice());
+                    assert_eq!(aarch64::neon::decode_u64_batch_simd(data).unwrap().0.as_slice(), scalar_u64.as_slice());
+                    let (floats, _) = aarch64::neon::decode_f64_batch_simd(data).unwrap();
+                    assert_eq!(floats.iter().map(|f| f.to_bits()).collect::<Vec<_>>(), scalar_u64);
+                }
+            }
+        }
     }
 
     #[test]
@@ -1,122 +1,63 @@
 // AVX2 optimizations for x86_64 architecture
 //
-// This module contains SIMD-optimized implementations using AVX2 instructions.
+// This module contains the batch decode kernels for the 4- and 8-byte numeric
+// types that are dispatched to on CPUs with AVX2. Aligned input is
+// reinterpreted in place, like the SSE4.1 kernels do. Unaligned input is moved
+// into an aligned buffer 32 bytes at a time with unaligned 256-bit loads and
+// stores, the last partial register being copied bytewise. x86_64 is
+// little-endian, like the wire format, so that move is the whole decode. The
+// kernels check for AVX2 at runtime and fail on CPUs without it.
 
 use crate::internal::error::{Error, Result};
-use std::mem;
-use std::slice;
-use std::arch::x86_64::*;
+use super::super::BatchResult;
+use std::arch::x86_64::{__m256i, _mm256_loadu_si256, _mm256_storeu_si256};
 
-/// Decodes a batch of u32 values using AVX2 SIMD instructions.
-/// Returns a slice of the decoded elements and the number of bytes read.
-#[cfg(target_feature = "avx2")]
-pub fn decode_u32_batch_simd(data: &[u8]) -> Result<(&[u32], usize)> {
-    let size = mem::size_of::<u32>();
-    if data.len() % size != 0 {
-        return Err(Error::CodecError(format!(
-            "Invalid data length for U32 batch decoding. Length ({}) must be a multiple of {}",
-            data.len(),
-            size
-        )));
-    }
+/// Bytes moved by one 256-bit load and store
+const REGISTER_BYTES: usize = 32;
 
-    let count = data.len() / size;
-    if count == 0 {
-        return Ok((&[], 0));
-    }
+/// Decodes a batch of u32 values on CPUs with AVX2.
+/// Returns a BatchResult containing the decoded elements and the number of bytes read.
+pub fn decode_u32_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, u32>, usize)> {
+    decode_batch(data, "U32")
+}
 
-    // Check alignment for AVX2 operations (32-byte alignment is ideal for AVX2)
-    let aligned = (data.as_ptr() as usize) % 32 == 0;
-    
-    if aligned {
-        // For aligned data, we can simply reinterpret the slice
-        // This is safe because we've already checked size and alignment
-        let decoded_slice = unsafe {
-            slice::from_raw_parts(data.as_ptr() as *const u32, count)
-        };
-        
-        Ok((decoded_slice, data.len()))
-    } else {
-        // For unaligned data, we need to use unaligned loads
-        // This is more complex and would require manual SIMD implementation
-        // For now, we'll use a scalar fallback for unaligned data
-        let mut values = Vec::with_capacity(count);
-        for i in 0..count {
-            let offset = i * size;
-            let value = u32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]);
-            values.push(value);
-        }
-        
-        // Return a reference to the vector's data
-        let slice = unsafe {
-            slice::from_raw_parts(values.as_ptr(), values.len())
-        };
-        
-        // We need to leak the vector to ensure the data remains valid
-        std::mem::forget(values);
-        
-        Ok((slice, data.len()))
-    }
+/// Decodes a batch of u64 values on CPUs with AVX2.
+pub fn decode_u64_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, u64>, usize)> {
+    decode_batch(data, "U64")
 }
 
-/// Decodes a batch of f32 values using AVX2 SIMD instructions.
-/// Returns a slice of the decoded elements and the number of bytes read.
-#[cfg(target_feature = "avx2")]
-pub fn decode_f32_batch_simd(data: &[u8]) -> Result<(&[f32], usize)> {
-    let size = mem::size_of::<f32>();
-    if data.len() % size != 0 {
-        return Err(Error::CodecError(format!(
-            "Invalid data length for F32 batch decoding. Length ({}) must be a multiple of {}",
-            data.len(),
-            size
-        )));
-    }
+/// Decodes a batch of f32 values on CPUs with AVX2.
+pub fn decode_f32_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, f32>, usize)> {
+    decode_batch(data, "F32")
+}
+
+/// Decodes a batch of f64 values on CPUs with AVX2.
+pub fn decode_f64_batch_simd(data: &[u8]) -> Result<(BatchResult<'_, f64>, usize)> {
+    decode_batch(data, "F64")
+}
 
-    let count = data.len() / size;
-    if count == 0 {
-        return Ok((&[], 0));
+/// Decodes a batch of fixed-size little-endian values on CPUs with AVX2.
+fn decode_batch<'a, T: bytemuck::Pod>(data: &'a [u8], type_name: &str) -> Result<(BatchResult<'a, T>, usize)> {
+    if !std::is_x86_feature_detected!("avx2") {
+        return Err(Error::CodecError("AVX2 batch decoding is not supported by this CPU".to_string()));
     }
+    // SAFETY: the CPU was checked to support AVX2
+    BatchResult::from_le_bytes(data, type_name, |src, dst| unsafe { copy_avx2(src, dst) })
+}
 
-    // Check alignment for AVX2 operations (32-byte alignment is ideal for AVX2)
-    let aligned = (data.as_ptr() as usize) % 32 == 0;
-    
-    if aligned {
-        // For aligned data, we can simply reinterpret the slice
-        // This is safe because we've already checked size and alignment
-        let decoded_slice = unsafe {
-            slice::from_raw_parts(data.as_ptr() as *const f32, count)
-        };
-        
-        Ok((decoded_slice, data.len()))
-    } else {
-        // For unaligned data, we need to use unaligned loads
-        // This is more complex and would require manual SIMD implementation
-        // For now, we'll use a scalar fallback for unaligned data
-        let mut values = Vec::with_capacity(count);
-        for i in 0..count {
-            let offset = i * size;
-            let value = f32::from_le_bytes([
-                data[offset],
-                data[offset + 1],
-                data[offset + 2],
-                data[offset + 3],
-            ]);
-            values.push(value);
-        }
-        
-        // Return a reference to the vector's data
-        let slice = unsafe {
-            slice::from_raw_parts(values.as_ptr(), values.len())
-        };
-        
-        // We need to leak the vector to ensure the data remains valid
-        std::mem::forget(values);
-        
-        Ok((slice, data.len()))
+/// Copies `src` into `dst` with 256-bit loads and stores.
+///
+/// # Safety
+///
+/// The CPU must support AVX2.
+#[target_feature(enable = "avx2")]
+unsafe fn copy_avx2(src: &[u8], dst: &mut [u8]) {
+    assert_eq!(src.len(), dst.len());
+    let full = src.len() - src.len() % REGISTER_BYTES;
+    for offset in (0..full).step_by(REGISTER_BYTES) {
+        // Both ranges are in bounds, and the loads and stores need no alignment
+        let register = _mm256_loadu_si256(src.as_ptr().add(offset).cast::<__m256i>());
+        _mm256_storeu_si256(dst.as_mut_ptr().add(offset).cast::<__m256i>(), register);
     }
+    dst[full..].copy_from_slice(&src[full..]);
 }
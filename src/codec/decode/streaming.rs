@@ -0,0 +1,616 @@
+// Streaming decoder for HTLV items
+//
+// `StreamingDecoder` reads HTLV items from an `io::Read` and yields events like
+// the pull decoder, owning the values it decodes. Large Bytes and String
+// values, which the encoder shards into a header item holding the total length
+// followed by one item per shard, are never buffered: their contents are passed
+// to a `LargeFieldSink` piece by piece as they are read, together with the
+// progress towards the total length. Memory use is bounded by the large field
+// threshold instead of by the size of the largest field.
+//
+// A sharded header is itself an 8-byte value of the field's type, so it is
+// recognized when the total length it holds exceeds the threshold and the next
+// item is a shard with the same tag and type whose length is the threshold. The
+// decoder must therefore use the large field threshold the data was encoded with.
+//
+// The limits of a `DecodeConfig` are applied as headers are read: an item
+// longer than `max_item_length` is rejected before its value is buffered, and
+// a large field announcing more than `max_large_field_size` before its first
+// piece reaches the sink.
+
+use crate::internal::error::{Error, Result};
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use crate::codec::encode::{LARGE_FIELD_THRESHOLD, TOTAL_LENGTH_HEADER_LEN};
+use crate::config::TonitruConfig;
+use crate::internal::cancel::{self, CancellationToken};
+use super::limits::DecodeConfig;
+use super::type_table::{self, ValueKind};
+use bytes::Bytes;
+use std::io::{self, Read, Write};
+
+// Largest piece of a large field handed to the sink at once
+const MAX_PIECE_SIZE: u64 = 64 * 1024;
+
+/// Progress of a large field being delivered to a sink.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LargeFieldProgress {
+    /// Tag of the field
+    pub tag: u64,
+    /// Bytes or String
+    pub value_type: HtlvValueType,
+    /// Total length announced by the field header
+    pub total_length: u64,
+    /// Bytes delivered so far, including the current piece
+    pub received: u64,
+}
+
+impl LargeFieldProgress {
+    /// Returns true once the whole field has been delivered.
+    pub fn is_complete(&self) -> bool {
+        self.received == self.total_length
+    }
+}
+
+/// Receives the contents of large fields as they are decoded.
+pub trait LargeFieldSink {
+    /// Receives the next piece of a large field; an error aborts decoding.
+    fn write_piece(&mut self, progress: &LargeFieldProgress, data: &[u8]) -> Result<()>;
+}
+
+impl<F: FnMut(&LargeFieldProgress, &[u8]) -> Result<()>> LargeFieldSink for F {
+    fn write_piece(&mut self, progress: &LargeFieldProgress, data: &[u8]) -> Result<()> {
+        self(progress, data)
+    }
+}
+
+/// A sink writing the contents of every large field into a writer.
+#[derive(Debug)]
+pub struct WriteSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> WriteSink<W> {
+    /// Creates a sink writing into `writer`.
+    pub fn new(writer: W) -> Self {
+        WriteSink { writer }
+    }
+
+    /// Returns the writer.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+impl<W: Write> LargeFieldSink for WriteSink<W> {
+    fn write_piece(&mut self, _progress: &LargeFieldProgress, data: &[u8]) -> Result<()> {
+        self.writer.write_all(data)?;
+        Ok(())
+    }
+}
+
+/// An event produced by the streaming decoder.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamEvent {
+    /// Start of an object with the given tag; its fields follow.
+    BeginObject(u64),
+    /// End of the innermost open object.
+    EndObject,
+    /// Start of an array with the given tag; its elements follow.
+    BeginArray(u64),
+    /// End of the innermost open array.
+    EndArray,
+    /// A basic value, decoded in memory.
+    Item(HtlvItem),
+    /// A large field whose contents have been delivered to the sink.
+    LargeField {
+        tag: u64,
+        value_type: HtlvValueType,
+        total_length: u64,
+    },
+}
+
+/// The header of an item: tag, type and length.
+#[derive(Debug, Clone, Copy)]
+struct ItemHeader {
+    tag: u64,
+    type_byte: u8,
+    value_type: HtlvValueType,
+    length: u64,
+    /// Offset of the first byte of the item
+    offset: u64,
+}
+
+/// An open object or array.
+#[derive(Debug, Clone, Copy)]
+struct Frame {
+    /// Offset at which the container's value ends
+    end: u64,
+    is_array: bool,
+}
+
+/// A decoder reading HTLV items from a reader and streaming large fields into a sink.
+///
+/// The decoder is an iterator over `StreamEvent`s; it stops after the first error.
+#[derive(Debug)]
+pub struct StreamingDecoder<R: Read, S: LargeFieldSink> {
+    reader: R,
+    sink: S,
+    threshold: u64,
+    limits: DecodeConfig,
+    /// Item headers read so far
+    items: usize,
+    offset: u64,
+    stack: Vec<Frame>,
+    /// Header read ahead while checking for a sharded field
+    pending: Option<ItemHeader>,
+    cancellation: Option<CancellationToken>,
+    failed: bool,
+}
+
+impl<R: Read, S: LargeFieldSink> StreamingDecoder<R, S> {
+    /// Creates a streaming decoder for data sharded at `LARGE_FIELD_THRESHOLD`.
+    pub fn new(reader: R, sink: S) -> Self {
+        Self::with_threshold(reader, sink, LARGE_FIELD_THRESHOLD)
+    }
+
+    /// Creates a streaming decoder using the large field threshold and the
+    /// decoder limits of the configuration.
+    pub fn with_config(reader: R, sink: S, config: &TonitruConfig) -> Self {
+        Self::with_threshold(reader, sink, config.large_field_threshold()).with_limits(config.decode_config())
+    }
+
+    /// Creates a streaming decoder for data sharded at `threshold` bytes.
+    pub fn with_threshold(reader: R, sink: S, threshold: usize) -> Self {
+        StreamingDecoder {
+            reader,
+            sink,
+            threshold: threshold.max(1) as u64,
+            limits: DecodeConfig::default(),
+            items: 0,
+            offset: 0,
+            stack: Vec::new(),
+            pending: None,
+            cancellation: None,
+            failed: false,
+        }
+    }
+
+    /// Applies the limits to the items read from now on.
+    pub fn with_limits(mut self, limits: DecodeConfig) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Fails with `Error::Cancelled` before the next event, or the next piece of
+    /// a large field, once the token is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Returns the number of bytes of complete items and headers consumed so far.
+    pub fn bytes_read(&self) -> u64 {
+        match &self.pending {
+            Some(header) => header.offset,
+            None => self.offset,
+        }
+    }
+
+    /// Returns the current nesting depth.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Returns the reader and the sink.
+    pub fn into_inner(self) -> (R, S) {
+        (self.reader, self.sink)
+    }
+
+    /// Returns the next event, `Ok(None)` at the end of the input.
+    pub fn next_event(&mut self) -> Result<Option<StreamEvent>> {
+        if self.failed {
+            return Ok(None);
+        }
+        let result = self.advance();
+        if result.is_err() {
+            self.failed = true;
+        }
+        result
+    }
+
+    fn advance(&mut self) -> Result<Option<StreamEvent>> {
+        cancel::check(&self.cancellation, "Decoding")?;
+
+        // Close the innermost container once its value is fully consumed; a
+        // header read ahead always belongs to it
+        if let (None, Some(frame)) = (&self.pending, self.stack.last().copied()) {
+            if self.offset >= frame.end {
+                self.stack.pop();
+                return Ok(Some(if frame.is_array { StreamEvent::EndArray } else { StreamEvent::EndObject }));
+            }
+        }
+
+        // Every top-level item is a message of its own for the item limit
+        let header = match self.pending.take() {
+            Some(header) => {
+                if self.stack.is_empty() {
+                    self.items = 1;
+                }
+                header
+            }
+            None => {
+                if self.stack.is_empty() {
+                    self.items = 0;
+                }
+                match self.read_header()? {
+                    Some(header) => header,
+                    None => return Ok(None),
+                }
+            }
+        };
+        let end = self.value_end(&header)?;
+
+        match header.value_type {
+            HtlvValueType::Array | HtlvValueType::Object => {
+                if self.stack.len() >= self.limits.nesting_depth() {
+                    return Err(Error::DepthExceeded { offset: self.offset as usize, limit: self.limits.nesting_depth() });
+                }
+                let is_array = header.value_type == HtlvValueType::Array;
+                self.stack.push(Frame { end, is_array });
+                Ok(Some(if is_array { StreamEvent::BeginArray(header.tag) } else { StreamEvent::BeginObject(header.tag) }))
+            }
+            HtlvValueType::Bytes | HtlvValueType::String if header.length == TOTAL_LENGTH_HEADER_LEN => {
+                let mut total = [0u8; 8];
+                self.read_exact(&mut total, "Value")?;
+                let total_length = u64::from_le_bytes(total);
+                if let Some(shard) = self.next_shard_of(&header, total_length)? {
+                    return self.stream_field(&header, total_length, shard).map(Some);
+                }
+                let value = decode_value(&header, total.to_vec())?;
+                Ok(Some(StreamEvent::Item(HtlvItem::new(header.tag, value))))
+            }
+            _ => {
+                let mut data = Vec::new();
+                (&mut self.reader).take(header.length).read_to_end(&mut data)?;
+                self.offset += data.len() as u64;
+                if (data.len() as u64) < header.length {
+                    return Err(Error::UnexpectedEof {
+                        what: "Value".to_string(),
+                        offset: (self.offset as usize).saturating_sub(data.len()),
+                        needed: header.length,
+                        got: data.len(),
+                    });
+                }
+                let value = decode_value(&header, data)?;
+                Ok(Some(StreamEvent::Item(HtlvItem::new(header.tag, value))))
+            }
+        }
+    }
+
+    /// Reads the next header if the 8-byte value just read may be the header of
+    /// a sharded field, returning it if it is the field's first shard and
+    /// keeping it for the next event otherwise.
+    fn next_shard_of(&mut self, header: &ItemHeader, total_length: u64) -> Result<Option<ItemHeader>> {
+        let container_done = matches!(self.stack.last(), Some(frame) if self.offset >= frame.end);
+        if total_length <= self.threshold || container_done {
+            return Ok(None);
+        }
+        let next = match self.read_header()? {
+            Some(next) => next,
+            None => return Ok(None),
+        };
+        let is_shard = next.tag == header.tag
+            && next.type_byte == header.type_byte
+            && next.length == total_length.min(self.threshold);
+        if is_shard {
+            self.limits.check_large_field_size(total_length, header.tag)?;
+            Ok(Some(next))
+        } else {
+            self.pending = Some(next);
+            Ok(None)
+        }
+    }
+
+    /// Passes the shards of a large field to the sink, starting with `shard`.
+    fn stream_field(&mut self, header: &ItemHeader, total_length: u64, mut shard: ItemHeader) -> Result<StreamEvent> {
+        let mut progress = LargeFieldProgress {
+            tag: header.tag,
+            value_type: header.value_type,
+            total_length,
+            received: 0,
+        };
+        let mut piece = vec![0u8; self.threshold.min(MAX_PIECE_SIZE) as usize];
+        loop {
+            self.value_end(&shard)?;
+            let mut left = shard.length;
+            while left > 0 {
+                cancel::check(&self.cancellation, "Decoding")?;
+                let size = left.min(piece.len() as u64) as usize;
+                if let Err(e) = self.read_exact(&mut piece[..size], "large field shard") {
+                    return Err(match e {
+                        Error::UnexpectedEof { .. } => self.large_field_truncated(&progress),
+                        other => other,
+                    });
+                }
+                left -= size as u64;
+                progress.received += size as u64;
+                self.sink.write_piece(&progress, &piece[..size])?;
+            }
+            if progress.is_complete() {
+                break;
+            }
+
+            shard = self.read_header()?.ok_or_else(|| self.large_field_truncated(&progress))?;
+            if shard.tag != header.tag || shard.type_byte != header.type_byte {
+                return Err(Error::CodecError(format!(
+                    "Expected a shard of the large field with tag {} at offset {}, found tag {} with type byte {}",
+                    header.tag, shard.offset, shard.tag, shard.type_byte
+                )));
+            }
+            if shard.length == 0 || shard.length > total_length - progress.received {
+                return Err(Error::CodecError(format!(
+                    "Invalid shard length {} at offset {}: {} bytes of the large field remain",
+                    shard.length, shard.offset, total_length - progress.received
+                )));
+            }
+        }
+
+        Ok(StreamEvent::LargeField { tag: header.tag, value_type: header.value_type, total_length })
+    }
+
+    /// Returns the error for data ending before all shards of a large field
+    /// were read.
+    fn large_field_truncated(&self, progress: &LargeFieldProgress) -> Error {
+        Error::UnexpectedEof {
+            what: format!("Large field with tag {}", progress.tag),
+            offset: self.offset as usize,
+            needed: progress.total_length,
+            got: progress.received as usize,
+        }
+    }
+
+    /// Returns the offset at which the value of an item ends, checking that it
+    /// lies within the enclosing container.
+    fn value_end(&self, header: &ItemHeader) -> Result<u64> {
+        let end = self.offset.checked_add(header.length).ok_or_else(|| Error::LengthOverflow {
+            what: "Value".to_string(),
+            offset: self.offset as usize,
+            length: header.length,
+        })?;
+        match self.stack.last() {
+            Some(frame) if end > frame.end => {
+                Err(Error::ExceedsContainer { what: "Value".to_string(), offset: header.offset as usize })
+            }
+            _ => Ok(end),
+        }
+    }
+
+    /// Reads an item header; `Ok(None)` if the input ends cleanly between
+    /// top-level items.
+    fn read_header(&mut self) -> Result<Option<ItemHeader>> {
+        let offset = self.offset;
+        let mut first = [0u8; 1];
+        loop {
+            match self.reader.read(&mut first) {
+                Ok(0) if self.stack.is_empty() => return Ok(None),
+                Ok(0) => {
+                    let end = self.stack.last().map_or(offset, |frame| frame.end);
+                    return Err(Error::UnexpectedEof {
+                        what: "Rest of the enclosing container".to_string(),
+                        offset: offset as usize,
+                        needed: end - offset,
+                        got: 0,
+                    });
+                }
+                Ok(_) => break,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.offset += 1;
+
+        let tag = self.read_varint_from(first[0], "item Tag")?;
+        let type_byte = self.read_byte("Type byte")?;
+        let value_type = type_table::handler_for_byte(type_byte)
+            .map(|handler| handler.value_type)
+            .ok_or(Error::UnknownType { type_byte, offset: self.offset as usize - 1 })?;
+        let first_length_byte = self.read_byte("Length")?;
+        let length = self.read_varint_from(first_length_byte, "Length")?;
+
+        self.items += 1;
+        self.limits.check_item_count(self.items)?;
+        self.limits.check_item_length(length, offset)?;
+
+        Ok(Some(ItemHeader { tag, type_byte, value_type, length, offset }))
+    }
+
+    /// Reads the rest of a varint whose first byte has been read.
+    fn read_varint_from(&mut self, first: u8, what: &str) -> Result<u64> {
+        let mut value = (first & 0x7F) as u64;
+        let mut byte = first;
+        let mut shift = 7;
+        while byte & 0x80 != 0 {
+            byte = self.read_byte(what)?;
+            // The tenth byte only holds the last bit of a u64 and ends the varint
+            if shift == 63 && byte > 1 {
+                return Err(Error::InvalidVarint { what: what.to_string(), offset: (self.offset as usize).saturating_sub(10) });
+            }
+            value |= ((byte & 0x7F) as u64) << shift;
+            shift += 7;
+        }
+        Ok(value)
+    }
+
+    fn read_byte(&mut self, what: &str) -> Result<u8> {
+        let mut byte = [0u8; 1];
+        self.read_exact(&mut byte, what)?;
+        Ok(byte[0])
+    }
+
+    fn read_exact(&mut self, buf: &mut [u8], what: &str) -> Result<()> {
+        let mut filled = 0;
+        while filled < buf.len() {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) => {
+                    return Err(Error::UnexpectedEof {
+                        what: what.to_string(),
+                        offset: self.offset as usize,
+                        needed: buf.len() as u64,
+                        got: filled,
+                    });
+                }
+                Ok(read) => filled += read,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        self.offset += buf.len() as u64;
+        Ok(())
+    }
+}
+
+impl<R: Read, S: LargeFieldSink> Iterator for StreamingDecoder<R, S> {
+    type Item = Result<StreamEvent>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_event().transpose()
+    }
+}
+
+/// Decodes the value of a basic or extension item read into memory.
+fn decode_value(header: &ItemHeader, data: Vec<u8>) -> Result<HtlvValue> {
+    let handler = type_table::handler(header.value_type);
+    if handler.kind == ValueKind::Extension {
+        return Ok(HtlvValue::Extension(header.type_byte, Bytes::from(data)));
+    }
+    (handler.validate)(header.value_type, header.length)?;
+    let decode = match handler.kind {
+        // Values holding several elements are batches
+        ValueKind::Batch if handler.element_size != Some(data.len()) => handler.batch_decode,
+        _ => handler.decode,
+    };
+    let decode = decode.ok_or_else(|| Error::CodecError(format!("Cannot decode {:?} value", header.value_type)))?;
+    decode(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::{encode_item, streaming::StreamingEncoder};
+
+    #[test]
+    fn test_large_fields_are_streamed_to_the_sink() {
+        let blob: Vec<u8> = (0..LARGE_FIELD_THRESHOLD * 3 + 17).map(|i| (i % 251) as u8).collect();
+        let text = "y".repeat(LARGE_FIELD_THRESHOLD + 1);
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U32(70000)),
+            HtlvItem::new(3, HtlvValue::Bytes(Bytes::from(blob.clone()))),
+            HtlvItem::new(4, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::String(Bytes::from(text.clone())))])),
+        ]));
+        let encoded = encode_item(&item).unwrap();
+
+        let mut received: Vec<(u64, Vec<u8>)> = Vec::new();
+        let mut last_progress = None;
+        let sink = |progress: &LargeFieldProgress, data: &[u8]| {
+            assert!(data.len() as u64 <= LARGE_FIELD_THRESHOLD as u64);
+            match received.last_mut() {
+                Some((tag, contents)) if *tag == progress.tag => contents.extend_from_slice(data),
+                _ => received.push((progress.tag, data.to_vec())),
+            }
+            assert_eq!(received.last().unwrap().1.len() as u64, progress.received);
+            last_progress = Some(*progress);
+            Ok(())
+        };
+        let mut decoder = StreamingDecoder::new(encoded.as_slice(), sink);
+        let events = decoder.by_ref().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(decoder.bytes_read(), encoded.len() as u64);
+        drop(decoder);
+
+        assert_eq!(events, vec![
+            StreamEvent::BeginObject(1),
+            StreamEvent::Item(HtlvItem::new(2, HtlvValue::U32(70000))),
+            StreamEvent::LargeField { tag: 3, value_type: HtlvValueType::Bytes, total_length: blob.len() as u64 },
+            StreamEvent::BeginArray(4),
+            StreamEvent::LargeField { tag: 0, value_type: HtlvValueType::String, total_length: text.len() as u64 },
+            StreamEvent::EndArray,
+            StreamEvent::EndObject,
+        ]);
+        assert_eq!(received, vec![(3, blob), (0, text.into_bytes())]);
+        assert!(last_progress.unwrap().is_complete());
+    }
+
+    #[test]
+    fn test_write_sink_and_eight_byte_values() {
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+        let mut encoder = StreamingEncoder::new(Vec::new());
+        encoder.begin_bytes(7, data.len() as u64).unwrap();
+        encoder.write_all(&data).unwrap();
+        encoder.end_field().unwrap();
+        // An 8-byte value that looks like a total length but has no shards
+        let eight_bytes = HtlvItem::new(7, HtlvValue::Bytes(Bytes::from(vec![0xFF; 8])));
+        let null = HtlvItem::new(8, HtlvValue::Null);
+        encoder.write_item(&HtlvItem::new(1, HtlvValue::Object(vec![eight_bytes.clone(), null.clone()]))).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        let mut decoder = StreamingDecoder::new(encoded.as_slice(), WriteSink::new(Vec::new()));
+        let events = decoder.by_ref().collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(events, vec![
+            StreamEvent::LargeField { tag: 7, value_type: HtlvValueType::Bytes, total_length: 5000 },
+            StreamEvent::BeginObject(1),
+            StreamEvent::Item(eight_bytes),
+            StreamEvent::Item(null),
+            StreamEvent::EndObject,
+        ]);
+        assert_eq!(decoder.into_inner().1.into_inner(), data);
+    }
+
+    #[test]
+    fn test_truncated_large_field_fails() {
+        let item = HtlvItem::new(5, HtlvValue::Bytes(Bytes::from(vec![1u8; LARGE_FIELD_THRESHOLD * 2])));
+        let encoded = encode_item(&item).unwrap();
+        let truncated = &encoded[..encoded.len() - 10];
+
+        let mut decoder = StreamingDecoder::new(truncated, |_: &LargeFieldProgress, _: &[u8]| Ok(()));
+        let err = decoder.next_event().unwrap_err();
+        assert!(
+            matches!(err, Error::UnexpectedEof { needed, got, .. }
+                if needed == (LARGE_FIELD_THRESHOLD * 2) as u64 && got == LARGE_FIELD_THRESHOLD),
+            "{:?}", err
+        );
+        assert!(decoder.next_event().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_varint_overflow_is_rejected() {
+        let mut crafted = vec![0x80; 9];
+        crafted.extend_from_slice(&[0x01, HtlvValueType::Null as u8, 0x00]);
+        let mut decoder = StreamingDecoder::new(crafted.as_slice(), WriteSink::new(Vec::new()));
+        assert_eq!(decoder.next_event().unwrap(), Some(StreamEvent::Item(HtlvItem::new(1 << 63, HtlvValue::Null))));
+
+        // Bits past the 64th are rejected, not dropped
+        crafted[9] = 0x02;
+        let mut decoder = StreamingDecoder::new(crafted.as_slice(), WriteSink::new(Vec::new()));
+        assert!(matches!(decoder.next_event(), Err(Error::InvalidVarint { offset: 0, .. })));
+    }
+
+    #[test]
+    fn test_limits_are_checked_before_reading_values() {
+        // A header announcing a huge value is rejected before any of it is buffered
+        let mut crafted = vec![0x01, HtlvValueType::Bytes as u8];
+        crafted.extend_from_slice(&crate::codec::varint::encode_varint(1 << 40));
+        let limits = DecodeConfig { max_item_length: 1 << 20, ..DecodeConfig::default() };
+        let mut decoder = StreamingDecoder::new(crafted.as_slice(), WriteSink::new(Vec::new())).with_limits(limits);
+        assert!(decoder.next_event().is_err());
+
+        // Large fields are bounded by their own limit, not by the item length
+        let item = HtlvItem::new(5, HtlvValue::Bytes(Bytes::from(vec![1u8; LARGE_FIELD_THRESHOLD * 2])));
+        let encoded = encode_item(&item).unwrap();
+        let limits = DecodeConfig { max_item_length: LARGE_FIELD_THRESHOLD, ..DecodeConfig::default() };
+        let decoder = StreamingDecoder::new(encoded.as_slice(), WriteSink::new(Vec::new())).with_limits(limits);
+        assert_eq!(decoder.count(), 1);
+        let limits = DecodeConfig { max_large_field_size: LARGE_FIELD_THRESHOLD, ..limits };
+        let mut decoder = StreamingDecoder::new(encoded.as_slice(), WriteSink::new(Vec::new())).with_limits(limits);
+        assert!(decoder.next_event().is_err());
+        assert!(decoder.into_inner().1.into_inner().is_empty());
+    }
+}
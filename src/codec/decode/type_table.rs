@@ -0,0 +1,303 @@
+// Per-type dispatch table for the decoder
+//
+// Everything the decoder needs to know about a value type lives in one
+// `TypeHandler` entry: how its values are decoded singly and in batches, how
+// their length is validated, and how many items a value of a given length
+// decodes into. The table is built once, indexed by the type byte, so the
+// state machine and the basic and batch decoders look a type up instead of
+// repeating match chains, and a new value type is added in one place. All type
+// bytes in `EXTENSION_TYPE_RANGE` share one handler, as their payloads are kept
+// opaque.
+
+use crate::codec::decode::pipeline_processor::batch_processor::process_batch_generic;
+use crate::codec::decode::pipeline_processor::PipelineProcessor;
+use crate::codec::external::{decode_payload, EXTERNAL_REF_HEADER_LEN};
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType, EXTENSION_TYPE_RANGE};
+use crate::internal::error::{Error, Result};
+
+// Smallest encoded item: one byte each for tag, type and length (a Null value)
+const MIN_ITEM_SIZE: u64 = 3;
+
+/// How the decoder treats values of a type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueKind {
+    /// Decoded as a single value
+    Basic,
+    /// Decoded as a batch of fixed-size elements
+    Batch,
+    /// Array or Object holding nested items
+    Complex,
+    /// Application-defined type whose payload is kept as raw bytes
+    Extension,
+}
+
+/// Decodes the raw bytes of a value.
+pub type DecodeFn = fn(&[u8]) -> Result<HtlvValue>;
+
+/// Checks that a value length is valid for the type.
+pub type ValidateFn = fn(HtlvValueType, u64) -> Result<()>;
+
+/// Returns how many items a value of the given length decodes into, at most.
+pub type SizeHintFn = fn(u64) -> usize;
+
+/// Decoding functions and properties of one value type.
+#[derive(Debug, Clone, Copy)]
+pub struct TypeHandler {
+    /// The type this entry describes
+    pub value_type: HtlvValueType,
+    /// How values of the type are decoded
+    pub kind: ValueKind,
+    /// Size of one element for fixed-size types
+    pub element_size: Option<usize>,
+    /// Alignment needed to decode batch elements in place
+    pub alignment: usize,
+    /// Decodes a single value; None for complex types
+    pub decode: Option<DecodeFn>,
+    /// Decodes a batch of elements into an Array; None for variable-size types
+    pub batch_decode: Option<DecodeFn>,
+    /// Validates a value length
+    pub validate: ValidateFn,
+    /// Bounds the number of items a value decodes into
+    pub size_hint: SizeHintFn,
+}
+
+/// Returns the handler of a value type.
+pub fn handler(value_type: HtlvValueType) -> &'static TypeHandler {
+    match value_type {
+        HtlvValueType::Extension => &EXTENSION_HANDLER,
+        core => &TYPE_TABLE[core as usize],
+    }
+}
+
+/// Returns the handler of the type with the given type byte, if the type is known.
+pub fn handler_for_byte(byte: u8) -> Option<&'static TypeHandler> {
+    if EXTENSION_TYPE_RANGE.contains(&byte) {
+        return Some(&EXTENSION_HANDLER);
+    }
+    TYPE_TABLE.get(byte as usize)
+}
+
+const fn basic(value_type: HtlvValueType, size: Option<usize>, decode: DecodeFn, validate: ValidateFn) -> TypeHandler {
+    TypeHandler {
+        value_type,
+        kind: ValueKind::Basic,
+        element_size: size,
+        alignment: 1,
+        decode: Some(decode),
+        batch_decode: None,
+        validate,
+        size_hint: single,
+    }
+}
+
+const fn batch(
+    value_type: HtlvValueType,
+    size: usize,
+    alignment: usize,
+    decode: DecodeFn,
+    batch_decode: DecodeFn,
+    validate: ValidateFn,
+    size_hint: SizeHintFn,
+) -> TypeHandler {
+    TypeHandler {
+        value_type,
+        kind: ValueKind::Batch,
+        element_size: Some(size),
+        alignment,
+        decode: Some(decode),
+        batch_decode: Some(batch_decode),
+        validate,
+        size_hint,
+    }
+}
+
+const fn complex(value_type: HtlvValueType) -> TypeHandler {
+    TypeHandler {
+        value_type,
+        kind: ValueKind::Complex,
+        element_size: None,
+        alignment: 1,
+        decode: None,
+        batch_decode: None,
+        validate: any_length,
+        size_hint: nested_items,
+    }
+}
+
+/// Handler shared by all extension type bytes
+static EXTENSION_HANDLER: TypeHandler = TypeHandler {
+    value_type: HtlvValueType::Extension,
+    kind: ValueKind::Extension,
+    element_size: None,
+    alignment: 1,
+    decode: None,
+    batch_decode: None,
+    validate: any_length,
+    size_hint: single,
+};
+
+/// Handlers indexed by type byte
+static TYPE_TABLE: [TypeHandler; 17] = {
+    use std::mem::align_of;
+    use HtlvValueType as T;
+    [
+        basic(T::Null, Some(0), decode_null, exact_length::<0>),
+        basic(T::Bool, Some(1), decode_bool, exact_length::<1>),
+        TypeHandler { batch_decode: Some(decode_batch::<u8>), ..basic(T::U8, Some(1), decode_u8, exact_length::<1>) },
+        batch(T::U16, 2, align_of::<u16>(), decode_u16, decode_batch::<u16>, multiple_length::<2>, elements::<2>),
+        batch(T::U32, 4, align_of::<u32>(), decode_u32, decode_batch::<u32>, multiple_length::<4>, elements::<4>),
+        batch(T::U64, 8, align_of::<u64>(), decode_u64, decode_batch::<u64>, multiple_length::<8>, elements::<8>),
+        TypeHandler { batch_decode: Some(decode_batch::<i8>), ..basic(T::I8, Some(1), decode_i8, exact_length::<1>) },
+        batch(T::I16, 2, align_of::<i16>(), decode_i16, decode_batch::<i16>, multiple_length::<2>, elements::<2>),
+        batch(T::I32, 4, align_of::<i32>(), decode_i32, decode_batch::<i32>, multiple_length::<4>, elements::<4>),
+        batch(T::I64, 8, align_of::<i64>(), decode_i64, decode_batch::<i64>, multiple_length::<8>, elements::<8>),
+        batch(T::F32, 4, align_of::<f32>(), decode_f32, decode_batch::<f32>, multiple_length::<4>, elements::<4>),
+        batch(T::F64, 8, align_of::<f64>(), decode_f64, decode_batch::<f64>, multiple_length::<8>, elements::<8>),
+        basic(T::Bytes, None, decode_bytes, any_length),
+        basic(T::String, None, decode_string, any_length),
+        complex(T::Array),
+        complex(T::Object),
+        basic(T::ExternalRef, None, decode_payload, min_length::<{ EXTERNAL_REF_HEADER_LEN as u64 }>),
+    ]
+};
+
+fn exact_length<const N: u64>(value_type: HtlvValueType, length: u64) -> Result<()> {
+    if length != N {
+        return Err(Error::CodecError(format!("Invalid length for {:?} value: {}", value_type, length)));
+    }
+    Ok(())
+}
+
+fn multiple_length<const N: u64>(_value_type: HtlvValueType, length: u64) -> Result<()> {
+    if !length.is_multiple_of(N) {
+        return Err(Error::CodecError(format!(
+            "Invalid data length for batch decoding. Length ({}) must be a multiple of {}",
+            length, N
+        )));
+    }
+    Ok(())
+}
+
+fn min_length<const N: u64>(value_type: HtlvValueType, length: u64) -> Result<()> {
+    if length < N {
+        return Err(Error::CodecError(format!("Invalid length for {:?} value: {}", value_type, length)));
+    }
+    Ok(())
+}
+
+fn any_length(_value_type: HtlvValueType, _length: u64) -> Result<()> {
+    Ok(())
+}
+
+fn single(_length: u64) -> usize {
+    1
+}
+
+fn elements<const N: u64>(length: u64) -> usize {
+    (length / N) as usize
+}
+
+fn nested_items(length: u64) -> usize {
+    (length / MIN_ITEM_SIZE) as usize
+}
+
+fn decode_null(_data: &[u8]) -> Result<HtlvValue> {
+    Ok(HtlvValue::Null)
+}
+
+fn decode_bool(data: &[u8]) -> Result<HtlvValue> {
+    match data.first() {
+        Some(byte) => Ok(HtlvValue::Bool(*byte != 0)),
+        None => Err(Error::CodecError("Incomplete data for Bool value".to_string())),
+    }
+}
+
+fn decode_u8(data: &[u8]) -> Result<HtlvValue> {
+    match data.first() {
+        Some(byte) => Ok(HtlvValue::U8(*byte)),
+        None => Err(Error::CodecError("Incomplete data for U8 value".to_string())),
+    }
+}
+
+fn decode_i8(data: &[u8]) -> Result<HtlvValue> {
+    match data.first() {
+        Some(byte) => Ok(HtlvValue::I8(*byte as i8)),
+        None => Err(Error::CodecError("Incomplete data for I8 value".to_string())),
+    }
+}
+
+macro_rules! fixed_size_decoders {
+    ($($name:ident: $ty:ty => $variant:ident),*) => {
+        $(
+            fn $name(data: &[u8]) -> Result<HtlvValue> {
+                let bytes = data.try_into().map_err(|_| {
+                    Error::CodecError(format!("Invalid length for {} value: {}", stringify!($variant), data.len()))
+                })?;
+                Ok(HtlvValue::$variant(<$ty>::from_le_bytes(bytes)))
+            }
+        )*
+    };
+}
+
+fixed_size_decoders!(
+    decode_u16: u16 => U16, decode_u32: u32 => U32, decode_u64: u64 => U64,
+    decode_i16: i16 => I16, decode_i32: i32 => I32, decode_i64: i64 => I64,
+    decode_f32: f32 => F32, decode_f64: f64 => F64
+);
+
+fn decode_bytes(data: &[u8]) -> Result<HtlvValue> {
+    // Bytes type can have any length
+    Ok(HtlvValue::Bytes(bytes::Bytes::copy_from_slice(data)))
+}
+
+fn decode_string(data: &[u8]) -> Result<HtlvValue> {
+    let s = String::from_utf8(data.to_vec())
+        .map_err(|e| Error::CodecError(format!("Invalid UTF-8 sequence for String value: {}", e)))?;
+    Ok(HtlvValue::String(bytes::Bytes::from(s)))
+}
+
+/// Decodes a batch through the four-stage pipeline into an Array of tag-0 items.
+fn decode_batch<T: PipelineProcessor>(data: &[u8]) -> Result<HtlvValue> {
+    let (values, _) = process_batch_generic::<T>(data)?;
+    Ok(HtlvValue::Array(values.into_iter().map(|value| HtlvItem::new(0, value)).collect()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_table_is_indexed_by_type_byte() {
+        for byte in 0..=u8::MAX {
+            match (HtlvValueType::from_byte(byte), handler_for_byte(byte)) {
+                (Some(value_type), Some(handler)) => {
+                    assert_eq!(handler.value_type, value_type);
+                    let opaque = matches!(handler.kind, ValueKind::Complex | ValueKind::Extension);
+                    assert_eq!(handler.decode.is_none(), opaque);
+                    if handler.kind == ValueKind::Batch {
+                        assert!(handler.batch_decode.is_some());
+                    }
+                }
+                (None, None) => {}
+                other => panic!("Type byte {} maps to {:?}", byte, other.0),
+            }
+        }
+    }
+
+    #[test]
+    fn test_handlers_decode_validate_and_hint() {
+        let u32_handler = handler(HtlvValueType::U32);
+        assert_eq!(u32_handler.kind, ValueKind::Batch);
+        assert_eq!((u32_handler.decode.unwrap())(&7u32.to_le_bytes()).unwrap(), HtlvValue::U32(7));
+        assert!((u32_handler.validate)(HtlvValueType::U32, 6).is_err());
+        assert_eq!((u32_handler.size_hint)(12), 3);
+
+        let batch = (u32_handler.batch_decode.unwrap())(&[1, 0, 0, 0, 2, 0, 0, 0]).unwrap();
+        assert_eq!(batch, HtlvValue::Array(vec![HtlvItem::new(0, HtlvValue::U32(1)), HtlvItem::new(0, HtlvValue::U32(2))]));
+
+        let null_handler = handler(HtlvValueType::Null);
+        let err = (null_handler.validate)(HtlvValueType::Null, 1).unwrap_err();
+        assert_eq!(err.to_string(), "Codec Error: Invalid length for Null value: 1");
+        assert_eq!((handler(HtlvValueType::Object).size_hint)(9), 3);
+    }
+}
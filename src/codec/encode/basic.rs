@@ -1,4 +1,5 @@
 use crate::internal::error::Result;
+use crate::codec::external;
 use crate::codec::varint;
 use crate::codec::types::{HtlvValue, HtlvValueType}; // Import HtlvItem for tests
 
@@ -41,6 +42,12 @@ pub fn encode_basic_value(value: &HtlvValue) -> Result<(u8, Vec<u8>)> {
         HtlvValue::F64(v) => Ok((HtlvValueType::F64 as u8, v.to_le_bytes().to_vec())),
         HtlvValue::Bytes(v) => Ok((HtlvValueType::Bytes as u8, v.to_vec())),
         HtlvValue::String(v) => Ok((HtlvValueType::String as u8, v.to_vec())),
+        HtlvValue::Extension(type_byte, v) => Ok((*type_byte, v.to_vec())),
+        HtlvValue::ExternalRef { locator, length, hash } => {
+            let mut payload = Vec::with_capacity(external::payload_len(locator));
+            external::encode_payload(locator, *length, hash, &mut payload);
+            Ok((HtlvValueType::ExternalRef as u8, payload))
+        }
         // Array and Object will be handled in complex.rs
         HtlvValue::Array(_) | HtlvValue::Object(_) => {
             Err(crate::internal::error::Error::CodecError("Attempted to encode complex type with basic encoder".to_string()))
@@ -48,6 +55,75 @@ pub fn encode_basic_value(value: &HtlvValue) -> Result<(u8, Vec<u8>)> {
     }
 }
 
+/// Appends the Type, Length and Value of a basic HtlvValue to `out` without
+/// intermediate allocations.
+pub(crate) fn encode_basic_value_into(value: &HtlvValue, out: &mut Vec<u8>) -> Result<()> {
+    let mut scratch = [0u8; 8];
+    let mut payload = Vec::new();
+    let bytes: &[u8] = match value {
+        HtlvValue::Null => &[],
+        HtlvValue::Bool(v) => {
+            scratch[0] = *v as u8;
+            &scratch[..1]
+        }
+        HtlvValue::U8(v) => {
+            scratch[0] = *v;
+            &scratch[..1]
+        }
+        HtlvValue::I8(v) => {
+            scratch[0] = *v as u8;
+            &scratch[..1]
+        }
+        HtlvValue::U16(v) => {
+            scratch[..2].copy_from_slice(&v.to_le_bytes());
+            &scratch[..2]
+        }
+        HtlvValue::I16(v) => {
+            scratch[..2].copy_from_slice(&v.to_le_bytes());
+            &scratch[..2]
+        }
+        HtlvValue::U32(v) => {
+            scratch[..4].copy_from_slice(&v.to_le_bytes());
+            &scratch[..4]
+        }
+        HtlvValue::I32(v) => {
+            scratch[..4].copy_from_slice(&v.to_le_bytes());
+            &scratch[..4]
+        }
+        HtlvValue::F32(v) => {
+            scratch[..4].copy_from_slice(&v.to_le_bytes());
+            &scratch[..4]
+        }
+        HtlvValue::U64(v) => {
+            scratch.copy_from_slice(&v.to_le_bytes());
+            &scratch[..]
+        }
+        HtlvValue::I64(v) => {
+            scratch.copy_from_slice(&v.to_le_bytes());
+            &scratch[..]
+        }
+        HtlvValue::F64(v) => {
+            scratch.copy_from_slice(&v.to_le_bytes());
+            &scratch[..]
+        }
+        HtlvValue::Bytes(v) => v.as_ref(),
+        HtlvValue::String(v) => v.as_ref(),
+        HtlvValue::Extension(_, v) => v.as_ref(),
+        HtlvValue::ExternalRef { locator, length, hash } => {
+            external::encode_payload(locator, *length, hash, &mut payload);
+            &payload
+        }
+        // Array and Object will be handled in complex.rs
+        HtlvValue::Array(_) | HtlvValue::Object(_) => {
+            return Err(crate::internal::error::Error::CodecError("Attempted to encode complex type with basic encoder".to_string()));
+        }
+    };
+    out.push(value.type_byte());
+    varint::encode_varint_into(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -150,4 +226,25 @@ mod tests {
         assert_eq!(type_byte_string, HtlvValueType::String as u8);
         assert_eq!(encoded_string, "你好".as_bytes().to_vec());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_encode_basic_value_into_matches_encode_basic_value() {
+        let values = [
+            HtlvValue::Null,
+            HtlvValue::Bool(true),
+            HtlvValue::I16(-2),
+            HtlvValue::F32(1.5),
+            HtlvValue::U64(u64::MAX),
+            HtlvValue::String(Bytes::from_static(b"abc")),
+        ];
+        for value in &values {
+            let (type_byte, encoded) = encode_basic_value(value).unwrap();
+            let mut out = vec![0xAA];
+            encode_basic_value_into(value, &mut out).unwrap();
+            assert_eq!(out[1], type_byte);
+            assert_eq!(out[2] as usize, encoded.len());
+            assert_eq!(&out[3..], &encoded[..]);
+        }
+        assert!(encode_basic_value_into(&HtlvValue::Array(vec![]), &mut Vec::new()).is_err());
+    }
+}
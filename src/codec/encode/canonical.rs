@@ -0,0 +1,108 @@
+// Canonical encoding for deterministic output
+//
+// `encode_item` writes the fields of an object in the order they were built
+// and shards large values at a configurable threshold, so two equal documents
+// can encode to different bytes. A `CanonicalEncoder` removes those degrees of
+// freedom, so the encoding can be hashed or signed:
+//
+// - the fields of every object are sorted by tag, and objects with duplicate
+//   tags are rejected, since their order could not be fixed;
+// - Bytes and String values are never sharded, whatever their size;
+// - every NaN is written as the same quiet NaN bit pattern;
+// - varints are written in their minimal form, as by every encoder.
+//
+// Array elements keep their order, which is part of the value. The output is
+// regular HTLV that any decoder reads.
+
+use crate::codec::encode::{encode_item_into, encoded_len};
+use crate::codec::types::{DedupPolicy, HtlvItem};
+use crate::internal::error::Result;
+
+/// Encodes items into a deterministic byte representation.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CanonicalEncoder;
+
+impl CanonicalEncoder {
+    /// Creates a canonical encoder.
+    pub fn new() -> Self {
+        CanonicalEncoder
+    }
+
+    /// Encodes an item canonically. Equal items always encode to the same
+    /// bytes, whatever the order of their object fields.
+    pub fn encode(&self, item: &HtlvItem) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        self.encode_into(item, &mut out)?;
+        Ok(out)
+    }
+
+    /// Appends the canonical encoding of an item to `out`.
+    pub fn encode_into(&self, item: &HtlvItem, out: &mut Vec<u8>) -> Result<()> {
+        let canonical = canonicalize(item)?;
+        out.reserve(encoded_len(&canonical, u64::MAX) as usize);
+        encode_item_into(&canonical, usize::MAX, out)
+    }
+
+    /// Returns the BLAKE3 hash of the canonical encoding of an item, a content
+    /// hash that does not depend on how the item was built.
+    pub fn content_hash(&self, item: &HtlvItem) -> Result<[u8; 32]> {
+        Ok(*blake3::hash(&self.encode(item)?).as_bytes())
+    }
+}
+
+/// Returns a copy of the item with sorted object fields and a single NaN.
+fn canonicalize(item: &HtlvItem) -> Result<HtlvItem> {
+    let mut canonical = item.clone();
+    canonical.dedup_by_tag(DedupPolicy::Reject)?;
+    canonical.normalize();
+    Ok(canonical)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode::decode_item;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::HtlvValue;
+    use bytes::Bytes;
+
+    fn document(fields: Vec<HtlvItem>) -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(fields))
+    }
+
+    #[test]
+    fn test_field_order_does_not_change_encoding() {
+        let name = HtlvItem::new(2, HtlvValue::String(Bytes::from("canonical")));
+        let flag = HtlvItem::new(5, HtlvValue::Bool(true));
+        let nested = HtlvItem::new(3, HtlvValue::Object(vec![flag.clone(), name.clone()]));
+        let a = document(vec![flag.clone(), nested.clone(), name.clone()]);
+        let b = document(vec![name.clone(), flag.clone(), HtlvItem::new(3, HtlvValue::Object(vec![name.clone(), flag.clone()]))]);
+
+        let encoder = CanonicalEncoder::new();
+        assert_ne!(encode_item(&a).unwrap(), encode_item(&b).unwrap());
+        assert_eq!(encoder.encode(&a).unwrap(), encoder.encode(&b).unwrap());
+        assert_eq!(encoder.content_hash(&a).unwrap(), encoder.content_hash(&b).unwrap());
+
+        let sorted = document(vec![name.clone(), HtlvItem::new(3, HtlvValue::Object(vec![name, flag.clone()])), flag]);
+        assert_eq!(decode_item(&encoder.encode(&a).unwrap()).unwrap().0, sorted);
+    }
+
+    #[test]
+    fn test_large_values_nan_and_duplicate_tags() {
+        let encoder = CanonicalEncoder::new();
+        let large = HtlvItem::new(4, HtlvValue::Bytes(Bytes::from(vec![7u8; 3000])));
+        let encoded = encoder.encode(&large).unwrap();
+        assert_eq!(encoded.len(), 1 + 1 + 2 + 3000);
+        assert_eq!(decode_item(&encoded).unwrap(), (large, encoded.len()));
+
+        let nan = |bits: u64| HtlvItem::new(1, HtlvValue::F64(f64::from_bits(bits)));
+        assert_eq!(
+            encoder.encode(&nan(0x7ff8_0000_0000_0001)).unwrap(),
+            encoder.encode(&nan(0xfff8_0000_0000_0000)).unwrap()
+        );
+
+        let flag = HtlvItem::new(2, HtlvValue::Bool(true));
+        let err = encoder.encode(&document(vec![flag.clone(), flag])).unwrap_err();
+        assert_eq!(err.to_string(), "Codec Error: Object with tag 1 holds tag 2 more than once");
+    }
+}
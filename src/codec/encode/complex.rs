@@ -1,27 +1,20 @@
 use crate::internal::error::Result;
-use crate::codec::types::{HtlvValue, HtlvValueType};
-use super::encode_item; // Import encode_item from the parent module
+use crate::codec::types::HtlvValue;
+use super::{encode_item_into, encoded_len, LARGE_FIELD_THRESHOLD};
 
 
 /// Encodes a complex HtlvValue (Array or Object) into bytes.
 /// Returns the value type byte and the encoded value bytes.
 pub fn encode_complex_value(value: &HtlvValue) -> Result<(u8, Vec<u8>)> {
     match value {
-        HtlvValue::Array(items) => {
-            let mut encoded_array_items = Vec::new();
-            for sub_item in items {
-                // Recursively call encode_item for nested items
-                encoded_array_items.extend_from_slice(&encode_item(sub_item)?);
+        HtlvValue::Array(items) | HtlvValue::Object(items) => {
+            let threshold = LARGE_FIELD_THRESHOLD as u64;
+            let mut encoded_items = Vec::with_capacity(items.iter().map(|item| encoded_len(item, threshold)).sum::<u64>() as usize);
+            for item in items {
+                // Nested items are encoded in place
+                encode_item_into(item, LARGE_FIELD_THRESHOLD, &mut encoded_items)?;
             }
-            Ok((HtlvValueType::Array as u8, encoded_array_items))
-        },
-        HtlvValue::Object(fields) => {
-            let mut encoded_object_fields = Vec::new();
-            for field_item in fields {
-                // Recursively call encode_item for nested fields
-                encoded_object_fields.extend_from_slice(&encode_item(field_item)?);
-            }
-            Ok((HtlvValueType::Object as u8, encoded_object_fields))
+            Ok((value.value_type() as u8, encoded_items))
         },
         // Basic types will be handled in basic.rs
         _ => {
@@ -0,0 +1,283 @@
+// Event-based push encoder for HTLV data
+//
+// `PushEncoder` mirrors the pull decoder: callers emit `begin_object`,
+// `field_*` and `end_object` calls and the encoded bytes are streamed into a
+// writer without building `HtlvItem` trees. HTLV prefixes every object and
+// array with its length, so the contents of open containers are buffered until
+// they are closed; top-level fields go straight to the writer.
+
+use crate::internal::error::{Error, Result};
+use crate::codec::varint;
+use crate::codec::types::HtlvValueType;
+use crate::codec::decode::decoder_state_machine::MAX_NESTING_DEPTH;
+use std::io::Write;
+
+/// An object or array that has been opened but not closed yet.
+#[derive(Debug)]
+struct OpenContainer {
+    tag: u64,
+    value_type: HtlvValueType,
+    /// Encoded contents of the container so far
+    body: Vec<u8>,
+}
+
+/// A push encoder streaming HTLV items into a writer.
+#[derive(Debug)]
+pub struct PushEncoder<W: Write> {
+    writer: W,
+    stack: Vec<OpenContainer>,
+    /// Buffers of closed containers, kept for reuse
+    spare_buffers: Vec<Vec<u8>>,
+}
+
+impl<W: Write> PushEncoder<W> {
+    /// Creates a push encoder writing into the given writer.
+    pub fn new(writer: W) -> Self {
+        PushEncoder {
+            writer,
+            stack: Vec::new(),
+            spare_buffers: Vec::new(),
+        }
+    }
+
+    /// Returns the current nesting depth.
+    pub fn depth(&self) -> usize {
+        self.stack.len()
+    }
+
+    /// Opens an object; the following items become its fields until `end_object`.
+    pub fn begin_object(&mut self, tag: u64) -> Result<()> {
+        self.begin_container(tag, HtlvValueType::Object)
+    }
+
+    /// Closes the innermost open object.
+    pub fn end_object(&mut self) -> Result<()> {
+        self.end_container(HtlvValueType::Object)
+    }
+
+    /// Opens an array; the following items become its elements until `end_array`.
+    pub fn begin_array(&mut self, tag: u64) -> Result<()> {
+        self.begin_container(tag, HtlvValueType::Array)
+    }
+
+    /// Closes the innermost open array.
+    pub fn end_array(&mut self) -> Result<()> {
+        self.end_container(HtlvValueType::Array)
+    }
+
+    /// Writes a Null field.
+    pub fn field_null(&mut self, tag: u64) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::Null, &[])
+    }
+
+    /// Writes a Bool field.
+    pub fn field_bool(&mut self, tag: u64, value: bool) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::Bool, &[value as u8])
+    }
+
+    /// Writes a U8 field.
+    pub fn field_u8(&mut self, tag: u64, value: u8) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::U8, &[value])
+    }
+
+    /// Writes a U16 field.
+    pub fn field_u16(&mut self, tag: u64, value: u16) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::U16, &value.to_le_bytes())
+    }
+
+    /// Writes a U32 field.
+    pub fn field_u32(&mut self, tag: u64, value: u32) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::U32, &value.to_le_bytes())
+    }
+
+    /// Writes a U64 field.
+    pub fn field_u64(&mut self, tag: u64, value: u64) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::U64, &value.to_le_bytes())
+    }
+
+    /// Writes an I8 field.
+    pub fn field_i8(&mut self, tag: u64, value: i8) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::I8, &value.to_le_bytes())
+    }
+
+    /// Writes an I16 field.
+    pub fn field_i16(&mut self, tag: u64, value: i16) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::I16, &value.to_le_bytes())
+    }
+
+    /// Writes an I32 field.
+    pub fn field_i32(&mut self, tag: u64, value: i32) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::I32, &value.to_le_bytes())
+    }
+
+    /// Writes an I64 field.
+    pub fn field_i64(&mut self, tag: u64, value: i64) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::I64, &value.to_le_bytes())
+    }
+
+    /// Writes an F32 field.
+    pub fn field_f32(&mut self, tag: u64, value: f32) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::F32, &value.to_le_bytes())
+    }
+
+    /// Writes an F64 field.
+    pub fn field_f64(&mut self, tag: u64, value: f64) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::F64, &value.to_le_bytes())
+    }
+
+    /// Writes a Bytes field.
+    ///
+    /// Unlike `encode_item`, large values are written as a single item rather than
+    /// being sharded.
+    pub fn field_bytes(&mut self, tag: u64, value: &[u8]) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::Bytes, value)
+    }
+
+    /// Writes a String field.
+    pub fn field_str(&mut self, tag: u64, value: &str) -> Result<()> {
+        self.field_raw(tag, HtlvValueType::String, value.as_bytes())
+    }
+
+    /// Flushes the underlying writer.
+    ///
+    /// Containers that are still open have not been written yet.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    /// Finishes encoding and returns the writer.
+    ///
+    /// Fails if any object or array is still open.
+    pub fn finish(mut self) -> Result<W> {
+        if let Some(open) = self.stack.last() {
+            return Err(Error::CodecError(format!(
+                "Cannot finish encoding with {} unclosed container(s), innermost has tag {}",
+                self.stack.len(),
+                open.tag
+            )));
+        }
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    /// Writes a basic item with an already-encoded value.
+    fn field_raw(&mut self, tag: u64, value_type: HtlvValueType, value: &[u8]) -> Result<()> {
+        match self.stack.last_mut() {
+            Some(parent) => {
+                write_header(&mut parent.body, tag, value_type, value.len())?;
+                parent.body.extend_from_slice(value);
+            }
+            None => {
+                write_header(&mut self.writer, tag, value_type, value.len())?;
+                self.writer.write_all(value)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn begin_container(&mut self, tag: u64, value_type: HtlvValueType) -> Result<()> {
+        if self.stack.len() >= MAX_NESTING_DEPTH {
+            return Err(Error::CodecError(format!("Maximum nesting depth ({}) exceeded", MAX_NESTING_DEPTH)));
+        }
+        let body = self.spare_buffers.pop().unwrap_or_default();
+        self.stack.push(OpenContainer { tag, value_type, body });
+        Ok(())
+    }
+
+    fn end_container(&mut self, value_type: HtlvValueType) -> Result<()> {
+        match self.stack.last() {
+            Some(open) if open.value_type == value_type => {}
+            Some(open) => {
+                return Err(Error::CodecError(format!(
+                    "Cannot close {:?}: innermost open container with tag {} is {:?}",
+                    value_type, open.tag, open.value_type
+                )));
+            }
+            None => return Err(Error::CodecError(format!("Cannot close {:?}: no open container", value_type))),
+        }
+
+        let mut container = self.stack.pop().expect("stack checked above");
+        self.field_raw(container.tag, container.value_type, &container.body)?;
+        container.body.clear();
+        self.spare_buffers.push(container.body);
+        Ok(())
+    }
+}
+
+/// Writes an item header (Tag + Type + Length).
+fn write_header<W: Write>(writer: &mut W, tag: u64, value_type: HtlvValueType, length: usize) -> Result<()> {
+    writer.write_all(&varint::encode_varint(tag))?;
+    writer.write_all(&[value_type as u8])?;
+    writer.write_all(&varint::encode_varint(length as u64))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::{HtlvItem, HtlvValue};
+    use bytes::Bytes;
+
+    #[test]
+    fn test_push_encoder_matches_encode_item() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U32(70000)),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::I16(-2)),
+                HtlvItem::new(0, HtlvValue::Bool(true)),
+            ])),
+            HtlvItem::new(4, HtlvValue::String(Bytes::from_static(b"sensor"))),
+            HtlvItem::new(5, HtlvValue::Null),
+        ]));
+
+        let mut encoder = PushEncoder::new(Vec::new());
+        encoder.begin_object(1).unwrap();
+        encoder.field_u32(2, 70000).unwrap();
+        encoder.begin_array(3).unwrap();
+        encoder.field_i16(0, -2).unwrap();
+        encoder.field_bool(0, true).unwrap();
+        encoder.end_array().unwrap();
+        encoder.field_str(4, "sensor").unwrap();
+        encoder.field_null(5).unwrap();
+        encoder.end_object().unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        assert_eq!(encoded, encode_item(&item).unwrap());
+    }
+
+    #[test]
+    fn test_top_level_fields_stream_directly() {
+        let mut encoder = PushEncoder::new(Vec::new());
+        encoder.field_u8(7, 9).unwrap();
+        encoder.field_f64(8, 0.5).unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        let mut expected = encode_item(&HtlvItem::new(7, HtlvValue::U8(9))).unwrap();
+        expected.extend_from_slice(&encode_item(&HtlvItem::new(8, HtlvValue::F64(0.5))).unwrap());
+        assert_eq!(encoded, expected);
+    }
+
+    #[test]
+    fn test_mismatched_and_unclosed_containers() {
+        let mut encoder = PushEncoder::new(Vec::new());
+        assert!(encoder.end_object().is_err());
+
+        encoder.begin_object(1).unwrap();
+        let err = encoder.end_array().unwrap_err();
+        assert!(err.to_string().contains("innermost open container with tag 1 is Object"));
+
+        assert_eq!(encoder.depth(), 1);
+        assert!(encoder.finish().is_err());
+    }
+
+    #[test]
+    fn test_depth_limit() {
+        let mut encoder = PushEncoder::new(Vec::new());
+        for _ in 0..MAX_NESTING_DEPTH {
+            encoder.begin_array(0).unwrap();
+        }
+        assert!(encoder.begin_array(0).is_err());
+    }
+}
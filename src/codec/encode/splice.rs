@@ -0,0 +1,261 @@
+// Incremental re-encoding of modified HTLV documents
+//
+// A `TrackedDocument` indexes the byte range of every item in an encoded
+// buffer. Items can then be replaced by path, which marks them and their
+// ancestors dirty. Re-encoding copies the byte ranges of untouched subtrees
+// from the original buffer and only encodes the replaced items, rewriting the
+// length prefixes of the containers on the way to them.
+
+use crate::internal::error::{Error, Result};
+use crate::codec::varint;
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use crate::codec::decode::pull::{PullDecoder, PullEvent};
+use super::encode_item;
+use bytes::Bytes;
+use std::ops::Range;
+
+/// The state of a tracked item.
+#[derive(Debug, Clone)]
+enum NodeState {
+    /// The item is unchanged, although its descendants may have been replaced
+    Original,
+    /// The item has been replaced
+    Replaced(HtlvItem),
+}
+
+/// An item of the tracked document.
+#[derive(Debug, Clone)]
+struct TrackedNode {
+    tag: u64,
+    value_type: HtlvValueType,
+    /// Byte range of the whole item (header and value) in the original buffer
+    range: Range<usize>,
+    /// Items nested in an array or object, in wire order
+    children: Vec<TrackedNode>,
+    state: NodeState,
+    /// Whether the item or any of its descendants has been replaced
+    dirty: bool,
+}
+
+/// An encoded document with dirty tracking, re-encoded by splicing untouched
+/// byte ranges from the original buffer.
+///
+/// Paths are lists of child indices, starting from the root item. Each index
+/// refers to an item on the wire, so a large Bytes or String value sharded by
+/// `encode_item` occupies several consecutive indices.
+#[derive(Debug, Clone)]
+pub struct TrackedDocument {
+    original: Bytes,
+    root: TrackedNode,
+}
+
+impl TrackedDocument {
+    /// Indexes a buffer holding exactly one encoded item.
+    pub fn parse(original: Bytes) -> Result<Self> {
+        let mut decoder = PullDecoder::new(&original);
+        let mut open: Vec<TrackedNode> = Vec::new();
+        let mut root = None;
+
+        loop {
+            let start = decoder.offset();
+            let event = match decoder.next_event()? {
+                Some(event) => event,
+                None => break,
+            };
+
+            let completed = match event {
+                PullEvent::BeginObject(tag) | PullEvent::BeginArray(tag) => {
+                    let value_type = match event {
+                        PullEvent::BeginArray(_) => HtlvValueType::Array,
+                        _ => HtlvValueType::Object,
+                    };
+                    open.push(TrackedNode::new(tag, value_type, start..start));
+                    continue;
+                }
+                PullEvent::EndObject | PullEvent::EndArray => {
+                    let mut node = open.pop()
+                        .ok_or_else(|| Error::CodecError("Unbalanced container end".to_string()))?;
+                    node.range.end = decoder.offset();
+                    node
+                }
+                PullEvent::Field(tag, value_type, _) => TrackedNode::new(tag, value_type, start..decoder.offset()),
+            };
+
+            match open.last_mut() {
+                Some(parent) => parent.children.push(completed),
+                None if root.is_none() => root = Some(completed),
+                None => return Err(Error::CodecError(format!("Trailing data after root item at offset {}", start))),
+            }
+        }
+
+        let root = root.ok_or_else(|| Error::CodecError("Document contains no item".to_string()))?;
+        Ok(TrackedDocument { original, root })
+    }
+
+    /// Returns the original encoded buffer.
+    pub fn original(&self) -> &Bytes {
+        &self.original
+    }
+
+    /// Returns true if any item has been replaced since parsing.
+    pub fn is_dirty(&self) -> bool {
+        self.root.dirty
+    }
+
+    /// Replaces the value of the item at `path`, keeping its tag.
+    pub fn set_value(&mut self, path: &[usize], value: HtlvValue) -> Result<()> {
+        let node = self.node_mut(path)?;
+        let item = HtlvItem::new(node.tag, value);
+        node.replace(item);
+        Ok(())
+    }
+
+    /// Replaces the item at `path`, including its tag.
+    pub fn replace_item(&mut self, path: &[usize], item: HtlvItem) -> Result<()> {
+        self.node_mut(path)?.replace(item);
+        Ok(())
+    }
+
+    /// Re-encodes the document, reusing the original bytes of untouched subtrees.
+    ///
+    /// Returns the original buffer unchanged if nothing has been replaced.
+    pub fn encode(&self) -> Result<Bytes> {
+        if !self.root.dirty {
+            return Ok(self.original.clone());
+        }
+        let mut out = Vec::with_capacity(self.original.len());
+        self.encode_node(&self.root, &mut out)?;
+        Ok(Bytes::from(out))
+    }
+
+    /// Finds the item at `path`, marking it and its ancestors dirty.
+    ///
+    /// Nothing is marked if the path is invalid.
+    fn node_mut(&mut self, path: &[usize]) -> Result<&mut TrackedNode> {
+        let mut node = &self.root;
+        for (depth, &index) in path.iter().enumerate() {
+            if let NodeState::Replaced(_) = node.state {
+                return Err(Error::CodecError(format!("Path {:?} points inside an already replaced item", &path[..=depth])));
+            }
+            node = node.children.get(index).ok_or_else(|| {
+                Error::CodecError(format!("Path index {} out of bounds at depth {} ({} items)", index, depth, node.children.len()))
+            })?;
+        }
+
+        let mut node = &mut self.root;
+        node.dirty = true;
+        for &index in path {
+            node = &mut node.children[index];
+            node.dirty = true;
+        }
+        Ok(node)
+    }
+
+    fn encode_node(&self, node: &TrackedNode, out: &mut Vec<u8>) -> Result<()> {
+        if !node.dirty {
+            out.extend_from_slice(&self.original[node.range.clone()]);
+            return Ok(());
+        }
+
+        match &node.state {
+            NodeState::Replaced(item) => out.extend_from_slice(&encode_item(item)?),
+            NodeState::Original => {
+                // Only some descendants changed, rebuild the container header around them
+                let mut body = Vec::new();
+                for child in &node.children {
+                    self.encode_node(child, &mut body)?;
+                }
+                out.extend_from_slice(&varint::encode_varint(node.tag));
+                out.push(node.value_type as u8);
+                out.extend_from_slice(&varint::encode_varint(body.len() as u64));
+                out.extend_from_slice(&body);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl TrackedNode {
+    fn new(tag: u64, value_type: HtlvValueType, range: Range<usize>) -> Self {
+        TrackedNode {
+            tag,
+            value_type,
+            range,
+            children: Vec::new(),
+            state: NodeState::Original,
+            dirty: false,
+        }
+    }
+
+    fn replace(&mut self, item: HtlvItem) {
+        self.children.clear();
+        self.state = NodeState::Replaced(item);
+        self.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(name: &'static [u8], count: u8) -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::String(Bytes::from_static(name))),
+            HtlvItem::new(3, HtlvValue::Object(vec![
+                HtlvItem::new(4, HtlvValue::U8(count)),
+                HtlvItem::new(5, HtlvValue::Bool(true)),
+            ])),
+            HtlvItem::new(6, HtlvValue::Bytes(Bytes::from_static(b"\x00\x01\x02"))),
+        ]))
+    }
+
+    #[test]
+    fn test_unmodified_document_reuses_buffer() {
+        let encoded = Bytes::from(encode_item(&sample(b"a", 1)).unwrap());
+        let document = TrackedDocument::parse(encoded.clone()).unwrap();
+        assert!(!document.is_dirty());
+        assert_eq!(document.encode().unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_splice_nested_change() {
+        let encoded = Bytes::from(encode_item(&sample(b"a", 1)).unwrap());
+        let mut document = TrackedDocument::parse(encoded).unwrap();
+
+        document.set_value(&[1, 0], HtlvValue::U8(200)).unwrap();
+        assert_eq!(document.encode().unwrap(), encode_item(&sample(b"a", 200)).unwrap());
+
+        // A change that alters the length of an item rewrites the enclosing lengths
+        document.set_value(&[0], HtlvValue::String(Bytes::from_static(b"a much longer name"))).unwrap();
+        assert_eq!(document.encode().unwrap(), encode_item(&sample(b"a much longer name", 200)).unwrap());
+    }
+
+    #[test]
+    fn test_replace_root() {
+        let encoded = Bytes::from(encode_item(&sample(b"a", 1)).unwrap());
+        let mut document = TrackedDocument::parse(encoded).unwrap();
+
+        let replacement = HtlvItem::new(9, HtlvValue::Null);
+        document.replace_item(&[], replacement.clone()).unwrap();
+        assert_eq!(document.encode().unwrap(), encode_item(&replacement).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_paths() {
+        let encoded = Bytes::from(encode_item(&sample(b"a", 1)).unwrap());
+        let mut document = TrackedDocument::parse(encoded).unwrap();
+
+        assert!(document.set_value(&[7], HtlvValue::Null).is_err());
+        assert!(!document.is_dirty());
+        document.set_value(&[1], HtlvValue::Null).unwrap();
+        assert!(document.set_value(&[1, 0], HtlvValue::U8(0)).is_err());
+    }
+
+    #[test]
+    fn test_parse_rejects_multiple_roots() {
+        let mut encoded = encode_item(&HtlvItem::new(1, HtlvValue::U8(1))).unwrap();
+        encoded.extend_from_slice(&encode_item(&HtlvItem::new(2, HtlvValue::U8(2))).unwrap());
+        assert!(TrackedDocument::parse(Bytes::from(encoded)).is_err());
+        assert!(TrackedDocument::parse(Bytes::new()).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+// Streaming encoder for HTLV items
+//
+// `StreamingEncoder` writes `HtlvItem` trees into a writer without building
+// the encoded bytes in memory. HTLV prefixes every object and array with its
+// length, so the encoded length of each container is computed up front from
+// its children instead of buffering them. Large Bytes and String values are
+// sharded exactly like `encode_item` does, and their contents can also be
+// streamed through the `io::Write` implementation once the field is opened
+// with its total length.
+
+use crate::internal::error::{Error, Result};
+use crate::codec::varint;
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use crate::config::TonitruConfig;
+use super::basic::encode_basic_value;
+use super::{encode_packed_into, encoded_len, LARGE_FIELD_THRESHOLD, TOTAL_LENGTH_HEADER_LEN};
+use std::io::{self, Write};
+
+/// A Bytes or String field whose contents are being streamed.
+#[derive(Debug)]
+struct OpenField {
+    tag: u64,
+    value_type: HtlvValueType,
+    /// Whether the field is written as a header followed by shards
+    sharded: bool,
+    /// Bytes of the field still to be written
+    remaining: u64,
+    /// Bytes still to be written into the current shard
+    shard_remaining: u64,
+}
+
+/// An encoder streaming HTLV items into a writer.
+#[derive(Debug)]
+pub struct StreamingEncoder<W: Write> {
+    writer: W,
+    threshold: usize,
+    open_field: Option<OpenField>,
+    bytes_written: u64,
+}
+
+impl<W: Write> StreamingEncoder<W> {
+    /// Creates a streaming encoder sharding values larger than `LARGE_FIELD_THRESHOLD`.
+    pub fn new(writer: W) -> Self {
+        Self::with_threshold(writer, LARGE_FIELD_THRESHOLD)
+    }
+
+    /// Creates a streaming encoder using the large field threshold of the configuration.
+    pub fn with_config(writer: W, config: &TonitruConfig) -> Self {
+        Self::with_threshold(writer, config.large_field_threshold())
+    }
+
+    /// Creates a streaming encoder sharding values larger than `threshold` bytes.
+    pub fn with_threshold(writer: W, threshold: usize) -> Self {
+        StreamingEncoder {
+            writer,
+            threshold: threshold.max(1),
+            open_field: None,
+            bytes_written: 0,
+        }
+    }
+
+    /// Returns the number of bytes written so far.
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    /// Returns the number of bytes `write_item` will write for the item.
+    pub fn encoded_len(&self, item: &HtlvItem) -> u64 {
+        encoded_len(item, self.threshold as u64)
+    }
+
+    /// Writes an item; the output is identical to `encode_item` with the same threshold.
+    pub fn write_item(&mut self, item: &HtlvItem) -> Result<()> {
+        self.ensure_no_open_field()?;
+        self.write_tree(item)
+    }
+
+    /// Opens a Bytes field of `total_len` bytes, whose contents are then written
+    /// through the `io::Write` implementation and completed with `end_field`.
+    pub fn begin_bytes(&mut self, tag: u64, total_len: u64) -> Result<()> {
+        self.begin_field(tag, HtlvValueType::Bytes, total_len)
+    }
+
+    /// Opens a String field of `total_len` bytes, like `begin_bytes`.
+    ///
+    /// The contents are not checked while streaming; they must form valid UTF-8
+    /// for the field to decode.
+    pub fn begin_string(&mut self, tag: u64, total_len: u64) -> Result<()> {
+        self.begin_field(tag, HtlvValueType::String, total_len)
+    }
+
+    /// Completes the open field, failing if fewer bytes than announced were written.
+    pub fn end_field(&mut self) -> Result<()> {
+        match self.open_field.take() {
+            Some(field) if field.remaining == 0 => Ok(()),
+            Some(field) => {
+                let remaining = field.remaining;
+                self.open_field = Some(field);
+                Err(Error::CodecError(format!("Cannot end field: {} announced bytes not written", remaining)))
+            }
+            None => Err(Error::CodecError("Cannot end field: no open field".to_string())),
+        }
+    }
+
+    /// Finishes encoding and returns the writer.
+    ///
+    /// Fails if a field is still open.
+    pub fn finish(mut self) -> Result<W> {
+        self.ensure_no_open_field()?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+
+    fn ensure_no_open_field(&self) -> Result<()> {
+        match &self.open_field {
+            Some(field) => Err(Error::CodecError(format!(
+                "Field with tag {} is still open with {} bytes to write",
+                field.tag, field.remaining
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn begin_field(&mut self, tag: u64, value_type: HtlvValueType, total_len: u64) -> Result<()> {
+        self.ensure_no_open_field()?;
+        let sharded = total_len > self.threshold as u64;
+        if sharded {
+            self.write_header(tag, value_type, TOTAL_LENGTH_HEADER_LEN)?;
+            self.put(&total_len.to_le_bytes())?;
+        } else {
+            self.write_header(tag, value_type, total_len)?;
+        }
+        self.open_field = Some(OpenField {
+            tag,
+            value_type,
+            sharded,
+            remaining: total_len,
+            shard_remaining: if sharded { 0 } else { total_len },
+        });
+        Ok(())
+    }
+
+    fn write_tree(&mut self, item: &HtlvItem) -> Result<()> {
+        match &item.value {
+            HtlvValue::Bytes(v) if v.len() > self.threshold => {
+                self.write_sharded(item.tag, HtlvValueType::Bytes, v)
+            }
+            HtlvValue::String(v) if v.len() > self.threshold => {
+                self.write_sharded(item.tag, HtlvValueType::String, v)
+            }
+            HtlvValue::Array(children) => {
+                let mut packed = Vec::new();
+                if encode_packed_into(item.tag, children, &mut packed) {
+                    return self.put(&packed);
+                }
+                self.write_children(item, children)
+            }
+            HtlvValue::Object(children) => self.write_children(item, children),
+            value => {
+                let (value_type_byte, encoded_value) = encode_basic_value(value)?;
+                self.put(&varint::encode_varint(item.tag))?;
+                self.put(&[value_type_byte])?;
+                self.put(&varint::encode_varint(encoded_value.len() as u64))?;
+                self.put(&encoded_value)
+            }
+        }
+    }
+
+    /// Writes an Array or Object header followed by its children.
+    fn write_children(&mut self, item: &HtlvItem, children: &[HtlvItem]) -> Result<()> {
+        let value_type = item.value.value_type();
+        let threshold = self.threshold as u64;
+        let length = children.iter().map(|child| encoded_len(child, threshold)).sum();
+        self.write_header(item.tag, value_type, length)?;
+        for child in children {
+            self.write_tree(child)?;
+        }
+        Ok(())
+    }
+
+    fn write_sharded(&mut self, tag: u64, value_type: HtlvValueType, data: &[u8]) -> Result<()> {
+        self.write_header(tag, value_type, TOTAL_LENGTH_HEADER_LEN)?;
+        self.put(&(data.len() as u64).to_le_bytes())?;
+        for chunk in data.chunks(self.threshold) {
+            self.write_header(tag, value_type, chunk.len() as u64)?;
+            self.put(chunk)?;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self, tag: u64, value_type: HtlvValueType, length: u64) -> Result<()> {
+        self.put(&varint::encode_varint(tag))?;
+        self.put(&[value_type as u8])?;
+        self.put(&varint::encode_varint(length))
+    }
+
+    fn put(&mut self, bytes: &[u8]) -> Result<()> {
+        self.writer.write_all(bytes)?;
+        self.bytes_written += bytes.len() as u64;
+        Ok(())
+    }
+
+    /// Writes as much of `buf` as fits into the current shard of the open field.
+    fn write_field_data(&mut self, buf: &[u8]) -> Result<usize> {
+        let (tag, value_type, sharded, remaining, shard_remaining) = match &self.open_field {
+            Some(field) => (field.tag, field.value_type, field.sharded, field.remaining, field.shard_remaining),
+            None => return Err(Error::CodecError("Cannot write field data: no open field".to_string())),
+        };
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if remaining == 0 {
+            return Err(Error::CodecError(format!("Field with tag {} exceeds its announced length", tag)));
+        }
+
+        let mut shard_remaining = shard_remaining;
+        if shard_remaining == 0 && sharded {
+            // Start the next shard, sized like `encode_item` does
+            shard_remaining = remaining.min(self.threshold as u64);
+            self.write_header(tag, value_type, shard_remaining)?;
+        }
+
+        let count = (buf.len() as u64).min(shard_remaining) as usize;
+        self.put(&buf[..count])?;
+        if let Some(field) = self.open_field.as_mut() {
+            field.remaining -= count as u64;
+            field.shard_remaining = shard_remaining - count as u64;
+        }
+        Ok(count)
+    }
+}
+
+impl<W: Write> Write for StreamingEncoder<W> {
+    /// Writes contents of the open field; fails if no field is open or the data
+    /// would exceed the announced length.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.write_field_data(buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e.to_string()))
+    }
+
+    /// Flushes the underlying writer, including partial shards of the open field.
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::encode_item;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_streaming_encoder_matches_encode_item() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U32(70000)),
+            HtlvItem::new(3, HtlvValue::Bytes(Bytes::from(vec![9u8; LARGE_FIELD_THRESHOLD * 2 + 5]))),
+            HtlvItem::new(4, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::String(Bytes::from("x".repeat(LARGE_FIELD_THRESHOLD + 1)))),
+                HtlvItem::new(0, HtlvValue::Null),
+            ])),
+        ]));
+
+        let mut encoder = StreamingEncoder::new(Vec::new());
+        let expected_len = encoder.encoded_len(&item);
+        encoder.write_item(&item).unwrap();
+        assert_eq!(encoder.bytes_written(), expected_len);
+        let encoded = encoder.finish().unwrap();
+
+        assert_eq!(encoded, encode_item(&item).unwrap());
+        assert_eq!(encoded.len() as u64, expected_len);
+    }
+
+    #[test]
+    fn test_streamed_field_contents() {
+        let data: Vec<u8> = (0..5000u32).map(|i| i as u8).collect();
+
+        let mut encoder = StreamingEncoder::new(Vec::new());
+        encoder.begin_bytes(7, data.len() as u64).unwrap();
+        for piece in data.chunks(333) {
+            encoder.write_all(piece).unwrap();
+            encoder.flush().unwrap();
+        }
+        assert!(encoder.write_all(b"extra").is_err());
+        encoder.end_field().unwrap();
+        let encoded = encoder.finish().unwrap();
+
+        let expected = encode_item(&HtlvItem::new(7, HtlvValue::Bytes(Bytes::from(data)))).unwrap();
+        assert_eq!(encoded, expected);
+
+        // A field must be completed before the encoder can move on
+        let mut encoder = StreamingEncoder::new(Vec::new());
+        encoder.begin_string(1, 4).unwrap();
+        encoder.write_all(b"ab").unwrap();
+        assert!(encoder.end_field().is_err());
+        assert!(encoder.write_item(&HtlvItem::new(2, HtlvValue::Null)).is_err());
+        encoder.write_all(b"cd").unwrap();
+        encoder.end_field().unwrap();
+        assert!(encoder.write_all(b"e").is_err());
+    }
+}
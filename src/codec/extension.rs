@@ -0,0 +1,405 @@
+// Application-defined extension value types
+//
+// Type bytes in `EXTENSION_TYPE_RANGE` are reserved for value types that
+// applications define themselves, such as a Decimal128 or an H3 cell id. The
+// codec carries their values as `HtlvValue::Extension(type_byte, payload)`
+// without interpreting the payload, so peers that do not know a type still
+// decode, forward and re-encode it unchanged. An `ExtensionRegistry` holds what
+// an application knows about each of its types: how a Rust value is encoded to
+// and decoded from the payload, how it is displayed, and how it maps to JSON.
+
+use std::any::{Any, TypeId};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::codec::types::{HtlvValue, EXTENSION_TYPE_RANGE};
+use crate::internal::error::{Error, Result};
+use crate::schema::mapper::value_to_json;
+
+type EncodeFn<T> = Box<dyn Fn(&T) -> Result<Vec<u8>> + Send + Sync>;
+type DecodeFn<T> = Box<dyn Fn(&[u8]) -> Result<T> + Send + Sync>;
+type DisplayFn<T> = Box<dyn Fn(&T) -> String + Send + Sync>;
+type ToJsonFn<T> = Box<dyn Fn(&T) -> serde_json::Value + Send + Sync>;
+type FromJsonFn<T> = Box<dyn Fn(&serde_json::Value) -> Result<T> + Send + Sync>;
+type PayloadFn<R> = Box<dyn Fn(&[u8]) -> Result<R> + Send + Sync>;
+type ErasedEncodeFn = Box<dyn Fn(&dyn Any) -> Result<Vec<u8>> + Send + Sync>;
+type JsonPayloadFn = Box<dyn Fn(&serde_json::Value) -> Result<Vec<u8>> + Send + Sync>;
+
+/// Definition of an extension type whose values are the Rust type `T`.
+pub struct ExtensionType<T> {
+    type_byte: u8,
+    name: String,
+    encode: EncodeFn<T>,
+    decode: DecodeFn<T>,
+    display: Option<DisplayFn<T>>,
+    json: Option<(ToJsonFn<T>, FromJsonFn<T>)>,
+}
+
+impl<T: 'static> ExtensionType<T> {
+    /// Defines an extension type with the type byte it is encoded under and the
+    /// functions converting its values to and from their payload.
+    pub fn new(
+        type_byte: u8,
+        name: &str,
+        encode: impl Fn(&T) -> Result<Vec<u8>> + Send + Sync + 'static,
+        decode: impl Fn(&[u8]) -> Result<T> + Send + Sync + 'static,
+    ) -> Self {
+        ExtensionType {
+            type_byte,
+            name: name.to_string(),
+            encode: Box::new(encode),
+            decode: Box::new(decode),
+            display: None,
+            json: None,
+        }
+    }
+
+    /// Sets how values are displayed; by default the payload is shown in hex.
+    pub fn with_display(mut self, display: impl Fn(&T) -> String + Send + Sync + 'static) -> Self {
+        self.display = Some(Box::new(display));
+        self
+    }
+
+    /// Sets how values map to and from JSON; by default the payload becomes a
+    /// base64 string and values cannot be read from JSON.
+    pub fn with_json(
+        mut self,
+        to_json: impl Fn(&T) -> serde_json::Value + Send + Sync + 'static,
+        from_json: impl Fn(&serde_json::Value) -> Result<T> + Send + Sync + 'static,
+    ) -> Self {
+        self.json = Some((Box::new(to_json), Box::new(from_json)));
+        self
+    }
+}
+
+/// A registered extension type, with its functions erased to work on payloads.
+struct RegisteredType {
+    name: String,
+    rust_type: TypeId,
+    rust_type_name: &'static str,
+    encode: ErasedEncodeFn,
+    decode: PayloadFn<Box<dyn Any>>,
+    display: PayloadFn<String>,
+    to_json: Option<PayloadFn<serde_json::Value>>,
+    from_json: Option<JsonPayloadFn>,
+}
+
+/// Registry of the extension types an application uses.
+///
+/// The registry is only needed to work with the Rust values of extension
+/// types; encoding and decoding HTLV items keeps extension payloads as they are.
+#[derive(Default)]
+pub struct ExtensionRegistry {
+    types: HashMap<u8, RegisteredType>,
+}
+
+impl fmt::Debug for ExtensionRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut types: Vec<_> = self.types.iter().map(|(byte, registered)| (*byte, &registered.name)).collect();
+        types.sort();
+        f.debug_struct("ExtensionRegistry").field("types", &types).finish()
+    }
+}
+
+impl ExtensionRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers an extension type.
+    ///
+    /// Fails if the type byte is outside `EXTENSION_TYPE_RANGE` or already
+    /// registered, or if the name is already used by another type.
+    pub fn register<T: 'static>(&mut self, extension: ExtensionType<T>) -> Result<()> {
+        let ExtensionType { type_byte, name, encode, decode, display, json } = extension;
+        if !EXTENSION_TYPE_RANGE.contains(&type_byte) {
+            return Err(Error::CodecError(format!(
+                "Extension type byte {:#04x} is outside the reserved range {:#04x}..={:#04x}",
+                type_byte, EXTENSION_TYPE_RANGE.start(), EXTENSION_TYPE_RANGE.end()
+            )));
+        }
+        if let Some(existing) = self.types.get(&type_byte) {
+            return Err(Error::CodecError(format!(
+                "Extension type byte {:#04x} is already registered for '{}'", type_byte, existing.name
+            )));
+        }
+        if self.types.values().any(|existing| existing.name == name) {
+            return Err(Error::CodecError(format!("Extension type name '{}' is already registered", name)));
+        }
+
+        let encode = Arc::new(encode);
+        let decode = Arc::new(decode);
+        let display: PayloadFn<String> = match display {
+            Some(display) => {
+                let decode = decode.clone();
+                Box::new(move |payload| Ok(display(&decode(payload)?)))
+            }
+            None => {
+                let name = name.clone();
+                Box::new(move |payload| {
+                    let hex: String = payload.iter().map(|byte| format!("{:02x}", byte)).collect();
+                    Ok(format!("{}({})", name, hex))
+                })
+            }
+        };
+        let (to_json, from_json) = match json {
+            Some((to_json, from_json)) => {
+                let (decode, encode) = (decode.clone(), encode.clone());
+                let to_json: PayloadFn<serde_json::Value> = Box::new(move |payload| Ok(to_json(&decode(payload)?)));
+                let from_json: JsonPayloadFn = Box::new(move |json| encode(&from_json(json)?));
+                (Some(to_json), Some(from_json))
+            }
+            None => (None, None),
+        };
+
+        self.types.insert(type_byte, RegisteredType {
+            name,
+            rust_type: TypeId::of::<T>(),
+            rust_type_name: std::any::type_name::<T>(),
+            encode: Box::new(move |value| match value.downcast_ref::<T>() {
+                Some(value) => encode(value),
+                None => Err(Error::CodecError("Extension value has the wrong Rust type".to_string())),
+            }),
+            decode: Box::new(move |payload| Ok(Box::new(decode(payload)?) as Box<dyn Any>)),
+            display,
+            to_json,
+            from_json,
+        });
+        Ok(())
+    }
+
+    /// Returns the name of the extension type with the given type byte, if registered.
+    pub fn name(&self, type_byte: u8) -> Option<&str> {
+        self.types.get(&type_byte).map(|registered| registered.name.as_str())
+    }
+
+    /// Returns the type byte of the extension type with the given name, if registered.
+    pub fn type_byte(&self, name: &str) -> Option<u8> {
+        self.types.iter().find(|(_, registered)| registered.name == name).map(|(byte, _)| *byte)
+    }
+
+    /// Encodes a Rust value as an extension value of the given type.
+    pub fn to_value<T: 'static>(&self, type_byte: u8, value: &T) -> Result<HtlvValue> {
+        let registered = self.registered_as::<T>(type_byte)?;
+        Ok(HtlvValue::Extension(type_byte, Bytes::from((registered.encode)(value)?)))
+    }
+
+    /// Decodes the Rust value of an extension value.
+    pub fn from_value<T: 'static>(&self, value: &HtlvValue) -> Result<T> {
+        let (type_byte, payload) = extension_parts(value)?;
+        let registered = self.registered_as::<T>(type_byte)?;
+        let decoded = (registered.decode)(payload)?;
+        Ok(*decoded.downcast::<T>().expect("type checked by registered_as"))
+    }
+
+    /// Checks that every extension value in the tree is of a registered type
+    /// and that its payload decodes.
+    pub fn validate(&self, value: &HtlvValue) -> Result<()> {
+        match value {
+            HtlvValue::Extension(type_byte, payload) => {
+                (self.registered(*type_byte)?.decode)(payload).map(|_| ())
+            }
+            HtlvValue::Array(items) | HtlvValue::Object(items) => {
+                items.iter().try_for_each(|item| self.validate(&item.value))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Displays an extension value with the display function of its type.
+    pub fn display(&self, value: &HtlvValue) -> Result<String> {
+        let (type_byte, payload) = extension_parts(value)?;
+        (self.registered(type_byte)?.display)(payload)
+    }
+
+    /// Converts a value tree to JSON like `schema::mapper::value_to_json`, using
+    /// the JSON mapping of registered extension types.
+    ///
+    /// Extension values of unregistered types, or of types without a JSON
+    /// mapping, become base64 strings of their payload.
+    pub fn to_json(&self, value: &HtlvValue) -> Result<serde_json::Value> {
+        match value {
+            HtlvValue::Extension(type_byte, payload) => {
+                match self.types.get(type_byte).and_then(|registered| registered.to_json.as_ref()) {
+                    Some(to_json) => to_json(payload),
+                    None => Ok(value_to_json(value)),
+                }
+            }
+            HtlvValue::Array(items) => {
+                items.iter().map(|item| self.to_json(&item.value)).collect::<Result<_>>().map(serde_json::Value::Array)
+            }
+            HtlvValue::Object(items) => items
+                .iter()
+                .map(|item| Ok((item.tag.to_string(), self.to_json(&item.value)?)))
+                .collect::<Result<_>>()
+                .map(serde_json::Value::Object),
+            other => Ok(value_to_json(other)),
+        }
+    }
+
+    /// Reads an extension value of the given type from JSON.
+    pub fn from_json(&self, type_byte: u8, json: &serde_json::Value) -> Result<HtlvValue> {
+        let registered = self.registered(type_byte)?;
+        let from_json = registered.from_json.as_ref().ok_or_else(|| {
+            Error::CodecError(format!("Extension type '{}' has no JSON mapping", registered.name))
+        })?;
+        Ok(HtlvValue::Extension(type_byte, Bytes::from(from_json(json)?)))
+    }
+
+    fn registered(&self, type_byte: u8) -> Result<&RegisteredType> {
+        self.types.get(&type_byte).ok_or_else(|| {
+            Error::CodecError(format!("Extension type byte {:#04x} is not registered", type_byte))
+        })
+    }
+
+    /// Returns the registered type, checking that its values are the Rust type `T`.
+    fn registered_as<T: 'static>(&self, type_byte: u8) -> Result<&RegisteredType> {
+        let registered = self.registered(type_byte)?;
+        if registered.rust_type != TypeId::of::<T>() {
+            return Err(Error::CodecError(format!(
+                "Extension type '{}' holds {}, not {}",
+                registered.name, registered.rust_type_name, std::any::type_name::<T>()
+            )));
+        }
+        Ok(registered)
+    }
+}
+
+/// Returns the type byte and payload of an extension value.
+fn extension_parts(value: &HtlvValue) -> Result<(u8, &[u8])> {
+    match value {
+        HtlvValue::Extension(type_byte, payload) => Ok((*type_byte, payload)),
+        other => Err(Error::CodecError(format!("Expected an Extension value, found {:?}", other.value_type()))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode::decode_item;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::HtlvItem;
+    use crate::codec::wire::{decode_item_with_format, encode_item_with_format, WireFormat};
+
+    const DECIMAL: u8 = 0x40;
+    const H3_CELL: u8 = 0x41;
+
+    /// Fixed-point decimal: mantissa * 10^-scale
+    #[derive(Debug, PartialEq)]
+    struct Decimal {
+        mantissa: i128,
+        scale: u8,
+    }
+
+    impl fmt::Display for Decimal {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            let divisor = 10i128.pow(self.scale as u32);
+            write!(f, "{}.{:0width$}", self.mantissa / divisor, (self.mantissa % divisor).abs(), width = self.scale as usize)
+        }
+    }
+
+    fn registry() -> ExtensionRegistry {
+        let decimal = ExtensionType::new(
+            DECIMAL,
+            "decimal128",
+            |d: &Decimal| {
+                let mut payload = d.mantissa.to_le_bytes().to_vec();
+                payload.push(d.scale);
+                Ok(payload)
+            },
+            |payload| match payload {
+                [mantissa @ .., scale] if mantissa.len() == 16 => Ok(Decimal {
+                    mantissa: i128::from_le_bytes(mantissa.try_into().unwrap()),
+                    scale: *scale,
+                }),
+                _ => Err(Error::CodecError(format!("Invalid decimal128 payload of {} bytes", payload.len()))),
+            },
+        )
+        .with_display(|d| d.to_string())
+        .with_json(
+            |d| serde_json::Value::String(d.to_string()),
+            |json| {
+                let text = json.as_str().ok_or_else(|| Error::CodecError("Expected a decimal string".to_string()))?;
+                let (whole, fraction) = text.split_once('.').unwrap_or((text, ""));
+                let mantissa = format!("{}{}", whole, fraction).parse()
+                    .map_err(|e| Error::CodecError(format!("Invalid decimal '{}': {}", text, e)))?;
+                Ok(Decimal { mantissa, scale: fraction.len() as u8 })
+            },
+        );
+        let h3_cell = ExtensionType::new(
+            H3_CELL,
+            "h3cell",
+            |cell: &u64| Ok(cell.to_le_bytes().to_vec()),
+            |payload| payload.try_into().map(u64::from_le_bytes)
+                .map_err(|_| Error::CodecError("Invalid h3cell payload".to_string())),
+        );
+
+        let mut registry = ExtensionRegistry::new();
+        registry.register(decimal).unwrap();
+        registry.register(h3_cell).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_extension_values_round_trip_through_both_wire_formats() {
+        let registry = registry();
+        let price = Decimal { mantissa: -12345, scale: 2 };
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(1, registry.to_value(DECIMAL, &price).unwrap()),
+            HtlvItem::new(2, registry.to_value(H3_CELL, &0x8a2a1072b59ffffu64).unwrap()),
+        ]));
+
+        let encoded = encode_item(&item).unwrap();
+        let (decoded, bytes_read) = decode_item(&encoded).unwrap();
+        assert_eq!(decoded, item);
+        assert_eq!(bytes_read, encoded.len());
+
+        let encoded_v2 = encode_item_with_format(&item, WireFormat::V2).unwrap();
+        let (decoded_v2, _) = decode_item_with_format(&encoded_v2, WireFormat::V2).unwrap();
+        assert_eq!(decoded_v2, item);
+
+        let HtlvValue::Object(items) = &decoded.value else { panic!("expected an Object") };
+        assert_eq!(registry.from_value::<Decimal>(&items[0].value).unwrap(), price);
+        assert_eq!(registry.from_value::<u64>(&items[1].value).unwrap(), 0x8a2a1072b59ffff);
+        registry.validate(&decoded.value).unwrap();
+
+        // Without the registry the values still decode, with their payload kept
+        assert_eq!(ExtensionRegistry::new().validate(&decoded.value).unwrap_err().to_string(),
+            "Codec Error: Extension type byte 0x40 is not registered");
+    }
+
+    #[test]
+    fn test_display_and_json_mappings() {
+        let registry = registry();
+        let price = registry.to_value(DECIMAL, &Decimal { mantissa: 1999, scale: 2 }).unwrap();
+        let cell = registry.to_value(H3_CELL, &1u64).unwrap();
+        assert_eq!(registry.display(&price).unwrap(), "19.99");
+        assert_eq!(registry.display(&cell).unwrap(), "h3cell(0100000000000000)");
+
+        let tree = HtlvValue::Array(vec![HtlvItem::new(0, price.clone()), HtlvItem::new(0, cell)]);
+        assert_eq!(registry.to_json(&tree).unwrap(), serde_json::json!(["19.99", "AQAAAAAAAAA="]));
+        assert_eq!(registry.from_json(DECIMAL, &serde_json::json!("19.99")).unwrap(), price);
+        assert!(registry.from_json(H3_CELL, &serde_json::json!(1)).is_err());
+    }
+
+    #[test]
+    fn test_registration_and_type_errors() {
+        let mut registry = registry();
+        let plain = |byte| ExtensionType::new(byte, "other", |v: &u8| Ok(vec![*v]), |p| Ok(p[0]));
+        assert!(registry.register(plain(0x0F)).is_err());
+        assert!(registry.register(plain(0x80)).is_err());
+        assert!(registry.register(plain(DECIMAL)).is_err());
+        registry.register(plain(0x7F)).unwrap();
+        assert_eq!(registry.name(0x7F), Some("other"));
+        assert_eq!(registry.type_byte("h3cell"), Some(H3_CELL));
+
+        let err = registry.to_value(H3_CELL, &1u32).unwrap_err();
+        assert!(err.to_string().contains("holds u64, not u32"), "{}", err);
+        assert!(registry.from_value::<u64>(&HtlvValue::U64(1)).is_err());
+        assert!(registry.from_value::<Decimal>(&HtlvValue::Extension(DECIMAL, Bytes::from_static(&[1]))).is_err());
+    }
+}
@@ -0,0 +1,196 @@
+// Bytes values stored outside the packet
+//
+// Packets carrying huge payloads, such as images or model weights, are costly
+// to relay and to keep in memory. An `HtlvValue::ExternalRef` stands for a
+// Bytes value held elsewhere, typically in object storage: it records where the
+// bytes live, their length and their BLAKE3 hash, so the packet stays small.
+// The codec never fetches anything itself; `resolve_value` and `resolve_item`
+// fetch the bytes through an application-provided `BlobResolver` when they are
+// accessed, and check them against the recorded length and hash.
+//
+// On the wire, an external reference is a value of type `ExternalRef` whose
+// payload is the 32-byte hash, the length as a little-endian u64 and then the
+// UTF-8 locator.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::RwLock;
+
+use bytes::Bytes;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::internal::error::{Error, Result};
+
+/// Length of the hash and length preceding the locator in an encoded reference
+pub const EXTERNAL_REF_HEADER_LEN: usize = 32 + 8;
+
+/// Fetches the bytes of external references.
+pub trait BlobResolver: Send + Sync {
+    /// Returns the bytes stored under the locator.
+    fn fetch(&self, locator: &str) -> Result<Bytes>;
+}
+
+impl<F: Fn(&str) -> Result<Bytes> + Send + Sync> BlobResolver for F {
+    fn fetch(&self, locator: &str) -> Result<Bytes> {
+        self(locator)
+    }
+}
+
+/// Returns an external reference to bytes stored under the locator.
+pub fn external_ref(locator: impl Into<String>, data: &[u8]) -> HtlvValue {
+    HtlvValue::ExternalRef {
+        locator: locator.into(),
+        length: data.len() as u64,
+        hash: *blake3::hash(data).as_bytes(),
+    }
+}
+
+/// Returns the bytes of a Bytes value or an external reference, fetching the
+/// latter through the resolver and checking its length and hash.
+pub fn resolve_value(value: &HtlvValue, resolver: &dyn BlobResolver) -> Result<Bytes> {
+    match value {
+        HtlvValue::Bytes(data) => Ok(data.clone()),
+        HtlvValue::ExternalRef { locator, length, hash } => {
+            let data = resolver.fetch(locator)?;
+            if data.len() as u64 != *length {
+                return Err(Error::CodecError(format!(
+                    "External blob '{}' has {} bytes, the reference expects {}",
+                    locator,
+                    data.len(),
+                    length
+                )));
+            }
+            if blake3::hash(&data).as_bytes() != hash {
+                return Err(Error::CodecError(format!("External blob '{}' does not match its hash", locator)));
+            }
+            Ok(data)
+        }
+        other => Err(Error::CodecError(format!("Expected Bytes or ExternalRef value, got {:?}", other.value_type()))),
+    }
+}
+
+/// Returns a copy of the item with every external reference in the tree
+/// replaced by the Bytes value it refers to.
+pub fn resolve_item(item: &HtlvItem, resolver: &dyn BlobResolver) -> Result<HtlvItem> {
+    let value = match &item.value {
+        HtlvValue::ExternalRef { .. } => HtlvValue::Bytes(resolve_value(&item.value, resolver)?),
+        HtlvValue::Array(items) => {
+            HtlvValue::Array(items.iter().map(|item| resolve_item(item, resolver)).collect::<Result<_>>()?)
+        }
+        HtlvValue::Object(items) => {
+            HtlvValue::Object(items.iter().map(|item| resolve_item(item, resolver)).collect::<Result<_>>()?)
+        }
+        value => value.clone(),
+    };
+    Ok(HtlvItem::new(item.tag, value))
+}
+
+/// A `BlobResolver` keeping blobs in memory, for tests and local caches.
+#[derive(Default)]
+pub struct MemoryBlobStore {
+    blobs: RwLock<HashMap<String, Bytes>>,
+}
+
+impl fmt::Debug for MemoryBlobStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let blobs = self.blobs.read().map(|blobs| blobs.len()).unwrap_or(0);
+        f.debug_struct("MemoryBlobStore").field("blobs", &blobs).finish()
+    }
+}
+
+impl MemoryBlobStore {
+    /// Creates an empty store.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores the bytes under the locator and returns a reference to them.
+    pub fn put(&self, locator: &str, data: Bytes) -> HtlvValue {
+        let reference = external_ref(locator, &data);
+        if let Ok(mut blobs) = self.blobs.write() {
+            blobs.insert(locator.to_string(), data);
+        }
+        reference
+    }
+}
+
+impl BlobResolver for MemoryBlobStore {
+    fn fetch(&self, locator: &str) -> Result<Bytes> {
+        let blobs = self
+            .blobs
+            .read()
+            .map_err(|_| Error::CodecError("Failed to acquire lock on blob store".to_string()))?;
+        blobs
+            .get(locator)
+            .cloned()
+            .ok_or_else(|| Error::CodecError(format!("External blob '{}' not found", locator)))
+    }
+}
+
+/// Appends the payload of an external reference.
+pub(crate) fn encode_payload(locator: &str, length: u64, hash: &[u8; 32], out: &mut Vec<u8>) {
+    out.extend_from_slice(hash);
+    out.extend_from_slice(&length.to_le_bytes());
+    out.extend_from_slice(locator.as_bytes());
+}
+
+/// Returns the length of the payload of an external reference.
+pub(crate) fn payload_len(locator: &str) -> usize {
+    EXTERNAL_REF_HEADER_LEN + locator.len()
+}
+
+/// Decodes the payload of an external reference.
+pub(crate) fn decode_payload(data: &[u8]) -> Result<HtlvValue> {
+    if data.len() < EXTERNAL_REF_HEADER_LEN {
+        return Err(Error::CodecError(format!("Invalid length for ExternalRef value: {}", data.len())));
+    }
+    let (hash, rest) = data.split_at(32);
+    let (length, locator) = rest.split_at(8);
+    let locator = std::str::from_utf8(locator)
+        .map_err(|e| Error::CodecError(format!("Invalid UTF-8 sequence for ExternalRef locator: {}", e)))?;
+    Ok(HtlvValue::ExternalRef {
+        locator: locator.to_string(),
+        length: u64::from_le_bytes(length.try_into().unwrap_or_default()),
+        hash: hash.try_into().unwrap_or_default(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode::decode_item;
+    use crate::codec::encode::encode_item;
+    use crate::codec::wire::{decode_item_with_format, encode_item_with_format, WireFormat};
+
+    #[test]
+    fn test_reference_round_trip_and_resolution() {
+        let store = MemoryBlobStore::new();
+        let blob = Bytes::from(vec![9u8; 100_000]);
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::String(Bytes::from("weights.bin"))),
+            HtlvItem::new(3, store.put("s3://models/weights.bin", blob.clone())),
+        ]));
+
+        let encoded = encode_item(&item).unwrap();
+        assert!(encoded.len() < 100);
+        assert_eq!(decode_item(&encoded).unwrap(), (item.clone(), encoded.len()));
+        let v2 = encode_item_with_format(&item, WireFormat::V2).unwrap();
+        assert_eq!(decode_item_with_format(&v2, WireFormat::V2).unwrap().0, item);
+
+        let resolved = resolve_item(&item, &store).unwrap();
+        let HtlvValue::Object(fields) = &resolved.value else { unreachable!() };
+        assert_eq!(fields[1].value, HtlvValue::Bytes(blob));
+    }
+
+    #[test]
+    fn test_resolution_checks_blob() {
+        let reference = external_ref("blob", b"original");
+        let tampered = |_: &str| Ok(Bytes::from_static(b"modified"));
+        let err = resolve_value(&reference, &tampered).unwrap_err();
+        assert_eq!(err.to_string(), "Codec Error: External blob 'blob' does not match its hash");
+        let truncated = |_: &str| Ok(Bytes::from_static(b"orig"));
+        assert!(resolve_value(&reference, &truncated).is_err());
+        assert!(resolve_value(&reference, &MemoryBlobStore::new()).is_err());
+        assert!(decode_payload(&[0u8; 39]).is_err());
+    }
+}
@@ -0,0 +1,7 @@
+// Bridges between HTLV and other serialization formats
+//
+// Consumers that cannot speak HTLV get the same data in a format they know.
+// Each bridge converts both ways without losing types or tags, and fails
+// rather than dropping what the other format cannot represent.
+
+pub mod msgpack; // HTLV <-> MessagePack
@@ -0,0 +1,344 @@
+// MessagePack bridge
+//
+// An HTLV byte stream, a sequence of encoded items, converts to a MessagePack
+// map from the tag of each item to its value, in stream order. Objects become
+// maps keyed by tag the same way, and Arrays become MessagePack arrays. Every
+// value is written in the MessagePack format of its exact HTLV type: a U16 is
+// always a uint 16, even when it would fit a fixint, and Extension values are
+// ext values with the same type byte. Converting back therefore yields the
+// same types, tags and order, and re-encodes to the same HTLV bytes. Note that
+// the decoder reads items of the batch-decoded numeric types, such as U16 or
+// F64, as Arrays even when they hold a single value, as that is how packed
+// arrays are encoded; such items become one-element MessagePack arrays.
+//
+// MessagePack written by other libraries uses the smallest format for each
+// number; positive fixints read as U8 and negative fixints as I8. What one
+// side cannot represent fails the conversion instead of being dropped:
+// ExternalRef values, Array items with a non-zero tag, map keys that are not
+// unsigned integers and ext types outside `EXTENSION_TYPE_RANGE`.
+
+use bytes::Bytes;
+
+use crate::codec::decode::decode_all;
+use crate::codec::decode::decoder_state_machine::MAX_NESTING_DEPTH;
+use crate::codec::encode::encode_item;
+use crate::codec::types::{HtlvItem, HtlvValue, EXTENSION_TYPE_RANGE};
+use crate::internal::cursor::WireCursor;
+use crate::internal::error::{Error, Result};
+
+/// Converts an HTLV byte stream to a MessagePack map from tags to values.
+pub fn htlv_to_msgpack(data: &[u8]) -> Result<Vec<u8>> {
+    let items = decode_all(data)?;
+    let mut out = Vec::new();
+    write_map(&items, &mut out)?;
+    Ok(out)
+}
+
+/// Converts a MessagePack map from tags to values to an HTLV byte stream.
+pub fn msgpack_to_htlv(data: &[u8]) -> Result<Vec<u8>> {
+    let (value, bytes_read) = value_from_msgpack(data)?;
+    if bytes_read != data.len() {
+        return Err(Error::CodecError(format!(
+            "{} trailing bytes after the MessagePack map",
+            data.len() - bytes_read
+        )));
+    }
+    let HtlvValue::Object(items) = value else {
+        return Err(Error::CodecError(format!(
+            "Expected a MessagePack map of tags to values, found {:?}",
+            value.value_type()
+        )));
+    };
+
+    let mut out = Vec::new();
+    for item in &items {
+        out.extend_from_slice(&encode_item(item)?);
+    }
+    Ok(out)
+}
+
+/// Appends the MessagePack encoding of a value.
+pub fn value_to_msgpack(value: &HtlvValue, out: &mut Vec<u8>) -> Result<()> {
+    match value {
+        HtlvValue::Null => out.push(0xc0),
+        HtlvValue::Bool(value) => out.push(if *value { 0xc3 } else { 0xc2 }),
+        HtlvValue::U8(value) => write_marked(out, 0xcc, &value.to_be_bytes()),
+        HtlvValue::U16(value) => write_marked(out, 0xcd, &value.to_be_bytes()),
+        HtlvValue::U32(value) => write_marked(out, 0xce, &value.to_be_bytes()),
+        HtlvValue::U64(value) => write_marked(out, 0xcf, &value.to_be_bytes()),
+        HtlvValue::I8(value) => write_marked(out, 0xd0, &value.to_be_bytes()),
+        HtlvValue::I16(value) => write_marked(out, 0xd1, &value.to_be_bytes()),
+        HtlvValue::I32(value) => write_marked(out, 0xd2, &value.to_be_bytes()),
+        HtlvValue::I64(value) => write_marked(out, 0xd3, &value.to_be_bytes()),
+        HtlvValue::F32(value) => write_marked(out, 0xca, &value.to_be_bytes()),
+        HtlvValue::F64(value) => write_marked(out, 0xcb, &value.to_be_bytes()),
+        HtlvValue::Bytes(bytes) => {
+            write_length(out, bytes.len(), [0xc4, 0xc5, 0xc6], "Bytes value")?;
+            out.extend_from_slice(bytes);
+        }
+        HtlvValue::String(bytes) => {
+            if bytes.len() < 32 {
+                out.push(0xa0 | bytes.len() as u8);
+            } else {
+                write_length(out, bytes.len(), [0xd9, 0xda, 0xdb], "String value")?;
+            }
+            out.extend_from_slice(bytes);
+        }
+        HtlvValue::Array(items) => {
+            if items.len() < 16 {
+                out.push(0x90 | items.len() as u8);
+            } else {
+                write_length(out, items.len(), [0, 0xdc, 0xdd], "Array")?;
+            }
+            for item in items {
+                if item.tag != 0 {
+                    return Err(Error::CodecError(format!(
+                        "Array item tag {} has no MessagePack equivalent",
+                        item.tag
+                    )));
+                }
+                value_to_msgpack(&item.value, out)?;
+            }
+        }
+        HtlvValue::Object(items) => write_map(items, out)?,
+        HtlvValue::Extension(type_byte, payload) => {
+            match payload.len() {
+                1 => out.push(0xd4),
+                2 => out.push(0xd5),
+                4 => out.push(0xd6),
+                8 => out.push(0xd7),
+                16 => out.push(0xd8),
+                len => write_length(out, len, [0xc7, 0xc8, 0xc9], "Extension value")?,
+            }
+            out.push(*type_byte);
+            out.extend_from_slice(payload);
+        }
+        HtlvValue::ExternalRef { .. } => {
+            return Err(Error::CodecError(
+                "ExternalRef values have no MessagePack equivalent; resolve them first".to_string(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Reads one MessagePack value, returning it and the number of bytes read.
+pub fn value_from_msgpack(data: &[u8]) -> Result<(HtlvValue, usize)> {
+    let mut cursor = WireCursor::new(data);
+    let value = read_value(&mut cursor, 0)?;
+    Ok((value, cursor.position()))
+}
+
+/// Writes items as a map from their tags to their values.
+fn write_map(items: &[HtlvItem], out: &mut Vec<u8>) -> Result<()> {
+    if items.len() < 16 {
+        out.push(0x80 | items.len() as u8);
+    } else {
+        write_length(out, items.len(), [0, 0xde, 0xdf], "Object")?;
+    }
+    for item in items {
+        write_tag(item.tag, out);
+        value_to_msgpack(&item.value, out)?;
+    }
+    Ok(())
+}
+
+/// Writes a tag as the smallest unsigned integer format holding it.
+fn write_tag(tag: u64, out: &mut Vec<u8>) {
+    match tag {
+        0..=0x7f => out.push(tag as u8),
+        0x80..=0xff => write_marked(out, 0xcc, &[tag as u8]),
+        0x100..=0xffff => write_marked(out, 0xcd, &(tag as u16).to_be_bytes()),
+        0x1_0000..=0xffff_ffff => write_marked(out, 0xce, &(tag as u32).to_be_bytes()),
+        _ => write_marked(out, 0xcf, &tag.to_be_bytes()),
+    }
+}
+
+/// Writes a format marker followed by its big-endian payload.
+fn write_marked(out: &mut Vec<u8>, marker: u8, payload: &[u8]) {
+    out.push(marker);
+    out.extend_from_slice(payload);
+}
+
+/// Writes a length with the 8, 16 or 32-bit marker of `markers`; a zero
+/// marker means the format has no 8-bit length.
+fn write_length(out: &mut Vec<u8>, len: usize, markers: [u8; 3], what: &str) -> Result<()> {
+    match len {
+        0..=0xff if markers[0] != 0 => write_marked(out, markers[0], &[len as u8]),
+        0..=0xffff => write_marked(out, markers[1], &(len as u16).to_be_bytes()),
+        _ => {
+            let len = u32::try_from(len).map_err(|_| {
+                Error::CodecError(format!("{} of length {} is too long for MessagePack", what, len))
+            })?;
+            write_marked(out, markers[2], &len.to_be_bytes())
+        }
+    }
+    Ok(())
+}
+
+fn read_value(cursor: &mut WireCursor<'_>, depth: usize) -> Result<HtlvValue> {
+    let marker = cursor.read_u8("MessagePack marker")?;
+    let value = match marker {
+        0x00..=0x7f => HtlvValue::U8(marker),
+        0xe0..=0xff => HtlvValue::I8(marker as i8),
+        0xc0 => HtlvValue::Null,
+        0xc2 => HtlvValue::Bool(false),
+        0xc3 => HtlvValue::Bool(true),
+        0xcc => HtlvValue::U8(cursor.read_u8("uint 8")?),
+        0xcd => HtlvValue::U16(u16::from_be_bytes(cursor.read_array("uint 16")?)),
+        0xce => HtlvValue::U32(u32::from_be_bytes(cursor.read_array("uint 32")?)),
+        0xcf => HtlvValue::U64(u64::from_be_bytes(cursor.read_array("uint 64")?)),
+        0xd0 => HtlvValue::I8(cursor.read_u8("int 8")? as i8),
+        0xd1 => HtlvValue::I16(i16::from_be_bytes(cursor.read_array("int 16")?)),
+        0xd2 => HtlvValue::I32(i32::from_be_bytes(cursor.read_array("int 32")?)),
+        0xd3 => HtlvValue::I64(i64::from_be_bytes(cursor.read_array("int 64")?)),
+        0xca => HtlvValue::F32(f32::from_be_bytes(cursor.read_array("float 32")?)),
+        0xcb => HtlvValue::F64(f64::from_be_bytes(cursor.read_array("float 64")?)),
+        0xc4..=0xc6 => {
+            let len = read_length(cursor, marker - 0xc4, "bin length")?;
+            HtlvValue::Bytes(Bytes::copy_from_slice(cursor.take(len, "bin data")?))
+        }
+        0xa0..=0xbf | 0xd9..=0xdb => {
+            let len = match marker {
+                0xa0..=0xbf => (marker & 0x1f) as u64,
+                _ => read_length(cursor, marker - 0xd9, "str length")?,
+            };
+            HtlvValue::String(Bytes::copy_from_slice(cursor.take(len, "str data")?))
+        }
+        0x90..=0x9f | 0xdc | 0xdd => {
+            let len = match marker {
+                0x90..=0x9f => (marker & 0x0f) as u64,
+                _ => read_length(cursor, marker - 0xdb, "array length")?,
+            };
+            check_depth(depth)?;
+            let mut items = Vec::new();
+            for _ in 0..len {
+                items.push(HtlvItem::new(0, read_value(cursor, depth + 1)?));
+            }
+            HtlvValue::Array(items)
+        }
+        0x80..=0x8f | 0xde | 0xdf => {
+            let len = match marker {
+                0x80..=0x8f => (marker & 0x0f) as u64,
+                _ => read_length(cursor, marker - 0xdd, "map length")?,
+            };
+            check_depth(depth)?;
+            let mut items = Vec::new();
+            for _ in 0..len {
+                let tag = read_tag(cursor)?;
+                items.push(HtlvItem::new(tag, read_value(cursor, depth + 1)?));
+            }
+            HtlvValue::Object(items)
+        }
+        0xd4..=0xd8 | 0xc7..=0xc9 => {
+            let len = match marker {
+                0xd4..=0xd8 => 1u64 << (marker - 0xd4),
+                _ => read_length(cursor, marker - 0xc7, "ext length")?,
+            };
+            let type_byte = cursor.read_u8("ext type")?;
+            if !EXTENSION_TYPE_RANGE.contains(&type_byte) {
+                return Err(Error::CodecError(format!(
+                    "MessagePack ext type {} is outside the extension type range",
+                    type_byte as i8
+                )));
+            }
+            HtlvValue::Extension(type_byte, Bytes::copy_from_slice(cursor.take(len, "ext data")?))
+        }
+        _ => return Err(Error::CodecError(format!("Invalid MessagePack marker {:#04x}", marker))),
+    };
+    Ok(value)
+}
+
+/// Reads a big-endian length of 1, 2 or 4 bytes for `width` 0, 1 or 2.
+fn read_length(cursor: &mut WireCursor<'_>, width: u8, what: &str) -> Result<u64> {
+    Ok(match width {
+        0 => cursor.read_u8(what)? as u64,
+        1 => u16::from_be_bytes(cursor.read_array(what)?) as u64,
+        _ => u32::from_be_bytes(cursor.read_array(what)?) as u64,
+    })
+}
+
+/// Reads a map key, which must be an unsigned integer to be a tag.
+fn read_tag(cursor: &mut WireCursor<'_>) -> Result<u64> {
+    let marker = cursor.read_u8("map key")?;
+    Ok(match marker {
+        0x00..=0x7f => marker as u64,
+        0xcc => cursor.read_u8("map key")? as u64,
+        0xcd => u16::from_be_bytes(cursor.read_array("map key")?) as u64,
+        0xce => u32::from_be_bytes(cursor.read_array("map key")?) as u64,
+        0xcf => u64::from_be_bytes(cursor.read_array("map key")?),
+        _ => {
+            return Err(Error::CodecError(format!(
+                "MessagePack map key with marker {:#04x} is not a tag; keys must be unsigned integers",
+                marker
+            )));
+        }
+    })
+}
+
+fn check_depth(depth: usize) -> Result<()> {
+    if depth >= MAX_NESTING_DEPTH {
+        return Err(Error::CodecError(format!("Maximum nesting depth ({}) exceeded", MAX_NESTING_DEPTH)));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_stream() -> Vec<u8> {
+        let items = [
+            HtlvItem::new(1, HtlvValue::U8(7)),
+            HtlvItem::new(300, HtlvValue::Object(vec![
+                HtlvItem::new(2, HtlvValue::I8(-3)),
+                HtlvItem::new(3, HtlvValue::F64(0.5)),
+                HtlvItem::new(4, HtlvValue::String(Bytes::from_static(b"sensor"))),
+                HtlvItem::new(5, HtlvValue::Bytes(Bytes::from(vec![9u8; 300]))),
+                HtlvItem::new(6, HtlvValue::Extension(0x40, Bytes::from_static(b"\x01\x02"))),
+                HtlvItem::new(7, HtlvValue::Null),
+                HtlvItem::new(8, HtlvValue::U16(9)),
+            ])),
+            HtlvItem::new(2, HtlvValue::Array((0..20).map(|i| HtlvItem::new(0, HtlvValue::U32(i))).collect())),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::Bool(true)),
+                HtlvItem::new(0, HtlvValue::I64(i64::MIN)),
+            ])),
+        ];
+        items.iter().flat_map(|item| encode_item(item).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_round_trip_keeps_types_and_tags() {
+        let stream = sample_stream();
+        let packed = htlv_to_msgpack(&stream).unwrap();
+        assert_eq!(&packed[..4], &[0x84, 0x01, 0xcc, 0x07]);
+        assert_eq!(msgpack_to_htlv(&packed).unwrap(), stream);
+    }
+
+    #[test]
+    fn test_foreign_msgpack_and_unrepresentable_values() {
+        // {1: 5, 2: -1, 3: [true, "hi"]} as written by a typical MessagePack library
+        let foreign = [0x83, 0x01, 0x05, 0x02, 0xff, 0x03, 0x92, 0xc3, 0xa2, b'h', b'i'];
+        let (value, bytes_read) = value_from_msgpack(&foreign).unwrap();
+        assert_eq!(bytes_read, foreign.len());
+        assert_eq!(value, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::U8(5)),
+            HtlvItem::new(2, HtlvValue::I8(-1)),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::Bool(true)),
+                HtlvItem::new(0, HtlvValue::String(Bytes::from_static(b"hi"))),
+            ])),
+        ]));
+
+        let err = msgpack_to_htlv(&[0x81, 0xa1, b'k', 0x01]).unwrap_err();
+        assert!(err.to_string().contains("keys must be unsigned integers"));
+        assert!(msgpack_to_htlv(&[0x92, 0x01, 0x02]).is_err());
+        assert!(msgpack_to_htlv(&[0x81, 0x01, 0xd4, 0xff, 0x00]).is_err());
+        assert!(msgpack_to_htlv(&[0x81, 0x01, 0xcd, 0x00]).is_err());
+
+        let tagged = HtlvValue::Array(vec![HtlvItem::new(4, HtlvValue::Null)]);
+        let err = value_to_msgpack(&tagged, &mut Vec::new()).unwrap_err();
+        assert_eq!(err.to_string(), "Codec Error: Array item tag 4 has no MessagePack equivalent");
+    }
+}
@@ -5,6 +5,18 @@ pub mod decode;
 pub mod rcu;
 pub mod varint;
 pub mod types;
+pub mod stats;
+pub mod wire;
+pub mod tag_table;
+pub mod tag_space;
+pub mod convert; // Rust values <-> HtlvValue, used by the Encode/Decode derives
+pub mod extension; // Registry of application-defined extension value types
+pub mod external; // Bytes values stored outside the packet and resolved on access
+pub mod transform; // Schema-driven per-field transforms such as field compression
+pub mod interop; // Conversion to and from other serialization formats
+
+#[cfg(feature = "derive")]
+pub use tonitru_derive::{Decode, Encode};
 
 use crate::internal::error::Result;
 use bytes::BytesMut;
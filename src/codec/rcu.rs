@@ -1,22 +1,24 @@
 use std::sync::Arc;
-use std::sync::atomic::{AtomicPtr, Ordering};
+use std::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
 use std::mem; // Import the mem module
 
-/// A basic Read-Copy-Update (RCU) prototype for lock-free reads.
+/// A basic Read-Copy-Update (RCU) cell for lock-free reads.
 ///
 /// This implementation allows multiple readers to access the data concurrently
 /// with a single writer. Reads are lock-free. Updates involve copying the data
 /// and atomically swapping a pointer.
 ///
-/// NOTE: This is a simplified prototype and does NOT include a grace period
-/// for safe memory reclamation of old data versions. In a real-world RCU,
-/// a mechanism is needed to ensure all readers of an old version have finished
-/// before that version's memory is deallocated.
+/// Old versions are reclaimed after a simple grace period: readers announce
+/// themselves in a counter while they take their reference, and an update
+/// waits until no reader is in that window before releasing the old pointer.
+/// Readers that already hold an `Arc` keep their version alive as usual.
 pub struct Rcu<T> {
     // Atomic pointer to the current data.
     // We use a raw pointer inside AtomicPtr because AtomicPtr works with raw pointers.
     // The data itself is managed by Arc for shared ownership.
     data: AtomicPtr<T>,
+    // Number of readers between loading the pointer and taking their reference
+    readers: AtomicUsize,
 }
 
 // Safety: Rcu is Send and Sync if T is Send and Sync.
@@ -34,6 +36,7 @@ impl<T> Rcu<T> {
 
         Rcu {
             data: AtomicPtr::new(raw_ptr),
+            readers: AtomicUsize::new(0),
         }
     }
 
@@ -42,8 +45,10 @@ impl<T> Rcu<T> {
     /// Returns an `Arc` to the data, ensuring the data remains valid
     /// as long as the `Arc` is held.
     pub fn read(&self) -> Arc<T> {
-        // Atomically load the current pointer.
-        let raw_ptr = self.data.load(Ordering::Acquire);
+        // Announce the read before loading the pointer, so that an update
+        // swapping the pointer afterwards waits for us.
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        let raw_ptr = self.data.load(Ordering::SeqCst);
 
         // Convert the raw pointer back to an Arc.
         // This increments the Arc's reference count.
@@ -54,14 +59,15 @@ impl<T> Rcu<T> {
         let cloned_arc = Arc::clone(&arc_data);
         mem::forget(arc_data); // Prevent decrementing ref count
 
+        self.readers.fetch_sub(1, Ordering::SeqCst);
         cloned_arc
     }
 
     /// Updates the data. This involves creating a new copy and atomically
     /// swapping the pointer.
     ///
-    /// NOTE: This prototype does NOT handle the safe deallocation of the
-    /// old data version.
+    /// The old version is released once no reader can still be taking a
+    /// reference to it; this may spin briefly under heavy read load.
     pub fn update(&self, new_data: T) {
         // Create a new Arc for the new data.
         let new_arc_data = Arc::new(new_data);
@@ -69,17 +75,15 @@ impl<T> Rcu<T> {
 
         // Atomically swap the pointer.
         // The old raw pointer is returned.
-        let old_raw_ptr = self.data.swap(new_raw_ptr, Ordering::Release);
-
-        // In a real RCU, the old_raw_ptr would be passed to a grace period
-        // mechanism for eventual deallocation. In this prototype, we just
-        // forget it, which will lead to a memory leak for the old data.
-        // DO NOT use this RCU prototype in production where updates occur!
-        let old_arc_data = unsafe { Arc::from_raw(old_raw_ptr) };
-        mem::forget(old_arc_data); // Leak the old Arc
-    }
+        let old_raw_ptr = self.data.swap(new_raw_ptr, Ordering::SeqCst);
 
-    // TODO: Implement a proper grace period mechanism for safe memory reclamation.
+        // Grace period: readers that loaded the old pointer have announced
+        // themselves before doing so and leave once they hold their own Arc.
+        while self.readers.load(Ordering::SeqCst) != 0 {
+            std::hint::spin_loop();
+        }
+        drop(unsafe { Arc::from_raw(old_raw_ptr) });
+    }
 }
 
 impl<T> Drop for Rcu<T> {
@@ -118,9 +122,7 @@ mod tests {
         let data2 = rcu.read();
         assert_eq!(*data2, 20);
 
-        // Old read should still see old data (due to Arc, but memory is leaked in this proto)
-        // In a real RCU, data1 would still be valid until a grace period passes.
-        // With this prototype's leak, data1 remains valid but its memory isn't reclaimed.
+        // Old read should still see old data; its Arc keeps the old version alive
         assert_eq!(*data1, 10);
     }
 
@@ -155,9 +157,9 @@ mod tests {
         // Allow more reads to happen
         thread::sleep(Duration::from_millis(50));
 
-        // Update data again (this will leak the 200 data)
-        println!("Main thread updating data to 300 (will leak 200)");
-        rcu.update(300); // This update will leak the previous version (200)
+        // Update data again
+        println!("Main thread updating data to 300");
+        rcu.update(300);
 
         // Wait for reader threads to finish
         for handle in handles {
@@ -167,9 +169,19 @@ mod tests {
         // Final read from main thread
         let final_data = rcu.read();
         assert_eq!(*final_data, 300);
+    }
 
-        // Note: This test demonstrates concurrent reads and updates, but
-        // the memory for the '100' and '200' data versions is leaked
-        // because the prototype lacks a grace period and safe reclamation.
+    #[test]
+    fn test_rcu_releases_old_versions() {
+        let rcu = Rcu::new(Arc::new(1));
+        let first = Arc::clone(&*rcu.read());
+        let held = rcu.read();
+        rcu.update(Arc::new(2));
+
+        // The version is still held by a reader, then released with it
+        assert_eq!(Arc::strong_count(&first), 2);
+        drop(held);
+        assert_eq!(Arc::strong_count(&first), 1);
+        assert_eq!(**rcu.read(), 2);
     }
 }
@@ -0,0 +1,312 @@
+// Document statistics for HTLV data
+//
+// `DocumentStats` summarizes the shape of a document: item counts per value
+// type, nesting depth, the largest field and how the serialized size splits
+// into item headers and payload. It is used by the CLI `inspect` command and
+// by capacity-planning tooling.
+
+use crate::internal::error::Result;
+use crate::codec::external;
+use crate::codec::varint;
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use crate::codec::encode::{LARGE_FIELD_THRESHOLD, TOTAL_LENGTH_HEADER_LEN};
+use crate::codec::decode::pull::{PullDecoder, PullEvent};
+use std::fmt;
+
+/// Number of value types: the core type bytes are 0 to 16, and extension types
+/// share the last slot.
+const VALUE_TYPE_COUNT: usize = 18;
+const EXTENSION_SLOT: usize = VALUE_TYPE_COUNT - 1;
+
+/// The largest basic field of a document.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LargestField {
+    /// The field tag
+    pub tag: u64,
+    /// The field value type
+    pub value_type: HtlvValueType,
+    /// The size of the field value in bytes
+    pub size: usize,
+}
+
+/// Statistics describing the shape and serialized size of a document.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DocumentStats {
+    /// Number of items per value type, indexed by type byte
+    type_counts: [usize; VALUE_TYPE_COUNT],
+    /// Maximum nesting depth; a document with a single basic item has depth 1
+    pub max_depth: usize,
+    /// The largest basic field, if the document has any
+    pub largest_field: Option<LargestField>,
+    /// Serialized bytes spent on item headers (Tag + Type + Length)
+    pub header_bytes: usize,
+    /// Serialized bytes spent on basic values
+    pub payload_bytes: usize,
+}
+
+impl Default for DocumentStats {
+    fn default() -> Self {
+        DocumentStats {
+            type_counts: [0; VALUE_TYPE_COUNT],
+            max_depth: 0,
+            largest_field: None,
+            header_bytes: 0,
+            payload_bytes: 0,
+        }
+    }
+}
+
+impl DocumentStats {
+    /// Collects statistics from encoded bytes without decoding them into items.
+    ///
+    /// The bytes may hold several top-level items. Statistics are gathered per wire
+    /// item, so a large Bytes or String value sharded by the encoder counts as a
+    /// header item plus one item per shard.
+    pub fn from_encoded(data: &[u8]) -> Result<Self> {
+        let mut stats = DocumentStats::default();
+        let mut decoder = PullDecoder::new(data);
+
+        loop {
+            let start = decoder.offset();
+            match decoder.next_event()? {
+                None => break,
+                Some(PullEvent::BeginObject(_)) => {
+                    stats.record_container(HtlvValueType::Object, decoder.depth(), decoder.offset() - start);
+                }
+                Some(PullEvent::BeginArray(_)) => {
+                    stats.record_container(HtlvValueType::Array, decoder.depth(), decoder.offset() - start);
+                }
+                Some(PullEvent::EndObject) | Some(PullEvent::EndArray) => {}
+                Some(PullEvent::Field(tag, value_type, value)) => {
+                    let header = decoder.offset() - start - value.len();
+                    stats.record_field(tag, value_type, decoder.depth() + 1, header, value.len());
+                }
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Returns the number of items of the given value type.
+    pub fn count(&self, value_type: HtlvValueType) -> usize {
+        self.type_counts[slot(value_type)]
+    }
+
+    /// Returns the total number of items.
+    pub fn total_items(&self) -> usize {
+        self.type_counts.iter().sum()
+    }
+
+    /// Returns the number of items per value type, omitting types that do not occur.
+    pub fn type_histogram(&self) -> Vec<(HtlvValueType, usize)> {
+        self.type_counts
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .filter_map(|(slot, &count)| {
+                let value_type = match slot {
+                    EXTENSION_SLOT => Some(HtlvValueType::Extension),
+                    byte => HtlvValueType::from_byte(byte as u8),
+                };
+                value_type.map(|t| (t, count))
+            })
+            .collect()
+    }
+
+    /// Returns the total serialized size in bytes.
+    pub fn serialized_size(&self) -> usize {
+        self.header_bytes + self.payload_bytes
+    }
+
+    fn record_container(&mut self, value_type: HtlvValueType, depth: usize, header: usize) {
+        self.type_counts[slot(value_type)] += 1;
+        self.max_depth = self.max_depth.max(depth);
+        self.header_bytes += header;
+    }
+
+    fn record_field(&mut self, tag: u64, value_type: HtlvValueType, depth: usize, header: usize, size: usize) {
+        self.type_counts[slot(value_type)] += 1;
+        self.max_depth = self.max_depth.max(depth);
+        self.header_bytes += header;
+        self.payload_bytes += size;
+        if self.largest_field.is_none_or(|largest| size > largest.size) {
+            self.largest_field = Some(LargestField { tag, value_type, size });
+        }
+    }
+
+    /// Records an item and its descendants, returning the encoded length of the item.
+    fn record_item(&mut self, item: &HtlvItem, depth: usize) -> usize {
+        let value_type = item.value.value_type();
+        let tag_len = varint::encode_varint(item.tag).len();
+
+        match &item.value {
+            HtlvValue::Array(items) | HtlvValue::Object(items) => {
+                self.type_counts[slot(value_type)] += 1;
+                self.max_depth = self.max_depth.max(depth);
+                let body: usize = items.iter().map(|sub_item| self.record_item(sub_item, depth + 1)).sum();
+                let header = tag_len + 1 + varint::encode_varint(body as u64).len();
+                self.header_bytes += header;
+                header + body
+            }
+            value => {
+                let size = basic_value_size(value);
+                self.record_field(item.tag, value_type, depth, 0, size);
+
+                // Mirror the sharding of large Bytes and String values by the encoder
+                if size > LARGE_FIELD_THRESHOLD {
+                    let total_length_len = TOTAL_LENGTH_HEADER_LEN as usize;
+                    let mut headers = tag_len + 1 + varint::encode_varint(TOTAL_LENGTH_HEADER_LEN).len();
+                    for shard_start in (0..size).step_by(LARGE_FIELD_THRESHOLD) {
+                        let shard_len = (size - shard_start).min(LARGE_FIELD_THRESHOLD);
+                        headers += tag_len + 1 + varint::encode_varint(shard_len as u64).len();
+                    }
+                    self.header_bytes += headers;
+                    self.payload_bytes += total_length_len;
+                    headers + total_length_len + size
+                } else {
+                    let header = tag_len + 1 + varint::encode_varint(size as u64).len();
+                    self.header_bytes += header;
+                    header + size
+                }
+            }
+        }
+    }
+}
+
+/// Returns the index of a value type in the type counts.
+fn slot(value_type: HtlvValueType) -> usize {
+    match value_type {
+        HtlvValueType::Extension => EXTENSION_SLOT,
+        core => core as usize,
+    }
+}
+
+/// Returns the encoded size of a basic value.
+fn basic_value_size(value: &HtlvValue) -> usize {
+    match value {
+        HtlvValue::Null => 0,
+        HtlvValue::Bool(_) | HtlvValue::U8(_) | HtlvValue::I8(_) => 1,
+        HtlvValue::U16(_) | HtlvValue::I16(_) => 2,
+        HtlvValue::U32(_) | HtlvValue::I32(_) | HtlvValue::F32(_) => 4,
+        HtlvValue::U64(_) | HtlvValue::I64(_) | HtlvValue::F64(_) => 8,
+        HtlvValue::Bytes(v) | HtlvValue::String(v) | HtlvValue::Extension(_, v) => v.len(),
+        HtlvValue::ExternalRef { locator, .. } => external::payload_len(locator),
+        HtlvValue::Array(_) | HtlvValue::Object(_) => 0,
+    }
+}
+
+impl HtlvItem {
+    /// Collects statistics about the item and its descendants.
+    ///
+    /// Sizes are those produced by `encode_item`, including the sharding of large
+    /// Bytes and String values, which are counted as a single item here.
+    pub fn stats(&self) -> DocumentStats {
+        let mut stats = DocumentStats::default();
+        stats.record_item(self, 1);
+        stats
+    }
+}
+
+impl fmt::Display for DocumentStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Items: {}", self.total_items())?;
+        for (value_type, count) in self.type_histogram() {
+            writeln!(f, "  {:?}: {}", value_type, count)?;
+        }
+        writeln!(f, "Max depth: {}", self.max_depth)?;
+        match self.largest_field {
+            Some(largest) => writeln!(
+                f,
+                "Largest field: tag {} ({:?}, {} bytes)",
+                largest.tag, largest.value_type, largest.size
+            )?,
+            None => writeln!(f, "Largest field: none")?,
+        }
+        write!(
+            f,
+            "Serialized size: {} bytes ({} header, {} payload)",
+            self.serialized_size(),
+            self.header_bytes,
+            self.payload_bytes
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::encode_item;
+    use bytes::Bytes;
+
+    fn sample() -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U32(1)),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::U8(1)),
+                HtlvItem::new(0, HtlvValue::U8(2)),
+            ])),
+            HtlvItem::new(300, HtlvValue::String(Bytes::from_static(b"hello world"))),
+        ]))
+    }
+
+    #[test]
+    fn test_item_stats() {
+        let stats = sample().stats();
+        assert_eq!(stats.total_items(), 6);
+        assert_eq!(stats.count(HtlvValueType::U8), 2);
+        assert_eq!(stats.count(HtlvValueType::Object), 1);
+        assert_eq!(stats.type_histogram().len(), 5);
+        assert_eq!(stats.max_depth, 3);
+        assert_eq!(
+            stats.largest_field,
+            Some(LargestField { tag: 300, value_type: HtlvValueType::String, size: 11 })
+        );
+        assert_eq!(stats.payload_bytes, 4 + 1 + 1 + 11);
+        assert_eq!(stats.serialized_size(), encode_item(&sample()).unwrap().len());
+    }
+
+    #[test]
+    fn test_encoded_stats_match_item_stats() {
+        let encoded = encode_item(&sample()).unwrap();
+        assert_eq!(DocumentStats::from_encoded(&encoded).unwrap(), sample().stats());
+    }
+
+    #[test]
+    fn test_extension_and_external_values() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::Extension(0x41, Bytes::from_static(b"payload"))),
+            HtlvItem::new(3, crate::codec::external::external_ref("blob", b"data")),
+        ]));
+        let stats = item.stats();
+        assert_eq!(stats.count(HtlvValueType::Extension), 1);
+        assert_eq!(stats.count(HtlvValueType::ExternalRef), 1);
+        assert_eq!(stats.type_histogram().len(), 3);
+        let encoded = encode_item(&item).unwrap();
+        assert_eq!(stats.serialized_size(), encoded.len());
+        assert_eq!(DocumentStats::from_encoded(&encoded).unwrap(), stats);
+    }
+
+    #[test]
+    fn test_large_field_size() {
+        let item = HtlvItem::new(5, HtlvValue::Bytes(Bytes::from(vec![0u8; LARGE_FIELD_THRESHOLD * 2 + 10])));
+        let encoded = encode_item(&item).unwrap();
+
+        let stats = item.stats();
+        assert_eq!(stats.count(HtlvValueType::Bytes), 1);
+        assert_eq!(stats.serialized_size(), encoded.len());
+
+        // On the wire the value is a header item plus three shards
+        let wire_stats = DocumentStats::from_encoded(&encoded).unwrap();
+        assert_eq!(wire_stats.count(HtlvValueType::Bytes), 4);
+        assert_eq!(wire_stats.serialized_size(), encoded.len());
+        assert_eq!(wire_stats.payload_bytes, stats.payload_bytes);
+    }
+
+    #[test]
+    fn test_display() {
+        let text = HtlvItem::new(1, HtlvValue::Null).stats().to_string();
+        assert!(text.contains("Items: 1"));
+        assert!(text.contains("Null: 1"));
+        assert!(text.contains("Serialized size: 3 bytes (3 header, 0 payload)"));
+    }
+}
@@ -0,0 +1,266 @@
+// Tag namespaces and collision-free tag allocation
+//
+// Teams sharing a schema each declare a namespace, i.e. a range of tags they
+// own. `TagSpace` hands out tags from those ranges at runtime and rejects
+// overlapping namespaces or reused tags. The `define_tag_namespaces!` macro
+// declares tag constants the same way, with the checks done at compile time.
+// Keep the ranges low: small tags encode to fewer varint bytes.
+
+use crate::internal::error::{Error, Result};
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+/// A named range of tags owned by one team or component.
+#[derive(Debug, Clone)]
+struct Namespace {
+    name: String,
+    range: Range<u64>,
+    /// Tags handed out or reserved so far
+    used: BTreeSet<u64>,
+}
+
+/// Allocates tags within declared, non-overlapping namespaces.
+#[derive(Debug, Clone, Default)]
+pub struct TagSpace {
+    namespaces: Vec<Namespace>,
+}
+
+impl TagSpace {
+    /// Creates an empty tag space.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declares a namespace owning the given range of tags.
+    ///
+    /// Fails if the name is already declared, the range is empty, or it overlaps
+    /// the range of another namespace.
+    pub fn declare(&mut self, name: &str, range: Range<u64>) -> Result<()> {
+        if range.is_empty() {
+            return Err(Error::SchemaError(format!("Tag namespace '{}' has an empty range {:?}", name, range)));
+        }
+        for namespace in &self.namespaces {
+            if namespace.name == name {
+                return Err(Error::SchemaError(format!("Tag namespace '{}' is already declared", name)));
+            }
+            if namespace.range.start < range.end && range.start < namespace.range.end {
+                return Err(Error::SchemaError(format!(
+                    "Tag namespace '{}' {:?} overlaps namespace '{}' {:?}",
+                    name, range, namespace.name, namespace.range
+                )));
+            }
+        }
+
+        self.namespaces.push(Namespace {
+            name: name.to_string(),
+            range,
+            used: BTreeSet::new(),
+        });
+        Ok(())
+    }
+
+    /// Allocates the lowest unused tag of a namespace.
+    pub fn allocate(&mut self, name: &str) -> Result<u64> {
+        let namespace = self.namespace_mut(name)?;
+        // Walk the used tags in order to find the first gap
+        let mut candidate = namespace.range.start;
+        for &used in namespace.used.range(namespace.range.clone()) {
+            if used != candidate {
+                break;
+            }
+            candidate += 1;
+        }
+
+        if candidate >= namespace.range.end {
+            return Err(Error::SchemaError(format!(
+                "Tag namespace '{}' {:?} is exhausted",
+                namespace.name, namespace.range
+            )));
+        }
+        namespace.used.insert(candidate);
+        Ok(candidate)
+    }
+
+    /// Reserves a specific tag in a namespace, e.g. one already used on the wire.
+    pub fn reserve(&mut self, name: &str, tag: u64) -> Result<()> {
+        let namespace = self.namespace_mut(name)?;
+        if !namespace.range.contains(&tag) {
+            return Err(Error::SchemaError(format!(
+                "Tag {} is outside namespace '{}' {:?}",
+                tag, namespace.name, namespace.range
+            )));
+        }
+        if !namespace.used.insert(tag) {
+            return Err(Error::SchemaError(format!("Tag {} is already used in namespace '{}'", tag, namespace.name)));
+        }
+        Ok(())
+    }
+
+    /// Returns the name of the namespace owning a tag.
+    pub fn namespace_of(&self, tag: u64) -> Option<&str> {
+        self.namespaces
+            .iter()
+            .find(|namespace| namespace.range.contains(&tag))
+            .map(|namespace| namespace.name.as_str())
+    }
+
+    /// Returns true if the tag has been allocated or reserved.
+    pub fn is_used(&self, tag: u64) -> bool {
+        self.namespaces.iter().any(|namespace| namespace.used.contains(&tag))
+    }
+
+    fn namespace_mut(&mut self, name: &str) -> Result<&mut Namespace> {
+        self.namespaces
+            .iter_mut()
+            .find(|namespace| namespace.name == name)
+            .ok_or_else(|| Error::SchemaError(format!("Tag namespace '{}' is not declared", name)))
+    }
+}
+
+/// Returns true if none of the `(start, end)` ranges overlap and none is empty.
+/// Used by `define_tag_namespaces!` at compile time.
+pub const fn ranges_are_disjoint(ranges: &[(u64, u64)]) -> bool {
+    let mut i = 0;
+    while i < ranges.len() {
+        if ranges[i].0 >= ranges[i].1 {
+            return false;
+        }
+        let mut j = i + 1;
+        while j < ranges.len() {
+            if ranges[i].0 < ranges[j].1 && ranges[j].0 < ranges[i].1 {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Returns true if all values are distinct and below `limit`.
+/// Used by `define_tag_namespaces!` at compile time.
+pub const fn offsets_are_valid(offsets: &[u64], limit: u64) -> bool {
+    let mut i = 0;
+    while i < offsets.len() {
+        if offsets[i] >= limit {
+            return false;
+        }
+        let mut j = i + 1;
+        while j < offsets.len() {
+            if offsets[i] == offsets[j] {
+                return false;
+            }
+            j += 1;
+        }
+        i += 1;
+    }
+    true
+}
+
+/// Declares tag constants grouped in namespaces, checked at compile time.
+///
+/// Each namespace becomes a module holding `START`, `END` and one constant per
+/// tag, whose value is the namespace start plus the given offset. Compilation
+/// fails if namespace ranges overlap, an offset falls outside its namespace, or
+/// two tags of a namespace share an offset.
+///
+/// ```ignore
+/// tonitru::define_tag_namespaces! {
+///     pub sensors: 0x100..0x200 {
+///         TEMPERATURE = 0,
+///         HUMIDITY = 1,
+///     }
+///     pub billing: 0x200..0x280 {
+///         AMOUNT = 0,
+///     }
+/// }
+///
+/// assert_eq!(sensors::HUMIDITY, 0x101);
+/// ```
+#[macro_export]
+macro_rules! define_tag_namespaces {
+    ($($vis:vis $namespace:ident : $start:literal .. $end:literal { $($tag:ident = $offset:literal),* $(,)? })*) => {
+        $(
+            #[allow(non_snake_case, dead_code)]
+            $vis mod $namespace {
+                /// First tag of the namespace
+                pub const START: u64 = $start;
+                /// End of the namespace (exclusive)
+                pub const END: u64 = $end;
+                $(pub const $tag: u64 = START + $offset;)*
+
+                const _: () = assert!(
+                    $crate::codec::tag_space::offsets_are_valid(&[$($offset),*], END - START),
+                    concat!("tags of namespace `", stringify!($namespace), "` must be distinct and fit in its range")
+                );
+            }
+        )*
+
+        const _: () = assert!(
+            $crate::codec::tag_space::ranges_are_disjoint(&[$(($start, $end)),*]),
+            "tag namespaces must be non-empty and must not overlap"
+        );
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    crate::define_tag_namespaces! {
+        sensors: 0x100..0x200 {
+            TEMPERATURE = 0,
+            HUMIDITY = 1,
+        }
+        billing: 0x200..0x210 {
+            AMOUNT = 15,
+        }
+    }
+
+    #[test]
+    fn test_macro_constants() {
+        assert_eq!(sensors::START, 0x100);
+        assert_eq!(sensors::TEMPERATURE, 0x100);
+        assert_eq!(sensors::HUMIDITY, 0x101);
+        assert_eq!(billing::AMOUNT, 0x20F);
+    }
+
+    #[test]
+    fn test_const_checks() {
+        assert!(ranges_are_disjoint(&[(0, 10), (10, 20)]));
+        assert!(!ranges_are_disjoint(&[(0, 10), (9, 20)]));
+        assert!(!ranges_are_disjoint(&[(5, 5)]));
+        assert!(offsets_are_valid(&[0, 3], 4));
+        assert!(!offsets_are_valid(&[0, 4], 4));
+        assert!(!offsets_are_valid(&[1, 1], 4));
+    }
+
+    #[test]
+    fn test_declare_rejects_overlaps() {
+        let mut space = TagSpace::new();
+        space.declare("sensors", 100..200).unwrap();
+        assert!(space.declare("sensors", 300..400).is_err());
+        assert!(space.declare("billing", 150..250).is_err());
+        assert!(space.declare("billing", 200..200).is_err());
+        space.declare("billing", 200..300).unwrap();
+
+        assert_eq!(space.namespace_of(250), Some("billing"));
+        assert_eq!(space.namespace_of(50), None);
+    }
+
+    #[test]
+    fn test_allocate_and_reserve() {
+        let mut space = TagSpace::new();
+        space.declare("small", 10..13).unwrap();
+
+        space.reserve("small", 11).unwrap();
+        assert!(space.reserve("small", 11).is_err());
+        assert!(space.reserve("small", 13).is_err());
+
+        assert_eq!(space.allocate("small").unwrap(), 10);
+        assert_eq!(space.allocate("small").unwrap(), 12);
+        assert!(space.allocate("small").is_err());
+        assert!(space.is_used(11));
+        assert!(space.allocate("missing").is_err());
+    }
+}
@@ -0,0 +1,244 @@
+// Per-packet tag dictionaries
+//
+// Hash-derived 64-bit tags cost up to 10 varint bytes on every occurrence. A
+// `TagTable` lists the distinct tags of a packet once, ordered by frequency,
+// and the items are encoded with the small local ids (indices into the table)
+// instead. The table is written in front of the items:
+//
+//   [tag count (varint)] [full tag (varint)]* [items with local ids]
+//
+// Whether a packet body carries a tag table is signalled in the frame header
+// (see `MetadataHeader::set_tag_table`); decoding remaps the ids back to the
+// full tags transparently.
+
+use crate::internal::error::{Error, Result};
+use crate::codec::varint;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::wire::{self, WireFormat};
+use std::collections::HashMap;
+
+/// A mapping between the full tags of a packet and small local ids.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TagTable {
+    /// Full tags, indexed by local id
+    tags: Vec<u64>,
+    /// Local ids, keyed by full tag
+    ids: HashMap<u64, u64>,
+}
+
+impl TagTable {
+    /// Builds a table holding every tag of the item, the most frequent ones first
+    /// so that they get the shortest local ids.
+    pub fn build(item: &HtlvItem) -> Self {
+        let mut counts: HashMap<u64, usize> = HashMap::new();
+        count_tags(item, &mut counts);
+
+        let mut tags: Vec<(u64, usize)> = counts.into_iter().collect();
+        // Ties are broken by tag value so that the table is deterministic
+        tags.sort_unstable_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        Self::from_tags(tags.into_iter().map(|(tag, _)| tag).collect())
+    }
+
+    fn from_tags(tags: Vec<u64>) -> Self {
+        let ids = tags.iter().enumerate().map(|(id, &tag)| (tag, id as u64)).collect();
+        TagTable { tags, ids }
+    }
+
+    /// Returns the number of tags in the table.
+    pub fn len(&self) -> usize {
+        self.tags.len()
+    }
+
+    /// Returns true if the table holds no tags.
+    pub fn is_empty(&self) -> bool {
+        self.tags.is_empty()
+    }
+
+    /// Returns the local id of a full tag.
+    pub fn local_id(&self, tag: u64) -> Option<u64> {
+        self.ids.get(&tag).copied()
+    }
+
+    /// Returns the full tag of a local id.
+    pub fn full_tag(&self, id: u64) -> Option<u64> {
+        usize::try_from(id).ok().and_then(|id| self.tags.get(id)).copied()
+    }
+
+    /// Encodes the table into bytes.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut encoded = varint::encode_varint(self.tags.len() as u64);
+        for &tag in &self.tags {
+            encoded.extend_from_slice(&varint::encode_varint(tag));
+        }
+        encoded
+    }
+
+    /// Decodes a table from bytes.
+    /// Returns the table and the number of bytes read.
+    pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
+        let (count, mut bytes_read) = varint::decode_varint(data)?;
+        // Every tag takes at least one byte, which bounds the allocation
+        if count > (data.len() - bytes_read) as u64 {
+            return Err(Error::CodecError(format!("Tag table declares {} tags but only {} bytes follow", count, data.len() - bytes_read)));
+        }
+
+        let mut tags = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let (tag, len) = varint::decode_varint(&data[bytes_read..])?;
+            tags.push(tag);
+            bytes_read += len;
+        }
+
+        let table = Self::from_tags(tags);
+        if table.ids.len() != table.tags.len() {
+            return Err(Error::CodecError("Tag table contains duplicate tags".to_string()));
+        }
+        Ok((table, bytes_read))
+    }
+
+    /// Returns a copy of the item with every tag replaced by its local id.
+    pub fn to_local(&self, item: &HtlvItem) -> Result<HtlvItem> {
+        self.remap(item, &|tag| {
+            self.local_id(tag).ok_or_else(|| Error::CodecError(format!("Tag {} is not in the tag table", tag)))
+        })
+    }
+
+    /// Returns a copy of the item with every local id replaced by its full tag.
+    pub fn to_full(&self, item: &HtlvItem) -> Result<HtlvItem> {
+        self.remap(item, &|id| {
+            self.full_tag(id).ok_or_else(|| Error::CodecError(format!("Local tag id {} is out of range ({} tags)", id, self.tags.len())))
+        })
+    }
+
+    fn remap(&self, item: &HtlvItem, map: &dyn Fn(u64) -> Result<u64>) -> Result<HtlvItem> {
+        let value = match &item.value {
+            HtlvValue::Array(items) => HtlvValue::Array(self.remap_all(items, map)?),
+            HtlvValue::Object(items) => HtlvValue::Object(self.remap_all(items, map)?),
+            other => other.clone(),
+        };
+        Ok(HtlvItem::new(map(item.tag)?, value))
+    }
+
+    fn remap_all(&self, items: &[HtlvItem], map: &dyn Fn(u64) -> Result<u64>) -> Result<Vec<HtlvItem>> {
+        items.iter().map(|item| self.remap(item, map)).collect()
+    }
+}
+
+fn count_tags(item: &HtlvItem, counts: &mut HashMap<u64, usize>) {
+    *counts.entry(item.tag).or_insert(0) += 1;
+    if let HtlvValue::Array(items) | HtlvValue::Object(items) = &item.value {
+        for sub_item in items {
+            count_tags(sub_item, counts);
+        }
+    }
+}
+
+/// Encodes an item preceded by a tag table, with local ids in place of the tags.
+pub fn encode_with_tag_table(item: &HtlvItem, format: WireFormat) -> Result<Vec<u8>> {
+    let table = TagTable::build(item);
+    let mut encoded = table.encode();
+    encoded.extend_from_slice(&wire::encode_item_with_format(&table.to_local(item)?, format)?);
+    Ok(encoded)
+}
+
+/// Decodes an item preceded by a tag table, restoring the full tags.
+/// Returns the decoded item and the number of bytes read.
+pub fn decode_with_tag_table(data: &[u8], format: WireFormat) -> Result<(HtlvItem, usize)> {
+    let (table, table_bytes) = TagTable::decode(data)?;
+    let (item, item_bytes) = wire::decode_item_with_format(&data[table_bytes..], format)?;
+    Ok((table.to_full(&item)?, table_bytes + item_bytes))
+}
+
+/// Encodes an item with a tag table only if that makes the encoding smaller.
+/// Returns whether the table was used, to be recorded in the frame header, and the bytes.
+pub fn encode_with_optional_tag_table(item: &HtlvItem, format: WireFormat) -> Result<(bool, Vec<u8>)> {
+    let plain = wire::encode_item_with_format(item, format)?;
+    let with_table = encode_with_tag_table(item, format)?;
+    if with_table.len() < plain.len() {
+        Ok((true, with_table))
+    } else {
+        Ok((false, plain))
+    }
+}
+
+/// Decodes an item, with or without a tag table as recorded in the frame header.
+pub fn decode_with_optional_tag_table(data: &[u8], format: WireFormat, has_tag_table: bool) -> Result<(HtlvItem, usize)> {
+    if has_tag_table {
+        decode_with_tag_table(data, format)
+    } else {
+        wire::decode_item_with_format(data, format)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+    use std::collections::hash_map::DefaultHasher;
+    use std::hash::{Hash, Hasher};
+
+    /// Derives a tag from a field name, like schemas without explicit tags do.
+    fn hash_tag(name: &str) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn readings() -> HtlvItem {
+        let reading = |i: u8| HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(hash_tag("temperature"), HtlvValue::U8(20 + i)),
+            HtlvItem::new(hash_tag("humidity"), HtlvValue::U8(40 + i)),
+            HtlvItem::new(hash_tag("sensor"), HtlvValue::String(Bytes::from_static(b"s1"))),
+        ]));
+        HtlvItem::new(1, HtlvValue::Array((0..10).map(reading).collect()))
+    }
+
+    #[test]
+    fn test_table_orders_by_frequency() {
+        let table = TagTable::build(&readings());
+        assert_eq!(table.len(), 5);
+        // Tag 0 occurs 10 times and the field tags 10 times each, tag 1 only once
+        assert_eq!(table.full_tag(4), Some(1));
+        assert_eq!(table.local_id(0), Some(0));
+
+        let (decoded, bytes_read) = TagTable::decode(&table.encode()).unwrap();
+        assert_eq!(decoded, table);
+        assert_eq!(bytes_read, table.encode().len());
+    }
+
+    #[test]
+    fn test_round_trip_shrinks_hash_tags() {
+        let item = readings();
+        for format in [WireFormat::V1, WireFormat::V2] {
+            let plain = wire::encode_item_with_format(&item, format).unwrap();
+            let (used, encoded) = encode_with_optional_tag_table(&item, format).unwrap();
+            assert!(used);
+            assert!(encoded.len() < plain.len());
+
+            let (decoded, bytes_read) = decode_with_optional_tag_table(&encoded, format, used).unwrap();
+            assert_eq!(bytes_read, encoded.len());
+            assert_eq!(decoded, item);
+        }
+    }
+
+    #[test]
+    fn test_small_tags_skip_table() {
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![HtlvItem::new(2, HtlvValue::U8(1))]));
+        let (used, encoded) = encode_with_optional_tag_table(&item, WireFormat::V2).unwrap();
+        assert!(!used);
+        assert_eq!(encoded, wire::encode_item_with_format(&item, WireFormat::V2).unwrap());
+    }
+
+    #[test]
+    fn test_invalid_tables() {
+        // Declares 3 tags with a single byte following
+        assert!(TagTable::decode(&[0x03, 0x01]).is_err());
+        // Duplicate tags
+        assert!(TagTable::decode(&[0x02, 0x05, 0x05]).is_err());
+
+        let table = TagTable::decode(&[0x01, 0x05]).unwrap().0;
+        let item = HtlvItem::new(1, HtlvValue::Null);
+        assert!(table.to_full(&item).is_err());
+        assert!(table.to_local(&item).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+// Schema-driven transforms of field values
+//
+// Some schema options apply to single fields rather than whole packets:
+// `SchemaOptions::encrypt` and `SchemaOptions::compress`. `transform_fields`
+// walks an item together with its schema and replaces the values of the fields
+// a selector picks, without descending into them; fields unknown to the schema
+// are left alone. Field-level encryption builds on it, and so does
+// `FieldCompressor`, which compresses the values of fields marked
+// `compress: true`.
+//
+// A compressed field becomes a `Bytes` envelope made of the compression
+// strategy byte and the compressed v2 encoding of the original value, so
+// fields of any type can be compressed and decompressing needs nothing but the
+// envelope. Values smaller than the minimum size, or that do not compress,
+// are stored with the `NoCompression` strategy, so every marked field holds an
+// envelope.
+
+use std::collections::HashMap;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::wire::{decode_item_with_format, encode_item_with_format, WireFormat};
+use crate::compress::{compress_or_passthrough, get_compressor, CompressionStrategy};
+use crate::internal::cursor::WireCursor;
+use crate::internal::error::{Error, Result};
+use crate::schema::types::{Schema, SchemaField, SchemaType};
+
+/// Encoded size under which a field is not worth compressing
+pub const DEFAULT_MIN_FIELD_SIZE: usize = 128;
+
+/// Returns a copy of the item with the values of the selected fields replaced
+/// by `transform`.
+pub fn transform_fields(
+    schema: &Schema,
+    item: &HtlvItem,
+    select: &dyn Fn(&SchemaField) -> bool,
+    transform: &dyn Fn(&SchemaField, &HtlvValue) -> Result<HtlvValue>,
+) -> Result<HtlvItem> {
+    let walker = Walker { definitions: &schema.definitions, select, transform };
+    Ok(HtlvItem::new(item.tag, walker.walk(&schema.root_type, &item.value)?))
+}
+
+struct Walker<'a> {
+    definitions: &'a HashMap<String, SchemaType>,
+    select: &'a dyn Fn(&SchemaField) -> bool,
+    transform: &'a dyn Fn(&SchemaField, &HtlvValue) -> Result<HtlvValue>,
+}
+
+impl Walker<'_> {
+    fn walk(&self, schema_type: &SchemaType, value: &HtlvValue) -> Result<HtlvValue> {
+        match (schema_type, value) {
+            (SchemaType::Object(fields), HtlvValue::Object(items)) => {
+                let mut transformed = Vec::with_capacity(items.len());
+                for item in items {
+                    let value = match fields.iter().find(|field| field.tag == item.tag) {
+                        Some(field) if (self.select)(field) => (self.transform)(field, &item.value)?,
+                        Some(field) => self.walk(&field.field_type, &item.value)?,
+                        None => item.value.clone(),
+                    };
+                    transformed.push(HtlvItem::new(item.tag, value));
+                }
+                Ok(HtlvValue::Object(transformed))
+            }
+            (SchemaType::Array(element_type), HtlvValue::Array(items)) => {
+                let mut transformed = Vec::with_capacity(items.len());
+                for item in items {
+                    transformed.push(HtlvItem::new(item.tag, self.walk(element_type, &item.value)?));
+                }
+                Ok(HtlvValue::Array(transformed))
+            }
+            (SchemaType::Ref(name), value) => match self.definitions.get(name) {
+                Some(definition) => self.walk(definition, value),
+                None => Err(Error::SchemaError(format!("Unknown type reference '{}'", name))),
+            },
+            _ => Ok(value.clone()),
+        }
+    }
+}
+
+/// Compresses the values of the fields marked `compress: true` in a schema.
+#[derive(Debug, Clone, Copy)]
+pub struct FieldCompressor {
+    strategy: CompressionStrategy,
+    min_size: usize,
+}
+
+impl FieldCompressor {
+    /// Creates a compressor using the strategy for fields of at least
+    /// `DEFAULT_MIN_FIELD_SIZE` encoded bytes.
+    pub fn new(strategy: CompressionStrategy) -> Self {
+        FieldCompressor { strategy, min_size: DEFAULT_MIN_FIELD_SIZE }
+    }
+
+    /// Sets the encoded size under which fields are stored uncompressed.
+    pub fn with_min_size(mut self, min_size: usize) -> Self {
+        self.min_size = min_size;
+        self
+    }
+
+    /// Returns a copy of the item with the marked fields compressed.
+    pub fn compress(&self, schema: &Schema, item: &HtlvItem) -> Result<HtlvItem> {
+        transform_fields(schema, item, &|field| field.options.compress, &|field, value| self.pack(field, value))
+    }
+
+    /// Returns a copy of the item with the marked fields decompressed, whatever
+    /// strategy their envelopes record.
+    pub fn decompress(&self, schema: &Schema, item: &HtlvItem) -> Result<HtlvItem> {
+        transform_fields(schema, item, &|field| field.options.compress, &unpack)
+    }
+
+    fn pack(&self, field: &SchemaField, value: &HtlvValue) -> Result<HtlvValue> {
+        let encoded = encode_item_with_format(&HtlvItem::new(field.tag, value.clone()), WireFormat::V2)?;
+        let (applied, payload) = if encoded.len() < self.min_size {
+            (CompressionStrategy::NoCompression, encoded)
+        } else {
+            compress_or_passthrough(self.strategy, &encoded)?
+        };
+
+        let mut envelope = Vec::with_capacity(1 + payload.len());
+        envelope.push(applied as u8);
+        envelope.extend_from_slice(&payload);
+        Ok(HtlvValue::Bytes(envelope.into()))
+    }
+}
+
+fn unpack(field: &SchemaField, value: &HtlvValue) -> Result<HtlvValue> {
+    let HtlvValue::Bytes(envelope) = value else {
+        return Err(Error::CompressionError(format!(
+            "Compressed field '{}' holds a {:?} value instead of bytes",
+            field.name,
+            value.value_type()
+        )));
+    };
+    let mut cursor = WireCursor::new(envelope).with_truncation_error(Error::CompressionError);
+    let strategy = CompressionStrategy::from_u8(cursor.read_u8("field compression strategy")?)?;
+    let encoded = get_compressor(strategy)?.decompress(cursor.rest())?;
+    let (item, _) = decode_item_with_format(&encoded, WireFormat::V2)?;
+    Ok(item.value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::testing::{self, field};
+    use crate::schema::types::SchemaOptions;
+
+    fn schema() -> Schema {
+        let compress = || SchemaOptions { compress: true, ..Default::default() };
+        let mut schema = testing::schema("docs", "Document", SchemaType::Object(vec![
+            field("title", 1, SchemaType::String, compress()),
+            field("body", 2, SchemaType::String, compress()),
+            field("sections", 3, SchemaType::Array(Box::new(SchemaType::Ref("Section".to_string()))), SchemaOptions::default()),
+        ]));
+        schema.definitions.insert(
+            "Section".to_string(),
+            SchemaType::Object(vec![field("text", 1, SchemaType::String, compress())]),
+        );
+        schema
+    }
+
+    fn document() -> HtlvItem {
+        let text = |s: String| HtlvValue::String(s.into());
+        HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, text("Short".to_string())),
+            HtlvItem::new(2, text("lorem ipsum ".repeat(60))),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::Object(vec![HtlvItem::new(1, text("dolor ".repeat(40)))])),
+            ])),
+            HtlvItem::new(4, HtlvValue::Bool(true)),
+        ]))
+    }
+
+    #[test]
+    fn test_marked_fields_round_trip() {
+        let compressor = FieldCompressor::new(CompressionStrategy::Zstd);
+        let compressed = compressor.compress(&schema(), &document()).unwrap();
+        let HtlvValue::Object(fields) = &compressed.value else { panic!("Expected an object") };
+
+        // Small values are wrapped without compression, large ones compressed
+        let HtlvValue::Bytes(title) = &fields[0].value else { panic!("Expected an envelope") };
+        assert_eq!(title[0], CompressionStrategy::NoCompression as u8);
+        let HtlvValue::Bytes(body) = &fields[1].value else { panic!("Expected an envelope") };
+        assert_eq!(body[0], CompressionStrategy::Zstd as u8);
+        assert!(body.len() < 100);
+        assert_eq!(fields[3].value, HtlvValue::Bool(true));
+
+        // Decompression does not depend on the configured strategy
+        let reader = FieldCompressor::new(CompressionStrategy::Brotli);
+        assert_eq!(reader.decompress(&schema(), &compressed).unwrap(), document());
+    }
+
+    #[test]
+    fn test_decompress_rejects_plain_values() {
+        let err = FieldCompressor::new(CompressionStrategy::Zstd).decompress(&schema(), &document()).unwrap_err();
+        assert_eq!(err.to_string(), "Compression Error: Compressed field 'title' holds a String value instead of bytes");
+
+        let mut schema = schema();
+        schema.definitions.clear();
+        let err = FieldCompressor::new(CompressionStrategy::Zstd).compress(&schema, &document()).unwrap_err();
+        assert_eq!(err.to_string(), "Schema Error: Unknown type reference 'Section'");
+    }
+}
@@ -1,10 +1,18 @@
 use bytes::Bytes;
 use bitflags::bitflags;
+use serde::{Deserialize, Serialize};
+
+use crate::internal::error::{Error, Result};
 
 /// Represents a single HTLV (HyperNova) data item.
 /// This struct is used internally for representing parsed HTLV values,
 /// especially within complex types like Arrays and Objects.
-#[derive(Debug, PartialEq, Clone)]
+///
+/// `clone()` is cheap for Bytes and String values: the clone shares the
+/// underlying buffer, which for decoded items is usually the network buffer the
+/// item was decoded from. That buffer stays alive as long as any clone holds a
+/// view into it. Use `deep_clone()` or `make_owned()` to detach the item from it.
+#[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub struct HtlvItem {
     pub tag: u64,
     pub value: HtlvValue,
@@ -30,6 +38,19 @@ pub enum HtlvValue {
     String(Bytes),
     Array(Vec<HtlvItem>),
     Object(Vec<HtlvItem>),
+    /// Application-defined value: its type byte, in `EXTENSION_TYPE_RANGE`, and
+    /// its encoded payload. See `codec::extension` for registering such types.
+    Extension(u8, Bytes),
+    /// Bytes value stored outside the packet, such as in object storage. It is
+    /// fetched through a `BlobResolver` when accessed; see `codec::external`.
+    ExternalRef {
+        /// Where the bytes are stored, in a form the resolver understands
+        locator: String,
+        /// Length of the bytes
+        length: u64,
+        /// BLAKE3 hash of the bytes
+        hash: [u8; 32],
+    },
     // TODO: Add support for other complex types like maps
 }
 
@@ -53,10 +74,52 @@ impl HtlvValue {
             HtlvValue::String(_) => HtlvValueType::String,
             HtlvValue::Array(_) => HtlvValueType::Array,
             HtlvValue::Object(_) => HtlvValueType::Object,
+            HtlvValue::Extension(..) => HtlvValueType::Extension,
+            HtlvValue::ExternalRef { .. } => HtlvValueType::ExternalRef,
+        }
+    }
+
+    /// Returns the type byte written for the value, which for extension values
+    /// is their own type byte rather than `HtlvValueType::Extension`.
+    pub fn type_byte(&self) -> u8 {
+        match self {
+            HtlvValue::Extension(type_byte, _) => *type_byte,
+            other => other.value_type() as u8,
+        }
+    }
+
+    /// Returns the number held by a one-element batch, as the decoder returns
+    /// numbers wider than a byte, or the value itself.
+    pub(crate) fn as_scalar(&self) -> &HtlvValue {
+        match self {
+            HtlvValue::Array(items) if is_batch_of_one(items) => &items[0].value,
+            value => value,
+        }
+    }
+
+    /// Owned counterpart of `as_scalar`.
+    pub(crate) fn into_scalar(self) -> HtlvValue {
+        match self {
+            HtlvValue::Array(mut items) if is_batch_of_one(&items) => items.remove(0).value,
+            value => value,
         }
     }
 }
 
+/// Tells whether the items are a batch holding a single number
+fn is_batch_of_one(items: &[HtlvItem]) -> bool {
+    matches!(items, [item] if item.tag == 0 && matches!(item.value,
+        HtlvValue::U16(_) | HtlvValue::U32(_) | HtlvValue::U64(_)
+        | HtlvValue::I16(_) | HtlvValue::I32(_) | HtlvValue::I64(_)
+        | HtlvValue::F32(_) | HtlvValue::F64(_)))
+}
+
+/// Type bytes reserved for application-defined extension types.
+///
+/// The core types never use them, and the top bit stays clear so that they do
+/// not collide with compact v2 headers.
+pub const EXTENSION_TYPE_RANGE: std::ops::RangeInclusive<u8> = 0x40..=0x7F;
+
 /// Defines the byte representation for each HtlvValue type.
 #[repr(u8)]
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
@@ -77,6 +140,9 @@ pub enum HtlvValueType {
     String = 13,
     Array = 14,
     Object = 15,
+    ExternalRef = 16,
+    /// Any type byte in `EXTENSION_TYPE_RANGE`; the byte itself is kept in the value
+    Extension = 0x40,
     // TODO: Assign type bytes for other complex types if needed
 }
 
@@ -100,6 +166,8 @@ impl HtlvValueType {
             13 => Some(HtlvValueType::String),
             14 => Some(HtlvValueType::Array),
             15 => Some(HtlvValueType::Object),
+            16 => Some(HtlvValueType::ExternalRef),
+            byte if EXTENSION_TYPE_RANGE.contains(&byte) => Some(HtlvValueType::Extension),
             _ => None, // Unknown type
         }
     }
@@ -110,6 +178,154 @@ impl HtlvItem {
     pub fn new(tag: u64, value: HtlvValue) -> Self {
         HtlvItem { tag, value }
     }
+
+    /// Returns a copy of the item whose Bytes and String values own fresh buffers,
+    /// so it no longer keeps the buffers of the original alive.
+    pub fn deep_clone(&self) -> Self {
+        HtlvItem::new(self.tag, self.value.deep_clone())
+    }
+
+    /// Detaches the Bytes and String values of the item from the buffers they share,
+    /// copying their contents into fresh buffers.
+    pub fn make_owned(&mut self) {
+        self.value.make_owned();
+    }
+
+    /// Returns the memory used by the item tree itself, in bytes, excluding the
+    /// contents of Bytes and String values, which may be shared.
+    pub fn shallow_size(&self) -> usize {
+        std::mem::size_of::<HtlvItem>() + self.value.heap_size(false)
+    }
+
+    /// Returns the memory used by the item tree including the contents of Bytes
+    /// and String values, i.e. the size of a `deep_clone()`.
+    pub fn deep_size(&self) -> usize {
+        std::mem::size_of::<HtlvItem>() + self.value.heap_size(true)
+    }
+
+    /// Sorts the fields of every object in the tree by tag. The sort is stable,
+    /// so fields sharing a tag keep their order; array elements are not reordered.
+    pub fn sort_fields_by_tag(&mut self) {
+        match &mut self.value {
+            HtlvValue::Object(fields) => {
+                fields.iter_mut().for_each(HtlvItem::sort_fields_by_tag);
+                fields.sort_by_key(|field| field.tag);
+            }
+            HtlvValue::Array(elements) => elements.iter_mut().for_each(HtlvItem::sort_fields_by_tag),
+            _ => {}
+        }
+    }
+
+    /// Removes the fields sharing a tag with another field of the same object,
+    /// in every object of the tree, as the policy says. The remaining fields
+    /// keep their order.
+    pub fn dedup_by_tag(&mut self, policy: DedupPolicy) -> Result<()> {
+        match &mut self.value {
+            HtlvValue::Object(fields) => {
+                for field in fields.iter_mut() {
+                    field.dedup_by_tag(policy)?;
+                }
+                let mut seen = std::collections::HashSet::with_capacity(fields.len());
+                match policy {
+                    DedupPolicy::KeepFirst => fields.retain(|field| seen.insert(field.tag)),
+                    DedupPolicy::KeepLast => {
+                        fields.reverse();
+                        fields.retain(|field| seen.insert(field.tag));
+                        fields.reverse();
+                    }
+                    DedupPolicy::Reject => {
+                        if let Some(field) = fields.iter().find(|field| !seen.insert(field.tag)) {
+                            return Err(Error::CodecError(format!(
+                                "Object with tag {} holds tag {} more than once",
+                                self.tag, field.tag
+                            )));
+                        }
+                    }
+                }
+            }
+            HtlvValue::Array(elements) => {
+                for element in elements.iter_mut() {
+                    element.dedup_by_tag(policy)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// Brings the tree into normal form, so that items with the same meaning
+    /// compare equal: duplicate fields are removed with `DedupPolicy::KeepLast`,
+    /// fields are sorted by tag and every NaN becomes the same quiet NaN.
+    pub fn normalize(&mut self) {
+        // Keeping the last duplicate never fails
+        let _ = self.dedup_by_tag(DedupPolicy::KeepLast);
+        self.sort_fields_by_tag();
+        self.value.normalize_nan();
+    }
+}
+
+/// What `HtlvItem::dedup_by_tag` does with fields sharing a tag.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DedupPolicy {
+    /// Keeps the first field with each tag
+    KeepFirst,
+    /// Keeps the last field with each tag, as most JSON parsers do with keys
+    KeepLast,
+    /// Fails with a codec error on the first duplicate
+    Reject,
+}
+
+impl HtlvValue {
+    /// Returns a copy of the value whose Bytes and String values own fresh buffers.
+    pub fn deep_clone(&self) -> Self {
+        match self {
+            HtlvValue::Bytes(v) => HtlvValue::Bytes(Bytes::copy_from_slice(v)),
+            HtlvValue::String(v) => HtlvValue::String(Bytes::copy_from_slice(v)),
+            HtlvValue::Extension(type_byte, v) => HtlvValue::Extension(*type_byte, Bytes::copy_from_slice(v)),
+            HtlvValue::Array(items) => HtlvValue::Array(items.iter().map(HtlvItem::deep_clone).collect()),
+            HtlvValue::Object(items) => HtlvValue::Object(items.iter().map(HtlvItem::deep_clone).collect()),
+            other => other.clone(),
+        }
+    }
+
+    /// Detaches the Bytes and String values from the buffers they share.
+    pub fn make_owned(&mut self) {
+        match self {
+            HtlvValue::Bytes(v) | HtlvValue::String(v) | HtlvValue::Extension(_, v) => {
+                *v = Bytes::copy_from_slice(v)
+            }
+            HtlvValue::Array(items) | HtlvValue::Object(items) => {
+                items.iter_mut().for_each(HtlvItem::make_owned);
+            }
+            _ => {}
+        }
+    }
+
+    /// Replaces every NaN in the tree with the standard quiet NaN.
+    fn normalize_nan(&mut self) {
+        match self {
+            HtlvValue::F32(value) if value.is_nan() => *value = f32::NAN,
+            HtlvValue::F64(value) if value.is_nan() => *value = f64::NAN,
+            HtlvValue::Array(items) | HtlvValue::Object(items) => {
+                items.iter_mut().for_each(|item| item.value.normalize_nan());
+            }
+            _ => {}
+        }
+    }
+
+    /// Returns the heap memory owned by the value, optionally including the
+    /// contents of Bytes and String values.
+    pub(crate) fn heap_size(&self, include_payloads: bool) -> usize {
+        match self {
+            HtlvValue::Bytes(v) | HtlvValue::String(v) | HtlvValue::Extension(_, v) if include_payloads => v.len(),
+            HtlvValue::ExternalRef { locator, .. } => locator.capacity(),
+            HtlvValue::Array(items) | HtlvValue::Object(items) => {
+                let children: usize = items.iter().map(|item| item.value.heap_size(include_payloads)).sum();
+                items.capacity() * std::mem::size_of::<HtlvItem>() + children
+            }
+            _ => 0,
+        }
+    }
 }
 
 bitflags! {
@@ -165,4 +381,99 @@ impl HTLVBlock {
             nested,
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(payload: Bytes) -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::Bytes(payload.slice(0..4))),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::String(payload.slice(4..8))),
+            ])),
+            HtlvItem::new(4, HtlvValue::U32(7)),
+        ]))
+    }
+
+    fn bytes_ptr(item: &HtlvItem) -> *const u8 {
+        match &item.value {
+            HtlvValue::Object(items) => match &items[0].value {
+                HtlvValue::Bytes(v) => v.as_ptr(),
+                _ => unreachable!(),
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn test_clone_shares_and_deep_clone_detaches() {
+        let buffer = Bytes::from(b"abcdefgh".to_vec());
+        let item = sample(buffer.clone());
+
+        assert_eq!(bytes_ptr(&item.clone()), buffer.as_ptr());
+
+        let deep = item.deep_clone();
+        assert_eq!(deep, item);
+        assert_ne!(bytes_ptr(&deep), buffer.as_ptr());
+    }
+
+    #[test]
+    fn test_make_owned() {
+        let buffer = Bytes::from(b"abcdefgh".to_vec());
+        let mut item = sample(buffer.clone());
+        let original = item.clone();
+
+        item.make_owned();
+        assert_eq!(item, original);
+        assert_ne!(bytes_ptr(&item), buffer.as_ptr());
+    }
+
+    #[test]
+    fn test_shallow_and_deep_size() {
+        let item = sample(Bytes::from(b"abcdefgh".to_vec()));
+        assert_eq!(item.deep_size() - item.shallow_size(), 8);
+
+        let scalar = HtlvItem::new(1, HtlvValue::U64(0));
+        assert_eq!(scalar.shallow_size(), std::mem::size_of::<HtlvItem>());
+        assert_eq!(scalar.deep_size(), scalar.shallow_size());
+    }
+
+    #[test]
+    fn test_sort_dedup_and_normalize() {
+        let field = |tag, value| HtlvItem::new(tag, HtlvValue::U8(value));
+        let object = |fields| HtlvItem::new(1, HtlvValue::Object(fields));
+        let item = object(vec![
+            field(3, 0),
+            HtlvItem::new(2, HtlvValue::Array(vec![object(vec![field(9, 1), field(8, 2)])])),
+            field(3, 1),
+            field(1, 2),
+        ]);
+
+        let mut sorted = item.clone();
+        sorted.sort_fields_by_tag();
+        let HtlvValue::Object(fields) = &sorted.value else { unreachable!() };
+        assert_eq!(fields.iter().map(|field| field.tag).collect::<Vec<_>>(), vec![1, 2, 3, 3]);
+        assert_eq!(fields[3], field(3, 1));
+        assert_eq!(fields[1], HtlvItem::new(2, HtlvValue::Array(vec![object(vec![field(8, 2), field(9, 1)])])));
+
+        let mut first = item.clone();
+        first.dedup_by_tag(DedupPolicy::KeepFirst).unwrap();
+        let HtlvValue::Object(fields) = &first.value else { unreachable!() };
+        assert_eq!((fields.len(), &fields[0]), (3, &field(3, 0)));
+        let err = item.clone().dedup_by_tag(DedupPolicy::Reject).unwrap_err();
+        assert_eq!(err.to_string(), "Codec Error: Object with tag 1 holds tag 3 more than once");
+
+        let mut normal = item.clone();
+        normal.normalize();
+        let mut reordered = object(vec![field(1, 2), field(3, 1), HtlvItem::new(2, HtlvValue::Array(vec![object(vec![field(8, 2), field(9, 1)])]))]);
+        reordered.normalize();
+        assert_eq!(normal, reordered);
+
+        let mut nan = HtlvItem::new(1, HtlvValue::F64(f64::from_bits(0xfff8_0000_0000_0001)));
+        nan.normalize();
+        let HtlvValue::F64(value) = nan.value else { unreachable!() };
+        assert_eq!(value.to_bits(), f64::NAN.to_bits());
+    }
+}
@@ -3,7 +3,13 @@ use crate::internal::error::{Error, Result};
 /// Encodes an unsigned 64-bit integer using a variable-length scheme (similar to LEB128).
 /// Returns the encoded bytes.
 pub fn encode_varint(value: u64) -> Vec<u8> {
-    let mut buf = Vec::new();
+    let mut buf = Vec::with_capacity(encoded_varint_len(value));
+    encode_varint_into(value, &mut buf);
+    buf
+}
+
+/// Appends the variable-length encoding of `value` to `out`.
+pub fn encode_varint_into(value: u64, out: &mut Vec<u8>) {
     let mut value = value;
 
     loop {
@@ -12,16 +18,30 @@ pub fn encode_varint(value: u64) -> Vec<u8> {
         if value != 0 {
             byte |= 0x80;
         }
-        buf.push(byte);
+        out.push(byte);
         if value == 0 {
             break;
         }
     }
-    buf
+}
+
+/// Returns the number of bytes the variable-length encoding of `value` takes.
+pub fn encoded_varint_len(value: u64) -> usize {
+    let mut value = value;
+    let mut len = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        len += 1;
+    }
+    len
 }
 
 /// Decodes an unsigned 64-bit integer from a variable-length encoded byte slice.
 /// Returns the decoded value and the number of bytes read.
+///
+/// Fails with `Error::UnexpectedEof` if the slice ends within the varint, and
+/// with `Error::InvalidVarint` if it does not fit in 64 bits; their offsets are
+/// relative to the slice.
 pub fn decode_varint(data: &[u8]) -> Result<(u64, usize)> {
     let mut value = 0u64;
     let mut shift = 0;
@@ -29,20 +49,20 @@ pub fn decode_varint(data: &[u8]) -> Result<(u64, usize)> {
 
     for byte in data {
         bytes_read += 1;
+        // The tenth byte only holds the last bit of a u64 and ends the varint
+        if shift == 63 && *byte > 1 {
+            return Err(Error::InvalidVarint { what: "varint".to_string(), offset: 0 });
+        }
         let low_seven_bits = (byte & 0x7F) as u64;
         value |= low_seven_bits << shift;
         if (byte & 0x80) == 0 {
             return Ok((value, bytes_read));
         }
         shift += 7;
-        if shift >= 64 {
-            // Value is too large to fit in u64
-            return Err(Error::CodecError("Varint value too large".to_string()));
-        }
     }
 
     // Incomplete varint
-    Err(Error::CodecError("Incomplete varint data".to_string()))
+    Err(Error::UnexpectedEof { what: "varint".to_string(), offset: 0, needed: data.len() as u64 + 1, got: data.len() })
 }
 
 #[cfg(test)]
@@ -82,5 +102,9 @@ mod tests {
         // A varint that would result in a value > u64::MAX
         let data = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x01];
         assert!(decode_varint(&data).is_err());
+        // Bits past the 64th in the tenth byte are rejected, not dropped
+        let data = vec![0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0x03];
+        assert!(decode_varint(&data).is_err());
+        assert_eq!(decode_varint(&encode_varint(u64::MAX)).unwrap(), (u64::MAX, 10));
     }
 }
\ No newline at end of file
@@ -0,0 +1,347 @@
+// Wire format versions for HTLV items
+//
+// Version 1 is the original item layout: Tag (varint) + Type (1 byte) +
+// Length (varint) + Value. Version 2 is a backward-compatible extension for
+// small values: when the top bit of the type byte is set, the byte is a compact
+// header `1LLL TTTT` carrying the value type in the low nibble and the value
+// length (0 to 7 bytes) in bits 4-6, so no length varint follows. Integers are
+// additionally stored with their minimal number of little-endian bytes. A type
+// byte without the top bit starts a full version 1 header, and its value is
+// read as version 1 reads it, so version 1 output decodes unchanged: a batch
+// type holding more than one element is a packed batch, and the header item
+// and shards of a large field come out as separate items, as `decode_item`
+// returns them. A full header holding exactly one element is read as that
+// element, as version 2 writes integers of full width with a full header.
+// Extension types do not fit in the nibble and always use the full header.
+//
+// The version is negotiated per packet through the frame header (see
+// `MetadataHeader::set_wire_format`), so version 1 peers keep working.
+
+use crate::internal::error::{Error, Result};
+use crate::codec::external;
+use crate::codec::varint;
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use crate::codec::encode::encode_item;
+use crate::codec::decode::decode_item;
+use crate::codec::decode::batch_value_decoder::decode_batch_value;
+use crate::codec::decode::type_table::{self, ValueKind};
+use crate::codec::decode::decoder_state_machine::MAX_NESTING_DEPTH;
+use crate::internal::cursor::WireCursor;
+use bytes::Bytes;
+
+/// Flag marking a compact v2 item header.
+const COMPACT_HEADER_FLAG: u8 = 0x80;
+/// Largest value length that fits in a compact header.
+const COMPACT_MAX_LENGTH: usize = 7;
+
+/// Version of the item layout used in a packet body.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[repr(u8)]
+pub enum WireFormat {
+    /// Original layout with an explicit length on every item
+    #[default]
+    V1 = 0,
+    /// Compact headers and minimal-width integers for small values
+    V2 = 1,
+}
+
+impl WireFormat {
+    /// Converts a u8 value to a WireFormat.
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(WireFormat::V1),
+            1 => Ok(WireFormat::V2),
+            _ => Err(Error::CodecError(format!("Unknown wire format version: {}", value))),
+        }
+    }
+}
+
+/// Encodes an item using the given wire format.
+pub fn encode_item_with_format(item: &HtlvItem, format: WireFormat) -> Result<Vec<u8>> {
+    match format {
+        WireFormat::V1 => encode_item(item),
+        WireFormat::V2 => {
+            let mut out = Vec::new();
+            encode_item_v2(item, &mut out);
+            Ok(out)
+        }
+    }
+}
+
+/// Decodes an item encoded with the given wire format.
+/// Returns the decoded item and the number of bytes read.
+pub fn decode_item_with_format(data: &[u8], format: WireFormat) -> Result<(HtlvItem, usize)> {
+    match format {
+        WireFormat::V1 => decode_item(data),
+        WireFormat::V2 => decode_item_v2(data, 0, 0),
+    }
+}
+
+fn encode_item_v2(item: &HtlvItem, out: &mut Vec<u8>) {
+    let type_byte = item.value.type_byte();
+    let mut body = Vec::new();
+    match &item.value {
+        HtlvValue::Null => {}
+        HtlvValue::Bool(v) => body.push(*v as u8),
+        HtlvValue::U8(v) => push_unsigned(&mut body, *v as u64),
+        HtlvValue::U16(v) => push_unsigned(&mut body, *v as u64),
+        HtlvValue::U32(v) => push_unsigned(&mut body, *v as u64),
+        HtlvValue::U64(v) => push_unsigned(&mut body, *v),
+        HtlvValue::I8(v) => push_signed(&mut body, *v as i64),
+        HtlvValue::I16(v) => push_signed(&mut body, *v as i64),
+        HtlvValue::I32(v) => push_signed(&mut body, *v as i64),
+        HtlvValue::I64(v) => push_signed(&mut body, *v),
+        HtlvValue::F32(v) => body.extend_from_slice(&v.to_le_bytes()),
+        HtlvValue::F64(v) => body.extend_from_slice(&v.to_le_bytes()),
+        HtlvValue::Bytes(v) | HtlvValue::String(v) | HtlvValue::Extension(_, v) => body.extend_from_slice(v),
+        HtlvValue::ExternalRef { locator, length, hash } => external::encode_payload(locator, *length, hash, &mut body),
+        HtlvValue::Array(items) | HtlvValue::Object(items) => {
+            for sub_item in items {
+                encode_item_v2(sub_item, &mut body);
+            }
+        }
+    }
+
+    out.extend_from_slice(&varint::encode_varint(item.tag));
+    if body.len() <= COMPACT_MAX_LENGTH && type_byte <= 0x0F {
+        out.push(COMPACT_HEADER_FLAG | ((body.len() as u8) << 4) | type_byte);
+    } else {
+        out.push(type_byte);
+        out.extend_from_slice(&varint::encode_varint(body.len() as u64));
+    }
+    out.extend_from_slice(&body);
+}
+
+/// Appends the minimal little-endian bytes of an unsigned integer (none for zero).
+fn push_unsigned(body: &mut Vec<u8>, value: u64) {
+    let len = 8 - value.leading_zeros() as usize / 8;
+    body.extend_from_slice(&value.to_le_bytes()[..len]);
+}
+
+/// Appends the minimal little-endian bytes that sign-extend back to the value (none for zero).
+fn push_signed(body: &mut Vec<u8>, value: i64) {
+    let len = (0..8)
+        .find(|&len| sign_extend(&value.to_le_bytes()[..len]) == value)
+        .unwrap_or(8);
+    body.extend_from_slice(&value.to_le_bytes()[..len]);
+}
+
+fn sign_extend(bytes: &[u8]) -> i64 {
+    if bytes.is_empty() {
+        return 0;
+    }
+    let fill = if bytes[bytes.len() - 1] & 0x80 != 0 { 0xFF } else { 0x00 };
+    let mut buf = [fill; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    i64::from_le_bytes(buf)
+}
+
+fn zero_extend(bytes: &[u8]) -> u64 {
+    let mut buf = [0u8; 8];
+    buf[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(buf)
+}
+
+/// Decodes the item starting at `start`; `data` ends with the enclosing
+/// container, and the returned offset is that of the end of the item.
+///
+/// Compact headers carry minimal-width values; full headers are read as
+/// version 1 reads them (see the module header).
+fn decode_item_v2(data: &[u8], start: usize, depth: usize) -> Result<(HtlvItem, usize)> {
+    let mut cursor = WireCursor::at(data, start);
+    let tag = cursor.read_varint("item Tag")?;
+    let type_offset = cursor.position();
+    let type_byte = cursor.read_u8("Type byte")?;
+
+    let compact = type_byte & COMPACT_HEADER_FLAG != 0;
+    let (type_bits, length) = if compact {
+        (type_byte & 0x0F, ((type_byte >> 4) & 0x07) as u64)
+    } else {
+        (type_byte, cursor.read_varint("Length")?)
+    };
+    let value_type = HtlvValueType::from_byte(type_bits)
+        .ok_or(Error::UnknownType { type_byte: type_bits, offset: type_offset })?;
+
+    let value_start = cursor.position();
+    let value = cursor.take(length, "Value")?;
+    let value_end = cursor.position();
+
+    // A full header with more than one element of a batch type is a packed batch
+    let handler = type_table::handler(value_type);
+    if !compact && handler.kind == ValueKind::Batch && handler.element_size.is_some_and(|size| value.len() > size) {
+        (handler.validate)(value_type, length)?;
+        let batch = decode_batch_value(value_type, length, value)?;
+        return Ok((HtlvItem::new(tag, batch), value_end));
+    }
+
+    let check_width = |max: usize| -> Result<()> {
+        if value.len() > max {
+            return Err(Error::CodecError(format!(
+                "Invalid length {} for {:?} value", value.len(), value_type
+            )));
+        }
+        Ok(())
+    };
+
+    let decoded = match value_type {
+        HtlvValueType::Null => { check_width(0)?; HtlvValue::Null }
+        HtlvValueType::Bool => {
+            if value.len() != 1 {
+                return Err(Error::CodecError(format!("Invalid length {} for Bool value", value.len())));
+            }
+            HtlvValue::Bool(value[0] != 0)
+        }
+        HtlvValueType::U8 => { check_width(1)?; HtlvValue::U8(zero_extend(value) as u8) }
+        HtlvValueType::U16 => { check_width(2)?; HtlvValue::U16(zero_extend(value) as u16) }
+        HtlvValueType::U32 => { check_width(4)?; HtlvValue::U32(zero_extend(value) as u32) }
+        HtlvValueType::U64 => { check_width(8)?; HtlvValue::U64(zero_extend(value)) }
+        HtlvValueType::I8 => { check_width(1)?; HtlvValue::I8(sign_extend(value) as i8) }
+        HtlvValueType::I16 => { check_width(2)?; HtlvValue::I16(sign_extend(value) as i16) }
+        HtlvValueType::I32 => { check_width(4)?; HtlvValue::I32(sign_extend(value) as i32) }
+        HtlvValueType::I64 => { check_width(8)?; HtlvValue::I64(sign_extend(value)) }
+        HtlvValueType::F32 => {
+            let bytes: [u8; 4] = value.try_into()
+                .map_err(|_| Error::CodecError(format!("Invalid length {} for F32 value", value.len())))?;
+            HtlvValue::F32(f32::from_le_bytes(bytes))
+        }
+        HtlvValueType::F64 => {
+            let bytes: [u8; 8] = value.try_into()
+                .map_err(|_| Error::CodecError(format!("Invalid length {} for F64 value", value.len())))?;
+            HtlvValue::F64(f64::from_le_bytes(bytes))
+        }
+        HtlvValueType::Bytes => HtlvValue::Bytes(Bytes::copy_from_slice(value)),
+        HtlvValueType::String => HtlvValue::String(Bytes::copy_from_slice(value)),
+        HtlvValueType::Extension => HtlvValue::Extension(type_bits, Bytes::copy_from_slice(value)),
+        HtlvValueType::ExternalRef => external::decode_payload(value)?,
+        HtlvValueType::Array | HtlvValueType::Object => {
+            if depth >= MAX_NESTING_DEPTH {
+                return Err(Error::DepthExceeded { offset: value_start, limit: MAX_NESTING_DEPTH });
+            }
+            let mut items = Vec::new();
+            let mut offset = value_start;
+            while offset < value_end {
+                let (sub_item, sub_end) = decode_item_v2(&data[..value_end], offset, depth + 1)
+                    .map_err(Error::within_container)?;
+                items.push(sub_item);
+                offset = sub_end;
+            }
+            if value_type == HtlvValueType::Array {
+                HtlvValue::Array(items)
+            } else {
+                HtlvValue::Object(items)
+            }
+        }
+    };
+
+    Ok((HtlvItem::new(tag, decoded), value_end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::LARGE_FIELD_THRESHOLD;
+
+    fn telemetry() -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U64(5)),
+            HtlvItem::new(3, HtlvValue::I32(-2)),
+            HtlvItem::new(4, HtlvValue::I64(i64::MIN)),
+            HtlvItem::new(5, HtlvValue::U32(u32::MAX)),
+            HtlvItem::new(6, HtlvValue::Bool(true)),
+            HtlvItem::new(7, HtlvValue::F32(1.5)),
+            HtlvItem::new(8, HtlvValue::F64(-0.25)),
+            HtlvItem::new(9, HtlvValue::String(Bytes::from_static(b"ok"))),
+            HtlvItem::new(10, HtlvValue::Bytes(Bytes::from_static(b"a longer payload"))),
+            HtlvItem::new(11, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::Null),
+                HtlvItem::new(0, HtlvValue::U16(0)),
+                HtlvItem::new(0, HtlvValue::I8(127)),
+            ])),
+        ]))
+    }
+
+    #[test]
+    fn test_v2_round_trip() {
+        let item = telemetry();
+        let encoded = encode_item_with_format(&item, WireFormat::V2).unwrap();
+        let (decoded, bytes_read) = decode_item_with_format(&encoded, WireFormat::V2).unwrap();
+        assert_eq!(decoded, item);
+        assert_eq!(bytes_read, encoded.len());
+    }
+
+    #[test]
+    fn test_v2_is_smaller_for_small_fields() {
+        let small = HtlvItem::new(2, HtlvValue::U64(5));
+        assert_eq!(encode_item(&small).unwrap().len(), 11);
+        assert_eq!(encode_item_with_format(&small, WireFormat::V2).unwrap(), vec![0x02, 0x95, 0x05]);
+
+        let item = telemetry();
+        let v1 = encode_item_with_format(&item, WireFormat::V1).unwrap();
+        let v2 = encode_item_with_format(&item, WireFormat::V2).unwrap();
+        assert_eq!(v1, encode_item(&item).unwrap());
+        assert!(v2.len() * 100 < v1.len() * 85);
+    }
+
+    #[test]
+    fn test_v2_reads_v1_headers() {
+        // A v1-style header (no compact flag) with a full-width value is valid v2
+        let v1 = encode_item(&HtlvItem::new(3, HtlvValue::U32(7))).unwrap();
+        let (decoded, _) = decode_item_with_format(&v1, WireFormat::V2).unwrap();
+        assert_eq!(decoded, HtlvItem::new(3, HtlvValue::U32(7)));
+    }
+
+    #[test]
+    fn test_v2_reads_v1_output() {
+        // Packed batches and a sharded field, nested and at the top level
+        let large = HtlvValue::Bytes(Bytes::from(vec![7u8; LARGE_FIELD_THRESHOLD + 5]));
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::U32(1)),
+                HtlvItem::new(0, HtlvValue::U32(u32::MAX)),
+            ])),
+            HtlvItem::new(3, HtlvValue::Array(vec![
+                HtlvItem::new(0, HtlvValue::F64(0.5)),
+                HtlvItem::new(0, HtlvValue::F64(-2.0)),
+                HtlvItem::new(0, HtlvValue::F64(8.0)),
+            ])),
+            HtlvItem::new(4, large.clone()),
+            HtlvItem::new(5, HtlvValue::String(Bytes::from_static(b"tail"))),
+        ]));
+        let encoded = encode_item(&item).unwrap();
+        let (decoded, bytes_read) = decode_item_with_format(&encoded, WireFormat::V2).unwrap();
+        assert_eq!(bytes_read, encoded.len());
+        assert_eq!(decoded, decode_item(&encoded).unwrap().0);
+
+        // A top-level sharded field yields its header item and then its shards
+        let encoded = encode_item(&HtlvItem::new(6, large)).unwrap();
+        let mut offset = 0;
+        while offset < encoded.len() {
+            let (v2, v2_read) = decode_item_with_format(&encoded[offset..], WireFormat::V2).unwrap();
+            let (v1, v1_read) = decode_item(&encoded[offset..]).unwrap();
+            assert_eq!((v2, v2_read), (v1, v1_read));
+            offset += v2_read;
+        }
+        assert_eq!(offset, encoded.len());
+
+        // A batch whose length is not a multiple of the element size is rejected
+        let data = [0x01, HtlvValueType::U32 as u8, 6, 1, 2, 3, 4, 5, 6];
+        assert!(decode_item_with_format(&data, WireFormat::V2).is_err());
+    }
+
+    #[test]
+    fn test_v2_rejects_oversized_values() {
+        // Compact U16 header claiming 3 value bytes
+        let data = [0x01, COMPACT_HEADER_FLAG | (3 << 4) | HtlvValueType::U16 as u8, 1, 2, 3];
+        assert!(decode_item_with_format(&data, WireFormat::V2).is_err());
+        // Truncated value
+        let data = [0x01, COMPACT_HEADER_FLAG | (2 << 4) | HtlvValueType::U16 as u8, 1];
+        assert!(decode_item_with_format(&data, WireFormat::V2).is_err());
+    }
+
+    #[test]
+    fn test_wire_format_from_u8() {
+        assert_eq!(WireFormat::from_u8(0).unwrap(), WireFormat::V1);
+        assert_eq!(WireFormat::from_u8(1).unwrap(), WireFormat::V2);
+        assert!(WireFormat::from_u8(2).is_err());
+    }
+}
@@ -1,7 +1,7 @@
 use crate::internal::error::{Error, Result};
 use super::Compressor; // Import the Compressor trait
 use brotli; // Import the brotli crate
-use std::io::{Read, Write};
+use std::io::{self, Read, Write};
 
 /// Compresses data using Brotli algorithm.
 pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
@@ -19,6 +19,21 @@ pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
     Ok(decompressed_data)
 }
 
+/// Compresses data using Brotli algorithm, appending the result to `out`.
+pub fn compress_into(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let mut writer = brotli::CompressorWriter::new(out, 4096, 11, 22);
+    writer.write_all(data).map_err(|e| Error::CompressionError(format!("Brotli compression failed: {}", e)))?;
+    writer.flush().map_err(|e| Error::CompressionError(format!("Brotli compression flush failed: {}", e)))?;
+    Ok(())
+}
+
+/// Decompresses data using Brotli algorithm, appending the result to `out`.
+pub fn decompress_into(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let mut reader = brotli::Decompressor::new(data, 4096);
+    reader.read_to_end(out).map_err(|e| Error::CompressionError(format!("Brotli decompression failed: {}", e)))?;
+    Ok(())
+}
+
 /// Brotli Compressor implementation.
 #[derive(Debug)]
 pub struct BrotliCompressor;
@@ -33,6 +48,28 @@ impl Compressor for BrotliCompressor {
         // Call the specific decompression function
         decompress(data)
     }
+
+    fn compress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        compress_into(data, out)
+    }
+
+    fn decompress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        decompress_into(data, out)
+    }
+
+    fn compress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        let mut compressor = brotli::CompressorWriter::new(writer, 4096, 11, 22);
+        let read = io::copy(reader, &mut compressor)
+            .map_err(|e| Error::CompressionError(format!("Brotli compression failed: {}", e)))?;
+        compressor.flush().map_err(|e| Error::CompressionError(format!("Brotli compression flush failed: {}", e)))?;
+        Ok(read)
+    }
+
+    fn decompress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        let mut decompressor = brotli::Decompressor::new(reader, 4096);
+        io::copy(&mut decompressor, writer)
+            .map_err(|e| Error::CompressionError(format!("Brotli decompression failed: {}", e)))
+    }
 }
 
 #[cfg(test)]
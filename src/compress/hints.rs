@@ -0,0 +1,312 @@
+use crate::codec::types::{HtlvItem, HtlvValue};
+use super::{magic, CompressionStrategy};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::hash::{Hash, Hasher};
+
+/// Default number of samples kept per field.
+pub const DEFAULT_WINDOW_SIZE: usize = 64;
+
+/// Entropy (in bits per byte) above which data is considered not worth compressing.
+pub const HIGH_ENTROPY_THRESHOLD: f64 = 7.5;
+
+/// Minimum value size in bytes for the entropy estimate to be meaningful.
+/// Shorter values cannot reach a high byte entropy even when random.
+const MIN_ENTROPY_SAMPLE_SIZE: usize = 64;
+
+/// Estimates the Shannon entropy of the data in bits per byte (0.0 to 8.0).
+pub fn estimate_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+
+    let mut histogram = [0usize; 256];
+    for &byte in data {
+        histogram[byte as usize] += 1;
+    }
+
+    let len = data.len() as f64;
+    histogram
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Returns true if the data starts with the magic number of an already-compressed
+/// format (see `magic::PrecompressedFormat`), which would not benefit from further compression.
+pub fn is_precompressed(data: &[u8]) -> bool {
+    magic::detect_format(data).is_some()
+}
+
+/// A single observation of a field value.
+#[derive(Debug, Clone)]
+struct FieldSample {
+    /// Hash of the value, used for the cardinality estimate.
+    value_hash: u64,
+    /// Size of the value in bytes.
+    size: usize,
+    /// Entropy of the value in bits per byte.
+    entropy: f64,
+    /// Whether the value looks like already-compressed data.
+    precompressed: bool,
+}
+
+impl FieldSample {
+    fn from_value(value: &HtlvValue) -> Self {
+        let bytes: Vec<u8> = match value {
+            HtlvValue::Null => Vec::new(),
+            HtlvValue::Bool(v) => vec![*v as u8],
+            HtlvValue::U8(v) => vec![*v],
+            HtlvValue::U16(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::U32(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::U64(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::I8(v) => vec![*v as u8],
+            HtlvValue::I16(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::I32(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::I64(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::F32(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::F64(v) => v.to_le_bytes().to_vec(),
+            HtlvValue::Bytes(v) | HtlvValue::String(v) | HtlvValue::Extension(_, v) => v.to_vec(),
+            HtlvValue::ExternalRef { locator, .. } => locator.as_bytes().to_vec(),
+            // Complex values are not sampled themselves, only their children
+            HtlvValue::Array(_) | HtlvValue::Object(_) => Vec::new(),
+        };
+
+        let mut hasher = DefaultHasher::new();
+        bytes.hash(&mut hasher);
+
+        let precompressed = matches!(value, HtlvValue::Bytes(_)) && is_precompressed(&bytes);
+
+        FieldSample {
+            value_hash: hasher.finish(),
+            size: bytes.len(),
+            entropy: estimate_entropy(&bytes),
+            precompressed,
+        }
+    }
+}
+
+/// Compression hint for a single field, derived from a window of observed values.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldHint {
+    /// The field tag.
+    pub tag: u64,
+    /// Number of samples in the window.
+    pub samples: usize,
+    /// Number of distinct values in the window.
+    pub cardinality: usize,
+    /// Average entropy of the sampled values, in bits per byte.
+    pub entropy: f64,
+    /// Average size of the sampled values, in bytes.
+    pub average_size: f64,
+    /// Whether the field looks incompressible (already-compressed or high-entropy data).
+    pub incompressible: bool,
+}
+
+impl FieldHint {
+    /// Returns true if the field repeats a small set of values, which compresses well.
+    pub fn is_low_cardinality(&self) -> bool {
+        self.samples > 1 && self.cardinality * 4 <= self.samples
+    }
+}
+
+/// Collects per-field statistics over a sliding window of encoded items.
+///
+/// Fields are identified by their tag. Values nested inside arrays and objects
+/// are recorded under their own tags, so fields that share a tag at different
+/// nesting levels are aggregated together.
+#[derive(Debug)]
+pub struct FieldStatsCollector {
+    /// The maximum number of samples kept per field.
+    window_size: usize,
+    /// Sample windows, keyed by field tag.
+    fields: HashMap<u64, VecDeque<FieldSample>>,
+}
+
+impl Default for FieldStatsCollector {
+    fn default() -> Self {
+        FieldStatsCollector {
+            window_size: DEFAULT_WINDOW_SIZE,
+            fields: HashMap::new(),
+        }
+    }
+}
+
+impl FieldStatsCollector {
+    /// Creates a new FieldStatsCollector with the default window size.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new FieldStatsCollector with the specified window size.
+    pub fn with_window_size(window_size: usize) -> Self {
+        FieldStatsCollector {
+            window_size: window_size.max(1),
+            fields: HashMap::new(),
+        }
+    }
+
+    /// Records the values of an item and all of its nested items.
+    pub fn observe(&mut self, item: &HtlvItem) {
+        match &item.value {
+            HtlvValue::Array(items) | HtlvValue::Object(items) => {
+                for sub_item in items {
+                    self.observe(sub_item);
+                }
+            }
+            value => {
+                let window = self.fields.entry(item.tag).or_default();
+                if window.len() == self.window_size {
+                    window.pop_front();
+                }
+                window.push_back(FieldSample::from_value(value));
+            }
+        }
+    }
+
+    /// Returns the compression hint for a field, or `None` if it has not been observed.
+    pub fn hint(&self, tag: u64) -> Option<FieldHint> {
+        let window = self.fields.get(&tag)?;
+        if window.is_empty() {
+            return None;
+        }
+
+        let samples = window.len();
+        let cardinality = window.iter().map(|s| s.value_hash).collect::<HashSet<_>>().len();
+        let entropy = window.iter().map(|s| s.entropy).sum::<f64>() / samples as f64;
+        let average_size = window.iter().map(|s| s.size).sum::<usize>() as f64 / samples as f64;
+        let precompressed = window.iter().filter(|s| s.precompressed).count();
+
+        let incompressible = precompressed * 2 > samples
+            || (average_size >= MIN_ENTROPY_SAMPLE_SIZE as f64 && entropy > HIGH_ENTROPY_THRESHOLD);
+
+        Some(FieldHint {
+            tag,
+            samples,
+            cardinality,
+            entropy,
+            average_size,
+            incompressible,
+        })
+    }
+
+    /// Returns the compression hints for all observed fields, ordered by tag.
+    pub fn hints(&self) -> Vec<FieldHint> {
+        let mut tags: Vec<u64> = self.fields.keys().copied().collect();
+        tags.sort_unstable();
+        tags.into_iter().filter_map(|tag| self.hint(tag)).collect()
+    }
+
+    /// Clears all collected statistics.
+    pub fn clear(&mut self) {
+        self.fields.clear();
+    }
+}
+
+/// Suggests a compression strategy from field hints, weighting each field by its size.
+///
+/// Returns `None` if the hints are not conclusive, in which case the caller should
+/// fall back to inspecting the data itself.
+pub fn suggest_strategy(hints: &[FieldHint]) -> Option<CompressionStrategy> {
+    let total_size: f64 = hints.iter().map(|h| h.average_size).sum();
+    if total_size == 0.0 {
+        return None;
+    }
+
+    let incompressible_size: f64 = hints
+        .iter()
+        .filter(|h| h.incompressible)
+        .map(|h| h.average_size)
+        .sum();
+    if incompressible_size * 2.0 > total_size {
+        // Most of the payload would not shrink, skip compression altogether
+        return Some(CompressionStrategy::NoCompression);
+    }
+
+    let repetitive_size: f64 = hints
+        .iter()
+        .filter(|h| h.is_low_cardinality())
+        .map(|h| h.average_size)
+        .sum();
+    if repetitive_size * 2.0 > total_size {
+        // Repeated values are handled well by Zstd at a low CPU cost
+        return Some(CompressionStrategy::Zstd);
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_estimate_entropy() {
+        assert_eq!(estimate_entropy(b""), 0.0);
+        assert_eq!(estimate_entropy(&[b'a'; 100]), 0.0);
+
+        let uniform: Vec<u8> = (0..=255).collect();
+        assert!((estimate_entropy(&uniform) - 8.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_is_precompressed() {
+        assert!(is_precompressed(&[0xFF, 0xD8, 0xFF, 0xE0, 0x00]));
+        assert!(is_precompressed(b"PK\x03\x04rest"));
+        assert!(!is_precompressed(b"plain text"));
+    }
+
+    #[test]
+    fn test_collector_cardinality_and_window() {
+        let mut collector = FieldStatsCollector::with_window_size(8);
+        for i in 0..20u64 {
+            let item = HtlvItem::new(0, HtlvValue::Object(vec![
+                HtlvItem::new(1, HtlvValue::U64(i % 2)),
+                HtlvItem::new(2, HtlvValue::U64(i)),
+            ]));
+            collector.observe(&item);
+        }
+
+        let status = collector.hint(1).unwrap();
+        assert_eq!(status.samples, 8);
+        assert_eq!(status.cardinality, 2);
+        assert!(status.is_low_cardinality());
+
+        let counter = collector.hint(2).unwrap();
+        assert_eq!(counter.cardinality, 8);
+        assert!(!counter.is_low_cardinality());
+
+        assert!(collector.hint(3).is_none());
+        assert_eq!(collector.hints().len(), 2);
+    }
+
+    #[test]
+    fn test_suggest_strategy_skips_precompressed_fields() {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        jpeg.extend_from_slice(&[0x42; 1000]);
+
+        let mut collector = FieldStatsCollector::new();
+        collector.observe(&HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String(Bytes::from_static(b"photo.jpg"))),
+            HtlvItem::new(2, HtlvValue::Bytes(Bytes::from(jpeg))),
+        ])));
+
+        assert!(collector.hint(2).unwrap().incompressible);
+        assert!(!collector.hint(1).unwrap().incompressible);
+        assert_eq!(suggest_strategy(&collector.hints()), Some(CompressionStrategy::NoCompression));
+    }
+
+    #[test]
+    fn test_suggest_strategy_inconclusive() {
+        assert_eq!(suggest_strategy(&[]), None);
+
+        let mut collector = FieldStatsCollector::new();
+        collector.observe(&HtlvItem::new(1, HtlvValue::String(Bytes::from_static(b"a unique message"))));
+        assert_eq!(suggest_strategy(&collector.hints()), None);
+    }
+}
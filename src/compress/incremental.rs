@@ -1,5 +1,8 @@
-use crate::internal::error::Result;
-use super::{Compressor, CompressionStrategy, get_compressor};
+use crate::internal::error::{Error, Result};
+use crate::internal::memory::{MemoryBudget, MemorySubsystem};
+use crate::codec::varint::{decode_varint, encode_varint};
+use crate::internal::cursor::WireCursor;
+use super::{zstd, Compressor, CompressionStrategy, get_compressor};
 use std::fmt::Debug;
 use std::collections::HashMap;
 
@@ -7,6 +10,11 @@ use std::collections::HashMap;
 /// This is to prevent memory leaks if many different context IDs are used.
 const MAX_CONTEXT_CACHE_SIZE: usize = 100;
 
+/// Magic number at the start of a compressor snapshot.
+const SNAPSHOT_MAGIC: &[u8; 4] = b"TNIC";
+/// Version of the snapshot format.
+const SNAPSHOT_VERSION: u8 = 2;
+
 /// Represents a compression context for incremental compression.
 #[derive(Debug, Clone)]
 struct CompressionContext {
@@ -16,6 +24,8 @@ struct CompressionContext {
     dictionary: Vec<u8>,
     /// The maximum size of the dictionary.
     max_dict_size: usize,
+    /// Number of dictionary updates so far; both ends of a stream must agree on it.
+    generation: u64,
 }
 
 impl CompressionContext {
@@ -25,11 +35,19 @@ impl CompressionContext {
             strategy,
             dictionary: Vec::new(),
             max_dict_size,
+            generation: 0,
         }
     }
 
-    /// Updates the dictionary with new data.
+    /// Returns the size the dictionary will have after `update_dictionary(data)`.
+    fn dictionary_size_after(&self, data: &[u8]) -> usize {
+        std::cmp::min(self.dictionary.len() + data.len(), self.max_dict_size)
+    }
+
+    /// Updates the dictionary with new data and advances the generation.
     fn update_dictionary(&mut self, data: &[u8]) {
+        self.generation += 1;
+
         // If the new data alone is larger than max_dict_size, just use a portion of it
         if data.len() >= self.max_dict_size {
             // Use only the last max_dict_size bytes of the data
@@ -59,7 +77,15 @@ impl CompressionContext {
 /// Compressor that supports incremental compression.
 ///
 /// This compressor maintains a dictionary of previously seen data for each context ID,
-/// allowing for more efficient compression of similar data over time.
+/// allowing for more efficient compression of similar data over time. With the Zstd
+/// strategy the dictionary is passed to zstd as a raw-content dictionary; other
+/// strategies compress without it.
+///
+/// Each compressed message starts with the varint-encoded dictionary generation it
+/// was compressed with, followed by the compressed payload. The sender and the
+/// receiver of a stream each keep their own compressor, and messages must be
+/// decompressed in the order they were compressed; a message whose generation does
+/// not match the receiving context is rejected.
 #[derive(Debug)]
 pub struct IncrementalCompressor {
     /// The default compression strategy to use.
@@ -68,6 +94,8 @@ pub struct IncrementalCompressor {
     max_dict_size: usize,
     /// Cache of compression contexts, keyed by context ID.
     contexts: HashMap<u64, CompressionContext>,
+    /// Optional memory budget the dictionaries are accounted against.
+    memory_budget: Option<MemoryBudget>,
 }
 
 impl Default for IncrementalCompressor {
@@ -76,6 +104,7 @@ impl Default for IncrementalCompressor {
             default_strategy: CompressionStrategy::Zstd, // Default to Zstd
             max_dict_size: 64 * 1024, // 64KB default dictionary size
             contexts: HashMap::new(),
+            memory_budget: None,
         }
     }
 }
@@ -87,6 +116,7 @@ impl IncrementalCompressor {
             default_strategy,
             max_dict_size: 64 * 1024, // 64KB default dictionary size
             contexts: HashMap::new(),
+            memory_budget: None,
         }
     }
 
@@ -96,52 +126,82 @@ impl IncrementalCompressor {
             default_strategy,
             max_dict_size,
             contexts: HashMap::new(),
+            memory_budget: None,
         }
     }
 
+    /// Sets the memory budget that context dictionaries are accounted against.
+    ///
+    /// Dictionary growth that would exceed the budget makes `compress_with_context`
+    /// and `decompress_with_context` fail with `Error::MemoryLimitExceeded`.
+    /// Existing dictionaries are moved over to the new budget, which fails if they
+    /// do not fit in it.
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) -> Result<()> {
+        let total = self.total_dictionary_size();
+        budget.reserve(MemorySubsystem::Dictionaries, total)?;
+        self.release_dictionary(total);
+        self.memory_budget = Some(budget);
+        Ok(())
+    }
+
+    /// Returns the total size of all context dictionaries in bytes.
+    pub fn total_dictionary_size(&self) -> usize {
+        self.contexts.values().map(|context| context.dictionary.len()).sum()
+    }
+
     /// Compresses data incrementally using the specified context ID.
     ///
     /// The context ID is used to identify a stream of related data that can benefit from
     /// incremental compression. For example, all messages in a specific conversation could
     /// use the same context ID.
     ///
-    /// Returns the compressed data.
+    /// Returns the dictionary generation header followed by the compressed data.
     pub fn compress_with_context(&mut self, data: &[u8], context_id: u64) -> Result<Vec<u8>> {
         // Get or create the context
         let context = self.get_or_create_context(context_id);
 
-        // Get the appropriate compressor for the context's strategy
-        let compressor = get_compressor(context.strategy)?;
-
-        // Compress the data
-        // Note: In a real implementation with dictionary compression,
-        // you would use the dictionary with the compression algorithm.
-        // For now, we'll just compress normally for demonstration
-        let compressed = compressor.compress(data)?;
+        // Write the generation of the dictionary the data is compressed with
+        let mut compressed = encode_varint(context.generation);
+        if context.strategy == CompressionStrategy::Zstd && !context.dictionary.is_empty() {
+            zstd::compress_with_dictionary(data, &context.dictionary, &mut compressed)?;
+        } else {
+            get_compressor(context.strategy)?.compress_into(data, &mut compressed)?;
+        }
 
         // Update the context's dictionary with the new data
-        self.update_context_dictionary(context_id, data);
+        self.update_context_dictionary(context_id, data)?;
 
         // Return the compressed data
         Ok(compressed)
     }
 
     /// Decompresses data that was compressed incrementally using the specified context ID.
+    ///
+    /// Fails if the data was compressed with a different dictionary generation than
+    /// the context is at, e.g. when messages are lost, reordered or decompressed twice.
     pub fn decompress_with_context(&mut self, data: &[u8], context_id: u64) -> Result<Vec<u8>> {
         // Get or create the context
         let context = self.get_or_create_context(context_id);
 
-        // Get the appropriate compressor for the context's strategy
-        let compressor = get_compressor(context.strategy)?;
+        // Check that the data was compressed with the dictionary this context holds
+        let (generation, header_length) = decode_varint(data)?;
+        if generation != context.generation {
+            return Err(Error::CompressionError(format!(
+                "Dictionary generation mismatch for context {}: data has generation {}, context is at {}",
+                context_id, generation, context.generation
+            )));
+        }
 
-        // Decompress the data
-        // Note: In a real implementation with dictionary compression,
-        // you would use the dictionary with the decompression algorithm.
-        // For now, we'll just decompress normally for demonstration
-        let decompressed = compressor.decompress(data)?;
+        let payload = &data[header_length..];
+        let mut decompressed = Vec::new();
+        if context.strategy == CompressionStrategy::Zstd && !context.dictionary.is_empty() {
+            zstd::decompress_with_dictionary(payload, &context.dictionary, &mut decompressed)?;
+        } else {
+            get_compressor(context.strategy)?.decompress_into(payload, &mut decompressed)?;
+        }
 
         // Update the context's dictionary with the decompressed data
-        self.update_context_dictionary(context_id, &decompressed);
+        self.update_context_dictionary(context_id, &decompressed)?;
 
         // Return the decompressed data
         Ok(decompressed)
@@ -153,7 +213,7 @@ impl IncrementalCompressor {
         if self.contexts.len() > MAX_CONTEXT_CACHE_SIZE {
             // This is a simple approach: just clear the entire cache
             // A more sophisticated approach would use LRU or similar
-            self.contexts.clear();
+            self.clear_all_contexts();
         }
 
         // Get or create the context
@@ -163,25 +223,117 @@ impl IncrementalCompressor {
     }
 
     /// Updates the dictionary of a compression context with new data.
-    fn update_context_dictionary(&mut self, context_id: u64, data: &[u8]) {
+    fn update_context_dictionary(&mut self, context_id: u64, data: &[u8]) -> Result<()> {
         // This is a bit awkward due to Rust's borrowing rules
         // We need to clone the data first, then update the context
         let data_clone = data.to_vec();
 
         if let Some(context) = self.contexts.get_mut(&context_id) {
+            // Account for dictionary growth before it happens
+            let old_size = context.dictionary.len();
+            let new_size = context.dictionary_size_after(&data_clone);
+            if let Some(budget) = &self.memory_budget {
+                if new_size > old_size {
+                    budget.reserve(MemorySubsystem::Dictionaries, new_size - old_size)?;
+                } else {
+                    budget.release(MemorySubsystem::Dictionaries, old_size - new_size);
+                }
+            }
             context.update_dictionary(&data_clone);
         }
+        Ok(())
+    }
+
+    /// Releases the budget accounted for a dictionary of the given size.
+    fn release_dictionary(&self, size: usize) {
+        if let Some(budget) = &self.memory_budget {
+            budget.release(MemorySubsystem::Dictionaries, size);
+        }
     }
 
     /// Clears the context for the specified context ID.
     pub fn clear_context(&mut self, context_id: u64) {
-        self.contexts.remove(&context_id);
+        if let Some(context) = self.contexts.remove(&context_id) {
+            self.release_dictionary(context.dictionary.len());
+        }
     }
 
     /// Clears all contexts.
     pub fn clear_all_contexts(&mut self) {
+        self.release_dictionary(self.total_dictionary_size());
         self.contexts.clear();
     }
+
+    /// Serializes the contexts and their dictionaries, so that a restarted or
+    /// migrated process can continue the same incremental streams.
+    ///
+    /// The memory budget is not part of the snapshot. Dictionaries hold recent
+    /// plaintext, so snapshots must be protected like the data itself.
+    pub fn snapshot(&self) -> Vec<u8> {
+        let mut out = SNAPSHOT_MAGIC.to_vec();
+        out.push(SNAPSHOT_VERSION);
+        out.push(self.default_strategy as u8);
+        out.extend_from_slice(&encode_varint(self.max_dict_size as u64));
+
+        // Sort the contexts so that equal states give equal snapshots
+        let mut context_ids: Vec<&u64> = self.contexts.keys().collect();
+        context_ids.sort();
+        out.extend_from_slice(&encode_varint(context_ids.len() as u64));
+        for context_id in context_ids {
+            let context = &self.contexts[context_id];
+            out.extend_from_slice(&encode_varint(*context_id));
+            out.push(context.strategy as u8);
+            out.extend_from_slice(&encode_varint(context.max_dict_size as u64));
+            out.extend_from_slice(&encode_varint(context.generation));
+            out.extend_from_slice(&encode_varint(context.dictionary.len() as u64));
+            out.extend_from_slice(&context.dictionary);
+        }
+        out
+    }
+
+    /// Recreates a compressor from a snapshot taken by `snapshot`.
+    ///
+    /// The restored compressor has no memory budget; set one with
+    /// `set_memory_budget` before use if needed.
+    pub fn restore(data: &[u8]) -> Result<Self> {
+        if !data.starts_with(SNAPSHOT_MAGIC) {
+            return Err(Error::CompressionError("Missing compressor snapshot magic number".to_string()));
+        }
+        let mut reader = WireCursor::at(data, SNAPSHOT_MAGIC.len())
+            .with_truncation_error(Error::CompressionError);
+        let version = reader.read_u8("snapshot version")?;
+        if version != SNAPSHOT_VERSION {
+            return Err(Error::CompressionError(format!("Unsupported compressor snapshot version {}", version)));
+        }
+
+        let default_strategy = CompressionStrategy::from_u8(reader.read_u8("default strategy")?)?;
+        let max_dict_size = reader.read_varint("maximum dictionary size")? as usize;
+        let mut compressor = Self::with_dict_size(default_strategy, max_dict_size);
+        let context_count = reader.read_varint("context count")?;
+        for _ in 0..context_count {
+            let context_id = reader.read_varint("context ID")?;
+            let strategy = CompressionStrategy::from_u8(reader.read_u8("context strategy")?)?;
+            let mut context = CompressionContext::new(strategy, reader.read_varint("maximum dictionary size")? as usize);
+            context.generation = reader.read_varint("dictionary generation")?;
+            context.dictionary = reader.read_length_prefixed("dictionary")?.to_vec();
+            if context.dictionary.len() > context.max_dict_size {
+                return Err(Error::CompressionError(format!(
+                    "Dictionary of context {} exceeds its maximum size", context_id
+                )));
+            }
+            compressor.contexts.insert(context_id, context);
+        }
+        if !reader.is_empty() {
+            return Err(Error::CompressionError("Trailing bytes after compressor snapshot".to_string()));
+        }
+        Ok(compressor)
+    }
+}
+
+impl Drop for IncrementalCompressor {
+    fn drop(&mut self) {
+        self.release_dictionary(self.total_dictionary_size());
+    }
 }
 
 impl Compressor for IncrementalCompressor {
@@ -221,15 +373,16 @@ mod tests {
         // Create a test data
         let original_data = b"This is a test string for incremental compression.";
 
-        // Create an incremental compressor with Zstd strategy
+        // Create incremental compressors with Zstd strategy for both ends of the stream
         let mut compressor = IncrementalCompressor::new(CompressionStrategy::Zstd);
+        let mut decompressor = IncrementalCompressor::new(CompressionStrategy::Zstd);
 
         // Compress the data with a context ID
         let context_id = 1;
         let compressed_data = compressor.compress_with_context(original_data, context_id).unwrap();
 
         // Decompress the data with the same context ID
-        let decompressed_data = compressor.decompress_with_context(&compressed_data, context_id).unwrap();
+        let decompressed_data = decompressor.decompress_with_context(&compressed_data, context_id).unwrap();
 
         // Verify the decompressed data matches the original
         assert_eq!(decompressed_data, original_data.to_vec());
@@ -241,8 +394,9 @@ mod tests {
         let data1 = b"This is data for context 1.";
         let data2 = b"This is completely different data for context 2.";
 
-        // Create an incremental compressor
+        // Create incremental compressors for both ends of the streams
         let mut compressor = IncrementalCompressor::default();
+        let mut decompressor = IncrementalCompressor::default();
 
         // Compress data with different context IDs
         let context1_id = 1;
@@ -251,8 +405,8 @@ mod tests {
         let compressed2 = compressor.compress_with_context(data2, context2_id).unwrap();
 
         // Decompress data with the same context IDs
-        let decompressed1 = compressor.decompress_with_context(&compressed1, context1_id).unwrap();
-        let decompressed2 = compressor.decompress_with_context(&compressed2, context2_id).unwrap();
+        let decompressed1 = decompressor.decompress_with_context(&compressed1, context1_id).unwrap();
+        let decompressed2 = decompressor.decompress_with_context(&compressed2, context2_id).unwrap();
 
         // Verify the decompressed data matches the original
         assert_eq!(decompressed1, data1.to_vec());
@@ -261,8 +415,9 @@ mod tests {
 
     #[test]
     fn test_incremental_compression_sequential_data() {
-        // Create an incremental compressor
+        // Create incremental compressors for both ends of the stream
         let mut compressor = IncrementalCompressor::default();
+        let mut decompressor = IncrementalCompressor::default();
         let context_id = 1;
 
         // Compress and decompress a sequence of related data
@@ -276,14 +431,51 @@ mod tests {
         let compressed3 = compressor.compress_with_context(data3, context_id).unwrap();
 
         // Decompress each part
-        let decompressed1 = compressor.decompress_with_context(&compressed1, context_id).unwrap();
-        let decompressed2 = compressor.decompress_with_context(&compressed2, context_id).unwrap();
-        let decompressed3 = compressor.decompress_with_context(&compressed3, context_id).unwrap();
+        let decompressed1 = decompressor.decompress_with_context(&compressed1, context_id).unwrap();
+        let decompressed2 = decompressor.decompress_with_context(&compressed2, context_id).unwrap();
+        let decompressed3 = decompressor.decompress_with_context(&compressed3, context_id).unwrap();
 
         // Verify the decompressed data matches the original
         assert_eq!(decompressed1, data1.to_vec());
         assert_eq!(decompressed2, data2.to_vec());
         assert_eq!(decompressed3, data3.to_vec());
+        assert_eq!(decompressor.contexts[&context_id].dictionary, compressor.contexts[&context_id].dictionary);
+    }
+
+    #[test]
+    fn test_dictionary_improves_repetitive_stream() {
+        let message = b"{\"user\":\"alice\",\"action\":\"update\",\"fields\":[\"name\",\"email\",\"address\"]}";
+        let mut compressor = IncrementalCompressor::default();
+        let mut decompressor = IncrementalCompressor::default();
+
+        let first = compressor.compress_with_context(message, 1).unwrap();
+        let second = compressor.compress_with_context(message, 1).unwrap();
+        assert_eq!(first[0], 0);
+        assert_eq!(second[0], 1);
+        // The second message is mostly a reference into the dictionary
+        assert!(second.len() < first.len() / 2, "{} vs {}", second.len(), first.len());
+
+        assert_eq!(decompressor.decompress_with_context(&first, 1).unwrap(), message);
+        assert_eq!(decompressor.decompress_with_context(&second, 1).unwrap(), message);
+    }
+
+    #[test]
+    fn test_generation_mismatch_is_rejected() {
+        let mut compressor = IncrementalCompressor::default();
+        let mut decompressor = IncrementalCompressor::default();
+        let first = compressor.compress_with_context(b"first message", 1).unwrap();
+        let second = compressor.compress_with_context(b"second message", 1).unwrap();
+
+        // Skipping a message leaves the receiver one generation behind
+        let err = decompressor.decompress_with_context(&second, 1).unwrap_err();
+        assert!(err.to_string().contains("generation mismatch"), "{}", err);
+        assert_eq!(decompressor.contexts[&1].generation, 0);
+
+        assert_eq!(decompressor.decompress_with_context(&first, 1).unwrap(), b"first message");
+        assert_eq!(decompressor.decompress_with_context(&second, 1).unwrap(), b"second message");
+
+        // Replaying a message is rejected as well
+        assert!(decompressor.decompress_with_context(&first, 1).is_err());
     }
 
     #[test]
@@ -338,6 +530,31 @@ mod tests {
         assert!(compressor.contexts.len() <= MAX_CONTEXT_CACHE_SIZE);
     }
 
+    #[test]
+    fn test_dictionary_memory_budget() {
+        let budget = MemoryBudget::unlimited();
+        budget.set_subsystem_limit(MemorySubsystem::Dictionaries, 150);
+
+        let mut compressor = IncrementalCompressor::with_dict_size(CompressionStrategy::Zstd, 100);
+        compressor.set_memory_budget(budget.clone()).unwrap();
+
+        // Two contexts of 100 bytes each would exceed the 150 byte cap
+        compressor.compress_with_context(&[b'a'; 100], 1).unwrap();
+        assert_eq!(budget.used_by(MemorySubsystem::Dictionaries), 100);
+        let result = compressor.compress_with_context(&[b'b'; 100], 2);
+        assert!(result.is_err());
+        assert_eq!(budget.used_by(MemorySubsystem::Dictionaries), 100);
+
+        // Clearing a context releases its dictionary
+        compressor.clear_context(1);
+        assert_eq!(budget.used_by(MemorySubsystem::Dictionaries), 0);
+        compressor.compress_with_context(&[b'b'; 100], 2).unwrap();
+        assert_eq!(budget.used_by(MemorySubsystem::Dictionaries), 100);
+
+        drop(compressor);
+        assert_eq!(budget.used(), 0);
+    }
+
     #[test]
     fn test_dictionary_size_limit() {
         // Create an incremental compressor with a small dictionary size
@@ -356,4 +573,27 @@ mod tests {
             panic!("Context not found");
         }
     }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut compressor = IncrementalCompressor::with_dict_size(CompressionStrategy::Zstd, 64);
+        compressor.compress_with_context(b"first message of stream one", 1).unwrap();
+        compressor.compress_with_context(b"stream two", 2).unwrap();
+        compressor.compress_with_context(&[b'x'; 100], 2).unwrap();
+
+        let snapshot = compressor.snapshot();
+        let mut restored = IncrementalCompressor::restore(&snapshot).unwrap();
+        assert_eq!(restored.snapshot(), snapshot);
+        assert_eq!(restored.total_dictionary_size(), compressor.total_dictionary_size());
+        assert_eq!(restored.contexts[&2].dictionary, vec![b'x'; 64]);
+        assert_eq!(restored.contexts[&2].generation, 2);
+
+        // Both continue the streams identically
+        let compressed = compressor.compress_with_context(b"second message", 1).unwrap();
+        assert_eq!(restored.decompress_with_context(&compressed, 1).unwrap(), b"second message");
+        assert_eq!(restored.contexts[&1].dictionary, compressor.contexts[&1].dictionary);
+
+        assert!(IncrementalCompressor::restore(&snapshot[..snapshot.len() - 1]).is_err());
+        assert!(IncrementalCompressor::restore(b"TNIC\x01").is_err());
+    }
 }
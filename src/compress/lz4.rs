@@ -0,0 +1,103 @@
+use crate::internal::error::{Error, Result};
+use super::Compressor;
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+use std::fmt::Debug;
+use std::io::{self, Read, Write};
+
+/// Compresses data using the LZ4 frame format.
+pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    compress_into(data, &mut out)?;
+    Ok(out)
+}
+
+/// Decompresses data in the LZ4 frame format.
+pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
+    let mut out = Vec::new();
+    decompress_into(data, &mut out)?;
+    Ok(out)
+}
+
+/// Compresses data using the LZ4 frame format, appending the result to `out`.
+pub fn compress_into(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let mut encoder = FrameEncoder::new(out);
+    encoder.write_all(data).map_err(|e| Error::CompressionError(format!("LZ4 compression failed: {}", e)))?;
+    encoder.finish().map(|_| ()).map_err(|e| Error::CompressionError(format!("LZ4 compression failed: {}", e)))
+}
+
+/// Decompresses data in the LZ4 frame format, appending the result to `out`.
+pub fn decompress_into(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    FrameDecoder::new(data)
+        .read_to_end(out)
+        .map(|_| ())
+        .map_err(|e| Error::CompressionError(format!("LZ4 decompression failed: {}", e)))
+}
+
+/// LZ4 Compressor implementation, trading compression ratio for speed.
+#[derive(Debug)]
+pub struct Lz4Compressor;
+
+impl Compressor for Lz4Compressor {
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        compress(data)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        decompress(data)
+    }
+
+    fn compress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        compress_into(data, out)
+    }
+
+    fn decompress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        decompress_into(data, out)
+    }
+
+    fn compress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        let mut encoder = FrameEncoder::new(writer);
+        let read = io::copy(reader, &mut encoder)
+            .map_err(|e| Error::CompressionError(format!("LZ4 compression failed: {}", e)))?;
+        encoder.finish().map_err(|e| Error::CompressionError(format!("LZ4 compression failed: {}", e)))?;
+        Ok(read)
+    }
+
+    fn decompress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        io::copy(&mut FrameDecoder::new(reader), writer)
+            .map_err(|e| Error::CompressionError(format!("LZ4 decompression failed: {}", e)))
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lz4_compression() {
+        let original_data = b"This is a test string for LZ4 compression. This is a test string for LZ4 compression. This is a test string for LZ4 compression.";
+        let compressor = Lz4Compressor;
+        let compressed_data = compressor.compress(original_data).unwrap();
+        // Output is an LZ4 frame
+        assert!(compressed_data.starts_with(&[0x04, 0x22, 0x4D, 0x18]));
+        let decompressed_data = compressor.decompress(&compressed_data).unwrap();
+        assert_eq!(decompressed_data, original_data.to_vec());
+    }
+
+    #[test]
+    fn test_lz4_empty_data() {
+        let compressor = Lz4Compressor;
+        let compressed_data = compressor.compress(b"").unwrap();
+        let decompressed_data = compressor.decompress(&compressed_data).unwrap();
+        assert!(decompressed_data.is_empty());
+    }
+
+    #[test]
+    fn test_lz4_invalid_data() {
+        let invalid_data = vec![0xFF, 0xFF, 0xFF]; // Not an LZ4 frame
+        let compressor = Lz4Compressor;
+        let decompressed_result = compressor.decompress(&invalid_data);
+        assert!(decompressed_result.is_err());
+        assert!(decompressed_result.unwrap_err().to_string().contains("LZ4 decompression failed"));
+    }
+}
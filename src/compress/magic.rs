@@ -0,0 +1,130 @@
+use crate::codec::types::{HtlvItem, HtlvValue};
+
+/// Already-compressed formats recognized by their magic number.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrecompressedFormat {
+    /// gzip stream (RFC 1952)
+    Gzip,
+    /// Zstandard frame
+    Zstd,
+    /// LZ4 frame
+    Lz4,
+    /// JPEG image
+    Jpeg,
+    /// PNG image
+    Png,
+    /// ZIP archive
+    Zip,
+}
+
+/// Magic numbers of the recognized formats.
+const MAGIC_NUMBERS: &[(PrecompressedFormat, &[u8])] = &[
+    (PrecompressedFormat::Gzip, &[0x1F, 0x8B]),
+    (PrecompressedFormat::Zstd, &[0x28, 0xB5, 0x2F, 0xFD]),
+    (PrecompressedFormat::Lz4, &[0x04, 0x22, 0x4D, 0x18]),
+    (PrecompressedFormat::Jpeg, &[0xFF, 0xD8, 0xFF]),
+    (PrecompressedFormat::Png, &[0x89, 0x50, 0x4E, 0x47, 0x0D, 0x0A, 0x1A, 0x0A]),
+    (PrecompressedFormat::Zip, &[0x50, 0x4B, 0x03, 0x04]),
+];
+
+/// Detects whether the data starts with the magic number of an already-compressed format.
+pub fn detect_format(data: &[u8]) -> Option<PrecompressedFormat> {
+    MAGIC_NUMBERS
+        .iter()
+        .find(|(_, magic)| data.starts_with(magic))
+        .map(|(format, _)| *format)
+}
+
+/// Returns true if the value is a Bytes field holding already-compressed content.
+///
+/// Only `Bytes` values are checked; `String` values are text and never carry
+/// binary container formats.
+pub fn is_precompressed_value(value: &HtlvValue) -> bool {
+    match value {
+        HtlvValue::Bytes(data) => detect_format(data).is_some(),
+        _ => false,
+    }
+}
+
+/// Returns true if most of the item's payload consists of already-compressed Bytes fields,
+/// in which case compressing the encoded item would only waste CPU.
+pub fn is_item_incompressible(item: &HtlvItem) -> bool {
+    let (precompressed, total) = payload_sizes(&item.value);
+    total > 0 && precompressed * 2 > total
+}
+
+/// Returns the size of precompressed payload bytes and the total payload bytes of a value.
+fn payload_sizes(value: &HtlvValue) -> (usize, usize) {
+    match value {
+        HtlvValue::Array(items) | HtlvValue::Object(items) => {
+            items.iter().fold((0, 0), |(precompressed, total), item| {
+                let (p, t) = payload_sizes(&item.value);
+                (precompressed + p, total + t)
+            })
+        }
+        HtlvValue::Bytes(data) => {
+            let size = data.len();
+            if detect_format(data).is_some() {
+                (size, size)
+            } else {
+                (0, size)
+            }
+        }
+        HtlvValue::String(data) => (0, data.len()),
+        // Scalars are small, count a fixed size so they do not dominate the ratio
+        _ => (0, 8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    #[test]
+    fn test_detect_format() {
+        assert_eq!(detect_format(&[0x1F, 0x8B, 0x08, 0x00]), Some(PrecompressedFormat::Gzip));
+        assert_eq!(detect_format(&[0x28, 0xB5, 0x2F, 0xFD, 0x00]), Some(PrecompressedFormat::Zstd));
+        assert_eq!(detect_format(&[0xFF, 0xD8, 0xFF, 0xE0]), Some(PrecompressedFormat::Jpeg));
+        assert_eq!(detect_format(b"\x89PNG\r\n\x1a\n...."), Some(PrecompressedFormat::Png));
+        assert_eq!(detect_format(b"PK\x03\x04...."), Some(PrecompressedFormat::Zip));
+        assert_eq!(detect_format(b"plain text"), None);
+        assert_eq!(detect_format(&[0x1F]), None); // Truncated magic
+        assert_eq!(detect_format(b""), None);
+    }
+
+    #[test]
+    fn test_detect_real_zstd_frame() {
+        let compressed = crate::compress::zstd::compress(b"some data").unwrap();
+        assert_eq!(detect_format(&compressed), Some(PrecompressedFormat::Zstd));
+    }
+
+    #[test]
+    fn test_is_precompressed_value() {
+        let png = Bytes::from_static(b"\x89PNG\r\n\x1a\nIHDR");
+        assert!(is_precompressed_value(&HtlvValue::Bytes(png.clone())));
+        // Strings are never treated as precompressed
+        assert!(!is_precompressed_value(&HtlvValue::String(png)));
+        assert!(!is_precompressed_value(&HtlvValue::U32(0x1F8B)));
+    }
+
+    #[test]
+    fn test_is_item_incompressible() {
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE0];
+        jpeg.extend_from_slice(&[0u8; 256]);
+
+        let photo = HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::U64(42)),
+            HtlvItem::new(2, HtlvValue::Bytes(Bytes::from(jpeg))),
+        ]));
+        assert!(is_item_incompressible(&photo));
+
+        let text = HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String(Bytes::from_static(b"hello hello hello"))),
+            HtlvItem::new(2, HtlvValue::Bytes(Bytes::from_static(&[0x1F, 0x8B]))),
+        ]));
+        assert!(!is_item_incompressible(&text));
+
+        assert!(!is_item_incompressible(&HtlvItem::new(0, HtlvValue::Array(vec![]))));
+    }
+}
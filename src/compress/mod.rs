@@ -1,20 +1,71 @@
-use crate::internal::error::Result;
+use crate::internal::error::{Error, Result};
+use crate::internal::alloc::BufferAllocator;
+use crate::internal::diagnostics::{DiagnosticKind, DiagnosticSource, Diagnostics};
+use crate::codec::types::HtlvItem;
+use crate::codec::encode::encode_item;
 use std::fmt::Debug; // Import Debug trait
+use std::io::{Read, Write};
 
 pub mod zstd;
-// Removed lz4 module: pub mod lz4;
+pub mod lz4;
 pub mod brotli;
 pub mod no_compression;
 pub mod sharded;
 pub mod incremental;
+pub mod hints;
+pub mod magic;
+pub mod side_channel;
 
 /// Trait for compression algorithms.
-pub trait Compressor: Debug { // Added Debug bound
+///
+/// Compressors are `Send + Sync`, so a boxed compressor can be shared by the
+/// threads of a server.
+pub trait Compressor: Debug + Send + Sync { // Added Debug bound
     /// Compresses the given data.
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>>;
 
     /// Decompresses the given data.
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>>;
+
+    /// Compresses the given data, appending the result to `out`.
+    ///
+    /// Implementations should write directly into `out` so that callers can supply
+    /// buffers from their own allocator; the default falls back to `compress`.
+    fn compress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        out.extend_from_slice(&self.compress(data)?);
+        Ok(())
+    }
+
+    /// Decompresses the given data, appending the result to `out`.
+    fn decompress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        out.extend_from_slice(&self.decompress(data)?);
+        Ok(())
+    }
+
+    /// Compresses everything read from `reader` into `writer` and returns the
+    /// number of uncompressed bytes read.
+    ///
+    /// Implementations should stream, so that inputs larger than memory can be
+    /// compressed; the default reads the whole input and calls `compress`.
+    fn compress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        writer.write_all(&self.compress(&data)?)?;
+        Ok(data.len() as u64)
+    }
+
+    /// Decompresses everything read from `reader` into `writer` and returns the
+    /// number of decompressed bytes written.
+    ///
+    /// Implementations should stream; the default reads the whole input and
+    /// calls `decompress`.
+    fn decompress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        let mut data = Vec::new();
+        reader.read_to_end(&mut data)?;
+        let decompressed = self.decompress(&data)?;
+        writer.write_all(&decompressed)?;
+        Ok(decompressed.len() as u64)
+    }
 }
 
 /// Defines the compression strategy to use.
@@ -23,21 +74,132 @@ pub trait Compressor: Debug { // Added Debug bound
 pub enum CompressionStrategy {
     NoCompression = 0,
     Zstd = 1,
-    // Removed Lz4 variant: Lz4,
+    Lz4 = 2, // LZ4 frame format, fastest of the codecs
     Brotli = 3, // Explicitly set to 3 to match packet.rs
     // TODO: Add other strategies if needed (e.g., based on data type)
 }
 
+impl CompressionStrategy {
+    /// Converts the byte stored in an envelope or header back to a strategy.
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(CompressionStrategy::NoCompression),
+            1 => Ok(CompressionStrategy::Zstd),
+            2 => Ok(CompressionStrategy::Lz4),
+            3 => Ok(CompressionStrategy::Brotli),
+            _ => Err(Error::CompressionError(format!("Unknown compression strategy: {}", value))),
+        }
+    }
+}
+
 /// Returns a Compressor implementation based on the given strategy.
 pub fn get_compressor(strategy: CompressionStrategy) -> Result<Box<dyn Compressor>> {
     match strategy {
         CompressionStrategy::NoCompression => Ok(Box::new(no_compression::NoCompressionCompressor)),
         CompressionStrategy::Zstd => Ok(Box::new(zstd::ZstdCompressor)),
-        // Removed Lz4 match arm: CompressionStrategy::Lz4 => Ok(Box::new(lz4::Lz4Compressor)),
+        CompressionStrategy::Lz4 => Ok(Box::new(lz4::Lz4Compressor)),
         CompressionStrategy::Brotli => Ok(Box::new(brotli::BrotliCompressor)),
     }
 }
 
+/// Compresses data with the given strategy, unless compression would not help.
+///
+/// Data that already starts with the magic number of a compressed format (gzip, zstd, lz4,
+/// JPEG, PNG, ZIP) is passed through untouched, and so is data whose compressed form
+/// would be no smaller than the original. Returns the strategy actually applied, which
+/// should be recorded in the packet header, together with the resulting bytes.
+pub fn compress_or_passthrough(strategy: CompressionStrategy, data: &[u8]) -> Result<(CompressionStrategy, Vec<u8>)> {
+    compress_or_passthrough_inner(strategy, data, None)
+}
+
+/// Compresses data like `compress_or_passthrough`, reporting to the diagnostics sink
+/// when compression was skipped because the data was already compressed or would
+/// have expanded.
+pub fn compress_or_passthrough_with_diagnostics(
+    strategy: CompressionStrategy,
+    data: &[u8],
+    diagnostics: &Diagnostics,
+) -> Result<(CompressionStrategy, Vec<u8>)> {
+    compress_or_passthrough_inner(strategy, data, Some(diagnostics))
+}
+
+fn compress_or_passthrough_inner(
+    strategy: CompressionStrategy,
+    data: &[u8],
+    diagnostics: Option<&Diagnostics>,
+) -> Result<(CompressionStrategy, Vec<u8>)> {
+    if strategy == CompressionStrategy::NoCompression {
+        return Ok((CompressionStrategy::NoCompression, data.to_vec()));
+    }
+    if let Some(format) = magic::detect_format(data) {
+        if let Some(diagnostics) = diagnostics {
+            diagnostics.report(
+                DiagnosticSource::Compress,
+                DiagnosticKind::AlreadyCompressed,
+                format!("Data is already compressed ({:?}), {:?} compression skipped", format, strategy),
+            );
+        }
+        return Ok((CompressionStrategy::NoCompression, data.to_vec()));
+    }
+
+    let compressed = get_compressor(strategy)?.compress(data)?;
+    if compressed.len() >= data.len() {
+        // Compression inflated the data, send it as-is
+        if let Some(diagnostics) = diagnostics {
+            diagnostics.report(
+                DiagnosticSource::Compress,
+                DiagnosticKind::CompressionExpanded { original: data.len(), compressed: compressed.len() },
+                format!("{:?} compression would expand {} bytes to {}, data stored as-is", strategy, data.len(), compressed.len()),
+            );
+        }
+        return Ok((CompressionStrategy::NoCompression, data.to_vec()));
+    }
+
+    Ok((strategy, compressed))
+}
+
+/// Encodes an HTLV item and compresses it with the given strategy, unless most of its
+/// payload consists of already-compressed Bytes fields.
+///
+/// Returns the strategy actually applied together with the resulting bytes.
+pub fn compress_item_or_passthrough(strategy: CompressionStrategy, item: &HtlvItem) -> Result<(CompressionStrategy, Vec<u8>)> {
+    let encoded = encode_item(item)?;
+    if magic::is_item_incompressible(item) {
+        return Ok((CompressionStrategy::NoCompression, encoded));
+    }
+    compress_or_passthrough(strategy, &encoded)
+}
+
+/// Compresses data with the given strategy into a buffer obtained from the allocator.
+///
+/// On failure the buffer is handed back to the allocator.
+pub fn compress_with_allocator(strategy: CompressionStrategy, data: &[u8], allocator: &dyn BufferAllocator) -> Result<Vec<u8>> {
+    let compressor = get_compressor(strategy)?;
+    let mut out = allocator.allocate(data.len());
+    match compressor.compress_into(data, &mut out) {
+        Ok(()) => Ok(out),
+        Err(e) => {
+            allocator.recycle(out);
+            Err(e)
+        }
+    }
+}
+
+/// Decompresses data with the given strategy into a buffer obtained from the allocator.
+///
+/// `size_hint` is the expected decompressed size, used as the initial capacity.
+/// On failure the buffer is handed back to the allocator.
+pub fn decompress_with_allocator(strategy: CompressionStrategy, data: &[u8], size_hint: usize, allocator: &dyn BufferAllocator) -> Result<Vec<u8>> {
+    let compressor = get_compressor(strategy)?;
+    let mut out = allocator.allocate(size_hint);
+    match compressor.decompress_into(data, &mut out) {
+        Ok(()) => Ok(out),
+        Err(e) => {
+            allocator.recycle(out);
+            Err(e)
+        }
+    }
+}
 
 // NOTE: Dynamic compression strategy selector has been permanently shelved.
 // Zstd is now used as the default compression method.
@@ -56,6 +218,21 @@ mod tests {
     use super::*;
     // Removed unused import: use crate::internal::error::Error;
 
+    #[test]
+    fn test_compress_or_passthrough_with_diagnostics() {
+        let diagnostics = Diagnostics::new();
+        let (strategy, _) = compress_or_passthrough_with_diagnostics(CompressionStrategy::Zstd, b"abc", &diagnostics).unwrap();
+        assert_eq!(strategy, CompressionStrategy::NoCompression);
+        assert!(matches!(diagnostics.entries()[0].kind, DiagnosticKind::CompressionExpanded { original: 3, .. }));
+
+        let compressed = get_compressor(CompressionStrategy::Zstd).unwrap().compress(&[0u8; 4096]).unwrap();
+        compress_or_passthrough_with_diagnostics(CompressionStrategy::Zstd, &compressed, &diagnostics).unwrap();
+        assert_eq!(diagnostics.entries()[1].kind, DiagnosticKind::AlreadyCompressed);
+
+        compress_or_passthrough_with_diagnostics(CompressionStrategy::Zstd, &[0u8; 4096], &diagnostics).unwrap();
+        assert_eq!(diagnostics.len(), 2);
+    }
+
     #[test]
     fn test_get_compressor_zstd() {
         let compressor = get_compressor(CompressionStrategy::Zstd).unwrap();
@@ -68,12 +245,11 @@ mod tests {
         assert!(compressor.compress(b"test").is_ok()); // Basic check that the compressor is functional
     }
 
-    // Removed LZ4 test:
-    // #[test]
-    // fn test_get_compressor_lz4() {
-    //     let compressor = get_compressor(CompressionStrategy::Lz4).unwrap();
-    //     assert!(compressor.compress(b"test").is_ok()); // Basic check
-    // }
+    #[test]
+    fn test_get_compressor_lz4() {
+        let compressor = get_compressor(CompressionStrategy::Lz4).unwrap();
+        assert!(compressor.compress(b"test").is_ok()); // Basic check
+    }
 
     #[test]
     fn test_get_compressor_brotli() {
@@ -91,4 +267,76 @@ mod tests {
         let compressed = compressor.compress(test_data).unwrap();
         assert_eq!(compressed, test_data.to_vec()); // Data should be unchanged
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_compress_or_passthrough() {
+        let text = b"repetitive text repetitive text repetitive text repetitive text".to_vec();
+        let (strategy, compressed) = compress_or_passthrough(CompressionStrategy::Zstd, &text).unwrap();
+        assert_eq!(strategy, CompressionStrategy::Zstd);
+        assert!(compressed.len() < text.len());
+
+        // Already zstd-compressed data is passed through
+        let (strategy, passed) = compress_or_passthrough(CompressionStrategy::Brotli, &compressed).unwrap();
+        assert_eq!(strategy, CompressionStrategy::NoCompression);
+        assert_eq!(passed, compressed);
+
+        // Tiny data that would inflate is passed through
+        let (strategy, passed) = compress_or_passthrough(CompressionStrategy::Zstd, b"ab").unwrap();
+        assert_eq!(strategy, CompressionStrategy::NoCompression);
+        assert_eq!(passed, b"ab".to_vec());
+    }
+
+    #[test]
+    fn test_compress_item_or_passthrough() {
+        use crate::codec::types::HtlvValue;
+        use bytes::Bytes;
+
+        let mut gzip = vec![0x1F, 0x8B, 0x08, 0x00];
+        gzip.extend_from_slice(&[0u8; 512]);
+        let item = HtlvItem::new(1, HtlvValue::Bytes(Bytes::from(gzip)));
+
+        let (strategy, data) = compress_item_or_passthrough(CompressionStrategy::Zstd, &item).unwrap();
+        assert_eq!(strategy, CompressionStrategy::NoCompression);
+        assert_eq!(data, encode_item(&item).unwrap());
+    }
+
+    #[test]
+    fn test_compress_with_allocator_round_trip() {
+        use crate::internal::alloc::PoolAllocator;
+
+        let pool = PoolAllocator::new(4);
+        let data = b"pooled buffers, pooled buffers, pooled buffers, pooled buffers".to_vec();
+
+        for strategy in [CompressionStrategy::NoCompression, CompressionStrategy::Zstd, CompressionStrategy::Lz4, CompressionStrategy::Brotli] {
+            let compressed = compress_with_allocator(strategy, &data, &pool).unwrap();
+            let decompressed = decompress_with_allocator(strategy, &compressed, data.len(), &pool).unwrap();
+            assert_eq!(decompressed, data);
+            pool.recycle(compressed);
+            pool.recycle(decompressed);
+        }
+
+        // Failed decompression returns the buffer to the pool
+        let pooled = pool.pooled();
+        assert!(decompress_with_allocator(CompressionStrategy::Zstd, &[0xFF; 3], 16, &pool).is_err());
+        assert_eq!(pool.pooled(), pooled);
+    }
+
+    #[test]
+    fn test_stream_round_trip() {
+        use std::io::Cursor;
+
+        let data = b"streamed through every codec ".repeat(2000);
+        for strategy in [CompressionStrategy::NoCompression, CompressionStrategy::Zstd, CompressionStrategy::Lz4, CompressionStrategy::Brotli] {
+            let compressor = get_compressor(strategy).unwrap();
+            let mut compressed = Vec::new();
+            assert_eq!(compressor.compress_stream(&mut Cursor::new(&data), &mut compressed).unwrap(), data.len() as u64);
+            assert_eq!(compressor.decompress(&compressed).unwrap(), data, "{:?}", strategy);
+
+            let mut decompressed = Vec::new();
+            let written = compressor.decompress_stream(&mut Cursor::new(compressor.compress(&data).unwrap()), &mut decompressed).unwrap();
+            assert_eq!(written, data.len() as u64);
+            assert_eq!(decompressed, data, "{:?}", strategy);
+        }
+        assert!(get_compressor(CompressionStrategy::Zstd).unwrap().decompress_stream(&mut Cursor::new([0xFFu8; 8]), &mut Vec::new()).is_err());
+    }
+}
@@ -1,6 +1,7 @@
 use crate::internal::error::Result;
 use super::Compressor;
 use std::fmt::Debug;
+use std::io::{self, Read, Write};
 
 /// A compressor that performs no compression, simply returning the original data.
 /// 
@@ -22,6 +23,28 @@ impl Compressor for NoCompressionCompressor {
     fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
         Ok(data.to_vec())
     }
+
+    /// Appends the data to `out` unchanged.
+    fn compress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        out.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Appends the data to `out` unchanged.
+    fn decompress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        out.extend_from_slice(data);
+        Ok(())
+    }
+
+    /// Copies the stream unchanged.
+    fn compress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        Ok(io::copy(reader, writer)?)
+    }
+
+    /// Copies the stream unchanged.
+    fn decompress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        Ok(io::copy(reader, writer)?)
+    }
 }
 
 #[cfg(test)]
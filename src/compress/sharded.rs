@@ -1,11 +1,21 @@
 use crate::internal::error::{Error, Result};
+use crate::internal::cursor::WireCursor;
+use crate::internal::memory::{MemoryBudget, MemorySubsystem};
+use crate::internal::alloc::BufferAllocator;
+use crate::internal::progress::{ProgressReporter, ProgressStage};
+use crate::internal::cancel::{self, CancellationToken};
 use super::{Compressor, CompressionStrategy, get_compressor};
 use std::fmt::Debug;
+use std::io::{self, Read, Write};
 
 /// Maximum size of a single shard in bytes.
 /// This is set to 1MB by default, which is a good balance between compression efficiency and memory usage.
 pub const DEFAULT_SHARD_SIZE: usize = 1024 * 1024; // 1MB
 
+/// Shard count written by `compress_stream`, which cannot know the number of
+/// shards up front; the shards of such a stream end with an empty shard.
+pub const STREAMED_SHARD_COUNT: u32 = u32::MAX;
+
 /// Metadata for a compressed shard.
 #[derive(Debug, Clone)]
 pub struct ShardMetadata {
@@ -39,6 +49,10 @@ pub struct ShardedCompressor {
     pub shard_size: usize,
     /// The compression strategy to use for each shard.
     pub strategy: CompressionStrategy,
+    /// Observer notified after each shard is compressed or decompressed.
+    pub progress: Option<ProgressReporter>,
+    /// Token checked before each shard is compressed or decompressed.
+    pub cancellation: Option<CancellationToken>,
 }
 
 impl Default for ShardedCompressor {
@@ -46,6 +60,8 @@ impl Default for ShardedCompressor {
         ShardedCompressor {
             shard_size: DEFAULT_SHARD_SIZE,
             strategy: CompressionStrategy::Zstd, // Default to Zstd
+            progress: None,
+            cancellation: None,
         }
     }
 }
@@ -56,6 +72,8 @@ impl ShardedCompressor {
         ShardedCompressor {
             shard_size: DEFAULT_SHARD_SIZE,
             strategy,
+            progress: None,
+            cancellation: None,
         }
     }
 
@@ -64,6 +82,26 @@ impl ShardedCompressor {
         ShardedCompressor {
             shard_size,
             strategy,
+            progress: None,
+            cancellation: None,
+        }
+    }
+
+    /// Reports progress to the observer after each shard.
+    pub fn with_progress(mut self, progress: ProgressReporter) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Fails with `Error::Cancelled` before the next shard once the token is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    fn report_progress(&self, stage: ProgressStage, bytes_processed: usize, total_bytes: usize) {
+        if let Some(progress) = &self.progress {
+            progress.report(stage, bytes_processed as u64, total_bytes as u64, 0);
         }
     }
 
@@ -82,6 +120,8 @@ impl ShardedCompressor {
         let compressor = get_compressor(self.strategy)?;
 
         while offset < data.len() {
+            cancel::check(&self.cancellation, "Compression")?;
+
             // Calculate the end of this shard
             let end = std::cmp::min(offset + self.shard_size, data.len());
             let shard_data = &data[offset..end];
@@ -104,6 +144,7 @@ impl ShardedCompressor {
 
             // Move to the next shard
             offset = end;
+            self.report_progress(ProgressStage::Compress, offset, data.len());
         }
 
         Ok(shards)
@@ -120,28 +161,70 @@ impl ShardedCompressor {
             .map(|shard| shard.metadata.original_size as usize)
             .sum();
         let mut result = Vec::with_capacity(total_size);
+        self.decompress_shards_into(shards, &mut result)?;
+        Ok(result)
+    }
+
+    /// Decompresses shards like `decompress_from_shards`, reassembling the data in a
+    /// buffer obtained from the allocator.
+    ///
+    /// On failure the buffer is handed back to the allocator.
+    pub fn decompress_from_shards_with_allocator(&self, shards: &[CompressedShard], allocator: &dyn BufferAllocator) -> Result<Vec<u8>> {
+        let total_size: usize = shards.iter()
+            .map(|shard| shard.metadata.original_size as usize)
+            .sum();
+        let mut result = allocator.allocate(total_size);
+        match self.decompress_shards_into(shards, &mut result) {
+            Ok(()) => Ok(result),
+            Err(e) => {
+                allocator.recycle(result);
+                Err(e)
+            }
+        }
+    }
 
+    /// Decompresses each shard directly into `out`, verifying the declared sizes.
+    fn decompress_shards_into(&self, shards: &[CompressedShard], out: &mut Vec<u8>) -> Result<()> {
+        let total_size: usize = shards.iter().map(|shard| shard.metadata.original_size as usize).sum();
+        let mut processed = 0;
         for shard in shards {
+            cancel::check(&self.cancellation, "Decompression")?;
+
             // Get the appropriate compressor for this shard
             let compressor = get_compressor(shard.metadata.strategy)?;
 
-            // Decompress the shard
-            let decompressed_data = compressor.decompress(&shard.data)?;
+            // Decompress the shard and append it to the output
+            let start = out.len();
+            compressor.decompress_into(&shard.data, out)?;
 
             // Verify the decompressed size matches the original size
-            if decompressed_data.len() != shard.metadata.original_size as usize {
+            let decompressed_size = out.len() - start;
+            if decompressed_size != shard.metadata.original_size as usize {
                 return Err(Error::CompressionError(format!(
                     "Decompressed size mismatch: expected {}, got {}",
                     shard.metadata.original_size,
-                    decompressed_data.len()
+                    decompressed_size
                 )));
             }
-
-            // Append the decompressed data to the result
-            result.extend_from_slice(&decompressed_data);
+            processed += decompressed_size;
+            self.report_progress(ProgressStage::Decompress, processed, total_size);
         }
 
-        Ok(result)
+        Ok(())
+    }
+
+    /// Decompresses shards like `decompress_from_shards`, accounting the reassembly
+    /// buffer against the given memory budget.
+    ///
+    /// The sizes declared in the shard metadata are reserved up front, so oversized
+    /// inputs fail with `Error::MemoryLimitExceeded` before anything is decompressed.
+    /// The reservation is released once the result is handed back to the caller.
+    pub fn decompress_from_shards_with_budget(&self, shards: &[CompressedShard], budget: &MemoryBudget) -> Result<Vec<u8>> {
+        let total_size = shards.iter()
+            .try_fold(0usize, |total, shard| total.checked_add(shard.metadata.original_size as usize))
+            .ok_or_else(|| Error::CompressionError("Total shard size overflows usize".to_string()))?;
+        let _reservation = budget.try_reserve(MemorySubsystem::ShardReassembly, total_size)?;
+        self.decompress_from_shards(shards)
     }
 }
 
@@ -155,6 +238,9 @@ impl Compressor for ShardedCompressor {
     ///   - Original size (4 bytes)
     ///   - Compressed size (4 bytes)
     ///   - Compressed data (variable length)
+    ///
+    /// Streams written by `compress_stream` have a shard count of
+    /// `STREAMED_SHARD_COUNT` and end with a shard of strategy 0 and sizes 0.
     fn compress(&self, data: &[u8]) -> Result<Vec<u8>> {
         // Compress the data into shards
         let shards = self.compress_to_shards(data)?;
@@ -194,58 +280,42 @@ impl Compressor for ShardedCompressor {
             return Ok(Vec::new());
         }
 
+        let mut cursor = WireCursor::new(data).with_truncation_error(Error::CompressionError);
+
         // Ensure we have at least 4 bytes for the shard count
-        if data.len() < 4 {
+        if cursor.remaining() < 4 {
             return Err(Error::CompressionError("Invalid sharded compression data: too short".to_string()));
         }
 
         // Read the number of shards
-        let mut shard_count_bytes = [0u8; 4];
-        shard_count_bytes.copy_from_slice(&data[0..4]);
-        let shard_count = u32::from_le_bytes(shard_count_bytes) as usize;
+        let shard_count = cursor.read_u32_le("shard count")?;
 
-        // Parse the shards
-        let mut shards = Vec::with_capacity(shard_count);
-        let mut offset = 4; // Start after the shard count
+        // Parse the shards; every shard takes at least 9 bytes, which bounds the
+        // capacity an adversarial shard count can request
+        let streamed = shard_count == STREAMED_SHARD_COUNT;
+        let capacity = if streamed { 0 } else { shard_count as usize };
+        let mut shards = Vec::with_capacity(std::cmp::min(capacity, cursor.remaining() / 9));
 
-        for _ in 0..shard_count {
+        while streamed || shards.len() < shard_count as usize {
             // Ensure we have enough data for the shard metadata
-            if offset + 9 > data.len() {
+            if cursor.remaining() < 9 {
                 return Err(Error::CompressionError("Invalid sharded compression data: truncated metadata".to_string()));
             }
 
             // Read the compression strategy
-            let strategy_byte = data[offset];
-            offset += 1;
-
-            // Convert the strategy byte to a CompressionStrategy
-            let strategy = match strategy_byte {
-                0 => CompressionStrategy::NoCompression,
-                1 => CompressionStrategy::Zstd,
-                3 => CompressionStrategy::Brotli,
-                _ => return Err(Error::CompressionError(format!("Unknown compression strategy: {}", strategy_byte))),
-            };
+            let strategy_byte = cursor.read_u8("shard strategy")?;
 
-            // Read the original size
-            let mut original_size_bytes = [0u8; 4];
-            original_size_bytes.copy_from_slice(&data[offset..offset+4]);
-            let original_size = u32::from_le_bytes(original_size_bytes);
-            offset += 4;
-
-            // Read the compressed size
-            let mut compressed_size_bytes = [0u8; 4];
-            compressed_size_bytes.copy_from_slice(&data[offset..offset+4]);
-            let compressed_size = u32::from_le_bytes(compressed_size_bytes);
-            offset += 4;
-
-            // Ensure we have enough data for the compressed data
-            if offset + compressed_size as usize > data.len() {
-                return Err(Error::CompressionError("Invalid sharded compression data: truncated shard data".to_string()));
+            let strategy = CompressionStrategy::from_u8(strategy_byte)?;
+
+            // Read the original and compressed sizes
+            let original_size = cursor.read_u32_le("shard original size")?;
+            let compressed_size = cursor.read_u32_le("shard compressed size")?;
+            if streamed && original_size == 0 && compressed_size == 0 {
+                break;
             }
 
-            // Read the compressed data
-            let shard_data = data[offset..offset+compressed_size as usize].to_vec();
-            offset += compressed_size as usize;
+            // Read the compressed data, which must lie within the input
+            let shard_data = cursor.take(compressed_size as u64, "shard data")?.to_vec();
 
             // Create the shard metadata
             let metadata = ShardMetadata {
@@ -264,6 +334,102 @@ impl Compressor for ShardedCompressor {
         // Decompress the shards
         self.decompress_from_shards(&shards)
     }
+
+    /// Compresses the stream shard by shard, holding one shard in memory at a
+    /// time. Progress is reported with a total of 0, as the size of the stream
+    /// is unknown.
+    fn compress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        let compressor = get_compressor(self.strategy)?;
+        writer.write_all(&STREAMED_SHARD_COUNT.to_le_bytes())?;
+
+        let mut shard = Vec::with_capacity(self.shard_size);
+        let mut compressed = Vec::new();
+        let mut total = 0u64;
+        loop {
+            cancel::check(&self.cancellation, "Compression")?;
+            shard.clear();
+            (&mut *reader).take(self.shard_size.max(1) as u64).read_to_end(&mut shard)?;
+            if shard.is_empty() {
+                break;
+            }
+            compressed.clear();
+            compressor.compress_into(&shard, &mut compressed)?;
+            writer.write_all(&shard_header(self.strategy, shard.len(), compressed.len()))?;
+            writer.write_all(&compressed)?;
+            total += shard.len() as u64;
+            self.report_progress(ProgressStage::Compress, total as usize, 0);
+        }
+        writer.write_all(&shard_header(CompressionStrategy::NoCompression, 0, 0))?;
+        Ok(total)
+    }
+
+    /// Decompresses the stream shard by shard, holding one shard in memory at a
+    /// time. Reads both the output of `compress` and that of `compress_stream`.
+    fn decompress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        let mut count = Vec::with_capacity(4);
+        (&mut *reader).take(4).read_to_end(&mut count)?;
+        match count.len() {
+            0 => return Ok(0),
+            4 => {}
+            _ => return Err(Error::CompressionError("Invalid sharded compression data: too short".to_string())),
+        }
+        let shard_count = u32::from_le_bytes([count[0], count[1], count[2], count[3]]);
+        let streamed = shard_count == STREAMED_SHARD_COUNT;
+
+        let mut compressed = Vec::new();
+        let mut shard = Vec::new();
+        let mut total = 0u64;
+        let mut shards = 0u32;
+        while streamed || shards < shard_count {
+            let mut header = [0u8; 9];
+            reader.read_exact(&mut header).map_err(|e| match e.kind() {
+                io::ErrorKind::UnexpectedEof => {
+                    Error::CompressionError("Invalid sharded compression data: truncated metadata".to_string())
+                }
+                _ => e.into(),
+            })?;
+            let strategy = CompressionStrategy::from_u8(header[0])?;
+            let original_size = u32::from_le_bytes([header[1], header[2], header[3], header[4]]);
+            let compressed_size = u32::from_le_bytes([header[5], header[6], header[7], header[8]]);
+            if streamed && original_size == 0 && compressed_size == 0 {
+                break;
+            }
+            cancel::check(&self.cancellation, "Decompression")?;
+
+            compressed.clear();
+            (&mut *reader).take(compressed_size as u64).read_to_end(&mut compressed)?;
+            if compressed.len() != compressed_size as usize {
+                return Err(Error::CompressionError(format!(
+                    "Incomplete data for shard data: expected {} bytes, got {}",
+                    compressed_size,
+                    compressed.len()
+                )));
+            }
+            shard.clear();
+            get_compressor(strategy)?.decompress_into(&compressed, &mut shard)?;
+            if shard.len() != original_size as usize {
+                return Err(Error::CompressionError(format!(
+                    "Decompressed size mismatch: expected {}, got {}",
+                    original_size,
+                    shard.len()
+                )));
+            }
+            writer.write_all(&shard)?;
+            total += shard.len() as u64;
+            shards += 1;
+            self.report_progress(ProgressStage::Decompress, total as usize, 0);
+        }
+        Ok(total)
+    }
+}
+
+/// Returns the strategy and sizes preceding the data of a shard.
+fn shard_header(strategy: CompressionStrategy, original_size: usize, compressed_size: usize) -> [u8; 9] {
+    let mut header = [0u8; 9];
+    header[0] = strategy as u8;
+    header[1..5].copy_from_slice(&(original_size as u32).to_le_bytes());
+    header[5..9].copy_from_slice(&(compressed_size as u32).to_le_bytes());
+    header
 }
 
 #[cfg(test)]
@@ -354,6 +520,79 @@ mod tests {
         assert_eq!(decompressed_data, original_data);
     }
 
+    #[test]
+    fn test_progress_is_reported_per_shard() {
+        use crate::internal::progress::{Progress, ProgressReporter};
+        use std::sync::{Arc, Mutex};
+
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&reports);
+        let reporter = ProgressReporter::new(move |progress: &Progress| {
+            seen.lock().unwrap().push((progress.stage, progress.bytes_processed, progress.total_bytes))
+        });
+        let compressor = ShardedCompressor::with_shard_size(CompressionStrategy::Zstd, 1000).with_progress(reporter);
+        let data = vec![3u8; 2500];
+        let compressed = compressor.compress(&data).unwrap();
+        assert_eq!(compressor.decompress(&compressed).unwrap(), data);
+
+        assert_eq!(*reports.lock().unwrap(), vec![
+            (ProgressStage::Compress, 1000, 2500),
+            (ProgressStage::Compress, 2000, 2500),
+            (ProgressStage::Compress, 2500, 2500),
+            (ProgressStage::Decompress, 1000, 2500),
+            (ProgressStage::Decompress, 2000, 2500),
+            (ProgressStage::Decompress, 2500, 2500),
+        ]);
+    }
+
+    #[test]
+    fn test_cancellation_between_shards() {
+        let token = CancellationToken::new();
+        let compressor = ShardedCompressor::with_shard_size(CompressionStrategy::Zstd, 1000)
+            .with_cancellation(token.clone());
+        let compressed = compressor.compress(&[1u8; 3000]).unwrap();
+
+        token.cancel();
+        assert!(matches!(compressor.compress(&[1u8; 3000]), Err(Error::Cancelled(_))));
+        let err = compressor.decompress(&compressed).unwrap_err();
+        assert_eq!(err.to_string(), "Cancelled: Decompression was cancelled");
+    }
+
+    #[test]
+    fn test_decompress_from_shards_with_budget() {
+        let compressor = ShardedCompressor::with_shard_size(CompressionStrategy::Zstd, 100);
+        let data = vec![b'x'; 350];
+        let shards = compressor.compress_to_shards(&data).unwrap();
+
+        let budget = MemoryBudget::with_limit(1000);
+        let decompressed = compressor.decompress_from_shards_with_budget(&shards, &budget).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(budget.used(), 0);
+
+        budget.set_subsystem_limit(MemorySubsystem::ShardReassembly, 300);
+        let result = compressor.decompress_from_shards_with_budget(&shards, &budget);
+        assert!(matches!(result, Err(Error::MemoryLimitExceeded { .. })));
+    }
+
+    #[test]
+    fn test_decompress_from_shards_with_allocator() {
+        use crate::internal::alloc::FnAllocator;
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let compressor = ShardedCompressor::with_shard_size(CompressionStrategy::Zstd, 100);
+        let data = vec![b'y'; 250];
+        let shards = compressor.compress_to_shards(&data).unwrap();
+
+        let requested = AtomicUsize::new(0);
+        let allocator = FnAllocator::new(|capacity| {
+            requested.store(capacity, Ordering::SeqCst);
+            Vec::with_capacity(capacity)
+        });
+        let decompressed = compressor.decompress_from_shards_with_allocator(&shards, &allocator).unwrap();
+        assert_eq!(decompressed, data);
+        assert_eq!(requested.load(Ordering::SeqCst), 250);
+    }
+
     #[test]
     fn test_different_compression_strategies() {
         // Create test data
@@ -363,6 +602,7 @@ mod tests {
         let strategies = [
             CompressionStrategy::NoCompression,
             CompressionStrategy::Zstd,
+            CompressionStrategy::Lz4,
             CompressionStrategy::Brotli,
         ];
 
@@ -402,4 +642,50 @@ mod tests {
         assert!(result.is_err());
         assert!(result.unwrap_err().to_string().contains("truncated metadata"));
     }
+
+    #[test]
+    fn test_streaming_shard_by_shard() {
+        use std::io::Cursor;
+
+        let compressor = ShardedCompressor::with_shard_size(CompressionStrategy::Zstd, 1000);
+        let data: Vec<u8> = (0..4500).map(|i| (i % 97) as u8).collect();
+        let mut streamed = Vec::new();
+        assert_eq!(compressor.compress_stream(&mut Cursor::new(&data), &mut streamed).unwrap(), 4500);
+        assert_eq!(&streamed[..4], &STREAMED_SHARD_COUNT.to_le_bytes());
+
+        // Streamed and counted forms are read by both decoders
+        assert_eq!(compressor.decompress(&streamed).unwrap(), data);
+        let mut out = Vec::new();
+        assert_eq!(compressor.decompress_stream(&mut Cursor::new(&streamed), &mut out).unwrap(), 4500);
+        assert_eq!(out, data);
+        let counted = compressor.compress(&data).unwrap();
+        out.clear();
+        compressor.decompress_stream(&mut Cursor::new(&counted), &mut out).unwrap();
+        assert_eq!(out, data);
+
+        // A stream cut before its end marker is rejected
+        let err = compressor.decompress_stream(&mut Cursor::new(&streamed[..streamed.len() - 9]), &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("truncated metadata"), "{}", err);
+        let err = compressor.decompress_stream(&mut Cursor::new(&streamed[..20]), &mut Vec::new()).unwrap_err();
+        assert!(err.to_string().contains("Incomplete data for shard data"), "{}", err);
+    }
+
+    #[test]
+    fn test_sharded_decompression_rejects_adversarial_sizes() {
+        let compressor = ShardedCompressor::default();
+
+        // A huge shard count with no shards behind it
+        let result = compressor.decompress(&u32::MAX.to_le_bytes());
+        assert!(result.unwrap_err().to_string().contains("truncated metadata"));
+
+        // A shard whose compressed size runs past the end of the data
+        let mut data = 1u32.to_le_bytes().to_vec();
+        data.push(0);
+        data.extend_from_slice(&3u32.to_le_bytes());
+        data.extend_from_slice(&u32::MAX.to_le_bytes());
+        data.extend_from_slice(b"abc");
+        let err = compressor.decompress(&data).unwrap_err();
+        assert!(matches!(err, Error::CompressionError(_)));
+        assert!(err.to_string().contains("Incomplete data for shard data"), "{}", err);
+    }
 }
@@ -0,0 +1,332 @@
+// Compression side-channel mitigation
+//
+// Compressing a body that holds both a secret and data an attacker chooses,
+// then encrypting it, leaks the secret through the ciphertext length: the
+// attacker guesses a prefix of the secret, and the body compresses better
+// when the guess is right (CRIME, BREACH). A `SideChannelPolicy` knows which
+// tags carry secrets and which carry attacker-controlled data, and when an
+// item holds both it either
+//
+//   DisableCompression  sends the whole body uncompressed
+//   IsolateSecrets      compresses the body without its secret fields and
+//                       appends them uncompressed
+//
+// An isolated body is laid out as
+//
+//   varint length, public section   the item without its secret fields,
+//                                   compressed with the recorded strategy
+//   varint count                    number of secret fields
+//   per secret field:
+//     varint depth, depth varints   child indices leading to the field
+//     encoded field                 the field item, uncompressed
+//
+// Secret fields are listed in document order, so inserting each one back at
+// its indices restores the original item.
+
+use std::collections::HashSet;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::varint::encode_varint_into;
+use crate::codec::wire::{decode_item_with_format, encode_item_with_format, WireFormat};
+use crate::internal::cursor::WireCursor;
+use crate::internal::error::{Error, Result};
+use crate::schema::policy::SecurityLabel;
+use crate::schema::types::{Schema, SchemaType};
+use super::{compress_or_passthrough, get_compressor, CompressionStrategy};
+
+/// Name of the custom security label marking attacker-controlled fields
+pub const UNTRUSTED_LABEL: &str = "untrusted";
+
+/// What to do with a body that mixes secret and attacker-controlled fields
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SideChannelMode {
+    /// Send the body uncompressed
+    DisableCompression,
+    /// Compress the body without its secret fields, which are sent uncompressed
+    IsolateSecrets,
+}
+
+/// A body compressed under a side-channel policy
+#[derive(Debug, Clone, PartialEq)]
+pub struct GuardedBody {
+    /// The strategy applied to the body, or to its public section if isolated
+    pub strategy: CompressionStrategy,
+    /// Whether the body is laid out as a public and a secret section
+    pub isolated: bool,
+    /// The body bytes
+    pub data: Vec<u8>,
+}
+
+/// Decides how to compress bodies that mix secret and attacker-controlled
+/// fields.
+///
+/// Fields are identified by their tag at any nesting level; everything nested
+/// in a secret field is secret too.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SideChannelPolicy {
+    mode: SideChannelMode,
+    secret_tags: HashSet<u64>,
+    untrusted_tags: HashSet<u64>,
+}
+
+impl SideChannelPolicy {
+    /// Creates a policy without any secret or attacker-controlled tags
+    pub fn new(mode: SideChannelMode) -> Self {
+        SideChannelPolicy {
+            mode,
+            secret_tags: HashSet::new(),
+            untrusted_tags: HashSet::new(),
+        }
+    }
+
+    /// Creates a policy from the labels of a schema: fields labeled `secret`
+    /// or `pii` are secret, and fields with the custom `untrusted` label are
+    /// attacker-controlled.
+    pub fn from_schema(schema: &Schema, mode: SideChannelMode) -> Self {
+        let mut policy = SideChannelPolicy::new(mode);
+        collect_labeled_tags(&schema.root_type, &mut policy);
+        for definition in schema.definitions.values() {
+            collect_labeled_tags(definition, &mut policy);
+        }
+        policy
+    }
+
+    /// Returns the policy with the tag marked as carrying a secret
+    pub fn secret(mut self, tag: u64) -> Self {
+        self.secret_tags.insert(tag);
+        self
+    }
+
+    /// Returns the policy with the tag marked as carrying attacker-controlled data
+    pub fn untrusted(mut self, tag: u64) -> Self {
+        self.untrusted_tags.insert(tag);
+        self
+    }
+
+    /// Returns the mode applied to mixed bodies
+    pub fn mode(&self) -> SideChannelMode {
+        self.mode
+    }
+
+    /// Returns true if the item holds both a secret and an attacker-controlled field
+    pub fn is_mixed(&self, item: &HtlvItem) -> bool {
+        let (mut secret, mut untrusted) = (false, false);
+        self.scan(item, false, &mut secret, &mut untrusted);
+        secret && untrusted
+    }
+
+    fn scan(&self, item: &HtlvItem, in_secret: bool, secret: &mut bool, untrusted: &mut bool) {
+        let in_secret = in_secret || self.secret_tags.contains(&item.tag);
+        *secret |= in_secret;
+        *untrusted |= self.untrusted_tags.contains(&item.tag);
+        if let HtlvValue::Array(children) | HtlvValue::Object(children) = &item.value {
+            for child in children {
+                self.scan(child, in_secret, secret, untrusted);
+            }
+        }
+    }
+
+    /// Encodes the item and compresses it with the strategy, unless the item
+    /// mixes secret and attacker-controlled fields, in which case the mode
+    /// decides.
+    pub fn compress_item(&self, strategy: CompressionStrategy, item: &HtlvItem, format: WireFormat) -> Result<GuardedBody> {
+        if strategy == CompressionStrategy::NoCompression || !self.is_mixed(item) {
+            let (strategy, data) = compress_or_passthrough(strategy, &encode_item_with_format(item, format)?)?;
+            return Ok(GuardedBody { strategy, isolated: false, data });
+        }
+        // A secret root leaves nothing to compress apart from it
+        if self.mode == SideChannelMode::DisableCompression || self.secret_tags.contains(&item.tag) {
+            let data = encode_item_with_format(item, format)?;
+            return Ok(GuardedBody { strategy: CompressionStrategy::NoCompression, isolated: false, data });
+        }
+
+        let mut public = item.clone();
+        let mut secrets = Vec::new();
+        self.extract_secrets(&mut public, &mut Vec::new(), &mut secrets);
+        let (strategy, section) = compress_or_passthrough(strategy, &encode_item_with_format(&public, format)?)?;
+
+        let mut data = Vec::with_capacity(section.len() + 16);
+        encode_varint_into(section.len() as u64, &mut data);
+        data.extend_from_slice(&section);
+        encode_varint_into(secrets.len() as u64, &mut data);
+        for (path, secret) in &secrets {
+            encode_varint_into(path.len() as u64, &mut data);
+            for index in path {
+                encode_varint_into(*index as u64, &mut data);
+            }
+            data.extend_from_slice(&encode_item_with_format(secret, format)?);
+        }
+        Ok(GuardedBody { strategy, isolated: true, data })
+    }
+
+    /// Moves the secret fields nested in the item to `secrets`, in document
+    /// order, with their child indices in the original item
+    fn extract_secrets(&self, item: &mut HtlvItem, path: &mut Vec<usize>, secrets: &mut Vec<(Vec<usize>, HtlvItem)>) {
+        let (HtlvValue::Array(children) | HtlvValue::Object(children)) = &mut item.value else {
+            return;
+        };
+        let mut kept = Vec::with_capacity(children.len());
+        for (index, mut child) in std::mem::take(children).into_iter().enumerate() {
+            path.push(index);
+            if self.secret_tags.contains(&child.tag) {
+                secrets.push((path.clone(), child));
+            } else {
+                self.extract_secrets(&mut child, path, secrets);
+                kept.push(child);
+            }
+            path.pop();
+        }
+        *children = kept;
+    }
+}
+
+/// Decodes an isolated body, decompressing its public section with the
+/// strategy and inserting the secret fields back into it
+pub fn open_isolated(strategy: CompressionStrategy, data: &[u8], format: WireFormat) -> Result<HtlvItem> {
+    let mut cursor = WireCursor::new(data);
+    let section = cursor.read_length_prefixed("public section")?;
+    let section = match strategy {
+        CompressionStrategy::NoCompression => section.to_vec(),
+        strategy => get_compressor(strategy)?.decompress(section)?,
+    };
+    let (mut item, _) = decode_item_with_format(&section, format)?;
+
+    let count = cursor.read_varint("secret field count")?;
+    for _ in 0..count {
+        let depth = cursor.read_varint("secret field depth")?;
+        if depth == 0 || depth > cursor.remaining() as u64 {
+            return Err(Error::CodecError(format!("Invalid secret field depth {}", depth)));
+        }
+        let path = (0..depth)
+            .map(|_| cursor.read_varint("secret field index"))
+            .collect::<Result<Vec<_>>>()?;
+        let (secret, length) = decode_item_with_format(cursor.rest(), format)?;
+        cursor.skip(length as u64, "secret field")?;
+        insert_at(&mut item, &path, secret)?;
+    }
+    if !cursor.is_empty() {
+        return Err(Error::CodecError(format!("{} trailing bytes after the secret fields", cursor.remaining())));
+    }
+    Ok(item)
+}
+
+fn insert_at(item: &mut HtlvItem, path: &[u64], secret: HtlvItem) -> Result<()> {
+    let invalid = || Error::CodecError(format!("Secret field path {:?} does not fit the item", path));
+    let (last, parents) = path.split_last().ok_or_else(invalid)?;
+    let mut current = item;
+    for index in parents {
+        let (HtlvValue::Array(children) | HtlvValue::Object(children)) = &mut current.value else {
+            return Err(invalid());
+        };
+        current = children.get_mut(*index as usize).ok_or_else(invalid)?;
+    }
+    let (HtlvValue::Array(children) | HtlvValue::Object(children)) = &mut current.value else {
+        return Err(invalid());
+    };
+    if *last as usize > children.len() {
+        return Err(invalid());
+    }
+    children.insert(*last as usize, secret);
+    Ok(())
+}
+
+fn collect_labeled_tags(schema_type: &SchemaType, policy: &mut SideChannelPolicy) {
+    match schema_type {
+        SchemaType::Object(fields) => {
+            for field in fields {
+                for label in &field.options.labels {
+                    match label {
+                        SecurityLabel::Secret | SecurityLabel::Pii => {
+                            policy.secret_tags.insert(field.tag);
+                        }
+                        SecurityLabel::Custom(name) if name == UNTRUSTED_LABEL => {
+                            policy.untrusted_tags.insert(field.tag);
+                        }
+                        _ => {}
+                    }
+                }
+                collect_labeled_tags(&field.field_type, policy);
+            }
+        }
+        SchemaType::Array(element_type) | SchemaType::Map(_, element_type) => collect_labeled_tags(element_type, policy),
+        SchemaType::Union(types) => {
+            for member in types {
+                collect_labeled_tags(member, policy);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bytes::Bytes;
+
+    fn item(query: &str) -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::String(Bytes::from(query.to_string()))),
+            HtlvItem::new(3, HtlvValue::Object(vec![
+                HtlvItem::new(4, HtlvValue::String(Bytes::from("token=7f3a9c2e5b1d8f60"))),
+                HtlvItem::new(5, HtlvValue::Bool(true)),
+            ])),
+            HtlvItem::new(4, HtlvValue::String(Bytes::from("token=0123456789abcdef"))),
+            HtlvItem::new(6, HtlvValue::String(Bytes::from("padding ".repeat(40)))),
+        ]))
+    }
+
+    #[test]
+    fn test_unmixed_body_is_compressed() {
+        let policy = SideChannelPolicy::new(SideChannelMode::IsolateSecrets).secret(4);
+        assert!(!policy.is_mixed(&item("token=7f3a")));
+
+        let body = policy.compress_item(CompressionStrategy::Zstd, &item("token=7f3a"), WireFormat::V1).unwrap();
+        assert_eq!(body.strategy, CompressionStrategy::Zstd);
+        assert!(!body.isolated);
+    }
+
+    #[test]
+    fn test_mixed_body_disables_compression() {
+        let policy = SideChannelPolicy::new(SideChannelMode::DisableCompression).secret(4).untrusted(2);
+        let body = policy.compress_item(CompressionStrategy::Zstd, &item("token=7f3a"), WireFormat::V1).unwrap();
+        assert_eq!(body.strategy, CompressionStrategy::NoCompression);
+        assert_eq!(body.data, encode_item_with_format(&item("token=7f3a"), WireFormat::V1).unwrap());
+    }
+
+    #[test]
+    fn test_isolated_secrets_round_trip_and_stop_the_oracle() {
+        let policy = SideChannelPolicy::new(SideChannelMode::IsolateSecrets).secret(4).untrusted(2);
+        let body = policy.compress_item(CompressionStrategy::Zstd, &item("token=7f3a"), WireFormat::V1).unwrap();
+        assert!(body.isolated);
+        assert_eq!(body.strategy, CompressionStrategy::Zstd);
+        assert_eq!(open_isolated(body.strategy, &body.data, WireFormat::V1).unwrap(), item("token=7f3a"));
+
+        // The compressed section does not depend on the secrets, so guesses
+        // in the query cannot be checked against them
+        let public_section = |secret: &str| {
+            let mut item = item("token=7f3a");
+            item.value = match item.value {
+                HtlvValue::Object(mut children) => {
+                    children[2] = HtlvItem::new(4, HtlvValue::String(Bytes::from(secret.to_string())));
+                    HtlvValue::Object(children)
+                }
+                value => value,
+            };
+            let body = policy.compress_item(CompressionStrategy::Zstd, &item, WireFormat::V1).unwrap();
+            WireCursor::new(&body.data).read_length_prefixed("public section").unwrap().to_vec()
+        };
+        assert_eq!(public_section("token=7f3a9c2e5b1d8f60"), public_section("token=zzzzzzzzzzzzzzzz"));
+    }
+
+    #[test]
+    fn test_open_isolated_rejects_bad_paths() {
+        let policy = SideChannelPolicy::new(SideChannelMode::IsolateSecrets).secret(4).untrusted(2);
+        let mut body = policy.compress_item(CompressionStrategy::NoCompression, &item("x"), WireFormat::V1).unwrap();
+        assert!(!body.isolated);
+
+        body = policy.compress_item(CompressionStrategy::Lz4, &item("x"), WireFormat::V1).unwrap();
+        body.data.push(0);
+        assert!(open_isolated(body.strategy, &body.data, WireFormat::V1).is_err());
+        assert!(insert_at(&mut item("x"), &[9, 0], HtlvItem::new(4, HtlvValue::Null)).is_err());
+    }
+}
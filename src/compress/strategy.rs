@@ -1,5 +1,6 @@
 use crate::internal::error::{Error, Result};
 use super::{CompressionStrategy, Compressor, get_compressor};
+use super::hints::{self, FieldHint};
 
 /// Enum representing different data types for compression strategy selection.
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -143,6 +144,14 @@ impl CompressionSelector {
         }
     }
 
+    /// Selects the compression strategy using per-field hints collected by the encoder.
+    ///
+    /// The hints take precedence (e.g. a payload dominated by already-compressed
+    /// fields is sent uncompressed); if they are inconclusive, the data itself is inspected.
+    pub fn select_strategy_with_hints(&self, data: &[u8], field_hints: &[FieldHint]) -> CompressionStrategy {
+        hints::suggest_strategy(field_hints).unwrap_or_else(|| self.select_strategy(data))
+    }
+
     /// Gets a compressor based on the selected strategy for the given data.
     pub fn get_compressor_for_data(&self, data: &[u8]) -> Result<Box<dyn Compressor>> {
         let strategy = self.select_strategy(data);
@@ -193,6 +202,29 @@ mod tests {
         assert_eq!(selector.select_strategy_for_type(DataType::Unknown), CompressionStrategy::Zstd);
     }
 
+    #[test]
+    fn test_select_strategy_with_hints() {
+        use crate::codec::types::{HtlvItem, HtlvValue};
+        use crate::compress::hints::FieldStatsCollector;
+        use bytes::Bytes;
+
+        let selector = CompressionSelector::default();
+        let text = b"This is a text payload that would normally go to Brotli";
+
+        // Without hints the data itself decides
+        assert_eq!(selector.select_strategy_with_hints(text, &[]), CompressionStrategy::Brotli);
+
+        // A payload dominated by a ZIP archive field is sent uncompressed
+        let mut zip = b"PK\x03\x04".to_vec();
+        zip.extend_from_slice(&[0u8; 512]);
+        let mut collector = FieldStatsCollector::new();
+        collector.observe(&HtlvItem::new(1, HtlvValue::Bytes(Bytes::from(zip))));
+        assert_eq!(
+            selector.select_strategy_with_hints(text, &collector.hints()),
+            CompressionStrategy::NoCompression
+        );
+    }
+
     #[test]
     fn test_select_strategy_with_different_bandwidth() {
         // Create a selector with high bandwidth and low latency
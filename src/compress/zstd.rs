@@ -1,7 +1,9 @@
 use crate::internal::error::{Error, Result};
 use super::Compressor; // Import the Compressor trait
 use zstd; // Import the zstd crate
+use zstd::dict::{DecoderDictionary, EncoderDictionary};
 use std::fmt::Debug; // Import Debug trait
+use std::io::{self, Read, Write};
 
 /// Compresses data using Zstandard algorithm.
 pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
@@ -13,6 +15,41 @@ pub fn decompress(data: &[u8]) -> Result<Vec<u8>> {
     zstd::decode_all(data).map_err(|e| Error::CompressionError(format!("Zstd decompression failed: {}", e)))
 }
 
+/// Compresses data using Zstandard algorithm, appending the result to `out`.
+pub fn compress_into(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    zstd::stream::copy_encode(data, out, 0).map_err(|e| Error::CompressionError(format!("Zstd compression failed: {}", e)))
+}
+
+/// Decompresses data using Zstandard algorithm, appending the result to `out`.
+pub fn decompress_into(data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    zstd::stream::copy_decode(data, out).map_err(|e| Error::CompressionError(format!("Zstd decompression failed: {}", e)))
+}
+
+/// Compresses data using Zstandard with `dictionary` as raw-content dictionary,
+/// appending the result to `out`.
+///
+/// The same dictionary must be passed to `decompress_with_dictionary`.
+pub fn compress_with_dictionary(data: &[u8], dictionary: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let dictionary = EncoderDictionary::copy(dictionary, 0);
+    zstd::stream::write::Encoder::with_prepared_dictionary(out, &dictionary)
+        .and_then(|mut encoder| {
+            encoder.write_all(data)?;
+            encoder.finish()
+        })
+        .map(|_| ())
+        .map_err(|e| Error::CompressionError(format!("Zstd dictionary compression failed: {}", e)))
+}
+
+/// Decompresses data compressed by `compress_with_dictionary` with the same
+/// dictionary, appending the result to `out`.
+pub fn decompress_with_dictionary(data: &[u8], dictionary: &[u8], out: &mut Vec<u8>) -> Result<()> {
+    let dictionary = DecoderDictionary::copy(dictionary);
+    zstd::stream::read::Decoder::with_prepared_dictionary(data, &dictionary)
+        .and_then(|mut decoder| decoder.read_to_end(out))
+        .map(|_| ())
+        .map_err(|e| Error::CompressionError(format!("Zstd dictionary decompression failed: {}", e)))
+}
+
 /// Zstandard Compressor implementation.
 #[derive(Debug)] // Added Debug derive
 pub struct ZstdCompressor;
@@ -27,6 +64,30 @@ impl Compressor for ZstdCompressor {
         // Call the specific decompression function
         decompress(data)
     }
+
+    fn compress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        compress_into(data, out)
+    }
+
+    fn decompress_into(&self, data: &[u8], out: &mut Vec<u8>) -> Result<()> {
+        decompress_into(data, out)
+    }
+
+    fn compress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        zstd::stream::write::Encoder::new(writer, 0)
+            .and_then(|mut encoder| {
+                let read = io::copy(reader, &mut encoder)?;
+                encoder.finish()?;
+                Ok(read)
+            })
+            .map_err(|e| Error::CompressionError(format!("Zstd compression failed: {}", e)))
+    }
+
+    fn decompress_stream(&self, reader: &mut dyn Read, writer: &mut dyn Write) -> Result<u64> {
+        zstd::stream::read::Decoder::new(reader)
+            .and_then(|mut decoder| io::copy(&mut decoder, writer))
+            .map_err(|e| Error::CompressionError(format!("Zstd decompression failed: {}", e)))
+    }
 }
 
 
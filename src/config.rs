@@ -0,0 +1,387 @@
+// Crate-wide configuration
+//
+// `TonitruConfig` gathers the limits and defaults that are otherwise spread
+// over the codec, compressors, allocators and validator. It is built with
+// `TonitruConfigBuilder`, optionally from `TONITRU_*` environment variables,
+// and hands out components configured accordingly.
+
+use crate::codec::decode::decoder_state_machine::MAX_NESTING_DEPTH;
+use crate::codec::decode::limits::DecodeConfig;
+use crate::codec::decode::simd_optimizations;
+use crate::compress::incremental::IncrementalCompressor;
+use crate::compress::sharded::{ShardedCompressor, DEFAULT_SHARD_SIZE};
+use crate::compress::CompressionStrategy;
+use crate::internal::alloc::PoolAllocator;
+use crate::internal::error::{Error, Result};
+use crate::internal::memory::MemoryBudget;
+use crate::schema::validator::ValidatorConfig;
+
+/// Smallest accepted large field threshold, in bytes
+pub const MIN_LARGE_FIELD_THRESHOLD: usize = 64;
+
+/// Default large field threshold, in bytes
+pub const DEFAULT_LARGE_FIELD_THRESHOLD: usize = 1024;
+
+/// Default dictionary size of incremental compressors, in bytes
+pub const DEFAULT_DICTIONARY_SIZE: usize = 64 * 1024;
+
+/// Default number of recycled buffers kept by pool allocators
+pub const DEFAULT_POOL_SIZE: usize = 16;
+
+/// Configuration shared by the components of the crate
+#[derive(Debug, Clone)]
+pub struct TonitruConfig {
+    max_nesting_depth: usize,
+    large_field_threshold: usize,
+    memory_limit: Option<usize>,
+    compression: CompressionStrategy,
+    shard_size: usize,
+    dictionary_size: usize,
+    simd: bool,
+    pool_size: usize,
+    validator: ValidatorConfig,
+    decoder: DecodeConfig,
+}
+
+impl Default for TonitruConfig {
+    fn default() -> Self {
+        Self {
+            max_nesting_depth: MAX_NESTING_DEPTH,
+            large_field_threshold: DEFAULT_LARGE_FIELD_THRESHOLD,
+            memory_limit: None,
+            compression: CompressionStrategy::Zstd,
+            shard_size: DEFAULT_SHARD_SIZE,
+            dictionary_size: DEFAULT_DICTIONARY_SIZE,
+            simd: true,
+            pool_size: DEFAULT_POOL_SIZE,
+            validator: ValidatorConfig::default(),
+            decoder: DecodeConfig::default(),
+        }
+    }
+}
+
+impl TonitruConfig {
+    /// Returns a builder starting from the default configuration
+    pub fn builder() -> TonitruConfigBuilder {
+        TonitruConfigBuilder::new()
+    }
+
+    /// Builds a configuration from the default values overridden by the
+    /// `TONITRU_*` environment variables
+    pub fn from_env() -> Result<Self> {
+        TonitruConfigBuilder::new().with_env()?.build()
+    }
+
+    /// Maximum nesting depth of complex values accepted by the decoder
+    pub fn max_nesting_depth(&self) -> usize {
+        self.max_nesting_depth
+    }
+
+    /// Size above which Bytes and String values are encoded as shards
+    pub fn large_field_threshold(&self) -> usize {
+        self.large_field_threshold
+    }
+
+    /// Total memory limit in bytes, or `None` when memory use is only accounted
+    pub fn memory_limit(&self) -> Option<usize> {
+        self.memory_limit
+    }
+
+    /// Default compression strategy
+    pub fn compression(&self) -> CompressionStrategy {
+        self.compression
+    }
+
+    /// Shard size of sharded compressors, in bytes
+    pub fn shard_size(&self) -> usize {
+        self.shard_size
+    }
+
+    /// Dictionary size of incremental compressors, in bytes
+    pub fn dictionary_size(&self) -> usize {
+        self.dictionary_size
+    }
+
+    /// Whether the SIMD decoding paths may be used
+    pub fn simd(&self) -> bool {
+        self.simd
+    }
+
+    /// Number of recycled buffers kept by pool allocators
+    pub fn pool_size(&self) -> usize {
+        self.pool_size
+    }
+
+    /// Validation policy for schema validators
+    pub fn validator_config(&self) -> ValidatorConfig {
+        self.validator.clone()
+    }
+
+    /// Limits applied by decoders to untrusted input
+    pub fn decode_config(&self) -> DecodeConfig {
+        self.decoder
+    }
+
+    /// Creates a memory budget with the configured limit
+    pub fn memory_budget(&self) -> MemoryBudget {
+        match self.memory_limit {
+            Some(limit) => MemoryBudget::with_limit(limit),
+            None => MemoryBudget::unlimited(),
+        }
+    }
+
+    /// Creates an incremental compressor with the configured strategy and
+    /// dictionary size
+    pub fn incremental_compressor(&self) -> IncrementalCompressor {
+        IncrementalCompressor::with_dict_size(self.compression, self.dictionary_size)
+    }
+
+    /// Creates a sharded compressor with the configured strategy and shard size
+    pub fn sharded_compressor(&self) -> ShardedCompressor {
+        ShardedCompressor::with_shard_size(self.compression, self.shard_size)
+    }
+
+    /// Creates a pool allocator with the configured pool size
+    pub fn pool_allocator(&self) -> PoolAllocator {
+        PoolAllocator::new(self.pool_size)
+    }
+
+    /// Applies the process-wide settings, currently the SIMD switch
+    pub fn apply_global(&self) {
+        simd_optimizations::set_simd_enabled(self.simd);
+    }
+}
+
+/// Builder for `TonitruConfig`
+#[derive(Debug, Clone, Default)]
+pub struct TonitruConfigBuilder {
+    config: TonitruConfig,
+}
+
+impl TonitruConfigBuilder {
+    /// Creates a builder starting from the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the maximum nesting depth, at most `MAX_NESTING_DEPTH`
+    pub fn max_nesting_depth(mut self, depth: usize) -> Self {
+        self.config.max_nesting_depth = depth;
+        self
+    }
+
+    /// Sets the size above which Bytes and String values are sharded
+    pub fn large_field_threshold(mut self, threshold: usize) -> Self {
+        self.config.large_field_threshold = threshold;
+        self
+    }
+
+    /// Sets the total memory limit; `None` removes the limit
+    pub fn memory_limit(mut self, limit: Option<usize>) -> Self {
+        self.config.memory_limit = limit;
+        self
+    }
+
+    /// Sets the default compression strategy
+    pub fn compression(mut self, strategy: CompressionStrategy) -> Self {
+        self.config.compression = strategy;
+        self
+    }
+
+    /// Sets the shard size of sharded compressors
+    pub fn shard_size(mut self, size: usize) -> Self {
+        self.config.shard_size = size;
+        self
+    }
+
+    /// Sets the dictionary size of incremental compressors
+    pub fn dictionary_size(mut self, size: usize) -> Self {
+        self.config.dictionary_size = size;
+        self
+    }
+
+    /// Enables or disables the SIMD decoding paths
+    pub fn simd(mut self, enabled: bool) -> Self {
+        self.config.simd = enabled;
+        self
+    }
+
+    /// Sets the number of recycled buffers kept by pool allocators
+    pub fn pool_size(mut self, size: usize) -> Self {
+        self.config.pool_size = size;
+        self
+    }
+
+    /// Sets the validation policy for schema validators
+    pub fn validator(mut self, validator: ValidatorConfig) -> Self {
+        self.config.validator = validator;
+        self
+    }
+
+    /// Sets the decoder limits; their nesting depth is replaced by the
+    /// maximum nesting depth of the configuration
+    pub fn decode_limits(mut self, limits: DecodeConfig) -> Self {
+        self.config.decoder = limits;
+        self
+    }
+
+    /// Overrides settings from the `TONITRU_*` environment variables
+    pub fn with_env(self) -> Result<Self> {
+        self.with_env_vars(std::env::vars())
+    }
+
+    /// Overrides settings from the given variables, as if they were the
+    /// environment. Variables without the `TONITRU_` prefix are ignored;
+    /// unknown `TONITRU_` variables are rejected.
+    ///
+    /// - `TONITRU_MAX_NESTING_DEPTH`, `TONITRU_LARGE_FIELD_THRESHOLD`,
+    ///   `TONITRU_SHARD_SIZE`, `TONITRU_DICTIONARY_SIZE`, `TONITRU_POOL_SIZE`,
+    ///   `TONITRU_MAX_ITEM_LENGTH`, `TONITRU_MAX_TOTAL_ITEMS`,
+    ///   `TONITRU_MAX_LARGE_FIELD_SIZE`: numbers
+    /// - `TONITRU_MEMORY_LIMIT`: a number of bytes or `unlimited`
+    /// - `TONITRU_COMPRESSION`: `none`, `zstd`, `lz4` or `brotli`
+    /// - `TONITRU_SIMD`, `TONITRU_ALLOW_UNKNOWN_FIELDS`: booleans
+    pub fn with_env_vars<I, K, V>(mut self, vars: I) -> Result<Self>
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<str>,
+        V: AsRef<str>,
+    {
+        for (name, value) in vars {
+            let (name, value) = (name.as_ref(), value.as_ref().trim());
+            if !name.starts_with("TONITRU_") {
+                continue;
+            }
+            match name {
+                "TONITRU_MAX_NESTING_DEPTH" => self.config.max_nesting_depth = parse_number(name, value)?,
+                "TONITRU_LARGE_FIELD_THRESHOLD" => self.config.large_field_threshold = parse_number(name, value)?,
+                "TONITRU_MEMORY_LIMIT" => {
+                    self.config.memory_limit = if value.eq_ignore_ascii_case("unlimited") {
+                        None
+                    } else {
+                        Some(parse_number(name, value)?)
+                    };
+                }
+                "TONITRU_COMPRESSION" => self.config.compression = parse_strategy(name, value)?,
+                "TONITRU_SHARD_SIZE" => self.config.shard_size = parse_number(name, value)?,
+                "TONITRU_DICTIONARY_SIZE" => self.config.dictionary_size = parse_number(name, value)?,
+                "TONITRU_SIMD" => self.config.simd = parse_bool(name, value)?,
+                "TONITRU_POOL_SIZE" => self.config.pool_size = parse_number(name, value)?,
+                "TONITRU_MAX_ITEM_LENGTH" => self.config.decoder.max_item_length = parse_number(name, value)?,
+                "TONITRU_MAX_TOTAL_ITEMS" => self.config.decoder.max_total_items = parse_number(name, value)?,
+                "TONITRU_MAX_LARGE_FIELD_SIZE" => self.config.decoder.max_large_field_size = parse_number(name, value)?,
+                "TONITRU_ALLOW_UNKNOWN_FIELDS" => self.config.validator.allow_unknown_fields = parse_bool(name, value)?,
+                _ => return Err(Error::ConfigError(format!("Unknown configuration variable {}", name))),
+            }
+        }
+        Ok(self)
+    }
+
+    /// Validates the settings and returns the configuration
+    pub fn build(self) -> Result<TonitruConfig> {
+        let config = self.config;
+        if config.max_nesting_depth == 0 || config.max_nesting_depth > MAX_NESTING_DEPTH {
+            return Err(Error::ConfigError(format!(
+                "Maximum nesting depth must be between 1 and {}, got {}",
+                MAX_NESTING_DEPTH, config.max_nesting_depth
+            )));
+        }
+        if config.large_field_threshold < MIN_LARGE_FIELD_THRESHOLD {
+            return Err(Error::ConfigError(format!(
+                "Large field threshold must be at least {} bytes, got {}",
+                MIN_LARGE_FIELD_THRESHOLD, config.large_field_threshold
+            )));
+        }
+        if config.shard_size == 0 {
+            return Err(Error::ConfigError("Shard size must not be zero".to_string()));
+        }
+        if config.dictionary_size == 0 {
+            return Err(Error::ConfigError("Dictionary size must not be zero".to_string()));
+        }
+        Ok(TonitruConfig {
+            validator: ValidatorConfig { max_nesting_depth: config.max_nesting_depth, ..config.validator.clone() },
+            decoder: DecodeConfig { max_nesting_depth: config.max_nesting_depth, ..config.decoder },
+            ..config
+        })
+    }
+}
+
+fn parse_number(name: &str, value: &str) -> Result<usize> {
+    value
+        .parse()
+        .map_err(|_| Error::ConfigError(format!("{} must be a number, got {:?}", name, value)))
+}
+
+fn parse_bool(name: &str, value: &str) -> Result<bool> {
+    match value.to_ascii_lowercase().as_str() {
+        "1" | "true" | "on" | "yes" => Ok(true),
+        "0" | "false" | "off" | "no" => Ok(false),
+        _ => Err(Error::ConfigError(format!("{} must be a boolean, got {:?}", name, value))),
+    }
+}
+
+fn parse_strategy(name: &str, value: &str) -> Result<CompressionStrategy> {
+    match value.to_ascii_lowercase().as_str() {
+        "none" => Ok(CompressionStrategy::NoCompression),
+        "zstd" => Ok(CompressionStrategy::Zstd),
+        "lz4" => Ok(CompressionStrategy::Lz4),
+        "brotli" => Ok(CompressionStrategy::Brotli),
+        _ => Err(Error::ConfigError(format!("{} must be none, zstd, lz4 or brotli, got {:?}", name, value))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::decode::{decode_item, decode_item_with_config};
+    use crate::codec::encode::{encode_item, encode_item_with_config};
+    use crate::codec::types::{HtlvItem, HtlvValue};
+    use bytes::Bytes;
+
+    #[test]
+    fn test_builder_and_env() {
+        let config = TonitruConfig::builder()
+            .compression(CompressionStrategy::Brotli)
+            .with_env_vars([
+                ("PATH", "/bin"),
+                ("TONITRU_MAX_NESTING_DEPTH", "4"),
+                ("TONITRU_MEMORY_LIMIT", "4096"),
+                ("TONITRU_SIMD", "off"),
+                ("TONITRU_ALLOW_UNKNOWN_FIELDS", "yes"),
+            ])
+            .unwrap()
+            .build()
+            .unwrap();
+        assert_eq!(config.max_nesting_depth(), 4);
+        assert_eq!(config.validator_config().max_nesting_depth, 4);
+        assert!(config.validator_config().allow_unknown_fields);
+        assert_eq!(config.memory_limit(), Some(4096));
+        assert_eq!(config.compression(), CompressionStrategy::Brotli);
+        assert!(!config.simd());
+        assert!(config.memory_budget().try_reserve(crate::internal::memory::MemorySubsystem::DecodeBuffers, 8192).is_err());
+
+        assert!(TonitruConfigBuilder::new().with_env_vars([("TONITRU_SIMD", "maybe")]).is_err());
+        assert!(TonitruConfigBuilder::new().with_env_vars([("TONITRU_TYPO", "1")]).is_err());
+        assert!(TonitruConfig::builder().max_nesting_depth(MAX_NESTING_DEPTH + 1).build().is_err());
+        assert!(TonitruConfig::builder().large_field_threshold(8).build().is_err());
+    }
+
+    #[test]
+    fn test_config_propagates_into_codec() {
+        let mut item = HtlvItem::new(1, HtlvValue::Bytes(Bytes::from(vec![7u8; 300])));
+        for _ in 0..3 {
+            item = HtlvItem::new(1, HtlvValue::Array(vec![item]));
+        }
+        let config = TonitruConfig::builder().max_nesting_depth(2).large_field_threshold(128).build().unwrap();
+
+        // The smaller threshold shards the nested value, which still decodes
+        let sharded = encode_item_with_config(&item, &config).unwrap();
+        assert!(sharded.len() > encode_item(&item).unwrap().len());
+        assert!(decode_item(&sharded).is_ok());
+
+        // The configured depth rejects the nesting the default allows
+        assert!(decode_item_with_config(&sharded, &config.decode_config()).is_err());
+        let relaxed = TonitruConfig::default();
+        assert!(decode_item_with_config(&sharded, &relaxed.decode_config()).is_ok());
+    }
+}
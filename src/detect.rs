@@ -0,0 +1,183 @@
+// Content sniffing for Tonitru artifacts
+//
+// `detect` tells apart the kinds of data a tool or ingestion endpoint may be
+// handed: archives, complete packets, bare encoded items (in either wire
+// format), several items back to back, and compressed blobs. Packets and items
+// carry no magic number, so they are recognized structurally: a packet must
+// parse and pass its checksum, and an item must walk cleanly to the end of the
+// input with plausible value sizes. Archives and compressed blobs are
+// recognized by their magic numbers.
+
+use crate::archive::{ARCHIVE_MAGIC, FOOTER_MAGIC};
+use crate::codec::decode::pull::{PullDecoder, PullEvent};
+use crate::codec::external::EXTERNAL_REF_HEADER_LEN;
+use crate::codec::types::HtlvValueType;
+use crate::codec::wire::{decode_item_with_format, WireFormat};
+use crate::compress::magic::{detect_format, PrecompressedFormat};
+use crate::internal::packet::{DataBodyType, Packet};
+
+/// The kind of a Tonitru artifact
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ContentKind {
+    /// A `.tna` archive
+    Archive,
+    /// A complete packet: metadata header, body and a valid checksum
+    Packet(DataBodyType),
+    /// A single encoded item
+    Item(WireFormat),
+    /// Several wire format v1 items back to back
+    ItemStream(usize),
+    /// A compressed or otherwise precompressed blob
+    Compressed(PrecompressedFormat),
+    /// None of the above
+    Unknown,
+}
+
+/// Detects the kind of a Tonitru artifact
+///
+/// The checks run from the most to the least specific: archive magic numbers
+/// at both ends and a packet checksum are conclusive, compression magic
+/// numbers are nearly so, and the structural item checks come last. Empty
+/// input is `Unknown`.
+pub fn detect(data: &[u8]) -> ContentKind {
+    if data.is_empty() {
+        return ContentKind::Unknown;
+    }
+
+    if data.starts_with(ARCHIVE_MAGIC) && data.ends_with(FOOTER_MAGIC) {
+        return ContentKind::Archive;
+    }
+
+    if let Ok(packet) = Packet::parse_packet(data) {
+        if let Ok(body_type) = DataBodyType::from_u8(packet.header.body_type) {
+            return ContentKind::Packet(body_type);
+        }
+    }
+
+    if let Some(format) = detect_format(data) {
+        return ContentKind::Compressed(format);
+    }
+
+    match count_v1_items(data) {
+        Some(1) => return ContentKind::Item(WireFormat::V1),
+        Some(items) => return ContentKind::ItemStream(items),
+        None => {}
+    }
+
+    match decode_item_with_format(data, WireFormat::V2) {
+        Ok((_, bytes_read)) if bytes_read == data.len() => ContentKind::Item(WireFormat::V2),
+        _ => ContentKind::Unknown,
+    }
+}
+
+/// Walks wire format v1 items to the end of the input; returns the number of
+/// top-level items, or `None` if the data is not a sequence of valid items
+fn count_v1_items(data: &[u8]) -> Option<usize> {
+    let mut decoder = PullDecoder::new(data);
+    let mut items = 0;
+
+    loop {
+        let top_level = decoder.depth() == 0;
+        match decoder.next_event() {
+            Ok(None) => break,
+            Ok(Some(PullEvent::Field(_, value_type, value))) => {
+                if !plausible_size(value_type, value.len()) {
+                    return None;
+                }
+            }
+            Ok(Some(PullEvent::EndObject)) | Ok(Some(PullEvent::EndArray)) => continue,
+            Ok(Some(_)) => {}
+            Err(_) => return None,
+        }
+        if top_level {
+            items += 1;
+        }
+    }
+
+    if items == 0 {
+        None
+    } else {
+        Some(items)
+    }
+}
+
+/// Returns true if a v1 value of the given type can have the given length;
+/// multi-byte numbers may hold a batch of values
+fn plausible_size(value_type: HtlvValueType, length: usize) -> bool {
+    let multiple_of = |size: usize| length > 0 && length.is_multiple_of(size);
+    match value_type {
+        HtlvValueType::Null => length == 0,
+        HtlvValueType::Bool | HtlvValueType::U8 | HtlvValueType::I8 => length == 1,
+        HtlvValueType::U16 | HtlvValueType::I16 => multiple_of(2),
+        HtlvValueType::U32 | HtlvValueType::I32 | HtlvValueType::F32 => multiple_of(4),
+        HtlvValueType::U64 | HtlvValueType::I64 | HtlvValueType::F64 => multiple_of(8),
+        HtlvValueType::Bytes | HtlvValueType::String | HtlvValueType::Extension => true,
+        HtlvValueType::ExternalRef => length >= EXTERNAL_REF_HEADER_LEN,
+        HtlvValueType::Array | HtlvValueType::Object => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::ArchiveWriter;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::{HtlvItem, HtlvValue};
+    use crate::codec::wire::encode_item_with_format;
+    use crate::compress::{get_compressor, CompressionStrategy};
+    use crate::internal::packet::{DataBody, MetadataHeader};
+
+    fn sample_item() -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::U8(7)),
+            HtlvItem::new(3, HtlvValue::String("hello".into())),
+        ]))
+    }
+
+    #[test]
+    fn test_detect_items() {
+        let encoded = encode_item(&sample_item()).unwrap();
+        assert_eq!(detect(&encoded), ContentKind::Item(WireFormat::V1));
+
+        let mut stream = encoded.clone();
+        stream.extend_from_slice(&encoded);
+        stream.extend_from_slice(&encode_item(&HtlvItem::new(4, HtlvValue::Bool(true))).unwrap());
+        assert_eq!(detect(&stream), ContentKind::ItemStream(3));
+
+        // A compact v2 header is not a valid v1 type byte
+        let compact = encode_item_with_format(&HtlvItem::new(5, HtlvValue::U32(9)), WireFormat::V2).unwrap();
+        assert_eq!(detect(&compact), ContentKind::Item(WireFormat::V2));
+    }
+
+    #[test]
+    fn test_detect_packet() {
+        let header = MetadataHeader { schema_id: 1, timestamp: 2, shard_id: 3, flow_flags: 0, body_type: 0, key_id: None };
+        let body = DataBody::Raw(encode_item(&sample_item()).unwrap());
+        let packet = Packet::build_packet(header, body).unwrap();
+
+        let mut data = packet.header.encode().unwrap();
+        data.extend_from_slice(&packet.body.encode().unwrap());
+        data.extend_from_slice(&packet.checksum.encode());
+        assert_eq!(detect(&data), ContentKind::Packet(DataBodyType::Raw));
+
+        // A corrupted packet fails its checksum and is not an item either
+        let last = data.len() - 1;
+        data[last] ^= 0xFF;
+        assert_eq!(detect(&data), ContentKind::Unknown);
+    }
+
+    #[test]
+    fn test_detect_compressed_and_unknown() {
+        let encoded = encode_item(&sample_item()).unwrap();
+        let compressed = get_compressor(CompressionStrategy::Zstd).unwrap().compress(&encoded).unwrap();
+        assert_eq!(detect(&compressed), ContentKind::Compressed(PrecompressedFormat::Zstd));
+
+        let archive = ArchiveWriter::new(Vec::new()).finish().unwrap();
+        assert_eq!(detect(&archive), ContentKind::Archive);
+
+        assert_eq!(detect(&[]), ContentKind::Unknown);
+        assert_eq!(detect(b"{\"not\": \"tonitru\"}"), ContentKind::Unknown);
+        // Truncated item
+        assert_eq!(detect(&encoded[..encoded.len() - 1]), ContentKind::Unknown);
+    }
+}
@@ -2,9 +2,10 @@
 //
 // This module provides AES-GCM encryption and decryption functionality.
 
+use crate::encrypt::rng::SecureRng;
 use crate::internal::error::{Error, Result};
 use aes_gcm::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit},
     Aes256Gcm, Key, Nonce,
 };
 use std::collections::HashMap;
@@ -17,7 +18,6 @@ const KEY_SIZE: usize = 32;
 const NONCE_SIZE: usize = 12;
 
 /// AES-GCM encryptor implementation
-#[derive(Debug)]
 pub struct AesGcmEncryptor {
     // Default key used when no key_id is provided
     default_key: Key<Aes256Gcm>,
@@ -25,10 +25,18 @@ pub struct AesGcmEncryptor {
     cipher_cache: Arc<Mutex<HashMap<String, Aes256Gcm>>>,
 }
 
+impl std::fmt::Debug for AesGcmEncryptor {
+    // Keys and ciphers are not printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cached = self.cipher_cache.lock().map(|cache| cache.len()).unwrap_or(0);
+        f.debug_struct("AesGcmEncryptor").field("cached_keys", &cached).finish_non_exhaustive()
+    }
+}
+
 impl AesGcmEncryptor {
     /// Creates a new AesGcmEncryptor with a randomly generated default key.
     pub fn new() -> Result<Self> {
-        let default_key = Aes256Gcm::generate_key(&mut OsRng);
+        let default_key = Aes256Gcm::generate_key(&mut SecureRng);
         
         Ok(Self {
             default_key,
@@ -108,7 +116,7 @@ impl super::Encryptor for AesGcmEncryptor {
         let cipher = self.get_cipher(key_id)?;
         
         // Generate a random nonce
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let nonce = Aes256Gcm::generate_nonce(&mut SecureRng);
         
         // Encrypt the data
         let ciphertext = cipher.encrypt(&nonce, data).map_err(|e| {
@@ -143,11 +151,16 @@ impl super::Encryptor for AesGcmEncryptor {
         
         Ok(plaintext)
     }
+
+    fn strategy(&self) -> super::EncryptionStrategy {
+        super::EncryptionStrategy::AesGcm
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encrypt::Encryptor;
     
     #[test]
     fn test_aes_gcm_encrypt_decrypt() {
@@ -2,9 +2,10 @@
 //
 // This module provides ChaCha20-Poly1305 encryption and decryption functionality.
 
+use crate::encrypt::rng::SecureRng;
 use crate::internal::error::{Error, Result};
 use chacha20poly1305::{
-    aead::{Aead, AeadCore, KeyInit, OsRng},
+    aead::{Aead, AeadCore, KeyInit},
     ChaCha20Poly1305, Key, Nonce,
 };
 use std::collections::HashMap;
@@ -17,18 +18,25 @@ const KEY_SIZE: usize = 32;
 const NONCE_SIZE: usize = 12;
 
 /// ChaCha20-Poly1305 encryptor implementation
-#[derive(Debug)]
 pub struct ChaCha20Poly1305Encryptor {
     // Default key used when no key_id is provided
-    default_key: Key<ChaCha20Poly1305>,
+    default_key: Key,
     // Cache of cipher instances for different keys
     cipher_cache: Arc<Mutex<HashMap<String, ChaCha20Poly1305>>>,
 }
 
+impl std::fmt::Debug for ChaCha20Poly1305Encryptor {
+    // Keys and ciphers are not printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cached = self.cipher_cache.lock().map(|cache| cache.len()).unwrap_or(0);
+        f.debug_struct("ChaCha20Poly1305Encryptor").field("cached_keys", &cached).finish_non_exhaustive()
+    }
+}
+
 impl ChaCha20Poly1305Encryptor {
     /// Creates a new ChaCha20Poly1305Encryptor with a randomly generated default key.
     pub fn new() -> Result<Self> {
-        let default_key = ChaCha20Poly1305::generate_key(&mut OsRng);
+        let default_key = ChaCha20Poly1305::generate_key(&mut SecureRng);
         
         Ok(Self {
             default_key,
@@ -46,7 +54,7 @@ impl ChaCha20Poly1305Encryptor {
             )));
         }
         
-        let default_key = *Key::<ChaCha20Poly1305>::from_slice(key);
+        let default_key = *Key::from_slice(key);
         
         Ok(Self {
             default_key,
@@ -64,7 +72,7 @@ impl ChaCha20Poly1305Encryptor {
             )));
         }
         
-        let cipher = ChaCha20Poly1305::new(Key::<ChaCha20Poly1305>::from_slice(key));
+        let cipher = ChaCha20Poly1305::new(Key::from_slice(key));
         
         let mut cache = self.cipher_cache.lock().map_err(|_| {
             Error::EncryptionError("Failed to acquire lock on cipher cache".to_string())
@@ -108,7 +116,7 @@ impl super::Encryptor for ChaCha20Poly1305Encryptor {
         let cipher = self.get_cipher(key_id)?;
         
         // Generate a random nonce
-        let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let nonce = ChaCha20Poly1305::generate_nonce(&mut SecureRng);
         
         // Encrypt the data
         let ciphertext = cipher.encrypt(&nonce, data).map_err(|e| {
@@ -143,11 +151,16 @@ impl super::Encryptor for ChaCha20Poly1305Encryptor {
         
         Ok(plaintext)
     }
+
+    fn strategy(&self) -> super::EncryptionStrategy {
+        super::EncryptionStrategy::ChaCha20Poly1305
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encrypt::Encryptor;
     
     #[test]
     fn test_chacha20_poly1305_encrypt_decrypt() {
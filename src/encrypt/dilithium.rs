@@ -0,0 +1,145 @@
+// Dilithium signatures for Tonitru packets
+//
+// An attacker with a large quantum computer could forge Ed25519 signatures
+// (see encrypt::signature). Dilithium3, the CRYSTALS-Dilithium parameter set at
+// NIST security level 3 like Kyber768, is believed to resist such attackers.
+// `DilithiumSigningKey` and `DilithiumVerifyingKey` wrap the keys of
+// pqcrypto-dilithium; packets are signed with them alongside Ed25519 through
+// the hybrid signatures of encrypt::hybrid_signature.
+//
+// Dilithium keys and signatures are large: a signature takes 3293 bytes and a
+// public key 1952. Key generation draws on the randomness of pqcrypto itself.
+
+use std::fmt;
+
+use pqcrypto_dilithium::dilithium3;
+use pqcrypto_traits::sign::{DetachedSignature as _, PublicKey as _, SecretKey as _};
+
+use crate::internal::error::{Error, Result};
+
+/// Length of a Dilithium3 signature in bytes
+pub const DILITHIUM_SIGNATURE_LEN: usize = dilithium3::signature_bytes();
+/// Length of a Dilithium3 public key in bytes
+pub const DILITHIUM_PUBLIC_KEY_LEN: usize = dilithium3::public_key_bytes();
+/// Length of a Dilithium3 private key in bytes
+pub const DILITHIUM_SECRET_KEY_LEN: usize = dilithium3::secret_key_bytes();
+
+/// A Dilithium3 private key with its public key, used to sign.
+#[derive(Clone)]
+pub struct DilithiumSigningKey {
+    public_key: dilithium3::PublicKey,
+    secret_key: dilithium3::SecretKey,
+}
+
+impl fmt::Debug for DilithiumSigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DilithiumSigningKey").field("verifying_key", &self.verifying_key()).finish_non_exhaustive()
+    }
+}
+
+impl DilithiumSigningKey {
+    /// Generates a random key.
+    pub fn generate() -> Self {
+        let (public_key, secret_key) = dilithium3::keypair();
+        DilithiumSigningKey { public_key, secret_key }
+    }
+
+    /// Creates a key from its public and private key bytes. Dilithium private
+    /// keys do not yield their public key, so both are needed.
+    pub fn from_bytes(public_key: &[u8], secret_key: &[u8]) -> Result<Self> {
+        let DilithiumVerifyingKey { key: public_key } = DilithiumVerifyingKey::from_bytes(public_key)?;
+        let secret_key = dilithium3::SecretKey::from_bytes(secret_key).map_err(|_| {
+            Error::EncryptionError(format!(
+                "Invalid Dilithium private key length: {} bytes, expected {}",
+                secret_key.len(),
+                DILITHIUM_SECRET_KEY_LEN
+            ))
+        })?;
+        Ok(DilithiumSigningKey { public_key, secret_key })
+    }
+
+    /// Returns the private key bytes.
+    pub fn secret_key_bytes(&self) -> &[u8] {
+        self.secret_key.as_bytes()
+    }
+
+    /// Returns the public key matching this key.
+    pub fn verifying_key(&self) -> DilithiumVerifyingKey {
+        DilithiumVerifyingKey { key: self.public_key }
+    }
+
+    /// Signs a message.
+    pub fn sign(&self, message: &[u8]) -> Vec<u8> {
+        dilithium3::detached_sign(message, &self.secret_key).as_bytes().to_vec()
+    }
+}
+
+/// A Dilithium3 public key, used to verify signatures.
+#[derive(Clone, Copy)]
+pub struct DilithiumVerifyingKey {
+    key: dilithium3::PublicKey,
+}
+
+impl fmt::Debug for DilithiumVerifyingKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The key is long; its hash identifies it well enough
+        let fingerprint = blake3::hash(self.key.as_bytes());
+        f.debug_struct("DilithiumVerifyingKey").field("fingerprint", &fingerprint.to_hex()).finish()
+    }
+}
+
+impl PartialEq for DilithiumVerifyingKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.key.as_bytes() == other.key.as_bytes()
+    }
+}
+
+impl Eq for DilithiumVerifyingKey {}
+
+impl DilithiumVerifyingKey {
+    /// Creates a key from its public key bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let key = dilithium3::PublicKey::from_bytes(bytes).map_err(|_| {
+            Error::EncryptionError(format!(
+                "Invalid Dilithium public key length: {} bytes, expected {}",
+                bytes.len(),
+                DILITHIUM_PUBLIC_KEY_LEN
+            ))
+        })?;
+        Ok(DilithiumVerifyingKey { key })
+    }
+
+    /// Returns the public key bytes.
+    pub fn as_bytes(&self) -> &[u8] {
+        self.key.as_bytes()
+    }
+
+    /// Verifies the signature of a message.
+    pub fn verify(&self, message: &[u8], signature: &[u8]) -> Result<()> {
+        let failed = || Error::EncryptionError("Dilithium signature verification failed".to_string());
+        let signature = dilithium3::DetachedSignature::from_bytes(signature).map_err(|_| failed())?;
+        dilithium3::verify_detached_signature(&signature, message, &self.key).map_err(|_| failed())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = DilithiumSigningKey::generate();
+        let restored = DilithiumSigningKey::from_bytes(key.verifying_key().as_bytes(), key.secret_key_bytes()).unwrap();
+        let public = DilithiumVerifyingKey::from_bytes(restored.verifying_key().as_bytes()).unwrap();
+        assert_eq!(public, key.verifying_key());
+
+        let signature = key.sign(b"message");
+        assert_eq!(signature.len(), DILITHIUM_SIGNATURE_LEN);
+        assert!(public.verify(b"message", &signature).is_ok());
+        let err = public.verify(b"massage", &signature).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Dilithium signature verification failed");
+        assert!(public.verify(b"message", &signature[1..]).is_err());
+        assert!(DilithiumSigningKey::generate().verifying_key().verify(b"message", &signature).is_err());
+        assert!(DilithiumVerifyingKey::from_bytes(&[0u8; 32]).is_err());
+    }
+}
@@ -7,9 +7,9 @@ use crate::internal::error::{Error, Result};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
-use rand_core::OsRng;
+use crate::encrypt::rng::SecureRng;
 use aes_gcm::{
-    aead::{Aead, KeyInit},
+    aead::{Aead, AeadCore, KeyInit},
     Aes256Gcm, Key, Nonce,
 };
 use chacha20poly1305::{ChaCha20Poly1305};
@@ -48,7 +48,6 @@ pub enum SymmetricAlgorithm {
 /// ECC encryptor implementation
 ///
 /// This encryptor uses ECC for key exchange and a symmetric algorithm for data encryption.
-#[derive(Debug)]
 pub struct EccEncryptor {
     // Default keypair used when no key_id is provided
     default_private_key: StaticSecret,
@@ -59,10 +58,22 @@ pub struct EccEncryptor {
     symmetric_algorithm: SymmetricAlgorithm,
 }
 
+impl std::fmt::Debug for EccEncryptor {
+    // Private keys are not printed
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let cached = self.keypair_cache.lock().map(|cache| cache.len()).unwrap_or(0);
+        f.debug_struct("EccEncryptor")
+            .field("default_public_key", &self.default_public_key)
+            .field("cached_keypairs", &cached)
+            .field("symmetric_algorithm", &self.symmetric_algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
 impl EccEncryptor {
     /// Creates a new EccEncryptor with a randomly generated default keypair.
     pub fn new(symmetric_algorithm: SymmetricAlgorithm) -> Result<Self> {
-        let default_private_key = StaticSecret::new(OsRng);
+        let default_private_key = StaticSecret::random_from_rng(SecureRng);
         let default_public_key = PublicKey::from(&default_private_key);
         
         Ok(Self {
@@ -138,7 +149,7 @@ impl EccEncryptor {
     
     /// Generates a new keypair and adds it to the cache.
     pub fn generate_keypair(&self, key_id: &str) -> Result<()> {
-        let private_key = StaticSecret::new(OsRng);
+        let private_key = StaticSecret::random_from_rng(SecureRng);
         let public_key = PublicKey::from(&private_key);
         
         let mut cache = self.keypair_cache.lock().map_err(|_| {
@@ -151,7 +162,7 @@ impl EccEncryptor {
     }
     
     /// Gets the keypair for the given key_id, or the default keypair if None.
-    fn get_keypair(&self, key_id: Option<&str>) -> Result<(&StaticSecret, PublicKey)> {
+    fn get_keypair(&self, key_id: Option<&str>) -> Result<(StaticSecret, PublicKey)> {
         match key_id {
             Some(id) => {
                 let cache = self.keypair_cache.lock().map_err(|_| {
@@ -159,12 +170,12 @@ impl EccEncryptor {
                 })?;
                 
                 if let Some((private_key, public_key)) = cache.get(id) {
-                    Ok((private_key, *public_key))
+                    Ok((private_key.clone(), *public_key))
                 } else {
                     Err(Error::EncryptionError(format!("Key ID '{}' not found in cache", id)))
                 }
             }
-            None => Ok((&self.default_private_key, self.default_public_key)),
+            None => Ok((self.default_private_key.clone(), self.default_public_key)),
         }
     }
     
@@ -187,7 +198,7 @@ impl super::Encryptor for EccEncryptor {
         let (_, public_key) = self.get_keypair(key_id)?;
         
         // Generate an ephemeral key for this encryption
-        let ephemeral_secret = EphemeralSecret::new(OsRng);
+        let ephemeral_secret = EphemeralSecret::random_from_rng(SecureRng);
         let ephemeral_public = PublicKey::from(&ephemeral_secret);
         
         // Perform key exchange to get a shared secret
@@ -197,7 +208,7 @@ impl super::Encryptor for EccEncryptor {
         let symmetric_key = self.derive_symmetric_key(shared_secret.as_bytes());
         
         // Generate a random nonce
-        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let nonce = Aes256Gcm::generate_nonce(&mut SecureRng);
         
         // Encrypt the data with the chosen symmetric algorithm
         let ciphertext = match self.symmetric_algorithm {
@@ -208,7 +219,7 @@ impl super::Encryptor for EccEncryptor {
                 })?
             }
             SymmetricAlgorithm::ChaCha20Poly1305 => {
-                let cipher = ChaCha20Poly1305::new(Key::<ChaCha20Poly1305>::from_slice(&symmetric_key));
+                let cipher = ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&symmetric_key));
                 cipher.encrypt(&nonce, data).map_err(|e| {
                     Error::EncryptionError(format!("ChaCha20-Poly1305 encryption failed: {}", e))
                 })?
@@ -260,7 +271,7 @@ impl super::Encryptor for EccEncryptor {
                 })?
             }
             SymmetricAlgorithm::ChaCha20Poly1305 => {
-                let cipher = ChaCha20Poly1305::new(Key::<ChaCha20Poly1305>::from_slice(&symmetric_key));
+                let cipher = ChaCha20Poly1305::new(chacha20poly1305::Key::from_slice(&symmetric_key));
                 cipher.decrypt(nonce, ciphertext).map_err(|e| {
                     Error::EncryptionError(format!("ChaCha20-Poly1305 decryption failed: {}", e))
                 })?
@@ -269,4 +280,11 @@ impl super::Encryptor for EccEncryptor {
         
         Ok(plaintext)
     }
+
+    fn strategy(&self) -> super::EncryptionStrategy {
+        match self.symmetric_algorithm {
+            SymmetricAlgorithm::AesGcm => super::EncryptionStrategy::EccAesGcm,
+            SymmetricAlgorithm::ChaCha20Poly1305 => super::EncryptionStrategy::EccChaCha20Poly1305,
+        }
+    }
 }
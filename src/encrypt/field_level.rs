@@ -2,10 +2,33 @@
 //
 // This module provides field-level encryption capabilities, allowing selective
 // encryption of specific fields in a data structure.
+//
+// `FieldLevelEncryptor` selects fields by tag through named policies.
+// `FieldLevelProcessor` selects them from a schema instead: it encrypts the
+// fields whose options say `encrypt: true`, and optionally those whose security
+// labels require it. An encrypted field becomes a `Bytes` value made of the
+// encryption strategy byte, the key ID as a varint length and UTF-8 bytes, and
+// the sealed v2 encoding of the original value, so fields of any type can be
+// encrypted and decryption needs nothing but the envelope and the keys.
+//
+// The strategy and key ID of an envelope are its `FieldKeyVersion`. Rotating a
+// field key does not require rewriting stored data at once: a processor with
+// the new key ID, whose encryptors still hold the retired keys, reads fields of
+// every version. Records are moved to the new key lazily, by `reseal` when they
+// are written back, and in the background by a `FieldKeySweeper` working
+// through stored records batch by batch.
 
 use crate::internal::error::{Error, Result};
 use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use crate::codec::transform::transform_fields;
+use crate::codec::varint;
+use crate::codec::wire::{decode_item_with_format, encode_item_with_format, WireFormat};
+use crate::internal::cancel::{self, CancellationToken};
+use crate::internal::cursor::WireCursor;
+use crate::schema::policy::PolicyEngine;
+use crate::schema::types::{Schema, SchemaField};
 use super::{Encryptor, EncryptionStrategy, get_encryptor};
+use std::cell::Cell;
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
 
@@ -59,6 +82,8 @@ pub struct FieldLevelEncryptor {
     policies: Arc<Mutex<HashMap<String, FieldEncryptionPolicy>>>,
     /// Cache of encryptors for different strategies
     encryptor_cache: Arc<Mutex<HashMap<EncryptionStrategy, Box<dyn Encryptor>>>>,
+    /// Token checked before each field is encrypted or decrypted
+    cancellation: Option<CancellationToken>,
 }
 
 impl FieldLevelEncryptor {
@@ -67,8 +92,15 @@ impl FieldLevelEncryptor {
         Ok(Self {
             policies: Arc::new(Mutex::new(HashMap::new())),
             encryptor_cache: Arc::new(Mutex::new(HashMap::new())),
+            cancellation: None,
         })
     }
+
+    /// Fails with `Error::Cancelled` before the next field once the token is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
     
     /// Adds a policy.
     pub fn add_policy(&self, policy_name: &str, policy: FieldEncryptionPolicy) -> Result<()> {
@@ -132,8 +164,8 @@ impl FieldLevelEncryptor {
         
         // Serialize the value to bytes
         let value_bytes = match &item.value {
-            HtlvValue::Bytes(bytes) => bytes.clone(),
-            HtlvValue::String(s) => s.as_bytes().to_vec(),
+            HtlvValue::Bytes(bytes) => bytes.to_vec(),
+            HtlvValue::String(s) => s.to_vec(),
             // For other types, we need to serialize them first
             // This is a simplified version; in a real implementation,
             // you would use the codec module to properly serialize the value
@@ -148,7 +180,7 @@ impl FieldLevelEncryptor {
         // Create a new item with the encrypted value
         Ok(HtlvItem {
             tag: item.tag,
-            value: HtlvValue::Bytes(encrypted_bytes),
+            value: HtlvValue::Bytes(encrypted_bytes.into()),
         })
     }
     
@@ -178,7 +210,7 @@ impl FieldLevelEncryptor {
         // In a real implementation, you would need to know the original type.
         Ok(HtlvItem {
             tag: item.tag,
-            value: HtlvValue::Bytes(decrypted_bytes),
+            value: HtlvValue::Bytes(decrypted_bytes.into()),
         })
     }
     
@@ -191,6 +223,7 @@ impl FieldLevelEncryptor {
                 let mut encrypted_fields = Vec::with_capacity(fields.len());
                 
                 for field in fields {
+                    cancel::check(&self.cancellation, "Field encryption")?;
                     let encrypted_field = self.encrypt_field(field, &policy, key_id)?;
                     encrypted_fields.push(encrypted_field);
                 }
@@ -213,6 +246,7 @@ impl FieldLevelEncryptor {
                 let mut decrypted_fields = Vec::with_capacity(fields.len());
                 
                 for field in fields {
+                    cancel::check(&self.cancellation, "Field decryption")?;
                     let decrypted_field = self.decrypt_field(field, &policy, key_id)?;
                     decrypted_fields.push(decrypted_field);
                 }
@@ -227,6 +261,222 @@ impl FieldLevelEncryptor {
     }
 }
 
+/// Schema-driven field-level encryptor
+///
+/// Walks an item together with its schema and encrypts the values of the
+/// fields that must be encrypted, leaving the rest of the item readable.
+#[derive(Debug)]
+pub struct FieldLevelProcessor {
+    /// Encryptors by strategy; the one of `strategy` seals new fields
+    encryptors: HashMap<EncryptionStrategy, Box<dyn Encryptor>>,
+    /// Strategy used to encrypt fields
+    strategy: EncryptionStrategy,
+    /// Key ID fields are encrypted under
+    key_id: Option<String>,
+    /// Policy whose labels also mark fields for encryption
+    policy: Option<PolicyEngine>,
+    /// Token checked before each field is encrypted or decrypted
+    cancellation: Option<CancellationToken>,
+}
+
+impl FieldLevelProcessor {
+    /// Creates a processor encrypting fields with the encryptor, which holds
+    /// the key, under the optional key ID.
+    pub fn new(encryptor: impl Encryptor + 'static, key_id: Option<&str>) -> Self {
+        let strategy = encryptor.strategy();
+        let mut encryptors: HashMap<EncryptionStrategy, Box<dyn Encryptor>> = HashMap::new();
+        encryptors.insert(strategy, Box::new(encryptor));
+        Self { encryptors, strategy, key_id: key_id.map(str::to_string), policy: None, cancellation: None }
+    }
+
+    /// Adds an encryptor used only to decrypt fields sealed with its strategy,
+    /// e.g. by an earlier configuration.
+    pub fn with_decryptor(mut self, encryptor: impl Encryptor + 'static) -> Self {
+        self.encryptors.entry(encryptor.strategy()).or_insert_with(|| Box::new(encryptor));
+        self
+    }
+
+    /// Also encrypts the fields whose security labels the policy encrypts.
+    pub fn with_policy(mut self, policy: PolicyEngine) -> Self {
+        self.policy = Some(policy);
+        self
+    }
+
+    /// Fails with `Error::Cancelled` before the next field once the token is cancelled.
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Returns the key version new fields are sealed with.
+    pub fn key_version(&self) -> FieldKeyVersion {
+        FieldKeyVersion { strategy: self.strategy, key_id: self.key_id.clone().unwrap_or_default() }
+    }
+
+    /// Returns a copy of the item with the fields marked for encryption encrypted.
+    pub fn encrypt(&self, schema: &Schema, item: &HtlvItem) -> Result<HtlvItem> {
+        transform_fields(schema, item, &|field| self.requires_encryption(field), &|field, value| self.seal(field, value))
+    }
+
+    /// Returns a copy of the item with the encrypted fields decrypted.
+    pub fn decrypt(&self, schema: &Schema, item: &HtlvItem) -> Result<HtlvItem> {
+        transform_fields(schema, item, &|field| self.requires_encryption(field), &|field, value| self.open(field, value))
+    }
+
+    /// Returns the number of encrypted fields of the item sealed with another
+    /// key version than the current one.
+    pub fn stale_fields(&self, schema: &Schema, item: &HtlvItem) -> Result<usize> {
+        let stale = Cell::new(0);
+        transform_fields(schema, item, &|field| self.requires_encryption(field), &|field, value| {
+            let (version, _) = parse_envelope(field, value)?;
+            if version != self.key_version() {
+                stale.set(stale.get() + 1);
+            }
+            Ok(value.clone())
+        })?;
+        Ok(stale.get())
+    }
+
+    /// Returns a copy of an encrypted item with the fields sealed under older
+    /// key versions re-encrypted with the current one, and the number of
+    /// fields re-encrypted.
+    ///
+    /// Fields already at the current version are kept as they are. Calling
+    /// this when a stored record is written back moves records to a new key
+    /// as they are updated, without decrypting the rest of the item.
+    pub fn reseal(&self, schema: &Schema, item: &HtlvItem) -> Result<(HtlvItem, usize)> {
+        let resealed = Cell::new(0);
+        let item = transform_fields(schema, item, &|field| self.requires_encryption(field), &|field, value| {
+            let (version, _) = parse_envelope(field, value)?;
+            if version == self.key_version() {
+                return Ok(value.clone());
+            }
+            resealed.set(resealed.get() + 1);
+            self.seal(field, &self.open(field, value)?)
+        })?;
+        Ok((item, resealed.get()))
+    }
+
+    fn requires_encryption(&self, field: &SchemaField) -> bool {
+        match &self.policy {
+            Some(policy) => policy.requires_encryption(field),
+            None => field.options.encrypt,
+        }
+    }
+
+    /// Encrypts a field value into its envelope.
+    fn seal(&self, field: &SchemaField, value: &HtlvValue) -> Result<HtlvValue> {
+        cancel::check(&self.cancellation, "Field encryption")?;
+        let encryptor = &self.encryptors[&self.strategy];
+        let plain = encode_item_with_format(&HtlvItem::new(field.tag, value.clone()), WireFormat::V2)?;
+        let key_id = self.key_id.as_deref().unwrap_or("");
+
+        let mut envelope = vec![self.strategy as u8];
+        envelope.extend_from_slice(&varint::encode_varint(key_id.len() as u64));
+        envelope.extend_from_slice(key_id.as_bytes());
+        envelope.extend_from_slice(&encryptor.encrypt(&plain, self.key_id.as_deref())?);
+        Ok(HtlvValue::Bytes(envelope.into()))
+    }
+
+    /// Decrypts a field envelope back into the original value.
+    fn open(&self, field: &SchemaField, value: &HtlvValue) -> Result<HtlvValue> {
+        cancel::check(&self.cancellation, "Field decryption")?;
+        let (version, sealed) = parse_envelope(field, value)?;
+        let encryptor = self.encryptors.get(&version.strategy).ok_or_else(|| {
+            Error::EncryptionError(format!("No encryptor registered for {:?} in field '{}'", version.strategy, field.name))
+        })?;
+
+        let key_id = (!version.key_id.is_empty()).then_some(version.key_id.as_str());
+        let plain = encryptor.decrypt(sealed, key_id)?;
+        let (item, _) = decode_item_with_format(&plain, WireFormat::V2)?;
+        Ok(item.value)
+    }
+}
+
+/// The strategy and key ID an encrypted field was sealed with
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct FieldKeyVersion {
+    /// Strategy the field was encrypted with
+    pub strategy: EncryptionStrategy,
+    /// Key ID the field was encrypted under, empty for the default key of the encryptor
+    pub key_id: String,
+}
+
+/// Splits a field envelope into its key version and the sealed value.
+fn parse_envelope<'a>(field: &SchemaField, value: &'a HtlvValue) -> Result<(FieldKeyVersion, &'a [u8])> {
+    let HtlvValue::Bytes(envelope) = value else {
+        return Err(Error::EncryptionError(format!(
+            "Encrypted field '{}' holds a {:?} value instead of bytes",
+            field.name,
+            value.value_type()
+        )));
+    };
+    let mut cursor = WireCursor::new(envelope).with_truncation_error(Error::EncryptionError);
+    let strategy = EncryptionStrategy::from_u8(cursor.read_u8("field encryption strategy")?)?;
+    let key_id = std::str::from_utf8(cursor.read_length_prefixed("field key ID")?)
+        .map_err(|e| Error::EncryptionError(format!("Invalid key ID in field '{}': {}", field.name, e)))?;
+    Ok((FieldKeyVersion { strategy, key_id: key_id.to_string() }, cursor.rest()))
+}
+
+/// Counts of a sweep over stored records
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SweepReport {
+    /// Records examined
+    pub records_scanned: usize,
+    /// Records holding at least one field sealed under an older key version
+    pub records_rewritten: usize,
+    /// Fields re-encrypted with the current key version
+    pub fields_resealed: usize,
+}
+
+impl SweepReport {
+    /// Adds the counts of another sweep to this one.
+    pub fn merge(&mut self, other: SweepReport) {
+        self.records_scanned += other.records_scanned;
+        self.records_rewritten += other.records_rewritten;
+        self.fields_resealed += other.fields_resealed;
+    }
+}
+
+/// Moves stored records to the current field key version in the background
+///
+/// The sweeper is cheap to clone and can be handed to a background thread,
+/// which feeds it batches of stored records and writes back the records the
+/// report counts as rewritten. Records are only rewritten if they hold a field
+/// sealed under an older key version, so sweeping is idempotent and can be
+/// interrupted and resumed at any batch.
+#[derive(Debug, Clone)]
+pub struct FieldKeySweeper {
+    processor: Arc<FieldLevelProcessor>,
+    schema: Arc<Schema>,
+}
+
+impl FieldKeySweeper {
+    /// Creates a sweeper resealing records of the schema with the processor.
+    pub fn new(processor: Arc<FieldLevelProcessor>, schema: Arc<Schema>) -> Self {
+        Self { processor, schema }
+    }
+
+    /// Re-encrypts the stale fields of a batch of records in place and returns
+    /// the counts of the batch.
+    ///
+    /// Fails on the first record that cannot be resealed, e.g. because its key
+    /// is no longer held; the records before it are already rewritten.
+    pub fn sweep(&self, records: &mut [HtlvItem]) -> Result<SweepReport> {
+        let mut report = SweepReport::default();
+        for record in records.iter_mut() {
+            report.records_scanned += 1;
+            let (resealed, fields) = self.processor.reseal(&self.schema, record)?;
+            if fields > 0 {
+                *record = resealed;
+                report.records_rewritten += 1;
+                report.fields_resealed += fields;
+            }
+        }
+        Ok(report)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -254,4 +504,111 @@ mod tests {
     }
     
     // More tests would be added for FieldLevelEncryptor
+
+    use crate::schema::testing::{self, field};
+    use crate::schema::types::{SchemaOptions, SchemaType};
+
+    fn schema() -> Schema {
+        let encrypt = || SchemaOptions { encrypt: true, ..Default::default() };
+        let address = SchemaType::Object(vec![
+            field("city", 1, SchemaType::String, SchemaOptions::default()),
+            field("street", 2, SchemaType::String, encrypt()),
+        ]);
+        testing::schema("users", "User", SchemaType::Object(vec![
+            field("id", 1, SchemaType::UInt64, SchemaOptions::default()),
+            field("ssn", 2, SchemaType::UInt64, encrypt()),
+            field("addresses", 3, SchemaType::Array(Box::new(address)), SchemaOptions::default()),
+        ]))
+    }
+
+    fn user() -> HtlvItem {
+        let address = |city: &str, street: &str| HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String(city.to_string().into())),
+            HtlvItem::new(2, HtlvValue::String(street.to_string().into())),
+        ]));
+        HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::U64(7)),
+            HtlvItem::new(2, HtlvValue::U64(123_456_789)),
+            HtlvItem::new(3, HtlvValue::Array(vec![address("Oslo", "Storgata 1"), address("Bergen", "Bryggen 2")])),
+        ]))
+    }
+
+    #[test]
+    fn test_processor_encrypts_marked_fields_only() {
+        use crate::encrypt::aes_gcm::AesGcmEncryptor;
+
+        let processor = FieldLevelProcessor::new(AesGcmEncryptor::with_key(&[5u8; 32]).unwrap(), None);
+        let encrypted = processor.encrypt(&schema(), &user()).unwrap();
+        let HtlvValue::Object(fields) = &encrypted.value else { panic!("Expected an object") };
+        assert_eq!(fields[0].value, HtlvValue::U64(7));
+        let HtlvValue::Bytes(envelope) = &fields[1].value else { panic!("Expected an envelope") };
+        assert_eq!(&envelope[..2], &[EncryptionStrategy::AesGcm as u8, 0]);
+        let HtlvValue::Array(addresses) = &fields[2].value else { panic!("Expected an array") };
+        let HtlvValue::Object(address) = &addresses[1].value else { panic!("Expected an object") };
+        assert_eq!(address[0].value, HtlvValue::String("Bergen".to_string().into()));
+        assert!(matches!(address[1].value, HtlvValue::Bytes(_)));
+
+        assert_eq!(processor.decrypt(&schema(), &encrypted).unwrap(), user());
+    }
+
+    #[test]
+    fn test_processor_key_ids_and_decryptors() {
+        use crate::encrypt::aes_gcm::AesGcmEncryptor;
+        use crate::encrypt::chacha20_poly1305::ChaCha20Poly1305Encryptor;
+
+        let old = AesGcmEncryptor::with_key(&[1u8; 32]).unwrap();
+        old.add_key("2025", &[2u8; 32]).unwrap();
+        let sealed = FieldLevelProcessor::new(old, Some("2025")).encrypt(&schema(), &user()).unwrap();
+
+        // A processor that moved on to another strategy still reads old fields
+        let current = FieldLevelProcessor::new(ChaCha20Poly1305Encryptor::with_key(&[3u8; 32]).unwrap(), None);
+        let err = current.decrypt(&schema(), &sealed).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: No encryptor registered for AesGcm in field 'ssn'");
+
+        let reader = AesGcmEncryptor::with_key(&[9u8; 32]).unwrap();
+        reader.add_key("2025", &[2u8; 32]).unwrap();
+        let current = current.with_decryptor(reader);
+        assert_eq!(current.decrypt(&schema(), &sealed).unwrap(), user());
+
+        let err = current.decrypt(&schema(), &user()).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Encrypted field 'ssn' holds a U64 value instead of bytes");
+    }
+
+    #[test]
+    fn test_key_rotation_reseal_and_sweep() {
+        use crate::encrypt::aes_gcm::AesGcmEncryptor;
+
+        let keys = || {
+            let encryptor = AesGcmEncryptor::with_key(&[0u8; 32]).unwrap();
+            encryptor.add_key("v1", &[1u8; 32]).unwrap();
+            encryptor.add_key("v2", &[2u8; 32]).unwrap();
+            encryptor
+        };
+        let old = FieldLevelProcessor::new(keys(), Some("v1"));
+        let mut records = vec![old.encrypt(&schema(), &user()).unwrap(), old.encrypt(&schema(), &user()).unwrap()];
+
+        // After rotation, fields of both versions are readable
+        let current = Arc::new(FieldLevelProcessor::new(keys(), Some("v2")));
+        assert_eq!(current.key_version(), FieldKeyVersion { strategy: EncryptionStrategy::AesGcm, key_id: "v2".to_string() });
+        assert_eq!(current.decrypt(&schema(), &records[0]).unwrap(), user());
+        assert_eq!(current.stale_fields(&schema(), &records[0]).unwrap(), 3);
+
+        // Writing a record back reseals it lazily
+        let (written, resealed) = current.reseal(&schema(), &records[0]).unwrap();
+        assert_eq!(resealed, 3);
+        assert_eq!(current.stale_fields(&schema(), &written).unwrap(), 0);
+        assert_eq!(current.reseal(&schema(), &written).unwrap(), (written.clone(), 0));
+        records[0] = written;
+
+        let sweeper = FieldKeySweeper::new(Arc::clone(&current), Arc::new(schema()));
+        let report = sweeper.sweep(&mut records).unwrap();
+        assert_eq!(report, SweepReport { records_scanned: 2, records_rewritten: 1, fields_resealed: 3 });
+        assert_eq!(sweeper.sweep(&mut records).unwrap().records_rewritten, 0);
+        assert!(records.iter().all(|record| current.decrypt(&schema(), record).unwrap() == user()));
+
+        // Once swept, the retired key can be dropped
+        let v2_only = AesGcmEncryptor::with_key(&[0u8; 32]).unwrap();
+        v2_only.add_key("v2", &[2u8; 32]).unwrap();
+        assert_eq!(FieldLevelProcessor::new(v2_only, Some("v2")).decrypt(&schema(), &records[1]).unwrap(), user());
+    }
 }
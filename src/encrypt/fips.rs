@@ -0,0 +1,259 @@
+// FIPS mode of the encryption module
+//
+// Deployments bound to FIPS 140 build with the `fips` feature. AES-256-GCM and
+// SHA-256 checksums are then computed by aws-lc-rs, whose AWS-LC module is
+// FIPS validated, instead of RustCrypto, and its generator draws the keys and
+// nonces. The wire format does not change: a FIPS peer exchanges AES-GCM
+// packets with any other peer.
+//
+// Only approved strategies are usable in FIPS mode. `get_encryptor`, the
+// packet builder and packet decryption fail fast on the others, rather than
+// sending or accepting data protected by algorithms outside the boundary:
+//
+//   NoEncryption   approved, no cryptography involved
+//   AesGcm         approved
+//   ChaCha20       not approved
+//   Kyber          not approved; the Kyber round 3 implementation predates
+//                  ML-KEM (FIPS 203) and is not validated
+//   ECC            not approved; X25519 key agreement is not in SP 800-56A
+//   hybrids        not approved, as they include one of the above
+//
+// The other primitives of the crate are restricted the same way:
+//
+//   SHA-256 checksums     approved, computed by AWS-LC; packet builders
+//                         default to them in FIPS mode
+//   BLAKE3, CRC32C, XXH3  not approved; packets checksummed with them are
+//                         neither built nor parsed
+//   Ed25519, Dilithium    not available; signing packets and verifying their
+//                         signatures fail, as both are computed outside the
+//                         validated module
+//   HKDF                  not available; `RatchetEncryptor`, which derives its
+//                         keys with it, cannot be created
+//   BLAKE3 hashes         kept where they identify content rather than protect
+//                         it: external references, dictionary and schema
+//                         fingerprints, archive Merkle trees and Bloom filters
+//
+// Without the feature, everything is allowed and the `check_*` functions never
+// fail, so code can call them unconditionally.
+
+use crate::encrypt::EncryptionStrategy;
+use crate::internal::checksum::ChecksumAlgorithm;
+use crate::internal::error::{Error, Result};
+
+/// True when the crate is built in FIPS mode
+pub const ENABLED: bool = cfg!(feature = "fips");
+
+/// Returns true if the strategy only uses FIPS approved algorithms.
+pub fn is_approved(strategy: EncryptionStrategy) -> bool {
+    matches!(strategy, EncryptionStrategy::NoEncryption | EncryptionStrategy::AesGcm)
+}
+
+/// Fails in FIPS mode if the strategy is not approved.
+pub fn check_approved(strategy: EncryptionStrategy) -> Result<()> {
+    if ENABLED && !is_approved(strategy) {
+        return Err(Error::EncryptionError(format!(
+            "{:?} is not a FIPS approved encryption strategy",
+            strategy
+        )));
+    }
+    Ok(())
+}
+
+/// Fails in FIPS mode if the checksum algorithm is not approved.
+pub fn check_checksum(algorithm: ChecksumAlgorithm) -> Result<()> {
+    if ENABLED && !algorithm.is_fips_approved() {
+        return Err(Error::EncryptionError(format!(
+            "{:?} is not a FIPS approved checksum algorithm",
+            algorithm
+        )));
+    }
+    Ok(())
+}
+
+/// Fails in FIPS mode, where packets are neither signed nor verified.
+pub fn check_signatures() -> Result<()> {
+    unavailable("Packet signing")
+}
+
+/// Fails in FIPS mode, where no keys are derived with HKDF.
+pub fn check_key_derivation() -> Result<()> {
+    unavailable("HKDF key derivation")
+}
+
+fn unavailable(what: &str) -> Result<()> {
+    if ENABLED {
+        return Err(Error::EncryptionError(format!("{} is not available in FIPS mode", what)));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "fips")]
+pub use backend::FipsAesGcmEncryptor;
+
+#[cfg(feature = "fips")]
+mod backend {
+    use crate::encrypt::{EncryptionStrategy, Encryptor};
+    use crate::internal::error::{Error, Result};
+    use aws_lc_rs::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM, NONCE_LEN};
+    use std::collections::HashMap;
+    use std::sync::{Arc, Mutex};
+
+    /// The length of the AES-GCM key in bytes (256 bits)
+    const KEY_SIZE: usize = 32;
+
+    /// AES-256-GCM encryptor backed by the validated AWS-LC module
+    ///
+    /// Ciphertexts are laid out as those of `AesGcmEncryptor`: the nonce
+    /// followed by the sealed data and tag.
+    pub struct FipsAesGcmEncryptor {
+        // Key used when no key_id is provided
+        default_key: Arc<LessSafeKey>,
+        // Keys by key_id
+        key_cache: Arc<Mutex<HashMap<String, Arc<LessSafeKey>>>>,
+    }
+
+    impl std::fmt::Debug for FipsAesGcmEncryptor {
+        // Keys are not printed
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            let cached = self.key_cache.lock().map(|cache| cache.len()).unwrap_or(0);
+            f.debug_struct("FipsAesGcmEncryptor").field("cached_keys", &cached).finish_non_exhaustive()
+        }
+    }
+
+    impl FipsAesGcmEncryptor {
+        /// Creates an encryptor with a default key drawn from the AWS-LC generator.
+        pub fn new() -> Result<Self> {
+            let mut key = [0u8; KEY_SIZE];
+            aws_lc_rs::rand::fill(&mut key)
+                .map_err(|_| Error::EncryptionError("Failed to generate AES-GCM key".to_string()))?;
+            Self::with_key(&key)
+        }
+
+        /// Creates an encryptor with the provided default key.
+        pub fn with_key(key: &[u8]) -> Result<Self> {
+            Ok(Self {
+                default_key: Arc::new(sealing_key(key)?),
+                key_cache: Arc::new(Mutex::new(HashMap::new())),
+            })
+        }
+
+        /// Adds a key to the key cache.
+        pub fn add_key(&self, key_id: &str, key: &[u8]) -> Result<()> {
+            let key = Arc::new(sealing_key(key)?);
+            self.lock_cache()?.insert(key_id.to_string(), key);
+            Ok(())
+        }
+
+        /// Removes a key from the key cache.
+        pub fn remove_key(&self, key_id: &str) -> Result<()> {
+            self.lock_cache()?.remove(key_id);
+            Ok(())
+        }
+
+        fn lock_cache(&self) -> Result<std::sync::MutexGuard<'_, HashMap<String, Arc<LessSafeKey>>>> {
+            self.key_cache
+                .lock()
+                .map_err(|_| Error::EncryptionError("Failed to acquire lock on key cache".to_string()))
+        }
+
+        /// Gets the key for the given key_id, or the default key if None.
+        fn get_key(&self, key_id: Option<&str>) -> Result<Arc<LessSafeKey>> {
+            match key_id {
+                Some(id) => self
+                    .lock_cache()?
+                    .get(id)
+                    .cloned()
+                    .ok_or_else(|| Error::EncryptionError(format!("Key ID '{}' not found in cache", id))),
+                None => Ok(Arc::clone(&self.default_key)),
+            }
+        }
+    }
+
+    fn sealing_key(key: &[u8]) -> Result<LessSafeKey> {
+        if key.len() != KEY_SIZE {
+            return Err(Error::EncryptionError(format!(
+                "Invalid AES-GCM key size: expected {} bytes, got {} bytes",
+                KEY_SIZE,
+                key.len()
+            )));
+        }
+        let key = UnboundKey::new(&AES_256_GCM, key)
+            .map_err(|_| Error::EncryptionError("AWS-LC rejected the AES-GCM key".to_string()))?;
+        Ok(LessSafeKey::new(key))
+    }
+
+    impl Encryptor for FipsAesGcmEncryptor {
+        fn encrypt(&self, data: &[u8], key_id: Option<&str>) -> Result<Vec<u8>> {
+            let key = self.get_key(key_id)?;
+            let mut nonce = [0u8; NONCE_LEN];
+            aws_lc_rs::rand::fill(&mut nonce)
+                .map_err(|_| Error::EncryptionError("Failed to generate AES-GCM nonce".to_string()))?;
+
+            let mut sealed = data.to_vec();
+            key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::empty(), &mut sealed)
+                .map_err(|_| Error::EncryptionError("AES-GCM encryption failed".to_string()))?;
+
+            let mut result = Vec::with_capacity(NONCE_LEN + sealed.len());
+            result.extend_from_slice(&nonce);
+            result.extend_from_slice(&sealed);
+            Ok(result)
+        }
+
+        fn decrypt(&self, data: &[u8], key_id: Option<&str>) -> Result<Vec<u8>> {
+            if data.len() < NONCE_LEN {
+                return Err(Error::EncryptionError("Data too short to contain nonce".to_string()));
+            }
+            let key = self.get_key(key_id)?;
+            let nonce = Nonce::try_assume_unique_for_key(&data[..NONCE_LEN])
+                .map_err(|_| Error::EncryptionError("Invalid AES-GCM nonce".to_string()))?;
+
+            let mut sealed = data[NONCE_LEN..].to_vec();
+            let length = key
+                .open_in_place(nonce, Aad::empty(), &mut sealed)
+                .map_err(|_| Error::EncryptionError("AES-GCM decryption failed".to_string()))?
+                .len();
+            sealed.truncate(length);
+            Ok(sealed)
+        }
+
+        fn strategy(&self) -> EncryptionStrategy {
+            EncryptionStrategy::AesGcm
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt::aes_gcm::AesGcmEncryptor;
+    use crate::encrypt::{get_encryptor, Encryptor};
+
+    #[test]
+    fn test_only_approved_strategies_in_fips_mode() {
+        assert!(is_approved(EncryptionStrategy::AesGcm));
+        assert!(!is_approved(EncryptionStrategy::ChaCha20Poly1305));
+        assert!(!is_approved(EncryptionStrategy::Hybrid));
+
+        assert!(check_approved(EncryptionStrategy::AesGcm).is_ok());
+        assert_eq!(check_approved(EncryptionStrategy::EccAesGcm).is_ok(), !ENABLED);
+        assert_eq!(get_encryptor(EncryptionStrategy::ChaCha20Poly1305).is_ok(), !ENABLED);
+
+        assert!(check_checksum(ChecksumAlgorithm::Sha256).is_ok());
+        assert_eq!(check_checksum(ChecksumAlgorithm::Blake3).is_ok(), !ENABLED);
+        assert_eq!(check_signatures().is_ok(), !ENABLED);
+        assert_eq!(check_key_derivation().is_ok(), !ENABLED);
+
+        // AES-GCM ciphertexts are the same format with either backend
+        let key = [5u8; 32];
+        let encryptor = AesGcmEncryptor::with_key(&key).unwrap();
+        let sealed = encryptor.encrypt(b"approved", None).unwrap();
+        #[cfg(feature = "fips")]
+        {
+            let fips = FipsAesGcmEncryptor::with_key(&key).unwrap();
+            assert_eq!(fips.decrypt(&sealed, None).unwrap(), b"approved");
+            let sealed = fips.encrypt(b"approved", None).unwrap();
+            assert_eq!(encryptor.decrypt(&sealed, None).unwrap(), b"approved");
+        }
+        assert_eq!(encryptor.decrypt(&sealed, None).unwrap(), b"approved");
+    }
+}
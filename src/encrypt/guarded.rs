@@ -0,0 +1,274 @@
+// Access-controlled decryption with an audit trail
+//
+// Compliance rules around personal data ask not only that PII is encrypted but
+// that every decryption has a stated purpose, stays within what the key may be
+// used for and leaves a trace. A `GuardedDecryptor` wraps an encryptor and only
+// decrypts through `GuardedDecryptor::decrypt`, which takes a
+// `DecryptionContext` naming the purpose and the actor. Before decrypting it
+// checks the `KeyAccessPolicy` of the key: the purposes the key may be
+// decrypted for, and how many decryptions it allows per time window. Every
+// call, granted or not, is reported to the attached `AuditSink`.
+//
+// Keys are identified by the key ID passed to `decrypt`; the encryptor's
+// default key is the empty ID. A key without a policy is never decrypted
+// unless a default policy is set.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::encrypt::Encryptor;
+use crate::internal::error::{Error, Result};
+
+/// Who decrypts and why
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DecryptionContext<'a> {
+    /// Purpose of the decryption, checked against the key's policy
+    pub purpose: &'a str,
+    /// User or service asking for the decryption, recorded in the audit trail
+    pub actor: &'a str,
+}
+
+impl<'a> DecryptionContext<'a> {
+    /// Creates a context for the actor decrypting for the purpose
+    pub fn new(purpose: &'a str, actor: &'a str) -> Self {
+        Self { purpose, actor }
+    }
+}
+
+/// What a key may be decrypted for
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct KeyAccessPolicy {
+    allowed_purposes: Vec<String>,
+    rate_limit: Option<(usize, Duration)>,
+}
+
+impl KeyAccessPolicy {
+    /// Creates a policy allowing no purpose
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows decryption for the purpose
+    pub fn allow_purpose(mut self, purpose: &str) -> Self {
+        self.allowed_purposes.push(purpose.to_string());
+        self
+    }
+
+    /// Allows at most `max` decryptions within any window of length `per`
+    pub fn with_rate_limit(mut self, max: usize, per: Duration) -> Self {
+        self.rate_limit = Some((max, per));
+        self
+    }
+
+    /// Returns true if the policy allows the purpose
+    pub fn allows(&self, purpose: &str) -> bool {
+        self.allowed_purposes.iter().any(|allowed| allowed == purpose)
+    }
+}
+
+/// Outcome of a guarded decryption
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditOutcome {
+    /// The data was decrypted
+    Granted,
+    /// The key has no policy, or its policy does not allow the purpose
+    PurposeDenied,
+    /// The key's rate limit was reached
+    RateLimited,
+    /// Decryption failed, or the key's policy could not be checked
+    Failed,
+}
+
+/// A record of one guarded decryption call
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecryptionAudit {
+    /// Time of the call
+    pub time: SystemTime,
+    /// Key ID of the call, empty for the default key
+    pub key_id: String,
+    /// Purpose given by the caller
+    pub purpose: String,
+    /// Actor given by the caller
+    pub actor: String,
+    /// Size of the ciphertext in bytes
+    pub ciphertext_len: usize,
+    /// What happened
+    pub outcome: AuditOutcome,
+}
+
+/// Receives the audit records of guarded decryptions
+pub trait AuditSink: Send + Sync {
+    /// Records a decryption call. Must not fail; sinks that can fail should
+    /// buffer or raise an alert themselves.
+    fn record(&self, audit: &DecryptionAudit);
+}
+
+impl<F: Fn(&DecryptionAudit) + Send + Sync> AuditSink for F {
+    fn record(&self, audit: &DecryptionAudit) {
+        self(audit)
+    }
+}
+
+/// Decrypts only with a stated purpose, under per-key policies, and audits
+/// every call
+pub struct GuardedDecryptor {
+    inner: Box<dyn Encryptor>,
+    policies: HashMap<String, KeyAccessPolicy>,
+    default_policy: Option<KeyAccessPolicy>,
+    /// Times of the recent decryptions of each rate-limited key
+    recent: Mutex<HashMap<String, VecDeque<Instant>>>,
+    sink: Option<Box<dyn AuditSink>>,
+}
+
+impl fmt::Debug for GuardedDecryptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("GuardedDecryptor")
+            .field("inner", &self.inner)
+            .field("policies", &self.policies)
+            .field("default_policy", &self.default_policy)
+            .field("audited", &self.sink.is_some())
+            .finish()
+    }
+}
+
+impl GuardedDecryptor {
+    /// Wraps the encryptor, which holds the keys; no key may be decrypted
+    /// until a policy allows it
+    pub fn new(inner: impl Encryptor + 'static) -> Self {
+        Self {
+            inner: Box::new(inner),
+            policies: HashMap::new(),
+            default_policy: None,
+            recent: Mutex::new(HashMap::new()),
+            sink: None,
+        }
+    }
+
+    /// Sets the policy of a key; the empty key ID is the default key
+    pub fn with_policy(mut self, key_id: &str, policy: KeyAccessPolicy) -> Self {
+        self.policies.insert(key_id.to_string(), policy);
+        self
+    }
+
+    /// Sets the policy of the keys without a policy of their own
+    pub fn with_default_policy(mut self, policy: KeyAccessPolicy) -> Self {
+        self.default_policy = Some(policy);
+        self
+    }
+
+    /// Reports every decryption call to the sink
+    pub fn with_audit_sink(mut self, sink: impl AuditSink + 'static) -> Self {
+        self.sink = Some(Box::new(sink));
+        self
+    }
+
+    /// Decrypts the data if the key's policy allows the purpose of the context
+    /// and its rate limit is not reached
+    pub fn decrypt(&self, data: &[u8], key_id: Option<&str>, context: &DecryptionContext<'_>) -> Result<Vec<u8>> {
+        let key = key_id.unwrap_or("");
+        let checked = self.check(key, context.purpose);
+        let outcome = checked.as_ref().copied().unwrap_or(AuditOutcome::Failed);
+        let result = match checked {
+            Err(e) => Err(e),
+            Ok(AuditOutcome::PurposeDenied) => Err(Error::EncryptionError(format!(
+                "Decryption with key '{}' is not allowed for purpose '{}'",
+                key, context.purpose
+            ))),
+            Ok(AuditOutcome::RateLimited) => Err(Error::EncryptionError(format!(
+                "Decryption rate limit of key '{}' reached",
+                key
+            ))),
+            Ok(_) => self.inner.decrypt(data, key_id),
+        };
+
+        if let Some(sink) = &self.sink {
+            sink.record(&DecryptionAudit {
+                time: SystemTime::now(),
+                key_id: key.to_string(),
+                purpose: context.purpose.to_string(),
+                actor: context.actor.to_string(),
+                ciphertext_len: data.len(),
+                outcome: match (&result, outcome) {
+                    (Err(_), AuditOutcome::Granted) => AuditOutcome::Failed,
+                    _ => outcome,
+                },
+            });
+        }
+        result
+    }
+
+    /// Checks the purpose and the rate limit of the key, counting the call
+    /// against the limit if it is granted
+    fn check(&self, key: &str, purpose: &str) -> Result<AuditOutcome> {
+        let Some(policy) = self.policies.get(key).or(self.default_policy.as_ref()) else {
+            return Ok(AuditOutcome::PurposeDenied);
+        };
+        if !policy.allows(purpose) {
+            return Ok(AuditOutcome::PurposeDenied);
+        }
+        let Some((max, per)) = policy.rate_limit else {
+            return Ok(AuditOutcome::Granted);
+        };
+
+        let mut recent = self.recent.lock().map_err(|_| {
+            Error::EncryptionError("Failed to acquire lock on decryption rate limits".to_string())
+        })?;
+        let times = recent.entry(key.to_string()).or_default();
+        let now = Instant::now();
+        while times.front().is_some_and(|time| now.duration_since(*time) >= per) {
+            times.pop_front();
+        }
+        if times.len() >= max {
+            return Ok(AuditOutcome::RateLimited);
+        }
+        times.push_back(now);
+        Ok(AuditOutcome::Granted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt::aes_gcm::AesGcmEncryptor;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_policies_rate_limits_and_audit() {
+        let inner = AesGcmEncryptor::with_key(&[1u8; 32]).unwrap();
+        inner.add_key("pii", &[2u8; 32]).unwrap();
+        let ssn = inner.encrypt(b"123-45-6789", Some("pii")).unwrap();
+        let other = inner.encrypt(b"not pii", None).unwrap();
+
+        let audits = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&audits);
+        let guarded = GuardedDecryptor::new(inner)
+            .with_policy("pii", KeyAccessPolicy::new().allow_purpose("billing").with_rate_limit(2, Duration::from_secs(3600)))
+            .with_audit_sink(move |audit: &DecryptionAudit| sink.lock().unwrap().push(audit.clone()));
+
+        let billing = DecryptionContext::new("billing", "invoice-service");
+        assert_eq!(guarded.decrypt(&ssn, Some("pii"), &billing).unwrap(), b"123-45-6789");
+        let err = guarded.decrypt(&ssn, Some("pii"), &DecryptionContext::new("marketing", "crm")).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Decryption with key 'pii' is not allowed for purpose 'marketing'");
+        // Keys without a policy are denied, and failed decryptions count
+        assert!(guarded.decrypt(&other, None, &billing).is_err());
+        assert!(guarded.decrypt(&other, Some("pii"), &billing).is_err());
+        assert!(guarded.decrypt(&ssn, Some("pii"), &billing).is_err());
+
+        let outcomes: Vec<_> = audits.lock().unwrap().iter().map(|audit| audit.outcome).collect();
+        assert_eq!(outcomes, vec![
+            AuditOutcome::Granted,
+            AuditOutcome::PurposeDenied,
+            AuditOutcome::PurposeDenied,
+            AuditOutcome::Failed,
+            AuditOutcome::RateLimited,
+        ]);
+        let first = &audits.lock().unwrap()[0];
+        assert_eq!((first.key_id.as_str(), first.actor.as_str(), first.ciphertext_len), ("pii", "invoice-service", ssn.len()));
+
+        let open = GuardedDecryptor::new(AesGcmEncryptor::with_key(&[1u8; 32]).unwrap())
+            .with_default_policy(KeyAccessPolicy::new().allow_purpose("billing"));
+        assert_eq!(open.decrypt(&other, None, &billing).unwrap(), b"not pii");
+    }
+}
@@ -0,0 +1,171 @@
+// Hybrid Ed25519 + Dilithium signatures
+//
+// Moving a fleet to post-quantum signatures takes time: for a while, some
+// receivers know the Dilithium keys of their senders and others only the
+// Ed25519 ones. A hybrid-signed packet carries both signatures of the same
+// message. `HybridSigningKey` holds both private keys of a sender, and a
+// receiver's `HybridVerifyingKey` holds the public keys it knows, either or
+// both. The `SignaturePolicy` decides which packets are authentic:
+//
+//   Either   every signature the receiver has a key for verifies, and there is
+//            at least one; for the migration, while Dilithium keys roll out
+//   Both     both signatures are present and verify; once every receiver has
+//            the Dilithium keys
+//
+// Under `Either` a packet can be no stronger than Ed25519 for receivers without
+// the Dilithium key. Dropping a signature from a packet does not help an
+// attacker: the header flags recording which signatures follow are covered by
+// the checksum that both signatures sign.
+
+use crate::encrypt::dilithium::{DilithiumSigningKey, DilithiumVerifyingKey};
+use crate::encrypt::signature::{SigningKey, VerifyingKey, SIGNATURE_LEN};
+use crate::internal::error::{Error, Result};
+
+/// Which signatures of a hybrid-signed message must verify
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SignaturePolicy {
+    /// Every signature the receiver holds a key for verifies, and at least one does
+    #[default]
+    Either,
+    /// Both the Ed25519 and the Dilithium signature verify
+    Both,
+}
+
+/// The Ed25519 and Dilithium signatures of a message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HybridSignature {
+    /// Ed25519 signature
+    pub classical: [u8; SIGNATURE_LEN],
+    /// Dilithium3 signature
+    pub post_quantum: Vec<u8>,
+}
+
+/// The Ed25519 and Dilithium private keys of a sender
+#[derive(Debug, Clone)]
+pub struct HybridSigningKey {
+    classical: SigningKey,
+    post_quantum: DilithiumSigningKey,
+}
+
+impl HybridSigningKey {
+    /// Combines the private keys of a sender.
+    pub fn new(classical: SigningKey, post_quantum: DilithiumSigningKey) -> Self {
+        HybridSigningKey { classical, post_quantum }
+    }
+
+    /// Generates random keys.
+    pub fn generate() -> Self {
+        Self::new(SigningKey::generate(), DilithiumSigningKey::generate())
+    }
+
+    /// Returns the Ed25519 private key.
+    pub fn classical(&self) -> &SigningKey {
+        &self.classical
+    }
+
+    /// Returns the Dilithium private key.
+    pub fn post_quantum(&self) -> &DilithiumSigningKey {
+        &self.post_quantum
+    }
+
+    /// Returns both public keys matching these keys.
+    pub fn verifying_key(&self) -> HybridVerifyingKey {
+        HybridVerifyingKey::new(Some(self.classical.verifying_key()), Some(self.post_quantum.verifying_key()))
+    }
+
+    /// Signs a message with both keys.
+    pub fn sign(&self, message: &[u8]) -> HybridSignature {
+        HybridSignature {
+            classical: self.classical.sign(message),
+            post_quantum: self.post_quantum.sign(message),
+        }
+    }
+}
+
+/// The public keys a receiver knows for a sender: Ed25519, Dilithium or both
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HybridVerifyingKey {
+    classical: Option<VerifyingKey>,
+    post_quantum: Option<DilithiumVerifyingKey>,
+}
+
+impl HybridVerifyingKey {
+    /// Combines the public keys known for a sender.
+    pub fn new(classical: Option<VerifyingKey>, post_quantum: Option<DilithiumVerifyingKey>) -> Self {
+        HybridVerifyingKey { classical, post_quantum }
+    }
+
+    /// Returns the Ed25519 public key, if known.
+    pub fn classical(&self) -> Option<&VerifyingKey> {
+        self.classical.as_ref()
+    }
+
+    /// Returns the Dilithium public key, if known.
+    pub fn post_quantum(&self) -> Option<&DilithiumVerifyingKey> {
+        self.post_quantum.as_ref()
+    }
+
+    /// Verifies the signatures present on a message under the policy.
+    pub fn verify(
+        &self,
+        message: &[u8],
+        classical: Option<&[u8; SIGNATURE_LEN]>,
+        post_quantum: Option<&[u8]>,
+        policy: SignaturePolicy,
+    ) -> Result<()> {
+        if policy == SignaturePolicy::Both {
+            let missing = |what: &str| Error::EncryptionError(format!("Signature policy requires {}", what));
+            if classical.is_none() || post_quantum.is_none() {
+                return Err(missing("both an Ed25519 and a Dilithium signature"));
+            }
+            if self.classical.is_none() || self.post_quantum.is_none() {
+                return Err(missing("both an Ed25519 and a Dilithium key"));
+            }
+        }
+
+        let mut verified = 0;
+        if let (Some(key), Some(signature)) = (&self.classical, classical) {
+            key.verify(message, signature)?;
+            verified += 1;
+        }
+        if let (Some(key), Some(signature)) = (&self.post_quantum, post_quantum) {
+            key.verify(message, signature)?;
+            verified += 1;
+        }
+        if verified == 0 {
+            return Err(Error::EncryptionError("No signature matches a known key".to_string()));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies() {
+        let sender = HybridSigningKey::generate();
+        let signature = sender.sign(b"message");
+        let both = sender.verifying_key();
+        let classical_only = HybridVerifyingKey::new(Some(sender.classical().verifying_key()), None);
+        let pq = Some(signature.post_quantum.as_slice());
+
+        for policy in [SignaturePolicy::Either, SignaturePolicy::Both] {
+            assert!(both.verify(b"message", Some(&signature.classical), pq, policy).is_ok());
+        }
+        // Receivers without the Dilithium key accept under Either only
+        assert!(classical_only.verify(b"message", Some(&signature.classical), pq, SignaturePolicy::Either).is_ok());
+        let err = classical_only.verify(b"message", Some(&signature.classical), pq, SignaturePolicy::Both).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Signature policy requires both an Ed25519 and a Dilithium key");
+        assert!(both.verify(b"message", Some(&signature.classical), None, SignaturePolicy::Both).is_err());
+
+        // A bad signature fails even when the other one verifies
+        let forged = HybridSigningKey::generate().sign(b"message");
+        let forged_pq = Some(forged.post_quantum.as_slice());
+        assert!(both.verify(b"message", Some(&signature.classical), forged_pq, SignaturePolicy::Either).is_err());
+        let pq_only = HybridVerifyingKey::new(None, Some(sender.post_quantum().verifying_key()));
+        let err = pq_only.verify(b"message", Some(&signature.classical), None, SignaturePolicy::Either).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: No signature matches a known key");
+    }
+}
@@ -5,14 +5,26 @@
 // key management systems.
 
 use crate::internal::error::{Error, Result};
-use std::collections::HashMap;
+use crate::encrypt::aes_gcm::AesGcmEncryptor;
+use crate::encrypt::chacha20_poly1305::ChaCha20Poly1305Encryptor;
+use crate::encrypt::{EncryptionStrategy, Encryptor};
+use crate::internal::memory::{MemoryBudget, MemorySubsystem};
+use crate::codec::varint::{decode_varint, encode_varint};
+use crate::internal::cursor::WireCursor;
+use std::collections::{HashMap, HashSet};
 use std::sync::{Arc, Mutex, RwLock};
-use std::time::{Duration, SystemTime};
-use rand_core::{OsRng, RngCore};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use rand_core::RngCore;
+use crate::encrypt::rng::SecureRng;
 use aes_gcm::aead::KeyInit;
 use chacha20poly1305::ChaCha20Poly1305;
 use x25519_dalek::{StaticSecret, PublicKey};
 
+/// Magic number at the start of an exported key set (before sealing)
+const KEY_SET_MAGIC: &[u8; 4] = b"TNKS";
+/// Version of the exported key set format
+const KEY_SET_VERSION: u8 = 1;
+
 /// Key types supported by the key manager
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum KeyType {
@@ -44,7 +56,6 @@ pub struct KeyMetadata {
 }
 
 /// Key material (sensitive)
-#[derive(Debug)]
 enum KeyMaterial {
     /// AES-GCM key
     AesGcm([u8; 32]),
@@ -56,6 +67,83 @@ enum KeyMaterial {
     Kyber768([u8; 1184], [u8; 2400]),
 }
 
+impl std::fmt::Debug for KeyMaterial {
+    // Only the key type is printed, never the key bytes
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            KeyMaterial::AesGcm(_) => "AesGcm",
+            KeyMaterial::ChaCha20Poly1305(_) => "ChaCha20Poly1305",
+            KeyMaterial::X25519(_, _) => "X25519",
+            KeyMaterial::Kyber768(_, _) => "Kyber768",
+        };
+        write!(f, "KeyMaterial::{}(..)", name)
+    }
+}
+
+impl KeyType {
+    pub(crate) fn to_u8(self) -> u8 {
+        match self {
+            KeyType::AesGcm => 0,
+            KeyType::ChaCha20Poly1305 => 1,
+            KeyType::X25519 => 2,
+            KeyType::Kyber768 => 3,
+        }
+    }
+
+    pub(crate) fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(KeyType::AesGcm),
+            1 => Ok(KeyType::ChaCha20Poly1305),
+            2 => Ok(KeyType::X25519),
+            3 => Ok(KeyType::Kyber768),
+            _ => Err(Error::EncryptionError(format!("Unknown key type in key set: {}", value))),
+        }
+    }
+}
+
+impl KeyMaterial {
+    /// Returns the secret bytes of the key material, from which it can be rebuilt
+    fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            KeyMaterial::AesGcm(key) | KeyMaterial::ChaCha20Poly1305(key) => key.to_vec(),
+            KeyMaterial::X25519(private_key, _) => private_key.to_bytes().to_vec(),
+            KeyMaterial::Kyber768(public_key, secret_key) => [&public_key[..], &secret_key[..]].concat(),
+        }
+    }
+
+    /// Rebuilds key material from the bytes returned by `to_bytes`
+    fn from_bytes(key_type: KeyType, bytes: &[u8]) -> Result<Self> {
+        let invalid = || Error::EncryptionError(format!("Invalid {:?} key material in key set", key_type));
+        match key_type {
+            KeyType::AesGcm => Ok(KeyMaterial::AesGcm(bytes.try_into().map_err(|_| invalid())?)),
+            KeyType::ChaCha20Poly1305 => Ok(KeyMaterial::ChaCha20Poly1305(bytes.try_into().map_err(|_| invalid())?)),
+            KeyType::X25519 => {
+                let private_bytes: [u8; 32] = bytes.try_into().map_err(|_| invalid())?;
+                let private_key = StaticSecret::from(private_bytes);
+                let public_key = PublicKey::from(&private_key);
+                Ok(KeyMaterial::X25519(private_key, public_key))
+            }
+            KeyType::Kyber768 => {
+                if bytes.len() != 1184 + 2400 {
+                    return Err(invalid());
+                }
+                let (public_key, secret_key) = bytes.split_at(1184);
+                Ok(KeyMaterial::Kyber768(public_key.try_into().unwrap(), secret_key.try_into().unwrap()))
+            }
+        }
+    }
+
+    /// Returns the approximate size of the key material in bytes, used for memory accounting.
+    fn size(&self) -> usize {
+        match self {
+            KeyMaterial::AesGcm(key) => key.len(),
+            KeyMaterial::ChaCha20Poly1305(key) => key.len(),
+            KeyMaterial::X25519(_, _) => 64,
+            KeyMaterial::Kyber768(public_key, secret_key) => public_key.len() + secret_key.len(),
+        }
+    }
+}
+
 /// A key entry in the key manager
 #[derive(Debug)]
 struct KeyEntry {
@@ -92,9 +180,15 @@ pub struct KeyManager {
     rotation_policies: Arc<RwLock<HashMap<KeyType, KeyRotationPolicy>>>,
     /// External key provider (if any)
     external_provider: Option<Box<dyn ExternalKeyProvider>>,
+    /// Memory budget the cached key material is accounted against (if any)
+    memory_budget: Option<MemoryBudget>,
 }
 
 /// Trait for external key providers
+///
+/// `key_providers` implements it over environment variables and over a
+/// passphrase-protected keystore file, and `sealed_storage` over keys sealed
+/// by a TPM or the Secure Enclave.
 pub trait ExternalKeyProvider: Send + Sync + std::fmt::Debug {
     /// Gets a key from the external provider
     fn get_key(&self, key_id: &str, key_type: KeyType) -> Result<Vec<u8>>;
@@ -114,6 +208,7 @@ impl KeyManager {
             primary_keys: Arc::new(RwLock::new(HashMap::new())),
             rotation_policies: Arc::new(RwLock::new(HashMap::new())),
             external_provider: None,
+            memory_budget: None,
         }
     }
     
@@ -122,6 +217,20 @@ impl KeyManager {
         self.external_provider = Some(provider);
     }
     
+    /// Sets the memory budget that cached key material is accounted against.
+    ///
+    /// Should be set before any key is generated; keys generated earlier are not accounted.
+    pub fn set_memory_budget(&mut self, budget: MemoryBudget) {
+        self.memory_budget = Some(budget);
+    }
+
+    /// Releases the budget accounted for a removed key entry.
+    fn release_key_entry(&self, entry: &KeyEntry) {
+        if let Some(budget) = &self.memory_budget {
+            budget.release(MemorySubsystem::KeyCache, entry.material.size());
+        }
+    }
+
     /// Sets a key rotation policy
     pub fn set_rotation_policy(&self, policy: KeyRotationPolicy) -> Result<()> {
         let mut policies = self.rotation_policies.write().map_err(|_| {
@@ -141,20 +250,20 @@ impl KeyManager {
         // Generate key material based on type
         let material = match key_type {
             KeyType::AesGcm => {
-                let key = aes_gcm::Aes256Gcm::generate_key(&mut OsRng);
-                KeyMaterial::AesGcm(*key.as_slice().try_into().unwrap())
+                let key = aes_gcm::Aes256Gcm::generate_key(&mut SecureRng);
+                KeyMaterial::AesGcm(key.into())
             }
             KeyType::ChaCha20Poly1305 => {
-                let key = ChaCha20Poly1305::generate_key(&mut OsRng);
-                KeyMaterial::ChaCha20Poly1305(*key.as_slice().try_into().unwrap())
+                let key = ChaCha20Poly1305::generate_key(&mut SecureRng);
+                KeyMaterial::ChaCha20Poly1305(key.into())
             }
             KeyType::X25519 => {
-                let private_key = StaticSecret::new(OsRng);
+                let private_key = StaticSecret::random_from_rng(SecureRng);
                 let public_key = PublicKey::from(&private_key);
                 KeyMaterial::X25519(private_key, public_key)
             }
             KeyType::Kyber768 => {
-                let (public_key, secret_key) = kyber_rust::kyber768::keypair();
+                let (public_key, secret_key) = crate::encrypt::kyber::kyber_keypair()?;
                 KeyMaterial::Kyber768(public_key, secret_key)
             }
         };
@@ -169,18 +278,32 @@ impl KeyManager {
             metadata: HashMap::new(),
         };
         
-        // Create key entry
-        let entry = KeyEntry {
-            metadata: metadata.clone(),
-            material,
+        // Account for the key material before storing or caching it, and give
+        // it back if the key cannot be kept
+        let size = material.size();
+        if let Some(budget) = &self.memory_budget {
+            budget.reserve(MemorySubsystem::KeyCache, size)?;
+        }
+        let locked = self.store_external(&key_id, &material).and_then(|()| {
+            self.keys.write().map_err(|_| {
+                Error::EncryptionError("Failed to acquire write lock on keys".to_string())
+            })
+        });
+        let mut keys = match locked {
+            Ok(keys) => keys,
+            Err(e) => {
+                if let Some(budget) = &self.memory_budget {
+                    budget.release(MemorySubsystem::KeyCache, size);
+                }
+                return Err(e);
+            }
         };
-        
+
         // Store the key
-        let mut keys = self.keys.write().map_err(|_| {
-            Error::EncryptionError("Failed to acquire write lock on keys".to_string())
-        })?;
-        
-        keys.insert(key_id.clone(), entry);
+        keys.insert(key_id.clone(), KeyEntry {
+            metadata: metadata.clone(),
+            material,
+        });
         
         // Update primary key if needed
         if make_primary {
@@ -198,36 +321,26 @@ impl KeyManager {
             primary_keys.insert(key_type, key_id.clone());
         }
         
-        // Store in external provider if available
-        if let Some(provider) = &self.external_provider {
-            match key_type {
-                KeyType::AesGcm => {
-                    if let KeyMaterial::AesGcm(key_data) = &material {
-                        provider.store_key(&key_id, key_type, key_data)?;
-                    }
-                }
-                KeyType::ChaCha20Poly1305 => {
-                    if let KeyMaterial::ChaCha20Poly1305(key_data) = &material {
-                        provider.store_key(&key_id, key_type, key_data)?;
-                    }
-                }
-                KeyType::X25519 => {
-                    if let KeyMaterial::X25519(private_key, _) = &material {
-                        let private_bytes = private_key.to_bytes();
-                        provider.store_key(&key_id, key_type, &private_bytes)?;
-                    }
-                }
-                KeyType::Kyber768 => {
-                    if let KeyMaterial::Kyber768(_, secret_key) = &material {
-                        provider.store_key(&key_id, key_type, secret_key)?;
-                    }
-                }
-            }
-        }
-        
         Ok(key_id)
     }
     
+    /// Stores the key material in the external provider, if there is one
+    fn store_external(&self, key_id: &str, material: &KeyMaterial) -> Result<()> {
+        let Some(provider) = &self.external_provider else {
+            return Ok(());
+        };
+        match material {
+            KeyMaterial::AesGcm(key_data) => provider.store_key(key_id, KeyType::AesGcm, key_data),
+            KeyMaterial::ChaCha20Poly1305(key_data) => {
+                provider.store_key(key_id, KeyType::ChaCha20Poly1305, key_data)
+            }
+            KeyMaterial::X25519(private_key, _) => {
+                provider.store_key(key_id, KeyType::X25519, &private_key.to_bytes())
+            }
+            KeyMaterial::Kyber768(_, secret_key) => provider.store_key(key_id, KeyType::Kyber768, secret_key),
+        }
+    }
+
     /// Gets a key by ID
     pub fn get_key(&self, key_id: &str) -> Result<KeyMetadata> {
         // Try to get from local cache first
@@ -268,6 +381,121 @@ impl KeyManager {
         )))
     }
     
+    /// Encrypts a data key under a symmetric master key (envelope encryption)
+    ///
+    /// The master key must be an AES-GCM or ChaCha20-Poly1305 key held by
+    /// the manager. The wrapped key carries its own nonce.
+    pub fn wrap_key(&self, key_id: &str, data_key: &[u8]) -> Result<Vec<u8>> {
+        self.master_key_encryptor(key_id)?.encrypt(data_key, None)
+    }
+
+    /// Decrypts a data key wrapped by `wrap_key` with the same master key
+    pub fn unwrap_key(&self, key_id: &str, wrapped_key: &[u8]) -> Result<Vec<u8>> {
+        self.master_key_encryptor(key_id)?.decrypt(wrapped_key, None)
+    }
+
+    /// Returns an encryptor whose default key is the given symmetric key
+    fn master_key_encryptor(&self, key_id: &str) -> Result<Box<dyn Encryptor>> {
+        let keys = self.keys.read().map_err(|_| {
+            Error::EncryptionError("Failed to acquire read lock on keys".to_string())
+        })?;
+
+        match keys.get(key_id).map(|entry| &entry.material) {
+            Some(KeyMaterial::AesGcm(key)) => Ok(Box::new(AesGcmEncryptor::with_key(key)?)),
+            Some(KeyMaterial::ChaCha20Poly1305(key)) => Ok(Box::new(ChaCha20Poly1305Encryptor::with_key(key)?)),
+            Some(material) => Err(Error::EncryptionError(format!(
+                "Key ID '{}' is not a symmetric key: {:?}",
+                key_id, material
+            ))),
+            None => Err(Error::EncryptionError(format!("Key ID '{}' not found", key_id))),
+        }
+    }
+
+    /// Exports all cached keys with their metadata, sealed with AES-256-GCM
+    /// under `wrapping_key`, so that a restarted or migrated process can take
+    /// over live sessions with `import_keys`
+    ///
+    /// Rotation policies and the external provider are not exported.
+    pub fn export_keys(&self, wrapping_key: &[u8]) -> Result<Vec<u8>> {
+        let keys = self.keys.read().map_err(|_| {
+            Error::EncryptionError("Failed to acquire read lock on keys".to_string())
+        })?;
+
+        let mut plain = KEY_SET_MAGIC.to_vec();
+        plain.push(KEY_SET_VERSION);
+        plain.extend_from_slice(&encode_varint(keys.len() as u64));
+        let mut ids: Vec<&String> = keys.keys().collect();
+        ids.sort();
+        for id in ids {
+            let entry = &keys[id];
+            let metadata = &entry.metadata;
+            put_bytes(&mut plain, metadata.id.as_bytes());
+            plain.push(metadata.key_type.to_u8());
+            put_time(&mut plain, metadata.created_at);
+            match metadata.expires_at {
+                Some(expires_at) => {
+                    plain.push(1);
+                    put_time(&mut plain, expires_at);
+                }
+                None => plain.push(0),
+            }
+            plain.push(metadata.is_primary as u8);
+            let mut extra: Vec<(&String, &String)> = metadata.metadata.iter().collect();
+            extra.sort();
+            plain.extend_from_slice(&encode_varint(extra.len() as u64));
+            for (name, value) in extra {
+                put_bytes(&mut plain, name.as_bytes());
+                put_bytes(&mut plain, value.as_bytes());
+            }
+            put_bytes(&mut plain, &entry.material.to_bytes());
+        }
+
+        let sealed = AesGcmEncryptor::with_key(wrapping_key)?.encrypt(&plain, None);
+        // Best effort: do not leave the key material lying around in memory
+        plain.fill(0);
+        sealed
+    }
+
+    /// Imports the keys of a key set exported by `export_keys`; returns the
+    /// number of keys imported
+    ///
+    /// Keys the manager already holds are left as they are. Imported primary
+    /// keys become the primary keys of their type.
+    pub fn import_keys(&self, sealed: &[u8], wrapping_key: &[u8]) -> Result<usize> {
+        let mut plain = AesGcmEncryptor::with_key(wrapping_key)?.decrypt(sealed, None)
+            .map_err(|e| Error::EncryptionError(format!("Cannot open key set: {}", e)))?;
+        let entries = parse_key_set(&plain);
+        plain.fill(0);
+        let entries = entries?;
+
+        let mut keys = self.keys.write().map_err(|_| {
+            Error::EncryptionError("Failed to acquire write lock on keys".to_string())
+        })?;
+        let mut primary_keys = self.primary_keys.write().map_err(|_| {
+            Error::EncryptionError("Failed to acquire write lock on primary keys".to_string())
+        })?;
+
+        // Reserve the budget of every new key at once, so that a set that does
+        // not fit leaves the manager as it was
+        let entries: Vec<KeyEntry> = entries.into_iter().filter(|entry| !keys.contains_key(&entry.metadata.id)).collect();
+        if let Some(budget) = &self.memory_budget {
+            budget.reserve(MemorySubsystem::KeyCache, entries.iter().map(|entry| entry.material.size()).sum())?;
+        }
+
+        let imported = entries.len();
+        for entry in entries {
+            if entry.metadata.is_primary {
+                let key_type = entry.metadata.key_type;
+                if let Some(old_entry) = primary_keys.get(&key_type).and_then(|old_id| keys.get_mut(old_id)) {
+                    old_entry.metadata.is_primary = false;
+                }
+                primary_keys.insert(key_type, entry.metadata.id.clone());
+            }
+            keys.insert(entry.metadata.id.clone(), entry);
+        }
+        Ok(imported)
+    }
+
     /// Rotates keys according to the rotation policy
     pub fn rotate_keys(&self) -> Result<()> {
         let policies = self.rotation_policies.read().map_err(|_| {
@@ -281,6 +509,66 @@ impl KeyManager {
         Ok(())
     }
     
+    /// Rotates the key types whose primary key has expired, and those with a
+    /// rotation policy but no primary key yet
+    ///
+    /// Returns the number of key types rotated.
+    pub fn rotate_expired_keys(&self) -> Result<usize> {
+        let policies: Vec<KeyRotationPolicy> = self
+            .rotation_policies
+            .read()
+            .map_err(|_| Error::EncryptionError("Failed to acquire read lock on rotation policies".to_string()))?
+            .values()
+            .cloned()
+            .collect();
+
+        let now = SystemTime::now();
+        let mut rotated = 0;
+        for policy in &policies {
+            let expired = match self.get_primary_key(policy.key_type) {
+                Ok(primary) => primary.expires_at.is_some_and(|expires_at| expires_at <= now),
+                Err(_) => true,
+            };
+            if expired {
+                self.rotate_key_type(policy.key_type, policy)?;
+                rotated += 1;
+            }
+        }
+        Ok(rotated)
+    }
+
+    /// Spawns a task on the current tokio runtime that calls
+    /// `rotate_expired_keys` every `interval`
+    ///
+    /// The task only holds a weak reference to the manager and ends once the
+    /// manager is dropped; abort the returned handle to stop it earlier. A
+    /// failed rotation is retried at the next tick. Panics if called outside
+    /// of a tokio runtime.
+    #[cfg(feature = "async")]
+    pub fn start_rotation_task(self: &Arc<Self>, interval: Duration) -> tokio::task::JoinHandle<()> {
+        let manager = Arc::downgrade(self);
+        tokio::spawn(async move {
+            let mut ticks = tokio::time::interval(interval);
+            ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+            loop {
+                ticks.tick().await;
+                let Some(manager) = manager.upgrade() else {
+                    break;
+                };
+                let _ = manager.rotate_expired_keys();
+            }
+        })
+    }
+
+    /// Returns an encryptor sealing data under the primary key of a symmetric
+    /// key type, which records the key ID in the ciphertext
+    pub fn keyed_encryptor(self: &Arc<Self>, key_type: KeyType) -> Result<KeyedEncryptor> {
+        match key_type {
+            KeyType::AesGcm | KeyType::ChaCha20Poly1305 => Ok(KeyedEncryptor { keys: Arc::clone(self), key_type }),
+            other => Err(Error::EncryptionError(format!("{:?} is not a symmetric key type", other))),
+        }
+    }
+
     /// Rotates keys for a specific key type
     fn rotate_key_type(&self, key_type: KeyType, policy: &KeyRotationPolicy) -> Result<()> {
         // Generate a new primary key
@@ -303,7 +591,9 @@ impl KeyManager {
             
             // Remove keys
             for id in keys_to_remove {
-                keys.remove(&id);
+                if let Some(entry) = keys.remove(&id) {
+                    self.release_key_entry(&entry);
+                }
                 
                 // Remove from external provider if available
                 if let Some(provider) = &self.external_provider {
@@ -331,7 +621,9 @@ impl KeyManager {
             // Remove excess keys
             if type_keys.len() > policy.old_keys_to_keep {
                 for (id, _) in type_keys.iter().skip(policy.old_keys_to_keep) {
-                    keys.remove(id);
+                    if let Some(entry) = keys.remove(id) {
+                        self.release_key_entry(&entry);
+                    }
                     
                     // Remove from external provider if available
                     if let Some(provider) = &self.external_provider {
@@ -347,7 +639,7 @@ impl KeyManager {
     /// Generates a random key ID
     fn generate_key_id(&self) -> String {
         let mut bytes = [0u8; 16];
-        OsRng.fill_bytes(&mut bytes);
+        SecureRng.fill_bytes(&mut bytes);
         
         hex::encode(bytes)
     }
@@ -366,3 +658,210 @@ impl KeyManager {
         None
     }
 }
+
+/// An encryptor sealing data under the primary key of a symmetric key type
+///
+/// The ciphertext starts with the ID of the key it was sealed with, prefixed
+/// with its varint length, followed by the output of the AES-GCM or
+/// ChaCha20-Poly1305 encryptor of that key. Decryption looks the key up by the
+/// recorded ID, so data sealed before a rotation stays readable for as long as
+/// the rotation policy retains the old key.
+#[derive(Debug, Clone)]
+pub struct KeyedEncryptor {
+    keys: Arc<KeyManager>,
+    key_type: KeyType,
+}
+
+impl Encryptor for KeyedEncryptor {
+    /// Seals the data under the key `key_id`, or under the current primary key
+    /// of the encryptor's key type if it is `None`
+    fn encrypt(&self, data: &[u8], key_id: Option<&str>) -> Result<Vec<u8>> {
+        let key_id = match key_id {
+            Some(key_id) => key_id.to_string(),
+            None => self.keys.get_primary_key(self.key_type)?.id,
+        };
+        let sealed = self.keys.master_key_encryptor(&key_id)?.encrypt(data, None)?;
+        let mut out = Vec::with_capacity(key_id.len() + sealed.len() + 1);
+        put_bytes(&mut out, key_id.as_bytes());
+        out.extend_from_slice(&sealed);
+        Ok(out)
+    }
+
+    /// Opens data sealed by `encrypt`; the key ID recorded in the ciphertext
+    /// is used and `key_id` is ignored
+    fn decrypt(&self, data: &[u8], _key_id: Option<&str>) -> Result<Vec<u8>> {
+        let (length, offset) = decode_varint(data)?;
+        let end = usize::try_from(length).ok().and_then(|length| offset.checked_add(length)).filter(|end| *end <= data.len())
+            .ok_or_else(|| Error::EncryptionError("Ciphertext is too short for its key ID".to_string()))?;
+        let key_id = std::str::from_utf8(&data[offset..end])
+            .map_err(|_| Error::EncryptionError("Invalid key ID in ciphertext".to_string()))?;
+        self.keys.master_key_encryptor(key_id)?.decrypt(&data[end..], None)
+    }
+
+    fn strategy(&self) -> EncryptionStrategy {
+        match self.key_type {
+            KeyType::ChaCha20Poly1305 => EncryptionStrategy::ChaCha20Poly1305,
+            _ => EncryptionStrategy::AesGcm,
+        }
+    }
+}
+
+/// Appends a varint length followed by the bytes
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    out.extend_from_slice(&encode_varint(bytes.len() as u64));
+    out.extend_from_slice(bytes);
+}
+
+/// Appends a time as seconds and nanoseconds since the Unix epoch
+fn put_time(out: &mut Vec<u8>, time: SystemTime) {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    out.extend_from_slice(&encode_varint(since_epoch.as_secs()));
+    out.extend_from_slice(&encode_varint(since_epoch.subsec_nanos() as u64));
+}
+
+/// Parses the plaintext of an exported key set
+fn parse_key_set(data: &[u8]) -> Result<Vec<KeyEntry>> {
+    let mut cursor = WireCursor::new(data).with_truncation_error(Error::EncryptionError);
+    if cursor.read_array::<4>("key set magic").ok().as_ref() != Some(KEY_SET_MAGIC) {
+        return Err(Error::EncryptionError("Missing key set magic number".to_string()));
+    }
+    let version = cursor.read_u8("key set version")?;
+    if version != KEY_SET_VERSION {
+        return Err(Error::EncryptionError(format!("Unsupported key set version {}", version)));
+    }
+
+    let count = cursor.read_varint("key count")?;
+    let mut entries = Vec::new();
+    let mut ids = HashSet::new();
+    for _ in 0..count {
+        let id = read_string(&mut cursor, "key ID")?;
+        if !ids.insert(id.clone()) {
+            return Err(Error::EncryptionError(format!("Duplicate key '{}' in key set", id)));
+        }
+        let key_type = KeyType::from_u8(cursor.read_u8("key type")?)?;
+        let created_at = read_time(&mut cursor)?;
+        let expires_at = match cursor.read_u8("key expiration flag")? {
+            0 => None,
+            _ => Some(read_time(&mut cursor)?),
+        };
+        let is_primary = cursor.read_u8("key primary flag")? != 0;
+        let mut metadata = HashMap::new();
+        for _ in 0..cursor.read_varint("key metadata count")? {
+            let name = read_string(&mut cursor, "key metadata name")?;
+            metadata.insert(name, read_string(&mut cursor, "key metadata value")?);
+        }
+        let material = KeyMaterial::from_bytes(key_type, cursor.read_length_prefixed("key material")?)?;
+        entries.push(KeyEntry {
+            metadata: KeyMetadata { id, key_type, created_at, expires_at, is_primary, metadata },
+            material,
+        });
+    }
+    Ok(entries)
+}
+
+/// Reads a length-prefixed UTF-8 string of a key set
+fn read_string(cursor: &mut WireCursor, what: &str) -> Result<String> {
+    String::from_utf8(cursor.read_length_prefixed(what)?.to_vec())
+        .map_err(|_| Error::EncryptionError(format!("Invalid UTF-8 in {}", what)))
+}
+
+/// Reads a time written by `put_time`
+fn read_time(cursor: &mut WireCursor) -> Result<SystemTime> {
+    let secs = cursor.read_varint("key time")?;
+    let nanos = cursor.read_varint("key time")?;
+    let nanos = u32::try_from(nanos).ok().filter(|nanos| *nanos < 1_000_000_000)
+        .ok_or_else(|| Error::EncryptionError("Invalid time in key set".to_string()))?;
+    Ok(UNIX_EPOCH + Duration::new(secs, nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_export_and_import_keys() {
+        let manager = KeyManager::new();
+        let master = manager.generate_key(KeyType::AesGcm, true).unwrap();
+        let session = manager.generate_key(KeyType::ChaCha20Poly1305, true).unwrap();
+        let exchange = manager.generate_key(KeyType::X25519, false).unwrap();
+        let wrapped = manager.wrap_key(&master, b"data key").unwrap();
+
+        let wrapping_key = [7u8; 32];
+        let sealed = manager.export_keys(&wrapping_key).unwrap();
+        assert!(!sealed.windows(master.len()).any(|window| window == master.as_bytes()));
+
+        // A set that does not fit the budget is not imported at all
+        let mut bounded = KeyManager::new();
+        let budget = MemoryBudget::with_limit(100);
+        bounded.set_memory_budget(budget.clone());
+        assert!(bounded.import_keys(&sealed, &wrapping_key).is_err());
+        assert!(bounded.get_key(&master).is_err());
+        assert_eq!(budget.used(), 0);
+
+        let restored = KeyManager::new();
+        assert!(restored.import_keys(&sealed, &[8u8; 32]).is_err());
+        assert_eq!(restored.import_keys(&sealed, &wrapping_key).unwrap(), 3);
+        assert_eq!(restored.import_keys(&sealed, &wrapping_key).unwrap(), 0);
+
+        // The restored manager can use the keys of the original one
+        assert_eq!(restored.unwrap_key(&master, &wrapped).unwrap(), b"data key");
+        assert_eq!(restored.get_primary_key(KeyType::ChaCha20Poly1305).unwrap().id, session);
+        let metadata = restored.get_key(&exchange).unwrap();
+        assert_eq!(metadata.created_at, manager.get_key(&exchange).unwrap().created_at);
+        assert!(!metadata.is_primary);
+    }
+
+    #[test]
+    fn test_keyed_encryptor_decrypts_after_rotation() {
+        let manager = Arc::new(KeyManager::new());
+        manager.set_rotation_policy(KeyRotationPolicy {
+            key_type: KeyType::ChaCha20Poly1305,
+            lifetime: Duration::from_secs(3600),
+            keep_old_keys: true,
+            old_keys_to_keep: 1,
+        }).unwrap();
+        assert!(manager.keyed_encryptor(KeyType::X25519).is_err());
+        let encryptor = manager.keyed_encryptor(KeyType::ChaCha20Poly1305).unwrap();
+        assert!(encryptor.encrypt(b"no key yet", None).is_err());
+
+        // Without a primary key the type counts as expired
+        assert_eq!(manager.rotate_expired_keys().unwrap(), 1);
+        assert_eq!(manager.rotate_expired_keys().unwrap(), 0);
+        let first = manager.get_primary_key(KeyType::ChaCha20Poly1305).unwrap().id;
+        let sealed = encryptor.encrypt(b"before rotation", None).unwrap();
+        assert_eq!(&sealed[1..1 + first.len()], first.as_bytes());
+
+        manager.rotate_keys().unwrap();
+        assert_ne!(manager.get_primary_key(KeyType::ChaCha20Poly1305).unwrap().id, first);
+        assert_eq!(encryptor.decrypt(&sealed, None).unwrap(), b"before rotation");
+        let sealed_after = encryptor.encrypt(b"after rotation", None).unwrap();
+        assert_eq!(encryptor.decrypt(&sealed_after, None).unwrap(), b"after rotation");
+
+        // The second rotation drops the first key, beyond the one retained
+        manager.rotate_keys().unwrap();
+        assert_eq!(encryptor.decrypt(&sealed_after, None).unwrap(), b"after rotation");
+        assert!(encryptor.decrypt(&sealed, None).is_err());
+        assert!(encryptor.decrypt(&sealed[..10], None).is_err());
+    }
+
+    #[cfg(feature = "async")]
+    #[tokio::test]
+    async fn test_rotation_task() {
+        let manager = Arc::new(KeyManager::new());
+        manager.set_rotation_policy(KeyRotationPolicy {
+            key_type: KeyType::AesGcm,
+            lifetime: Duration::ZERO,
+            keep_old_keys: false,
+            old_keys_to_keep: 0,
+        }).unwrap();
+        let task = manager.start_rotation_task(Duration::from_millis(5));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let first = manager.get_primary_key(KeyType::AesGcm).unwrap().id;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_ne!(manager.get_primary_key(KeyType::AesGcm).unwrap().id, first);
+
+        // The task ends once the manager is dropped
+        drop(manager);
+        task.await.unwrap();
+    }
+}
@@ -0,0 +1,454 @@
+// Ready-made `ExternalKeyProvider` implementations
+//
+// `EnvKeyProvider` reads keys provisioned by the deployment environment: an
+// environment variable `<prefix><key id>`, or else a file named after the key
+// ID in a secrets directory such as `/run/secrets`. Both hold the key type and
+// the hex key bytes, e.g. `aes-gcm:00112233...`. The environment is read-only,
+// so storing or deleting keys fails.
+//
+// `FileKeyProvider` keeps keys in a keystore file sealed with AES-256-GCM under
+// a key derived from a passphrase with Argon2id. Layout:
+//
+//   header   magic "TNKF", the format version, the 16-byte salt and the
+//            Argon2 memory cost (KiB), iterations and parallelism as varints
+//   sealed   nonce and AES-GCM ciphertext of the key count, then the ID, type
+//            and bytes of each key
+//
+// The file is rewritten whole, through a temporary file, whenever a key is
+// stored or deleted.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use argon2::{Algorithm, Argon2, Params, Version};
+use rand_core::RngCore;
+use crate::encrypt::rng::SecureRng;
+
+use crate::codec::varint::encode_varint_into;
+use crate::encrypt::aes_gcm::AesGcmEncryptor;
+use crate::encrypt::key_management::{ExternalKeyProvider, KeyType};
+use crate::encrypt::Encryptor;
+use crate::internal::cursor::WireCursor;
+use crate::internal::error::{Error, Result};
+
+/// Default prefix of the environment variables read by `EnvKeyProvider`
+pub const DEFAULT_ENV_PREFIX: &str = "TONITRU_KEY_";
+
+/// Magic number at the start of a keystore file
+const KEYSTORE_MAGIC: &[u8; 4] = b"TNKF";
+/// Version of the keystore file format
+const KEYSTORE_VERSION: u8 = 1;
+/// Length of the Argon2 salt of a keystore
+const SALT_LEN: usize = 16;
+
+/// Returns true if a key ID can name a file of a key directory: key IDs naming
+/// other files than direct children are never read or written
+pub(crate) fn is_file_key_id(key_id: &str) -> bool {
+    !(key_id.is_empty() || key_id.contains(['/', '\\']) || key_id.starts_with('.'))
+}
+
+/// Replaces the file at the path with the data, through a temporary file that
+/// is synced before it is renamed over the original
+pub(crate) fn write_atomically(path: &Path, data: &[u8]) -> Result<()> {
+    let temporary = path.with_extension("tmp");
+    let mut file = File::create(&temporary)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Returns the name of a key type in environment key values
+fn type_name(key_type: KeyType) -> &'static str {
+    match key_type {
+        KeyType::AesGcm => "aes-gcm",
+        KeyType::ChaCha20Poly1305 => "chacha20-poly1305",
+        KeyType::X25519 => "x25519",
+        KeyType::Kyber768 => "kyber768",
+    }
+}
+
+/// Parses a `<type>:<hex>` key value
+fn parse_key_value(key_id: &str, value: &str) -> Result<(KeyType, Vec<u8>)> {
+    let invalid = |reason: &str| Error::EncryptionError(format!("Invalid value for key '{}': {}", key_id, reason));
+    let (name, hex_key) = value.trim().split_once(':').ok_or_else(|| invalid("expected <type>:<hex>"))?;
+    let key_type = [KeyType::AesGcm, KeyType::ChaCha20Poly1305, KeyType::X25519, KeyType::Kyber768]
+        .into_iter()
+        .find(|key_type| type_name(*key_type) == name)
+        .ok_or_else(|| invalid(&format!("unknown key type '{}'", name)))?;
+    let key = hex::decode(hex_key).map_err(|e| invalid(&e.to_string()))?;
+    Ok((key_type, key))
+}
+
+/// Returns the key if it has the requested type
+fn check_type(key_id: &str, requested: KeyType, (key_type, key): (KeyType, Vec<u8>)) -> Result<Vec<u8>> {
+    if key_type != requested {
+        return Err(Error::EncryptionError(format!(
+            "Key '{}' is a {:?} key, not a {:?} key",
+            key_id, key_type, requested
+        )));
+    }
+    Ok(key)
+}
+
+/// Reads keys from environment variables and a secrets directory
+#[derive(Debug, Clone)]
+pub struct EnvKeyProvider {
+    prefix: String,
+    secrets_dir: Option<PathBuf>,
+}
+
+impl Default for EnvKeyProvider {
+    fn default() -> Self {
+        Self { prefix: DEFAULT_ENV_PREFIX.to_string(), secrets_dir: None }
+    }
+}
+
+impl EnvKeyProvider {
+    /// Creates a provider reading variables with the default prefix
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Reads variables named `<prefix><key id>` instead
+    pub fn with_prefix(mut self, prefix: &str) -> Self {
+        self.prefix = prefix.to_string();
+        self
+    }
+
+    /// Falls back to files named after the key ID in the directory
+    pub fn with_secrets_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.secrets_dir = Some(dir.into());
+        self
+    }
+
+    /// Returns the type and bytes of a key, if it is provisioned
+    fn lookup(&self, key_id: &str) -> Result<Option<(KeyType, Vec<u8>)>> {
+        if let Ok(value) = std::env::var(format!("{}{}", self.prefix, key_id)) {
+            return parse_key_value(key_id, &value).map(Some);
+        }
+        let Some(dir) = &self.secrets_dir else { return Ok(None) };
+        if !is_file_key_id(key_id) {
+            return Ok(None);
+        }
+        match fs::read_to_string(dir.join(key_id)) {
+            Ok(value) => parse_key_value(key_id, &value).map(Some),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+impl ExternalKeyProvider for EnvKeyProvider {
+    fn get_key(&self, key_id: &str, key_type: KeyType) -> Result<Vec<u8>> {
+        let key = self
+            .lookup(key_id)?
+            .ok_or_else(|| Error::EncryptionError(format!("Key ID '{}' not found in the environment", key_id)))?;
+        check_type(key_id, key_type, key)
+    }
+
+    fn store_key(&self, key_id: &str, _key_type: KeyType, _key_data: &[u8]) -> Result<()> {
+        Err(Error::EncryptionError(format!("Cannot store key '{}': environment keys are read-only", key_id)))
+    }
+
+    fn list_keys(&self, key_type: Option<KeyType>) -> Result<Vec<String>> {
+        let mut ids: Vec<String> = std::env::vars()
+            .filter_map(|(name, _)| name.strip_prefix(&self.prefix).map(str::to_string))
+            .collect();
+        if let Some(dir) = &self.secrets_dir {
+            for entry in fs::read_dir(dir)? {
+                let entry = entry?;
+                if entry.file_type()?.is_file() {
+                    ids.extend(entry.file_name().to_str().map(str::to_string));
+                }
+            }
+        }
+        ids.sort();
+        ids.dedup();
+
+        let mut listed = Vec::new();
+        for id in ids {
+            if let Some((found, _)) = self.lookup(&id)? {
+                if key_type.is_none_or(|key_type| key_type == found) {
+                    listed.push(id);
+                }
+            }
+        }
+        Ok(listed)
+    }
+
+    fn delete_key(&self, key_id: &str) -> Result<()> {
+        Err(Error::EncryptionError(format!("Cannot delete key '{}': environment keys are read-only", key_id)))
+    }
+}
+
+/// Argon2id cost parameters for deriving the keystore key from a passphrase
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KdfParams {
+    /// Memory cost in KiB
+    pub memory_kib: u32,
+    /// Number of iterations
+    pub iterations: u32,
+    /// Degree of parallelism
+    pub parallelism: u32,
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self {
+            memory_kib: Params::DEFAULT_M_COST,
+            iterations: Params::DEFAULT_T_COST,
+            parallelism: Params::DEFAULT_P_COST,
+        }
+    }
+}
+
+impl KdfParams {
+    /// Derives a 256-bit key from the passphrase and salt
+    fn derive(&self, passphrase: &[u8], salt: &[u8]) -> Result<[u8; 32]> {
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| Error::ConfigError(format!("Invalid keystore KDF parameters: {}", e)))?;
+        let mut key = [0u8; 32];
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase, salt, &mut key)
+            .map_err(|e| Error::EncryptionError(format!("Keystore key derivation failed: {}", e)))?;
+        Ok(key)
+    }
+}
+
+/// Keeps keys in a passphrase-protected keystore file
+pub struct FileKeyProvider {
+    path: PathBuf,
+    salt: [u8; SALT_LEN],
+    params: KdfParams,
+    /// Sealing encryptor holding the derived key
+    encryptor: AesGcmEncryptor,
+    keys: RwLock<HashMap<String, (KeyType, Vec<u8>)>>,
+}
+
+impl fmt::Debug for FileKeyProvider {
+    // Neither the keys nor the derived key are printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let keys = self.keys.read().map(|keys| keys.len()).unwrap_or(0);
+        f.debug_struct("FileKeyProvider")
+            .field("path", &self.path)
+            .field("params", &self.params)
+            .field("keys", &keys)
+            .finish()
+    }
+}
+
+impl FileKeyProvider {
+    /// Opens the keystore at `path`, or creates an empty one with the default
+    /// KDF parameters if there is no file yet
+    pub fn open(path: impl AsRef<Path>, passphrase: &str) -> Result<Self> {
+        Self::open_with_params(path, passphrase, KdfParams::default())
+    }
+
+    /// Opens the keystore at `path`, or creates an empty one with the given KDF
+    /// parameters; an existing keystore keeps the parameters it was created with
+    ///
+    /// Fails if the passphrase does not open an existing keystore.
+    pub fn open_with_params(path: impl AsRef<Path>, passphrase: &str, params: KdfParams) -> Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let data = match fs::read(&path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                let mut salt = [0u8; SALT_LEN];
+                SecureRng.fill_bytes(&mut salt);
+                let encryptor = AesGcmEncryptor::with_key(&params.derive(passphrase.as_bytes(), &salt)?)?;
+                let provider = Self { path, salt, params, encryptor, keys: RwLock::new(HashMap::new()) };
+                provider.save(&HashMap::new())?;
+                return Ok(provider);
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut cursor = WireCursor::new(&data).with_truncation_error(Error::EncryptionError);
+        if cursor.read_array::<4>("keystore magic")? != *KEYSTORE_MAGIC {
+            return Err(Error::EncryptionError("Missing keystore magic number".to_string()));
+        }
+        let version = cursor.read_u8("keystore version")?;
+        if version != KEYSTORE_VERSION {
+            return Err(Error::EncryptionError(format!("Unsupported keystore version {}", version)));
+        }
+        let salt = cursor.read_array::<SALT_LEN>("keystore salt")?;
+        let mut read_cost = |what| {
+            let cost = cursor.read_varint(what)?;
+            u32::try_from(cost).map_err(|_| Error::EncryptionError(format!("Invalid keystore {}: {}", what, cost)))
+        };
+        let params = KdfParams {
+            memory_kib: read_cost("memory cost")?,
+            iterations: read_cost("iterations")?,
+            parallelism: read_cost("parallelism")?,
+        };
+
+        let encryptor = AesGcmEncryptor::with_key(&params.derive(passphrase.as_bytes(), &salt)?)?;
+        let mut plain = encryptor
+            .decrypt(cursor.rest(), None)
+            .map_err(|_| Error::EncryptionError("Cannot open keystore: wrong passphrase or corrupted file".to_string()))?;
+        let keys = parse_keys(&plain);
+        plain.fill(0);
+        Ok(Self { path, salt, params, encryptor, keys: RwLock::new(keys?) })
+    }
+
+    /// Returns the path of the keystore file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Seals the keys and replaces the keystore file with them
+    fn save(&self, keys: &HashMap<String, (KeyType, Vec<u8>)>) -> Result<()> {
+        let mut ids: Vec<_> = keys.keys().collect();
+        ids.sort();
+        let mut plain = Vec::new();
+        encode_varint_into(ids.len() as u64, &mut plain);
+        for id in ids {
+            let (key_type, key) = &keys[id];
+            encode_varint_into(id.len() as u64, &mut plain);
+            plain.extend_from_slice(id.as_bytes());
+            plain.push(key_type.to_u8());
+            encode_varint_into(key.len() as u64, &mut plain);
+            plain.extend_from_slice(key);
+        }
+        let sealed = self.encryptor.encrypt(&plain, None);
+        plain.fill(0);
+
+        let mut data = KEYSTORE_MAGIC.to_vec();
+        data.push(KEYSTORE_VERSION);
+        data.extend_from_slice(&self.salt);
+        encode_varint_into(self.params.memory_kib as u64, &mut data);
+        encode_varint_into(self.params.iterations as u64, &mut data);
+        encode_varint_into(self.params.parallelism as u64, &mut data);
+        data.extend_from_slice(&sealed?);
+        write_atomically(&self.path, &data)
+    }
+}
+
+/// Parses the plaintext of a keystore
+fn parse_keys(data: &[u8]) -> Result<HashMap<String, (KeyType, Vec<u8>)>> {
+    let mut cursor = WireCursor::new(data).with_truncation_error(Error::EncryptionError);
+    let count = cursor.read_varint("keystore key count")?;
+    let mut keys = HashMap::new();
+    for _ in 0..count {
+        let id = std::str::from_utf8(cursor.read_length_prefixed("keystore key ID")?)
+            .map_err(|e| Error::EncryptionError(format!("Invalid key ID in keystore: {}", e)))?;
+        let key_type = KeyType::from_u8(cursor.read_u8("keystore key type")?)?;
+        let key = cursor.read_length_prefixed("keystore key")?.to_vec();
+        keys.insert(id.to_string(), (key_type, key));
+    }
+    Ok(keys)
+}
+
+impl ExternalKeyProvider for FileKeyProvider {
+    fn get_key(&self, key_id: &str, key_type: KeyType) -> Result<Vec<u8>> {
+        let keys = self.keys.read().map_err(|_| {
+            Error::EncryptionError("Failed to acquire read lock on keystore".to_string())
+        })?;
+        let key = keys
+            .get(key_id)
+            .cloned()
+            .ok_or_else(|| Error::EncryptionError(format!("Key ID '{}' not found in keystore", key_id)))?;
+        check_type(key_id, key_type, key)
+    }
+
+    fn store_key(&self, key_id: &str, key_type: KeyType, key_data: &[u8]) -> Result<()> {
+        let mut keys = self.keys.write().map_err(|_| {
+            Error::EncryptionError("Failed to acquire write lock on keystore".to_string())
+        })?;
+        let previous = keys.insert(key_id.to_string(), (key_type, key_data.to_vec()));
+        if let Err(e) = self.save(&keys) {
+            // Keep the cache in line with the file
+            match previous {
+                Some(previous) => keys.insert(key_id.to_string(), previous),
+                None => keys.remove(key_id),
+            };
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn list_keys(&self, key_type: Option<KeyType>) -> Result<Vec<String>> {
+        let keys = self.keys.read().map_err(|_| {
+            Error::EncryptionError("Failed to acquire read lock on keystore".to_string())
+        })?;
+        let mut ids: Vec<String> = keys
+            .iter()
+            .filter(|(_, (found, _))| key_type.is_none_or(|key_type| key_type == *found))
+            .map(|(id, _)| id.clone())
+            .collect();
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn delete_key(&self, key_id: &str) -> Result<()> {
+        let mut keys = self.keys.write().map_err(|_| {
+            Error::EncryptionError("Failed to acquire write lock on keystore".to_string())
+        })?;
+        let Some(previous) = keys.remove(key_id) else {
+            return Ok(());
+        };
+        if let Err(e) = self.save(&keys) {
+            keys.insert(key_id.to_string(), previous);
+            return Err(e);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fast() -> KdfParams {
+        KdfParams { memory_kib: 64, iterations: 1, parallelism: 1 }
+    }
+
+    #[test]
+    fn test_env_provider_reads_variables_and_secrets() {
+        let dir = std::env::temp_dir().join(format!("tonitru-env-keys-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("db"), format!("chacha20-poly1305:{}\n", hex::encode([4u8; 32]))).unwrap();
+        fs::write(dir.join("broken"), "aes-gcm:zz").unwrap();
+        std::env::set_var("TONITRU_TEST_KEY_api", format!("aes-gcm:{}", hex::encode([3u8; 32])));
+
+        let provider = EnvKeyProvider::new().with_prefix("TONITRU_TEST_KEY_").with_secrets_dir(&dir);
+        assert_eq!(provider.get_key("api", KeyType::AesGcm).unwrap(), [3u8; 32]);
+        assert_eq!(provider.get_key("db", KeyType::ChaCha20Poly1305).unwrap(), [4u8; 32]);
+        let err = provider.get_key("db", KeyType::AesGcm).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Key 'db' is a ChaCha20Poly1305 key, not a AesGcm key");
+        assert!(provider.get_key("missing", KeyType::AesGcm).is_err());
+        assert!(provider.get_key("../db", KeyType::ChaCha20Poly1305).is_err());
+        assert!(provider.list_keys(None).is_err());
+        assert!(provider.store_key("new", KeyType::AesGcm, &[0u8; 32]).is_err());
+
+        fs::remove_file(dir.join("broken")).unwrap();
+        assert_eq!(provider.list_keys(None).unwrap(), vec!["api", "db"]);
+        assert_eq!(provider.list_keys(Some(KeyType::AesGcm)).unwrap(), vec!["api"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_file_provider_persists_sealed_keys() {
+        let path = std::env::temp_dir().join(format!("tonitru-keystore-{}.tnkf", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let provider = FileKeyProvider::open_with_params(&path, "correct horse", fast()).unwrap();
+        provider.store_key("master", KeyType::AesGcm, &[1u8; 32]).unwrap();
+        provider.store_key("exchange", KeyType::X25519, &[2u8; 32]).unwrap();
+        provider.delete_key("exchange").unwrap();
+        provider.store_key("session", KeyType::ChaCha20Poly1305, &[3u8; 32]).unwrap();
+        assert!(!fs::read(&path).unwrap().windows(32).any(|window| window == [1u8; 32]));
+
+        assert!(FileKeyProvider::open(&path, "wrong").is_err());
+        let reopened = FileKeyProvider::open(&path, "correct horse").unwrap();
+        assert_eq!(reopened.get_key("master", KeyType::AesGcm).unwrap(), [1u8; 32]);
+        assert_eq!(reopened.list_keys(None).unwrap(), vec!["master", "session"]);
+        assert_eq!(reopened.list_keys(Some(KeyType::X25519)).unwrap(), Vec::<String>::new());
+        assert!(reopened.get_key("master", KeyType::ChaCha20Poly1305).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,22 +1,74 @@
 // Kyber post-quantum encryption implementation for Tonitru
 //
 // This module provides Kyber768 post-quantum encryption and decryption functionality.
+//
+// `KyberEncryptor` encapsulates a new shared secret for every message, which
+// costs a Kyber ciphertext of 1088 bytes per message. Streams should instead
+// run the key encapsulation once with `KyberKem` and encrypt their packets with
+// a `SessionEncryptor` keyed by the shared secret: the sender encapsulates to
+// the receiver's public key and sends the ciphertext once, the receiver
+// decapsulates it, and both derive the same AES-256-GCM session key.
+//
+// The Kyber768 primitives come from pqcrypto-kyber; keys, ciphertexts and
+// secrets are handled as byte arrays.
 
+use crate::encrypt::aes_gcm::AesGcmEncryptor;
 use crate::internal::error::{Error, Result};
-use kyber_rust::{
-    kyber768::{
-        decapsulate as kyber_decapsulate, encapsulate as kyber_encapsulate,
-        keypair as kyber_keypair,
-    },
-    KYBER_CIPHERTEXTBYTES, KYBER_PUBLICKEYBYTES, KYBER_SECRETKEYBYTES, KYBER_SYMBYTES,
-};
 use aes_gcm::{
     aead::{Aead, KeyInit},
     Aes256Gcm, Key, Nonce,
 };
 use std::collections::HashMap;
+use std::fmt;
 use std::sync::{Arc, Mutex};
 
+use pqcrypto_kyber::kyber768;
+use pqcrypto_traits::kem::{Ciphertext as _, PublicKey as _, SecretKey as _, SharedSecret as _};
+
+/// Length of a Kyber768 public key in bytes
+pub const KYBER_PUBLICKEYBYTES: usize = kyber768::public_key_bytes();
+/// Length of a Kyber768 private key in bytes
+pub const KYBER_SECRETKEYBYTES: usize = kyber768::secret_key_bytes();
+/// Length of a Kyber768 ciphertext in bytes
+pub const KYBER_CIPHERTEXTBYTES: usize = kyber768::ciphertext_bytes();
+/// Length of a Kyber768 shared secret in bytes
+pub const KYBER_SYMBYTES: usize = kyber768::shared_secret_bytes();
+
+/// Context of the BLAKE3 key derivation turning a shared secret into a session key
+const SESSION_KEY_CONTEXT: &str = "Tonitru Kyber768 session key v1";
+
+/// Generates a Kyber768 keypair.
+pub(crate) fn kyber_keypair() -> Result<([u8; KYBER_PUBLICKEYBYTES], [u8; KYBER_SECRETKEYBYTES])> {
+    let (public_key, secret_key) = kyber768::keypair();
+    Ok((to_array(public_key.as_bytes(), "public key")?, to_array(secret_key.as_bytes(), "private key")?))
+}
+
+/// Encapsulates a new shared secret to a public key.
+fn kyber_encapsulate(public_key: &[u8]) -> Result<([u8; KYBER_CIPHERTEXTBYTES], [u8; KYBER_SYMBYTES])> {
+    let public_key = kyber768::PublicKey::from_bytes(public_key).map_err(|e| invalid("public key", e))?;
+    let (secret, ciphertext) = kyber768::encapsulate(&public_key);
+    Ok((to_array(ciphertext.as_bytes(), "ciphertext")?, to_array(secret.as_bytes(), "shared secret")?))
+}
+
+/// Recovers the shared secret carried by a ciphertext.
+fn kyber_decapsulate(ciphertext: &[u8], secret_key: &[u8]) -> Result<[u8; KYBER_SYMBYTES]> {
+    let ciphertext = kyber768::Ciphertext::from_bytes(ciphertext).map_err(|e| invalid("ciphertext", e))?;
+    let secret_key = kyber768::SecretKey::from_bytes(secret_key).map_err(|e| invalid("private key", e))?;
+    to_array(kyber768::decapsulate(&ciphertext, &secret_key).as_bytes(), "shared secret")
+}
+
+/// Copies the bytes of a Kyber768 value into an array of its length.
+fn to_array<const N: usize>(bytes: &[u8], what: &str) -> Result<[u8; N]> {
+    bytes.try_into().map_err(|_| {
+        Error::EncryptionError(format!("Kyber768 {} must be {} bytes, got {}", what, N, bytes.len()))
+    })
+}
+
+/// Returns the error for bytes pqcrypto-kyber does not accept as a value.
+fn invalid(what: &str, e: pqcrypto_traits::Error) -> Error {
+    Error::EncryptionError(format!("Invalid Kyber768 {}: {}", what, e))
+}
+
 /// KyberEncryptor implementation
 ///
 /// This encryptor uses Kyber768 for key encapsulation and AES-GCM for data encryption.
@@ -34,7 +86,7 @@ pub struct KyberEncryptor {
 impl KyberEncryptor {
     /// Creates a new KyberEncryptor with a randomly generated default keypair.
     pub fn new() -> Result<Self> {
-        let (public_key, secret_key) = kyber_keypair();
+        let (public_key, secret_key) = kyber_keypair()?;
         
         Ok(Self {
             default_public_key: public_key,
@@ -84,7 +136,7 @@ impl KyberEncryptor {
     
     /// Generates a new keypair and adds it to the cache.
     pub fn generate_keypair(&self, key_id: &str) -> Result<()> {
-        let (public_key, secret_key) = kyber_keypair();
+        let (public_key, secret_key) = kyber_keypair()?;
         self.add_keypair(key_id, public_key, secret_key)
     }
     
@@ -113,7 +165,7 @@ impl super::Encryptor for KyberEncryptor {
         let (public_key, _) = self.get_keypair(key_id)?;
         
         // Encapsulate a shared secret using Kyber
-        let (ciphertext, shared_secret) = kyber_encapsulate(&public_key);
+        let (ciphertext, shared_secret) = kyber_encapsulate(&public_key)?;
         
         // Use the shared secret as an AES key
         let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&shared_secret));
@@ -152,7 +204,7 @@ impl super::Encryptor for KyberEncryptor {
         let encrypted_data = &data[KYBER_CIPHERTEXTBYTES..];
         
         // Decapsulate the shared secret using Kyber
-        let shared_secret = kyber_decapsulate(&kyber_ciphertext, &secret_key);
+        let shared_secret = kyber_decapsulate(&kyber_ciphertext, &secret_key)?;
         
         // Use the shared secret as an AES key
         let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&shared_secret));
@@ -167,11 +219,132 @@ impl super::Encryptor for KyberEncryptor {
         
         Ok(decrypted_data)
     }
+
+    fn strategy(&self) -> super::EncryptionStrategy {
+        super::EncryptionStrategy::Kyber
+    }
+}
+
+/// A Kyber768 key encapsulation mechanism.
+///
+/// A KEM built from a public key only can encapsulate; decapsulating needs the
+/// secret key as well.
+#[derive(Clone)]
+pub struct KyberKem {
+    public_key: [u8; KYBER_PUBLICKEYBYTES],
+    secret_key: Option<[u8; KYBER_SECRETKEYBYTES]>,
+}
+
+impl fmt::Debug for KyberKem {
+    // The secret key is not printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KyberKem").field("has_secret_key", &self.secret_key.is_some()).finish_non_exhaustive()
+    }
+}
+
+impl KyberKem {
+    /// Creates a KEM with a randomly generated keypair.
+    pub fn generate() -> Result<Self> {
+        let (public_key, secret_key) = kyber_keypair()?;
+        Ok(Self { public_key, secret_key: Some(secret_key) })
+    }
+
+    /// Creates a KEM with the provided keypair.
+    pub fn with_keypair(public_key: [u8; KYBER_PUBLICKEYBYTES], secret_key: [u8; KYBER_SECRETKEYBYTES]) -> Self {
+        Self { public_key, secret_key: Some(secret_key) }
+    }
+
+    /// Creates a KEM that encapsulates to the peer's public key.
+    pub fn from_public_key(public_key: &[u8]) -> Result<Self> {
+        let public_key = public_key.try_into().map_err(|_| {
+            Error::EncryptionError(format!(
+                "Invalid Kyber public key size: expected {} bytes, got {} bytes",
+                KYBER_PUBLICKEYBYTES,
+                public_key.len()
+            ))
+        })?;
+        Ok(Self { public_key, secret_key: None })
+    }
+
+    /// Returns the public key, to be sent to the peers that encapsulate.
+    pub fn public_key(&self) -> &[u8; KYBER_PUBLICKEYBYTES] {
+        &self.public_key
+    }
+
+    /// Generates a shared secret and the ciphertext that carries it to the
+    /// holder of the secret key.
+    pub fn encapsulate(&self) -> Result<([u8; KYBER_CIPHERTEXTBYTES], SharedSecret)> {
+        let (ciphertext, secret) = kyber_encapsulate(&self.public_key)?;
+        Ok((ciphertext, SharedSecret(secret)))
+    }
+
+    /// Recovers the shared secret carried by a ciphertext.
+    ///
+    /// Kyber decapsulation does not fail on a tampered ciphertext; it yields a
+    /// different secret, so the first packet decrypted with it fails instead.
+    pub fn decapsulate(&self, ciphertext: &[u8]) -> Result<SharedSecret> {
+        let secret_key = self.secret_key.as_ref().ok_or_else(|| {
+            Error::EncryptionError("Kyber KEM has no secret key to decapsulate with".to_string())
+        })?;
+        Ok(SharedSecret(kyber_decapsulate(ciphertext, secret_key)?))
+    }
+}
+
+/// A secret shared by the two ends of a key encapsulation.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SharedSecret([u8; KYBER_SYMBYTES]);
+
+impl fmt::Debug for SharedSecret {
+    // The secret is not printed
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SharedSecret(..)")
+    }
+}
+
+impl SharedSecret {
+    /// Returns the secret bytes.
+    pub fn as_bytes(&self) -> &[u8; KYBER_SYMBYTES] {
+        &self.0
+    }
+}
+
+/// An encryptor for the packets of a session, keyed by a Kyber shared secret.
+///
+/// Each message costs a random nonce and an authentication tag, as with
+/// `AesGcmEncryptor`, instead of a new encapsulation. Key ids are ignored:
+/// the session has a single key.
+#[derive(Debug)]
+pub struct SessionEncryptor {
+    inner: AesGcmEncryptor,
+}
+
+impl SessionEncryptor {
+    /// Creates an encryptor with the session key derived from the shared secret.
+    pub fn new(secret: &SharedSecret) -> Result<Self> {
+        let key = blake3::derive_key(SESSION_KEY_CONTEXT, secret.as_bytes());
+        Ok(Self { inner: AesGcmEncryptor::with_key(&key)? })
+    }
+}
+
+impl super::Encryptor for SessionEncryptor {
+    fn encrypt(&self, data: &[u8], _key_id: Option<&str>) -> Result<Vec<u8>> {
+        self.inner.encrypt(data, None)
+    }
+
+    fn decrypt(&self, data: &[u8], _key_id: Option<&str>) -> Result<Vec<u8>> {
+        self.inner.decrypt(data, None)
+    }
+
+    // Session packets are plain AES-GCM packets under the session key
+    fn strategy(&self) -> super::EncryptionStrategy {
+        super::EncryptionStrategy::AesGcm
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::encrypt::Encryptor;
     
     #[test]
     fn test_kyber_encrypt_decrypt() {
@@ -187,7 +360,7 @@ mod tests {
     
     #[test]
     fn test_kyber_with_keypair() {
-        let (public_key, secret_key) = kyber_keypair();
+        let (public_key, secret_key) = kyber_keypair().unwrap();
         let encryptor = KyberEncryptor::with_keypair(public_key, secret_key).unwrap();
         let data = b"Test data with custom keypair";
         
@@ -223,4 +396,26 @@ mod tests {
         assert!(encryptor.encrypt(data, Some(key_id)).is_err());
         assert!(encryptor.decrypt(&encrypted, Some(key_id)).is_err());
     }
+    
+    #[test]
+    fn test_kem_session() {
+        let receiver = KyberKem::generate().unwrap();
+        let sender = KyberKem::from_public_key(receiver.public_key()).unwrap();
+        let (ciphertext, sender_secret) = sender.encapsulate().unwrap();
+        let receiver_secret = receiver.decapsulate(&ciphertext).unwrap();
+        assert_eq!(sender_secret, receiver_secret);
+        assert!(sender.decapsulate(&ciphertext).is_err());
+        assert!(receiver.decapsulate(&ciphertext[1..]).is_err());
+        
+        let sending = SessionEncryptor::new(&sender_secret).unwrap();
+        let receiving = SessionEncryptor::new(&receiver_secret).unwrap();
+        for packet in [&b"first packet"[..], b"second packet"] {
+            let encrypted = sending.encrypt(packet, None).unwrap();
+            assert_eq!(encrypted.len(), 12 + packet.len() + 16);
+            assert_eq!(receiving.decrypt(&encrypted, None).unwrap(), packet);
+        }
+        
+        let other = SessionEncryptor::new(&KyberKem::generate().unwrap().encapsulate().unwrap().1).unwrap();
+        assert!(other.decrypt(&sending.encrypt(b"packet", None).unwrap(), None).is_err());
+    }
 }
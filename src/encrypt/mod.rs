@@ -1,7 +1,10 @@
 // Encryption module for Tonitru network native data format
 //
 // This module provides encryption and decryption capabilities for Tonitru data.
-// It supports multiple encryption algorithms and field-level encryption.
+// It supports multiple encryption algorithms and field-level encryption, and
+// Ed25519 and hybrid Ed25519 + Dilithium signatures for packet authenticity.
+// With the `fips` feature, it is restricted to FIPS approved algorithms (see
+// `fips`).
 
 use crate::internal::error::{Error, Result};
 use std::fmt::Debug;
@@ -10,11 +13,20 @@ pub mod aes_gcm;
 pub mod chacha20_poly1305;
 pub mod kyber;
 pub mod ecc;
+pub mod dilithium;
 pub mod field_level;
+pub mod fips;
+pub mod guarded;
+pub mod hybrid_signature;
 pub mod key_management;
+pub mod key_providers;
+pub mod ratchet;
+pub mod rng;
+pub mod sealed_storage;
+pub mod signature;
 
 /// Defines the encryption strategy to use.
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy)]
 #[repr(u8)] // Ensure enum variants have a fixed u8 representation
 pub enum EncryptionStrategy {
     /// No encryption, data is stored as-is
@@ -35,8 +47,28 @@ pub enum EncryptionStrategy {
     EccChaCha20Poly1305 = 7,
 }
 
+impl EncryptionStrategy {
+    /// Converts the value recorded in a packet header back to a strategy.
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(EncryptionStrategy::NoEncryption),
+            1 => Ok(EncryptionStrategy::AesGcm),
+            2 => Ok(EncryptionStrategy::ChaCha20Poly1305),
+            3 => Ok(EncryptionStrategy::Kyber),
+            4 => Ok(EncryptionStrategy::Hybrid),
+            5 => Ok(EncryptionStrategy::ChaChaKyberHybrid),
+            6 => Ok(EncryptionStrategy::EccAesGcm),
+            7 => Ok(EncryptionStrategy::EccChaCha20Poly1305),
+            _ => Err(Error::EncryptionError(format!("Unknown encryption strategy: {}", value))),
+        }
+    }
+}
+
 /// Trait for encryption algorithms.
-pub trait Encryptor: Debug {
+///
+/// Encryptors are `Send + Sync`, so a keyed encryptor can be shared by the
+/// threads of a server; implementations guard their key caches with locks.
+pub trait Encryptor: Debug + Send + Sync {
     /// Encrypts the given data.
     ///
     /// # Arguments
@@ -60,12 +92,23 @@ pub trait Encryptor: Debug {
     ///
     /// Returns the decrypted data or an error.
     fn decrypt(&self, data: &[u8], key_id: Option<&str>) -> Result<Vec<u8>>;
+
+    /// Returns the strategy this encryptor implements, which is recorded in
+    /// packet headers so that receivers can select a matching encryptor.
+    fn strategy(&self) -> EncryptionStrategy;
 }
 
 /// Returns an Encryptor implementation based on the given strategy.
+///
+/// In FIPS mode, strategies that are not approved are rejected and AES-GCM is
+/// provided by the validated backend.
 pub fn get_encryptor(strategy: EncryptionStrategy) -> Result<Box<dyn Encryptor>> {
+    fips::check_approved(strategy)?;
     match strategy {
         EncryptionStrategy::NoEncryption => Ok(Box::new(NoEncryptionEncryptor)),
+        #[cfg(feature = "fips")]
+        EncryptionStrategy::AesGcm => Ok(Box::new(fips::FipsAesGcmEncryptor::new()?)),
+        #[cfg(not(feature = "fips"))]
         EncryptionStrategy::AesGcm => Ok(Box::new(aes_gcm::AesGcmEncryptor::new()?)),
         EncryptionStrategy::ChaCha20Poly1305 => Ok(Box::new(chacha20_poly1305::ChaCha20Poly1305Encryptor::new()?)),
         EncryptionStrategy::Kyber => Ok(Box::new(kyber::KyberEncryptor::new()?)),
@@ -88,6 +131,10 @@ impl Encryptor for NoEncryptionEncryptor {
     fn decrypt(&self, data: &[u8], _key_id: Option<&str>) -> Result<Vec<u8>> {
         Ok(data.to_vec())
     }
+
+    fn strategy(&self) -> EncryptionStrategy {
+        EncryptionStrategy::NoEncryption
+    }
 }
 
 /// A hybrid encryptor that combines AES-GCM and Kyber for both
@@ -124,6 +171,10 @@ impl Encryptor for HybridEncryptor {
         // Then decrypt with AES-GCM
         self.aes_gcm.decrypt(&kyber_decrypted, key_id)
     }
+
+    fn strategy(&self) -> EncryptionStrategy {
+        EncryptionStrategy::Hybrid
+    }
 }
 
 /// A hybrid encryptor that combines ChaCha20-Poly1305 and Kyber for both
@@ -160,6 +211,10 @@ impl Encryptor for ChaChaKyberHybridEncryptor {
         // Then decrypt with ChaCha20-Poly1305
         self.chacha.decrypt(&kyber_decrypted, key_id)
     }
+
+    fn strategy(&self) -> EncryptionStrategy {
+        EncryptionStrategy::ChaChaKyberHybrid
+    }
 }
 
 #[cfg(test)]
@@ -191,6 +246,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "fips"))]
     fn test_chacha20_poly1305_encryption() {
         let encryptor = get_encryptor(EncryptionStrategy::ChaCha20Poly1305).unwrap();
         let data = b"Test data for ChaCha20-Poly1305 encryption";
@@ -203,6 +259,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "fips"))]
     fn test_kyber_encryption() {
         let encryptor = get_encryptor(EncryptionStrategy::Kyber).unwrap();
         let data = b"Test data for Kyber encryption";
@@ -215,6 +272,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "fips"))]
     fn test_hybrid_encryption() {
         let encryptor = get_encryptor(EncryptionStrategy::Hybrid).unwrap();
         let data = b"Test data for hybrid encryption";
@@ -227,6 +285,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "fips"))]
     fn test_chacha_kyber_hybrid_encryption() {
         let encryptor = get_encryptor(EncryptionStrategy::ChaChaKyberHybrid).unwrap();
         let data = b"Test data for ChaCha20-Poly1305 + Kyber hybrid encryption";
@@ -239,6 +298,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "fips"))]
     fn test_ecc_aes_gcm_encryption() {
         let encryptor = get_encryptor(EncryptionStrategy::EccAesGcm).unwrap();
         let data = b"Test data for ECC + AES-GCM encryption";
@@ -251,6 +311,7 @@ mod tests {
     }
 
     #[test]
+    #[cfg(not(feature = "fips"))]
     fn test_ecc_chacha20_poly1305_encryption() {
         let encryptor = get_encryptor(EncryptionStrategy::EccChaCha20Poly1305).unwrap();
         let data = b"Test data for ECC + ChaCha20-Poly1305 encryption";
@@ -0,0 +1,293 @@
+// Session key ratcheting for long-lived streams
+//
+// A transport session that keeps a single AEAD key for hours exposes all of its
+// traffic if that key leaks. `RatchetEncryptor` derives a chain of keys from
+// the session key with HKDF-SHA256 instead and moves to the next key of the
+// chain every so many messages or bytes, as set by the `RatchetPolicy`. Moving
+// on replaces the chain key with one derived from it and forgets the old one,
+// so a key leaked mid-stream decrypts the messages of its own epoch and later
+// ones, but none of the earlier ones.
+//
+// Each direction of a session has its own chain, labelled by the role of the
+// sender, so the two peers rotate independently. Every message starts with the
+// epoch of its key as a varint, followed by an AES-256-GCM packet under that
+// key. Receivers follow the epochs of the sender: a message of a later epoch
+// moves the receiving chain forward once it has been authenticated, and
+// messages of earlier epochs are rejected since their keys are gone.
+
+use std::fmt;
+use std::sync::Mutex;
+
+use hkdf::Hkdf;
+use sha2::Sha256;
+
+use crate::codec::varint::encode_varint_into;
+use crate::encrypt::aes_gcm::AesGcmEncryptor;
+use crate::encrypt::{fips, Encryptor};
+use crate::internal::cursor::WireCursor;
+use crate::internal::error::{Error, Result};
+
+/// HKDF salt turning the session key into the root of the chains
+const RATCHET_SALT: &[u8] = b"Tonitru session ratchet v1";
+/// HKDF info of the key encrypting the messages of an epoch
+const MESSAGE_KEY_INFO: &[u8] = b"message key";
+/// HKDF info of the chain key of the next epoch
+const CHAIN_KEY_INFO: &[u8] = b"chain key";
+/// Furthest a receiver moves its chain forward for a single message
+pub const MAX_EPOCH_SKIP: u64 = 1024;
+
+/// Which side of the session an encryptor is on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RatchetRole {
+    /// The side that opened the session
+    Initiator,
+    /// The side that accepted the session
+    Responder,
+}
+
+impl RatchetRole {
+    /// Returns the HKDF info of the chain this role sends on
+    fn sending_label(self) -> &'static [u8] {
+        match self {
+            RatchetRole::Initiator => b"initiator chain",
+            RatchetRole::Responder => b"responder chain",
+        }
+    }
+
+    fn peer(self) -> Self {
+        match self {
+            RatchetRole::Initiator => RatchetRole::Responder,
+            RatchetRole::Responder => RatchetRole::Initiator,
+        }
+    }
+}
+
+/// When the sending key moves to the next epoch
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RatchetPolicy {
+    max_messages: u64,
+    max_bytes: u64,
+}
+
+impl Default for RatchetPolicy {
+    /// Rotates every 1024 messages or 64 MiB of plaintext, whichever comes first
+    fn default() -> Self {
+        Self { max_messages: 1024, max_bytes: 64 << 20 }
+    }
+}
+
+impl RatchetPolicy {
+    /// Creates the default policy
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Rotates after the key has encrypted this many messages
+    pub fn with_max_messages(mut self, max_messages: u64) -> Self {
+        self.max_messages = max_messages.max(1);
+        self
+    }
+
+    /// Rotates after the key has encrypted this many bytes of plaintext
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.max_bytes = max_bytes.max(1);
+        self
+    }
+}
+
+/// One direction of a session: the key of the current epoch and the means to
+/// derive the next
+struct Chain {
+    epoch: u64,
+    chain_key: [u8; 32],
+    cipher: AesGcmEncryptor,
+}
+
+impl Chain {
+    fn new(session_key: &[u8; 32], label: &[u8]) -> Result<Self> {
+        let mut chain_key = [0u8; 32];
+        Hkdf::<Sha256>::new(Some(RATCHET_SALT), session_key)
+            .expand(label, &mut chain_key)
+            .map_err(|_| Error::EncryptionError("Failed to derive ratchet chain key".to_string()))?;
+        Self::at_epoch(0, chain_key)
+    }
+
+    fn at_epoch(epoch: u64, chain_key: [u8; 32]) -> Result<Self> {
+        let message_key = expand(&chain_key, MESSAGE_KEY_INFO)?;
+        Ok(Self { epoch, chain_key, cipher: AesGcmEncryptor::with_key(&message_key)? })
+    }
+
+    /// Moves to the next epoch, forgetting the key of the current one
+    fn advance(&mut self) -> Result<()> {
+        *self = self.ahead(self.epoch + 1)?;
+        Ok(())
+    }
+
+    /// Derives the chain at a later epoch, leaving this one as it is
+    fn ahead(&self, epoch: u64) -> Result<Self> {
+        let mut chain_key = self.chain_key;
+        for _ in self.epoch..epoch {
+            chain_key = expand(&chain_key, CHAIN_KEY_INFO)?;
+        }
+        Self::at_epoch(epoch, chain_key)
+    }
+}
+
+/// Derives 32 bytes from a chain key
+fn expand(chain_key: &[u8; 32], info: &[u8]) -> Result<[u8; 32]> {
+    let failed = || Error::EncryptionError("Failed to derive ratchet key".to_string());
+    let mut key = [0u8; 32];
+    Hkdf::<Sha256>::from_prk(chain_key).map_err(|_| failed())?.expand(info, &mut key).map_err(|_| failed())?;
+    Ok(key)
+}
+
+/// The sending chain with what its current key has encrypted
+struct Sending {
+    chain: Chain,
+    messages: u64,
+    bytes: u64,
+}
+
+/// Encrypts the messages of a session under a ratcheting key
+///
+/// Both peers create one from the same session key, such as one derived from
+/// a Kyber shared secret, with opposite roles. Key ids are ignored.
+pub struct RatchetEncryptor {
+    policy: RatchetPolicy,
+    sending: Mutex<Sending>,
+    receiving: Mutex<Chain>,
+}
+
+impl fmt::Debug for RatchetEncryptor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RatchetEncryptor")
+            .field("policy", &self.policy)
+            .field("send_epoch", &self.send_epoch())
+            .field("receive_epoch", &self.receive_epoch())
+            .finish()
+    }
+}
+
+impl RatchetEncryptor {
+    /// Creates the encryptor of one side of a session with the default policy.
+    /// Fails in FIPS mode, as the keys are derived with HKDF.
+    pub fn new(session_key: &[u8; 32], role: RatchetRole) -> Result<Self> {
+        fips::check_key_derivation()?;
+        Ok(Self {
+            policy: RatchetPolicy::default(),
+            sending: Mutex::new(Sending { chain: Chain::new(session_key, role.sending_label())?, messages: 0, bytes: 0 }),
+            receiving: Mutex::new(Chain::new(session_key, role.peer().sending_label())?),
+        })
+    }
+
+    /// Sets when the sending key rotates
+    pub fn with_policy(mut self, policy: RatchetPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Returns the epoch of the sending key
+    pub fn send_epoch(&self) -> Option<u64> {
+        self.sending.lock().ok().map(|sending| sending.chain.epoch)
+    }
+
+    /// Returns the epoch of the receiving key
+    pub fn receive_epoch(&self) -> Option<u64> {
+        self.receiving.lock().ok().map(|chain| chain.epoch)
+    }
+}
+
+impl Encryptor for RatchetEncryptor {
+    fn encrypt(&self, data: &[u8], _key_id: Option<&str>) -> Result<Vec<u8>> {
+        let mut sending = self.sending.lock().map_err(|_| {
+            Error::EncryptionError("Failed to acquire lock on ratchet sending chain".to_string())
+        })?;
+        if sending.messages >= self.policy.max_messages || sending.bytes >= self.policy.max_bytes {
+            sending.chain.advance()?;
+            sending.messages = 0;
+            sending.bytes = 0;
+        }
+
+        let mut out = Vec::with_capacity(data.len() + 40);
+        encode_varint_into(sending.chain.epoch, &mut out);
+        out.extend_from_slice(&sending.chain.cipher.encrypt(data, None)?);
+        sending.messages += 1;
+        sending.bytes = sending.bytes.saturating_add(data.len() as u64);
+        Ok(out)
+    }
+
+    fn decrypt(&self, data: &[u8], _key_id: Option<&str>) -> Result<Vec<u8>> {
+        let mut cursor = WireCursor::new(data).with_truncation_error(Error::EncryptionError);
+        let epoch = cursor.read_varint("ratchet epoch")?;
+        let mut receiving = self.receiving.lock().map_err(|_| {
+            Error::EncryptionError("Failed to acquire lock on ratchet receiving chain".to_string())
+        })?;
+        if epoch < receiving.epoch {
+            return Err(Error::EncryptionError(format!(
+                "Message of ratchet epoch {} arrived after the key moved to epoch {}",
+                epoch, receiving.epoch
+            )));
+        }
+        if epoch - receiving.epoch > MAX_EPOCH_SKIP {
+            return Err(Error::EncryptionError(format!(
+                "Message of ratchet epoch {} is more than {} epochs ahead of epoch {}",
+                epoch, MAX_EPOCH_SKIP, receiving.epoch
+            )));
+        }
+        if epoch == receiving.epoch {
+            return receiving.cipher.decrypt(cursor.rest(), None);
+        }
+
+        // Only move forward once the message proves the epoch authentic
+        let next = receiving.ahead(epoch)?;
+        let plaintext = next.cipher.decrypt(cursor.rest(), None)?;
+        *receiving = next;
+        Ok(plaintext)
+    }
+
+    // Messages are AES-GCM packets under the key of their epoch
+    fn strategy(&self) -> super::EncryptionStrategy {
+        super::EncryptionStrategy::AesGcm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keys_rotate_and_old_epochs_are_rejected() {
+        let policy = RatchetPolicy::new().with_max_messages(2);
+        let client = RatchetEncryptor::new(&[5u8; 32], RatchetRole::Initiator).unwrap().with_policy(policy);
+        let server = RatchetEncryptor::new(&[5u8; 32], RatchetRole::Responder).unwrap();
+
+        let messages: Vec<_> = (0..5u8).map(|i| client.encrypt(&[i; 10], None).unwrap()).collect();
+        let epochs: Vec<_> = messages.iter().map(|message| message[0]).collect();
+        assert_eq!(epochs, vec![0, 0, 1, 1, 2]);
+
+        assert_eq!(server.decrypt(&messages[0], None).unwrap(), [0u8; 10]);
+        // Skipping a lost message moves the chain forward
+        assert_eq!(server.decrypt(&messages[4], None).unwrap(), [4u8; 10]);
+        assert_eq!(server.receive_epoch(), Some(2));
+        let err = server.decrypt(&messages[2], None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Encryption Error: Message of ratchet epoch 1 arrived after the key moved to epoch 2"
+        );
+
+        // The directions have separate chains, and a forged epoch leaves the chain alone
+        let reply = server.encrypt(b"ack", None).unwrap();
+        assert!(server.decrypt(&reply, None).is_err());
+        assert_eq!(client.decrypt(&reply, None).unwrap(), b"ack");
+        let mut forged = reply.clone();
+        forged[0] = 3;
+        assert!(client.decrypt(&forged, None).is_err());
+        assert_eq!(client.receive_epoch(), Some(0));
+
+        let by_bytes = RatchetEncryptor::new(&[5u8; 32], RatchetRole::Initiator)
+            .unwrap()
+            .with_policy(RatchetPolicy::new().with_max_bytes(16));
+        let epochs: Vec<_> = (0..3).map(|_| by_bytes.encrypt(&[0u8; 10], None).unwrap()[0]).collect();
+        assert_eq!(epochs, vec![0, 0, 1]);
+    }
+}
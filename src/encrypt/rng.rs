@@ -0,0 +1,147 @@
+// Source of randomness for the encryption module
+//
+// Every key, nonce, salt and key ID generated under `crate::encrypt` is drawn
+// from `SecureRng`, which forwards to the operating system generator. With the
+// `deterministic-rng` feature, a test can run code under
+// `with_deterministic_rng`: until the closure returns, the encryptors and the
+// `KeyManager` draw from the given `DeterministicRng` instead. Ciphertexts and
+// generated keys are then the same on every run, so encryption round trips can
+// be checked byte for byte against golden fixtures.
+//
+// A `DeterministicRng` is a BLAKE3 output stream keyed by a seed. It is not
+// meant to protect real data, and the feature must not be enabled outside of
+// tests. The override is per thread: work running on other threads, such as the
+// key rotation task, keeps using the operating system generator, and so does
+// Kyber key generation, which draws its own randomness.
+
+use rand_core::{CryptoRng, OsRng, RngCore};
+
+/// Cryptographically secure generator used throughout the encryption module
+///
+/// Draws from the operating system, unless a `DeterministicRng` is installed on
+/// the current thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SecureRng;
+
+impl RngCore for SecureRng {
+    fn next_u32(&mut self) -> u32 {
+        rand_core::impls::next_u32_via_fill(self)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        rand_core::impls::next_u64_via_fill(self)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        #[cfg(any(test, feature = "deterministic-rng"))]
+        if deterministic::fill(dest) {
+            return;
+        }
+        OsRng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> std::result::Result<(), rand_core::Error> {
+        #[cfg(any(test, feature = "deterministic-rng"))]
+        if deterministic::fill(dest) {
+            return Ok(());
+        }
+        OsRng.try_fill_bytes(dest)
+    }
+}
+
+impl CryptoRng for SecureRng {}
+
+#[cfg(any(test, feature = "deterministic-rng"))]
+pub use deterministic::{with_deterministic_rng, DeterministicRng};
+
+#[cfg(any(test, feature = "deterministic-rng"))]
+mod deterministic {
+    use std::cell::RefCell;
+    use std::fmt;
+
+    /// Key derivation context of the seed
+    const SEED_CONTEXT: &str = "Tonitru deterministic test RNG v1";
+
+    thread_local! {
+        static INSTALLED: RefCell<Option<DeterministicRng>> = const { RefCell::new(None) };
+    }
+
+    /// Reproducible stream of random bytes, for tests only
+    #[derive(Clone)]
+    pub struct DeterministicRng {
+        stream: blake3::OutputReader,
+    }
+
+    impl fmt::Debug for DeterministicRng {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            f.debug_struct("DeterministicRng").field("position", &self.stream.position()).finish()
+        }
+    }
+
+    impl DeterministicRng {
+        /// Creates a generator; the same seed always yields the same bytes
+        pub fn from_seed(seed: u64) -> Self {
+            let mut hasher = blake3::Hasher::new_derive_key(SEED_CONTEXT);
+            hasher.update(&seed.to_le_bytes());
+            Self { stream: hasher.finalize_xof() }
+        }
+    }
+
+    /// Restores the generator installed before `with_deterministic_rng`, also
+    /// when the closure panics
+    struct Restore(Option<DeterministicRng>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            INSTALLED.with(|installed| *installed.borrow_mut() = previous);
+        }
+    }
+
+    /// Runs `f` with the encryption module drawing its randomness on this
+    /// thread from `rng`
+    pub fn with_deterministic_rng<R>(rng: DeterministicRng, f: impl FnOnce() -> R) -> R {
+        let previous = INSTALLED.with(|installed| installed.borrow_mut().replace(rng));
+        let _restore = Restore(previous);
+        f()
+    }
+
+    /// Fills `dest` from the installed generator, returning false if there is none
+    pub(super) fn fill(dest: &mut [u8]) -> bool {
+        INSTALLED.with(|installed| match installed.borrow_mut().as_mut() {
+            Some(rng) => {
+                rng.stream.fill(dest);
+                true
+            }
+            None => false,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt::aes_gcm::AesGcmEncryptor;
+    use crate::encrypt::key_management::{KeyManager, KeyType};
+    use crate::encrypt::Encryptor;
+
+    #[test]
+    fn test_deterministic_rng_reproduces_ciphertexts_and_keys() {
+        let run = |seed| {
+            with_deterministic_rng(DeterministicRng::from_seed(seed), || {
+                let encryptor = AesGcmEncryptor::new().unwrap();
+                let ciphertext = encryptor.encrypt(b"golden", None).unwrap();
+                let manager = KeyManager::new();
+                let key_id = manager.generate_key(KeyType::ChaCha20Poly1305, true).unwrap();
+                let wrapped = manager.wrap_key(&key_id, &[1u8; 32]).unwrap();
+                (ciphertext, key_id, wrapped)
+            })
+        };
+
+        assert_eq!(run(7), run(7));
+        assert_ne!(run(7), run(8));
+        // Outside the closure the operating system generator is back
+        let encryptor = AesGcmEncryptor::with_key(&[0u8; 32]).unwrap();
+        assert_ne!(encryptor.encrypt(b"golden", None).unwrap(), encryptor.encrypt(b"golden", None).unwrap());
+    }
+}
@@ -0,0 +1,402 @@
+// Key storage sealed by platform security hardware
+//
+// A `SealedKeyProvider` is an `ExternalKeyProvider` that writes every key to a
+// directory only after a `KeySealer` has sealed it, so master keys never exist
+// in plaintext on disk. The sealers ship behind per-platform features:
+//
+//   tpm             `Tpm2Sealer`, Linux: the key becomes a TPM2 sealed data
+//                   object under a primary key of the owner hierarchy; only
+//                   the same TPM can unseal it
+//   secure-enclave  `SecureEnclaveSealer`, macOS: the key is encrypted with
+//                   ECIES under a P-256 key generated inside the Secure Enclave,
+//                   whose private half never leaves it
+//
+// Each key is stored in `<key id>.sealed`: magic "TNSK", the format version,
+// the key type, the name of the sealer as a varint length and UTF-8 bytes, and
+// the sealed blob. Blobs are only handed to the sealer that produced them.
+
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::codec::varint::encode_varint_into;
+use crate::encrypt::key_management::{ExternalKeyProvider, KeyType};
+use crate::encrypt::key_providers::{is_file_key_id, write_atomically};
+use crate::internal::cursor::WireCursor;
+use crate::internal::error::{Error, Result};
+
+/// Magic number at the start of a sealed key file
+const SEALED_KEY_MAGIC: &[u8; 4] = b"TNSK";
+/// Version of the sealed key file format
+const SEALED_KEY_VERSION: u8 = 1;
+/// Extension of sealed key files
+const SEALED_KEY_EXTENSION: &str = "sealed";
+
+/// Seals key bytes so that only the sealing device can recover them
+pub trait KeySealer: Send + Sync + fmt::Debug {
+    /// Returns the name recorded with the blobs of this sealer
+    fn name(&self) -> &str;
+    /// Seals the key bytes into an opaque blob
+    fn seal(&self, key: &[u8]) -> Result<Vec<u8>>;
+    /// Recovers the key bytes from a blob returned by `seal`
+    fn unseal(&self, blob: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Stores keys sealed by a `KeySealer` in a directory
+#[derive(Debug)]
+pub struct SealedKeyProvider<S: KeySealer> {
+    dir: PathBuf,
+    sealer: S,
+}
+
+impl<S: KeySealer> SealedKeyProvider<S> {
+    /// Creates a provider storing keys in the directory, which is created if
+    /// it does not exist
+    pub fn new(dir: impl Into<PathBuf>, sealer: S) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, sealer })
+    }
+
+    /// Returns the directory keys are stored in
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the path of the file of a key
+    fn key_path(&self, key_id: &str) -> Result<PathBuf> {
+        if !is_file_key_id(key_id) {
+            return Err(Error::EncryptionError(format!("Invalid key ID for sealed storage: '{}'", key_id)));
+        }
+        Ok(self.dir.join(format!("{}.{}", key_id, SEALED_KEY_EXTENSION)))
+    }
+
+    /// Reads the type and sealed blob of a key
+    fn read(&self, key_id: &str) -> Result<(KeyType, Vec<u8>)> {
+        let data = match fs::read(self.key_path(key_id)?) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(Error::EncryptionError(format!("Key ID '{}' not found in sealed storage", key_id)));
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let mut cursor = WireCursor::new(&data).with_truncation_error(Error::EncryptionError);
+        if cursor.read_array::<4>("sealed key magic")? != *SEALED_KEY_MAGIC {
+            return Err(Error::EncryptionError(format!("Missing sealed key magic number for key '{}'", key_id)));
+        }
+        let version = cursor.read_u8("sealed key version")?;
+        if version != SEALED_KEY_VERSION {
+            return Err(Error::EncryptionError(format!("Unsupported sealed key version {}", version)));
+        }
+        let key_type = KeyType::from_u8(cursor.read_u8("sealed key type")?)?;
+        let sealer = cursor.read_length_prefixed("sealer name")?;
+        if sealer != self.sealer.name().as_bytes() {
+            return Err(Error::EncryptionError(format!(
+                "Key '{}' was sealed by '{}', not by '{}'",
+                key_id,
+                String::from_utf8_lossy(sealer),
+                self.sealer.name()
+            )));
+        }
+        Ok((key_type, cursor.rest().to_vec()))
+    }
+}
+
+impl<S: KeySealer> ExternalKeyProvider for SealedKeyProvider<S> {
+    fn get_key(&self, key_id: &str, key_type: KeyType) -> Result<Vec<u8>> {
+        let (found, blob) = self.read(key_id)?;
+        if found != key_type {
+            return Err(Error::EncryptionError(format!(
+                "Key '{}' is a {:?} key, not a {:?} key",
+                key_id, found, key_type
+            )));
+        }
+        self.sealer.unseal(&blob)
+    }
+
+    fn store_key(&self, key_id: &str, key_type: KeyType, key_data: &[u8]) -> Result<()> {
+        let path = self.key_path(key_id)?;
+        let mut data = SEALED_KEY_MAGIC.to_vec();
+        data.push(SEALED_KEY_VERSION);
+        data.push(key_type.to_u8());
+        encode_varint_into(self.sealer.name().len() as u64, &mut data);
+        data.extend_from_slice(self.sealer.name().as_bytes());
+        data.extend_from_slice(&self.sealer.seal(key_data)?);
+        write_atomically(&path, &data)
+    }
+
+    fn list_keys(&self, key_type: Option<KeyType>) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some(SEALED_KEY_EXTENSION) {
+                continue;
+            }
+            let Some(id) = path.file_stem().and_then(|stem| stem.to_str()) else { continue };
+            if key_type.is_none_or(|key_type| self.read(id).is_ok_and(|(found, _)| found == key_type)) {
+                ids.push(id.to_string());
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    fn delete_key(&self, key_id: &str) -> Result<()> {
+        match fs::remove_file(self.key_path(key_id)?) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(all(target_os = "linux", feature = "tpm"))]
+pub use self::tpm::Tpm2Sealer;
+
+#[cfg(all(target_os = "linux", feature = "tpm"))]
+mod tpm {
+    use std::sync::Mutex;
+
+    use tss_esapi::attributes::ObjectAttributesBuilder;
+    use tss_esapi::interface_types::algorithm::{HashingAlgorithm, PublicAlgorithm};
+    use tss_esapi::interface_types::key_bits::RsaKeyBits;
+    use tss_esapi::interface_types::resource_handles::Hierarchy;
+    use tss_esapi::structures::{
+        Digest, KeyedHashScheme, Private, Public, PublicBuilder, PublicKeyedHashParameters, RsaExponent,
+        SensitiveData, SymmetricDefinitionObject,
+    };
+    use tss_esapi::tcti_ldr::TctiNameConf;
+    use tss_esapi::traits::{Marshall, UnMarshall};
+    use tss_esapi::utils::create_restricted_decryption_rsa_public;
+    use tss_esapi::Context;
+
+    use super::KeySealer;
+    use crate::codec::varint::encode_varint_into;
+    use crate::internal::cursor::WireCursor;
+    use crate::internal::error::{Error, Result};
+
+    fn tpm_error(e: tss_esapi::Error) -> Error {
+        Error::EncryptionError(format!("TPM2 operation failed: {}", e))
+    }
+
+    /// Seals keys as TPM2 sealed data objects
+    ///
+    /// The parent is a primary RSA key of the owner hierarchy, recreated from
+    /// its template on every call, so no TPM handle needs to be persisted. A
+    /// blob is the marshalled public area followed by the private area of the
+    /// sealed object, each prefixed with its varint length.
+    pub struct Tpm2Sealer {
+        context: Mutex<Context>,
+    }
+
+    impl std::fmt::Debug for Tpm2Sealer {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("Tpm2Sealer").finish_non_exhaustive()
+        }
+    }
+
+    impl Tpm2Sealer {
+        /// Connects to the TPM named by the `TPM2TOOLS_TCTI` environment
+        /// variable, or to the default TCTI
+        pub fn new() -> Result<Self> {
+            let tcti = TctiNameConf::from_environment_variable().unwrap_or(TctiNameConf::Device(Default::default()));
+            let context = Context::new(tcti).map_err(tpm_error)?;
+            Ok(Self { context: Mutex::new(context) })
+        }
+
+        /// Runs `f` with a context and the handle of the primary key
+        fn with_primary<T>(&self, f: impl FnOnce(&mut Context, tss_esapi::handles::KeyHandle) -> tss_esapi::Result<T>) -> Result<T> {
+            let mut context = self.context.lock().map_err(|_| {
+                Error::EncryptionError("Failed to acquire lock on TPM context".to_string())
+            })?;
+            context.execute_with_nullauth_session(|context| {
+                let template = create_restricted_decryption_rsa_public(
+                    SymmetricDefinitionObject::AES_128_CFB,
+                    RsaKeyBits::Rsa2048,
+                    RsaExponent::default(),
+                )?;
+                let primary = context.create_primary(Hierarchy::Owner, template, None, None, None, None)?;
+                let result = f(context, primary.key_handle);
+                context.flush_context(primary.key_handle.into())?;
+                result
+            }).map_err(tpm_error)
+        }
+    }
+
+    impl KeySealer for Tpm2Sealer {
+        fn name(&self) -> &str {
+            "tpm2"
+        }
+
+        fn seal(&self, key: &[u8]) -> Result<Vec<u8>> {
+            let sensitive = SensitiveData::try_from(key.to_vec()).map_err(tpm_error)?;
+            let (public, private) = self.with_primary(|context, parent| {
+                let attributes = ObjectAttributesBuilder::new()
+                    .with_fixed_tpm(true)
+                    .with_fixed_parent(true)
+                    .with_user_with_auth(true)
+                    .build()?;
+                let template = PublicBuilder::new()
+                    .with_public_algorithm(PublicAlgorithm::KeyedHash)
+                    .with_name_hashing_algorithm(HashingAlgorithm::Sha256)
+                    .with_object_attributes(attributes)
+                    .with_keyed_hash_parameters(PublicKeyedHashParameters::new(KeyedHashScheme::Null))
+                    .with_keyed_hash_unique_identifier(Digest::default())
+                    .build()?;
+                let created = context.create(parent, template, None, Some(sensitive), None, None)?;
+                Ok((created.out_public, created.out_private))
+            })?;
+
+            let public = public.marshall().map_err(tpm_error)?;
+            let mut blob = Vec::new();
+            encode_varint_into(public.len() as u64, &mut blob);
+            blob.extend_from_slice(&public);
+            encode_varint_into(private.value().len() as u64, &mut blob);
+            blob.extend_from_slice(private.value());
+            Ok(blob)
+        }
+
+        fn unseal(&self, blob: &[u8]) -> Result<Vec<u8>> {
+            let mut cursor = WireCursor::new(blob).with_truncation_error(Error::EncryptionError);
+            let public = Public::unmarshall(cursor.read_length_prefixed("TPM2 public area")?).map_err(tpm_error)?;
+            let private = Private::try_from(cursor.read_length_prefixed("TPM2 private area")?.to_vec()).map_err(tpm_error)?;
+            self.with_primary(|context, parent| {
+                let sealed = context.load(parent, private, public)?;
+                let key = context.unseal(sealed.into());
+                context.flush_context(sealed.into())?;
+                Ok(key?.value().to_vec())
+            })
+        }
+    }
+}
+
+#[cfg(all(target_os = "macos", feature = "secure-enclave"))]
+pub use self::secure_enclave::SecureEnclaveSealer;
+
+#[cfg(all(target_os = "macos", feature = "secure-enclave"))]
+mod secure_enclave {
+    use security_framework::item::{ItemClass, ItemSearchOptions, Reference, SearchResult};
+    use security_framework::key::{Algorithm, GenerateKeyOptions, KeyType, SecKey, Token};
+
+    use super::KeySealer;
+    use crate::internal::error::{Error, Result};
+
+    /// ECIES variant used to seal keys under the Secure Enclave key
+    const ALGORITHM: Algorithm = Algorithm::ECIESEncryptionCofactorVariableIVX963SHA256AESGCM;
+
+    /// Seals keys with a P-256 key held in the Secure Enclave
+    ///
+    /// The enclave key is looked up in the keychain by label and generated on
+    /// first use. Sealing only needs its public half; unsealing runs inside
+    /// the enclave.
+    #[derive(Debug)]
+    pub struct SecureEnclaveSealer {
+        label: String,
+        key: SecKey,
+    }
+
+    impl SecureEnclaveSealer {
+        /// Opens the enclave key with the keychain label, creating it if needed
+        pub fn new(label: &str) -> Result<Self> {
+            let found = ItemSearchOptions::new()
+                .class(ItemClass::key())
+                .label(label)
+                .load_refs(true)
+                .search()
+                .unwrap_or_default()
+                .into_iter()
+                .find_map(|result| match result {
+                    SearchResult::Ref(Reference::Key(key)) => Some(key),
+                    _ => None,
+                });
+            let key = match found {
+                Some(key) => key,
+                None => {
+                    let mut options = GenerateKeyOptions::default();
+                    options
+                        .set_key_type(KeyType::ec())
+                        .set_size_in_bits(256)
+                        .set_label(label)
+                        .set_token(Token::SecureEnclave);
+                    SecKey::new(&options).map_err(|e| {
+                        Error::EncryptionError(format!("Cannot create Secure Enclave key '{}': {}", label, e))
+                    })?
+                }
+            };
+            Ok(Self { label: label.to_string(), key })
+        }
+    }
+
+    impl KeySealer for SecureEnclaveSealer {
+        fn name(&self) -> &str {
+            "secure-enclave"
+        }
+
+        fn seal(&self, key: &[u8]) -> Result<Vec<u8>> {
+            let public_key = self.key.public_key().ok_or_else(|| {
+                Error::EncryptionError(format!("Secure Enclave key '{}' has no public key", self.label))
+            })?;
+            public_key
+                .encrypt_data(ALGORITHM, key)
+                .map_err(|e| Error::EncryptionError(format!("Secure Enclave sealing failed: {}", e)))
+        }
+
+        fn unseal(&self, blob: &[u8]) -> Result<Vec<u8>> {
+            self.key
+                .decrypt_data(ALGORITHM, blob)
+                .map_err(|e| Error::EncryptionError(format!("Secure Enclave unsealing failed: {}", e)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encrypt::aes_gcm::AesGcmEncryptor;
+    use crate::encrypt::Encryptor;
+
+    /// Stands in for a hardware sealer
+    #[derive(Debug)]
+    struct TestSealer(&'static str, AesGcmEncryptor);
+
+    impl KeySealer for TestSealer {
+        fn name(&self) -> &str {
+            self.0
+        }
+
+        fn seal(&self, key: &[u8]) -> Result<Vec<u8>> {
+            self.1.encrypt(key, None)
+        }
+
+        fn unseal(&self, blob: &[u8]) -> Result<Vec<u8>> {
+            self.1.decrypt(blob, None)
+        }
+    }
+
+    #[test]
+    fn test_keys_are_stored_sealed() {
+        let dir = std::env::temp_dir().join(format!("tonitru-sealed-keys-{}", std::process::id()));
+        let sealer = || TestSealer("test", AesGcmEncryptor::with_key(&[7u8; 32]).unwrap());
+        let provider = SealedKeyProvider::new(&dir, sealer()).unwrap();
+        provider.store_key("master", KeyType::AesGcm, &[1u8; 32]).unwrap();
+        provider.store_key("session", KeyType::ChaCha20Poly1305, &[2u8; 32]).unwrap();
+        let on_disk = fs::read(dir.join("master.sealed")).unwrap();
+        assert!(!on_disk.windows(32).any(|window| window == [1u8; 32]));
+
+        let reopened = SealedKeyProvider::new(&dir, sealer()).unwrap();
+        assert_eq!(reopened.get_key("master", KeyType::AesGcm).unwrap(), [1u8; 32]);
+        assert!(reopened.get_key("master", KeyType::X25519).is_err());
+        assert!(reopened.get_key("../master", KeyType::AesGcm).is_err());
+        assert_eq!(reopened.list_keys(None).unwrap(), vec!["master", "session"]);
+        assert_eq!(reopened.list_keys(Some(KeyType::AesGcm)).unwrap(), vec!["master"]);
+
+        // Blobs are never handed to another sealer
+        let other = SealedKeyProvider::new(&dir, TestSealer("other", AesGcmEncryptor::with_key(&[7u8; 32]).unwrap())).unwrap();
+        let err = other.get_key("master", KeyType::AesGcm).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Key 'master' was sealed by 'test', not by 'other'");
+
+        reopened.delete_key("master").unwrap();
+        reopened.delete_key("master").unwrap();
+        assert_eq!(reopened.list_keys(None).unwrap(), vec!["session"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
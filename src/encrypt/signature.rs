@@ -0,0 +1,118 @@
+// Ed25519 signatures for Tonitru packets
+//
+// The BLAKE3 checksum of a packet detects corruption, but anyone can recompute
+// it after tampering with the packet. A signature made with the sender's
+// private key proves that the packet comes from the holder of that key and was
+// not modified since. `SigningKey` and `VerifyingKey` wrap the Ed25519 keys of
+// ed25519-dalek; `Packet::build_packet_signed` and `Packet::parse_packet_verified`
+// use them to sign and verify packets.
+//
+// Verification is strict: signatures with a non-canonical encoding and weak
+// public keys are rejected, so a message has a single valid signature per key.
+
+use std::fmt;
+
+use ed25519_dalek::{Signer, SECRET_KEY_LENGTH};
+use crate::encrypt::rng::SecureRng;
+
+use crate::internal::error::{Error, Result};
+
+/// Length of an Ed25519 signature in bytes
+pub const SIGNATURE_LEN: usize = ed25519_dalek::SIGNATURE_LENGTH;
+/// Length of an Ed25519 public key in bytes
+pub const PUBLIC_KEY_LEN: usize = ed25519_dalek::PUBLIC_KEY_LENGTH;
+/// Length of an Ed25519 private key in bytes
+pub const SECRET_KEY_LEN: usize = SECRET_KEY_LENGTH;
+
+/// An Ed25519 private key, used to sign.
+#[derive(Clone)]
+pub struct SigningKey {
+    key: ed25519_dalek::SigningKey,
+}
+
+impl fmt::Debug for SigningKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SigningKey").field("verifying_key", &self.verifying_key()).finish_non_exhaustive()
+    }
+}
+
+impl SigningKey {
+    /// Generates a random key.
+    pub fn generate() -> Self {
+        SigningKey { key: ed25519_dalek::SigningKey::generate(&mut SecureRng) }
+    }
+
+    /// Creates a key from its 32 private key bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; SECRET_KEY_LEN] = bytes.try_into().map_err(|_| {
+            Error::EncryptionError(format!("Invalid Ed25519 private key length: {} bytes, expected {}", bytes.len(), SECRET_KEY_LEN))
+        })?;
+        Ok(SigningKey { key: ed25519_dalek::SigningKey::from_bytes(&bytes) })
+    }
+
+    /// Returns the private key bytes.
+    pub fn to_bytes(&self) -> [u8; SECRET_KEY_LEN] {
+        self.key.to_bytes()
+    }
+
+    /// Returns the public key matching this key.
+    pub fn verifying_key(&self) -> VerifyingKey {
+        VerifyingKey { key: self.key.verifying_key() }
+    }
+
+    /// Signs a message.
+    pub fn sign(&self, message: &[u8]) -> [u8; SIGNATURE_LEN] {
+        self.key.sign(message).to_bytes()
+    }
+}
+
+/// An Ed25519 public key, used to verify signatures.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VerifyingKey {
+    key: ed25519_dalek::VerifyingKey,
+}
+
+impl VerifyingKey {
+    /// Creates a key from its 32 public key bytes.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        let bytes: [u8; PUBLIC_KEY_LEN] = bytes.try_into().map_err(|_| {
+            Error::EncryptionError(format!("Invalid Ed25519 public key length: {} bytes, expected {}", bytes.len(), PUBLIC_KEY_LEN))
+        })?;
+        let key = ed25519_dalek::VerifyingKey::from_bytes(&bytes)
+            .map_err(|e| Error::EncryptionError(format!("Invalid Ed25519 public key: {}", e)))?;
+        Ok(VerifyingKey { key })
+    }
+
+    /// Returns the public key bytes.
+    pub fn to_bytes(&self) -> [u8; PUBLIC_KEY_LEN] {
+        self.key.to_bytes()
+    }
+
+    /// Verifies the signature of a message.
+    pub fn verify(&self, message: &[u8], signature: &[u8; SIGNATURE_LEN]) -> Result<()> {
+        let signature = ed25519_dalek::Signature::from_bytes(signature);
+        self.key
+            .verify_strict(message, &signature)
+            .map_err(|_| Error::EncryptionError("Signature verification failed".to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sign_and_verify() {
+        let key = SigningKey::generate();
+        let restored = SigningKey::from_bytes(&key.to_bytes()).unwrap();
+        let public = VerifyingKey::from_bytes(&restored.verifying_key().to_bytes()).unwrap();
+        assert_eq!(public, key.verifying_key());
+
+        let signature = key.sign(b"message");
+        assert!(public.verify(b"message", &signature).is_ok());
+        let err = public.verify(b"massage", &signature).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Signature verification failed");
+        assert!(SigningKey::generate().verifying_key().verify(b"message", &signature).is_err());
+        assert!(SigningKey::from_bytes(&[0u8; 31]).is_err());
+    }
+}
@@ -0,0 +1,257 @@
+// Allocator hooks for large Tonitru buffers
+//
+// The big byte buffers of the library (compression outputs, shard reassembly
+// buffers) can be obtained from a caller-supplied `BufferAllocator` instead of
+// the global allocator, so the library can run inside arena- or pool-allocated
+// server frameworks. Buffers are plain `Vec<u8>`s, which keeps the hook usable
+// on stable Rust without the unstable `allocator_api`.
+//
+// Allocators come in two flavors: `PoolAllocator` is `Send + Sync` and shared
+// by all threads, while `LocalPoolAllocator` keeps its pool in a `RefCell`
+// without locking, for thread-per-core runtimes where each core owns a pool.
+
+use std::cell::RefCell;
+use std::fmt;
+use std::sync::Mutex;
+
+/// A source of byte buffers for the large allocations made by the library.
+///
+/// The trait does not require `Send + Sync`, so single-threaded allocators can
+/// implement it; store a shared allocator as `dyn BufferAllocator + Send + Sync`.
+pub trait BufferAllocator: fmt::Debug {
+    /// Returns an empty buffer with at least `capacity` bytes of capacity.
+    fn allocate(&self, capacity: usize) -> Vec<u8>;
+
+    /// Hands back a buffer that is no longer needed, so it can be reused.
+    ///
+    /// The default implementation simply drops it.
+    fn recycle(&self, buffer: Vec<u8>) {
+        drop(buffer);
+    }
+}
+
+/// Allocates buffers from the global allocator.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemAllocator;
+
+impl BufferAllocator for SystemAllocator {
+    fn allocate(&self, capacity: usize) -> Vec<u8> {
+        Vec::with_capacity(capacity)
+    }
+}
+
+/// Allocates buffers through a caller-supplied callback.
+///
+/// The allocator is `Send + Sync` when the callback is.
+pub struct FnAllocator<F>
+where
+    F: Fn(usize) -> Vec<u8>,
+{
+    allocate_fn: F,
+}
+
+impl<F> FnAllocator<F>
+where
+    F: Fn(usize) -> Vec<u8>,
+{
+    /// Creates an allocator that calls `allocate_fn(capacity)` for every buffer.
+    pub fn new(allocate_fn: F) -> Self {
+        FnAllocator { allocate_fn }
+    }
+}
+
+impl<F> fmt::Debug for FnAllocator<F>
+where
+    F: Fn(usize) -> Vec<u8>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FnAllocator").finish_non_exhaustive()
+    }
+}
+
+impl<F> BufferAllocator for FnAllocator<F>
+where
+    F: Fn(usize) -> Vec<u8>,
+{
+    fn allocate(&self, capacity: usize) -> Vec<u8> {
+        let mut buffer = (self.allocate_fn)(capacity);
+        buffer.clear();
+        buffer.reserve(capacity);
+        buffer
+    }
+}
+
+/// Keeps recycled buffers in a pool and hands them out again.
+#[derive(Debug)]
+pub struct PoolAllocator {
+    /// Recycled buffers, ready for reuse
+    buffers: Mutex<Vec<Vec<u8>>>,
+    /// Maximum number of buffers kept in the pool
+    max_pooled: usize,
+}
+
+impl PoolAllocator {
+    /// Creates a pool that keeps at most `max_pooled` recycled buffers.
+    pub fn new(max_pooled: usize) -> Self {
+        PoolAllocator {
+            buffers: Mutex::new(Vec::new()),
+            max_pooled,
+        }
+    }
+
+    /// Returns the number of buffers currently waiting in the pool.
+    pub fn pooled(&self) -> usize {
+        self.buffers.lock().map(|buffers| buffers.len()).unwrap_or(0)
+    }
+}
+
+impl BufferAllocator for PoolAllocator {
+    fn allocate(&self, capacity: usize) -> Vec<u8> {
+        let reused = self.buffers.lock().ok().and_then(|mut buffers| {
+            // Prefer a buffer that is already large enough
+            match buffers.iter().position(|buffer| buffer.capacity() >= capacity) {
+                Some(index) => Some(buffers.swap_remove(index)),
+                None => buffers.pop(),
+            }
+        });
+
+        match reused {
+            Some(mut buffer) => {
+                buffer.clear();
+                buffer.reserve(capacity);
+                buffer
+            }
+            None => Vec::with_capacity(capacity),
+        }
+    }
+
+    fn recycle(&self, buffer: Vec<u8>) {
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.max_pooled {
+                buffers.push(buffer);
+            }
+        }
+    }
+}
+
+/// Keeps recycled buffers in a pool owned by one thread.
+///
+/// Unlike `PoolAllocator` it takes no lock, and it is neither `Send` nor `Sync`.
+#[derive(Debug)]
+pub struct LocalPoolAllocator {
+    /// Recycled buffers, ready for reuse
+    buffers: RefCell<Vec<Vec<u8>>>,
+    /// Maximum number of buffers kept in the pool
+    max_pooled: usize,
+}
+
+impl LocalPoolAllocator {
+    /// Creates a pool that keeps at most `max_pooled` recycled buffers.
+    pub fn new(max_pooled: usize) -> Self {
+        LocalPoolAllocator {
+            buffers: RefCell::new(Vec::new()),
+            max_pooled,
+        }
+    }
+
+    /// Returns the number of buffers currently waiting in the pool.
+    pub fn pooled(&self) -> usize {
+        self.buffers.borrow().len()
+    }
+}
+
+impl BufferAllocator for LocalPoolAllocator {
+    fn allocate(&self, capacity: usize) -> Vec<u8> {
+        let mut buffers = self.buffers.borrow_mut();
+        let reused = match buffers.iter().position(|buffer| buffer.capacity() >= capacity) {
+            Some(index) => Some(buffers.swap_remove(index)),
+            None => buffers.pop(),
+        };
+
+        let mut buffer = reused.unwrap_or_default();
+        buffer.clear();
+        buffer.reserve(capacity);
+        buffer
+    }
+
+    fn recycle(&self, buffer: Vec<u8>) {
+        let mut buffers = self.buffers.borrow_mut();
+        if buffers.len() < self.max_pooled {
+            buffers.push(buffer);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_system_allocator() {
+        let buffer = SystemAllocator.allocate(128);
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 128);
+    }
+
+    #[test]
+    fn test_fn_allocator_calls_callback() {
+        let calls = AtomicUsize::new(0);
+        let allocator = FnAllocator::new(|capacity| {
+            calls.fetch_add(1, Ordering::SeqCst);
+            vec![0xAA; capacity] // Callbacks may hand out dirty buffers
+        });
+
+        let buffer = allocator.allocate(16);
+        assert!(buffer.is_empty());
+        assert!(buffer.capacity() >= 16);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_pool_allocator_reuses_buffers() {
+        let pool = PoolAllocator::new(1);
+
+        let mut buffer = pool.allocate(64);
+        buffer.extend_from_slice(b"some data");
+        let ptr = buffer.as_ptr();
+        pool.recycle(buffer);
+        assert_eq!(pool.pooled(), 1);
+
+        // The pool is full, extra buffers are dropped
+        pool.recycle(Vec::with_capacity(8));
+        assert_eq!(pool.pooled(), 1);
+
+        let reused = pool.allocate(32);
+        assert!(reused.is_empty());
+        assert_eq!(reused.as_ptr(), ptr);
+        assert_eq!(pool.pooled(), 0);
+    }
+
+    #[test]
+    fn test_local_pool_allocator_reuses_buffers() {
+        use std::rc::Rc;
+
+        // Callbacks of a local allocator may hold single-threaded state
+        let calls = Rc::new(AtomicUsize::new(0));
+        let counter = Rc::clone(&calls);
+        let allocator = FnAllocator::new(move |capacity| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Vec::with_capacity(capacity)
+        });
+        allocator.allocate(4);
+        assert_eq!(calls.load(Ordering::SeqCst), 1);
+
+        let pool = LocalPoolAllocator::new(2);
+        let buffer = pool.allocate(64);
+        let ptr = buffer.as_ptr();
+        pool.recycle(buffer);
+        pool.recycle(Vec::with_capacity(8));
+        pool.recycle(Vec::with_capacity(8));
+        assert_eq!(pool.pooled(), 2);
+
+        let reused = pool.allocate(48);
+        assert_eq!(reused.as_ptr(), ptr);
+        assert_eq!(pool.pooled(), 1);
+    }
+}
@@ -0,0 +1,278 @@
+// Detection of malformed-input spikes per source
+//
+// A service decoding data from many producers wants to notice the one that
+// suddenly sends garbage, whether it is broken or probing the decoder. An
+// `AnomalyDetector` counts decoded items and decode errors per source, a name
+// the application chooses such as a peer address or a tenant, and rate limits
+// errors: once a source produces `max_errors` errors within `window`, the
+// source is flagged and the `AnomalyObserver` is called, so the service can
+// quarantine the producer. The observer is called once per spike; a flagged
+// source is re-armed when its errors fall back below the threshold, or when
+// the application calls `release`.
+//
+// Like `Diagnostics`, the detector is a cheaply clonable handle that the
+// decoders of several connections can share. Only the `max_errors` most recent
+// error times are kept per source, so a flood of errors costs no memory.
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::codec::decode::decode_item;
+use crate::codec::types::HtlvItem;
+use crate::internal::error::Result;
+
+/// Default number of errors within the window flagging a source.
+pub const DEFAULT_MAX_ERRORS: usize = 10;
+/// Default length of the window errors are counted in.
+pub const DEFAULT_WINDOW: Duration = Duration::from_secs(60);
+
+/// Counters of one source.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceCounters {
+    /// Items decoded from the source
+    pub items: u64,
+    /// Decode errors caused by the source
+    pub errors: u64,
+    /// Errors within the current window, at most the threshold
+    pub recent_errors: usize,
+    /// Whether the source is flagged as anomalous
+    pub flagged: bool,
+    /// Message of the last error
+    pub last_error: Option<String>,
+}
+
+/// A source exceeding the error threshold.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Anomaly {
+    /// The source that exceeded the threshold
+    pub source: String,
+    /// Number of errors that triggered the anomaly
+    pub errors: usize,
+    /// Window the errors occurred in
+    pub window: Duration,
+    /// Message of the error that crossed the threshold
+    pub last_error: String,
+}
+
+/// Receives anomalies.
+pub trait AnomalyObserver: Send + Sync {
+    /// Called once when a source exceeds the error threshold.
+    fn on_anomaly(&self, anomaly: &Anomaly);
+}
+
+impl<F: Fn(&Anomaly) + Send + Sync> AnomalyObserver for F {
+    fn on_anomaly(&self, anomaly: &Anomaly) {
+        self(anomaly)
+    }
+}
+
+#[derive(Debug, Default)]
+struct SourceState {
+    counters: SourceCounters,
+    error_times: VecDeque<Instant>,
+}
+
+/// A shared detector of decode error spikes per source.
+#[derive(Clone)]
+pub struct AnomalyDetector {
+    max_errors: usize,
+    window: Duration,
+    sources: Arc<Mutex<HashMap<String, SourceState>>>,
+    observer: Option<Arc<dyn AnomalyObserver>>,
+}
+
+impl fmt::Debug for AnomalyDetector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnomalyDetector")
+            .field("max_errors", &self.max_errors)
+            .field("window", &self.window)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for AnomalyDetector {
+    fn default() -> Self {
+        Self::new(DEFAULT_MAX_ERRORS, DEFAULT_WINDOW)
+    }
+}
+
+impl AnomalyDetector {
+    /// Creates a detector flagging sources with `max_errors` errors within
+    /// `window`. A threshold of zero is treated as one.
+    pub fn new(max_errors: usize, window: Duration) -> Self {
+        AnomalyDetector {
+            max_errors: max_errors.max(1),
+            window,
+            sources: Arc::new(Mutex::new(HashMap::new())),
+            observer: None,
+        }
+    }
+
+    /// Calls the observer whenever a source is flagged.
+    pub fn with_observer(mut self, observer: impl AnomalyObserver + 'static) -> Self {
+        self.observer = Some(Arc::new(observer));
+        self
+    }
+
+    /// Decodes bytes like `decode_item`, recording the outcome for the source.
+    pub fn decode(&self, source: &str, data: &[u8]) -> Result<(HtlvItem, usize)> {
+        let result = decode_item(data);
+        match &result {
+            Ok(_) => self.record_item(source),
+            Err(err) => {
+                self.record_error(source, &err.to_string());
+            }
+        }
+        result
+    }
+
+    /// Records an item decoded from the source.
+    pub fn record_item(&self, source: &str) {
+        if let Ok(mut sources) = self.sources.lock() {
+            sources.entry(source.to_string()).or_default().counters.items += 1;
+        }
+    }
+
+    /// Records a decode error caused by the source. Returns true if the source
+    /// is flagged after this error.
+    pub fn record_error(&self, source: &str, message: &str) -> bool {
+        let now = Instant::now();
+        let anomaly = {
+            let Ok(mut sources) = self.sources.lock() else {
+                return false;
+            };
+            let state = sources.entry(source.to_string()).or_default();
+            self.expire(state, now);
+            if state.error_times.len() == self.max_errors {
+                state.error_times.pop_front();
+            }
+            state.error_times.push_back(now);
+            let counters = &mut state.counters;
+            counters.errors += 1;
+            counters.recent_errors = state.error_times.len();
+            counters.last_error = Some(message.to_string());
+            if counters.flagged || counters.recent_errors < self.max_errors {
+                return counters.flagged;
+            }
+            counters.flagged = true;
+            Anomaly {
+                source: source.to_string(),
+                errors: counters.recent_errors,
+                window: self.window,
+                last_error: message.to_string(),
+            }
+        };
+        // The observer runs without the lock, so it may query the detector
+        if let Some(observer) = &self.observer {
+            observer.on_anomaly(&anomaly);
+        }
+        true
+    }
+
+    /// Returns the counters of the source, or `None` if nothing was recorded for it.
+    pub fn counters(&self, source: &str) -> Option<SourceCounters> {
+        let mut sources = self.sources.lock().ok()?;
+        let state = sources.get_mut(source)?;
+        self.expire(state, Instant::now());
+        Some(state.counters.clone())
+    }
+
+    /// Returns true if the source is flagged as anomalous.
+    pub fn is_flagged(&self, source: &str) -> bool {
+        self.counters(source).is_some_and(|counters| counters.flagged)
+    }
+
+    /// Returns the sources currently flagged.
+    pub fn flagged_sources(&self) -> Vec<String> {
+        let Ok(mut sources) = self.sources.lock() else {
+            return Vec::new();
+        };
+        let now = Instant::now();
+        sources
+            .iter_mut()
+            .filter_map(|(source, state)| {
+                self.expire(state, now);
+                state.counters.flagged.then(|| source.clone())
+            })
+            .collect()
+    }
+
+    /// Clears the recent errors of the source and unflags it, for instance once
+    /// its producer has been fixed. Its totals are kept.
+    pub fn release(&self, source: &str) {
+        if let Ok(mut sources) = self.sources.lock() {
+            if let Some(state) = sources.get_mut(source) {
+                state.error_times.clear();
+                state.counters.recent_errors = 0;
+                state.counters.flagged = false;
+            }
+        }
+    }
+
+    /// Stops tracking the source and returns its counters.
+    pub fn remove(&self, source: &str) -> Option<SourceCounters> {
+        self.sources.lock().ok()?.remove(source).map(|state| state.counters)
+    }
+
+    /// Drops the errors that left the window, re-arming a source back below
+    /// the threshold.
+    fn expire(&self, state: &mut SourceState, now: Instant) {
+        while state.error_times.front().is_some_and(|&time| now.duration_since(time) > self.window) {
+            state.error_times.pop_front();
+        }
+        state.counters.recent_errors = state.error_times.len();
+        if state.counters.recent_errors < self.max_errors {
+            state.counters.flagged = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::encode::encode_item;
+    use crate::codec::types::HtlvValue;
+
+    #[test]
+    fn test_spike_flags_source_once() {
+        let anomalies = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&anomalies);
+        let detector = AnomalyDetector::new(3, Duration::from_secs(3600))
+            .with_observer(move |anomaly: &Anomaly| sink.lock().unwrap().push(anomaly.clone()));
+        let valid = encode_item(&HtlvItem::new(1, HtlvValue::Bool(true))).unwrap();
+
+        assert!(detector.decode("good", &valid).is_ok());
+        for _ in 0..5 {
+            assert!(detector.decode("bad", &valid[..valid.len() - 1]).is_err());
+        }
+        assert!(detector.is_flagged("bad"));
+        assert!(!detector.is_flagged("good"));
+        assert_eq!(detector.flagged_sources(), vec!["bad".to_string()]);
+
+        let anomalies = anomalies.lock().unwrap().clone();
+        assert_eq!(anomalies.len(), 1);
+        assert_eq!((anomalies[0].source.as_str(), anomalies[0].errors), ("bad", 3));
+
+        let counters = detector.counters("bad").unwrap();
+        assert_eq!((counters.items, counters.errors, counters.recent_errors), (0, 5, 3));
+        detector.release("bad");
+        assert!(!detector.is_flagged("bad"));
+        assert_eq!(detector.counters("bad").unwrap().errors, 5);
+        assert_eq!(detector.counters("good").unwrap().items, 1);
+    }
+
+    #[test]
+    fn test_errors_expire_from_window() {
+        let detector = AnomalyDetector::new(2, Duration::from_millis(10));
+        assert!(!detector.record_error("peer", "Codec Error: truncated"));
+        assert!(detector.record_error("peer", "Codec Error: truncated"));
+
+        std::thread::sleep(Duration::from_millis(20));
+        let counters = detector.counters("peer").unwrap();
+        assert_eq!((counters.recent_errors, counters.flagged), (0, false));
+        assert!(!detector.record_error("peer", "Codec Error: truncated"));
+        assert_eq!(detector.remove("peer").unwrap().errors, 3);
+    }
+}
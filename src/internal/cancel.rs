@@ -0,0 +1,71 @@
+// Cooperative cancellation of long-running operations
+//
+// A `CancellationToken` is a cheaply clonable flag shared between the code
+// running an operation and the code that may abort it, such as a server
+// noticing that the client of a request has disconnected. Decoding, sharded
+// compression, field-level encryption and the packet builder check the token
+// between state transitions, shards, pieces and fields, and fail with
+// `Error::Cancelled` once it is set. Work already in progress for the current
+// step is finished first, so cancellation takes effect within one step.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use crate::internal::error::{Error, Result};
+
+/// A shared flag requesting that an operation stop.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    /// Creates a token that is not cancelled.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Requests cancellation of every operation holding a clone of the token.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    /// Returns true once cancellation has been requested.
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    /// Fails with `Error::Cancelled` if cancellation has been requested.
+    pub fn check(&self, what: &str) -> Result<()> {
+        if self.is_cancelled() {
+            return Err(Error::Cancelled(format!("{} was cancelled", what)));
+        }
+        Ok(())
+    }
+}
+
+/// Checks an optional token, for operations where cancellation is opt-in.
+pub(crate) fn check(token: &Option<CancellationToken>, what: &str) -> Result<()> {
+    match token {
+        Some(token) => token.check(what),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_clones_share_the_flag() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(clone.check("Decoding").is_ok());
+
+        token.cancel();
+        assert!(clone.is_cancelled());
+        let err = clone.check("Decoding").unwrap_err();
+        assert_eq!(err.to_string(), "Cancelled: Decoding was cancelled");
+        assert!(check(&None, "Decoding").is_ok());
+    }
+}
@@ -0,0 +1,166 @@
+// Packet checksum algorithms
+//
+// Every packet ends with a checksum over its header and body. BLAKE3 is the
+// default; deployments with other requirements pick the algorithm per packet
+// and it is recorded in the header flow_flags, so receivers verify whatever
+// each sender chose:
+//
+//   BLAKE3   32 bytes, cryptographic, the default and the format before the
+//            algorithm was recorded
+//   CRC32C   4 bytes, detects transmission errors only, for links where every
+//            byte of trailer counts
+//   XXH3     16 bytes (XXH3-128), detects corruption only, very fast
+//   SHA-256  32 bytes, cryptographic, for deployments restricted to FIPS
+//            approved algorithms; computed by AWS-LC with the `fips` feature
+//
+// Signatures sign the checksum, so signed packets need a cryptographic one.
+
+use crate::internal::error::{Error, Result};
+#[cfg(not(feature = "fips"))]
+use sha2::{Digest, Sha256};
+use std::fmt::Debug;
+
+/// Computes packet checksums.
+///
+/// Checksummers are `Send + Sync`, so a boxed checksummer can be shared by the
+/// threads of a server.
+pub trait Checksummer: Debug + Send + Sync {
+    /// Returns the algorithm recorded in the headers of the packets checksummed.
+    fn algorithm(&self) -> ChecksumAlgorithm;
+
+    /// Computes the checksum of the concatenation of the parts.
+    fn checksum(&self, parts: &[&[u8]]) -> Vec<u8>;
+}
+
+/// Defines the checksum algorithm of a packet.
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, Default)]
+#[repr(u8)] // Ensure enum variants have a fixed u8 representation
+pub enum ChecksumAlgorithm {
+    #[default]
+    Blake3 = 0,
+    Crc32c = 1,
+    Xxh3 = 2,
+    Sha256 = 3,
+}
+
+impl ChecksumAlgorithm {
+    /// Converts the bits stored in a header back to an algorithm.
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ChecksumAlgorithm::Blake3),
+            1 => Ok(ChecksumAlgorithm::Crc32c),
+            2 => Ok(ChecksumAlgorithm::Xxh3),
+            3 => Ok(ChecksumAlgorithm::Sha256),
+            _ => Err(Error::CodecError(format!("Unknown checksum algorithm: {}", value))),
+        }
+    }
+
+    /// Returns the length of the checksum in bytes.
+    pub fn digest_len(&self) -> usize {
+        match self {
+            ChecksumAlgorithm::Blake3 | ChecksumAlgorithm::Sha256 => 32,
+            ChecksumAlgorithm::Crc32c => 4,
+            ChecksumAlgorithm::Xxh3 => 16,
+        }
+    }
+
+    /// Returns true if finding two inputs with the same checksum is
+    /// infeasible, which signatures over the checksum rely on.
+    pub fn is_cryptographic(&self) -> bool {
+        matches!(self, ChecksumAlgorithm::Blake3 | ChecksumAlgorithm::Sha256)
+    }
+
+    /// Returns true if the algorithm is approved under FIPS 140.
+    pub fn is_fips_approved(&self) -> bool {
+        *self == ChecksumAlgorithm::Sha256
+    }
+}
+
+/// BLAKE3 checksums
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Blake3Checksummer;
+
+impl Checksummer for Blake3Checksummer {
+    fn algorithm(&self) -> ChecksumAlgorithm {
+        ChecksumAlgorithm::Blake3
+    }
+
+    fn checksum(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = blake3::Hasher::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().as_bytes().to_vec()
+    }
+}
+
+/// CRC32C (Castagnoli) checksums, little endian
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc32cChecksummer;
+
+impl Checksummer for Crc32cChecksummer {
+    fn algorithm(&self) -> ChecksumAlgorithm {
+        ChecksumAlgorithm::Crc32c
+    }
+
+    fn checksum(&self, parts: &[&[u8]]) -> Vec<u8> {
+        parts.iter().fold(0, |crc, part| crc32c::crc32c_append(crc, part)).to_le_bytes().to_vec()
+    }
+}
+
+/// XXH3-128 checksums, little endian
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Xxh3Checksummer;
+
+impl Checksummer for Xxh3Checksummer {
+    fn algorithm(&self) -> ChecksumAlgorithm {
+        ChecksumAlgorithm::Xxh3
+    }
+
+    fn checksum(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = xxhash_rust::xxh3::Xxh3::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.digest128().to_le_bytes().to_vec()
+    }
+}
+
+/// SHA-256 checksums
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Sha256Checksummer;
+
+impl Checksummer for Sha256Checksummer {
+    fn algorithm(&self) -> ChecksumAlgorithm {
+        ChecksumAlgorithm::Sha256
+    }
+
+    #[cfg(not(feature = "fips"))]
+    fn checksum(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut hasher = Sha256::new();
+        for part in parts {
+            hasher.update(part);
+        }
+        hasher.finalize().to_vec()
+    }
+
+    // In FIPS mode, digests come from the validated AWS-LC module
+    #[cfg(feature = "fips")]
+    fn checksum(&self, parts: &[&[u8]]) -> Vec<u8> {
+        let mut context = aws_lc_rs::digest::Context::new(&aws_lc_rs::digest::SHA256);
+        for part in parts {
+            context.update(part);
+        }
+        context.finish().as_ref().to_vec()
+    }
+}
+
+/// Returns a Checksummer implementation for the given algorithm.
+pub fn get_checksummer(algorithm: ChecksumAlgorithm) -> Box<dyn Checksummer> {
+    match algorithm {
+        ChecksumAlgorithm::Blake3 => Box::new(Blake3Checksummer),
+        ChecksumAlgorithm::Crc32c => Box::new(Crc32cChecksummer),
+        ChecksumAlgorithm::Xxh3 => Box::new(Xxh3Checksummer),
+        ChecksumAlgorithm::Sha256 => Box::new(Sha256Checksummer),
+    }
+}
@@ -0,0 +1,185 @@
+// Bounds-checked reading of wire data
+//
+// Offsets and lengths in packets, items and compressed containers come from
+// untrusted input, so adding them up with plain `usize` arithmetic can wrap or,
+// on 32-bit targets, silently truncate a 64-bit length. `WireCursor` reads
+// fields from a byte slice with checked arithmetic only: a length that does
+// not fit in `usize`, or whose end overflows, fails with
+// `Error::LengthOverflow`, and reading past the end of the data fails with
+// `Error::UnexpectedEof`, or an "Incomplete data" error of the kind chosen by
+// the caller.
+
+use crate::codec::varint;
+use crate::internal::error::{Error, Result};
+
+/// Reads fields from a byte slice, checking every offset and length.
+#[derive(Debug, Clone)]
+pub struct WireCursor<'a> {
+    data: &'a [u8],
+    position: usize,
+    truncated: Option<fn(String) -> Error>,
+}
+
+impl<'a> WireCursor<'a> {
+    /// Creates a cursor at the start of `data`; truncation is an `UnexpectedEof`.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self::at(data, 0)
+    }
+
+    /// Creates a cursor at `position` in `data`; truncation is an `UnexpectedEof`.
+    pub fn at(data: &'a [u8], position: usize) -> Self {
+        WireCursor { data, position: position.min(data.len()), truncated: None }
+    }
+
+    /// Sets the error variant reported when the data ends too early.
+    pub fn with_truncation_error(mut self, truncated: fn(String) -> Error) -> Self {
+        self.truncated = Some(truncated);
+        self
+    }
+
+    /// Returns the error for a field at the current offset that needs more
+    /// bytes than are left.
+    fn truncated(&self, what: &str, needed: u64, got: usize) -> Error {
+        match self.truncated {
+            Some(truncated) => truncated(format!(
+                "Incomplete data for {} at offset {}: expected {} bytes, got {}",
+                what, self.position, needed, got
+            )),
+            None => Error::UnexpectedEof { what: what.to_string(), offset: self.position, needed, got },
+        }
+    }
+
+    /// Returns the current offset in the data.
+    pub fn position(&self) -> usize {
+        self.position
+    }
+
+    /// Returns the number of bytes left after the current offset.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.position
+    }
+
+    /// Returns true if no bytes are left.
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Returns the bytes after the current offset without consuming them.
+    pub fn rest(&self) -> &'a [u8] {
+        &self.data[self.position..]
+    }
+
+    /// Returns the offset `length` bytes after the current one, checking that
+    /// it neither overflows nor lies past the end of the data.
+    pub fn end_of(&self, length: u64, what: &str) -> Result<usize> {
+        let end = usize::try_from(length)
+            .ok()
+            .and_then(|length| self.position.checked_add(length))
+            .ok_or_else(|| Error::LengthOverflow { what: what.to_string(), offset: self.position, length })?;
+        if end > self.data.len() {
+            return Err(self.truncated(what, length, self.remaining()));
+        }
+        Ok(end)
+    }
+
+    /// Reads the next `length` bytes.
+    pub fn take(&mut self, length: u64, what: &str) -> Result<&'a [u8]> {
+        let end = self.end_of(length, what)?;
+        let slice = &self.data[self.position..end];
+        self.position = end;
+        Ok(slice)
+    }
+
+    /// Skips the next `length` bytes.
+    pub fn skip(&mut self, length: u64, what: &str) -> Result<()> {
+        self.take(length, what).map(|_| ())
+    }
+
+    /// Reads the next `N` bytes as an array.
+    pub fn read_array<const N: usize>(&mut self, what: &str) -> Result<[u8; N]> {
+        let bytes = self.take(N as u64, what)?;
+        Ok(bytes.try_into().expect("take returns N bytes"))
+    }
+
+    /// Reads a byte.
+    pub fn read_u8(&mut self, what: &str) -> Result<u8> {
+        Ok(self.read_array::<1>(what)?[0])
+    }
+
+    /// Reads a little-endian u32.
+    pub fn read_u32_le(&mut self, what: &str) -> Result<u32> {
+        self.read_array(what).map(u32::from_le_bytes)
+    }
+
+    /// Reads a little-endian u64.
+    pub fn read_u64_le(&mut self, what: &str) -> Result<u64> {
+        self.read_array(what).map(u64::from_le_bytes)
+    }
+
+    /// Reads a varint.
+    pub fn read_varint(&mut self, what: &str) -> Result<u64> {
+        let (value, length) = varint::decode_varint(self.rest()).map_err(|e| match (e, self.truncated) {
+            (e, Some(truncated)) => truncated(format!("Failed to decode {} varint: {}", what, e)),
+            (Error::UnexpectedEof { needed, got, .. }, None) => self.truncated(what, needed, got),
+            (_, None) => Error::InvalidVarint { what: what.to_string(), offset: self.position },
+        })?;
+        self.position += length;
+        Ok(value)
+    }
+
+    /// Reads a varint length and then that many bytes.
+    pub fn read_length_prefixed(&mut self, what: &str) -> Result<&'a [u8]> {
+        let length = self.read_varint(what)?;
+        self.take(length, what)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reads_fields_in_order() {
+        let mut data = vec![7];
+        data.extend_from_slice(&0xdead_beefu32.to_le_bytes());
+        data.extend_from_slice(&varint::encode_varint(300));
+        data.extend_from_slice(&[2, b'o', b'k']);
+
+        let mut cursor = WireCursor::new(&data);
+        assert_eq!(cursor.read_u8("kind").unwrap(), 7);
+        assert_eq!(cursor.read_u32_le("flags").unwrap(), 0xdead_beef);
+        assert_eq!(cursor.read_varint("count").unwrap(), 300);
+        assert_eq!(cursor.read_length_prefixed("name").unwrap(), b"ok");
+        assert!(cursor.is_empty());
+    }
+
+    #[test]
+    fn test_overflowing_and_truncated_lengths() {
+        let data = [0u8; 4];
+        let mut cursor = WireCursor::at(&data, 2);
+
+        // A length whose end overflows is a typed error, whatever the target width
+        match cursor.take(u64::MAX, "value") {
+            Err(Error::LengthOverflow { what, offset, length }) => {
+                assert_eq!((what.as_str(), offset, length), ("value", 2, u64::MAX));
+            }
+            other => panic!("expected a length overflow, got {:?}", other),
+        }
+
+        let err = cursor.take(3, "value").unwrap_err();
+        assert_eq!(err.to_string(), "Unexpected End Of Data: value at offset 2 needs 3 bytes, got 2");
+        assert!(err.is_truncation());
+        let err = cursor.clone().with_truncation_error(Error::CompressionError).read_u64_le("size").unwrap_err();
+        assert_eq!(err.to_string(), "Compression Error: Incomplete data for size at offset 2: expected 8 bytes, got 2");
+
+        // A varint cut short may be completed; one that never ends may not
+        let err = WireCursor::at(&[0x80, 0x80], 1).read_varint("count").unwrap_err();
+        assert!(matches!(err, Error::UnexpectedEof { offset: 1, needed: 2, got: 1, .. }), "{:?}", err);
+        let err = WireCursor::at(&[0xff; 12], 1).read_varint("count").unwrap_err();
+        assert!(matches!(err, Error::InvalidVarint { offset: 1, .. }) && !err.is_truncation(), "{:?}", err);
+
+        // Failed reads leave the cursor where it was
+        assert_eq!(cursor.position(), 2);
+        assert_eq!(cursor.take(2, "value").unwrap(), &[0, 0]);
+    }
+}
@@ -0,0 +1,87 @@
+// Time budgets for processing a packet
+//
+// A `Deadline` bounds the wall-clock time an operation may take, so that a
+// pathological input, such as a deeply nested body or one that decompresses
+// to many small items, cannot hold up a connection beyond its latency target.
+// Like a `CancellationToken` it is checked between steps rather than by
+// interrupting them: decoding checks it at every state transition, and the
+// packet reader between decryption, decompression and decoding. Once the
+// budget is spent the next check fails with `Error::DeadlineExceeded`.
+
+use std::time::{Duration, Instant};
+
+use crate::internal::error::{Error, Result};
+
+/// A point in time by which an operation must finish.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Deadline {
+    start: Instant,
+    budget: Duration,
+}
+
+impl Deadline {
+    /// Creates a deadline `budget` from now.
+    pub fn after(budget: Duration) -> Self {
+        Deadline { start: Instant::now(), budget }
+    }
+
+    /// Returns the budget the deadline was created with.
+    pub fn budget(&self) -> Duration {
+        self.budget
+    }
+
+    /// Returns the time spent since the deadline was created.
+    pub fn elapsed(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    /// Returns the time left before the deadline, zero once it has passed.
+    pub fn remaining(&self) -> Duration {
+        self.budget.saturating_sub(self.elapsed())
+    }
+
+    /// Returns true once the budget is spent.
+    pub fn is_expired(&self) -> bool {
+        self.elapsed() >= self.budget
+    }
+
+    /// Fails with `Error::DeadlineExceeded` if the budget is spent.
+    pub fn check(&self, stage: &str) -> Result<()> {
+        let elapsed = self.elapsed();
+        if elapsed >= self.budget {
+            return Err(Error::DeadlineExceeded { stage: stage.to_string(), elapsed, budget: self.budget });
+        }
+        Ok(())
+    }
+}
+
+/// Checks an optional deadline, for operations where a budget is opt-in.
+pub(crate) fn check(deadline: &Option<Deadline>, stage: &str) -> Result<()> {
+    match deadline {
+        Some(deadline) => deadline.check(stage),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_fails_once_budget_is_spent() {
+        let deadline = Deadline::after(Duration::from_secs(3600));
+        assert!(deadline.check("Decoding").is_ok());
+        assert!(deadline.remaining() <= deadline.budget());
+        assert!(check(&None, "Decoding").is_ok());
+
+        let expired = Deadline::after(Duration::ZERO);
+        assert!(expired.is_expired());
+        assert_eq!(expired.remaining(), Duration::ZERO);
+        match check(&Some(expired), "Decompression") {
+            Err(Error::DeadlineExceeded { stage, budget, .. }) => {
+                assert_eq!((stage.as_str(), budget), ("Decompression", Duration::ZERO));
+            }
+            other => panic!("expected an exceeded deadline, got {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,165 @@
+// Diagnostics for non-fatal conditions
+//
+// A `Diagnostics` sink is a cheaply clonable handle that decoding, validation
+// and compression report soft problems into: conditions that do not fail the
+// operation but that an application may want to surface, such as a deprecated
+// field still being sent or compression that would have expanded the data.
+// The sink keeps a bounded number of entries and counts the ones it dropped.
+
+use std::fmt;
+use std::sync::{Arc, Mutex};
+
+/// Default number of diagnostics kept by a sink.
+pub const DEFAULT_MAX_DIAGNOSTICS: usize = 1024;
+
+/// The operation that reported a diagnostic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DiagnosticSource {
+    /// Decoding of HTLV data
+    Decode,
+    /// Schema validation
+    Validate,
+    /// Compression
+    Compress,
+}
+
+/// Kind of non-fatal condition.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A deprecated field is still being sent
+    DeprecatedField,
+    /// A field is present outside the schema versions that define it
+    FieldOutsideLifecycle,
+    /// Compressing the data would have expanded it, so it was stored as-is
+    CompressionExpanded {
+        /// Size of the data before compression
+        original: usize,
+        /// Size the compressed data would have had
+        compressed: usize,
+    },
+    /// The data was already compressed, so compression was skipped
+    AlreadyCompressed,
+    /// Batch data was not aligned for its element type and had to be copied
+    /// before decoding
+    UnalignedFallback {
+        /// Size of the batch in bytes
+        length: usize,
+    },
+}
+
+/// A non-fatal condition reported during an operation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    /// The operation that reported the condition
+    pub source: DiagnosticSource,
+    /// What was found
+    pub kind: DiagnosticKind,
+    /// Human-readable description
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}: {}", self.source, self.message)
+    }
+}
+
+#[derive(Debug)]
+struct DiagnosticsInner {
+    entries: Vec<Diagnostic>,
+    max_entries: usize,
+    dropped: usize,
+}
+
+/// A shared sink collecting diagnostics.
+#[derive(Debug, Clone)]
+pub struct Diagnostics {
+    inner: Arc<Mutex<DiagnosticsInner>>,
+}
+
+impl Default for Diagnostics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Diagnostics {
+    /// Creates a sink keeping up to `DEFAULT_MAX_DIAGNOSTICS` entries.
+    pub fn new() -> Self {
+        Self::with_max_entries(DEFAULT_MAX_DIAGNOSTICS)
+    }
+
+    /// Creates a sink keeping up to `max_entries` entries; later ones are only counted.
+    pub fn with_max_entries(max_entries: usize) -> Self {
+        Diagnostics {
+            inner: Arc::new(Mutex::new(DiagnosticsInner {
+                entries: Vec::new(),
+                max_entries,
+                dropped: 0,
+            })),
+        }
+    }
+
+    /// Records a diagnostic.
+    pub fn report(&self, source: DiagnosticSource, kind: DiagnosticKind, message: impl Into<String>) {
+        if let Ok(mut inner) = self.inner.lock() {
+            if inner.entries.len() < inner.max_entries {
+                inner.entries.push(Diagnostic { source, kind, message: message.into() });
+            } else {
+                inner.dropped += 1;
+            }
+        }
+    }
+
+    /// Returns a copy of the diagnostics recorded so far.
+    pub fn entries(&self) -> Vec<Diagnostic> {
+        self.inner.lock().map(|inner| inner.entries.clone()).unwrap_or_default()
+    }
+
+    /// Removes and returns the diagnostics recorded so far, resetting the dropped count.
+    pub fn take(&self) -> Vec<Diagnostic> {
+        match self.inner.lock() {
+            Ok(mut inner) => {
+                inner.dropped = 0;
+                std::mem::take(&mut inner.entries)
+            }
+            Err(_) => Vec::new(),
+        }
+    }
+
+    /// Returns the number of diagnostics recorded.
+    pub fn len(&self) -> usize {
+        self.inner.lock().map(|inner| inner.entries.len()).unwrap_or(0)
+    }
+
+    /// Returns true if no diagnostics have been recorded.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of diagnostics that were not kept because the sink was full.
+    pub fn dropped(&self) -> usize {
+        self.inner.lock().map(|inner| inner.dropped).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sink_is_shared_and_bounded() {
+        let diagnostics = Diagnostics::with_max_entries(2);
+        let handle = diagnostics.clone();
+        for length in 0..3 {
+            handle.report(DiagnosticSource::Decode, DiagnosticKind::UnalignedFallback { length }, "unaligned");
+        }
+
+        assert_eq!(diagnostics.len(), 2);
+        assert_eq!(diagnostics.dropped(), 1);
+        assert_eq!(diagnostics.entries()[1].kind, DiagnosticKind::UnalignedFallback { length: 1 });
+        assert_eq!(diagnostics.take().len(), 2);
+        assert!(handle.is_empty());
+        assert_eq!(handle.dropped(), 0);
+    }
+}
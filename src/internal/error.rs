@@ -1,5 +1,7 @@
 use thiserror::Error;
 use std::io; // Import std::io
+use std::time::Duration;
+use crate::internal::memory::MemorySubsystem;
 
 /// Unified error type for the Tonitru library.
 #[derive(Error, Debug)]
@@ -40,13 +42,151 @@ pub enum Error {
     #[error("Wasm Error: {0}")]
     WasmError(String),
 
+    /// Error related to configuration.
+    #[error("Config Error: {0}")]
+    ConfigError(String),
+
     /// Error related to internal utilities or distributed components.
     #[error("Internal Error: {0}")]
     InternalError(String),
 
+    /// A memory reservation would exceed the configured budget.
+    #[error("Memory Limit Exceeded: {subsystem} requested {requested} bytes with {used} of {limit} bytes in use")]
+    MemoryLimitExceeded {
+        /// The subsystem that requested the memory
+        subsystem: MemorySubsystem,
+        /// The number of bytes requested
+        requested: usize,
+        /// The number of bytes already in use when the request was made
+        used: usize,
+        /// The limit that would have been exceeded
+        limit: usize,
+    },
+
+    /// A length read from the wire does not fit in `usize`, or adding it to
+    /// the current offset overflows.
+    #[error("Length Overflow: {what} of {length} bytes at offset {offset} does not fit in memory")]
+    LengthOverflow {
+        /// The field whose length overflowed
+        what: String,
+        /// The offset at which the field starts
+        offset: usize,
+        /// The length read from the wire
+        length: u64,
+    },
+
+    /// The data ends before a field it announces. Unlike the other decoding
+    /// errors, this one may be cured by waiting for more bytes.
+    ///
+    /// Within an array or object whose whole value is present, running out of
+    /// data is corruption instead, reported as `ExceedsContainer`.
+    #[error("Unexpected End Of Data: {what} at offset {offset} needs {needed} bytes, got {got}")]
+    UnexpectedEof {
+        /// The field being read
+        what: String,
+        /// The offset at which the field starts
+        offset: usize,
+        /// The number of bytes the field needs
+        needed: u64,
+        /// The number of bytes available
+        got: usize,
+    },
+
+    /// An item header names a value type that does not exist.
+    #[error("Unknown Type: value type tag {type_byte} at offset {offset}")]
+    UnknownType {
+        /// The type byte read from the wire
+        type_byte: u8,
+        /// The offset of the type byte
+        offset: usize,
+    },
+
+    /// Arrays and objects are nested deeper than the decoder allows.
+    #[error("Depth Exceeded: value at offset {offset} is nested deeper than {limit} levels")]
+    DepthExceeded {
+        /// The offset of the array or object value one level too deep
+        offset: usize,
+        /// The nesting depth limit
+        limit: usize,
+    },
+
+    /// An item extends past the end of the array or object holding it.
+    #[error("Exceeds Container: {what} at offset {offset} extends past the end of its container")]
+    ExceedsContainer {
+        /// The field that does not fit
+        what: String,
+        /// The offset at which the field starts
+        offset: usize,
+    },
+
+    /// Bytes are left after the decoded item, which strict decoding rejects.
+    #[error("Trailing Data: {remaining} bytes after the item ending at offset {offset}")]
+    TrailingData {
+        /// The offset at which the item ends
+        offset: usize,
+        /// The number of bytes after it
+        remaining: usize,
+    },
+
+    /// A varint does not fit in 64 bits.
+    #[error("Invalid Varint: {what} at offset {offset} does not fit in 64 bits")]
+    InvalidVarint {
+        /// The field encoded as a varint
+        what: String,
+        /// The offset at which the varint starts
+        offset: usize,
+    },
+
+    /// The operation was aborted through its cancellation token.
+    #[error("Cancelled: {0}")]
+    Cancelled(String),
+
+    /// The time budget of an operation was spent before it finished.
+    #[error("Deadline Exceeded: {stage} after {elapsed:?} with a budget of {budget:?}")]
+    DeadlineExceeded {
+        /// The stage that was running when the budget ran out
+        stage: String,
+        /// The time spent when the deadline was checked
+        elapsed: Duration,
+        /// The budget that was exceeded
+        budget: Duration,
+    },
+
     // TODO: Add more specific error types as modules are implemented
 }
 
+impl Error {
+    /// Turns a truncation within an array or object whose whole value is
+    /// present into the corruption it is; other errors are kept.
+    pub(crate) fn within_container(self) -> Self {
+        match self {
+            Error::UnexpectedEof { what, offset, .. } => Error::ExceedsContainer { what, offset },
+            other => other,
+        }
+    }
+
+    /// Returns true if the data was cut short rather than corrupt, so that
+    /// decoding it again once more bytes arrived may succeed.
+    pub fn is_truncation(&self) -> bool {
+        matches!(self, Error::UnexpectedEof { .. })
+    }
+
+    /// Returns the offset into the input at which decoding failed, for the
+    /// errors that record one.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            Error::UnexpectedEof { offset, .. }
+            | Error::UnknownType { offset, .. }
+            | Error::DepthExceeded { offset, .. }
+            | Error::ExceedsContainer { offset, .. }
+            | Error::TrailingData { offset, .. }
+            | Error::InvalidVarint { offset, .. }
+            | Error::LengthOverflow { offset, .. } => Some(*offset),
+            _ => None,
+        }
+    }
+}
+
 /// A specialized `Result` type for Tonitru operations.
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -0,0 +1,358 @@
+// Async packet framing over tokio streams
+//
+// A stream carries a sequence of frames, each a packet encoded with
+// `Packet::encode_packet` preceded by its length as a little-endian u32.
+// `PacketReader` buffers whatever the stream delivers, so frames split over
+// several reads or coalesced into one read are both handled, and verifies the
+// checksum of every packet. Reading is cancel safe: a `read_packet` future
+// dropped before completion loses no data.
+//
+// `read_item` also opens the body of each packet: it decrypts, decompresses and
+// decodes it as recorded in the header. An optional per-packet time budget
+// bounds that work, so that a pathological packet fails with
+// `Error::DeadlineExceeded` instead of stalling the connection.
+//
+// A reader with a `ProtocolEventLog` attached emits an event for every frame it
+// receives and every checksum failure. One with a `MetricsRecorder` records the
+// time spent verifying checksums and opening bodies.
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::codec::types::HtlvItem;
+use crate::encrypt::{EncryptionStrategy, Encryptor};
+use crate::internal::deadline::Deadline;
+use crate::internal::error::{Error, Result};
+use crate::internal::metrics::MetricsRecorder;
+use crate::internal::packet::{Packet, CHECKSUM_FAILED};
+use crate::pipeline::open_item;
+use crate::protocol::events::{self, ProtocolEvent, ProtocolEventLog};
+use bytes::{Buf, BytesMut};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Size of the length prefix of a frame
+pub const FRAME_HEADER_LEN: usize = 4;
+
+/// Configuration for packet framing
+#[derive(Debug, Clone)]
+pub struct FramingConfig {
+    /// Largest accepted packet, in bytes, excluding the length prefix
+    pub max_frame_size: usize,
+    /// Initial capacity of the read buffer
+    pub read_buffer_size: usize,
+    /// Time allowed to verify, decrypt, decompress and decode one packet in
+    /// `read_item`, counted from the arrival of its last byte; None for no limit
+    pub packet_budget: Option<Duration>,
+}
+
+impl Default for FramingConfig {
+    fn default() -> Self {
+        Self {
+            max_frame_size: 16 * 1024 * 1024, // 16 MiB
+            read_buffer_size: 8 * 1024,
+            packet_budget: None,
+        }
+    }
+}
+
+/// Reads length-prefixed packets from an async stream.
+#[derive(Debug)]
+pub struct PacketReader<R> {
+    reader: R,
+    config: FramingConfig,
+    buffer: BytesMut,
+    frames_read: u64,
+    frames_received: u64,
+    events: Option<ProtocolEventLog>,
+    metrics: Option<MetricsRecorder>,
+}
+
+impl<R: AsyncRead + Unpin> PacketReader<R> {
+    /// Creates a reader with the default configuration.
+    pub fn new(reader: R) -> Self {
+        Self::with_config(reader, FramingConfig::default())
+    }
+
+    /// Creates a reader with a custom configuration.
+    pub fn with_config(reader: R, config: FramingConfig) -> Self {
+        let buffer = BytesMut::with_capacity(config.read_buffer_size);
+        PacketReader { reader, config, buffer, frames_read: 0, frames_received: 0, events: None, metrics: None }
+    }
+
+    /// Emits protocol events for the frames read to the log.
+    pub fn with_event_log(mut self, log: ProtocolEventLog) -> Self {
+        self.events = Some(log);
+        self
+    }
+
+    /// Records the time spent in the stages of reading packets.
+    pub fn with_metrics(mut self, metrics: MetricsRecorder) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Reads the next packet, or `None` if the stream ended cleanly between frames.
+    ///
+    /// Fails if the stream ends inside a frame, a frame exceeds the maximum size,
+    /// or a packet does not parse or fails checksum verification.
+    pub async fn read_packet(&mut self) -> Result<Option<Packet>> {
+        let Some(frame) = self.read_frame().await? else {
+            return Ok(None);
+        };
+        Ok(Some(self.parse_frame(&frame)?))
+    }
+
+    /// Reads the next packet and decodes the item in its body, or returns `None`
+    /// if the stream ended cleanly between frames.
+    ///
+    /// The layers of the body are removed as recorded in the header, encrypted
+    /// ones with the encryptor registered for the recorded strategy.
+    /// With a `packet_budget` configured, fails with `Error::DeadlineExceeded` once
+    /// this takes longer than the budget; the time spent waiting for the frame on
+    /// the stream does not count.
+    pub async fn read_item(
+        &mut self,
+        encryptors: &HashMap<EncryptionStrategy, Box<dyn Encryptor>>,
+    ) -> Result<Option<(Packet, HtlvItem)>> {
+        let Some(frame) = self.read_frame().await? else {
+            return Ok(None);
+        };
+        let deadline = self.config.packet_budget.map(Deadline::after);
+        let packet = self.parse_frame(&frame)?;
+        let decrypt = |sealed: &[u8]| packet.decrypt_layer(sealed, encryptors);
+        let item = open_item(&packet, &decrypt, deadline, self.metrics.as_ref())?;
+        Ok(Some((packet, item)))
+    }
+
+    /// Waits until a complete frame has arrived and splits it off the buffer.
+    async fn read_frame(&mut self) -> Result<Option<BytesMut>> {
+        loop {
+            if let Some(frame) = self.take_frame()? {
+                self.frames_received += 1;
+                return Ok(Some(frame));
+            }
+
+            if self.reader.read_buf(&mut self.buffer).await? == 0 {
+                return if self.buffer.is_empty() {
+                    Ok(None)
+                } else {
+                    Err(Error::ProtocolError(format!(
+                        "Stream ended inside a frame with {} bytes buffered",
+                        self.buffer.len()
+                    )))
+                };
+            }
+        }
+    }
+
+    /// Parses the packet of a frame, emitting the protocol event for it.
+    fn parse_frame(&mut self, frame: &[u8]) -> Result<Packet> {
+        let sequence = self.frames_received;
+        let bytes = frame.len() as u64;
+        let parsed = match &self.metrics {
+            Some(metrics) => Packet::parse_packet_with_metrics(frame, metrics),
+            None => Packet::parse_packet(frame),
+        };
+        match parsed {
+            Ok(packet) => {
+                self.frames_read += 1;
+                let schema_id = packet.header.schema_id;
+                events::emit(&self.events, ProtocolEvent::FrameReceived { sequence, bytes, schema_id });
+                Ok(packet)
+            }
+            Err(Error::CodecError(message)) if message == CHECKSUM_FAILED => {
+                events::emit(&self.events, ProtocolEvent::ChecksumFailure { sequence, bytes });
+                Err(Error::CodecError(message))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Returns the number of packets read so far.
+    pub fn frames_read(&self) -> u64 {
+        self.frames_read
+    }
+
+    /// Returns the underlying stream; buffered bytes of an incomplete frame are lost.
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+
+    /// Splits a complete frame off the buffer, if one has arrived.
+    fn take_frame(&mut self) -> Result<Option<BytesMut>> {
+        if self.buffer.len() < FRAME_HEADER_LEN {
+            return Ok(None);
+        }
+        let mut prefix = [0u8; FRAME_HEADER_LEN];
+        prefix.copy_from_slice(&self.buffer[..FRAME_HEADER_LEN]);
+        let length = u32::from_le_bytes(prefix) as usize;
+        if length > self.config.max_frame_size {
+            return Err(Error::ProtocolError(format!(
+                "Frame of {} bytes exceeds the maximum of {} bytes",
+                length, self.config.max_frame_size
+            )));
+        }
+        if self.buffer.len() < FRAME_HEADER_LEN + length {
+            self.buffer.reserve(FRAME_HEADER_LEN + length - self.buffer.len());
+            return Ok(None);
+        }
+        self.buffer.advance(FRAME_HEADER_LEN);
+        Ok(Some(self.buffer.split_to(length)))
+    }
+}
+
+/// Writes length-prefixed packets to an async stream.
+#[derive(Debug)]
+pub struct PacketWriter<W> {
+    writer: W,
+    config: FramingConfig,
+    frames_written: u64,
+}
+
+impl<W: AsyncWrite + Unpin> PacketWriter<W> {
+    /// Creates a writer with the default configuration.
+    pub fn new(writer: W) -> Self {
+        Self::with_config(writer, FramingConfig::default())
+    }
+
+    /// Creates a writer with a custom configuration.
+    pub fn with_config(writer: W, config: FramingConfig) -> Self {
+        PacketWriter { writer, config, frames_written: 0 }
+    }
+
+    /// Writes a packet as one frame. The frame is not flushed.
+    pub async fn write_packet(&mut self, packet: &Packet) -> Result<()> {
+        let encoded = packet.encode_packet()?;
+        if encoded.len() > self.config.max_frame_size {
+            return Err(Error::ProtocolError(format!(
+                "Packet of {} bytes exceeds the maximum frame size of {} bytes",
+                encoded.len(),
+                self.config.max_frame_size
+            )));
+        }
+        let mut frame = Vec::with_capacity(FRAME_HEADER_LEN + encoded.len());
+        frame.extend_from_slice(&(encoded.len() as u32).to_le_bytes());
+        frame.extend_from_slice(&encoded);
+        self.writer.write_all(&frame).await?;
+        self.frames_written += 1;
+        Ok(())
+    }
+
+    /// Flushes the underlying stream.
+    pub async fn flush(&mut self) -> Result<()> {
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    /// Flushes and shuts down the underlying stream.
+    pub async fn shutdown(&mut self) -> Result<()> {
+        self.writer.shutdown().await?;
+        Ok(())
+    }
+
+    /// Returns the number of packets written so far.
+    pub fn frames_written(&self) -> u64 {
+        self.frames_written
+    }
+
+    /// Returns the underlying stream.
+    pub fn into_inner(self) -> W {
+        self.writer
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::packet::{DataBody, MetadataHeader};
+
+    fn packet(timestamp: u64, body: &[u8]) -> Packet {
+        let header = MetadataHeader {
+            schema_id: 7,
+            timestamp,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        Packet::build_packet(header, DataBody::Raw(body.to_vec())).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_round_trip_over_small_pipe() {
+        // A tiny pipe forces frames to be split over many reads
+        let (client, server) = tokio::io::duplex(7);
+        let packets: Vec<Packet> = (0..5).map(|i| packet(i, &vec![i as u8; 100 * i as usize])).collect();
+
+        let sent = packets.clone();
+        let writer_task = tokio::spawn(async move {
+            let mut writer = PacketWriter::new(client);
+            for packet in &sent {
+                writer.write_packet(packet).await.unwrap();
+            }
+            writer.shutdown().await.unwrap();
+        });
+
+        let mut reader = PacketReader::new(server);
+        let mut received = Vec::new();
+        while let Some(packet) = reader.read_packet().await.unwrap() {
+            received.push(packet);
+        }
+        writer_task.await.unwrap();
+        assert_eq!(received, packets);
+        assert_eq!(reader.frames_read(), 5);
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_truncated_and_oversized_frames() {
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_packet(&packet(1, b"payload")).await.unwrap();
+        let frame = writer.into_inner();
+
+        let mut corrupt = frame.clone();
+        let last = corrupt.len() - 1;
+        corrupt[last] ^= 0xFF;
+        let mut reader = PacketReader::new(&corrupt[..]).with_event_log(ProtocolEventLog::new(1));
+        assert_eq!(reader.read_packet().await.unwrap_err().to_string(), "Codec Error: Checksum verification failed");
+        assert_eq!(reader.frames_read(), 0);
+
+        let err = PacketReader::new(&frame[..frame.len() - 3]).read_packet().await.unwrap_err();
+        assert!(err.to_string().contains("Stream ended inside a frame"));
+
+        let config = FramingConfig { max_frame_size: 8, ..FramingConfig::default() };
+        assert!(PacketReader::with_config(&frame[..], config).read_packet().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_item_opens_body_within_budget() {
+        use crate::codec::types::HtlvValue;
+        use crate::compress::CompressionStrategy;
+        use crate::encrypt::aes_gcm::AesGcmEncryptor;
+        use crate::internal::packet_builder::PacketBuilder;
+
+        let item = HtlvItem::new(1, HtlvValue::String(bytes::Bytes::from("x".repeat(512))));
+        let encryptor = AesGcmEncryptor::with_key(&[3u8; 32]).unwrap();
+        let sealed = PacketBuilder::new(7).item(&item).unwrap()
+            .compress(CompressionStrategy::Zstd).unwrap()
+            .encrypt(&encryptor, None).unwrap()
+            .build().unwrap();
+        let mut writer = PacketWriter::new(Vec::new());
+        writer.write_packet(&sealed).await.unwrap();
+        writer.write_packet(&packet(2, b"\x01\x00\x00")).await.unwrap();
+        let frames = writer.into_inner();
+
+        let mut encryptors: HashMap<EncryptionStrategy, Box<dyn Encryptor>> = HashMap::new();
+        encryptors.insert(EncryptionStrategy::AesGcm, Box::new(encryptor));
+        let config = FramingConfig { packet_budget: Some(Duration::from_secs(3600)), ..FramingConfig::default() };
+        let mut reader = PacketReader::with_config(&frames[..], config);
+        let (packet, opened) = reader.read_item(&encryptors).await.unwrap().unwrap();
+        assert_eq!((packet, opened), (sealed, item));
+        let (_, null) = reader.read_item(&encryptors).await.unwrap().unwrap();
+        assert_eq!(null, HtlvItem::new(1, HtlvValue::Null));
+        assert!(reader.read_item(&encryptors).await.unwrap().is_none());
+
+        let config = FramingConfig { packet_budget: Some(Duration::ZERO), ..FramingConfig::default() };
+        let err = PacketReader::with_config(&frames[..], config).read_item(&encryptors).await.unwrap_err();
+        assert!(matches!(err, Error::DeadlineExceeded { .. }), "{:?}", err);
+    }
+}
@@ -0,0 +1,327 @@
+// Memory accounting for Tonitru subsystems
+//
+// A `MemoryBudget` is a cheaply clonable handle shared by the subsystems that
+// hold potentially large buffers (decode buffers, compression dictionaries, key
+// caches, shard reassembly). Each subsystem reserves memory before allocating
+// and releases it when the buffer is dropped, so embedders can bound the
+// worst-case memory use of the library with a hard cap.
+
+use crate::internal::error::{Error, Result};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+/// Value used internally to represent "no limit".
+const UNLIMITED: usize = usize::MAX;
+
+/// The subsystems whose memory use is accounted for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MemorySubsystem {
+    /// Buffers holding data being decoded
+    DecodeBuffers,
+    /// Compression dictionaries (e.g. incremental compression contexts)
+    Dictionaries,
+    /// Cached encryption keys and ciphers
+    KeyCache,
+    /// Buffers used to reassemble sharded data
+    ShardReassembly,
+}
+
+impl MemorySubsystem {
+    /// All subsystems, in index order.
+    pub const ALL: [MemorySubsystem; 4] = [
+        MemorySubsystem::DecodeBuffers,
+        MemorySubsystem::Dictionaries,
+        MemorySubsystem::KeyCache,
+        MemorySubsystem::ShardReassembly,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            MemorySubsystem::DecodeBuffers => 0,
+            MemorySubsystem::Dictionaries => 1,
+            MemorySubsystem::KeyCache => 2,
+            MemorySubsystem::ShardReassembly => 3,
+        }
+    }
+}
+
+impl fmt::Display for MemorySubsystem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            MemorySubsystem::DecodeBuffers => "decode buffers",
+            MemorySubsystem::Dictionaries => "dictionaries",
+            MemorySubsystem::KeyCache => "key cache",
+            MemorySubsystem::ShardReassembly => "shard reassembly",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug)]
+struct BudgetInner {
+    /// Hard cap on the total memory across all subsystems
+    limit: AtomicUsize,
+    /// Memory currently reserved across all subsystems
+    used: AtomicUsize,
+    /// Per-subsystem caps
+    subsystem_limits: [AtomicUsize; 4],
+    /// Per-subsystem usage
+    subsystem_used: [AtomicUsize; 4],
+}
+
+/// A shared memory budget with per-subsystem accounting and a hard cap.
+///
+/// Cloning the handle shares the underlying counters.
+#[derive(Debug, Clone)]
+pub struct MemoryBudget {
+    inner: Arc<BudgetInner>,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        Self::unlimited()
+    }
+}
+
+impl MemoryBudget {
+    /// Creates a budget without any limit, which only accounts for memory use.
+    pub fn unlimited() -> Self {
+        Self::with_limit(UNLIMITED)
+    }
+
+    /// Creates a budget with a hard cap (in bytes) on the total memory across all subsystems.
+    pub fn with_limit(limit: usize) -> Self {
+        MemoryBudget {
+            inner: Arc::new(BudgetInner {
+                limit: AtomicUsize::new(limit),
+                used: AtomicUsize::new(0),
+                subsystem_limits: [
+                    AtomicUsize::new(UNLIMITED),
+                    AtomicUsize::new(UNLIMITED),
+                    AtomicUsize::new(UNLIMITED),
+                    AtomicUsize::new(UNLIMITED),
+                ],
+                subsystem_used: [
+                    AtomicUsize::new(0),
+                    AtomicUsize::new(0),
+                    AtomicUsize::new(0),
+                    AtomicUsize::new(0),
+                ],
+            }),
+        }
+    }
+
+    /// Sets the hard cap (in bytes) on the total memory across all subsystems.
+    ///
+    /// Lowering the cap does not affect existing reservations, only new ones.
+    pub fn set_limit(&self, limit: usize) {
+        self.inner.limit.store(limit, Ordering::SeqCst);
+    }
+
+    /// Sets a cap (in bytes) for a single subsystem.
+    pub fn set_subsystem_limit(&self, subsystem: MemorySubsystem, limit: usize) {
+        self.inner.subsystem_limits[subsystem.index()].store(limit, Ordering::SeqCst);
+    }
+
+    /// Returns the total cap, or `None` if the budget is unlimited.
+    pub fn limit(&self) -> Option<usize> {
+        match self.inner.limit.load(Ordering::SeqCst) {
+            UNLIMITED => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Returns the cap for a subsystem, or `None` if it has no dedicated cap.
+    pub fn subsystem_limit(&self, subsystem: MemorySubsystem) -> Option<usize> {
+        match self.inner.subsystem_limits[subsystem.index()].load(Ordering::SeqCst) {
+            UNLIMITED => None,
+            limit => Some(limit),
+        }
+    }
+
+    /// Returns the memory currently reserved across all subsystems.
+    pub fn used(&self) -> usize {
+        self.inner.used.load(Ordering::SeqCst)
+    }
+
+    /// Returns the memory currently reserved by a subsystem.
+    pub fn used_by(&self, subsystem: MemorySubsystem) -> usize {
+        self.inner.subsystem_used[subsystem.index()].load(Ordering::SeqCst)
+    }
+
+    /// Reserves memory for a subsystem.
+    ///
+    /// Fails with `Error::MemoryLimitExceeded` if the reservation would exceed the
+    /// subsystem cap or the total cap; nothing is reserved in that case. The caller
+    /// is responsible for calling `release` with the same size afterwards.
+    pub fn reserve(&self, subsystem: MemorySubsystem, bytes: usize) -> Result<()> {
+        let index = subsystem.index();
+
+        // Reserve in the subsystem first
+        let subsystem_limit = self.inner.subsystem_limits[index].load(Ordering::SeqCst);
+        self.inner.subsystem_used[index]
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+                used.checked_add(bytes).filter(|&new_used| new_used <= subsystem_limit)
+            })
+            .map_err(|used| Error::MemoryLimitExceeded {
+                subsystem,
+                requested: bytes,
+                used,
+                limit: subsystem_limit,
+            })?;
+
+        // Then in the total, rolling back the subsystem reservation on failure
+        let limit = self.inner.limit.load(Ordering::SeqCst);
+        if let Err(used) = self.inner.used.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| {
+            used.checked_add(bytes).filter(|&new_used| new_used <= limit)
+        }) {
+            self.inner.subsystem_used[index].fetch_sub(bytes, Ordering::SeqCst);
+            return Err(Error::MemoryLimitExceeded {
+                subsystem,
+                requested: bytes,
+                used,
+                limit,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Releases memory previously reserved with `reserve`.
+    pub fn release(&self, subsystem: MemorySubsystem, bytes: usize) {
+        let index = subsystem.index();
+        // Saturate instead of wrapping around if a caller releases more than it reserved
+        let _ = self.inner.subsystem_used[index]
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| Some(used.saturating_sub(bytes)));
+        let _ = self.inner.used
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |used| Some(used.saturating_sub(bytes)));
+    }
+
+    /// Reserves memory for a subsystem and returns a guard that releases it when dropped.
+    pub fn try_reserve(&self, subsystem: MemorySubsystem, bytes: usize) -> Result<MemoryReservation> {
+        self.reserve(subsystem, bytes)?;
+        Ok(MemoryReservation {
+            budget: self.clone(),
+            subsystem,
+            bytes,
+        })
+    }
+}
+
+/// A memory reservation that is released when dropped.
+#[derive(Debug)]
+pub struct MemoryReservation {
+    budget: MemoryBudget,
+    subsystem: MemorySubsystem,
+    bytes: usize,
+}
+
+impl MemoryReservation {
+    /// Returns the number of reserved bytes.
+    pub fn size(&self) -> usize {
+        self.bytes
+    }
+
+    /// Grows or shrinks the reservation to a new size.
+    ///
+    /// On failure the reservation keeps its previous size.
+    pub fn resize(&mut self, new_size: usize) -> Result<()> {
+        if new_size > self.bytes {
+            self.budget.reserve(self.subsystem, new_size - self.bytes)?;
+        } else {
+            self.budget.release(self.subsystem, self.bytes - new_size);
+        }
+        self.bytes = new_size;
+        Ok(())
+    }
+}
+
+impl Drop for MemoryReservation {
+    fn drop(&mut self) {
+        self.budget.release(self.subsystem, self.bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_budget_accounts_usage() {
+        let budget = MemoryBudget::unlimited();
+        assert_eq!(budget.limit(), None);
+
+        budget.reserve(MemorySubsystem::DecodeBuffers, 100).unwrap();
+        budget.reserve(MemorySubsystem::Dictionaries, 50).unwrap();
+        assert_eq!(budget.used(), 150);
+        assert_eq!(budget.used_by(MemorySubsystem::DecodeBuffers), 100);
+        assert_eq!(budget.used_by(MemorySubsystem::Dictionaries), 50);
+
+        budget.release(MemorySubsystem::DecodeBuffers, 100);
+        assert_eq!(budget.used(), 50);
+        assert_eq!(budget.used_by(MemorySubsystem::DecodeBuffers), 0);
+    }
+
+    #[test]
+    fn test_total_limit_is_enforced() {
+        let budget = MemoryBudget::with_limit(100);
+        budget.reserve(MemorySubsystem::KeyCache, 80).unwrap();
+
+        let result = budget.reserve(MemorySubsystem::ShardReassembly, 30);
+        match result {
+            Err(Error::MemoryLimitExceeded { subsystem, requested, used, limit }) => {
+                assert_eq!(subsystem, MemorySubsystem::ShardReassembly);
+                assert_eq!(requested, 30);
+                assert_eq!(used, 80);
+                assert_eq!(limit, 100);
+            }
+            other => panic!("Expected MemoryLimitExceeded, got {:?}", other),
+        }
+
+        // The failed reservation must not leak into the counters
+        assert_eq!(budget.used(), 80);
+        assert_eq!(budget.used_by(MemorySubsystem::ShardReassembly), 0);
+    }
+
+    #[test]
+    fn test_subsystem_limit_is_enforced() {
+        let budget = MemoryBudget::unlimited();
+        budget.set_subsystem_limit(MemorySubsystem::Dictionaries, 64);
+
+        assert!(budget.reserve(MemorySubsystem::Dictionaries, 65).is_err());
+        assert!(budget.reserve(MemorySubsystem::Dictionaries, 64).is_ok());
+        // Other subsystems are not affected
+        assert!(budget.reserve(MemorySubsystem::DecodeBuffers, 1000).is_ok());
+        assert_eq!(budget.used(), 1064);
+    }
+
+    #[test]
+    fn test_reservation_guard() {
+        let budget = MemoryBudget::with_limit(100);
+        {
+            let mut reservation = budget.try_reserve(MemorySubsystem::DecodeBuffers, 40).unwrap();
+            assert_eq!(budget.used(), 40);
+
+            reservation.resize(90).unwrap();
+            assert_eq!(budget.used(), 90);
+
+            assert!(reservation.resize(120).is_err());
+            assert_eq!(reservation.size(), 90);
+
+            reservation.resize(10).unwrap();
+            assert_eq!(budget.used(), 10);
+        }
+        assert_eq!(budget.used(), 0);
+    }
+
+    #[test]
+    fn test_error_message() {
+        let budget = MemoryBudget::with_limit(10);
+        let err = budget.reserve(MemorySubsystem::DecodeBuffers, 20).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Memory Limit Exceeded: decode buffers requested 20 bytes with 0 of 10 bytes in use"
+        );
+    }
+}
@@ -1,4 +1,17 @@
 // Placeholder for internal module
 
 pub mod error;
-pub mod packet;
\ No newline at end of file
+pub mod packet;
+pub mod checksum;
+pub mod memory;
+pub mod alloc;
+pub mod diagnostics;
+pub mod progress;
+pub mod metrics;
+pub mod cancel;
+pub mod anomaly;
+pub mod deadline;
+pub mod framing;
+pub mod cursor;
+pub mod packet_builder;
+pub mod padding;
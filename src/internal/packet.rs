@@ -1,13 +1,66 @@
 use crate::internal::error::{Error, Result};
 use crate::codec::varint; // Use varint for encoding/decoding fields
-use blake3; // Used for checksum calculation and verification
-use std::mem;
+use crate::internal::checksum::{get_checksummer, ChecksumAlgorithm};
+use crate::internal::cursor::WireCursor;
+use crate::internal::metrics::{self, MetricsRecorder, PipelineStage};
+use crate::internal::padding;
 use crate::compress::CompressionStrategy; // Import CompressionStrategy
+use crate::encrypt::{fips, EncryptionStrategy, Encryptor};
+use crate::encrypt::signature::{SigningKey, VerifyingKey, SIGNATURE_LEN};
+use crate::encrypt::dilithium::DILITHIUM_SIGNATURE_LEN;
+use crate::encrypt::hybrid_signature::{HybridSigningKey, HybridVerifyingKey, SignaturePolicy};
+use crate::codec::wire::WireFormat;
+use std::collections::HashMap;
 
 // Constants for encoding CompressionStrategy in flow_flags
 const COMPRESSION_STRATEGY_MASK: u32 = 0b11; // Use the lowest 2 bits for compression strategy
 const COMPRESSION_STRATEGY_SHIFT: u32 = 0; // Start from bit 0
 
+// Constants for encoding the body WireFormat version in flow_flags
+const WIRE_FORMAT_MASK: u32 = 0b11; // Two bits for the wire format version
+const WIRE_FORMAT_SHIFT: u32 = 2; // Right after the compression strategy bits
+
+// Flag in flow_flags marking a body that starts with a tag table (see codec::tag_table)
+const TAG_TABLE_FLAG: u32 = 1 << 4;
+
+// Constants for encoding EncryptionStrategy in flow_flags
+const ENCRYPTION_STRATEGY_MASK: u32 = 0b1111; // Four bits for the encryption strategy
+const ENCRYPTION_STRATEGY_SHIFT: u32 = 5; // Right after the tag table flag
+
+// Flag in the encoded flow_flags marking a header followed by a key ID. It is
+// derived from `MetadataHeader::key_id` when encoding and never kept in the
+// flow_flags of a decoded header.
+const KEY_ID_FLAG: u32 = 1 << 9;
+
+// Flag in flow_flags marking a packet whose checksum is followed by an Ed25519
+// signature (see encrypt::signature). It is set by `Packet::build_packet_signed`
+// and cleared by `Packet::build_packet`, so the checksum covers it.
+const SIGNED_FLAG: u32 = 1 << 10;
+
+// Flag in flow_flags marking a packet whose checksum, and Ed25519 signature if
+// any, is followed by a Dilithium signature (see encrypt::hybrid_signature). It
+// is set by `Packet::build_packet_hybrid_signed` and cleared by the other builders.
+const PQ_SIGNED_FLAG: u32 = 1 << 11;
+
+// Flag in flow_flags marking an encrypted body whose plaintext was padded
+// before encryption (see internal::padding)
+const PADDED_FLAG: u32 = 1 << 12;
+
+// Flag in flow_flags marking a body whose secret fields are kept out of the
+// compressed section (see compress::side_channel)
+const ISOLATED_SECRETS_FLAG: u32 = 1 << 13;
+
+// Constants for encoding the ChecksumAlgorithm of the packet in flow_flags
+const CHECKSUM_ALGORITHM_MASK: u32 = 0b11; // Two bits for the checksum algorithm
+const CHECKSUM_ALGORITHM_SHIFT: u32 = 14; // Right after the isolated secrets flag
+
+// Domain separation prefix of the message a packet signature signs, followed by
+// the packet checksum
+const SIGNATURE_CONTEXT: &[u8] = b"tonitru-packet-signature-v1";
+
+// Message of the codec error returned for a packet failing checksum verification
+pub(crate) const CHECKSUM_FAILED: &str = "Checksum verification failed";
+
 /// Represents the metadata header of a Tonitru packet.
 #[derive(Debug, PartialEq, Clone)] // Added Clone derive
 pub struct MetadataHeader {
@@ -16,6 +69,7 @@ pub struct MetadataHeader {
     pub shard_id: u64,
     pub flow_flags: u32, // Using u32 for flags
     pub body_type: u8, // Field to indicate the type of DataBody
+    pub key_id: Option<String>, // ID of the key an encrypted body was sealed with
     // TODO: Add more metadata fields as needed
 }
 
@@ -54,7 +108,8 @@ impl DataBodyType {
 /// Represents the checksum of a Tonitru packet.
 #[derive(Debug, PartialEq, Clone)] // Added Clone derive for completeness, though not strictly needed for the current errors
 pub struct Checksum {
-    pub blake3_hash: [u8; 32], // BLAKE3 hash (32 bytes)
+    pub algorithm: ChecksumAlgorithm, // Algorithm recorded in the header flow_flags
+    pub digest: Vec<u8>, // Checksum of the header and body, `algorithm.digest_len()` bytes
 }
 
 /// Represents a complete Tonitru network packet.
@@ -63,6 +118,10 @@ pub struct Packet {
     pub header: MetadataHeader,
     pub body: DataBody,
     pub checksum: Checksum,
+    /// Ed25519 signature of the checksum, for packets built with `build_packet_signed`
+    pub signature: Option<[u8; SIGNATURE_LEN]>,
+    /// Dilithium signature of the checksum, for packets built with `build_packet_hybrid_signed`
+    pub pq_signature: Option<Vec<u8>>,
 }
 
 impl MetadataHeader {
@@ -72,47 +131,43 @@ impl MetadataHeader {
         encoded.extend_from_slice(&varint::encode_varint(self.schema_id));
         encoded.extend_from_slice(&varint::encode_varint(self.timestamp));
         encoded.extend_from_slice(&varint::encode_varint(self.shard_id));
-        encoded.extend_from_slice(&self.flow_flags.to_le_bytes()); // Fixed size u32 (4 bytes)
+        let flow_flags = match self.key_id {
+            Some(_) => self.flow_flags | KEY_ID_FLAG,
+            None => self.flow_flags & !KEY_ID_FLAG,
+        };
+        encoded.extend_from_slice(&flow_flags.to_le_bytes()); // Fixed size u32 (4 bytes)
         encoded.push(self.body_type); // Encode body_type as a single byte
+        if let Some(key_id) = &self.key_id {
+            // Key ID as a varint length followed by its UTF-8 bytes
+            encoded.extend_from_slice(&varint::encode_varint(key_id.len() as u64));
+            encoded.extend_from_slice(key_id.as_bytes());
+        }
         // TODO: Encode other metadata fields
         Ok(encoded)
     }
 
     /// Decodes bytes into a MetadataHeader.
     pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
-        let mut bytes_read = 0;
-
-        let (schema_id, len) = varint::decode_varint(data)?;
-        bytes_read += len;
-
-        let remaining = &data[bytes_read..];
-        let (timestamp, len) = varint::decode_varint(remaining)?;
-        bytes_read += len;
-
-        let remaining = &data[bytes_read..];
-        let (shard_id, len) = varint::decode_varint(remaining)?;
-        bytes_read += len;
-
-        let remaining = &data[bytes_read..];
-        if remaining.len() < mem::size_of::<u32>() {
-             return Err(Error::CodecError("Incomplete data for flow_flags".to_string()));
-         }
-        let mut flags_bytes = [0u8; mem::size_of::<u32>()];
-        flags_bytes.copy_from_slice(&remaining[..mem::size_of::<u32>()]);
-        let flow_flags = u32::from_le_bytes(flags_bytes);
-        bytes_read += mem::size_of::<u32>();
-
-        let remaining = &data[bytes_read..];
-        if remaining.is_empty() {
-             return Err(Error::CodecError("Incomplete data for body_type".to_string()));
-         }
-        let body_type = remaining[0];
-        bytes_read += 1;
-
+        let mut cursor = WireCursor::new(data);
+        let schema_id = cursor.read_varint("schema_id")?;
+        let timestamp = cursor.read_varint("timestamp")?;
+        let shard_id = cursor.read_varint("shard_id")?;
+        let flow_flags = cursor.read_u32_le("flow_flags")?;
+        let body_type = cursor.read_u8("body_type")?;
+        let key_id = if flow_flags & KEY_ID_FLAG != 0 {
+            let bytes = cursor.read_length_prefixed("key_id")?;
+            let key_id = std::str::from_utf8(bytes)
+                .map_err(|e| Error::CodecError(format!("Invalid UTF-8 sequence for key_id: {}", e)))?;
+            Some(key_id.to_string())
+        } else {
+            None
+        };
+        let flow_flags = flow_flags & !KEY_ID_FLAG;
+        let bytes_read = cursor.position();
 
         // TODO: Decode other metadata fields
 
-        Ok((MetadataHeader { schema_id, timestamp, shard_id, flow_flags, body_type }, bytes_read))
+        Ok((MetadataHeader { schema_id, timestamp, shard_id, flow_flags, body_type, key_id }, bytes_read))
     }
 
     /// Sets the compression strategy in flow_flags.
@@ -126,12 +181,107 @@ impl MetadataHeader {
     /// Gets the compression strategy from flow_flags.
     pub fn get_compression_strategy(&self) -> Result<CompressionStrategy> {
         let strategy_bits = (self.flow_flags >> COMPRESSION_STRATEGY_SHIFT) & COMPRESSION_STRATEGY_MASK;
-        match strategy_bits as u8 {
-            0 => Ok(CompressionStrategy::NoCompression),
-            1 => Ok(CompressionStrategy::Zstd),
-            // Removed Lz4 case: 2 => Ok(CompressionStrategy::Lz4),
-            3 => Ok(CompressionStrategy::Brotli),
-            _ => Err(Error::CodecError(format!("Unknown compression strategy bits in flow_flags: {}", strategy_bits))),
+        CompressionStrategy::from_u8(strategy_bits as u8)
+    }
+
+    /// Sets the encryption strategy of the body in flow_flags.
+    pub fn set_encryption_strategy(&mut self, strategy: EncryptionStrategy) {
+        self.flow_flags &= !(ENCRYPTION_STRATEGY_MASK << ENCRYPTION_STRATEGY_SHIFT);
+        self.flow_flags |= ((strategy as u8) as u32) << ENCRYPTION_STRATEGY_SHIFT;
+    }
+
+    /// Gets the encryption strategy of the body from flow_flags.
+    /// Headers written before the strategy was recorded report `NoEncryption`.
+    pub fn get_encryption_strategy(&self) -> Result<EncryptionStrategy> {
+        let strategy_bits = (self.flow_flags >> ENCRYPTION_STRATEGY_SHIFT) & ENCRYPTION_STRATEGY_MASK;
+        EncryptionStrategy::from_u8(strategy_bits as u8)
+            .map_err(|_| Error::CodecError(format!("Unknown encryption strategy bits in flow_flags: {}", strategy_bits)))
+    }
+
+    /// Sets the wire format version of the body items in flow_flags.
+    pub fn set_wire_format(&mut self, format: WireFormat) {
+        self.flow_flags &= !(WIRE_FORMAT_MASK << WIRE_FORMAT_SHIFT);
+        self.flow_flags |= (format as u32) << WIRE_FORMAT_SHIFT;
+    }
+
+    /// Gets the wire format version of the body items from flow_flags.
+    /// Headers written before the version existed report `WireFormat::V1`.
+    pub fn get_wire_format(&self) -> Result<WireFormat> {
+        WireFormat::from_u8(((self.flow_flags >> WIRE_FORMAT_SHIFT) & WIRE_FORMAT_MASK) as u8)
+    }
+
+    /// Sets whether the body starts with a tag table in flow_flags.
+    pub fn set_tag_table(&mut self, has_tag_table: bool) {
+        if has_tag_table {
+            self.flow_flags |= TAG_TABLE_FLAG;
+        } else {
+            self.flow_flags &= !TAG_TABLE_FLAG;
+        }
+    }
+
+    /// Returns true if flow_flags mark the body as starting with a tag table.
+    pub fn has_tag_table(&self) -> bool {
+        self.flow_flags & TAG_TABLE_FLAG != 0
+    }
+
+    /// Returns true if flow_flags mark the packet as followed by a signature.
+    pub fn is_signed(&self) -> bool {
+        self.flow_flags & SIGNED_FLAG != 0
+    }
+
+    /// Returns true if flow_flags mark the packet as followed by a Dilithium signature.
+    pub fn is_pq_signed(&self) -> bool {
+        self.flow_flags & PQ_SIGNED_FLAG != 0
+    }
+
+    /// Sets whether the plaintext of the encrypted body is padded in flow_flags.
+    pub fn set_padded(&mut self, padded: bool) {
+        if padded {
+            self.flow_flags |= PADDED_FLAG;
+        } else {
+            self.flow_flags &= !PADDED_FLAG;
+        }
+    }
+
+    /// Returns true if flow_flags mark the plaintext of the encrypted body as padded.
+    pub fn is_padded(&self) -> bool {
+        self.flow_flags & PADDED_FLAG != 0
+    }
+
+    /// Sets whether the secret fields of the body are isolated from its
+    /// compressed section in flow_flags.
+    pub fn set_isolated_secrets(&mut self, isolated: bool) {
+        if isolated {
+            self.flow_flags |= ISOLATED_SECRETS_FLAG;
+        } else {
+            self.flow_flags &= !ISOLATED_SECRETS_FLAG;
+        }
+    }
+
+    /// Returns true if flow_flags mark the secret fields of the body as
+    /// isolated from its compressed section.
+    pub fn has_isolated_secrets(&self) -> bool {
+        self.flow_flags & ISOLATED_SECRETS_FLAG != 0
+    }
+
+    /// Sets the checksum algorithm of the packet in flow_flags.
+    pub fn set_checksum_algorithm(&mut self, algorithm: ChecksumAlgorithm) {
+        self.flow_flags &= !(CHECKSUM_ALGORITHM_MASK << CHECKSUM_ALGORITHM_SHIFT);
+        self.flow_flags |= (algorithm as u32) << CHECKSUM_ALGORITHM_SHIFT;
+    }
+
+    /// Gets the checksum algorithm of the packet from flow_flags.
+    /// Headers written before the algorithm was recorded report `Blake3`.
+    pub fn get_checksum_algorithm(&self) -> Result<ChecksumAlgorithm> {
+        ChecksumAlgorithm::from_u8(((self.flow_flags >> CHECKSUM_ALGORITHM_SHIFT) & CHECKSUM_ALGORITHM_MASK) as u8)
+    }
+
+    /// Removes the padding from a decrypted body if flow_flags mark it as padded.
+    pub(crate) fn unpad(&self, decrypted: Vec<u8>) -> Result<Vec<u8>> {
+        if self.is_padded() {
+            padding::unpad(decrypted)
+        } else {
+            Ok(decrypted)
         }
     }
 }
@@ -164,33 +314,65 @@ impl DataBody {
 impl Checksum {
     /// Creates a new Checksum from a BLAKE3 hash.
     pub fn new(blake3_hash: [u8; 32]) -> Self {
-        Checksum { blake3_hash }
+        Checksum { algorithm: ChecksumAlgorithm::Blake3, digest: blake3_hash.to_vec() }
+    }
+
+    /// Computes the checksum of a packet's encoded header and body.
+    pub fn compute(algorithm: ChecksumAlgorithm, header: &[u8], body: &[u8]) -> Self {
+        Checksum { algorithm, digest: get_checksummer(algorithm).checksum(&[header, body]) }
     }
 
     /// Encodes the Checksum into bytes.
     pub fn encode(&self) -> Vec<u8> {
-        self.blake3_hash.to_vec()
+        self.digest.clone()
     }
 
-    /// Decodes bytes into a Checksum.
-    pub fn decode(data: &[u8]) -> Result<(Self, usize)> {
-        if data.len() < 32 {
-            return Err(Error::CodecError("Incomplete data for BLAKE3 checksum".to_string()));
-        }
-        let mut hash_bytes = [0u8; 32];
-        hash_bytes.copy_from_slice(&data[..32]);
-        Ok((Checksum { blake3_hash: hash_bytes }, 32))
+    /// Decodes bytes into a Checksum of the algorithm.
+    pub fn decode(data: &[u8], algorithm: ChecksumAlgorithm) -> Result<(Self, usize)> {
+        let length = algorithm.digest_len();
+        let digest = WireCursor::new(data).take(length as u64, "checksum")?.to_vec();
+        Ok((Checksum { algorithm, digest }, length))
     }
 
-    /// Verifies the checksum against calculated hash.
-    pub fn verify(&self, calculated_hash: &[u8; 32]) -> bool {
-        &self.blake3_hash == calculated_hash
+    /// Verifies the checksum against a calculated one.
+    pub fn verify(&self, calculated: &[u8]) -> bool {
+        self.digest == calculated
     }
 }
 
 impl Packet {
     /// Builds a new Tonitru packet.
     pub fn build_packet(mut header: MetadataHeader, body: DataBody) -> Result<Self> {
+        header.flow_flags &= !(SIGNED_FLAG | PQ_SIGNED_FLAG);
+        Self::build_checksummed(header, body)
+    }
+
+    /// Builds a new Tonitru packet signed with the key. The signature covers
+    /// the header and the body, and is checked by `parse_packet_verified`.
+    /// Fails unless the checksum algorithm of the header is cryptographic.
+    pub fn build_packet_signed(mut header: MetadataHeader, body: DataBody, signing_key: &SigningKey) -> Result<Self> {
+        check_signable(&header)?;
+        header.flow_flags = (header.flow_flags | SIGNED_FLAG) & !PQ_SIGNED_FLAG;
+        let mut packet = Self::build_checksummed(header, body)?;
+        packet.signature = Some(signing_key.sign(&signed_message(&packet.checksum)));
+        Ok(packet)
+    }
+
+    /// Builds a new Tonitru packet signed with both the Ed25519 and the
+    /// Dilithium key. Receivers check it with `parse_packet_verified_hybrid`;
+    /// those knowing only the Ed25519 key can still use `parse_packet_verified`.
+    pub fn build_packet_hybrid_signed(mut header: MetadataHeader, body: DataBody, signing_key: &HybridSigningKey) -> Result<Self> {
+        check_signable(&header)?;
+        header.flow_flags |= SIGNED_FLAG | PQ_SIGNED_FLAG;
+        let mut packet = Self::build_checksummed(header, body)?;
+        let signature = signing_key.sign(&signed_message(&packet.checksum));
+        packet.signature = Some(signature.classical);
+        packet.pq_signature = Some(signature.post_quantum);
+        Ok(packet)
+    }
+
+    /// Builds a packet without signature over a header with final flow_flags.
+    fn build_checksummed(mut header: MetadataHeader, body: DataBody) -> Result<Self> {
         // Set body type in header based on DataBody variant
         header.body_type = match body {
             DataBody::Raw(_) => DataBodyType::Raw as u8,
@@ -198,52 +380,177 @@ impl Packet {
             DataBody::Encrypted(_) => DataBodyType::Encrypted as u8,
         };
 
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(&header.encode()?);
-        hasher.update(&body.encode()?);
-        let checksum = Checksum::new(*hasher.finalize().as_bytes());
+        let algorithm = header.get_checksum_algorithm()?;
+        fips::check_checksum(algorithm)?;
+        let checksum = Checksum::compute(algorithm, &header.encode()?, &body.encode()?);
 
-        Ok(Packet { header, body, checksum })
+        Ok(Packet { header, body, checksum, signature: None, pq_signature: None })
     }
 
     /// Parses bytes into a Tonitru packet.
+    ///
+    /// The signature of a signed packet is read but not verified; use
+    /// `parse_packet_verified` to verify it.
     pub fn parse_packet(data: &[u8]) -> Result<Self> {
-        let mut bytes_read = 0;
+        Self::parse(data, None)
+    }
+
+    /// Parses bytes into a Tonitru packet like `parse_packet`, recording the
+    /// time spent verifying its checksum.
+    pub fn parse_packet_with_metrics(data: &[u8], metrics: &MetricsRecorder) -> Result<Self> {
+        Self::parse(data, Some(metrics))
+    }
 
+    fn parse(data: &[u8], metrics: Option<&MetricsRecorder>) -> Result<Self> {
         // Decode Header
         let (header, header_bytes) = MetadataHeader::decode(data)?;
-        bytes_read += header_bytes;
+        let mut cursor = WireCursor::at(data, header_bytes);
 
         // Determine body type from header
         let body_type = DataBodyType::from_u8(header.body_type)?;
+        if body_type == DataBodyType::Encrypted {
+            header.get_encryption_strategy()?;
+        }
+
+        let algorithm = header.get_checksum_algorithm()?;
+        fips::check_checksum(algorithm)?;
+        if (header.is_signed() || header.is_pq_signed()) && !algorithm.is_cryptographic() {
+            return Err(Error::CodecError(format!("Signed packet with non-cryptographic {:?} checksum", algorithm)));
+        }
 
         // Decode Body
-        let remaining_data = &data[bytes_read..];
-        let body_length = remaining_data.len().checked_sub(32) // Checksum is the last 32 bytes
+        // Checksum, then the Ed25519 and Dilithium signatures
+        let mut trailer_length = algorithm.digest_len();
+        if header.is_signed() {
+            trailer_length += SIGNATURE_LEN;
+        }
+        if header.is_pq_signed() {
+            trailer_length += DILITHIUM_SIGNATURE_LEN;
+        }
+        let body_length = cursor.remaining().checked_sub(trailer_length)
             .ok_or_else(|| Error::CodecError("Incomplete data for body and checksum".to_string()))?;
-
-        let body_slice = &remaining_data[..body_length];
+        let body_slice = cursor.take(body_length as u64, "body")?;
         let body = DataBody::decode(body_slice, body_type)?;
-        bytes_read += body_length;
 
         // Decode Checksum
-        let remaining_data_after_body = &data[bytes_read..];
-        let (_checksum, _checksum_bytes) = Checksum::decode(remaining_data_after_body)?; // Added underscore
+        let (_checksum, _checksum_bytes) = Checksum::decode(cursor.take(algorithm.digest_len() as u64, "checksum")?, algorithm)?; // Added underscore
+        let signature = match header.is_signed() {
+            true => Some(cursor.read_array::<SIGNATURE_LEN>("signature")?),
+            false => None,
+        };
+        let pq_signature = match header.is_pq_signed() {
+            true => Some(cursor.take(DILITHIUM_SIGNATURE_LEN as u64, "Dilithium signature")?.to_vec()),
+            false => None,
+        };
+
+        // Verify checksum over the re-encoded header and body
+        let verified = metrics::time(metrics, PipelineStage::Checksum, || -> Result<bool> {
+            let calculated = Checksum::compute(algorithm, &header.encode()?, &body.encode()?);
+            Ok(_checksum.verify(&calculated.digest)) // Used _checksum
+        })?;
+        if !verified {
+            return Err(Error::CodecError(CHECKSUM_FAILED.to_string()));
+        }
 
-        // Verify checksum
-        let mut hasher = blake3::Hasher::new();
-        hasher.update(&header.encode()?); // Re-encode header to calculate hash
-        hasher.update(&body.encode()?);   // Re-encode body to calculate hash
-        let calculated_hash = hasher.finalize();
+        Ok(Packet { header, body, checksum: _checksum, signature, pq_signature }) // Used _checksum
+    }
 
-        if !_checksum.verify(calculated_hash.as_bytes()) { // Used _checksum
-            return Err(Error::CodecError("Checksum verification failed".to_string()));
+    /// Parses bytes into a Tonitru packet and verifies its signature with the
+    /// public key of the expected sender. Fails if the packet is not signed.
+    pub fn parse_packet_verified(data: &[u8], public_key: &VerifyingKey) -> Result<Self> {
+        let packet = Self::parse_packet(data)?;
+        packet.verify_signature(public_key)?;
+        Ok(packet)
+    }
+
+    /// Verifies the signature of the packet with the public key of the
+    /// expected sender. Fails if the packet is not signed.
+    pub fn verify_signature(&self, public_key: &VerifyingKey) -> Result<()> {
+        let signature = self.signature.as_ref()
+            .ok_or_else(|| Error::EncryptionError("Packet is not signed".to_string()))?;
+        fips::check_signatures()?;
+        public_key.verify(&signed_message(&self.checksum), signature)
+    }
+
+    /// Parses bytes into a Tonitru packet and verifies its signatures with the
+    /// public keys known for the expected sender, under the policy.
+    pub fn parse_packet_verified_hybrid(data: &[u8], public_key: &HybridVerifyingKey, policy: SignaturePolicy) -> Result<Self> {
+        let packet = Self::parse_packet(data)?;
+        packet.verify_hybrid_signature(public_key, policy)?;
+        Ok(packet)
+    }
+
+    /// Verifies the Ed25519 and Dilithium signatures of the packet with the
+    /// public keys known for the expected sender, under the policy.
+    pub fn verify_hybrid_signature(&self, public_key: &HybridVerifyingKey, policy: SignaturePolicy) -> Result<()> {
+        if self.signature.is_none() && self.pq_signature.is_none() {
+            return Err(Error::EncryptionError("Packet is not signed".to_string()));
         }
+        fips::check_signatures()?;
+        public_key.verify(
+            &signed_message(&self.checksum),
+            self.signature.as_ref(),
+            self.pq_signature.as_deref(),
+            policy,
+        )
+    }
+
+    /// Decrypts an encrypted body with the encryptor registered for the
+    /// strategy recorded in the header, under the recorded key ID, and
+    /// removes the padding of the plaintext if any.
+    pub fn decrypt_body(&self, encryptors: &HashMap<EncryptionStrategy, Box<dyn Encryptor>>) -> Result<Vec<u8>> {
+        let DataBody::Encrypted(sealed) = &self.body else {
+            return Err(Error::EncryptionError("Packet body is not encrypted".to_string()));
+        };
+        self.header.unpad(self.decrypt_layer(sealed, encryptors)?)
+    }
 
-        Ok(Packet { header, body, checksum: _checksum }) // Used _checksum
+    /// Decrypts the encrypted layer of the body, which is the body itself
+    /// unless the body was compressed after encryption.
+    pub(crate) fn decrypt_layer(&self, sealed: &[u8], encryptors: &HashMap<EncryptionStrategy, Box<dyn Encryptor>>) -> Result<Vec<u8>> {
+        let strategy = self.header.get_encryption_strategy()?;
+        fips::check_approved(strategy)?;
+        let encryptor = encryptors.get(&strategy).ok_or_else(|| {
+            Error::EncryptionError(format!("No encryptor registered for {:?}", strategy))
+        })?;
+        encryptor.decrypt(sealed, self.header.key_id.as_deref())
+    }
+
+    /// Encodes the packet into bytes, the inverse of `parse_packet`.
+    pub fn encode_packet(&self) -> Result<Vec<u8>> {
+        let mut data = self.header.encode()?;
+        data.extend_from_slice(&self.body.encode()?);
+        data.extend_from_slice(&self.checksum.encode());
+        if let Some(signature) = &self.signature {
+            data.extend_from_slice(signature);
+        }
+        if let Some(signature) = &self.pq_signature {
+            data.extend_from_slice(signature);
+        }
+        Ok(data)
     }
 } // Added closing brace for impl Packet
 
+/// Fails if packets with the header could not be signed safely: a signature
+/// over a checksum that is easy to collide would also cover forged packets.
+/// Also fails in FIPS mode, where packets are not signed.
+fn check_signable(header: &MetadataHeader) -> Result<()> {
+    fips::check_signatures()?;
+    let algorithm = header.get_checksum_algorithm()?;
+    if !algorithm.is_cryptographic() {
+        return Err(Error::EncryptionError(format!("Cannot sign a packet with a non-cryptographic {:?} checksum", algorithm)));
+    }
+    Ok(())
+}
+
+/// Returns the message a packet signature signs.
+fn signed_message(checksum: &Checksum) -> Vec<u8> {
+    let mut message = Vec::with_capacity(SIGNATURE_CONTEXT.len() + checksum.digest.len());
+    message.extend_from_slice(SIGNATURE_CONTEXT);
+    message.extend_from_slice(&checksum.digest);
+    message
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -257,6 +564,7 @@ mod tests {
             shard_id: 10,
             flow_flags: 0b101, // Example flags
             body_type: 0, // Will be set by build_packet
+            key_id: None,
         };
         let body = DataBody::Raw(vec![1, 2, 3, 4, 5]);
 
@@ -291,6 +599,7 @@ mod tests {
             shard_id: 20,
             flow_flags: 0b110,
             body_type: 0, // Will be set by build_packet
+            key_id: None,
         };
         let body = DataBody::Compressed(vec![6, 7, 8, 9, 10]);
 
@@ -321,6 +630,7 @@ mod tests {
             shard_id: 30,
             flow_flags: 0b111,
             body_type: 0, // Will be set by build_packet
+            key_id: None,
         };
         let body = DataBody::Encrypted(vec![11, 12, 13, 14, 15]);
 
@@ -352,11 +662,10 @@ mod tests {
             shard_id: 10,
             flow_flags: 0b101,
             body_type: 0, // Will be set by build_packet
+            key_id: None,
         };
         let body = DataBody::Raw(vec![1, 2, 3, 4, 5]);
 
-        // Removed Lz4 compression strategy setting: header.set_compression_strategy(CompressionStrategy::Lz4);
-
         // Build a valid packet
         let packet = Packet::build_packet(header.clone(), body.clone()).unwrap();
 
@@ -383,6 +692,7 @@ mod tests {
             shard_id: 10,
             flow_flags: 0b101,
             body_type: 0, // Will be set by build_packet
+            key_id: None,
         };
         let body = DataBody::Raw(vec![1, 2, 3, 4, 5]);
 
@@ -416,6 +726,7 @@ mod tests {
             shard_id: 10,
             flow_flags: 0b101,
             body_type: 99, // An unknown body type
+            key_id: None,
         };
         let body = DataBody::Raw(vec![1, 2, 3, 4, 5]);
 
@@ -447,6 +758,7 @@ mod tests {
             shard_id: 456,
             flow_flags: 0, // Start with no flags
             body_type: 0,
+            key_id: None,
         };
 
         // Test setting and getting NoCompression
@@ -459,21 +771,10 @@ mod tests {
         assert_eq!(header.get_compression_strategy().unwrap(), CompressionStrategy::Zstd);
         assert_eq!(header.flow_flags & COMPRESSION_STRATEGY_MASK, 1);
 
-        // Removed Lz4 test:
-        // #[test]
-        // fn test_setting_and_getting_lz4() {
-        //     let mut header = MetadataHeader {
-        //         schema_id: 1,
-        //         timestamp: 123,
-        //         shard_id: 456,
-        //         flow_flags: 0, // Start with no flags
-        //         body_type: 0,
-        //     };
-        //     header.set_compression_strategy(CompressionStrategy::Lz4);
-        //     assert_eq!(header.get_compression_strategy().unwrap(), CompressionStrategy::Lz4);
-        //     assert_eq!(header.flow_flags & COMPRESSION_STRATEGY_MASK, 2);
-        // }
-
+        // Test setting and getting Lz4
+        header.set_compression_strategy(CompressionStrategy::Lz4);
+        assert_eq!(header.get_compression_strategy().unwrap(), CompressionStrategy::Lz4);
+        assert_eq!(header.flow_flags & COMPRESSION_STRATEGY_MASK, 2);
 
         // Test setting and getting Brotli
         header.set_compression_strategy(CompressionStrategy::Brotli);
@@ -487,10 +788,220 @@ mod tests {
             shard_id: 456,
             flow_flags: 0b1111_1100, // Some other flags set
             body_type: 0,
+            key_id: None,
         };
         header_with_other_flags.set_compression_strategy(CompressionStrategy::Zstd);
         assert_eq!(header_with_other_flags.get_compression_strategy().unwrap(), CompressionStrategy::Zstd);
         assert_eq!(header_with_other_flags.flow_flags & COMPRESSION_STRATEGY_MASK, 1);
         assert_eq!(header_with_other_flags.flow_flags & 0b1111_1100, 0b1111_1100); // Other flags should be preserved
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_metadata_header_wire_format_flags() {
+        let mut header = MetadataHeader {
+            schema_id: 1,
+            timestamp: 123,
+            shard_id: 456,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        // Headers without a version are read as v1
+        assert_eq!(header.get_wire_format().unwrap(), WireFormat::V1);
+
+        header.set_compression_strategy(CompressionStrategy::Brotli);
+        header.set_wire_format(WireFormat::V2);
+        assert_eq!(header.get_wire_format().unwrap(), WireFormat::V2);
+        assert_eq!(header.get_compression_strategy().unwrap(), CompressionStrategy::Brotli);
+
+        header.set_wire_format(WireFormat::V1);
+        assert_eq!(header.get_wire_format().unwrap(), WireFormat::V1);
+
+        header.flow_flags |= 0b11 << WIRE_FORMAT_SHIFT;
+        assert!(header.get_wire_format().is_err());
+    }
+
+    #[test]
+    fn test_metadata_header_tag_table_flag() {
+        let mut header = MetadataHeader {
+            schema_id: 1,
+            timestamp: 123,
+            shard_id: 456,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        assert!(!header.has_tag_table());
+
+        header.set_wire_format(WireFormat::V2);
+        header.set_tag_table(true);
+        assert!(header.has_tag_table());
+        assert_eq!(header.get_wire_format().unwrap(), WireFormat::V2);
+
+        header.set_tag_table(false);
+        assert!(!header.has_tag_table());
+        assert_eq!(header.get_wire_format().unwrap(), WireFormat::V2);
+    }
+
+    #[test]
+    fn test_metadata_header_encryption_strategy_and_key_id() {
+        let mut header = MetadataHeader {
+            schema_id: 1,
+            timestamp: 123,
+            shard_id: 456,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        // Headers without a strategy are read as unencrypted
+        assert_eq!(header.get_encryption_strategy().unwrap(), EncryptionStrategy::NoEncryption);
+
+        header.set_tag_table(true);
+        header.set_encryption_strategy(EncryptionStrategy::EccChaCha20Poly1305);
+        assert_eq!(header.get_encryption_strategy().unwrap(), EncryptionStrategy::EccChaCha20Poly1305);
+        header.set_encryption_strategy(EncryptionStrategy::AesGcm);
+        assert_eq!(header.get_encryption_strategy().unwrap(), EncryptionStrategy::AesGcm);
+        assert!(header.has_tag_table());
+
+        // The key ID travels after the header fields and is not left in flow_flags
+        header.key_id = Some("key-2026".to_string());
+        let encoded = header.encode().unwrap();
+        let (decoded, length) = MetadataHeader::decode(&encoded).unwrap();
+        assert_eq!(length, encoded.len());
+        assert_eq!(decoded, header);
+        assert_eq!(decoded.flow_flags & KEY_ID_FLAG, 0);
+
+        header.flow_flags |= ENCRYPTION_STRATEGY_MASK << ENCRYPTION_STRATEGY_SHIFT;
+        let err = header.get_encryption_strategy().unwrap_err();
+        assert_eq!(err.to_string(), "Codec Error: Unknown encryption strategy bits in flow_flags: 15");
+    }
+
+    #[test]
+    #[cfg(not(feature = "fips"))]
+    fn test_packet_decrypt_body_selects_encryptor() {
+        use crate::encrypt::aes_gcm::AesGcmEncryptor;
+        use crate::encrypt::chacha20_poly1305::ChaCha20Poly1305Encryptor;
+
+        let chacha = ChaCha20Poly1305Encryptor::with_key(&[7u8; 32]).unwrap();
+        chacha.add_key("tenant-a", &[9u8; 32]).unwrap();
+        let mut header = MetadataHeader {
+            schema_id: 5,
+            timestamp: 99,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: Some("tenant-a".to_string()),
+        };
+        header.set_encryption_strategy(chacha.strategy());
+        let sealed = chacha.encrypt(b"secret body", Some("tenant-a")).unwrap();
+        let packet = Packet::build_packet(header, DataBody::Encrypted(sealed)).unwrap();
+        let packet = Packet::parse_packet(&packet.encode_packet().unwrap()).unwrap();
+
+        let mut encryptors: HashMap<EncryptionStrategy, Box<dyn Encryptor>> = HashMap::new();
+        encryptors.insert(EncryptionStrategy::AesGcm, Box::new(AesGcmEncryptor::with_key(&[7u8; 32]).unwrap()));
+        let err = packet.decrypt_body(&encryptors).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: No encryptor registered for ChaCha20Poly1305");
+
+        encryptors.insert(EncryptionStrategy::ChaCha20Poly1305, Box::new(chacha));
+        assert_eq!(packet.decrypt_body(&encryptors).unwrap(), b"secret body");
+    }
+
+    #[test]
+    fn test_packet_signed_round_trip_and_tampering() {
+        let sender = SigningKey::generate();
+        let public_key = sender.verifying_key();
+        let header = MetadataHeader {
+            schema_id: 6,
+            timestamp: 100,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        let packet = Packet::build_packet_signed(header.clone(), DataBody::Raw(vec![1, 2, 3]), &sender).unwrap();
+        assert!(packet.header.is_signed());
+        let encoded = packet.encode_packet().unwrap();
+        assert_eq!(Packet::parse_packet_verified(&encoded, &public_key).unwrap(), packet);
+        assert_eq!(Packet::parse_packet(&encoded).unwrap(), packet);
+
+        // A forged signature, a corrupted one and a missing one are all rejected
+        let forged = Packet::build_packet_signed(header.clone(), DataBody::Raw(vec![9]), &SigningKey::generate()).unwrap();
+        let err = Packet::parse_packet_verified(&forged.encode_packet().unwrap(), &public_key).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Signature verification failed");
+        let mut corrupted = encoded.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(Packet::parse_packet_verified(&corrupted, &public_key).is_err());
+        let unsigned = Packet::build_packet(packet.header.clone(), DataBody::Raw(vec![1, 2, 3])).unwrap();
+        assert!(!unsigned.header.is_signed());
+        let err = Packet::parse_packet_verified(&unsigned.encode_packet().unwrap(), &public_key).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Packet is not signed");
+    }
+
+    #[test]
+    fn test_packet_hybrid_signed_round_trip() {
+        let sender = HybridSigningKey::generate();
+        let public_key = sender.verifying_key();
+        let header = MetadataHeader {
+            schema_id: 6,
+            timestamp: 100,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        let packet = Packet::build_packet_hybrid_signed(header.clone(), DataBody::Raw(vec![1, 2, 3]), &sender).unwrap();
+        assert!(packet.header.is_signed() && packet.header.is_pq_signed());
+        let encoded = packet.encode_packet().unwrap();
+        assert_eq!(Packet::parse_packet_verified_hybrid(&encoded, &public_key, SignaturePolicy::Both).unwrap(), packet);
+        // Receivers that only know the Ed25519 key still verify the packet
+        let classical = sender.classical().verifying_key();
+        assert_eq!(Packet::parse_packet_verified(&encoded, &classical).unwrap(), packet);
+        let classical_only = HybridVerifyingKey::new(Some(classical), None);
+        assert!(Packet::parse_packet_verified_hybrid(&encoded, &classical_only, SignaturePolicy::Either).is_ok());
+        assert!(Packet::parse_packet_verified_hybrid(&encoded, &classical_only, SignaturePolicy::Both).is_err());
+
+        // Packets signed with Ed25519 alone fail the Both policy, and a
+        // corrupted Dilithium signature fails either policy
+        let signed = Packet::build_packet_signed(packet.header.clone(), DataBody::Raw(vec![1, 2, 3]), sender.classical()).unwrap();
+        assert!(!signed.header.is_pq_signed());
+        let signed = signed.encode_packet().unwrap();
+        assert!(Packet::parse_packet_verified_hybrid(&signed, &public_key, SignaturePolicy::Either).is_ok());
+        assert!(Packet::parse_packet_verified_hybrid(&signed, &public_key, SignaturePolicy::Both).is_err());
+        let mut corrupted = encoded.clone();
+        *corrupted.last_mut().unwrap() ^= 1;
+        assert!(Packet::parse_packet_verified_hybrid(&corrupted, &public_key, SignaturePolicy::Either).is_err());
+    }
+
+    #[test]
+    fn test_packet_checksum_algorithms() {
+        for (algorithm, length) in [
+            (ChecksumAlgorithm::Blake3, 32),
+            (ChecksumAlgorithm::Crc32c, 4),
+            (ChecksumAlgorithm::Xxh3, 16),
+            (ChecksumAlgorithm::Sha256, 32),
+        ] {
+            let mut header = MetadataHeader {
+                schema_id: 7,
+                timestamp: 100,
+                shard_id: 0,
+                flow_flags: 0,
+                body_type: 0,
+                key_id: None,
+            };
+            header.set_checksum_algorithm(algorithm);
+            let packet = Packet::build_packet(header.clone(), DataBody::Raw(vec![1, 2, 3])).unwrap();
+            assert_eq!(packet.checksum.algorithm, algorithm);
+            assert_eq!(packet.checksum.digest.len(), length);
+            let mut encoded = packet.encode_packet().unwrap();
+            assert_eq!(Packet::parse_packet(&encoded).unwrap(), packet);
+
+            let body_end = encoded.len() - length - 1;
+            encoded[body_end] ^= 1;
+            assert!(Packet::parse_packet(&encoded).is_err(), "{:?} missed a corrupted body", algorithm);
+
+            // Signatures need a checksum that cannot be forged
+            let signed = Packet::build_packet_signed(header, DataBody::Raw(vec![1]), &SigningKey::generate());
+            assert_eq!(signed.is_ok(), algorithm.is_cryptographic());
+        }
+    }
+}
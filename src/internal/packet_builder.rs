@@ -0,0 +1,372 @@
+// Typestate builder for Tonitru packets
+//
+// `PacketBuilder` tracks in its type parameter which stage the body has
+// reached, so misuse is rejected by the compiler instead of at runtime: a
+// packet cannot be built without a body, a body is compressed at most once and
+// never after encryption, and encryption is only reachable by handing over the
+// keyed encryptor that seals the body. A padded body must be encrypted next:
+// padding sent in the clear would hide nothing.
+
+use std::marker::PhantomData;
+
+use crate::codec::encode::encode_item;
+use crate::codec::types::HtlvItem;
+use crate::codec::wire::WireFormat;
+use crate::compress::{compress_or_passthrough, CompressionStrategy};
+use crate::encrypt::{fips, Encryptor};
+use crate::internal::checksum::ChecksumAlgorithm;
+use crate::internal::error::Result;
+use crate::internal::packet::{DataBody, DataBodyType, MetadataHeader, Packet};
+use crate::internal::padding::{self, PaddingPolicy};
+use crate::internal::progress::{ProgressReporter, ProgressStage};
+use crate::internal::cancel::{self, CancellationToken};
+
+/// No body has been set yet; the packet cannot be built.
+#[derive(Debug)]
+pub struct NoBody;
+
+/// The body is set and neither compressed nor encrypted.
+#[derive(Debug)]
+pub struct Plain;
+
+/// The body went through compression; it cannot be compressed again.
+#[derive(Debug)]
+pub struct Compressed;
+
+/// The body is padded; it must be encrypted before the packet is built.
+#[derive(Debug)]
+pub struct Padded;
+
+/// The body is encrypted; it can neither be compressed nor encrypted again.
+#[derive(Debug)]
+pub struct Encrypted;
+
+mod sealed {
+    pub trait Sealed {}
+    impl Sealed for super::NoBody {}
+    impl Sealed for super::Plain {}
+    impl Sealed for super::Compressed {}
+    impl Sealed for super::Padded {}
+    impl Sealed for super::Encrypted {}
+}
+
+/// A stage of the packet body.
+pub trait BodyState: sealed::Sealed {}
+impl BodyState for NoBody {}
+impl BodyState for Plain {}
+impl BodyState for Compressed {}
+impl BodyState for Padded {}
+impl BodyState for Encrypted {}
+
+/// A stage at which the packet can be built.
+pub trait Buildable: BodyState {}
+impl Buildable for Plain {}
+impl Buildable for Compressed {}
+impl Buildable for Encrypted {}
+
+/// A stage from which the body can be encrypted.
+pub trait Encryptable: BodyState {}
+impl Encryptable for Plain {}
+impl Encryptable for Compressed {}
+impl Encryptable for Padded {}
+
+/// A stage from which the body can be padded.
+pub trait Paddable: BodyState {}
+impl Paddable for Plain {}
+impl Paddable for Compressed {}
+
+/// Builder for packets, checking the order of body stages at compile time.
+///
+/// ```
+/// use tonitru::compress::CompressionStrategy;
+/// use tonitru::encrypt::aes_gcm::AesGcmEncryptor;
+/// use tonitru::internal::packet_builder::PacketBuilder;
+///
+/// let encryptor = AesGcmEncryptor::with_key(&[7u8; 32]).unwrap();
+/// let packet = PacketBuilder::new(42)
+///     .timestamp(1_700_000_000)
+///     .body(vec![0u8; 256])
+///     .compress(CompressionStrategy::Zstd).unwrap()
+///     .encrypt(&encryptor, None).unwrap()
+///     .build().unwrap();
+/// ```
+///
+/// Building without a body does not compile:
+///
+/// ```compile_fail
+/// use tonitru::internal::packet_builder::PacketBuilder;
+/// let packet = PacketBuilder::new(1).timestamp(5).build();
+/// ```
+///
+/// Neither does compressing twice, or compressing after encryption:
+///
+/// ```compile_fail
+/// use tonitru::compress::CompressionStrategy;
+/// use tonitru::internal::packet_builder::PacketBuilder;
+/// let builder = PacketBuilder::new(1).body(vec![1, 2, 3])
+///     .compress(CompressionStrategy::Zstd).unwrap()
+///     .compress(CompressionStrategy::Brotli);
+/// ```
+///
+/// Nor does building a padded body without encrypting it:
+///
+/// ```compile_fail
+/// use tonitru::internal::packet_builder::PacketBuilder;
+/// use tonitru::internal::padding::PaddingPolicy;
+/// let packet = PacketBuilder::new(1).body(vec![1, 2, 3]).pad(&PaddingPolicy::Padme).unwrap().build();
+/// ```
+#[derive(Debug)]
+pub struct PacketBuilder<S: BodyState> {
+    header: MetadataHeader,
+    body: Vec<u8>,
+    progress: Option<ProgressReporter>,
+    cancellation: Option<CancellationToken>,
+    state: PhantomData<S>,
+}
+
+impl PacketBuilder<NoBody> {
+    /// Starts a packet for the given schema, with all other header fields zero,
+    /// except for the SHA-256 checksum algorithm in FIPS mode.
+    pub fn new(schema_id: u64) -> Self {
+        let mut header = MetadataHeader {
+            schema_id,
+            timestamp: 0,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: DataBodyType::Raw as u8,
+            key_id: None,
+        };
+        // BLAKE3, the default otherwise, is not FIPS approved
+        if fips::ENABLED {
+            header.set_checksum_algorithm(ChecksumAlgorithm::Sha256);
+        }
+        PacketBuilder {
+            header,
+            body: Vec::new(),
+            progress: None,
+            cancellation: None,
+            state: PhantomData,
+        }
+    }
+
+    /// Sets the raw body.
+    pub fn body(self, body: Vec<u8>) -> PacketBuilder<Plain> {
+        self.into_state(body)
+    }
+
+    /// Sets the body to the encoding of an item.
+    pub fn item(self, item: &HtlvItem) -> Result<PacketBuilder<Plain>> {
+        cancel::check(&self.cancellation, "Packet encoding")?;
+        let body = encode_item(item)?;
+        self.report(ProgressStage::Encode, body.len(), body.len());
+        Ok(self.into_state(body))
+    }
+}
+
+impl<S: BodyState> PacketBuilder<S> {
+    /// Sets the timestamp.
+    pub fn timestamp(mut self, timestamp: u64) -> Self {
+        self.header.timestamp = timestamp;
+        self
+    }
+
+    /// Sets the shard ID.
+    pub fn shard_id(mut self, shard_id: u64) -> Self {
+        self.header.shard_id = shard_id;
+        self
+    }
+
+    /// Sets the wire format version of the body items.
+    pub fn wire_format(mut self, format: WireFormat) -> Self {
+        self.header.set_wire_format(format);
+        self
+    }
+
+    /// Marks the body as starting with a tag table.
+    pub fn tag_table(mut self, has_tag_table: bool) -> Self {
+        self.header.set_tag_table(has_tag_table);
+        self
+    }
+
+    /// Sets the checksum algorithm of the packet trailer.
+    pub fn checksum(mut self, algorithm: ChecksumAlgorithm) -> Self {
+        self.header.set_checksum_algorithm(algorithm);
+        self
+    }
+
+    /// Reports the completion of each stage to the observer.
+    pub fn progress(mut self, progress: ProgressReporter) -> Self {
+        self.progress = Some(progress);
+        self
+    }
+
+    /// Fails the next stage with `Error::Cancelled` once the token is cancelled.
+    pub fn cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    fn report(&self, stage: ProgressStage, bytes_processed: usize, total_bytes: usize) {
+        if let Some(progress) = &self.progress {
+            progress.report(stage, bytes_processed as u64, total_bytes as u64, 0);
+        }
+    }
+
+    fn into_state<T: BodyState>(self, body: Vec<u8>) -> PacketBuilder<T> {
+        PacketBuilder {
+            header: self.header,
+            body,
+            progress: self.progress,
+            cancellation: self.cancellation,
+            state: PhantomData,
+        }
+    }
+}
+
+impl PacketBuilder<Plain> {
+    /// Compresses the body, unless compression would not help; the strategy
+    /// actually applied is recorded in the header.
+    pub fn compress(mut self, strategy: CompressionStrategy) -> Result<PacketBuilder<Compressed>> {
+        cancel::check(&self.cancellation, "Packet compression")?;
+        let (applied, body) = compress_or_passthrough(strategy, &self.body)?;
+        self.report(ProgressStage::Compress, self.body.len(), self.body.len());
+        self.header.set_compression_strategy(applied);
+        if applied != CompressionStrategy::NoCompression {
+            self.header.body_type = DataBodyType::Compressed as u8;
+        }
+        Ok(self.into_state(body))
+    }
+}
+
+impl<S: Paddable> PacketBuilder<S> {
+    /// Pads the body under the policy so that its encrypted length reveals
+    /// less of its contents. The pad length is recorded inside the body, and
+    /// the header marks the body as padded.
+    pub fn pad(mut self, policy: &PaddingPolicy) -> Result<PacketBuilder<Padded>> {
+        cancel::check(&self.cancellation, "Packet padding")?;
+        let body = padding::pad(std::mem::take(&mut self.body), policy)?;
+        self.header.set_padded(true);
+        Ok(self.into_state(body))
+    }
+}
+
+impl<S: Encryptable> PacketBuilder<S> {
+    /// Encrypts the body with the encryptor, which holds the key, under the
+    /// optional key ID. The encryptor's strategy and the key ID are recorded in
+    /// the header, so that `Packet::decrypt_body` can select the encryptor.
+    pub fn encrypt(mut self, encryptor: &dyn Encryptor, key_id: Option<&str>) -> Result<PacketBuilder<Encrypted>> {
+        cancel::check(&self.cancellation, "Packet encryption")?;
+        fips::check_approved(encryptor.strategy())?;
+        let body = encryptor.encrypt(&self.body, key_id)?;
+        self.report(ProgressStage::Encrypt, self.body.len(), self.body.len());
+        self.header.body_type = DataBodyType::Encrypted as u8;
+        self.header.set_encryption_strategy(encryptor.strategy());
+        self.header.key_id = key_id.map(str::to_string);
+        Ok(self.into_state(body))
+    }
+}
+
+impl<S: Buildable> PacketBuilder<S> {
+    /// Builds the packet and computes its checksum.
+    pub fn build(self) -> Result<Packet> {
+        cancel::check(&self.cancellation, "Packet building")?;
+        let length = self.body.len();
+        let body = match DataBodyType::from_u8(self.header.body_type)? {
+            DataBodyType::Raw => DataBody::Raw(self.body),
+            DataBodyType::Compressed => DataBody::Compressed(self.body),
+            DataBodyType::Encrypted => DataBody::Encrypted(self.body),
+        };
+        let packet = Packet::build_packet(self.header, body)?;
+        if let Some(progress) = &self.progress {
+            progress.report(ProgressStage::Build, length as u64, length as u64, 0);
+        }
+        Ok(packet)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::get_compressor;
+    use crate::encrypt::aes_gcm::AesGcmEncryptor;
+
+    #[test]
+    fn test_build_compressed_and_encrypted() {
+        let body = vec![5u8; 1024];
+        let packet = PacketBuilder::new(9).timestamp(3).body(body.clone())
+            .compress(CompressionStrategy::Zstd).unwrap()
+            .build().unwrap();
+        assert_eq!(packet.header.get_compression_strategy().unwrap(), CompressionStrategy::Zstd);
+        let DataBody::Compressed(compressed) = &packet.body else { panic!("Expected compressed body") };
+        assert_eq!(get_compressor(CompressionStrategy::Zstd).unwrap().decompress(compressed).unwrap(), body);
+
+        let encryptor = AesGcmEncryptor::with_key(&[1u8; 32]).unwrap();
+        let packet = PacketBuilder::new(9).body(body.clone())
+            .encrypt(&encryptor, None).unwrap()
+            .shard_id(4)
+            .build().unwrap();
+        assert_eq!(packet.header.shard_id, 4);
+        assert_eq!(packet.header.get_encryption_strategy().unwrap(), encryptor.strategy());
+        let DataBody::Encrypted(sealed) = &packet.body else { panic!("Expected encrypted body") };
+        assert_eq!(encryptor.decrypt(sealed, None).unwrap(), body);
+        assert_eq!(Packet::parse_packet(&packet.encode_packet().unwrap()).unwrap(), packet);
+    }
+
+    #[test]
+    fn test_stages_report_progress() {
+        use std::sync::{Arc, Mutex};
+
+        let stages = Arc::new(Mutex::new(Vec::new()));
+        let seen = Arc::clone(&stages);
+        let reporter = ProgressReporter::new(move |progress: &crate::internal::progress::Progress| {
+            seen.lock().unwrap().push(progress.stage)
+        });
+        let item = HtlvItem::new(1, crate::codec::types::HtlvValue::String(bytes::Bytes::from("a".repeat(512))));
+        let encryptor = AesGcmEncryptor::with_key(&[2u8; 32]).unwrap();
+        PacketBuilder::new(1).progress(reporter).item(&item).unwrap()
+            .compress(CompressionStrategy::Zstd).unwrap()
+            .encrypt(&encryptor, None).unwrap()
+            .build().unwrap();
+        assert_eq!(*stages.lock().unwrap(), vec![
+            ProgressStage::Encode, ProgressStage::Compress, ProgressStage::Encrypt, ProgressStage::Build,
+        ]);
+    }
+
+    #[test]
+    fn test_cancelled_builder_stops_at_next_stage() {
+        let token = CancellationToken::new();
+        let builder = PacketBuilder::new(1).cancellation(token.clone()).body(vec![7u8; 256])
+            .compress(CompressionStrategy::Zstd).unwrap();
+        token.cancel();
+        let err = builder.build().unwrap_err();
+        assert_eq!(err.to_string(), "Cancelled: Packet building was cancelled");
+    }
+
+    #[test]
+    fn test_padded_body_hides_its_length() {
+        let encryptor = AesGcmEncryptor::with_key(&[3u8; 32]).unwrap();
+        let policy = PaddingPolicy::Buckets(vec![128, 512]);
+        let sealed: Vec<_> = [10, 100].iter().map(|len| {
+            PacketBuilder::new(1).body(vec![9u8; *len])
+                .pad(&policy).unwrap()
+                .encrypt(&encryptor, None).unwrap()
+                .build().unwrap()
+        }).collect();
+        assert_eq!(sealed[0].body.encode().unwrap().len(), sealed[1].body.encode().unwrap().len());
+
+        let mut encryptors: std::collections::HashMap<_, Box<dyn Encryptor>> = std::collections::HashMap::new();
+        encryptors.insert(encryptor.strategy(), Box::new(encryptor));
+        let parsed = Packet::parse_packet(&sealed[1].encode_packet().unwrap()).unwrap();
+        assert!(parsed.header.is_padded());
+        assert_eq!(parsed.decrypt_body(&encryptors).unwrap(), vec![9u8; 100]);
+    }
+
+    #[test]
+    fn test_incompressible_body_stays_raw() {
+        let packet = PacketBuilder::new(1).body(vec![1, 2, 3])
+            .compress(CompressionStrategy::Zstd).unwrap()
+            .build().unwrap();
+        assert_eq!(packet.body, DataBody::Raw(vec![1, 2, 3]));
+        assert_eq!(packet.header.get_compression_strategy().unwrap(), CompressionStrategy::NoCompression);
+    }
+}
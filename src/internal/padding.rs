@@ -0,0 +1,116 @@
+// Padding of packet bodies against traffic analysis
+//
+// Encryption hides what a telemetry packet says but not how long it is, and
+// sizes alone often tell readings apart. A `PaddingPolicy` rounds the body up
+// to one of a few lengths before it is encrypted:
+//
+//   Buckets  the smallest of a fixed list of sizes the body fits in, and
+//            multiples of the largest beyond it
+//   Padme    PADMÉ (Nikitin et al., 2019): keeps the top bits of the length
+//            and zeroes the rest, costing at most about 12% of overhead while
+//            leaving O(log log L) bits of the length L visible
+//
+// A padded body is the body, zero bytes, then the number of zero bytes as a
+// 32-bit little-endian trailer, so the pad length travels inside the
+// encrypted envelope. The packet header only records that the body is padded.
+
+use crate::internal::error::{Error, Result};
+
+/// Length of the trailer recording the pad length
+pub const PAD_TRAILER_LEN: usize = 4;
+
+/// How a body is padded before encryption
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PaddingPolicy {
+    /// Pads to the smallest size of the list that fits, or to a multiple of
+    /// the largest
+    Buckets(Vec<usize>),
+    /// Pads to the PADMÉ length
+    Padme,
+}
+
+impl PaddingPolicy {
+    /// Returns the padded length of a body of `len` bytes, trailer included
+    pub fn padded_len(&self, len: usize) -> Result<usize> {
+        let len = len.checked_add(PAD_TRAILER_LEN).ok_or_else(too_long)?;
+        match self {
+            PaddingPolicy::Buckets(sizes) => {
+                let largest = sizes.iter().copied().max().filter(|largest| *largest > 0).ok_or_else(|| {
+                    Error::ConfigError("Padding buckets must include a non-zero size".to_string())
+                })?;
+                match sizes.iter().copied().filter(|size| *size >= len).min() {
+                    Some(size) => Ok(size),
+                    None => len.div_ceil(largest).checked_mul(largest).ok_or_else(too_long),
+                }
+            }
+            PaddingPolicy::Padme => Ok(padme(len)),
+        }
+    }
+}
+
+fn too_long() -> Error {
+    Error::ConfigError("Body is too long to pad".to_string())
+}
+
+/// Returns the PADMÉ length of `len`
+fn padme(len: usize) -> usize {
+    if len < 2 {
+        return len;
+    }
+    let exponent = usize::BITS - 1 - len.leading_zeros();
+    let exponent_bits = u32::BITS - exponent.leading_zeros();
+    let mask = (1usize << (exponent - exponent_bits)) - 1;
+    (len + mask) & !mask
+}
+
+/// Pads a body under the policy, appending the zero bytes and the trailer
+pub fn pad(mut body: Vec<u8>, policy: &PaddingPolicy) -> Result<Vec<u8>> {
+    let padded_len = policy.padded_len(body.len())?;
+    let pad_len = padded_len - body.len() - PAD_TRAILER_LEN;
+    let pad_len_field = u32::try_from(pad_len).map_err(|_| too_long())?;
+    body.resize(body.len() + pad_len, 0);
+    body.extend_from_slice(&pad_len_field.to_le_bytes());
+    Ok(body)
+}
+
+/// Removes the padding added by `pad`
+pub fn unpad(mut padded: Vec<u8>) -> Result<Vec<u8>> {
+    let invalid = || Error::ProtocolError("Invalid padding of decrypted body".to_string());
+    let trailer_start = padded.len().checked_sub(PAD_TRAILER_LEN).ok_or_else(invalid)?;
+    let trailer: [u8; PAD_TRAILER_LEN] = padded[trailer_start..].try_into().map_err(|_| invalid())?;
+    let body_len = trailer_start.checked_sub(u32::from_le_bytes(trailer) as usize).ok_or_else(invalid)?;
+    if padded[body_len..trailer_start].iter().any(|byte| *byte != 0) {
+        return Err(invalid());
+    }
+    padded.truncate(body_len);
+    Ok(padded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_policies_and_round_trip() {
+        let buckets = PaddingPolicy::Buckets(vec![64, 256, 1024]);
+        assert_eq!(buckets.padded_len(10).unwrap(), 64);
+        assert_eq!(buckets.padded_len(60).unwrap(), 64);
+        assert_eq!(buckets.padded_len(61).unwrap(), 256);
+        assert_eq!(buckets.padded_len(3000).unwrap(), 3072);
+        assert!(PaddingPolicy::Buckets(vec![]).padded_len(1).is_err());
+
+        // PADMÉ keeps the top bits: 9 -> 10, 1000 -> 1024 (plus the trailer)
+        assert_eq!(PaddingPolicy::Padme.padded_len(5).unwrap(), 10);
+        assert_eq!(PaddingPolicy::Padme.padded_len(1000).unwrap(), 1024);
+        assert_eq!(PaddingPolicy::Padme.padded_len(65_536 - 4).unwrap(), 65_536);
+
+        for body in [vec![], vec![0u8; 3], b"reading 21.5".to_vec()] {
+            let padded = pad(body.clone(), &buckets).unwrap();
+            assert_eq!(padded.len(), 64);
+            assert_eq!(unpad(padded).unwrap(), body);
+        }
+        assert!(unpad(vec![1, 0, 0, 0, 0, 0]).is_ok());
+        assert!(unpad(vec![1, 1, 0, 0, 0]).is_err());
+        assert!(unpad(vec![9, 0, 0, 0]).is_err());
+    }
+}
@@ -1,9 +1,35 @@
 // Tonitru library entry point
 // Core modules will be defined here
+//
+// Thread safety: values, packets, configurations, builders, encryptors,
+// compressors, key managers, views and the shared sinks (`Diagnostics`,
+// `ProgressReporter`, `CancellationToken`) are `Send + Sync`, and the traits
+// users implement for them require it. The exceptions are single-threaded by
+// design: `LocalDecoder` and `LocalPoolAllocator` for thread-per-core
+// runtimes. The tests below keep this list honest.
+
+// Lets code generated by tonitru-derive refer to `::tonitru` inside this crate too
+extern crate self as tonitru;
 
 pub mod codec;
 pub mod internal;
+pub mod config; // Crate-wide configuration
 pub mod compress; // Declare the compress module
+pub mod protocol; // Declare the protocol module
+pub mod schema; // Declare the schema module
+pub mod detect; // Content sniffing for Tonitru artifacts
+pub mod encrypt; // Encryption and key management
+pub mod archive; // .tna archive container
+pub mod view; // Materialized views over streamed packets
+pub mod serde; // Serde integration for HtlvValue
+pub mod pipeline; // Compress + encrypt + packet in one call
+pub mod perf; // Throughput suite for performance gates in CI
+
+pub use config::{TonitruConfig, TonitruConfigBuilder};
+pub use detect::{detect, ContentKind};
+
+#[doc(hidden)]
+pub use bytes as __bytes; // Used by code generated by tonitru-derive
 
 #[cfg(test)]
 mod tests {
@@ -11,4 +37,34 @@ mod tests {
     fn it_works() {
         assert_eq!(2 + 2, 4);
     }
+
+    #[test]
+    fn public_types_are_send_and_sync() {
+        fn send_sync<T: Send + Sync>() {}
+
+        send_sync::<crate::codec::types::HtlvItem>();
+        send_sync::<crate::TonitruConfig>();
+        send_sync::<crate::internal::packet::Packet>();
+        send_sync::<crate::internal::packet_builder::PacketBuilder<crate::internal::packet_builder::Plain>>();
+        send_sync::<crate::internal::framing::PacketReader<tokio::io::DuplexStream>>();
+        send_sync::<crate::pipeline::PipelineBuilder>();
+        send_sync::<Box<dyn crate::compress::Compressor>>();
+        send_sync::<Box<dyn crate::encrypt::Encryptor>>();
+        send_sync::<crate::encrypt::field_level::FieldLevelEncryptor>();
+        send_sync::<crate::encrypt::field_level::FieldLevelProcessor>();
+        send_sync::<crate::encrypt::key_management::KeyManager>();
+        send_sync::<crate::encrypt::guarded::GuardedDecryptor>();
+        send_sync::<crate::encrypt::hybrid_signature::HybridSigningKey>();
+        send_sync::<crate::encrypt::ratchet::RatchetEncryptor>();
+        send_sync::<crate::compress::sharded::ShardedCompressor>();
+        send_sync::<crate::internal::alloc::PoolAllocator>();
+        send_sync::<crate::internal::diagnostics::Diagnostics>();
+        send_sync::<crate::internal::progress::ProgressReporter>();
+        send_sync::<crate::internal::cancel::CancellationToken>();
+        send_sync::<crate::internal::anomaly::AnomalyDetector>();
+        send_sync::<crate::internal::deadline::Deadline>();
+        send_sync::<crate::view::View>();
+        send_sync::<crate::archive::log::PacketLog>();
+        send_sync::<crate::schema::parser::SchemaParser>();
+    }
 }
\ No newline at end of file
@@ -0,0 +1,302 @@
+// Throughput measurements for performance gates in CI
+//
+// The benches under `benches/` measure this crate on the machines of its
+// developers. Downstream projects care about their own hardware: `run_suite`
+// times encoding, decoding, compression and encryption of a representative
+// document on the machine it runs on and returns structured results, which a
+// CI job compares against throughput floors with `SuiteReport::violations`.
+//
+// Each operation runs for at least the configured measuring time after one
+// warm-up iteration, so results are stable enough for floors with some margin;
+// they are not a substitute for a statistical benchmark harness.
+
+use std::fmt;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use bytes::Bytes;
+
+use crate::codec::decode::decode_item;
+use crate::codec::encode::{encode_item, encoded_len};
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::compress::{get_compressor, CompressionStrategy};
+use crate::encrypt::aes_gcm::AesGcmEncryptor;
+use crate::encrypt::chacha20_poly1305::ChaCha20Poly1305Encryptor;
+use crate::encrypt::{EncryptionStrategy, Encryptor};
+use crate::internal::error::{Error, Result};
+
+/// An operation measured by the suite.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PerfOperation {
+    /// Encoding the document with `encode_item`
+    Encode,
+    /// Decoding the encoded document with `decode_item`
+    Decode,
+    /// Compressing the encoded document
+    Compress(CompressionStrategy),
+    /// Decompressing the compressed document
+    Decompress(CompressionStrategy),
+    /// Encrypting the encoded document
+    Encrypt(EncryptionStrategy),
+    /// Decrypting the encrypted document
+    Decrypt(EncryptionStrategy),
+}
+
+impl fmt::Display for PerfOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PerfOperation::Encode => write!(f, "encode"),
+            PerfOperation::Decode => write!(f, "decode"),
+            PerfOperation::Compress(strategy) => write!(f, "compress({:?})", strategy),
+            PerfOperation::Decompress(strategy) => write!(f, "decompress({:?})", strategy),
+            PerfOperation::Encrypt(strategy) => write!(f, "encrypt({:?})", strategy),
+            PerfOperation::Decrypt(strategy) => write!(f, "decrypt({:?})", strategy),
+        }
+    }
+}
+
+/// Configuration of the suite.
+#[derive(Debug, Clone)]
+pub struct SuiteConfig {
+    /// Approximate encoded size of the document, in bytes
+    pub payload_size: usize,
+    /// Minimum time each operation is measured for
+    pub measure_time: Duration,
+    /// Minimum number of measured iterations of each operation
+    pub min_iterations: u64,
+    /// Compression strategies to measure
+    pub compression: Vec<CompressionStrategy>,
+    /// Encryption strategies to measure; only symmetric ciphers are supported
+    pub encryption: Vec<EncryptionStrategy>,
+}
+
+impl Default for SuiteConfig {
+    fn default() -> Self {
+        SuiteConfig {
+            payload_size: 64 * 1024,
+            measure_time: Duration::from_millis(200),
+            min_iterations: 10,
+            compression: vec![CompressionStrategy::Zstd, CompressionStrategy::Lz4],
+            encryption: vec![EncryptionStrategy::AesGcm, EncryptionStrategy::ChaCha20Poly1305],
+        }
+    }
+}
+
+/// The measurement of one operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PerfResult {
+    /// The measured operation
+    pub operation: PerfOperation,
+    /// Input bytes processed by one iteration
+    pub bytes_per_iteration: u64,
+    /// Number of measured iterations
+    pub iterations: u64,
+    /// Time taken by the measured iterations
+    pub elapsed: Duration,
+}
+
+impl PerfResult {
+    /// Returns the throughput in MiB of input per second.
+    pub fn throughput_mib_s(&self) -> f64 {
+        let bytes = (self.bytes_per_iteration * self.iterations) as f64;
+        bytes / (1024.0 * 1024.0) / self.elapsed.as_secs_f64().max(f64::MIN_POSITIVE)
+    }
+
+    /// Returns the average time of one iteration.
+    pub fn time_per_iteration(&self) -> Duration {
+        Duration::from_secs_f64(self.elapsed.as_secs_f64() / self.iterations.max(1) as f64)
+    }
+}
+
+/// A minimum throughput for an operation.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ThroughputFloor {
+    /// The operation the floor applies to
+    pub operation: PerfOperation,
+    /// Minimum throughput in MiB per second
+    pub min_mib_s: f64,
+}
+
+/// An operation slower than its floor, or not measured at all.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FloorViolation {
+    /// The floor that was not met
+    pub floor: ThroughputFloor,
+    /// The measured throughput, or `None` if the operation was not measured
+    pub measured_mib_s: Option<f64>,
+}
+
+impl fmt::Display for FloorViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.measured_mib_s {
+            Some(measured) => write!(
+                f,
+                "{} ran at {:.1} MiB/s, below the floor of {:.1} MiB/s",
+                self.floor.operation, measured, self.floor.min_mib_s
+            ),
+            None => write!(f, "{} was not measured", self.floor.operation),
+        }
+    }
+}
+
+/// The results of a suite run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SuiteReport {
+    /// Size of the encoded document, in bytes
+    pub encoded_size: usize,
+    /// One result per measured operation, in the order they ran
+    pub results: Vec<PerfResult>,
+}
+
+impl SuiteReport {
+    /// Returns the result of an operation, if it was measured.
+    pub fn get(&self, operation: PerfOperation) -> Option<&PerfResult> {
+        self.results.iter().find(|result| result.operation == operation)
+    }
+
+    /// Returns the floors the measured throughputs do not meet.
+    pub fn violations(&self, floors: &[ThroughputFloor]) -> Vec<FloorViolation> {
+        floors
+            .iter()
+            .filter_map(|floor| {
+                let measured = self.get(floor.operation).map(PerfResult::throughput_mib_s);
+                match measured {
+                    Some(measured) if measured >= floor.min_mib_s => None,
+                    measured_mib_s => Some(FloorViolation { floor: *floor, measured_mib_s }),
+                }
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for SuiteReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "document: {} bytes encoded", self.encoded_size)?;
+        for result in &self.results {
+            writeln!(
+                f,
+                "{:<28} {:>10.1} MiB/s {:>12?}/iter",
+                result.operation.to_string(),
+                result.throughput_mib_s(),
+                result.time_per_iteration()
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the suite with the default configuration.
+pub fn run_suite() -> Result<SuiteReport> {
+    run_suite_with_config(&SuiteConfig::default())
+}
+
+/// Runs the suite with a custom configuration.
+pub fn run_suite_with_config(config: &SuiteConfig) -> Result<SuiteReport> {
+    let item = document(config.payload_size);
+    let encoded = encode_item(&item)?;
+    let input_len = encoded.len() as u64;
+    let mut results = Vec::new();
+
+    results.push(measure(config, PerfOperation::Encode, input_len, || encode_item(&item).map(drop))?);
+    results.push(measure(config, PerfOperation::Decode, input_len, || decode_item(&encoded).map(drop))?);
+
+    for &strategy in &config.compression {
+        let compressor = get_compressor(strategy)?;
+        let compressed = compressor.compress(&encoded)?;
+        results.push(measure(config, PerfOperation::Compress(strategy), input_len, || {
+            compressor.compress(&encoded).map(drop)
+        })?);
+        results.push(measure(config, PerfOperation::Decompress(strategy), input_len, || {
+            compressor.decompress(&compressed).map(drop)
+        })?);
+    }
+
+    for &strategy in &config.encryption {
+        let encryptor = encryptor(strategy)?;
+        let sealed = encryptor.encrypt(&encoded, None)?;
+        results.push(measure(config, PerfOperation::Encrypt(strategy), input_len, || {
+            encryptor.encrypt(&encoded, None).map(drop)
+        })?);
+        results.push(measure(config, PerfOperation::Decrypt(strategy), input_len, || {
+            encryptor.decrypt(&sealed, None).map(drop)
+        })?);
+    }
+
+    Ok(SuiteReport { encoded_size: encoded.len(), results })
+}
+
+/// Runs the operation once to warm up, then until both the measuring time
+/// and the minimum number of iterations are reached.
+fn measure(config: &SuiteConfig, operation: PerfOperation, bytes: u64, mut run: impl FnMut() -> Result<()>) -> Result<PerfResult> {
+    black_box(run())?;
+    let start = Instant::now();
+    let mut iterations = 0;
+    while iterations < config.min_iterations.max(1) || start.elapsed() < config.measure_time {
+        black_box(run())?;
+        iterations += 1;
+    }
+    Ok(PerfResult { operation, bytes_per_iteration: bytes, iterations, elapsed: start.elapsed() })
+}
+
+/// Returns an encryptor with a fixed key for the strategy.
+fn encryptor(strategy: EncryptionStrategy) -> Result<Box<dyn Encryptor>> {
+    let key = [0x42u8; 32];
+    match strategy {
+        EncryptionStrategy::AesGcm => Ok(Box::new(AesGcmEncryptor::with_key(&key)?)),
+        EncryptionStrategy::ChaCha20Poly1305 => Ok(Box::new(ChaCha20Poly1305Encryptor::with_key(&key)?)),
+        other => Err(Error::EncryptionError(format!("{:?} is not supported by the performance suite", other))),
+    }
+}
+
+/// Builds an event-log-like document of about `size` encoded bytes: records
+/// of short strings, flags and small binary values that compress moderately.
+fn document(size: usize) -> HtlvItem {
+    let mut records = Vec::new();
+    let mut encoded = 0;
+    let mut index = 0u64;
+    while encoded < size {
+        let record = HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String(Bytes::from(format!("user-{:05}", index % 997)))),
+            HtlvItem::new(2, HtlvValue::String(Bytes::from(format!("event {} from region {}", index, index % 7)))),
+            HtlvItem::new(3, HtlvValue::Bool(index.is_multiple_of(3))),
+            HtlvItem::new(4, HtlvValue::Bytes(Bytes::from(index.to_le_bytes().repeat(4)))),
+        ]));
+        encoded += encoded_len(&record, u64::MAX) as usize;
+        records.push(record);
+        index += 1;
+    }
+    HtlvItem::new(1, HtlvValue::Array(records))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_suite_measures_every_operation() {
+        let config = SuiteConfig {
+            payload_size: 4096,
+            measure_time: Duration::ZERO,
+            min_iterations: 2,
+            ..SuiteConfig::default()
+        };
+        let report = run_suite_with_config(&config).unwrap();
+        assert!(report.encoded_size >= 4096);
+        assert_eq!(report.results.len(), 2 + 2 * 2 + 2 * 2);
+        assert!(report.results.iter().all(|result| result.iterations == 2 && result.throughput_mib_s() > 0.0));
+
+        let decode = PerfOperation::Decode;
+        let kyber = PerfOperation::Encrypt(EncryptionStrategy::Kyber);
+        let violations = report.violations(&[
+            ThroughputFloor { operation: decode, min_mib_s: 0.0 },
+            ThroughputFloor { operation: decode, min_mib_s: f64::INFINITY },
+            ThroughputFloor { operation: kyber, min_mib_s: 1.0 },
+        ]);
+        assert_eq!(violations.len(), 2);
+        assert!(violations[0].to_string().starts_with("decode ran at"));
+        assert_eq!(violations[1].to_string(), "encrypt(Kyber) was not measured");
+
+        let config = SuiteConfig { encryption: vec![EncryptionStrategy::Kyber], ..config };
+        assert!(run_suite_with_config(&config).is_err());
+    }
+}
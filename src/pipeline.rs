@@ -0,0 +1,432 @@
+// Sealing items into packets and opening them again in one call
+//
+// A `PipelineBuilder` holds the compression and encryption to apply to packet
+// bodies, in the order the builder methods were called, and turns an item into
+// a packet with `seal` and a packet back into an item with `open`. Both sides
+// read the layers from the packet header rather than from their own
+// configuration: the body type records the outermost layer, and the
+// compression and encryption strategies record which layers are present, so a
+// packet opens correctly whichever order it was sealed in.
+//
+// A `SideChannelPolicy` guards compression that precedes encryption: bodies
+// mixing secret and attacker-controlled fields are sent uncompressed, or with
+// their secret fields outside the compressed section.
+
+use std::borrow::Cow;
+use std::fmt;
+
+use crate::codec::decode::{decode_item_with_options, DecodeOptions};
+use crate::codec::tag_table::decode_with_optional_tag_table;
+use crate::codec::types::HtlvItem;
+use crate::codec::wire::{encode_item_with_format, WireFormat};
+use crate::compress::{compress_or_passthrough, get_compressor, CompressionStrategy};
+use crate::compress::side_channel::{self, SideChannelPolicy};
+use crate::encrypt::{EncryptionStrategy, Encryptor};
+use crate::internal::deadline::{self, Deadline};
+use crate::internal::error::{Error, Result};
+use crate::internal::metrics::{self, MetricsRecorder, PipelineStage};
+use crate::internal::packet::{DataBody, DataBodyType, MetadataHeader, Packet};
+
+/// Seals items into packets and opens packets into items.
+///
+/// ```
+/// use tonitru::codec::types::{HtlvItem, HtlvValue};
+/// use tonitru::compress::CompressionStrategy;
+/// use tonitru::encrypt::aes_gcm::AesGcmEncryptor;
+/// use tonitru::pipeline::PipelineBuilder;
+///
+/// let pipeline = PipelineBuilder::new(42)
+///     .compress(CompressionStrategy::Zstd)
+///     .encrypt(AesGcmEncryptor::with_key(&[7u8; 32]).unwrap(), None);
+/// let item = HtlvItem::new(1, HtlvValue::Bool(true));
+/// let bytes = pipeline.seal(&item).unwrap().encode_packet().unwrap();
+/// assert_eq!(pipeline.open(&bytes).unwrap(), item);
+/// ```
+pub struct PipelineBuilder {
+    schema_id: u64,
+    wire_format: WireFormat,
+    compression: Option<CompressionStrategy>,
+    side_channel: Option<SideChannelPolicy>,
+    encryptor: Option<Box<dyn Encryptor>>,
+    key_id: Option<String>,
+    encrypt_first: bool,
+    metrics: Option<MetricsRecorder>,
+}
+
+impl fmt::Debug for PipelineBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PipelineBuilder")
+            .field("schema_id", &self.schema_id)
+            .field("wire_format", &self.wire_format)
+            .field("compression", &self.compression)
+            .field("side_channel", &self.side_channel)
+            .field("encryption", &self.encryptor.as_ref().map(|encryptor| encryptor.strategy()))
+            .field("key_id", &self.key_id)
+            .field("encrypt_first", &self.encrypt_first)
+            .field("metrics", &self.metrics.is_some())
+            .finish()
+    }
+}
+
+impl PipelineBuilder {
+    /// Creates a pipeline sealing packets for the given schema, without
+    /// compression or encryption.
+    pub fn new(schema_id: u64) -> Self {
+        PipelineBuilder {
+            schema_id,
+            wire_format: WireFormat::V1,
+            compression: None,
+            side_channel: None,
+            encryptor: None,
+            key_id: None,
+            encrypt_first: false,
+            metrics: None,
+        }
+    }
+
+    /// Sets the wire format version items are encoded with.
+    pub fn wire_format(mut self, format: WireFormat) -> Self {
+        self.wire_format = format;
+        self
+    }
+
+    /// Compresses bodies with the strategy, unless compression would not help.
+    /// Compression is applied after encryption if `encrypt` was called first.
+    pub fn compress(mut self, strategy: CompressionStrategy) -> Self {
+        self.compression = Some(strategy);
+        self
+    }
+
+    /// Guards compression of bodies that are encrypted afterwards with the
+    /// side-channel policy. Compression after encryption is not affected.
+    pub fn side_channel(mut self, policy: SideChannelPolicy) -> Self {
+        self.side_channel = Some(policy);
+        self
+    }
+
+    /// Encrypts bodies with the encryptor, which holds the key, under the
+    /// optional key ID. Encryption is applied after compression if `compress`
+    /// was called first.
+    pub fn encrypt(mut self, encryptor: impl Encryptor + 'static, key_id: Option<&str>) -> Self {
+        self.encrypt_first = self.compression.is_none();
+        self.encryptor = Some(Box::new(encryptor));
+        self.key_id = key_id.map(str::to_string);
+        self
+    }
+
+    /// Records the time spent opening packets in each stage: checksum
+    /// verification, decryption, decompression and decoding.
+    pub fn metrics(mut self, metrics: MetricsRecorder) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Encodes the item and applies the configured layers to it.
+    pub fn seal(&self, item: &HtlvItem) -> Result<Packet> {
+        let mut header = MetadataHeader {
+            schema_id: self.schema_id,
+            timestamp: 0,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: DataBodyType::Raw as u8,
+            key_id: None,
+        };
+        header.set_wire_format(self.wire_format);
+        let mut outermost = DataBodyType::Raw;
+        let mut body = match (&self.side_channel, self.compression) {
+            (Some(policy), Some(strategy)) if !self.encrypt_first => {
+                let guarded = policy.compress_item(strategy, item, self.wire_format)?;
+                header.set_compression_strategy(guarded.strategy);
+                header.set_isolated_secrets(guarded.isolated);
+                // An isolated body is not a compressed stream as a whole
+                if guarded.strategy != CompressionStrategy::NoCompression && !guarded.isolated {
+                    outermost = DataBodyType::Compressed;
+                }
+                guarded.data
+            }
+            _ => encode_item_with_format(item, self.wire_format)?,
+        };
+
+        if self.encrypt_first {
+            self.encrypt_body(&mut header, &mut body, &mut outermost)?;
+        }
+        if let Some(strategy) = self.compression.filter(|_| self.side_channel.is_none() || self.encrypt_first) {
+            let (applied, compressed) = compress_or_passthrough(strategy, &body)?;
+            header.set_compression_strategy(applied);
+            if applied != CompressionStrategy::NoCompression {
+                body = compressed;
+                outermost = DataBodyType::Compressed;
+            }
+        }
+        if !self.encrypt_first {
+            self.encrypt_body(&mut header, &mut body, &mut outermost)?;
+        }
+
+        let body = match outermost {
+            DataBodyType::Raw => DataBody::Raw(body),
+            DataBodyType::Compressed => DataBody::Compressed(body),
+            DataBodyType::Encrypted => DataBody::Encrypted(body),
+        };
+        Packet::build_packet(header, body)
+    }
+
+    /// Parses an encoded packet and opens it like `open_packet`.
+    pub fn open(&self, data: &[u8]) -> Result<HtlvItem> {
+        let packet = match &self.metrics {
+            Some(metrics) => Packet::parse_packet_with_metrics(data, metrics)?,
+            None => Packet::parse_packet(data)?,
+        };
+        self.open_packet(&packet)
+    }
+
+    /// Removes the layers of the packet body and decodes its item.
+    ///
+    /// Fails if the body is encrypted with another strategy than the pipeline's
+    /// encryptor, or the pipeline has no encryptor. A pipeline with an encryptor
+    /// rejects bodies that are not encrypted: the checksums are not keyed, so
+    /// anyone could otherwise inject plaintext packets.
+    pub fn open_packet(&self, packet: &Packet) -> Result<HtlvItem> {
+        if let Some(encryptor) = &self.encryptor {
+            // Other strategies fail when the body is decrypted
+            let strategy = packet.header.get_encryption_strategy()?;
+            if strategy == EncryptionStrategy::NoEncryption || matches!(packet.body, DataBody::Raw(_)) {
+                return Err(Error::EncryptionError(format!(
+                    "Packet is not encrypted, but the pipeline encrypts with {:?}",
+                    encryptor.strategy()
+                )));
+            }
+        }
+        open_item(packet, &|sealed| self.decrypt(packet, sealed), None, self.metrics.as_ref())
+    }
+
+    fn encrypt_body(&self, header: &mut MetadataHeader, body: &mut Vec<u8>, outermost: &mut DataBodyType) -> Result<()> {
+        if let Some(encryptor) = &self.encryptor {
+            *body = encryptor.encrypt(body, self.key_id.as_deref())?;
+            header.set_encryption_strategy(encryptor.strategy());
+            header.key_id = self.key_id.clone();
+            *outermost = DataBodyType::Encrypted;
+        }
+        Ok(())
+    }
+
+    fn decrypt(&self, packet: &Packet, sealed: &[u8]) -> Result<Vec<u8>> {
+        let strategy = packet.header.get_encryption_strategy()?;
+        match &self.encryptor {
+            Some(encryptor) if encryptor.strategy() == strategy => encryptor.decrypt(sealed, packet.header.key_id.as_deref()),
+            Some(encryptor) => Err(Error::EncryptionError(format!(
+                "Packet is encrypted with {:?}, but the pipeline encrypts with {:?}",
+                strategy,
+                encryptor.strategy()
+            ))),
+            None => Err(Error::EncryptionError(format!(
+                "Packet is encrypted with {:?}, but the pipeline has no encryptor",
+                strategy
+            ))),
+        }
+    }
+}
+
+/// Removes the layers of a packet body, outermost first, and decodes its item.
+///
+/// `decrypt` is called with the encrypted layer, if any. The deadline is
+/// checked between layers and, for v1 items, while decoding. With a metrics
+/// recorder, decryption and decompression are timed, and so are the decoding
+/// stages of v1 items without a tag table.
+pub(crate) fn open_item(
+    packet: &Packet,
+    decrypt: &dyn Fn(&[u8]) -> Result<Vec<u8>>,
+    deadline: Option<Deadline>,
+    metrics: Option<&MetricsRecorder>,
+) -> Result<HtlvItem> {
+    deadline::check(&deadline, "Checksum verification")?;
+    let header = &packet.header;
+    let body = match &packet.body {
+        DataBody::Raw(data) if header.has_isolated_secrets() => return open_isolated_layer(header, data, &deadline),
+        DataBody::Raw(data) => Cow::Borrowed(data.as_slice()),
+        DataBody::Encrypted(data) if header.has_isolated_secrets() => {
+            let body = decrypt_layer(header, data, decrypt, &deadline, metrics)?;
+            return open_isolated_layer(header, &body, &deadline);
+        }
+        DataBody::Encrypted(data) => {
+            let body = decrypt_layer(header, data, decrypt, &deadline, metrics)?;
+            decompress_layer(header, Cow::Owned(body), &deadline, metrics)?
+        }
+        DataBody::Compressed(data) => {
+            let body = decompress_layer(header, Cow::Borrowed(data), &deadline, metrics)?;
+            // A compressed body with an encryption strategy was encrypted first
+            if header.get_encryption_strategy()? == EncryptionStrategy::NoEncryption {
+                body
+            } else {
+                Cow::Owned(decrypt_layer(header, &body, decrypt, &deadline, metrics)?)
+            }
+        }
+    };
+
+    let format = header.get_wire_format()?;
+    let observed = deadline.is_some() || metrics.is_some();
+    let (item, _) = if observed && format == WireFormat::V1 && !header.has_tag_table() {
+        let options = DecodeOptions { deadline, metrics: metrics.cloned(), ..DecodeOptions::default() };
+        decode_item_with_options(&body, &options)?
+    } else {
+        decode_with_optional_tag_table(&body, format, header.has_tag_table())?
+    };
+    deadline::check(&deadline, "Decoding")?;
+    Ok(item)
+}
+
+fn decrypt_layer(
+    header: &MetadataHeader,
+    body: &[u8],
+    decrypt: &dyn Fn(&[u8]) -> Result<Vec<u8>>,
+    deadline: &Option<Deadline>,
+    metrics: Option<&MetricsRecorder>,
+) -> Result<Vec<u8>> {
+    let body = header.unpad(metrics::time(metrics, PipelineStage::Decrypt, || decrypt(body))?)?;
+    deadline::check(deadline, "Decryption")?;
+    Ok(body)
+}
+
+fn open_isolated_layer(header: &MetadataHeader, body: &[u8], deadline: &Option<Deadline>) -> Result<HtlvItem> {
+    let item = side_channel::open_isolated(header.get_compression_strategy()?, body, header.get_wire_format()?)?;
+    deadline::check(deadline, "Decoding")?;
+    Ok(item)
+}
+
+fn decompress_layer<'a>(
+    header: &MetadataHeader,
+    body: Cow<'a, [u8]>,
+    deadline: &Option<Deadline>,
+    metrics: Option<&MetricsRecorder>,
+) -> Result<Cow<'a, [u8]>> {
+    match header.get_compression_strategy()? {
+        CompressionStrategy::NoCompression => Ok(body),
+        strategy => {
+            let body = metrics::time(metrics, PipelineStage::Decompress, || get_compressor(strategy)?.decompress(&body))?;
+            deadline::check(deadline, "Decompression")?;
+            Ok(Cow::Owned(body))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::types::HtlvValue;
+    use crate::encrypt::aes_gcm::AesGcmEncryptor;
+    use crate::encrypt::chacha20_poly1305::ChaCha20Poly1305Encryptor;
+
+    fn item() -> HtlvItem {
+        HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::String(bytes::Bytes::from("abc".repeat(100)))),
+            HtlvItem::new(3, HtlvValue::Bool(true)),
+        ]))
+    }
+
+    #[test]
+    fn test_seal_and_open_in_either_order() {
+        let compress_first = PipelineBuilder::new(9)
+            .compress(CompressionStrategy::Zstd)
+            .encrypt(AesGcmEncryptor::with_key(&[1u8; 32]).unwrap(), None);
+        let packet = compress_first.seal(&item()).unwrap();
+        assert_eq!(packet.header.body_type, DataBodyType::Encrypted as u8);
+        assert_eq!(packet.header.get_compression_strategy().unwrap(), CompressionStrategy::Zstd);
+        assert_eq!(compress_first.open(&packet.encode_packet().unwrap()).unwrap(), item());
+
+        // Ciphertext does not compress, so compression after encryption is skipped
+        let encrypt_first = PipelineBuilder::new(9)
+            .encrypt(AesGcmEncryptor::with_key(&[1u8; 32]).unwrap(), None)
+            .compress(CompressionStrategy::Zstd);
+        let packet = encrypt_first.seal(&item()).unwrap();
+        assert_eq!(packet.header.get_compression_strategy().unwrap(), CompressionStrategy::NoCompression);
+        assert_eq!(encrypt_first.open_packet(&packet).unwrap(), item());
+
+        let plain = PipelineBuilder::new(9).wire_format(WireFormat::V2);
+        assert_eq!(plain.open_packet(&plain.seal(&item()).unwrap()).unwrap(), item());
+    }
+
+    #[test]
+    fn test_open_compressed_layer_over_encrypted_body() {
+        let encryptor = AesGcmEncryptor::with_key(&[2u8; 32]).unwrap();
+        let mut header = MetadataHeader {
+            schema_id: 9,
+            timestamp: 0,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        header.set_encryption_strategy(encryptor.strategy());
+        header.set_compression_strategy(CompressionStrategy::Brotli);
+        let sealed = encryptor.encrypt(&crate::codec::encode::encode_item(&item()).unwrap(), None).unwrap();
+        let compressed = get_compressor(CompressionStrategy::Brotli).unwrap().compress(&sealed).unwrap();
+        let packet = Packet::build_packet(header, DataBody::Compressed(compressed)).unwrap();
+
+        let pipeline = PipelineBuilder::new(9).encrypt(encryptor, None).compress(CompressionStrategy::Brotli);
+        assert_eq!(pipeline.open_packet(&packet).unwrap(), item());
+    }
+
+    #[test]
+    fn test_side_channel_policy_isolates_secrets_before_encryption() {
+        use crate::compress::side_channel::{SideChannelMode, SideChannelPolicy};
+
+        let pipeline = PipelineBuilder::new(9)
+            .compress(CompressionStrategy::Zstd)
+            .side_channel(SideChannelPolicy::new(SideChannelMode::IsolateSecrets).secret(3).untrusted(2))
+            .encrypt(AesGcmEncryptor::with_key(&[4u8; 32]).unwrap(), None);
+        let packet = pipeline.seal(&item()).unwrap();
+        assert!(packet.header.has_isolated_secrets());
+        assert_eq!(packet.header.get_compression_strategy().unwrap(), CompressionStrategy::Zstd);
+        assert_eq!(pipeline.open(&packet.encode_packet().unwrap()).unwrap(), item());
+
+        let unencrypted = PipelineBuilder::new(9)
+            .compress(CompressionStrategy::Zstd)
+            .side_channel(SideChannelPolicy::new(SideChannelMode::DisableCompression).secret(3).untrusted(2));
+        let packet = unencrypted.seal(&item()).unwrap();
+        assert!(!packet.header.has_isolated_secrets());
+        assert_eq!(packet.header.get_compression_strategy().unwrap(), CompressionStrategy::NoCompression);
+        assert_eq!(unencrypted.open_packet(&packet).unwrap(), item());
+    }
+
+    #[test]
+    fn test_open_with_mismatched_or_missing_encryptor() {
+        let sealer = PipelineBuilder::new(9).encrypt(AesGcmEncryptor::with_key(&[3u8; 32]).unwrap(), None);
+        let packet = sealer.seal(&item()).unwrap();
+
+        let other = PipelineBuilder::new(9).encrypt(ChaCha20Poly1305Encryptor::with_key(&[3u8; 32]).unwrap(), None);
+        let err = other.open_packet(&packet).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Packet is encrypted with AesGcm, but the pipeline encrypts with ChaCha20Poly1305");
+
+        let err = PipelineBuilder::new(9).open_packet(&packet).unwrap_err();
+        assert_eq!(err.to_string(), "Encryption Error: Packet is encrypted with AesGcm, but the pipeline has no encryptor");
+
+        // Plaintext packets, compressed or not, cannot be injected into an encrypted pipeline
+        for plain in [PipelineBuilder::new(9), PipelineBuilder::new(9).compress(CompressionStrategy::Zstd)] {
+            let err = sealer.open_packet(&plain.seal(&item()).unwrap()).unwrap_err();
+            assert_eq!(err.to_string(), "Encryption Error: Packet is not encrypted, but the pipeline encrypts with AesGcm");
+        }
+        let mut forged = PipelineBuilder::new(9).seal(&item()).unwrap();
+        forged.header.set_encryption_strategy(EncryptionStrategy::AesGcm);
+        assert!(sealer.open_packet(&forged).is_err());
+    }
+
+    #[test]
+    fn test_open_records_stage_latencies() {
+        let histograms = std::sync::Arc::new(crate::internal::metrics::StageHistograms::new());
+        let pipeline = PipelineBuilder::new(9)
+            .compress(CompressionStrategy::Zstd)
+            .encrypt(AesGcmEncryptor::with_key(&[5u8; 32]).unwrap(), None)
+            .metrics(MetricsRecorder::new(histograms.clone()));
+        let item = HtlvItem::new(1, HtlvValue::Object(vec![
+            HtlvItem::new(2, HtlvValue::String(bytes::Bytes::from("abc".repeat(100)))),
+            HtlvItem::new(3, HtlvValue::Array((1..=8).map(|i| HtlvItem::new(0, HtlvValue::U32(i))).collect())),
+        ]));
+        assert_eq!(pipeline.open(&pipeline.seal(&item).unwrap().encode_packet().unwrap()).unwrap(), item);
+
+        let count = |stage| histograms.stage(stage).snapshot().count();
+        for stage in PipelineStage::ALL {
+            assert!(count(stage) > 0, "{} not recorded", stage.name());
+        }
+        assert_eq!(count(PipelineStage::Checksum), 1);
+        assert_eq!(count(PipelineStage::Decrypt), 1);
+    }
+}
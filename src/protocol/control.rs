@@ -0,0 +1,163 @@
+// Scaffolding shared by the control messages of the protocol
+//
+// Every control message is an HTLV object sent as the Raw body of a packet
+// whose schema ID names the kind of message. Messages with several variants
+// carry the variant in a `TAG_KIND` field; the other fields are specific to
+// each message. This module builds and parses those packets and objects, so
+// that the message modules only deal with their own fields.
+
+use bytes::Bytes;
+
+use crate::codec::decode::decode_item;
+use crate::codec::encode::encode_item;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::{DataBody, MetadataHeader, Packet};
+
+/// Root tag of a control message object
+pub(crate) const TAG_MESSAGE: u64 = 0;
+/// Tag of the field holding the variant of a message
+pub(crate) const TAG_KIND: u64 = 1;
+
+/// Builds the object of a message variant, its kind followed by its fields
+pub(crate) fn message(kind: u8, fields: Vec<HtlvItem>) -> HtlvItem {
+    let mut items = Vec::with_capacity(fields.len() + 1);
+    items.push(HtlvItem::new(TAG_KIND, HtlvValue::U8(kind)));
+    items.extend(fields);
+    HtlvItem::new(TAG_MESSAGE, HtlvValue::Object(items))
+}
+
+/// Builds a control packet carrying the message object
+pub(crate) fn to_control_packet(schema_id: u64, timestamp: u64, item: &HtlvItem) -> Result<Packet> {
+    let header = MetadataHeader {
+        schema_id,
+        timestamp,
+        shard_id: 0,
+        flow_flags: 0,
+        body_type: 0, // Will be set by build_packet
+        key_id: None,
+    };
+    let body = DataBody::Raw(encode_item(item)?);
+    Packet::build_packet(header, body)
+}
+
+/// Returns the message object of a control packet built by
+/// `to_control_packet`; `name` describes the message in errors
+pub(crate) fn from_control_packet(packet: &Packet, schema_id: u64, name: &str) -> Result<HtlvItem> {
+    if packet.header.schema_id != schema_id {
+        return Err(Error::ProtocolError(format!(
+            "Not a {} packet (schema_id {})", name, packet.header.schema_id
+        )));
+    }
+    let data = match &packet.body {
+        DataBody::Raw(data) => data,
+        _ => return Err(Error::ProtocolError(format!("Body of a {} packet must be Raw", name))),
+    };
+    let (item, _) = decode_item(data)?;
+    Ok(item)
+}
+
+/// The fields of a message object
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Fields<'a> {
+    fields: &'a [HtlvItem],
+    name: &'a str,
+}
+
+impl<'a> Fields<'a> {
+    /// Returns the fields of the message object; `name` describes the message
+    /// in errors
+    pub(crate) fn of(item: &'a HtlvItem, name: &'a str) -> Result<Self> {
+        match &item.value {
+            HtlvValue::Object(fields) => Ok(Fields { fields, name }),
+            other => Err(Error::ProtocolError(format!(
+                "Expected an Object for a {}, got {:?}", name, other.value_type()
+            ))),
+        }
+    }
+
+    /// Returns all the fields, in wire order
+    pub(crate) fn all(&self) -> &'a [HtlvItem] {
+        self.fields
+    }
+
+    /// Returns the field with the tag
+    pub(crate) fn get(&self, tag: u64) -> Result<&'a HtlvItem> {
+        self.fields.iter().find(|field| field.tag == tag).ok_or_else(|| {
+            Error::ProtocolError(format!("Field tag {} missing from {}", tag, self.name))
+        })
+    }
+
+    /// Returns the variant of the message
+    pub(crate) fn kind(&self) -> Result<u8> {
+        let kind = read_u64(self.get(TAG_KIND)?)?;
+        u8::try_from(kind).map_err(|_| Error::ProtocolError(format!("Unknown {} kind: {}", self.name, kind)))
+    }
+}
+
+/// Reads an unsigned field
+pub(crate) fn read_u64(item: &HtlvItem) -> Result<u64> {
+    match item.value.as_scalar() {
+        HtlvValue::U8(v) => Ok(*v as u64),
+        HtlvValue::U64(v) => Ok(*v),
+        other => Err(Error::ProtocolError(format!(
+            "Expected an unsigned integer for field tag {}, got {:?}", item.tag, other.value_type()
+        ))),
+    }
+}
+
+/// Reads a bytes or string field
+pub(crate) fn read_bytes(item: &HtlvItem) -> Result<&[u8]> {
+    match &item.value {
+        HtlvValue::Bytes(data) | HtlvValue::String(data) => Ok(data),
+        other => Err(Error::ProtocolError(format!(
+            "Expected Bytes for field tag {}, got {:?}", item.tag, other.value_type()
+        ))),
+    }
+}
+
+/// Reads a UTF-8 string field
+pub(crate) fn read_string(item: &HtlvItem) -> Result<String> {
+    String::from_utf8(read_bytes(item)?.to_vec())
+        .map_err(|e| Error::ProtocolError(format!("Invalid UTF-8 in field tag {}: {}", item.tag, e)))
+}
+
+/// Returns a string field value
+pub(crate) fn string(s: &str) -> HtlvValue {
+    HtlvValue::String(Bytes::from(s.to_string()))
+}
+
+/// Returns a bytes field value
+pub(crate) fn bytes(b: &[u8]) -> HtlvValue {
+    HtlvValue::Bytes(Bytes::from(b.to_vec()))
+}
+
+/// Sends a control packet through encoding and parsing, and parses the message
+/// it carries
+#[cfg(test)]
+pub(crate) fn wire<T>(packet: Result<Packet>, parse: impl Fn(&Packet) -> Result<T>) -> T {
+    let encoded = packet.unwrap().encode_packet().unwrap();
+    parse(&Packet::parse_packet(&encoded).unwrap()).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kind_must_fit_a_byte() {
+        let item = HtlvItem::new(TAG_MESSAGE, HtlvValue::Object(vec![
+            HtlvItem::new(TAG_KIND, HtlvValue::U64(0x101)),
+        ]));
+        let fields = Fields::of(&item, "test message").unwrap();
+        assert!(matches!(fields.kind(), Err(Error::ProtocolError(message)) if message.contains("257")));
+        assert!(fields.get(2).is_err());
+
+        let item = message(3, vec![HtlvItem::new(2, string("x"))]);
+        let packet = wire(to_control_packet(9, 0, &item), |packet| from_control_packet(packet, 9, "test"));
+        let fields = Fields::of(&packet, "test message").unwrap();
+        assert_eq!(fields.kind().unwrap(), 3);
+        assert_eq!(read_string(fields.get(2).unwrap()).unwrap(), "x");
+        assert!(from_control_packet(&to_control_packet(9, 0, &item).unwrap(), 8, "test").is_err());
+    }
+}
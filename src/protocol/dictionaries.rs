@@ -0,0 +1,345 @@
+// Negotiation of zstd compression dictionaries between peers
+//
+// Dictionaries trained on typical payloads compress small packets far better
+// than plain zstd, but a packet compressed with a dictionary is unreadable to a
+// peer without it. During the handshake the peers therefore agree on the
+// dictionaries either side may reference, with two control packets:
+//
+//   Advertise  initiator -> responder: id, size and BLAKE3 hash of every
+//              dictionary the initiator holds
+//   Accept     responder -> initiator: the advertised ids the responder holds
+//              with the same contents
+//
+// Both ends then compress with `NegotiatedDictionaries`, which prefixes each
+// payload with the id of the dictionary it was compressed with. A dictionary
+// that was not agreed on is never referenced: compressing with it falls back to
+// plain zstd, so a peer missing a dictionary costs compression ratio but never
+// breaks the connection.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+
+use bytes::Bytes;
+
+use crate::archive::ByteReader;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::varint::{decode_varint, encode_varint_into};
+use crate::compress::zstd;
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::protocol::control::{self, read_bytes, Fields};
+use crate::protocol::events::{self, ProtocolEvent, ProtocolEventLog};
+
+/// Schema ID used in the metadata header of dictionary negotiation packets.
+pub const DICTIONARY_SCHEMA_ID: u64 = 0x4449_4354; // "DICT"
+
+/// Identifier of a dictionary, shared by the peers. Zero means no dictionary.
+pub type DictionaryId = u32;
+
+// Message kinds
+const KIND_ADVERTISE: u8 = 1;
+const KIND_ACCEPT: u8 = 2;
+
+// Tags used for the fields of a negotiation message object
+const TAG_DICTIONARIES: u64 = 2;
+
+/// Describes negotiation messages in errors
+const MESSAGE_NAME: &str = "dictionary negotiation message";
+
+/// Id, size and content hash of a dictionary, as advertised to peers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DictionaryInfo {
+    /// Identifier of the dictionary
+    pub id: DictionaryId,
+    /// Size of the dictionary in bytes
+    pub size: u64,
+    /// BLAKE3 hash of the dictionary
+    pub hash: [u8; 32],
+}
+
+/// The dictionaries a peer holds
+#[derive(Debug, Clone, Default)]
+pub struct DictionarySet {
+    dictionaries: HashMap<DictionaryId, Arc<[u8]>>,
+}
+
+impl DictionarySet {
+    /// Creates an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a dictionary, replacing the one with the same id
+    pub fn insert(&mut self, id: DictionaryId, dictionary: Vec<u8>) -> Result<()> {
+        if id == 0 {
+            return Err(Error::CompressionError("Dictionary id 0 is reserved for no dictionary".to_string()));
+        }
+        self.dictionaries.insert(id, Arc::from(dictionary));
+        Ok(())
+    }
+
+    /// Trains a dictionary of at most `max_size` bytes on sample payloads and
+    /// adds it under the id
+    pub fn train(&mut self, id: DictionaryId, samples: &[Vec<u8>], max_size: usize) -> Result<()> {
+        let dictionary = ::zstd::dict::from_samples(samples, max_size)
+            .map_err(|e| Error::CompressionError(format!("Zstd dictionary training failed: {}", e)))?;
+        self.insert(id, dictionary)
+    }
+
+    /// Returns the dictionary with the id
+    pub fn get(&self, id: DictionaryId) -> Option<&[u8]> {
+        self.dictionaries.get(&id).map(|dictionary| &dictionary[..])
+    }
+
+    /// Returns the id, size and hash of every dictionary, ordered by id
+    pub fn infos(&self) -> Vec<DictionaryInfo> {
+        let mut infos: Vec<_> = self
+            .dictionaries
+            .iter()
+            .map(|(&id, dictionary)| DictionaryInfo {
+                id,
+                size: dictionary.len() as u64,
+                hash: *blake3::hash(dictionary).as_bytes(),
+            })
+            .collect();
+        infos.sort_by_key(|info| info.id);
+        infos
+    }
+
+    /// Returns true if the set holds a dictionary matching the info
+    fn matches(&self, info: &DictionaryInfo) -> bool {
+        self.dictionaries.get(&info.id).is_some_and(|dictionary| {
+            dictionary.len() as u64 == info.size && blake3::hash(dictionary).as_bytes() == &info.hash
+        })
+    }
+}
+
+/// A dictionary negotiation control message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DictionaryMessage {
+    /// Lists the dictionaries the initiator holds
+    Advertise(Vec<DictionaryInfo>),
+    /// Lists the advertised dictionaries the responder holds as well
+    Accept(Vec<DictionaryId>),
+}
+
+impl DictionaryMessage {
+    /// Converts the message into an HTLV object item.
+    pub fn to_htlv_item(&self) -> HtlvItem {
+        let mut list = Vec::new();
+        let kind = match self {
+            DictionaryMessage::Advertise(infos) => {
+                for info in infos {
+                    encode_varint_into(info.id as u64, &mut list);
+                    encode_varint_into(info.size, &mut list);
+                    encode_varint_into(info.hash.len() as u64, &mut list);
+                    list.extend_from_slice(&info.hash);
+                }
+                KIND_ADVERTISE
+            }
+            DictionaryMessage::Accept(ids) => {
+                for &id in ids {
+                    encode_varint_into(id as u64, &mut list);
+                }
+                KIND_ACCEPT
+            }
+        };
+        control::message(kind, vec![HtlvItem::new(TAG_DICTIONARIES, HtlvValue::Bytes(Bytes::from(list)))])
+    }
+
+    /// Parses a message from an HTLV object item produced by `to_htlv_item`.
+    pub fn from_htlv_item(item: &HtlvItem) -> Result<Self> {
+        let fields = Fields::of(item, MESSAGE_NAME)?;
+        let mut reader = ByteReader::new(read_bytes(fields.get(TAG_DICTIONARIES)?)?);
+        fn read_id(reader: &mut ByteReader<'_>) -> Result<DictionaryId> {
+            let id = reader.read_varint()?;
+            DictionaryId::try_from(id).map_err(|_| Error::ProtocolError(format!("Invalid dictionary id: {}", id)))
+        }
+
+        match fields.kind()? {
+            KIND_ADVERTISE => {
+                let mut infos = Vec::new();
+                while !reader.is_empty() {
+                    let id = read_id(&mut reader)?;
+                    let size = reader.read_varint()?;
+                    let hash = reader.read_bytes()?.try_into()
+                        .map_err(|_| Error::ProtocolError("Invalid dictionary hash length".to_string()))?;
+                    infos.push(DictionaryInfo { id, size, hash });
+                }
+                Ok(DictionaryMessage::Advertise(infos))
+            }
+            KIND_ACCEPT => {
+                let mut ids = Vec::new();
+                while !reader.is_empty() {
+                    ids.push(read_id(&mut reader)?);
+                }
+                Ok(DictionaryMessage::Accept(ids))
+            }
+            kind => Err(Error::ProtocolError(format!("Unknown dictionary message kind: {}", kind))),
+        }
+    }
+
+    /// Builds an HTLV control packet carrying this message.
+    pub fn to_control_packet(&self, timestamp: u64) -> Result<Packet> {
+        control::to_control_packet(DICTIONARY_SCHEMA_ID, timestamp, &self.to_htlv_item())
+    }
+
+    /// Parses a message from a control packet built by `to_control_packet`.
+    pub fn from_control_packet(packet: &Packet) -> Result<Self> {
+        Self::from_htlv_item(&control::from_control_packet(packet, DICTIONARY_SCHEMA_ID, MESSAGE_NAME)?)
+    }
+}
+
+/// Runs the dictionary step of the handshake for one connection
+#[derive(Debug)]
+pub struct DictionaryNegotiator {
+    local: DictionarySet,
+    started: Instant,
+    events: Option<ProtocolEventLog>,
+}
+
+impl DictionaryNegotiator {
+    /// Creates a negotiator offering or accepting the local dictionaries
+    pub fn new(local: DictionarySet) -> Self {
+        Self { local, started: Instant::now(), events: None }
+    }
+
+    /// Emits the completion of the negotiation as a handshake step
+    pub fn with_event_log(mut self, log: ProtocolEventLog) -> Self {
+        self.events = Some(log);
+        self
+    }
+
+    /// Returns the message the initiator opens the negotiation with
+    pub fn advertise(&self) -> DictionaryMessage {
+        DictionaryMessage::Advertise(self.local.infos())
+    }
+
+    /// Answers the initiator's advertisement, on the responder side
+    ///
+    /// Advertised dictionaries the responder lacks, or holds with different
+    /// contents under the same id, are left out and will not be referenced.
+    pub fn respond(&self, message: &DictionaryMessage) -> Result<(DictionaryMessage, NegotiatedDictionaries)> {
+        let DictionaryMessage::Advertise(infos) = message else {
+            return Err(Error::ProtocolError("Expected a dictionary advertisement".to_string()));
+        };
+        let ids: Vec<_> = infos.iter().filter(|info| self.local.matches(info)).map(|info| info.id).collect();
+        let negotiated = self.negotiated(&ids);
+        Ok((DictionaryMessage::Accept(ids), negotiated))
+    }
+
+    /// Completes the negotiation with the responder's answer, on the
+    /// initiator side
+    pub fn complete(&self, message: &DictionaryMessage) -> Result<NegotiatedDictionaries> {
+        let DictionaryMessage::Accept(ids) = message else {
+            return Err(Error::ProtocolError("Expected a dictionary acceptance".to_string()));
+        };
+        if let Some(id) = ids.iter().find(|&&id| self.local.get(id).is_none()) {
+            return Err(Error::ProtocolError(format!("Peer accepted dictionary {} that was not advertised", id)));
+        }
+        Ok(self.negotiated(ids))
+    }
+
+    fn negotiated(&self, ids: &[DictionaryId]) -> NegotiatedDictionaries {
+        events::emit(&self.events, ProtocolEvent::HandshakeStep { step: "dictionaries", elapsed: self.started.elapsed() });
+        let dictionaries = ids
+            .iter()
+            .filter_map(|&id| Some((id, self.local.dictionaries.get(&id)?.clone())))
+            .collect();
+        NegotiatedDictionaries { dictionaries }
+    }
+}
+
+/// The dictionaries both peers of a connection hold
+#[derive(Debug, Clone, Default)]
+pub struct NegotiatedDictionaries {
+    dictionaries: HashMap<DictionaryId, Arc<[u8]>>,
+}
+
+impl NegotiatedDictionaries {
+    /// Returns true if the peers agreed on the dictionary
+    pub fn is_agreed(&self, id: DictionaryId) -> bool {
+        self.dictionaries.contains_key(&id)
+    }
+
+    /// Returns the ids of the agreed dictionaries, in ascending order
+    pub fn ids(&self) -> Vec<DictionaryId> {
+        let mut ids: Vec<_> = self.dictionaries.keys().copied().collect();
+        ids.sort_unstable();
+        ids
+    }
+
+    /// Compresses a payload with the dictionary, or with plain zstd if the
+    /// peers did not agree on it, prefixed with the id of the dictionary used
+    pub fn compress(&self, data: &[u8], id: Option<DictionaryId>) -> Result<Vec<u8>> {
+        let mut out = Vec::new();
+        match id.and_then(|id| Some((id, self.dictionaries.get(&id)?))) {
+            Some((id, dictionary)) => {
+                encode_varint_into(id as u64, &mut out);
+                zstd::compress_with_dictionary(data, dictionary, &mut out)?;
+            }
+            None => {
+                encode_varint_into(0, &mut out);
+                zstd::compress_into(data, &mut out)?;
+            }
+        }
+        Ok(out)
+    }
+
+    /// Decompresses a payload produced by `compress` on either side
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let (id, offset) = decode_varint(data)?;
+        let mut out = Vec::new();
+        if id == 0 {
+            zstd::decompress_into(&data[offset..], &mut out)?;
+            return Ok(out);
+        }
+        let dictionary = DictionaryId::try_from(id).ok().and_then(|id| self.dictionaries.get(&id)).ok_or_else(|| {
+            Error::ProtocolError(format!("Payload references dictionary {} that was not negotiated", id))
+        })?;
+        zstd::decompress_with_dictionary(&data[offset..], dictionary, &mut out)?;
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wire(message: &DictionaryMessage) -> DictionaryMessage {
+        control::wire(message.to_control_packet(0), DictionaryMessage::from_control_packet)
+    }
+
+    #[test]
+    fn test_negotiation_keeps_shared_dictionaries() {
+        let shared = b"{\"user\": \"\", \"event\": \"login\", \"region\": \"eu-west\"}".repeat(8);
+        let mut initiator = DictionarySet::new();
+        initiator.insert(1, shared.clone()).unwrap();
+        initiator.insert(2, b"initiator only".to_vec()).unwrap();
+        initiator.insert(3, b"version one".to_vec()).unwrap();
+        let mut responder = DictionarySet::new();
+        responder.insert(1, shared).unwrap();
+        responder.insert(3, b"version two".to_vec()).unwrap();
+        assert!(responder.insert(0, Vec::new()).is_err());
+
+        let initiator = DictionaryNegotiator::new(initiator);
+        let responder = DictionaryNegotiator::new(responder);
+        let (accept, responder_side) = responder.respond(&wire(&initiator.advertise())).unwrap();
+        assert_eq!(accept, DictionaryMessage::Accept(vec![1]));
+        let initiator_side = initiator.complete(&wire(&accept)).unwrap();
+        assert_eq!(initiator_side.ids(), vec![1]);
+        assert!(initiator.complete(&DictionaryMessage::Accept(vec![4])).is_err());
+
+        let payload = b"{\"user\": \"ada\", \"event\": \"login\", \"region\": \"eu-west\"}";
+        let with_dictionary = initiator_side.compress(payload, Some(1)).unwrap();
+        assert_eq!(with_dictionary[0], 1);
+        assert_eq!(responder_side.decompress(&with_dictionary).unwrap(), payload);
+
+        // Dictionaries that were not agreed on fall back to plain zstd
+        let fallback = initiator_side.compress(payload, Some(2)).unwrap();
+        assert_eq!(fallback[0], 0);
+        assert_eq!(responder_side.decompress(&fallback).unwrap(), payload);
+        assert!(responder_side.decompress(&[5, 0]).is_err());
+    }
+}
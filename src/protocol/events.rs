@@ -0,0 +1,197 @@
+// Structured log of wire-level protocol events
+//
+// Security monitoring wants to see what happens on a connection, not only its
+// counters: a burst of checksum failures or a handshake that never completes
+// is worth an alert. A `ProtocolEventLog` attached to a `PacketReader` or a
+// `ConnectionStatsRegistry` emits each such event as a `tracing` event with
+// target `tonitru::protocol`, so a SIEM pipeline can filter on it without
+// parsing messages. Events carry an `event` field naming the event and a
+// `connection_id` field, followed by the fields of the event; these names are
+// part of the public interface and do not change between releases.
+//
+// Logging is opt-in twice over: events are only emitted with the
+// `protocol-events` feature, which pulls in `tracing`, and only by components
+// a log was attached to. Without the feature, attaching a log compiles to
+// nothing. Rekeys are not detected by this crate; transport code emits them
+// through `ProtocolEventLog::emit` itself.
+
+use std::time::Duration;
+
+/// `tracing` target of protocol events
+pub const EVENT_TARGET: &str = "tonitru::protocol";
+
+/// A wire-level protocol event.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ProtocolEvent<'a> {
+    /// A complete frame arrived and its packet passed checksum verification.
+    FrameReceived {
+        /// Number of the frame on the connection, starting at 1
+        sequence: u64,
+        /// Size of the frame, excluding its length prefix
+        bytes: u64,
+        /// Schema ID recorded in the packet header
+        schema_id: u64,
+    },
+    /// A step of the handshake completed.
+    HandshakeStep {
+        /// Name of the step, such as `complete`
+        step: &'a str,
+        /// Time since the handshake started
+        elapsed: Duration,
+    },
+    /// The connection switched to a new key.
+    Rekey {
+        /// ID of the new key, if keys are named
+        key_id: Option<&'a str>,
+        /// Number of keys used on the connection so far, starting at 1
+        generation: u64,
+    },
+    /// A packet was retransmitted.
+    Retransmit {
+        /// Retransmits on the connection so far, including this one
+        retransmits: u64,
+    },
+    /// A frame arrived whose packet failed checksum verification.
+    ChecksumFailure {
+        /// Number of the frame on the connection, starting at 1
+        sequence: u64,
+        /// Size of the frame, excluding its length prefix
+        bytes: u64,
+    },
+}
+
+impl ProtocolEvent<'_> {
+    /// Returns the value of the `event` field of the event.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProtocolEvent::FrameReceived { .. } => "frame_received",
+            ProtocolEvent::HandshakeStep { .. } => "handshake_step",
+            ProtocolEvent::Rekey { .. } => "rekey",
+            ProtocolEvent::Retransmit { .. } => "retransmit",
+            ProtocolEvent::ChecksumFailure { .. } => "checksum_failure",
+        }
+    }
+}
+
+/// Emits the protocol events of one connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProtocolEventLog {
+    connection_id: u64,
+}
+
+impl ProtocolEventLog {
+    /// Creates a log for the events of the connection.
+    pub fn new(connection_id: u64) -> Self {
+        ProtocolEventLog { connection_id }
+    }
+
+    /// Returns the ID of the connection the events belong to.
+    pub fn connection_id(&self) -> u64 {
+        self.connection_id
+    }
+
+    /// Emits the event. Checksum failures are logged at `WARN` level, frames at
+    /// `DEBUG` and the other events at `INFO`.
+    #[cfg(feature = "protocol-events")]
+    pub fn emit(&self, event: &ProtocolEvent<'_>) {
+        let connection_id = self.connection_id;
+        let name = event.name();
+        match *event {
+            ProtocolEvent::FrameReceived { sequence, bytes, schema_id } => {
+                tracing::debug!(target: EVENT_TARGET, event = name, connection_id, sequence, bytes, schema_id)
+            }
+            ProtocolEvent::HandshakeStep { step, elapsed } => {
+                let elapsed_us = elapsed.as_micros() as u64;
+                tracing::info!(target: EVENT_TARGET, event = name, connection_id, step, elapsed_us)
+            }
+            ProtocolEvent::Rekey { key_id, generation } => {
+                tracing::info!(target: EVENT_TARGET, event = name, connection_id, key_id, generation)
+            }
+            ProtocolEvent::Retransmit { retransmits } => {
+                tracing::info!(target: EVENT_TARGET, event = name, connection_id, retransmits)
+            }
+            ProtocolEvent::ChecksumFailure { sequence, bytes } => {
+                tracing::warn!(target: EVENT_TARGET, event = name, connection_id, sequence, bytes)
+            }
+        }
+    }
+
+    /// Emits the event. Without the `protocol-events` feature this does nothing.
+    #[cfg(not(feature = "protocol-events"))]
+    pub fn emit(&self, _event: &ProtocolEvent<'_>) {}
+}
+
+/// Emits the event to the log, if one is attached.
+pub(crate) fn emit(log: &Option<ProtocolEventLog>, event: ProtocolEvent<'_>) {
+    if let Some(log) = log {
+        log.emit(&event);
+    }
+}
+
+#[cfg(all(test, feature = "protocol-events"))]
+mod tests {
+    use super::*;
+    use std::fmt;
+    use std::sync::{Arc, Mutex};
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    /// Records every event as its level, target and `name=value` fields.
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<String>>>,
+    }
+
+    struct Fields(String);
+
+    impl Visit for Fields {
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            self.0.push_str(&format!(" {}={:?}", field.name(), value));
+        }
+
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0.push_str(&format!(" {}={}", field.name(), value));
+        }
+    }
+
+    impl Subscriber for Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+        fn event(&self, event: &Event<'_>) {
+            let metadata = event.metadata();
+            let mut fields = Fields(format!("{} {}", metadata.level(), metadata.target()));
+            event.record(&mut fields);
+            self.events.lock().unwrap().push(fields.0);
+        }
+        fn enter(&self, _: &Id) {}
+        fn exit(&self, _: &Id) {}
+    }
+
+    #[test]
+    fn test_events_have_stable_fields() {
+        let recorder = Recorder::default();
+        let log = ProtocolEventLog::new(7);
+        tracing::subscriber::with_default(recorder.clone(), || {
+            log.emit(&ProtocolEvent::FrameReceived { sequence: 1, bytes: 120, schema_id: 42 });
+            log.emit(&ProtocolEvent::HandshakeStep { step: "complete", elapsed: Duration::from_millis(3) });
+            log.emit(&ProtocolEvent::Rekey { key_id: Some("k2"), generation: 2 });
+            log.emit(&ProtocolEvent::Retransmit { retransmits: 4 });
+            log.emit(&ProtocolEvent::ChecksumFailure { sequence: 2, bytes: 64 });
+        });
+
+        assert_eq!(*recorder.events.lock().unwrap(), vec![
+            "DEBUG tonitru::protocol event=frame_received connection_id=7 sequence=1 bytes=120 schema_id=42",
+            "INFO tonitru::protocol event=handshake_step connection_id=7 step=complete elapsed_us=3000",
+            "INFO tonitru::protocol event=rekey connection_id=7 key_id=k2 generation=2",
+            "INFO tonitru::protocol event=retransmit connection_id=7 retransmits=4",
+            "WARN tonitru::protocol event=checksum_failure connection_id=7 sequence=2 bytes=64",
+        ]);
+    }
+}
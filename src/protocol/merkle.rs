@@ -0,0 +1,114 @@
+// Merkle trees over BLAKE3 hashes
+//
+// Leaves and inner nodes are hashed with distinct prefixes so that a leaf can
+// never be passed off as an inner node. A node without a sibling on its level
+// is promoted unchanged, so trees of any size need no padding.
+
+/// A BLAKE3 hash
+pub type Hash = [u8; 32];
+
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/// Hashes a leaf of the tree
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[LEAF_PREFIX]);
+    hasher.update(data);
+    *hasher.finalize().as_bytes()
+}
+
+/// Hashes an inner node from its children
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[NODE_PREFIX]);
+    hasher.update(left);
+    hasher.update(right);
+    *hasher.finalize().as_bytes()
+}
+
+/// Computes the next level of the tree
+fn parent_level(level: &[Hash]) -> Vec<Hash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Returns the root of the tree over the given leaf hashes; the root of an
+/// empty tree is the hash of an empty leaf
+pub fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return leaf_hash(&[]);
+    }
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = parent_level(&level);
+    }
+    level[0]
+}
+
+/// Returns the sibling hashes proving the leaf at `index` from the bottom of
+/// the tree up
+pub fn merkle_proof(leaves: &[Hash], mut index: usize) -> Vec<Hash> {
+    let mut proof = Vec::new();
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        let sibling = index ^ 1;
+        if sibling < level.len() {
+            proof.push(level[sibling]);
+        }
+        level = parent_level(&level);
+        index /= 2;
+    }
+    proof
+}
+
+/// Checks that `leaf` is the leaf at `index` of a tree of `leaf_count` leaves
+/// with the given root
+pub fn verify_proof(leaf: &Hash, mut index: usize, mut leaf_count: usize, proof: &[Hash], root: &Hash) -> bool {
+    if index >= leaf_count {
+        return false;
+    }
+    let mut hash = *leaf;
+    let mut siblings = proof.iter();
+    while leaf_count > 1 {
+        let sibling = index ^ 1;
+        if sibling < leaf_count {
+            let Some(sibling_hash) = siblings.next() else {
+                return false;
+            };
+            hash = if index.is_multiple_of(2) { node_hash(&hash, sibling_hash) } else { node_hash(sibling_hash, &hash) };
+        }
+        leaf_count = leaf_count.div_ceil(2);
+        index /= 2;
+    }
+    siblings.next().is_none() && hash == *root
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_merkle_proofs() {
+        for count in 1..=9 {
+            let leaves: Vec<Hash> = (0..count).map(|i: u8| leaf_hash(&[i])).collect();
+            let root = merkle_root(&leaves);
+            for index in 0..leaves.len() {
+                let proof = merkle_proof(&leaves, index);
+                assert!(verify_proof(&leaves[index], index, count as usize, &proof, &root));
+                assert!(!verify_proof(&leaf_hash(b"other"), index, count as usize, &proof, &root));
+                if count > 1 {
+                    let other = (index + 1) % leaves.len();
+                    assert!(!verify_proof(&leaves[index], other, count as usize, &proof, &root));
+                }
+            }
+        }
+        assert_eq!(merkle_root(&[]), leaf_hash(&[]));
+    }
+}
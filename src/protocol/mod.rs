@@ -1 +1,14 @@
-// Placeholder for protocol module
\ No newline at end of file
+// Protocol module for Tonitru network transport
+//
+// The QUIC transport layer itself is not implemented yet. This module holds the
+// connection-level pieces that do not depend on it, such as statistics tracking,
+// the control messages of archive replication, of compression dictionary
+// negotiation and of schema synchronization, and the protocol event log.
+
+pub(crate) mod control;
+pub mod dictionaries;
+pub mod events;
+pub mod merkle;
+pub mod replication;
+pub mod schema_sync;
+pub mod stats;
@@ -0,0 +1,733 @@
+// Incremental replication of archives
+//
+// A primary mirrors finished archives to standby replicas with four control
+// packets, carried by whatever transport connects them:
+//
+//   Manifest  primary -> replica: the archive header, the index and footer,
+//             and per segment its length and the Merkle root of its chunks
+//   Resume    replica -> primary: the first chunk the replica still needs
+//   Chunk     primary -> replica: a chunk of segment data with the Merkle
+//             proof tying it to the root of its segment
+//   Complete  replica -> primary: the archive is in place and verified
+//
+// Archive files are the header, then the segments, then the index. A replica
+// appends verified chunks to a partial file and resumes from its length, so a
+// transfer interrupted by either side continues where it stopped. When a new
+// manifest shares a header and a run of leading segments with the one the
+// replica last saw, those bytes are kept and only the remaining segments are
+// shipped; an index rewritten in place (key rotation, shredding) ships no
+// segment data at all.
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::archive::{ArchiveReader, ByteReader};
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::varint::encode_varint;
+use crate::protocol::control::{self, bytes, read_bytes, read_u64, string, Fields};
+use crate::protocol::merkle::{leaf_hash, merkle_proof, merkle_root, verify_proof, Hash};
+
+/// Schema ID used in the metadata header of replication control packets.
+pub const REPLICATION_SCHEMA_ID: u64 = 0x5245_504C; // "REPL"
+
+/// Describes replication messages in errors
+const MESSAGE_NAME: &str = "replication message";
+
+// Message kinds
+const KIND_MANIFEST: u8 = 1;
+const KIND_RESUME: u8 = 2;
+const KIND_CHUNK: u8 = 3;
+const KIND_COMPLETE: u8 = 4;
+
+// Tags used for the fields of a replication message object
+const TAG_ARCHIVE: u64 = 2;
+const TAG_CHUNK_SIZE: u64 = 3;
+const TAG_HEADER: u64 = 4;
+const TAG_TAIL: u64 = 5;
+const TAG_SEGMENTS: u64 = 6;
+const TAG_SEGMENT: u64 = 7;
+const TAG_CHUNK: u64 = 8;
+const TAG_DATA: u64 = 9;
+const TAG_PROOF: u64 = 10;
+const TAG_ROOT: u64 = 11;
+
+/// Configuration of a replication source
+#[derive(Debug, Clone)]
+pub struct ReplicationConfig {
+    /// Size of the chunks segments are shipped in, in bytes
+    pub chunk_size: usize,
+}
+
+impl Default for ReplicationConfig {
+    fn default() -> Self {
+        Self { chunk_size: 64 * 1024 }
+    }
+}
+
+/// Length and Merkle root of the chunks of a segment
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SegmentDigest {
+    /// Length of the segment in bytes
+    pub length: u64,
+    /// Merkle root over the chunks of the segment
+    pub root: Hash,
+}
+
+/// Description of an archive sent to replicas before its data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ArchiveManifest {
+    /// File name of the archive on the replica
+    pub archive: String,
+    /// Size of the chunks of segment data
+    pub chunk_size: u64,
+    /// Header and schema section of the archive
+    pub header: Vec<u8>,
+    /// Index and footer of the archive
+    pub tail: Vec<u8>,
+    /// Digests of the segments, in file order
+    pub segments: Vec<SegmentDigest>,
+}
+
+impl ArchiveManifest {
+    /// Returns a fingerprint of the whole archive: the Merkle root over the
+    /// header, the tail and the segment roots
+    pub fn root(&self) -> Hash {
+        let mut leaves = vec![leaf_hash(&self.header), leaf_hash(&self.tail)];
+        leaves.extend(self.segments.iter().map(|segment| segment.root));
+        merkle_root(&leaves)
+    }
+
+    /// Returns the number of chunks of a segment
+    pub fn chunk_count(&self, segment: usize) -> u64 {
+        self.segments[segment].length.div_ceil(self.chunk_size).max(1)
+    }
+
+    /// Returns the length of a chunk
+    fn chunk_length(&self, segment: usize, chunk: u64) -> u64 {
+        let start = chunk * self.chunk_size;
+        (self.segments[segment].length - start).min(self.chunk_size)
+    }
+
+    /// Returns the length of the header and the first `count` segments
+    fn prefix_length(&self, count: usize) -> u64 {
+        self.header.len() as u64 + self.segments[..count].iter().map(|segment| segment.length).sum::<u64>()
+    }
+
+    /// Maps the length of a partial file to the next chunk it needs; returns
+    /// the segment, the chunk and the length of the file up to that chunk
+    fn position(&self, length: u64) -> Option<(usize, u64, u64)> {
+        let mut offset = self.header.len() as u64;
+        if length < offset {
+            return None;
+        }
+        for (segment, digest) in self.segments.iter().enumerate() {
+            if length < offset + digest.length {
+                let chunk = (length - offset) / self.chunk_size;
+                return Some((segment, chunk, offset + chunk * self.chunk_size));
+            }
+            offset += digest.length;
+        }
+        Some((self.segments.len(), 0, offset))
+    }
+
+    fn encode_segments(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        for segment in &self.segments {
+            out.extend_from_slice(&encode_varint(segment.length));
+            out.extend_from_slice(&segment.root);
+        }
+        out
+    }
+
+    fn decode_segments(data: &[u8]) -> Result<Vec<SegmentDigest>> {
+        let mut reader = ByteReader::new(data);
+        let mut segments = Vec::new();
+        while !reader.is_empty() {
+            let length = reader.read_varint()?;
+            let mut root = [0u8; 32];
+            for byte in &mut root {
+                *byte = reader.read_u8()?;
+            }
+            segments.push(SegmentDigest { length, root });
+        }
+        Ok(segments)
+    }
+}
+
+/// A replication control message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplicationMessage {
+    /// Describes an archive to replicate
+    Manifest(ArchiveManifest),
+    /// Asks for the chunks of an archive from the given position on
+    Resume { archive: String, segment: u64, chunk: u64 },
+    /// Carries a chunk of segment data
+    Chunk { archive: String, segment: u64, chunk: u64, data: Vec<u8>, proof: Vec<Hash> },
+    /// Confirms that the archive with the given manifest root is in place
+    Complete { archive: String, root: Hash },
+}
+
+impl ReplicationMessage {
+    /// Returns the name of the archive the message is about
+    pub fn archive(&self) -> &str {
+        match self {
+            ReplicationMessage::Manifest(manifest) => &manifest.archive,
+            ReplicationMessage::Resume { archive, .. }
+            | ReplicationMessage::Chunk { archive, .. }
+            | ReplicationMessage::Complete { archive, .. } => archive,
+        }
+    }
+
+    /// Converts the message into an HTLV object item.
+    pub fn to_htlv_item(&self) -> HtlvItem {
+        match self {
+            ReplicationMessage::Manifest(manifest) => control::message(KIND_MANIFEST, vec![
+                HtlvItem::new(TAG_ARCHIVE, string(&manifest.archive)),
+                HtlvItem::new(TAG_CHUNK_SIZE, HtlvValue::U64(manifest.chunk_size)),
+                HtlvItem::new(TAG_HEADER, bytes(&manifest.header)),
+                HtlvItem::new(TAG_TAIL, bytes(&manifest.tail)),
+                HtlvItem::new(TAG_SEGMENTS, bytes(&manifest.encode_segments())),
+            ]),
+            ReplicationMessage::Resume { archive, segment, chunk } => control::message(KIND_RESUME, vec![
+                HtlvItem::new(TAG_ARCHIVE, string(archive)),
+                HtlvItem::new(TAG_SEGMENT, HtlvValue::U64(*segment)),
+                HtlvItem::new(TAG_CHUNK, HtlvValue::U64(*chunk)),
+            ]),
+            ReplicationMessage::Chunk { archive, segment, chunk, data, proof } => control::message(KIND_CHUNK, vec![
+                HtlvItem::new(TAG_ARCHIVE, string(archive)),
+                HtlvItem::new(TAG_SEGMENT, HtlvValue::U64(*segment)),
+                HtlvItem::new(TAG_CHUNK, HtlvValue::U64(*chunk)),
+                HtlvItem::new(TAG_DATA, bytes(data)),
+                HtlvItem::new(TAG_PROOF, bytes(&proof.concat())),
+            ]),
+            ReplicationMessage::Complete { archive, root } => control::message(KIND_COMPLETE, vec![
+                HtlvItem::new(TAG_ARCHIVE, string(archive)),
+                HtlvItem::new(TAG_ROOT, bytes(root)),
+            ]),
+        }
+    }
+
+    /// Parses a message from an HTLV object item produced by `to_htlv_item`.
+    pub fn from_htlv_item(item: &HtlvItem) -> Result<Self> {
+        let fields = Fields::of(item, MESSAGE_NAME)?;
+        let archive = || -> Result<String> {
+            String::from_utf8(read_bytes(fields.get(TAG_ARCHIVE)?)?.to_vec())
+                .map_err(|e| Error::ProtocolError(format!("Invalid UTF-8 in archive name: {}", e)))
+        };
+
+        match fields.kind()? {
+            KIND_MANIFEST => {
+                let chunk_size = read_u64(fields.get(TAG_CHUNK_SIZE)?)?;
+                if chunk_size == 0 {
+                    return Err(Error::ProtocolError("Manifest chunk size must not be 0".to_string()));
+                }
+                Ok(ReplicationMessage::Manifest(ArchiveManifest {
+                    archive: archive()?,
+                    chunk_size,
+                    header: read_bytes(fields.get(TAG_HEADER)?)?.to_vec(),
+                    tail: read_bytes(fields.get(TAG_TAIL)?)?.to_vec(),
+                    segments: ArchiveManifest::decode_segments(read_bytes(fields.get(TAG_SEGMENTS)?)?)?,
+                }))
+            }
+            KIND_RESUME => Ok(ReplicationMessage::Resume {
+                archive: archive()?,
+                segment: read_u64(fields.get(TAG_SEGMENT)?)?,
+                chunk: read_u64(fields.get(TAG_CHUNK)?)?,
+            }),
+            KIND_CHUNK => {
+                let proof = read_bytes(fields.get(TAG_PROOF)?)?;
+                if proof.len() % 32 != 0 {
+                    return Err(Error::ProtocolError(format!("Invalid Merkle proof length: {}", proof.len())));
+                }
+                Ok(ReplicationMessage::Chunk {
+                    archive: archive()?,
+                    segment: read_u64(fields.get(TAG_SEGMENT)?)?,
+                    chunk: read_u64(fields.get(TAG_CHUNK)?)?,
+                    data: read_bytes(fields.get(TAG_DATA)?)?.to_vec(),
+                    proof: proof.chunks(32).map(|hash| hash.try_into().unwrap()).collect(),
+                })
+            }
+            KIND_COMPLETE => Ok(ReplicationMessage::Complete {
+                archive: archive()?,
+                root: read_bytes(fields.get(TAG_ROOT)?)?.try_into()
+                    .map_err(|_| Error::ProtocolError("Invalid manifest root length".to_string()))?,
+            }),
+            kind => Err(Error::ProtocolError(format!("Unknown replication message kind: {}", kind))),
+        }
+    }
+
+    /// Builds an HTLV control packet carrying this message.
+    pub fn to_control_packet(&self, timestamp: u64) -> Result<Packet> {
+        control::to_control_packet(REPLICATION_SCHEMA_ID, timestamp, &self.to_htlv_item())
+    }
+
+    /// Parses a message from a control packet built by `to_control_packet`.
+    pub fn from_control_packet(packet: &Packet) -> Result<Self> {
+        Self::from_htlv_item(&control::from_control_packet(packet, REPLICATION_SCHEMA_ID, MESSAGE_NAME)?)
+    }
+}
+
+/// Serves the chunks of a finished archive to replicas
+#[derive(Debug)]
+pub struct ReplicationSource<R: Read + Seek> {
+    reader: R,
+    manifest: ArchiveManifest,
+    /// Offset of each segment in the archive
+    offsets: Vec<u64>,
+    /// Leaf hashes of the chunks of each segment
+    leaves: Vec<Vec<Hash>>,
+}
+
+impl ReplicationSource<File> {
+    /// Opens the archive file at `path`, to be replicated under its file name
+    pub fn open(path: impl AsRef<Path>, config: ReplicationConfig) -> Result<Self> {
+        let path = path.as_ref();
+        let archive = path.file_name().and_then(|name| name.to_str())
+            .ok_or_else(|| Error::ProtocolError(format!("Invalid archive path: {}", path.display())))?;
+        Self::new(File::open(path)?, archive, config)
+    }
+}
+
+impl<R: Read + Seek> ReplicationSource<R> {
+    /// Reads an archive and hashes its segments, to be replicated under the
+    /// given name
+    pub fn new(mut reader: R, archive: &str, config: ReplicationConfig) -> Result<Self> {
+        check_archive_name(archive)?;
+        let chunk_size = config.chunk_size.max(1) as u64;
+        let (index, index_offset) = ArchiveReader::open(&mut reader)?.into_index();
+        let data_start = index.segments.first().map_or(index_offset, |segment| segment.offset);
+        let archive_len = reader.seek(SeekFrom::End(0))?;
+
+        let header = read_range(&mut reader, 0, data_start)?;
+        let tail = read_range(&mut reader, index_offset, archive_len - index_offset)?;
+        let mut segments = Vec::with_capacity(index.segments.len());
+        let mut leaves = Vec::with_capacity(index.segments.len());
+        for segment in &index.segments {
+            let data = read_range(&mut reader, segment.offset, segment.length)?;
+            let segment_leaves: Vec<Hash> = if data.is_empty() {
+                vec![leaf_hash(&[])]
+            } else {
+                data.chunks(chunk_size as usize).map(leaf_hash).collect()
+            };
+            segments.push(SegmentDigest { length: segment.length, root: merkle_root(&segment_leaves) });
+            leaves.push(segment_leaves);
+        }
+
+        let manifest = ArchiveManifest { archive: archive.to_string(), chunk_size, header, tail, segments };
+        let offsets = index.segments.iter().map(|segment| segment.offset).collect();
+        Ok(Self { reader, manifest, offsets, leaves })
+    }
+
+    /// Returns the manifest to send to replicas
+    pub fn manifest(&self) -> &ArchiveManifest {
+        &self.manifest
+    }
+
+    /// Builds the message carrying a chunk of a segment
+    pub fn chunk(&mut self, segment: u64, chunk: u64) -> Result<ReplicationMessage> {
+        let (segment_index, chunk_index) = (segment as usize, chunk as usize);
+        let leaves = self.leaves.get(segment_index)
+            .filter(|leaves| chunk_index < leaves.len())
+            .ok_or_else(|| Error::ProtocolError(format!("Archive has no chunk {} in segment {}", chunk, segment)))?;
+        let proof = merkle_proof(leaves, chunk_index);
+        let offset = self.offsets[segment_index] + chunk * self.manifest.chunk_size;
+        let data = read_range(&mut self.reader, offset, self.manifest.chunk_length(segment_index, chunk))?;
+        Ok(ReplicationMessage::Chunk { archive: self.manifest.archive.clone(), segment, chunk, data, proof })
+    }
+
+    /// Returns the positions of the chunks from `segment`/`chunk` to the end
+    /// of the archive, in the order a replica expects them
+    pub fn chunks_from(&self, segment: u64, chunk: u64) -> Vec<(u64, u64)> {
+        let mut positions = Vec::new();
+        for (index, leaves) in self.leaves.iter().enumerate().skip(segment as usize) {
+            let first = if index as u64 == segment { chunk } else { 0 };
+            positions.extend((first..leaves.len() as u64).map(|chunk| (index as u64, chunk)));
+        }
+        positions
+    }
+
+    /// Answers a message from a replica with the messages to send back:
+    /// the missing chunks for `Resume`, nothing for `Complete`
+    pub fn respond(&mut self, message: &ReplicationMessage) -> Result<Vec<ReplicationMessage>> {
+        if message.archive() != self.manifest.archive {
+            return Err(Error::ProtocolError(format!("Not replicating archive '{}'", message.archive())));
+        }
+        match message {
+            ReplicationMessage::Resume { segment, chunk, .. } => self
+                .chunks_from(*segment, *chunk)
+                .into_iter()
+                .map(|(segment, chunk)| self.chunk(segment, chunk))
+                .collect(),
+            ReplicationMessage::Complete { root, .. } if *root != self.manifest.root() => Err(Error::ProtocolError(
+                format!("Replica of '{}' completed with a different manifest", self.manifest.archive),
+            )),
+            ReplicationMessage::Complete { .. } => Ok(Vec::new()),
+            _ => Err(Error::ProtocolError("Unexpected message for a replication source".to_string())),
+        }
+    }
+}
+
+/// Receives archives from a primary into a directory
+///
+/// For each archive the replica keeps `<name>.manifest`, the last manifest
+/// received, and while a transfer is under way `<name>.partial`, the header
+/// and verified segment data received so far.
+#[derive(Debug)]
+pub struct Replica {
+    dir: PathBuf,
+    manifests: HashMap<String, ArchiveManifest>,
+}
+
+impl Replica {
+    /// Opens the replica directory, creating it if needed
+    pub fn open(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(Self { dir, manifests: HashMap::new() })
+    }
+
+    /// Returns the path of a replicated archive
+    pub fn archive_path(&self, archive: &str) -> PathBuf {
+        self.dir.join(archive)
+    }
+
+    /// Handles a message from the primary; returns the reply, if any
+    ///
+    /// Chunks that arrive out of order are dropped and answered with a
+    /// `Resume` for the expected chunk. Chunks that fail verification are
+    /// rejected with an error.
+    pub fn handle(&mut self, message: &ReplicationMessage) -> Result<Option<ReplicationMessage>> {
+        check_archive_name(message.archive())?;
+        match message {
+            ReplicationMessage::Manifest(manifest) => self.begin(manifest).map(Some),
+            ReplicationMessage::Chunk { archive, segment, chunk, data, proof } => {
+                self.receive(archive, *segment, *chunk, data, proof)
+            }
+            _ => Err(Error::ProtocolError("Unexpected message for a replica".to_string())),
+        }
+    }
+
+    fn file(&self, archive: &str, extension: &str) -> PathBuf {
+        self.dir.join(format!("{}.{}", archive, extension))
+    }
+
+    /// Starts or resumes the transfer of an archive
+    fn begin(&mut self, manifest: &ArchiveManifest) -> Result<ReplicationMessage> {
+        if manifest.chunk_size == 0 {
+            return Err(Error::ProtocolError("Manifest chunk size must not be 0".to_string()));
+        }
+        let archive = &manifest.archive;
+        let final_path = self.archive_path(archive);
+        let partial_path = self.file(archive, "partial");
+        let previous = self.load_manifest(archive)?;
+
+        if previous.as_ref() == Some(manifest) && final_path.exists() && !partial_path.exists() {
+            return Ok(ReplicationMessage::Complete { archive: archive.clone(), root: manifest.root() });
+        }
+
+        // Keep the bytes shared with the previous manifest: the header and the
+        // leading segments with identical digests
+        let keep = match &previous {
+            Some(previous) if previous.header == manifest.header && previous.chunk_size == manifest.chunk_size => {
+                let shared = previous.segments.iter().zip(&manifest.segments).take_while(|(a, b)| a == b).count();
+                let shared_length = manifest.prefix_length(shared);
+                // Past the shared prefix, a partial file holds chunks of
+                // segments that have changed since
+                if partial_path.exists() {
+                    fs::metadata(&partial_path)?.len().min(shared_length)
+                } else if final_path.exists() {
+                    shared_length.min(fs::metadata(&final_path)?.len())
+                } else {
+                    0
+                }
+            }
+            _ => 0,
+        };
+
+        if partial_path.exists() {
+            OpenOptions::new().write(true).open(&partial_path)?.set_len(keep)?;
+        } else {
+            let mut partial = File::create(&partial_path)?;
+            if keep > 0 {
+                let mut base = File::open(&final_path)?.take(keep);
+                std::io::copy(&mut base, &mut partial)?;
+            }
+        }
+        if keep < manifest.header.len() as u64 {
+            let mut partial = OpenOptions::new().write(true).open(&partial_path)?;
+            partial.set_len(0)?;
+            partial.write_all(&manifest.header)?;
+        }
+
+        let stored = ReplicationMessage::Manifest(manifest.clone()).to_control_packet(0)?;
+        fs::write(self.file(archive, "manifest"), stored.encode_packet()?)?;
+        self.manifests.insert(archive.clone(), manifest.clone());
+        self.resume_or_finish(archive)
+    }
+
+    /// Verifies and appends a chunk
+    fn receive(&mut self, archive: &str, segment: u64, chunk: u64, data: &[u8], proof: &[Hash]) -> Result<Option<ReplicationMessage>> {
+        let manifest = self.load_manifest(archive)?
+            .ok_or_else(|| Error::ProtocolError(format!("No manifest received for archive '{}'", archive)))?;
+        let partial_path = self.file(archive, "partial");
+        if !partial_path.exists() {
+            return Err(Error::ProtocolError(format!("No transfer under way for archive '{}'", archive)));
+        }
+
+        let (expected_segment, expected_chunk, length) = self.position(&manifest, &partial_path)?;
+        // A complete partial file expects no more chunks
+        if expected_segment == manifest.segments.len() || (segment as usize, chunk) != (expected_segment, expected_chunk) {
+            return self.resume_or_finish(archive).map(Some);
+        }
+        let segment = segment as usize;
+        let valid = data.len() as u64 == manifest.chunk_length(segment, chunk)
+            && verify_proof(&leaf_hash(data), chunk as usize, manifest.chunk_count(segment) as usize, proof, &manifest.segments[segment].root);
+        if !valid {
+            return Err(Error::ProtocolError(format!(
+                "Chunk {} of segment {} of '{}' failed verification", chunk, segment, archive
+            )));
+        }
+
+        let mut partial = OpenOptions::new().write(true).open(&partial_path)?;
+        partial.seek(SeekFrom::Start(length))?;
+        partial.write_all(data)?;
+
+        let complete = segment + 1 == manifest.segments.len() && chunk + 1 == manifest.chunk_count(segment);
+        if complete {
+            self.finish(&manifest).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Asks for the next missing chunk, or finishes the archive if none is
+    /// missing
+    fn resume_or_finish(&mut self, archive: &str) -> Result<ReplicationMessage> {
+        let manifest = self.load_manifest(archive)?
+            .ok_or_else(|| Error::ProtocolError(format!("No manifest received for archive '{}'", archive)))?;
+        let (segment, chunk, _) = self.position(&manifest, &self.file(archive, "partial"))?;
+        if segment == manifest.segments.len() {
+            return self.finish(&manifest);
+        }
+        Ok(ReplicationMessage::Resume { archive: archive.to_string(), segment: segment as u64, chunk })
+    }
+
+    /// Returns the next chunk the partial file needs, dropping any torn chunk
+    /// at its end
+    fn position(&self, manifest: &ArchiveManifest, partial_path: &Path) -> Result<(usize, u64, u64)> {
+        let length = fs::metadata(partial_path)?.len();
+        let (segment, chunk, aligned) = manifest.position(length)
+            .ok_or_else(|| Error::ProtocolError(format!("Partial file of '{}' lacks its header", manifest.archive)))?;
+        if aligned != length {
+            OpenOptions::new().write(true).open(partial_path)?.set_len(aligned)?;
+        }
+        Ok((segment, chunk, aligned))
+    }
+
+    /// Appends the index, checks the archive and moves it into place
+    fn finish(&mut self, manifest: &ArchiveManifest) -> Result<ReplicationMessage> {
+        let partial_path = self.file(&manifest.archive, "partial");
+        let mut partial = OpenOptions::new().read(true).write(true).open(&partial_path)?;
+        partial.seek(SeekFrom::Start(manifest.prefix_length(manifest.segments.len())))?;
+        partial.write_all(&manifest.tail)?;
+        partial.sync_all()?;
+        ArchiveReader::open(&mut partial)?;
+        drop(partial);
+
+        fs::rename(&partial_path, self.archive_path(&manifest.archive))?;
+        Ok(ReplicationMessage::Complete { archive: manifest.archive.clone(), root: manifest.root() })
+    }
+
+    /// Returns the last manifest received for an archive, loading it from
+    /// disk if needed
+    fn load_manifest(&mut self, archive: &str) -> Result<Option<ArchiveManifest>> {
+        if let Some(manifest) = self.manifests.get(archive) {
+            return Ok(Some(manifest.clone()));
+        }
+        let path = self.file(archive, "manifest");
+        if !path.exists() {
+            return Ok(None);
+        }
+        match ReplicationMessage::from_control_packet(&Packet::parse_packet(&fs::read(&path)?)?)? {
+            ReplicationMessage::Manifest(manifest) => {
+                self.manifests.insert(archive.to_string(), manifest.clone());
+                Ok(Some(manifest))
+            }
+            _ => Err(Error::ProtocolError(format!("Invalid manifest file: {}", path.display()))),
+        }
+    }
+}
+
+/// Rejects archive names that are not plain file names
+fn check_archive_name(archive: &str) -> Result<()> {
+    let plain = !archive.is_empty()
+        && archive != "."
+        && archive != ".."
+        && !archive.contains(['/', '\\']);
+    if !plain {
+        return Err(Error::ProtocolError(format!("Invalid archive name: '{}'", archive)));
+    }
+    Ok(())
+}
+
+/// Reads `length` bytes at `offset`
+fn read_range<R: Read + Seek>(reader: &mut R, offset: u64, length: u64) -> Result<Vec<u8>> {
+    let mut data = vec![0; length as usize];
+    reader.seek(SeekFrom::Start(offset))?;
+    reader.read_exact(&mut data)?;
+    Ok(data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::archive::{shred_segments, ArchiveConfig, ArchiveWriter};
+    use crate::codec::encode::encode_item;
+    use crate::internal::packet::{DataBody, MetadataHeader};
+    use bytes::Bytes;
+    use crate::encrypt::key_management::{KeyManager, KeyType};
+    use std::io::Cursor;
+    use std::sync::Arc;
+
+    fn packet(timestamp: u64) -> Packet {
+        let item = HtlvItem::new(1, HtlvValue::String(Bytes::from(format!("event {:04}", timestamp))));
+        let header = MetadataHeader { schema_id: 1, timestamp, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(encode_item(&item).unwrap())).unwrap()
+    }
+
+    fn archive(count: u64) -> Vec<u8> {
+        let config = ArchiveConfig { segment_packets: 4, ..ArchiveConfig::default() };
+        let mut writer = ArchiveWriter::with_config(Vec::new(), config);
+        writer.add_schema(1, br#"{"id": "e", "name": "E", "version": "1.0.0", "type": "string"}"#.to_vec()).unwrap();
+        for timestamp in 0..count {
+            writer.append(&packet(timestamp)).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    fn source(data: Vec<u8>) -> ReplicationSource<Cursor<Vec<u8>>> {
+        ReplicationSource::new(Cursor::new(data), "events.tna", ReplicationConfig { chunk_size: 50 }).unwrap()
+    }
+
+    /// Runs the protocol over control packets; returns the number of chunks
+    /// shipped
+    fn replicate<R: Read + Seek>(source: &mut ReplicationSource<R>, replica: &mut Replica) -> usize {
+        let wire = |message: &ReplicationMessage| {
+            let packet = message.to_control_packet(0).unwrap();
+            ReplicationMessage::from_control_packet(&Packet::parse_packet(&packet.encode_packet().unwrap()).unwrap()).unwrap()
+        };
+        let mut reply = replica.handle(&wire(&ReplicationMessage::Manifest(source.manifest().clone()))).unwrap().unwrap();
+        let mut shipped = 0;
+        loop {
+            let chunks = source.respond(&wire(&reply)).unwrap();
+            if chunks.is_empty() {
+                return shipped;
+            }
+            for chunk in &chunks {
+                shipped += 1;
+                if let Some(next) = replica.handle(&wire(chunk)).unwrap() {
+                    reply = next;
+                }
+            }
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("tonitru-replication-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn test_replicate_and_resume() {
+        let dir = temp_dir("resume");
+        let data = archive(10);
+        let mut source = source(data.clone());
+        let total = source.chunks_from(0, 0).len();
+        assert!(total > 3);
+
+        // Interrupted transfer: only the first two chunks arrive
+        let mut replica = Replica::open(&dir).unwrap();
+        let reply = replica.handle(&ReplicationMessage::Manifest(source.manifest().clone())).unwrap();
+        assert_eq!(reply, Some(ReplicationMessage::Resume { archive: "events.tna".to_string(), segment: 0, chunk: 0 }));
+        for (segment, chunk) in source.chunks_from(0, 0).into_iter().take(2) {
+            assert_eq!(replica.handle(&source.chunk(segment, chunk).unwrap()).unwrap(), None);
+        }
+
+        // Out-of-order chunks are answered with the expected position
+        let (segment, chunk) = source.chunks_from(0, 0)[3];
+        let reply = replica.handle(&source.chunk(segment, chunk).unwrap()).unwrap();
+        let (segment, chunk) = source.chunks_from(0, 0)[2];
+        assert_eq!(reply, Some(ReplicationMessage::Resume { archive: "events.tna".to_string(), segment, chunk }));
+
+        // Tampered chunks are rejected
+        if let ReplicationMessage::Chunk { archive, segment, chunk, mut data, proof } = source.chunk(segment, chunk).unwrap() {
+            data[0] ^= 1;
+            assert!(replica.handle(&ReplicationMessage::Chunk { archive, segment, chunk, data, proof }).is_err());
+        }
+
+        // A restarted replica resumes where it stopped
+        let mut replica = Replica::open(&dir).unwrap();
+        assert_eq!(replicate(&mut source, &mut replica), total - 2);
+        assert_eq!(fs::read(replica.archive_path("events.tna")).unwrap(), data);
+        assert_eq!(replicate(&mut source, &mut replica), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_incremental_replication() {
+        let dir = temp_dir("incremental");
+        let mut replica = Replica::open(&dir).unwrap();
+        let mut old = source(archive(8));
+        replicate(&mut old, &mut replica);
+
+        // Only the segments after the shared ones are shipped
+        let data = archive(12);
+        let mut new = source(data.clone());
+        let new_chunks = new.chunks_from(2, 0).len();
+        assert_eq!(replicate(&mut new, &mut replica), new_chunks);
+        assert_eq!(fs::read(replica.archive_path("events.tna")).unwrap(), data);
+
+        // An index rewritten in place ships no segment data
+        let keys = Arc::new(KeyManager::new());
+        let master = keys.generate_key(KeyType::AesGcm, true).unwrap();
+        let mut writer = ArchiveWriter::new(Vec::new());
+        writer.set_encryption(keys, &master).unwrap();
+        writer.append(&packet(1)).unwrap();
+        let primary = temp_dir("primary");
+        fs::create_dir_all(&primary).unwrap();
+        let path = primary.join("secret.tna");
+        fs::write(&path, writer.finish().unwrap()).unwrap();
+        replicate(&mut ReplicationSource::open(&path, ReplicationConfig::default()).unwrap(), &mut replica);
+
+        shred_segments(&path, &[0]).unwrap();
+        let mut source = ReplicationSource::open(&path, ReplicationConfig::default()).unwrap();
+        assert_eq!(replicate(&mut source, &mut replica), 0);
+        assert_eq!(fs::read(replica.archive_path("secret.tna")).unwrap(), fs::read(&path).unwrap());
+        fs::remove_dir_all(&dir).unwrap();
+        fs::remove_dir_all(&primary).unwrap();
+    }
+
+    #[test]
+    fn test_replication_message_packets() {
+        let message = ReplicationMessage::Complete { archive: "a.tna".to_string(), root: [7; 32] };
+        let packet = message.to_control_packet(5).unwrap();
+        assert_eq!(ReplicationMessage::from_control_packet(&packet).unwrap(), message);
+        assert!(Replica::open(temp_dir("names")).unwrap()
+            .handle(&ReplicationMessage::Resume { archive: "../x".to_string(), segment: 0, chunk: 0 })
+            .is_err());
+
+        // A manifest with chunks of 0 bytes is rejected instead of dividing by 0
+        let mut manifest = source(archive(2)).manifest().clone();
+        manifest.chunk_size = 0;
+        let message = ReplicationMessage::Manifest(manifest);
+        assert!(ReplicationMessage::from_control_packet(&message.to_control_packet(0).unwrap()).is_err());
+        let dir = temp_dir("zero-chunks");
+        assert!(Replica::open(&dir).unwrap().handle(&message).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
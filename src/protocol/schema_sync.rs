@@ -0,0 +1,411 @@
+// Delta synchronization of schema sets between peers
+//
+// Peers that exchange packets need the schemas the packets refer to. Instead of
+// each of them fetching schemas from a central registry, they can bring each
+// other up to date with three control packets:
+//
+//   Advertise    id, version and fingerprint of every schema the sender holds
+//   Request      the advertised schemas the receiver is missing
+//   Definitions  the JSON documents of the requested schemas
+//
+// Both peers advertise, so after one round each holds the union of the two
+// sets, and a fleet gossiping this way converges on the same schemas. Only
+// missing documents cross the wire; an advertisement costs a few dozen bytes
+// per schema.
+//
+// A schema version is immutable: a peer holding the same id and version with
+// another fingerprint (see `Schema::fingerprint`) does not request it and
+// records a conflict instead, for an operator to sort out. Received documents
+// are parsed and checked against what was advertised before they are added.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bytes::Bytes;
+
+use crate::archive::ByteReader;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::varint::encode_varint_into;
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::protocol::control::{self, read_bytes, Fields};
+use crate::schema::parser::SchemaParser;
+use crate::schema::types::{Schema, SchemaRegistry, SchemaVersion};
+
+/// Schema ID used in the metadata header of schema synchronization packets.
+pub const SCHEMA_SYNC_SCHEMA_ID: u64 = 0x5359_4E43; // "SYNC"
+
+// Message kinds
+const KIND_ADVERTISE: u8 = 1;
+const KIND_REQUEST: u8 = 2;
+const KIND_DEFINITIONS: u8 = 3;
+
+// Tags used for the fields of a synchronization message object
+const TAG_ENTRIES: u64 = 2;
+
+/// Describes synchronization messages in errors
+const MESSAGE_NAME: &str = "schema sync message";
+
+/// Id, version and fingerprint of a schema, as advertised to peers
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SchemaInfo {
+    /// Schema ID
+    pub id: String,
+    /// Schema version
+    pub version: SchemaVersion,
+    /// Structural fingerprint of the schema, see `Schema::fingerprint`
+    pub fingerprint: String,
+}
+
+#[derive(Debug, Clone)]
+struct SchemaEntry {
+    schema: Arc<Schema>,
+    fingerprint: String,
+    document: Arc<[u8]>,
+}
+
+/// The schemas a peer holds, with their JSON documents
+#[derive(Debug, Clone, Default)]
+pub struct SchemaSet {
+    schemas: HashMap<(String, SchemaVersion), SchemaEntry>,
+}
+
+impl SchemaSet {
+    /// Creates an empty set
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Parses a JSON schema document and adds it
+    ///
+    /// Adding a document identical in structure to the schema already held
+    /// under its id and version does nothing; adding one that differs fails.
+    pub fn insert(&mut self, document: Vec<u8>) -> Result<SchemaInfo> {
+        let json: serde_json::Value = serde_json::from_slice(&document)
+            .map_err(|e| Error::SchemaError(format!("Invalid schema document: {}", e)))?;
+        let schema = SchemaParser::new().parse_schema(&json)?;
+        let info = SchemaInfo { id: schema.id.clone(), version: schema.version.clone(), fingerprint: schema.fingerprint() };
+
+        let key = (info.id.clone(), info.version.clone());
+        if let Some(held) = self.schemas.get(&key) {
+            if held.fingerprint != info.fingerprint {
+                return Err(Error::SchemaError(format!(
+                    "Schema '{}' version {} is already held with another structure",
+                    info.id, info.version
+                )));
+            }
+            return Ok(info);
+        }
+        let entry = SchemaEntry { schema: Arc::new(schema), fingerprint: info.fingerprint.clone(), document: Arc::from(document) };
+        self.schemas.insert(key, entry);
+        Ok(info)
+    }
+
+    /// Returns the number of schemas
+    pub fn len(&self) -> usize {
+        self.schemas.len()
+    }
+
+    /// Returns true if the set holds no schema
+    pub fn is_empty(&self) -> bool {
+        self.schemas.is_empty()
+    }
+
+    /// Returns the schema with the id and version
+    pub fn get(&self, id: &str, version: &SchemaVersion) -> Option<Arc<Schema>> {
+        self.schemas.get(&(id.to_string(), version.clone())).map(|entry| entry.schema.clone())
+    }
+
+    /// Returns the JSON document of the schema with the id and version
+    pub fn document(&self, id: &str, version: &SchemaVersion) -> Option<&[u8]> {
+        self.schemas.get(&(id.to_string(), version.clone())).map(|entry| &entry.document[..])
+    }
+
+    /// Returns the id, version and fingerprint of every schema, ordered by id
+    /// and version
+    pub fn infos(&self) -> Vec<SchemaInfo> {
+        let mut infos: Vec<_> = self
+            .schemas
+            .iter()
+            .map(|((id, version), entry)| SchemaInfo {
+                id: id.clone(),
+                version: version.clone(),
+                fingerprint: entry.fingerprint.clone(),
+            })
+            .collect();
+        infos.sort_by(|a, b| {
+            (&a.id, a.version.major, a.version.minor, a.version.patch)
+                .cmp(&(&b.id, b.version.major, b.version.minor, b.version.patch))
+        });
+        infos
+    }
+
+    /// Registers every schema of the set in a registry that does not hold its
+    /// version yet, oldest version first
+    pub fn register_into(&self, registry: &mut SchemaRegistry) -> Result<()> {
+        for info in self.infos() {
+            if registry.get_schema_version(&info.id, &info.version).is_none() {
+                let schema = self.get(&info.id, &info.version).expect("info of a held schema");
+                registry.register_schema((*schema).clone())?;
+            }
+        }
+        Ok(())
+    }
+
+    fn entry(&self, info: &SchemaInfo) -> Option<&SchemaEntry> {
+        self.schemas.get(&(info.id.clone(), info.version.clone()))
+    }
+}
+
+/// A schema synchronization control message
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SchemaSyncMessage {
+    /// Lists the schemas the sender holds
+    Advertise(Vec<SchemaInfo>),
+    /// Lists the advertised schemas the sender is missing
+    Request(Vec<SchemaInfo>),
+    /// Carries the JSON documents of requested schemas
+    Definitions(Vec<Vec<u8>>),
+}
+
+impl SchemaSyncMessage {
+    /// Converts the message into an HTLV object item.
+    pub fn to_htlv_item(&self) -> HtlvItem {
+        let mut list = Vec::new();
+        let kind = match self {
+            SchemaSyncMessage::Advertise(infos) | SchemaSyncMessage::Request(infos) => {
+                for info in infos {
+                    write_bytes(info.id.as_bytes(), &mut list);
+                    encode_varint_into(info.version.major as u64, &mut list);
+                    encode_varint_into(info.version.minor as u64, &mut list);
+                    encode_varint_into(info.version.patch as u64, &mut list);
+                    write_bytes(info.fingerprint.as_bytes(), &mut list);
+                }
+                if matches!(self, SchemaSyncMessage::Advertise(_)) { KIND_ADVERTISE } else { KIND_REQUEST }
+            }
+            SchemaSyncMessage::Definitions(documents) => {
+                for document in documents {
+                    write_bytes(document, &mut list);
+                }
+                KIND_DEFINITIONS
+            }
+        };
+        control::message(kind, vec![HtlvItem::new(TAG_ENTRIES, HtlvValue::Bytes(Bytes::from(list)))])
+    }
+
+    /// Parses a message from an HTLV object item produced by `to_htlv_item`.
+    pub fn from_htlv_item(item: &HtlvItem) -> Result<Self> {
+        let fields = Fields::of(item, MESSAGE_NAME)?;
+        let mut reader = ByteReader::new(read_bytes(fields.get(TAG_ENTRIES)?)?);
+        fn read_string(reader: &mut ByteReader<'_>) -> Result<String> {
+            String::from_utf8(reader.read_bytes()?.to_vec())
+                .map_err(|_| Error::ProtocolError("Invalid UTF-8 in schema sync message".to_string()))
+        }
+        fn read_u32(reader: &mut ByteReader<'_>) -> Result<u32> {
+            let value = reader.read_varint()?;
+            u32::try_from(value).map_err(|_| Error::ProtocolError(format!("Invalid schema version number: {}", value)))
+        }
+
+        match fields.kind()? {
+            kind @ (KIND_ADVERTISE | KIND_REQUEST) => {
+                let mut infos = Vec::new();
+                while !reader.is_empty() {
+                    let id = read_string(&mut reader)?;
+                    let version = SchemaVersion::new(read_u32(&mut reader)?, read_u32(&mut reader)?, read_u32(&mut reader)?);
+                    let fingerprint = read_string(&mut reader)?;
+                    infos.push(SchemaInfo { id, version, fingerprint });
+                }
+                Ok(if kind == KIND_ADVERTISE { SchemaSyncMessage::Advertise(infos) } else { SchemaSyncMessage::Request(infos) })
+            }
+            KIND_DEFINITIONS => {
+                let mut documents = Vec::new();
+                while !reader.is_empty() {
+                    documents.push(reader.read_bytes()?.to_vec());
+                }
+                Ok(SchemaSyncMessage::Definitions(documents))
+            }
+            kind => Err(Error::ProtocolError(format!("Unknown schema sync message kind: {}", kind))),
+        }
+    }
+
+    /// Builds an HTLV control packet carrying this message.
+    pub fn to_control_packet(&self, timestamp: u64) -> Result<Packet> {
+        control::to_control_packet(SCHEMA_SYNC_SCHEMA_ID, timestamp, &self.to_htlv_item())
+    }
+
+    /// Parses a message from a control packet built by `to_control_packet`.
+    pub fn from_control_packet(packet: &Packet) -> Result<Self> {
+        Self::from_htlv_item(&control::from_control_packet(packet, SCHEMA_SYNC_SCHEMA_ID, MESSAGE_NAME)?)
+    }
+}
+
+fn write_bytes(bytes: &[u8], out: &mut Vec<u8>) {
+    encode_varint_into(bytes.len() as u64, out);
+    out.extend_from_slice(bytes);
+}
+
+/// Runs schema synchronization with one peer
+#[derive(Debug)]
+pub struct SchemaSync {
+    local: SchemaSet,
+    /// Schemas requested from the peer and not received yet
+    requested: Vec<SchemaInfo>,
+    /// Advertised schemas that conflict with a local schema
+    conflicts: Vec<SchemaInfo>,
+}
+
+impl SchemaSync {
+    /// Creates a synchronization offering and completing the local schemas
+    pub fn new(local: SchemaSet) -> Self {
+        Self { local, requested: Vec::new(), conflicts: Vec::new() }
+    }
+
+    /// Returns the message a peer opens synchronization with
+    pub fn advertise(&self) -> SchemaSyncMessage {
+        SchemaSyncMessage::Advertise(self.local.infos())
+    }
+
+    /// Handles a message from the peer and returns the answer, if any
+    ///
+    /// An advertisement is answered with a request for the missing schemas,
+    /// a request with their definitions; received definitions are added to
+    /// the local set.
+    pub fn handle(&mut self, message: &SchemaSyncMessage) -> Result<Option<SchemaSyncMessage>> {
+        match message {
+            SchemaSyncMessage::Advertise(infos) => {
+                let mut missing = Vec::new();
+                for info in infos {
+                    match self.local.entry(info) {
+                        None => missing.push(info.clone()),
+                        Some(entry) if entry.fingerprint != info.fingerprint => {
+                            if !self.conflicts.contains(info) {
+                                self.conflicts.push(info.clone());
+                            }
+                        }
+                        Some(_) => {}
+                    }
+                }
+                if missing.is_empty() {
+                    return Ok(None);
+                }
+                self.requested.extend(missing.iter().cloned());
+                Ok(Some(SchemaSyncMessage::Request(missing)))
+            }
+            SchemaSyncMessage::Request(infos) => {
+                let documents = infos
+                    .iter()
+                    .map(|info| match self.local.entry(info) {
+                        Some(entry) if entry.fingerprint == info.fingerprint => Ok(entry.document.to_vec()),
+                        _ => Err(Error::ProtocolError(format!(
+                            "Peer requested schema '{}' version {} that was not advertised",
+                            info.id, info.version
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>>>()?;
+                Ok(Some(SchemaSyncMessage::Definitions(documents)))
+            }
+            SchemaSyncMessage::Definitions(documents) => {
+                for document in documents {
+                    // Check against the request before the set takes the schema
+                    let mut candidate = SchemaSet::new();
+                    let info = candidate.insert(document.clone())?;
+                    let Some(position) = self.requested.iter().position(|requested| *requested == info) else {
+                        return Err(Error::ProtocolError(format!(
+                            "Peer sent schema '{}' version {} that was not requested or does not match its advertisement",
+                            info.id, info.version
+                        )));
+                    };
+                    self.requested.swap_remove(position);
+                    self.local.insert(document.clone())?;
+                }
+                Ok(None)
+            }
+        }
+    }
+
+    /// Returns the local schemas, including those received so far
+    pub fn local(&self) -> &SchemaSet {
+        &self.local
+    }
+
+    /// Returns the local schemas, ending the synchronization
+    pub fn into_local(self) -> SchemaSet {
+        self.local
+    }
+
+    /// Returns the schemas requested from the peer and not received yet
+    pub fn pending(&self) -> &[SchemaInfo] {
+        &self.requested
+    }
+
+    /// Returns the schemas the peer advertised with the id and version of a
+    /// local schema but another structure
+    pub fn conflicts(&self) -> &[SchemaInfo] {
+        &self.conflicts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(id: &str, version: &str, field: &str) -> Vec<u8> {
+        format!(
+            r#"{{"id": "{}", "name": "{}", "version": "{}", "type": "object",
+                "properties": {{"{}": {{"type": "string", "tag": 1}}}}}}"#,
+            id, id, version, field
+        )
+        .into_bytes()
+    }
+
+    fn wire(message: &SchemaSyncMessage) -> SchemaSyncMessage {
+        control::wire(message.to_control_packet(0), SchemaSyncMessage::from_control_packet)
+    }
+
+    #[test]
+    fn test_peers_converge_on_the_union_of_their_schemas() {
+        let mut a = SchemaSet::new();
+        a.insert(document("users", "1.0.0", "name")).unwrap();
+        a.insert(document("orders", "1.0.0", "item")).unwrap();
+        a.insert(document("events", "1.0.0", "kind")).unwrap();
+        let mut b = SchemaSet::new();
+        b.insert(document("users", "1.0.0", "name")).unwrap();
+        b.insert(document("users", "1.1.0", "email")).unwrap();
+        b.insert(document("events", "1.0.0", "type")).unwrap();
+        assert!(b.insert(document("events", "1.0.0", "other")).is_err());
+
+        let (mut a, mut b) = (SchemaSync::new(a), SchemaSync::new(b));
+        let request_from_b = b.handle(&wire(&a.advertise())).unwrap().unwrap();
+        let request_from_a = a.handle(&wire(&b.advertise())).unwrap().unwrap();
+        let SchemaSyncMessage::Request(requested) = &request_from_b else { panic!("expected a request") };
+        assert_eq!(requested.len(), 1);
+        assert_eq!(requested[0].id, "orders");
+
+        let to_b = a.handle(&wire(&request_from_b)).unwrap().unwrap();
+        let to_a = b.handle(&wire(&request_from_a)).unwrap().unwrap();
+        assert_eq!(b.handle(&wire(&to_b)).unwrap(), None);
+        assert_eq!(a.handle(&wire(&to_a)).unwrap(), None);
+
+        // Both hold the union, except the conflicting version of "events"
+        assert_eq!(a.local().infos().len(), 4);
+        let agreed = |sync: &SchemaSync| -> Vec<SchemaInfo> {
+            sync.local().infos().into_iter().filter(|info| info.id != "events").collect()
+        };
+        assert_eq!(agreed(&a), agreed(&b));
+        assert_eq!(a.conflicts().len(), 1);
+        assert_eq!(b.conflicts().len(), 1);
+        assert!(a.pending().is_empty() && b.pending().is_empty());
+        assert!(a.local().get("users", &SchemaVersion::new(1, 1, 0)).is_some());
+
+        // Nothing is requested once the sets agree, and unsolicited schemas are refused
+        assert_eq!(b.handle(&a.advertise()).unwrap(), None);
+        let unsolicited = SchemaSyncMessage::Definitions(vec![document("metrics", "1.0.0", "value")]);
+        assert!(b.handle(&unsolicited).is_err());
+        let events = a.local().infos().into_iter().find(|info| info.id == "events").unwrap();
+        assert!(b.handle(&SchemaSyncMessage::Request(vec![events])).is_err());
+
+        let mut registry = SchemaRegistry::new();
+        a.local().register_into(&mut registry).unwrap();
+        assert_eq!(registry.get_schema("users").unwrap().version, SchemaVersion::new(1, 1, 0));
+    }
+}
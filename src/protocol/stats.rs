@@ -0,0 +1,383 @@
+// Connection-level statistics for Tonitru transport
+//
+// This module tracks per-connection counters (packets, bytes, retransmits,
+// handshake time, cipher, compression ratio), derives a coarse health status
+// from them and exports snapshots as HTLV control packets for remote monitoring.
+// A registry with protocol events enabled also emits retransmits and completed
+// handshakes to the protocol event log.
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::protocol::control::{self, read_u64, Fields, TAG_MESSAGE};
+use crate::protocol::events::{ProtocolEvent, ProtocolEventLog};
+use bytes::Bytes;
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// Schema ID used in the metadata header of transport statistics control packets.
+pub const TRANSPORT_STATS_SCHEMA_ID: u64 = 0x5354_4154; // "STAT"
+
+/// Retransmit ratio above which a connection is reported as degraded.
+pub const DEGRADED_RETRANSMIT_RATIO: f64 = 0.05;
+/// Retransmit ratio above which a connection is reported as unhealthy.
+pub const UNHEALTHY_RETRANSMIT_RATIO: f64 = 0.20;
+
+// Tags used for the fields of an exported statistics object
+const TAG_CONNECTION_ID: u64 = 1;
+const TAG_PACKETS_SENT: u64 = 2;
+const TAG_PACKETS_RECEIVED: u64 = 3;
+const TAG_BYTES_SENT: u64 = 4;
+const TAG_BYTES_RECEIVED: u64 = 5;
+const TAG_RETRANSMITS: u64 = 6;
+const TAG_HANDSHAKE_MICROS: u64 = 7;
+const TAG_CIPHER: u64 = 8;
+const TAG_UNCOMPRESSED_BYTES: u64 = 9;
+const TAG_COMPRESSED_BYTES: u64 = 10;
+const TAG_HEALTH: u64 = 11;
+
+/// Describes statistics snapshots in errors
+const MESSAGE_NAME: &str = "transport stats";
+
+/// Coarse health status of a connection, derived from its statistics.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[repr(u8)]
+pub enum ConnectionHealth {
+    /// The connection is working normally.
+    Healthy = 0,
+    /// The connection works but shows elevated retransmits.
+    Degraded = 1,
+    /// The connection is losing a large share of its packets.
+    Unhealthy = 2,
+}
+
+impl ConnectionHealth {
+    /// Converts a u8 value to ConnectionHealth.
+    pub fn from_u8(value: u8) -> Result<Self> {
+        match value {
+            0 => Ok(ConnectionHealth::Healthy),
+            1 => Ok(ConnectionHealth::Degraded),
+            2 => Ok(ConnectionHealth::Unhealthy),
+            _ => Err(Error::ProtocolError(format!("Unknown ConnectionHealth value: {}", value))),
+        }
+    }
+}
+
+/// A snapshot of the statistics of a single connection.
+#[derive(Debug, PartialEq, Clone, Default)]
+pub struct TransportStats {
+    /// Number of packets sent
+    pub packets_sent: u64,
+    /// Number of packets received
+    pub packets_received: u64,
+    /// Number of bytes sent
+    pub bytes_sent: u64,
+    /// Number of bytes received
+    pub bytes_received: u64,
+    /// Number of retransmitted packets
+    pub retransmits: u64,
+    /// Time taken by the handshake (if completed)
+    pub handshake_time: Option<Duration>,
+    /// Name of the cipher in use (if any)
+    pub cipher: Option<String>,
+    /// Total size of payloads before compression
+    pub uncompressed_bytes: u64,
+    /// Total size of payloads after compression
+    pub compressed_bytes: u64,
+}
+
+impl TransportStats {
+    /// Creates an empty statistics snapshot.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the compression ratio (compressed / uncompressed), or `None`
+    /// if no compressed payloads have been recorded yet.
+    pub fn compression_ratio(&self) -> Option<f64> {
+        if self.uncompressed_bytes == 0 {
+            return None;
+        }
+        Some(self.compressed_bytes as f64 / self.uncompressed_bytes as f64)
+    }
+
+    /// Returns the ratio of retransmitted packets to sent packets.
+    pub fn retransmit_ratio(&self) -> f64 {
+        if self.packets_sent == 0 {
+            return 0.0;
+        }
+        self.retransmits as f64 / self.packets_sent as f64
+    }
+
+    /// Derives the health status of the connection from its retransmit ratio.
+    pub fn health(&self) -> ConnectionHealth {
+        let ratio = self.retransmit_ratio();
+        if ratio > UNHEALTHY_RETRANSMIT_RATIO {
+            ConnectionHealth::Unhealthy
+        } else if ratio > DEGRADED_RETRANSMIT_RATIO {
+            ConnectionHealth::Degraded
+        } else {
+            ConnectionHealth::Healthy
+        }
+    }
+
+    /// Converts the snapshot into an HTLV object item for the given connection.
+    pub fn to_htlv_item(&self, connection_id: u64) -> HtlvItem {
+        let handshake = match self.handshake_time {
+            Some(duration) => HtlvValue::U64(duration.as_micros() as u64),
+            None => HtlvValue::Null,
+        };
+        let cipher = match &self.cipher {
+            Some(name) => HtlvValue::String(Bytes::from(name.clone())),
+            None => HtlvValue::Null,
+        };
+
+        HtlvItem::new(TAG_MESSAGE, HtlvValue::Object(vec![
+            HtlvItem::new(TAG_CONNECTION_ID, HtlvValue::U64(connection_id)),
+            HtlvItem::new(TAG_PACKETS_SENT, HtlvValue::U64(self.packets_sent)),
+            HtlvItem::new(TAG_PACKETS_RECEIVED, HtlvValue::U64(self.packets_received)),
+            HtlvItem::new(TAG_BYTES_SENT, HtlvValue::U64(self.bytes_sent)),
+            HtlvItem::new(TAG_BYTES_RECEIVED, HtlvValue::U64(self.bytes_received)),
+            HtlvItem::new(TAG_RETRANSMITS, HtlvValue::U64(self.retransmits)),
+            HtlvItem::new(TAG_HANDSHAKE_MICROS, handshake),
+            HtlvItem::new(TAG_CIPHER, cipher),
+            HtlvItem::new(TAG_UNCOMPRESSED_BYTES, HtlvValue::U64(self.uncompressed_bytes)),
+            HtlvItem::new(TAG_COMPRESSED_BYTES, HtlvValue::U64(self.compressed_bytes)),
+            HtlvItem::new(TAG_HEALTH, HtlvValue::U8(self.health() as u8)),
+        ]))
+    }
+
+    /// Parses a snapshot from an HTLV object item produced by `to_htlv_item`.
+    /// Returns the connection ID and the statistics.
+    pub fn from_htlv_item(item: &HtlvItem) -> Result<(u64, Self)> {
+        let fields = Fields::of(item, MESSAGE_NAME)?;
+
+        let mut connection_id = None;
+        let mut stats = TransportStats::new();
+        for field in fields.all() {
+            match field.tag {
+                TAG_CONNECTION_ID => connection_id = Some(read_u64(field)?),
+                TAG_PACKETS_SENT => stats.packets_sent = read_u64(field)?,
+                TAG_PACKETS_RECEIVED => stats.packets_received = read_u64(field)?,
+                TAG_BYTES_SENT => stats.bytes_sent = read_u64(field)?,
+                TAG_BYTES_RECEIVED => stats.bytes_received = read_u64(field)?,
+                TAG_RETRANSMITS => stats.retransmits = read_u64(field)?,
+                TAG_HANDSHAKE_MICROS => {
+                    stats.handshake_time = match field.value {
+                        HtlvValue::Null => None,
+                        _ => Some(Duration::from_micros(read_u64(field)?)),
+                    };
+                }
+                TAG_CIPHER => {
+                    stats.cipher = match &field.value {
+                        HtlvValue::Null => None,
+                        HtlvValue::String(s) => Some(String::from_utf8(s.to_vec()).map_err(|e| {
+                            Error::ProtocolError(format!("Invalid UTF-8 in cipher name: {}", e))
+                        })?),
+                        other => return Err(Error::ProtocolError(format!(
+                            "Unexpected type for cipher field: {:?}", other.value_type()
+                        ))),
+                    };
+                }
+                TAG_UNCOMPRESSED_BYTES => stats.uncompressed_bytes = read_u64(field)?,
+                TAG_COMPRESSED_BYTES => stats.compressed_bytes = read_u64(field)?,
+                // Health is derived from the other fields, so it is only informational here
+                TAG_HEALTH => {}
+                // Ignore unknown fields for forward compatibility
+                _ => {}
+            }
+        }
+
+        let connection_id = connection_id
+            .ok_or_else(|| Error::ProtocolError("Transport stats missing connection ID".to_string()))?;
+        Ok((connection_id, stats))
+    }
+
+    /// Builds an HTLV control packet carrying this snapshot.
+    pub fn to_control_packet(&self, connection_id: u64, timestamp: u64) -> Result<Packet> {
+        control::to_control_packet(TRANSPORT_STATS_SCHEMA_ID, timestamp, &self.to_htlv_item(connection_id))
+    }
+
+    /// Parses a snapshot from a control packet built by `to_control_packet`.
+    /// Returns the connection ID and the statistics.
+    pub fn from_control_packet(packet: &Packet) -> Result<(u64, Self)> {
+        Self::from_htlv_item(&control::from_control_packet(packet, TRANSPORT_STATS_SCHEMA_ID, MESSAGE_NAME)?)
+    }
+}
+
+/// Tracks transport statistics for multiple connections, keyed by connection ID.
+#[derive(Debug, Default)]
+pub struct ConnectionStatsRegistry {
+    connections: HashMap<u64, TransportStats>,
+    log_events: bool,
+}
+
+impl ConnectionStatsRegistry {
+    /// Creates an empty registry.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Emits retransmits and completed handshakes as protocol events.
+    pub fn with_protocol_events(mut self) -> Self {
+        self.log_events = true;
+        self
+    }
+
+    /// Emits the event for the connection, if protocol events are enabled.
+    fn emit(&self, connection_id: u64, event: ProtocolEvent<'_>) {
+        if self.log_events {
+            ProtocolEventLog::new(connection_id).emit(&event);
+        }
+    }
+
+    /// Gets or creates the statistics entry for a connection.
+    fn entry(&mut self, connection_id: u64) -> &mut TransportStats {
+        self.connections.entry(connection_id).or_default()
+    }
+
+    /// Records a packet sent on the connection.
+    pub fn record_sent(&mut self, connection_id: u64, bytes: usize) {
+        let stats = self.entry(connection_id);
+        stats.packets_sent += 1;
+        stats.bytes_sent += bytes as u64;
+    }
+
+    /// Records a packet received on the connection.
+    pub fn record_received(&mut self, connection_id: u64, bytes: usize) {
+        let stats = self.entry(connection_id);
+        stats.packets_received += 1;
+        stats.bytes_received += bytes as u64;
+    }
+
+    /// Records a retransmitted packet on the connection.
+    pub fn record_retransmit(&mut self, connection_id: u64) {
+        let stats = self.entry(connection_id);
+        stats.retransmits += 1;
+        let retransmits = stats.retransmits;
+        self.emit(connection_id, ProtocolEvent::Retransmit { retransmits });
+    }
+
+    /// Records the time the handshake took on the connection.
+    pub fn record_handshake(&mut self, connection_id: u64, duration: Duration) {
+        self.entry(connection_id).handshake_time = Some(duration);
+        self.emit(connection_id, ProtocolEvent::HandshakeStep { step: "complete", elapsed: duration });
+    }
+
+    /// Sets the name of the cipher in use on the connection.
+    pub fn set_cipher(&mut self, connection_id: u64, cipher: &str) {
+        self.entry(connection_id).cipher = Some(cipher.to_string());
+    }
+
+    /// Records a payload compressed on the connection.
+    pub fn record_compression(&mut self, connection_id: u64, original_size: usize, compressed_size: usize) {
+        let stats = self.entry(connection_id);
+        stats.uncompressed_bytes += original_size as u64;
+        stats.compressed_bytes += compressed_size as u64;
+    }
+
+    /// Returns a snapshot of the statistics for a connection.
+    pub fn snapshot(&self, connection_id: u64) -> Option<TransportStats> {
+        self.connections.get(&connection_id).cloned()
+    }
+
+    /// Returns the health of a connection, or `None` if it is unknown.
+    pub fn health(&self, connection_id: u64) -> Option<ConnectionHealth> {
+        self.connections.get(&connection_id).map(|stats| stats.health())
+    }
+
+    /// Returns the IDs of all tracked connections.
+    pub fn connection_ids(&self) -> Vec<u64> {
+        self.connections.keys().copied().collect()
+    }
+
+    /// Builds a control packet for the given connection, or `None` if it is unknown.
+    pub fn export_control_packet(&self, connection_id: u64, timestamp: u64) -> Option<Result<Packet>> {
+        self.connections
+            .get(&connection_id)
+            .map(|stats| stats.to_control_packet(connection_id, timestamp))
+    }
+
+    /// Stops tracking a connection and returns its final statistics.
+    pub fn remove(&mut self, connection_id: u64) -> Option<TransportStats> {
+        self.connections.remove(&connection_id)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::packet::{DataBody, MetadataHeader};
+
+    #[test]
+    fn test_registry_records_counters() {
+        let mut registry = ConnectionStatsRegistry::new();
+        registry.record_sent(1, 100);
+        registry.record_sent(1, 50);
+        registry.record_received(1, 70);
+        registry.record_retransmit(1);
+        registry.record_handshake(1, Duration::from_millis(12));
+        registry.set_cipher(1, "AES-256-GCM");
+        registry.record_compression(1, 1000, 250);
+
+        let stats = registry.snapshot(1).unwrap();
+        assert_eq!(stats.packets_sent, 2);
+        assert_eq!(stats.bytes_sent, 150);
+        assert_eq!(stats.packets_received, 1);
+        assert_eq!(stats.bytes_received, 70);
+        assert_eq!(stats.retransmits, 1);
+        assert_eq!(stats.handshake_time, Some(Duration::from_millis(12)));
+        assert_eq!(stats.cipher.as_deref(), Some("AES-256-GCM"));
+        assert_eq!(stats.compression_ratio(), Some(0.25));
+
+        assert!(registry.snapshot(2).is_none());
+        assert!(registry.remove(1).is_some());
+        assert!(registry.connection_ids().is_empty());
+    }
+
+    #[test]
+    fn test_health_thresholds() {
+        let mut stats = TransportStats::new();
+        assert_eq!(stats.health(), ConnectionHealth::Healthy);
+
+        stats.packets_sent = 100;
+        stats.retransmits = 10;
+        assert_eq!(stats.health(), ConnectionHealth::Degraded);
+
+        stats.retransmits = 30;
+        assert_eq!(stats.health(), ConnectionHealth::Unhealthy);
+    }
+
+    #[test]
+    fn test_control_packet_roundtrip() {
+        let mut registry = ConnectionStatsRegistry::new();
+        registry.record_sent(7, 1200);
+        registry.record_received(7, 800);
+        registry.record_handshake(7, Duration::from_micros(1500));
+        registry.set_cipher(7, "ChaCha20-Poly1305");
+        registry.record_compression(7, 4096, 1024);
+
+        let packet = registry.export_control_packet(7, 1678886400).unwrap().unwrap();
+        assert_eq!(packet.header.schema_id, TRANSPORT_STATS_SCHEMA_ID);
+
+        let (connection_id, stats) = control::wire(Ok(packet), TransportStats::from_control_packet);
+        assert_eq!(connection_id, 7);
+        assert_eq!(stats, registry.snapshot(7).unwrap());
+    }
+
+    #[test]
+    fn test_from_control_packet_wrong_schema() {
+        let header = MetadataHeader {
+            schema_id: 1,
+            timestamp: 0,
+            shard_id: 0,
+            flow_flags: 0,
+            body_type: 0,
+            key_id: None,
+        };
+        let packet = Packet::build_packet(header, DataBody::Raw(vec![])).unwrap();
+        let result = TransportStats::from_control_packet(&packet);
+        assert!(result.is_err());
+        assert!(result.unwrap_err().to_string().contains("Not a transport stats packet"));
+    }
+}
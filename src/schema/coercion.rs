@@ -0,0 +1,181 @@
+// Type coercion rules for schema mapping
+//
+// Third-party JSON rarely matches the numeric types of a schema exactly: an
+// integer field may arrive as `1.0`, a float field as `1`, or a number as the
+// string "42". The rules below decide which of those conversions the mapper
+// performs:
+//
+// - Safe widening is always allowed: a conversion that preserves the value
+//   exactly, e.g. `1.0` into an integer or `1` into a float.
+// - Lossy conversions (fractions into integers, integers too large for the
+//   mantissa of a float) are only allowed when `allow_lossy` is set.
+// - Strings are parsed as numbers only when `parse_strings` is set.
+//
+// Values out of range for the target type are always rejected.
+
+use serde_json::Value;
+
+use crate::internal::error::{Error, Result};
+
+/// Largest integer magnitude an f64 represents exactly
+const F64_EXACT_INTEGER: u64 = 1 << 53;
+
+/// Largest integer magnitude an f32 represents exactly
+const F32_EXACT_INTEGER: u64 = 1 << 24;
+
+/// Coercion rules applied when mapping JSON values to numeric schema types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CoercionRules {
+    /// Allow conversions that lose information, e.g. `1.5` into an integer
+    /// (truncated towards zero)
+    pub allow_lossy: bool,
+    /// Allow numeric strings such as `"42"` in numeric fields
+    pub parse_strings: bool,
+}
+
+/// A JSON number in its most precise representation
+#[derive(Debug, Clone, Copy)]
+enum JsonNumber {
+    Unsigned(u64),
+    Signed(i64),
+    Float(f64),
+}
+
+impl CoercionRules {
+    /// Only safe widening, the default
+    pub fn strict() -> Self {
+        Self::default()
+    }
+
+    /// All coercions: lossy conversions and numeric strings
+    pub fn lenient() -> Self {
+        Self {
+            allow_lossy: true,
+            parse_strings: true,
+        }
+    }
+
+    /// Converts a JSON value to an unsigned integer no greater than `max`
+    pub fn coerce_unsigned(&self, json: &Value, max: u64, type_name: &str) -> Result<u64> {
+        let value = match self.number(json, type_name)? {
+            JsonNumber::Unsigned(u) => u,
+            JsonNumber::Signed(i) => {
+                return Err(Error::SchemaError(format!("Value {} is out of range for {}", i, type_name)));
+            }
+            JsonNumber::Float(f) => {
+                let f = self.integral(f, type_name)?;
+                if f < 0.0 || f > max as f64 {
+                    return Err(Error::SchemaError(format!("Value {} is out of range for {}", f, type_name)));
+                }
+                f as u64
+            }
+        };
+
+        if value > max {
+            return Err(Error::SchemaError(format!("Value {} is too large for {}", value, type_name)));
+        }
+        Ok(value)
+    }
+
+    /// Converts a JSON value to a signed integer within `min..=max`
+    pub fn coerce_signed(&self, json: &Value, min: i64, max: i64, type_name: &str) -> Result<i64> {
+        let value = match self.number(json, type_name)? {
+            JsonNumber::Unsigned(u) => i64::try_from(u)
+                .map_err(|_| Error::SchemaError(format!("Value {} is out of range for {}", u, type_name)))?,
+            JsonNumber::Signed(i) => i,
+            JsonNumber::Float(f) => {
+                let f = self.integral(f, type_name)?;
+                if f < min as f64 || f > max as f64 {
+                    return Err(Error::SchemaError(format!("Value {} is out of range for {}", f, type_name)));
+                }
+                f as i64
+            }
+        };
+
+        if value < min || value > max {
+            return Err(Error::SchemaError(format!("Value {} is out of range for {}", value, type_name)));
+        }
+        Ok(value)
+    }
+
+    /// Converts a JSON value to a 64-bit float
+    pub fn coerce_f64(&self, json: &Value, type_name: &str) -> Result<f64> {
+        self.coerce_float(json, F64_EXACT_INTEGER, type_name)
+    }
+
+    /// Converts a JSON value to a 32-bit float
+    ///
+    /// Rounding a fractional value to the nearest f32 is not considered lossy,
+    /// as float fields are approximate by nature.
+    pub fn coerce_f32(&self, json: &Value, type_name: &str) -> Result<f32> {
+        let f = self.coerce_float(json, F32_EXACT_INTEGER, type_name)?;
+        if f.abs() > f32::MAX as f64 {
+            return Err(Error::SchemaError(format!("Value {} is out of range for {}", f, type_name)));
+        }
+        Ok(f as f32)
+    }
+
+    fn coerce_float(&self, json: &Value, exact_limit: u64, type_name: &str) -> Result<f64> {
+        let (value, magnitude) = match self.number(json, type_name)? {
+            JsonNumber::Float(f) => return Ok(f),
+            JsonNumber::Unsigned(u) => (u as f64, u),
+            JsonNumber::Signed(i) => (i as f64, i.unsigned_abs()),
+        };
+
+        if magnitude > exact_limit && !self.allow_lossy {
+            return Err(Error::SchemaError(format!(
+                "Value {} cannot be represented exactly as {}", json, type_name
+            )));
+        }
+        Ok(value)
+    }
+
+    /// Returns the integral value of a float, truncating it if lossy
+    /// conversions are allowed
+    fn integral(&self, f: f64, type_name: &str) -> Result<f64> {
+        if !f.is_finite() {
+            return Err(Error::SchemaError(format!("Cannot convert {} to {}", f, type_name)));
+        }
+        if f.fract() == 0.0 {
+            Ok(f)
+        } else if self.allow_lossy {
+            Ok(f.trunc())
+        } else {
+            Err(Error::SchemaError(format!(
+                "Value {} is not an integer; lossy coercion to {} is disabled", f, type_name
+            )))
+        }
+    }
+
+    fn number(&self, json: &Value, type_name: &str) -> Result<JsonNumber> {
+        match json {
+            Value::Number(n) => {
+                if let Some(u) = n.as_u64() {
+                    Ok(JsonNumber::Unsigned(u))
+                } else if let Some(i) = n.as_i64() {
+                    Ok(JsonNumber::Signed(i))
+                } else if let Some(f) = n.as_f64() {
+                    Ok(JsonNumber::Float(f))
+                } else {
+                    Err(Error::SchemaError(format!("Cannot convert {} to {}", n, type_name)))
+                }
+            }
+            Value::String(s) if self.parse_strings => {
+                let s = s.trim();
+                if let Ok(u) = s.parse::<u64>() {
+                    Ok(JsonNumber::Unsigned(u))
+                } else if let Ok(i) = s.parse::<i64>() {
+                    Ok(JsonNumber::Signed(i))
+                } else {
+                    match s.parse::<f64>() {
+                        Ok(f) if f.is_finite() => Ok(JsonNumber::Float(f)),
+                        _ => Err(Error::SchemaError(format!("Cannot parse \"{}\" as {}", s, type_name))),
+                    }
+                }
+            }
+            other => Err(Error::SchemaError(format!(
+                "Type mismatch: expected {}, got {:?}", type_name, other
+            ))),
+        }
+    }
+}
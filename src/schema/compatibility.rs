@@ -0,0 +1,261 @@
+// Compatibility checks between versions of a Tonitru schema
+//
+// `check_compatibility(old, new)` takes the field changes `diff` finds between
+// two versions and classifies every one by the version bump it calls for under
+// `SchemaVersion` semantics:
+//
+//   Major  readers of one version cannot read data of the other: a type
+//          changed, a required field was removed or added without a default,
+//          an optional field became required, or a tag was reused for an
+//          unrelated field
+//   Minor  backwards-compatible additions: optional fields added or removed,
+//          numeric types widened, required fields made optional, renames
+//   Patch  constraints and options that do not change the wire layout
+//
+// The report then tells whether the version numbers of the two schemas
+// actually carry the bump the changes need.
+
+use std::fmt;
+
+use crate::schema::diff::{matched_changes, ChangeKind, FieldChange, FieldDelta, MatchedChange};
+use crate::schema::types::{Schema, SchemaType, SchemaVersion};
+
+/// The version bump a change calls for, from the least to the most disruptive
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ChangeLevel {
+    /// No change to what readers accept
+    Patch,
+    /// Backwards-compatible change
+    Minor,
+    /// Breaking change
+    Major,
+}
+
+impl fmt::Display for ChangeLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ChangeLevel::Patch => "patch",
+            ChangeLevel::Minor => "minor",
+            ChangeLevel::Major => "major",
+        })
+    }
+}
+
+/// What changed about a field
+#[derive(Debug, Clone, PartialEq)]
+pub enum CompatibilityChange {
+    /// The field only exists in the new version
+    FieldAdded { required: bool, has_default: bool },
+    /// The field only exists in the old version
+    FieldRemoved { required: bool },
+    /// The field was renamed; its tag and type are unchanged
+    Renamed { old: String, new: String },
+    /// The tag now belongs to a field with another name and type
+    TagReused { old: String, new: String },
+    /// The type of the field changed
+    TypeChanged { old: String, new: String, widening: bool },
+    /// The field became required or optional
+    RequiredChanged { required: bool },
+    /// A constraint or option of the field changed
+    ConstraintChanged,
+}
+
+/// A classified change to one field
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompatibilityIssue {
+    /// Dotted path of the field names from the root object, or from the name
+    /// of a type definition
+    pub path: String,
+    /// Field tag
+    pub tag: u64,
+    /// What changed
+    pub change: CompatibilityChange,
+    /// The version bump the change calls for
+    pub level: ChangeLevel,
+}
+
+impl fmt::Display for CompatibilityIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {} (tag {}): ", self.level, self.path, self.tag)?;
+        match &self.change {
+            CompatibilityChange::FieldAdded { required: true, has_default: false } => {
+                write!(f, "required field added without a default")
+            }
+            CompatibilityChange::FieldAdded { .. } => write!(f, "field added"),
+            CompatibilityChange::FieldRemoved { required: true } => write!(f, "required field removed"),
+            CompatibilityChange::FieldRemoved { required: false } => write!(f, "optional field removed"),
+            CompatibilityChange::Renamed { old, new } => write!(f, "renamed from {} to {}", old, new),
+            CompatibilityChange::TagReused { old, new } => write!(f, "tag of {} reused by {}", old, new),
+            CompatibilityChange::TypeChanged { old, new, widening: true } => write!(f, "type widened from {} to {}", old, new),
+            CompatibilityChange::TypeChanged { old, new, .. } => write!(f, "type changed from {} to {}", old, new),
+            CompatibilityChange::RequiredChanged { required: true } => write!(f, "optional field became required"),
+            CompatibilityChange::RequiredChanged { required: false } => write!(f, "required field became optional"),
+            CompatibilityChange::ConstraintChanged => write!(f, "constraints or options changed"),
+        }
+    }
+}
+
+/// The classified changes between two versions of a schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompatibilityReport {
+    /// ID of the new schema
+    pub schema_id: String,
+    /// Version of the old schema
+    pub old_version: SchemaVersion,
+    /// Version of the new schema
+    pub new_version: SchemaVersion,
+    /// Changes, in schema order
+    pub issues: Vec<CompatibilityIssue>,
+}
+
+impl CompatibilityReport {
+    /// Returns true if no change breaks readers of either version
+    pub fn is_compatible(&self) -> bool {
+        self.required_level().is_none_or(|level| level < ChangeLevel::Major)
+    }
+
+    /// Returns the breaking changes
+    pub fn breaking(&self) -> impl Iterator<Item = &CompatibilityIssue> {
+        self.issues.iter().filter(|issue| issue.level == ChangeLevel::Major)
+    }
+
+    /// Returns the bump the changes call for, or `None` if the versions are
+    /// structurally identical
+    pub fn required_level(&self) -> Option<ChangeLevel> {
+        self.issues.iter().map(|issue| issue.level).max()
+    }
+
+    /// Returns the bump from the old to the new version number, or `None` if
+    /// the new version is not greater
+    pub fn actual_level(&self) -> Option<ChangeLevel> {
+        let (old, new) = (&self.old_version, &self.new_version);
+        if new.major != old.major {
+            (new.major > old.major).then_some(ChangeLevel::Major)
+        } else if new.minor != old.minor {
+            (new.minor > old.minor).then_some(ChangeLevel::Minor)
+        } else {
+            (new.patch > old.patch).then_some(ChangeLevel::Patch)
+        }
+    }
+
+    /// Returns true if the new version number is bumped at least as much as
+    /// the changes call for
+    pub fn is_version_bump_sufficient(&self) -> bool {
+        match self.required_level() {
+            None => true,
+            Some(required) => self.actual_level().is_some_and(|actual| actual >= required),
+        }
+    }
+}
+
+impl fmt::Display for CompatibilityReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Schema '{}': {} -> {}", self.schema_id, self.old_version, self.new_version)?;
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        match self.required_level() {
+            None => writeln!(f, "no structural changes"),
+            Some(level) if self.is_version_bump_sufficient() => writeln!(f, "requires a {} version bump", level),
+            Some(level) => writeln!(f, "requires a {} version bump, which {} does not carry", level, self.new_version),
+        }
+    }
+}
+
+/// Classifies the changes from an old to a new version of a schema
+pub fn check_compatibility(old: &Schema, new: &Schema) -> CompatibilityReport {
+    let mut issues = Vec::new();
+    // Paths of tags reused by unrelated fields, whose nested changes mean nothing
+    let mut reused: Vec<String> = Vec::new();
+    for MatchedChange { change, old: old_field, new: new_field } in matched_changes(old, new) {
+        if reused.iter().any(|path| change.path.strip_prefix(path.as_str()).is_some_and(|rest| rest.starts_with('.'))) {
+            continue;
+        }
+        match (&change.kind, old_field, new_field) {
+            (ChangeKind::Removed, Some(old_field), _) => {
+                let level = if old_field.required { ChangeLevel::Major } else { ChangeLevel::Minor };
+                issues.push(issue(&change, CompatibilityChange::FieldRemoved { required: old_field.required }, level));
+            }
+            (ChangeKind::Added, _, Some(new_field)) => {
+                let has_default = new_field.default_value.is_some();
+                let level = if new_field.required && !has_default { ChangeLevel::Major } else { ChangeLevel::Minor };
+                issues.push(issue(&change, CompatibilityChange::FieldAdded { required: new_field.required, has_default }, level));
+            }
+            (ChangeKind::Modified(deltas), Some(old_field), Some(new_field)) => {
+                let renamed = deltas.iter().any(|delta| matches!(delta, FieldDelta::Renamed { .. }));
+                let retyped = deltas.iter().any(|delta| matches!(delta, FieldDelta::TypeChanged { .. }));
+                if renamed && retyped {
+                    // A different name and type under the same tag is another field
+                    let reuse = CompatibilityChange::TagReused { old: old_field.name.clone(), new: new_field.name.clone() };
+                    issues.push(issue(&change, reuse, ChangeLevel::Major));
+                    reused.push(change.path.clone());
+                    continue;
+                }
+
+                let mut constrained = false;
+                for delta in deltas {
+                    match delta {
+                        FieldDelta::Renamed { old, new } => {
+                            let rename = CompatibilityChange::Renamed { old: old.clone(), new: new.clone() };
+                            issues.push(issue(&change, rename, ChangeLevel::Minor));
+                        }
+                        FieldDelta::TypeChanged { old, new } => {
+                            let widening = is_widening(&old_field.field_type, &new_field.field_type);
+                            let retype = CompatibilityChange::TypeChanged { old: old.clone(), new: new.clone(), widening };
+                            issues.push(issue(&change, retype, if widening { ChangeLevel::Minor } else { ChangeLevel::Major }));
+                        }
+                        FieldDelta::RequiredChanged { new: required, .. } => {
+                            let level = if *required { ChangeLevel::Major } else { ChangeLevel::Minor };
+                            issues.push(issue(&change, CompatibilityChange::RequiredChanged { required: *required }, level));
+                        }
+                        FieldDelta::ConstraintChanged { .. } => constrained = true,
+                    }
+                }
+                if constrained {
+                    issues.push(issue(&change, CompatibilityChange::ConstraintChanged, ChangeLevel::Patch));
+                }
+            }
+            _ => {}
+        }
+    }
+
+    CompatibilityReport {
+        schema_id: new.id.clone(),
+        old_version: old.version.clone(),
+        new_version: new.version.clone(),
+        issues,
+    }
+}
+
+fn issue(change: &FieldChange, compatibility_change: CompatibilityChange, level: ChangeLevel) -> CompatibilityIssue {
+    CompatibilityIssue {
+        path: change.path.clone(),
+        tag: change.tag,
+        change: compatibility_change,
+        level,
+    }
+}
+
+/// Returns true if every value of the old type is a value of the new type
+fn is_widening(old: &SchemaType, new: &SchemaType) -> bool {
+    use SchemaType::*;
+    let rank = |schema_type: &SchemaType| match schema_type {
+        UInt8 | Int8 => Some(1),
+        UInt16 | Int16 => Some(2),
+        UInt32 | Int32 | Float32 => Some(3),
+        UInt64 | Int64 | Float64 => Some(4),
+        _ => None,
+    };
+    let (Some(old_rank), Some(new_rank)) = (rank(old), rank(new)) else {
+        return false;
+    };
+    match (old, new) {
+        (UInt8 | UInt16 | UInt32 | UInt64, UInt8 | UInt16 | UInt32 | UInt64) => new_rank > old_rank,
+        (Int8 | Int16 | Int32 | Int64, Int8 | Int16 | Int32 | Int64) => new_rank > old_rank,
+        (UInt8 | UInt16 | UInt32, Int16 | Int32 | Int64) => new_rank > old_rank,
+        (Float32, Float64) => true,
+        // Floats represent integers exactly up to their mantissa width
+        (UInt8 | UInt16 | Int8 | Int16, Float32 | Float64) | (UInt32 | Int32, Float64) => true,
+        _ => false,
+    }
+}
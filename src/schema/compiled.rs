@@ -0,0 +1,551 @@
+// Precompiled validation plans for Tonitru schemas
+//
+// `SchemaValidator` walks the schema types on every call: it builds a tag
+// lookup table for each object it validates and recompiles pattern
+// constraints for each value. A `CompiledSchema` does that work once: object
+// types become tag lookup tables, field constraints become closures with
+// their regexes compiled, and lifecycle warnings are resolved against the
+// schema version. A compiled schema accepts and rejects the same values as
+// `SchemaValidator` with the same configuration.
+//
+// Plans are stored in an arena and refer to each other by index. This lets a
+// compiled schema act as a `HeaderCheck` for the decoder, which keeps the plan
+// index of each complex item on its stack and rejects mismatching item headers
+// before their values are decoded.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::internal::error::{Error, Result};
+use crate::codec::decode::header_check::{HeaderCheck, UNCHECKED};
+use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
+use crate::schema::types::{Schema, SchemaType, SchemaField};
+use crate::schema::validator::{
+    ValidatorConfig, ValidationWarning, ValidationWarningKind,
+    check_length, check_min_value, check_pattern, compile_pattern, lifecycle_warning,
+};
+
+/// A constraint check compiled for one field
+type Constraint = Box<dyn Fn(&HtlvValue) -> Result<()> + Send + Sync>;
+
+/// Validation plan of a type; other plans are referred to by arena index
+enum Plan {
+    /// A scalar type, checked by comparing value types
+    Scalar(SchemaType),
+    Array(usize),
+    Object(ObjectPlan),
+    /// A map, whose items are map entries
+    Map(usize),
+    /// An entry of a map: key and value plans
+    MapEntry(usize, usize),
+    Union(Vec<usize>),
+    /// Index of a named type in `CompiledSchema::definitions`
+    Ref(usize),
+}
+
+/// Validation plan of an object type
+struct ObjectPlan {
+    fields: Vec<FieldPlan>,
+    /// Field tag to index in `fields`
+    by_tag: HashMap<u64, usize>,
+    /// Indices of the fields that are required in the schema version
+    required: Vec<usize>,
+}
+
+/// Validation plan of an object field
+struct FieldPlan {
+    name: String,
+    tag: u64,
+    plan: usize,
+    constraints: Vec<Constraint>,
+    /// Lifecycle warning reported whenever the field is present
+    warning: Option<ValidationWarningKind>,
+}
+
+/// State threaded through a validation pass
+struct PassState {
+    /// Named types being expanded, with the depth they were expanded at
+    expanding: Vec<(usize, usize)>,
+    /// Warnings collected so far
+    warnings: Vec<ValidationWarning>,
+}
+
+/// A schema prepared for repeated validation
+pub struct CompiledSchema {
+    schema: Arc<Schema>,
+    config: ValidatorConfig,
+    /// Arena of all plans
+    plans: Vec<Plan>,
+    root: usize,
+    /// Plans of the named types, in the order of `names`
+    definitions: Vec<usize>,
+    names: Vec<String>,
+}
+
+impl fmt::Debug for CompiledSchema {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CompiledSchema")
+            .field("schema_id", &self.schema.id)
+            .field("version", &self.schema.version)
+            .field("config", &self.config)
+            .finish()
+    }
+}
+
+impl CompiledSchema {
+    /// Compiles a schema with the default validator configuration
+    pub fn compile(schema: Arc<Schema>) -> Result<Self> {
+        Self::compile_with_config(schema, ValidatorConfig::default())
+    }
+
+    /// Compiles a schema with a custom validator configuration
+    ///
+    /// Fails if a definition is missing or a pattern constraint is not a
+    /// valid regular expression.
+    pub fn compile_with_config(schema: Arc<Schema>, config: ValidatorConfig) -> Result<Self> {
+        schema.check_definitions()?;
+
+        let mut names: Vec<String> = schema.definitions.keys().cloned().collect();
+        names.sort();
+        let indices: HashMap<&str, usize> = names.iter().enumerate().map(|(i, name)| (name.as_str(), i)).collect();
+
+        let mut compiler = Compiler {
+            schema: &schema,
+            config: &config,
+            indices: &indices,
+            plans: Vec::new(),
+        };
+        let root = compiler.compile_type(&schema.root_type)?;
+        let mut definitions = Vec::with_capacity(names.len());
+        for name in &names {
+            definitions.push(compiler.compile_type(&schema.definitions[name])?);
+        }
+        let plans = compiler.plans;
+
+        Ok(Self {
+            schema,
+            config,
+            plans,
+            root,
+            definitions,
+            names,
+        })
+    }
+
+    /// Returns the compiled schema
+    pub fn schema(&self) -> &Arc<Schema> {
+        &self.schema
+    }
+
+    /// Validates an HTLV item against the schema
+    pub fn validate(&self, item: &HtlvItem) -> Result<()> {
+        self.validate_with_warnings(item).map(|_| ())
+    }
+
+    /// Validates an HTLV item against the schema, returning the non-fatal
+    /// findings such as deprecated or removed fields that are still being sent
+    pub fn validate_with_warnings(&self, item: &HtlvItem) -> Result<Vec<ValidationWarning>> {
+        let mut state = PassState {
+            expanding: Vec::new(),
+            warnings: Vec::new(),
+        };
+        self.check_value(self.root, &item.value, 0, &mut state)?;
+        Ok(state.warnings)
+    }
+
+    /// Validates a batch of HTLV items against the schema, returning one result
+    /// per item in order. The scratch state of a pass is reused across items.
+    pub fn validate_batch(&self, items: &[HtlvItem]) -> Vec<Result<()>> {
+        let mut state = PassState {
+            expanding: Vec::new(),
+            warnings: Vec::new(),
+        };
+        items
+            .iter()
+            .map(|item| {
+                state.expanding.clear();
+                state.warnings.clear();
+                self.check_value(self.root, &item.value, 0, &mut state)
+            })
+            .collect()
+    }
+
+    /// Validates a batch like `validate_batch`, splitting the items over up to
+    /// `threads` scoped threads
+    pub fn validate_batch_parallel(&self, items: &[HtlvItem], threads: usize) -> Vec<Result<()>> {
+        let chunk_size = items.len().div_ceil(threads.max(1)).max(1);
+        if chunk_size >= items.len() {
+            return self.validate_batch(items);
+        }
+        std::thread::scope(|scope| {
+            let workers: Vec<_> = items
+                .chunks(chunk_size)
+                .map(|chunk| scope.spawn(move || self.validate_batch(chunk)))
+                .collect();
+            workers
+                .into_iter()
+                .flat_map(|worker| worker.join().expect("Batch validation thread panicked"))
+                .collect()
+        })
+    }
+
+    fn check_value(&self, plan: usize, value: &HtlvValue, depth: usize, state: &mut PassState) -> Result<()> {
+        if depth > self.config.max_nesting_depth {
+            return Err(Error::SchemaError(format!(
+                "Maximum nesting depth ({}) exceeded",
+                self.config.max_nesting_depth
+            )));
+        }
+
+        match (&self.plans[plan], value) {
+            (Plan::Scalar(schema_type), value) => {
+                if scalar_matches(schema_type, value) {
+                    Ok(())
+                } else {
+                    Err(Error::SchemaError(format!(
+                        "Type mismatch: expected {:?}, got {:?}", schema_type, value
+                    )))
+                }
+            }
+            (Plan::Array(element_plan), HtlvValue::Array(items)) => {
+                for item in items {
+                    self.check_value(*element_plan, &item.value, depth + 1, state)?;
+                }
+                Ok(())
+            }
+            (Plan::Object(object_plan), HtlvValue::Object(items)) => {
+                self.check_object(object_plan, items, depth, state)
+            }
+            (Plan::Map(entry_plan), HtlvValue::Object(items)) => {
+                for item in items {
+                    // Entries are validated at the depth of the map itself
+                    self.check_value(*entry_plan, &item.value, depth, state)?;
+                }
+                Ok(())
+            }
+            (Plan::MapEntry(key_plan, value_plan), HtlvValue::Object(entry)) => {
+                if entry.len() != 2 {
+                    return Err(Error::SchemaError(
+                        "Map entry must have exactly 2 fields (key and value)".to_string()
+                    ));
+                }
+                match entry.iter().find(|i| i.tag == 0) {
+                    Some(key_item) => self.check_value(*key_plan, &key_item.value, depth + 1, state)?,
+                    None => return Err(Error::SchemaError("Map entry missing key field (tag 0)".to_string())),
+                }
+                match entry.iter().find(|i| i.tag == 1) {
+                    Some(val_item) => self.check_value(*value_plan, &val_item.value, depth + 1, state)?,
+                    None => return Err(Error::SchemaError("Map entry missing value field (tag 1)".to_string())),
+                }
+                Ok(())
+            }
+            (Plan::MapEntry(_, _), _) => Err(Error::SchemaError(
+                "Map entry must be an object with key and value fields".to_string()
+            )),
+            (Plan::Union(variants), value) => {
+                let warning_count = state.warnings.len();
+                for &variant in variants {
+                    if self.check_value(variant, value, depth, state).is_ok() {
+                        return Ok(());
+                    }
+                    state.warnings.truncate(warning_count);
+                }
+                Err(Error::SchemaError(format!(
+                    "Value does not match any type in union: {:?}", value
+                )))
+            }
+            (Plan::Ref(index), value) => {
+                if state.expanding.contains(&(*index, depth)) {
+                    return Err(Error::SchemaError(format!(
+                        "Type '{}' refers to itself without nesting", self.names[*index]
+                    )));
+                }
+                state.expanding.push((*index, depth));
+                let result = self.check_value(self.definitions[*index], value, depth, state);
+                state.expanding.pop();
+                result
+            }
+            (plan, actual) => Err(Error::SchemaError(format!(
+                "Type mismatch: expected {}, got {:?}", plan.kind(), actual
+            ))),
+        }
+    }
+
+    fn check_object(&self, plan: &ObjectPlan, items: &[HtlvItem], depth: usize, state: &mut PassState) -> Result<()> {
+        let mut seen = SeenFields::new(plan.fields.len());
+
+        for item in items {
+            match plan.by_tag.get(&item.tag) {
+                Some(&index) => {
+                    let field = &plan.fields[index];
+                    self.check_value(field.plan, &item.value, depth + 1, state)?;
+                    if let Some(kind) = &field.warning {
+                        state.warnings.push(ValidationWarning {
+                            field: field.name.clone(),
+                            tag: field.tag,
+                            kind: kind.clone(),
+                        });
+                    }
+                    for constraint in &field.constraints {
+                        constraint(&item.value)?;
+                    }
+                    seen.insert(index);
+                }
+                None if !self.config.allow_unknown_fields => {
+                    return Err(Error::SchemaError(format!(
+                        "Unknown field with tag {} in object", item.tag
+                    )));
+                }
+                None => {}
+            }
+        }
+
+        for &index in &plan.required {
+            if !seen.contains(index) {
+                let field = &plan.fields[index];
+                return Err(Error::SchemaError(format!(
+                    "Required field '{}' (tag {}) is missing", field.name, field.tag
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the plan of the child items of a complex plan, or `UNCHECKED`
+    /// for unknown fields that are allowed
+    fn child_plan(&self, parent: usize, tag: u64) -> Result<usize> {
+        match &self.plans[parent] {
+            Plan::Array(element_plan) => Ok(*element_plan),
+            Plan::Object(object_plan) => match object_plan.by_tag.get(&tag) {
+                Some(&index) => Ok(object_plan.fields[index].plan),
+                None if self.config.allow_unknown_fields => Ok(UNCHECKED),
+                None => Err(Error::SchemaError(format!("Unknown field with tag {} in object", tag))),
+            },
+            Plan::Map(entry_plan) => Ok(*entry_plan),
+            Plan::MapEntry(key_plan, _) if tag == 0 => Ok(*key_plan),
+            Plan::MapEntry(_, value_plan) if tag == 1 => Ok(*value_plan),
+            Plan::MapEntry(_, _) => Err(Error::SchemaError(format!(
+                "Unexpected field with tag {} in map entry (expected key tag 0 or value tag 1)", tag
+            ))),
+            // Headers only return plans of complex types
+            _ => Ok(UNCHECKED),
+        }
+    }
+
+    /// Resolves references and unions for an item header; returns the plan
+    /// matching the value type, or `UNCHECKED` if several union variants match
+    fn resolve_header(&self, plan: usize, value_type: HtlvValueType) -> Result<usize> {
+        match &self.plans[plan] {
+            Plan::Ref(index) => self.resolve_header(self.definitions[*index], value_type),
+            Plan::Union(variants) => {
+                let mut matching = variants
+                    .iter()
+                    .filter_map(|&variant| self.resolve_header(variant, value_type).ok());
+                match (matching.next(), matching.next()) {
+                    (Some(variant), None) => Ok(variant),
+                    (Some(_), Some(_)) => Ok(UNCHECKED),
+                    (None, _) => Err(Error::SchemaError(format!(
+                        "Value of type {:?} does not match any type in union", value_type
+                    ))),
+                }
+            }
+            Plan::Scalar(schema_type) => {
+                if scalar_header_matches(schema_type, value_type) {
+                    Ok(plan)
+                } else {
+                    Err(Error::SchemaError(format!(
+                        "Type mismatch: expected {:?}, got {:?}", schema_type, value_type
+                    )))
+                }
+            }
+            Plan::Array(_) if value_type == HtlvValueType::Array => Ok(plan),
+            Plan::Object(_) | Plan::Map(_) if value_type == HtlvValueType::Object => Ok(plan),
+            Plan::MapEntry(_, _) if value_type == HtlvValueType::Object => Ok(plan),
+            Plan::MapEntry(_, _) => Err(Error::SchemaError(
+                "Map entry must be an object with key and value fields".to_string()
+            )),
+            other => Err(Error::SchemaError(format!(
+                "Type mismatch: expected {}, got {:?}", other.kind(), value_type
+            ))),
+        }
+    }
+}
+
+impl HeaderCheck for CompiledSchema {
+    fn check_header(&self, parent: Option<usize>, tag: u64, value_type: HtlvValueType) -> Result<usize> {
+        let plan = match parent {
+            None => self.root,
+            Some(UNCHECKED) => return Ok(UNCHECKED),
+            Some(parent) => self.child_plan(parent, tag)?,
+        };
+        if plan == UNCHECKED {
+            return Ok(UNCHECKED);
+        }
+        self.resolve_header(plan, value_type)
+    }
+}
+
+impl Plan {
+    /// Describes the expected value in type mismatch errors
+    fn kind(&self) -> &'static str {
+        match self {
+            Plan::Scalar(_) => "scalar",
+            Plan::Array(_) => "Array",
+            Plan::Object(_) => "Object",
+            Plan::Map(_) => "Map",
+            Plan::MapEntry(_, _) => "MapEntry",
+            Plan::Union(_) => "Union",
+            Plan::Ref(_) => "Ref",
+        }
+    }
+}
+
+/// Builds the plans of one schema
+struct Compiler<'a> {
+    schema: &'a Schema,
+    config: &'a ValidatorConfig,
+    indices: &'a HashMap<&'a str, usize>,
+    plans: Vec<Plan>,
+}
+
+impl Compiler<'_> {
+    /// Compiles a type into the arena and returns the index of its plan
+    fn compile_type(&mut self, schema_type: &SchemaType) -> Result<usize> {
+        let plan = match schema_type {
+            SchemaType::Array(element_type) => Plan::Array(self.compile_type(element_type)?),
+            SchemaType::Object(fields) => Plan::Object(self.compile_object(fields)?),
+            SchemaType::Map(key_type, value_type) => {
+                let entry = Plan::MapEntry(self.compile_type(key_type)?, self.compile_type(value_type)?);
+                Plan::Map(self.push(entry))
+            }
+            SchemaType::Union(variants) => {
+                Plan::Union(variants.iter().map(|variant| self.compile_type(variant)).collect::<Result<_>>()?)
+            }
+            SchemaType::Ref(name) => match self.indices.get(name.as_str()) {
+                Some(&index) => Plan::Ref(index),
+                None => return Err(Error::SchemaError(format!("Unknown type reference '{}'", name))),
+            },
+            scalar => Plan::Scalar(scalar.clone()),
+        };
+        Ok(self.push(plan))
+    }
+
+    fn push(&mut self, plan: Plan) -> usize {
+        self.plans.push(plan);
+        self.plans.len() - 1
+    }
+
+    fn compile_object(&mut self, fields: &[SchemaField]) -> Result<ObjectPlan> {
+        let version = Some(&self.schema.version);
+        let mut plans = Vec::with_capacity(fields.len());
+        let mut by_tag = HashMap::with_capacity(fields.len());
+        let mut required = Vec::new();
+
+        for field in fields {
+            // Like a HashMap built from the fields, the last field with a tag wins
+            let index = plans.len();
+            by_tag.insert(field.tag, index);
+            if self.config.validate_required && field.required && field.is_active_in(&self.schema.version) {
+                required.push(index);
+            }
+            plans.push(FieldPlan {
+                name: field.name.clone(),
+                tag: field.tag,
+                plan: self.compile_type(&field.field_type)?,
+                constraints: self.compile_constraints(field)?,
+                warning: lifecycle_warning(field, version),
+            });
+        }
+
+        Ok(ObjectPlan {
+            fields: plans,
+            by_tag,
+            required,
+        })
+    }
+
+    fn compile_constraints(&self, field: &SchemaField) -> Result<Vec<Constraint>> {
+        let mut constraints: Vec<Constraint> = Vec::new();
+        if !self.config.validate_constraints {
+            return Ok(constraints);
+        }
+        let options = &field.options;
+
+        if let Some(min_value) = options.min_value.clone() {
+            let name = field.name.clone();
+            constraints.push(Box::new(move |value| check_min_value(&name, &min_value, value)));
+        }
+
+        if options.min_length.is_some() || options.max_length.is_some() {
+            let name = field.name.clone();
+            let (min_length, max_length) = (options.min_length, options.max_length);
+            constraints.push(Box::new(move |value| check_length(&name, min_length, max_length, value)));
+        }
+
+        if let Some(pattern) = &options.pattern {
+            let name = field.name.clone();
+            let regex = compile_pattern(&name, pattern)?;
+            constraints.push(Box::new(move |value| check_pattern(&name, &regex, value)));
+        }
+
+        Ok(constraints)
+    }
+}
+
+/// Returns true if an item header has the HTLV type of a scalar schema type
+fn scalar_header_matches(schema_type: &SchemaType, value_type: HtlvValueType) -> bool {
+    matches!(
+        (schema_type, value_type),
+        (SchemaType::Null, HtlvValueType::Null)
+            | (SchemaType::Boolean, HtlvValueType::Bool)
+            | (SchemaType::UInt8, HtlvValueType::U8)
+            | (SchemaType::UInt16, HtlvValueType::U16)
+            | (SchemaType::UInt32, HtlvValueType::U32)
+            | (SchemaType::UInt64, HtlvValueType::U64)
+            | (SchemaType::Int8, HtlvValueType::I8)
+            | (SchemaType::Int16, HtlvValueType::I16)
+            | (SchemaType::Int32, HtlvValueType::I32)
+            | (SchemaType::Int64, HtlvValueType::I64)
+            | (SchemaType::Float32, HtlvValueType::F32)
+            | (SchemaType::Float64, HtlvValueType::F64)
+            | (SchemaType::Binary, HtlvValueType::Bytes)
+            | (SchemaType::String, HtlvValueType::String)
+    )
+}
+
+/// Returns true if a value has the HTLV type of a scalar schema type
+fn scalar_matches(schema_type: &SchemaType, value: &HtlvValue) -> bool {
+    scalar_header_matches(schema_type, value.value_type())
+}
+
+/// Fields seen in an object; avoids allocating for objects of up to 64 fields
+enum SeenFields {
+    Small(u64),
+    Large(Vec<bool>),
+}
+
+impl SeenFields {
+    fn new(field_count: usize) -> Self {
+        if field_count <= 64 {
+            SeenFields::Small(0)
+        } else {
+            SeenFields::Large(vec![false; field_count])
+        }
+    }
+
+    fn insert(&mut self, index: usize) {
+        match self {
+            SeenFields::Small(bits) => *bits |= 1 << index,
+            SeenFields::Large(seen) => seen[index] = true,
+        }
+    }
+
+    fn contains(&self, index: usize) -> bool {
+        match self {
+            SeenFields::Small(bits) => *bits & (1 << index) != 0,
+            SeenFields::Large(seen) => seen[index],
+        }
+    }
+}
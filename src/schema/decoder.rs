@@ -0,0 +1,263 @@
+// Schema-aware decoding into documents with named fields
+//
+// `decode_item` knows nothing about schemas: it returns items keyed by tag
+// number, and wire format v1 even returns multi-byte numbers as batches of
+// one. A `SchemaDecoder` decodes bytes and then walks the item together with
+// its schema to normalize it:
+//
+// - numbers are converted to the numeric type of their field under the
+//   decoder's `CoercionRules`, and batches of one are unwrapped;
+// - missing fields are filled in according to a `DefaultValueStrategy`, at the
+//   root object only, or at every level for `DefaultValueStrategy::Recursive`.
+//
+// The resulting `DecodedDocument` looks fields up by name, or by alias, with
+// dotted paths such as `address.city` or `orders.0.total`. Normalization is
+// not validation: values that do not match their field are kept as they are,
+// so documents should still be checked with a `SchemaValidator` where that
+// matters.
+
+use std::sync::Arc;
+
+use crate::codec::decode::decode_item;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::codec::wire::{decode_item_with_format, WireFormat};
+use crate::internal::error::{Error, Result};
+use crate::schema::coercion::CoercionRules;
+use crate::schema::defaults::DefaultValueStrategy;
+use crate::schema::mapper::{value_to_json, MapperConfig, SchemaMapper};
+use crate::schema::types::{Schema, SchemaField, SchemaType};
+
+/// Decodes HTLV bytes into documents with fields named after a schema
+#[derive(Debug, Clone)]
+pub struct SchemaDecoder {
+    schema: Arc<Schema>,
+    defaults: DefaultValueStrategy,
+    wire_format: WireFormat,
+    mapper: SchemaMapper,
+}
+
+impl SchemaDecoder {
+    /// Creates a decoder for the schema, with strict coercion and without
+    /// default filling
+    ///
+    /// Fails if a type reference of the schema has no definition.
+    pub fn new(schema: Arc<Schema>) -> Result<Self> {
+        schema.check_definitions()?;
+        let mut decoder = Self {
+            schema,
+            defaults: DefaultValueStrategy::None,
+            wire_format: WireFormat::V1,
+            mapper: SchemaMapper::new(),
+        };
+        decoder.set_coercion(CoercionRules::strict());
+        Ok(decoder)
+    }
+
+    /// Fills missing fields according to the strategy
+    pub fn with_defaults(mut self, strategy: DefaultValueStrategy) -> Self {
+        self.defaults = strategy;
+        self
+    }
+
+    /// Converts numbers to the types of their fields under the rules
+    pub fn with_coercion(mut self, rules: CoercionRules) -> Self {
+        self.set_coercion(rules);
+        self
+    }
+
+    /// Sets the wire format version of the bytes to decode
+    pub fn with_wire_format(mut self, format: WireFormat) -> Self {
+        self.wire_format = format;
+        self
+    }
+
+    fn set_coercion(&mut self, coercion: CoercionRules) {
+        self.mapper = SchemaMapper::with_config(MapperConfig { coercion, ..MapperConfig::default() });
+        self.mapper.set_definitions(&self.schema);
+    }
+
+    /// Returns the schema documents are decoded with
+    pub fn schema(&self) -> &Arc<Schema> {
+        &self.schema
+    }
+
+    /// Decodes bytes holding a single item into a document
+    pub fn decode(&self, data: &[u8]) -> Result<DecodedDocument> {
+        let (item, _) = match self.wire_format {
+            WireFormat::V1 => decode_item(data)?,
+            format => decode_item_with_format(data, format)?,
+        };
+        self.normalize(item)
+    }
+
+    /// Normalizes an already decoded item into a document
+    pub fn normalize(&self, item: HtlvItem) -> Result<DecodedDocument> {
+        let value = self.normalize_value(&self.schema.root_type, item.value, 0)?;
+        Ok(DecodedDocument {
+            schema: Arc::clone(&self.schema),
+            item: HtlvItem::new(item.tag, value),
+        })
+    }
+
+    fn normalize_value(&self, schema_type: &SchemaType, value: HtlvValue, depth: usize) -> Result<HtlvValue> {
+        let schema_type = resolve(&self.schema, schema_type)?;
+        match (schema_type, value) {
+            (SchemaType::Object(fields), HtlvValue::Object(items)) => {
+                let mut normalized = Vec::with_capacity(items.len());
+                for item in items {
+                    let value = match fields.iter().find(|field| field.tag == item.tag) {
+                        Some(field) => self.normalize_value(&field.field_type, item.value, depth + 1)?,
+                        None => item.value,
+                    };
+                    normalized.push(HtlvItem::new(item.tag, value));
+                }
+                self.fill_defaults(schema_type, &mut normalized, depth)?;
+                Ok(HtlvValue::Object(normalized))
+            }
+            (SchemaType::Array(element_type), HtlvValue::Array(items)) => {
+                let mut normalized = Vec::with_capacity(items.len());
+                for item in items {
+                    normalized.push(HtlvItem::new(item.tag, self.normalize_value(element_type, item.value, depth + 1)?));
+                }
+                Ok(HtlvValue::Array(normalized))
+            }
+            (schema_type, value) if schema_type.is_numeric() => self.coerce_number(schema_type, value),
+            (_, value) => Ok(value),
+        }
+    }
+
+    /// Converts a number to the numeric schema type, keeping values the
+    /// coercion rules reject or that are not numbers
+    fn coerce_number(&self, schema_type: &SchemaType, value: HtlvValue) -> Result<HtlvValue> {
+        // Wire format v1 decodes multi-byte numbers as batches; a batch of
+        // one is the scalar itself
+        let value = value.into_scalar();
+        if self.mapper.schema_type_to_htlv_type(schema_type) == value.value_type() {
+            return Ok(value);
+        }
+        match self.mapper.json_to_htlv(schema_type, &value_to_json(&value)) {
+            Ok(coerced) => Ok(coerced),
+            Err(_) => Ok(value),
+        }
+    }
+
+    /// Adds the fields the default strategy provides and the object lacks
+    fn fill_defaults(&self, object_type: &SchemaType, items: &mut Vec<HtlvItem>, depth: usize) -> Result<()> {
+        let applies = match self.defaults {
+            DefaultValueStrategy::None => false,
+            DefaultValueStrategy::Recursive => true,
+            _ => depth == 0,
+        };
+        if !applies {
+            return Ok(());
+        }
+        if let HtlvValue::Object(defaults) = self.defaults.apply_defaults(object_type, None)? {
+            for default in defaults {
+                if !items.iter().any(|item| item.tag == default.tag) {
+                    items.push(default);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A decoded item whose fields can be looked up by name
+#[derive(Debug, Clone)]
+pub struct DecodedDocument {
+    schema: Arc<Schema>,
+    item: HtlvItem,
+}
+
+impl DecodedDocument {
+    /// Returns the normalized item
+    pub fn item(&self) -> &HtlvItem {
+        &self.item
+    }
+
+    /// Returns the normalized item, consuming the document
+    pub fn into_item(self) -> HtlvItem {
+        self.item
+    }
+
+    /// Returns the value at a dotted path of field names or aliases, with
+    /// array elements selected by index, or `None` if it is absent
+    pub fn get(&self, path: &str) -> Option<&HtlvValue> {
+        let mut schema_type = &self.schema.root_type;
+        let mut value = &self.item.value;
+        for segment in path.split('.') {
+            match (resolve(&self.schema, schema_type).ok()?, value) {
+                (SchemaType::Object(fields), HtlvValue::Object(items)) => {
+                    let field = find_field(fields, segment)?;
+                    value = &items.iter().find(|item| item.tag == field.tag)?.value;
+                    schema_type = &field.field_type;
+                }
+                (SchemaType::Array(element_type), HtlvValue::Array(items)) => {
+                    value = &items.get(segment.parse::<usize>().ok()?)?.value;
+                    schema_type = element_type;
+                }
+                _ => return None,
+            }
+        }
+        Some(value)
+    }
+
+    /// Returns the string at a path, if it is a valid UTF-8 string
+    pub fn get_str(&self, path: &str) -> Option<&str> {
+        match self.get(path)? {
+            HtlvValue::String(s) => std::str::from_utf8(s).ok(),
+            _ => None,
+        }
+    }
+
+    /// Returns the named fields of the root object in item order; fields
+    /// unknown to the schema are left out
+    pub fn fields(&self) -> Vec<(&str, &HtlvValue)> {
+        let (Ok(SchemaType::Object(fields)), HtlvValue::Object(items)) = (resolve(&self.schema, &self.schema.root_type), &self.item.value) else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .filter_map(|item| {
+                let field = fields.iter().find(|field| field.tag == item.tag)?;
+                Some((field.name.as_str(), &item.value))
+            })
+            .collect()
+    }
+
+    /// Returns the tags of the root object fields unknown to the schema
+    pub fn unknown_tags(&self) -> Vec<u64> {
+        let (Ok(SchemaType::Object(fields)), HtlvValue::Object(items)) = (resolve(&self.schema, &self.schema.root_type), &self.item.value) else {
+            return Vec::new();
+        };
+        items
+            .iter()
+            .filter(|item| !fields.iter().any(|field| field.tag == item.tag))
+            .map(|item| item.tag)
+            .collect()
+    }
+}
+
+/// Follows type references to the type they name
+fn resolve<'a>(schema: &'a Schema, schema_type: &'a SchemaType) -> Result<&'a SchemaType> {
+    let mut resolved = schema_type;
+    // A chain longer than the number of definitions is a cycle
+    for _ in 0..=schema.definitions.len() {
+        let SchemaType::Ref(name) = resolved else {
+            return Ok(resolved);
+        };
+        resolved = schema
+            .definitions
+            .get(name)
+            .ok_or_else(|| Error::SchemaError(format!("Unknown type reference '{}'", name)))?;
+    }
+    Err(Error::SchemaError(format!("Type reference cycle through {:?}", schema_type)))
+}
+
+/// Finds a field by name, then by alias
+fn find_field<'a>(fields: &'a [SchemaField], name: &str) -> Option<&'a SchemaField> {
+    fields
+        .iter()
+        .find(|field| field.name == name)
+        .or_else(|| fields.iter().find(|field| field.aliases.iter().any(|alias| alias == name)))
+}
@@ -151,6 +151,9 @@ impl DefaultValueStrategy {
                     // Use the first type in the union as the default
                     self.apply_defaults(&types[0], None)
                 },
+                SchemaType::Ref(name) => Err(Error::SchemaError(format!(
+                    "Cannot create default for type reference '{}'", name
+                ))),
             },
         }
     }
@@ -0,0 +1,394 @@
+// Structural diff between two versions of a Tonitru schema
+//
+// `diff(old, new)` matches fields by tag and reports added, removed and
+// changed fields, including changes to their constraints. Nested objects (and
+// arrays of objects) are compared field by field, as are the named type
+// definitions both versions share. The changeset renders as text for humans
+// and as JSON for tooling.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_json::{json, Value};
+
+use crate::schema::types::{Schema, SchemaType, SchemaField, SchemaVersion};
+use crate::schema::utils::field_path;
+
+/// How a field changed between two schema versions
+#[derive(Debug, Clone, PartialEq)]
+pub enum ChangeKind {
+    /// The field only exists in the new version
+    Added,
+    /// The field only exists in the old version
+    Removed,
+    /// The field exists in both versions with different properties
+    Modified(Vec<FieldDelta>),
+}
+
+/// A single property of a field that differs between two versions
+#[derive(Debug, Clone, PartialEq)]
+pub enum FieldDelta {
+    /// The field was renamed; its tag is unchanged
+    Renamed { old: String, new: String },
+    /// The type of the field changed
+    TypeChanged { old: String, new: String },
+    /// The field became required or optional
+    RequiredChanged { old: bool, new: bool },
+    /// A constraint or option of the field changed; `None` means unset
+    ConstraintChanged {
+        constraint: &'static str,
+        old: Option<String>,
+        new: Option<String>,
+    },
+}
+
+impl fmt::Display for FieldDelta {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FieldDelta::Renamed { old, new } => write!(f, "renamed: {} -> {}", old, new),
+            FieldDelta::TypeChanged { old, new } => write!(f, "type: {} -> {}", old, new),
+            FieldDelta::RequiredChanged { old, new } => write!(f, "required: {} -> {}", old, new),
+            FieldDelta::ConstraintChanged { constraint, old, new } => write!(
+                f,
+                "{}: {} -> {}",
+                constraint,
+                old.as_deref().unwrap_or("(none)"),
+                new.as_deref().unwrap_or("(none)")
+            ),
+        }
+    }
+}
+
+/// A change to one field
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldChange {
+    /// Dotted path of the field names from the root object, or from the name
+    /// of a type definition; uses the new name of renamed fields
+    pub path: String,
+    /// Field tag
+    pub tag: u64,
+    /// What changed
+    pub kind: ChangeKind,
+}
+
+impl fmt::Display for FieldChange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ChangeKind::Added => write!(f, "+ {} (tag {})", self.path, self.tag),
+            ChangeKind::Removed => write!(f, "- {} (tag {})", self.path, self.tag),
+            ChangeKind::Modified(deltas) => {
+                write!(f, "~ {} (tag {})", self.path, self.tag)?;
+                for delta in deltas {
+                    write!(f, "\n    {}", delta)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// The changes between two versions of a schema
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaDiff {
+    /// ID of the new schema
+    pub schema_id: String,
+    /// Version of the old schema
+    pub old_version: SchemaVersion,
+    /// Version of the new schema
+    pub new_version: SchemaVersion,
+    /// Field changes, in schema order
+    pub changes: Vec<FieldChange>,
+}
+
+impl SchemaDiff {
+    /// Returns true if the two versions are structurally identical
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// Returns the fields added in the new version
+    pub fn added(&self) -> impl Iterator<Item = &FieldChange> {
+        self.changes.iter().filter(|change| change.kind == ChangeKind::Added)
+    }
+
+    /// Returns the fields removed in the new version
+    pub fn removed(&self) -> impl Iterator<Item = &FieldChange> {
+        self.changes.iter().filter(|change| change.kind == ChangeKind::Removed)
+    }
+
+    /// Returns the fields present in both versions with different properties
+    pub fn modified(&self) -> impl Iterator<Item = &FieldChange> {
+        self.changes.iter().filter(|change| matches!(change.kind, ChangeKind::Modified(_)))
+    }
+
+    /// Renders the changeset as JSON
+    pub fn to_json(&self) -> Value {
+        let changes: Vec<Value> = self
+            .changes
+            .iter()
+            .map(|change| {
+                let mut value = json!({
+                    "path": change.path,
+                    "tag": change.tag,
+                });
+                match &change.kind {
+                    ChangeKind::Added => value["change"] = json!("added"),
+                    ChangeKind::Removed => value["change"] = json!("removed"),
+                    ChangeKind::Modified(deltas) => {
+                        value["change"] = json!("modified");
+                        value["details"] = deltas.iter().map(delta_to_json).collect();
+                    }
+                }
+                value
+            })
+            .collect();
+
+        json!({
+            "schemaId": self.schema_id,
+            "oldVersion": self.old_version.to_string(),
+            "newVersion": self.new_version.to_string(),
+            "changes": changes,
+        })
+    }
+}
+
+impl fmt::Display for SchemaDiff {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Schema '{}': {} -> {}", self.schema_id, self.old_version, self.new_version)?;
+        for change in &self.changes {
+            writeln!(f, "{}", change)?;
+        }
+        writeln!(
+            f,
+            "{} added, {} removed, {} modified",
+            self.added().count(),
+            self.removed().count(),
+            self.modified().count()
+        )
+    }
+}
+
+/// Computes the changes from an old to a new version of a schema
+pub fn diff(old: &Schema, new: &Schema) -> SchemaDiff {
+    SchemaDiff {
+        schema_id: new.id.clone(),
+        old_version: old.version.clone(),
+        new_version: new.version.clone(),
+        changes: matched_changes(old, new).into_iter().map(|matched| matched.change).collect(),
+    }
+}
+
+/// A field change with the field in each version it exists in
+#[derive(Debug, Clone)]
+pub(crate) struct MatchedChange<'a> {
+    pub(crate) change: FieldChange,
+    pub(crate) old: Option<&'a SchemaField>,
+    pub(crate) new: Option<&'a SchemaField>,
+}
+
+/// Computes the changes of `diff`, along with the fields they are about
+pub(crate) fn matched_changes<'a>(old: &'a Schema, new: &'a Schema) -> Vec<MatchedChange<'a>> {
+    let mut changes = Vec::new();
+    diff_types(&old.root_type, &new.root_type, "", &mut changes);
+
+    let mut names: Vec<&String> = new.definitions.keys().filter(|name| old.definitions.contains_key(*name)).collect();
+    names.sort();
+    for name in names {
+        diff_types(&old.definitions[name], &new.definitions[name], name, &mut changes);
+    }
+    changes
+}
+
+/// Returns a short description of a type, e.g. `array<string>`
+pub fn describe_type(schema_type: &SchemaType) -> String {
+    match schema_type {
+        SchemaType::Null => "null".to_string(),
+        SchemaType::Boolean => "boolean".to_string(),
+        SchemaType::UInt8 => "uint8".to_string(),
+        SchemaType::UInt16 => "uint16".to_string(),
+        SchemaType::UInt32 => "uint32".to_string(),
+        SchemaType::UInt64 => "uint64".to_string(),
+        SchemaType::Int8 => "int8".to_string(),
+        SchemaType::Int16 => "int16".to_string(),
+        SchemaType::Int32 => "int32".to_string(),
+        SchemaType::Int64 => "int64".to_string(),
+        SchemaType::Float32 => "float32".to_string(),
+        SchemaType::Float64 => "float64".to_string(),
+        SchemaType::String => "string".to_string(),
+        SchemaType::Binary => "binary".to_string(),
+        SchemaType::Array(element_type) => format!("array<{}>", describe_type(element_type)),
+        SchemaType::Object(fields) => {
+            let names: Vec<&str> = fields.iter().map(|field| field.name.as_str()).collect();
+            format!("object{{{}}}", names.join(", "))
+        }
+        SchemaType::Map(key_type, value_type) => {
+            format!("map<{}, {}>", describe_type(key_type), describe_type(value_type))
+        }
+        SchemaType::Union(variants) => {
+            let variants: Vec<String> = variants.iter().map(describe_type).collect();
+            format!("union<{}>", variants.join(" | "))
+        }
+        SchemaType::Ref(name) => name.clone(),
+    }
+}
+
+/// Compares two types at the same path; object fields are compared one by one
+fn diff_types<'a>(old: &'a SchemaType, new: &'a SchemaType, prefix: &str, changes: &mut Vec<MatchedChange<'a>>) {
+    if let (Some(old_fields), Some(new_fields)) = (object_fields(old), object_fields(new)) {
+        diff_fields(old_fields, new_fields, prefix, changes);
+    }
+}
+
+fn diff_fields<'a>(
+    old_fields: &'a [SchemaField],
+    new_fields: &'a [SchemaField],
+    prefix: &str,
+    changes: &mut Vec<MatchedChange<'a>>,
+) {
+    let new_by_tag: HashMap<u64, &SchemaField> = new_fields.iter().map(|field| (field.tag, field)).collect();
+
+    for old_field in old_fields {
+        let new_field = match new_by_tag.get(&old_field.tag) {
+            Some(field) => *field,
+            None => {
+                changes.push(MatchedChange {
+                    change: FieldChange {
+                        path: field_path(prefix, &old_field.name),
+                        tag: old_field.tag,
+                        kind: ChangeKind::Removed,
+                    },
+                    old: Some(old_field),
+                    new: None,
+                });
+                continue;
+            }
+        };
+
+        let path = field_path(prefix, &new_field.name);
+        let deltas = field_deltas(old_field, new_field);
+        if !deltas.is_empty() {
+            changes.push(MatchedChange {
+                change: FieldChange {
+                    path: path.clone(),
+                    tag: new_field.tag,
+                    kind: ChangeKind::Modified(deltas),
+                },
+                old: Some(old_field),
+                new: Some(new_field),
+            });
+        }
+        diff_types(&old_field.field_type, &new_field.field_type, &path, changes);
+    }
+
+    for new_field in new_fields {
+        if !old_fields.iter().any(|field| field.tag == new_field.tag) {
+            changes.push(MatchedChange {
+                change: FieldChange {
+                    path: field_path(prefix, &new_field.name),
+                    tag: new_field.tag,
+                    kind: ChangeKind::Added,
+                },
+                old: None,
+                new: Some(new_field),
+            });
+        }
+    }
+}
+
+fn field_deltas(old: &SchemaField, new: &SchemaField) -> Vec<FieldDelta> {
+    let mut deltas = Vec::new();
+
+    if old.name != new.name {
+        deltas.push(FieldDelta::Renamed {
+            old: old.name.clone(),
+            new: new.name.clone(),
+        });
+    }
+
+    // Nested object fields are reported individually
+    let both_objects = object_fields(&old.field_type).is_some() && object_fields(&new.field_type).is_some();
+    let same_shape = matches!(
+        (&old.field_type, &new.field_type),
+        (SchemaType::Object(_), SchemaType::Object(_)) | (SchemaType::Array(_), SchemaType::Array(_))
+    );
+    if old.field_type != new.field_type && !(both_objects && same_shape) {
+        deltas.push(FieldDelta::TypeChanged {
+            old: describe_type(&old.field_type),
+            new: describe_type(&new.field_type),
+        });
+    }
+
+    if old.required != new.required {
+        deltas.push(FieldDelta::RequiredChanged {
+            old: old.required,
+            new: new.required,
+        });
+    }
+
+    for ((constraint, old_value), (_, new_value)) in constraints(old).into_iter().zip(constraints(new)) {
+        if old_value != new_value {
+            deltas.push(FieldDelta::ConstraintChanged {
+                constraint,
+                old: old_value,
+                new: new_value,
+            });
+        }
+    }
+
+    deltas
+}
+
+/// Returns the constraints and options of a field, rendered for comparison
+fn constraints(field: &SchemaField) -> Vec<(&'static str, Option<String>)> {
+    let options = &field.options;
+    let flag = |set: bool| if set { Some("true".to_string()) } else { None };
+    let list = |items: Vec<String>| if items.is_empty() { None } else { Some(items.join(", ")) };
+
+    let mut aliases = field.aliases.clone();
+    aliases.sort();
+    let mut labels: Vec<String> = options.labels.iter().map(|label| label.to_string()).collect();
+    labels.sort();
+    let mut custom: Vec<String> = options.custom.iter().map(|(key, value)| format!("{}={}", key, value)).collect();
+    custom.sort();
+
+    vec![
+        ("default", field.default_value.as_ref().map(|value| format!("{:?}", value))),
+        ("min_value", options.min_value.as_ref().map(|value| format!("{:?}", value))),
+        ("max_value", options.max_value.as_ref().map(|value| format!("{:?}", value))),
+        ("pattern", options.pattern.clone()),
+        ("min_length", options.min_length.map(|length| length.to_string())),
+        ("max_length", options.max_length.map(|length| length.to_string())),
+        ("aliases", list(aliases)),
+        ("compress", flag(options.compress)),
+        ("encrypt", flag(options.encrypt)),
+        ("index", flag(options.index)),
+        ("deprecated", flag(options.deprecated)),
+        ("since_version", options.since_version.as_ref().map(|version| version.to_string())),
+        ("removed_in", options.removed_in.as_ref().map(|version| version.to_string())),
+        ("labels", list(labels)),
+        ("custom", list(custom)),
+    ]
+}
+
+fn delta_to_json(delta: &FieldDelta) -> Value {
+    match delta {
+        FieldDelta::Renamed { old, new } => json!({ "property": "name", "old": old, "new": new }),
+        FieldDelta::TypeChanged { old, new } => json!({ "property": "type", "old": old, "new": new }),
+        FieldDelta::RequiredChanged { old, new } => json!({ "property": "required", "old": old, "new": new }),
+        FieldDelta::ConstraintChanged { constraint, old, new } => {
+            json!({ "property": constraint, "old": old, "new": new })
+        }
+    }
+}
+
+/// Returns the fields of an object type, or of the elements of an array of objects
+pub(crate) fn object_fields(schema_type: &SchemaType) -> Option<&[SchemaField]> {
+    match schema_type {
+        SchemaType::Object(fields) => Some(fields),
+        SchemaType::Array(element_type) => match element_type.as_ref() {
+            SchemaType::Object(fields) => Some(fields),
+            _ => None,
+        },
+        _ => None,
+    }
+}
@@ -107,7 +107,7 @@ impl SchemaInference {
         let all_same_type = values.iter().all(|v| self.get_json_type(v) == first_type);
         
         if all_same_type {
-            match first_type {
+            match first_type.as_str() {
                 "null" => Ok(SchemaType::Null),
                 "boolean" => Ok(SchemaType::Boolean),
                 "number" => self.infer_numeric_type(values),
@@ -323,6 +323,7 @@ impl SchemaInference {
             // Create the field
             let field = SchemaField {
                 name,
+                aliases: Vec::new(),
                 tag,
                 field_type,
                 required,
@@ -0,0 +1,112 @@
+// Field lifecycle checks for Tonitru schemas
+//
+// This module compares two versions of a schema and reports changes that do
+// not follow the field lifecycle: fields have to be deprecated before they are
+// removed, and not removed before the version announced in `removed_in`.
+
+use std::fmt;
+
+use crate::schema::diff::object_fields;
+use crate::schema::types::{Schema, SchemaField, SchemaVersion, version_key};
+use crate::schema::utils::field_path;
+
+/// A lifecycle finding between two schema versions
+#[derive(Debug, Clone, PartialEq)]
+pub struct LifecycleIssue {
+    /// Dotted path of the field names from the root object
+    pub path: String,
+    /// Field tag
+    pub tag: u64,
+    /// Whether the change breaks readers of the old schema
+    pub breaking: bool,
+    /// Human-readable explanation
+    pub message: String,
+}
+
+impl fmt::Display for LifecycleIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {} (tag {}): {}",
+            if self.breaking { "breaking" } else { "note" },
+            self.path,
+            self.tag,
+            self.message
+        )
+    }
+}
+
+/// Checks the field lifecycle between an old and a new version of a schema
+pub fn check_lifecycle(old: &Schema, new: &Schema) -> Vec<LifecycleIssue> {
+    let mut issues = Vec::new();
+    if let (Some(old_fields), Some(new_fields)) = (object_fields(&old.root_type), object_fields(&new.root_type)) {
+        check_fields(old_fields, new_fields, &new.version, "", &mut issues);
+    }
+    issues
+}
+
+fn check_fields(
+    old_fields: &[SchemaField],
+    new_fields: &[SchemaField],
+    new_version: &SchemaVersion,
+    prefix: &str,
+    issues: &mut Vec<LifecycleIssue>,
+) {
+    for old_field in old_fields {
+        let path = field_path(prefix, &old_field.name);
+        let new_field = match new_fields.iter().find(|field| field.tag == old_field.tag) {
+            Some(field) => field,
+            None => {
+                if !old_field.is_deprecated() {
+                    issues.push(LifecycleIssue {
+                        path,
+                        tag: old_field.tag,
+                        breaking: true,
+                        message: "field removed without being deprecated first".to_string(),
+                    });
+                } else if let Some(removed_in) = &old_field.options.removed_in {
+                    if version_key(new_version) < version_key(removed_in) {
+                        issues.push(LifecycleIssue {
+                            path,
+                            tag: old_field.tag,
+                            breaking: true,
+                            message: format!("field removed in version {} but announced for removal in {}", new_version, removed_in),
+                        });
+                    }
+                }
+                continue;
+            }
+        };
+
+        if let Some(removed_in) = &new_field.options.removed_in {
+            if new_field.is_removed_in(new_version) {
+                issues.push(LifecycleIssue {
+                    path: path.clone(),
+                    tag: new_field.tag,
+                    breaking: false,
+                    message: format!("field is still declared in version {} although it is removed in {}", new_version, removed_in),
+                });
+            }
+        }
+
+        if let (Some(old_nested), Some(new_nested)) = (object_fields(&old_field.field_type), object_fields(&new_field.field_type)) {
+            check_fields(old_nested, new_nested, new_version, &path, issues);
+        }
+    }
+
+    for new_field in new_fields {
+        if old_fields.iter().any(|field| field.tag == new_field.tag) {
+            continue;
+        }
+        if let Some(since) = &new_field.options.since_version {
+            if version_key(since) > version_key(new_version) {
+                issues.push(LifecycleIssue {
+                    path: field_path(prefix, &new_field.name),
+                    tag: new_field.tag,
+                    breaking: false,
+                    message: format!("field is declared in version {} but only introduced in {}", new_version, since),
+                });
+            }
+        }
+    }
+}
@@ -0,0 +1,375 @@
+// Schema linting for Tonitru
+//
+// This module checks schemas for anti-patterns that are valid but costly or
+// error-prone on the wire: ambiguous unions, strings without a length bound,
+// unindexed high-cardinality keys, hash-derived tags and required fields that
+// are marked as deprecated. Each finding carries a severity so that tooling
+// can decide which ones fail a build.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::schema::types::{Schema, SchemaType, SchemaField};
+use crate::schema::utils::generate_tag_from_name;
+
+/// Severity of a lint finding
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LintSeverity {
+    /// Worth knowing, usually harmless
+    Info,
+    /// Likely to cause inefficiency or ambiguity
+    Warning,
+    /// Likely to cause incorrect behavior
+    Error,
+}
+
+impl fmt::Display for LintSeverity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LintSeverity::Info => write!(f, "info"),
+            LintSeverity::Warning => write!(f, "warning"),
+            LintSeverity::Error => write!(f, "error"),
+        }
+    }
+}
+
+/// The anti-patterns checked by the linter
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LintRule {
+    /// A union has several variants that accept the same kind of value
+    OverlappingUnion,
+    /// A string field has no `max_length`
+    UnboundedString,
+    /// A map key or identifier field with many distinct values is not indexed
+    UnindexedKey,
+    /// A field tag was derived from a hash of its name
+    HashDerivedTag,
+    /// A deprecated field is still required
+    DeprecatedRequired,
+}
+
+impl LintRule {
+    /// All rules, in reporting order
+    pub const ALL: [LintRule; 5] = [
+        LintRule::OverlappingUnion,
+        LintRule::UnboundedString,
+        LintRule::UnindexedKey,
+        LintRule::HashDerivedTag,
+        LintRule::DeprecatedRequired,
+    ];
+
+    /// Returns the name of the rule, as used in reports
+    pub fn name(&self) -> &'static str {
+        match self {
+            LintRule::OverlappingUnion => "overlapping-union",
+            LintRule::UnboundedString => "unbounded-string",
+            LintRule::UnindexedKey => "unindexed-key",
+            LintRule::HashDerivedTag => "hash-derived-tag",
+            LintRule::DeprecatedRequired => "deprecated-required",
+        }
+    }
+
+    /// Returns the severity the rule reports with
+    pub fn severity(&self) -> LintSeverity {
+        match self {
+            LintRule::OverlappingUnion => LintSeverity::Error,
+            LintRule::UnboundedString => LintSeverity::Warning,
+            LintRule::UnindexedKey => LintSeverity::Info,
+            LintRule::HashDerivedTag => LintSeverity::Warning,
+            LintRule::DeprecatedRequired => LintSeverity::Error,
+        }
+    }
+}
+
+impl fmt::Display for LintRule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// A single lint finding
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintIssue {
+    /// Rule that produced the finding
+    pub rule: LintRule,
+    /// Severity of the finding
+    pub severity: LintSeverity,
+    /// Dotted path of the field names from the root object ("" for the root itself)
+    pub path: String,
+    /// Human-readable explanation
+    pub message: String,
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = if self.path.is_empty() { "<root>" } else { &self.path };
+        write!(f, "{} [{}] {}: {}", self.severity, self.rule, path, self.message)
+    }
+}
+
+/// Findings produced by a `SchemaLinter`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LintReport {
+    /// ID of the schema the report is about
+    pub schema_id: String,
+    /// Findings, in schema order
+    pub issues: Vec<LintIssue>,
+}
+
+impl LintReport {
+    /// Returns true if no finding was reported
+    pub fn is_clean(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Returns true if at least one finding has `Error` severity
+    pub fn has_errors(&self) -> bool {
+        self.issues.iter().any(|issue| issue.severity == LintSeverity::Error)
+    }
+
+    /// Returns the number of findings with the given severity
+    pub fn count(&self, severity: LintSeverity) -> usize {
+        self.issues.iter().filter(|issue| issue.severity == severity).count()
+    }
+}
+
+impl fmt::Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for issue in &self.issues {
+            writeln!(f, "{}", issue)?;
+        }
+        writeln!(
+            f,
+            "Schema '{}': {} errors, {} warnings, {} infos",
+            self.schema_id,
+            self.count(LintSeverity::Error),
+            self.count(LintSeverity::Warning),
+            self.count(LintSeverity::Info)
+        )
+    }
+}
+
+/// Configuration for schema linting
+#[derive(Debug, Clone)]
+pub struct LintConfig {
+    /// Rules that are not checked
+    pub disabled_rules: HashSet<LintRule>,
+    /// Tags above this value are assumed to be hash-derived even if they do
+    /// not match the hash of the field name
+    pub max_explicit_tag: u64,
+}
+
+impl Default for LintConfig {
+    fn default() -> Self {
+        Self {
+            disabled_rules: HashSet::new(),
+            max_explicit_tag: u32::MAX as u64,
+        }
+    }
+}
+
+/// Schema linter
+#[derive(Debug, Default)]
+pub struct SchemaLinter {
+    config: LintConfig,
+}
+
+impl SchemaLinter {
+    /// Creates a new schema linter with default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a new schema linter with custom configuration
+    pub fn with_config(config: LintConfig) -> Self {
+        Self { config }
+    }
+
+    /// Checks a schema against all enabled rules
+    ///
+    /// Named type definitions are linted after the root type, with paths
+    /// starting at the name of the definition.
+    pub fn lint(&self, schema: &Schema) -> LintReport {
+        let mut issues = Vec::new();
+        let definitions = &schema.definitions;
+        self.lint_type(&schema.root_type, "", definitions, &mut issues);
+
+        let mut names: Vec<&String> = definitions.keys().collect();
+        names.sort();
+        for name in names {
+            self.lint_type(&definitions[name], name, definitions, &mut issues);
+        }
+        LintReport {
+            schema_id: schema.id.clone(),
+            issues,
+        }
+    }
+
+    fn report(&self, issues: &mut Vec<LintIssue>, rule: LintRule, path: &str, message: String) {
+        if !self.config.disabled_rules.contains(&rule) {
+            issues.push(LintIssue {
+                rule,
+                severity: rule.severity(),
+                path: path.to_string(),
+                message,
+            });
+        }
+    }
+
+    fn lint_type(
+        &self,
+        schema_type: &SchemaType,
+        path: &str,
+        definitions: &HashMap<String, SchemaType>,
+        issues: &mut Vec<LintIssue>,
+    ) {
+        match schema_type {
+            SchemaType::Object(fields) => {
+                for field in fields {
+                    let field_path = if path.is_empty() {
+                        field.name.clone()
+                    } else {
+                        format!("{}.{}", path, field.name)
+                    };
+                    self.lint_field(field, &field_path, definitions, issues);
+                }
+            }
+            SchemaType::Array(element_type) => self.lint_type(element_type, path, definitions, issues),
+            SchemaType::Map(key_type, value_type) => {
+                self.lint_type(key_type, path, definitions, issues);
+                self.lint_type(value_type, path, definitions, issues);
+            }
+            SchemaType::Union(variants) => {
+                self.lint_union(variants, path, definitions, issues);
+                for variant in variants {
+                    self.lint_type(variant, path, definitions, issues);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn lint_field(
+        &self,
+        field: &SchemaField,
+        path: &str,
+        definitions: &HashMap<String, SchemaType>,
+        issues: &mut Vec<LintIssue>,
+    ) {
+        if field.field_type == SchemaType::String && field.options.max_length.is_none() {
+            self.report(
+                issues,
+                LintRule::UnboundedString,
+                path,
+                "string field has no max_length".to_string(),
+            );
+        }
+
+        if !field.options.index {
+            if let SchemaType::Map(key_type, _) = &field.field_type {
+                if is_high_cardinality(key_type) {
+                    self.report(
+                        issues,
+                        LintRule::UnindexedKey,
+                        path,
+                        format!("map keyed by {:?} is not indexed", key_type),
+                    );
+                }
+            } else if is_identifier_name(&field.name) && is_high_cardinality(&field.field_type) {
+                self.report(
+                    issues,
+                    LintRule::UnindexedKey,
+                    path,
+                    format!("identifier field of type {:?} is not indexed", field.field_type),
+                );
+            }
+        }
+
+        if field.tag == generate_tag_from_name(&field.name) {
+            self.report(
+                issues,
+                LintRule::HashDerivedTag,
+                path,
+                format!("tag {} is the hash of the field name; assign a small explicit tag", field.tag),
+            );
+        } else if field.tag > self.config.max_explicit_tag {
+            self.report(
+                issues,
+                LintRule::HashDerivedTag,
+                path,
+                format!("tag {} looks hash-derived and takes up to 10 bytes per occurrence", field.tag),
+            );
+        }
+
+        if field.is_deprecated() && field.required {
+            self.report(
+                issues,
+                LintRule::DeprecatedRequired,
+                path,
+                "deprecated field is still required".to_string(),
+            );
+        }
+
+        self.lint_type(&field.field_type, path, definitions, issues);
+    }
+
+    fn lint_union(
+        &self,
+        variants: &[SchemaType],
+        path: &str,
+        definitions: &HashMap<String, SchemaType>,
+        issues: &mut Vec<LintIssue>,
+    ) {
+        for (i, variant) in variants.iter().enumerate() {
+            let kind = value_kind(variant, definitions);
+            if let Some(other) = variants[..i].iter().find(|other| value_kind(other, definitions) == kind) {
+                self.report(
+                    issues,
+                    LintRule::OverlappingUnion,
+                    path,
+                    format!("union variants {:?} and {:?} both accept {} values", other, variant, kind),
+                );
+            }
+        }
+    }
+}
+
+/// Checks a schema against all rules with the default configuration
+pub fn lint_schema(schema: &Schema) -> LintReport {
+    SchemaLinter::new().lint(schema)
+}
+
+/// Returns the kind of value a type accepts in the source data; union variants
+/// of the same kind cannot be told apart when mapping a value
+fn value_kind(schema_type: &SchemaType, definitions: &HashMap<String, SchemaType>) -> &'static str {
+    match schema_type {
+        SchemaType::Null => "null",
+        SchemaType::Boolean => "boolean",
+        SchemaType::Binary => "binary",
+        SchemaType::String => "string",
+        SchemaType::Array(_) => "array",
+        SchemaType::Object(_) | SchemaType::Map(_, _) => "object",
+        SchemaType::Union(_) => "union",
+        // Definitions never refer to themselves without nesting, so this terminates
+        SchemaType::Ref(name) => match definitions.get(name) {
+            Some(definition) => value_kind(definition, definitions),
+            None => "reference",
+        },
+        _ => "numeric",
+    }
+}
+
+/// Returns true for types whose values are typically unique per record
+fn is_high_cardinality(schema_type: &SchemaType) -> bool {
+    matches!(
+        schema_type,
+        SchemaType::String | SchemaType::Binary |
+        SchemaType::UInt32 | SchemaType::UInt64 | SchemaType::Int32 | SchemaType::Int64
+    )
+}
+
+/// Returns true for field names conventionally used for identifiers
+fn is_identifier_name(name: &str) -> bool {
+    let lower = name.to_ascii_lowercase();
+    lower == "id" || lower == "key" || lower.ends_with("_id") || lower.ends_with("_key") || name.ends_with("Id")
+}
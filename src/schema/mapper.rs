@@ -9,6 +9,8 @@ use crate::internal::error::{Error, Result};
 use crate::codec::types::{HtlvItem, HtlvValue, HtlvValueType};
 use crate::schema::types::{Schema, SchemaType, SchemaField};
 use crate::schema::defaults::DefaultValueStrategy;
+use crate::schema::policy::{PolicyAction, PolicyEngine, redacted_value};
+use crate::schema::coercion::CoercionRules;
 
 /// Configuration for schema mapping
 #[derive(Debug, Clone)]
@@ -24,6 +26,16 @@ pub struct MapperConfig {
     
     /// Custom type mappings (schema type name -> HTLV value type)
     pub custom_type_mappings: HashMap<String, HtlvValueType>,
+    
+    /// Access policy of the caller; fields it is not cleared for are redacted or dropped
+    pub policy: Option<PolicyEngine>,
+    
+    /// Rules for converting JSON numbers (and numeric strings) to numeric schema types
+    pub coercion: CoercionRules,
+    
+    /// Whether JSON keys match field names across naming conventions
+    /// (e.g. `userId`, `user_id` and `user-id`) when there is no exact match
+    pub match_naming_conventions: bool,
 }
 
 impl Default for MapperConfig {
@@ -33,6 +45,9 @@ impl Default for MapperConfig {
             validate: true,
             preserve_unknown_fields: false,
             custom_type_mappings: HashMap::new(),
+            policy: None,
+            coercion: CoercionRules::default(),
+            match_naming_conventions: true,
         }
     }
 }
@@ -41,19 +56,27 @@ impl Default for MapperConfig {
 #[derive(Debug, Clone)]
 pub struct SchemaMapper {
     config: MapperConfig,
+    /// Named types referred to by `SchemaType::Ref`
+    definitions: HashMap<String, SchemaType>,
 }
 
 impl SchemaMapper {
     /// Creates a new schema mapper with default configuration
     pub fn new() -> Self {
-        Self {
-            config: MapperConfig::default(),
-        }
+        Self::with_config(MapperConfig::default())
     }
     
     /// Creates a new schema mapper with custom configuration
     pub fn with_config(config: MapperConfig) -> Self {
-        Self { config }
+        Self {
+            config,
+            definitions: HashMap::new(),
+        }
+    }
+    
+    /// Uses the named types of a schema to resolve type references
+    pub fn set_definitions(&mut self, schema: &Schema) {
+        self.definitions = schema.definitions.clone();
     }
     
     /// Maps a schema type to an HTLV value type
@@ -81,6 +104,10 @@ impl SchemaMapper {
                 // Default to Object as it's the most flexible
                 HtlvValueType::Object
             }
+            SchemaType::Ref(name) => match self.definitions.get(name) {
+                Some(definition) => self.schema_type_to_htlv_type(definition),
+                None => HtlvValueType::Object,
+            },
         }
     }
     
@@ -109,105 +136,42 @@ impl SchemaMapper {
             // Boolean type
             (SchemaType::Boolean, serde_json::Value::Bool(b)) => Ok(HtlvValue::Bool(*b)),
             
-            // Number types
-            (SchemaType::UInt8, serde_json::Value::Number(n)) => {
-                if let Some(u) = n.as_u64() {
-                    if u <= u8::MAX as u64 {
-                        Ok(HtlvValue::U8(u as u8))
-                    } else {
-                        Err(Error::SchemaError(format!("Value {} is too large for UInt8", u)))
-                    }
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to UInt8", n)))
-                }
+            // Number types, converted according to the coercion rules
+            (SchemaType::UInt8, json) => {
+                let coercion = &self.config.coercion;
+                Ok(HtlvValue::U8(coercion.coerce_unsigned(json, u8::MAX as u64, "UInt8")? as u8))
             },
-            (SchemaType::UInt16, serde_json::Value::Number(n)) => {
-                if let Some(u) = n.as_u64() {
-                    if u <= u16::MAX as u64 {
-                        Ok(HtlvValue::U16(u as u16))
-                    } else {
-                        Err(Error::SchemaError(format!("Value {} is too large for UInt16", u)))
-                    }
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to UInt16", n)))
-                }
+            (SchemaType::UInt16, json) => {
+                let coercion = &self.config.coercion;
+                Ok(HtlvValue::U16(coercion.coerce_unsigned(json, u16::MAX as u64, "UInt16")? as u16))
             },
-            (SchemaType::UInt32, serde_json::Value::Number(n)) => {
-                if let Some(u) = n.as_u64() {
-                    if u <= u32::MAX as u64 {
-                        Ok(HtlvValue::U32(u as u32))
-                    } else {
-                        Err(Error::SchemaError(format!("Value {} is too large for UInt32", u)))
-                    }
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to UInt32", n)))
-                }
+            (SchemaType::UInt32, json) => {
+                let coercion = &self.config.coercion;
+                Ok(HtlvValue::U32(coercion.coerce_unsigned(json, u32::MAX as u64, "UInt32")? as u32))
             },
-            (SchemaType::UInt64, serde_json::Value::Number(n)) => {
-                if let Some(u) = n.as_u64() {
-                    Ok(HtlvValue::U64(u))
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to UInt64", n)))
-                }
+            (SchemaType::UInt64, json) => {
+                Ok(HtlvValue::U64(self.config.coercion.coerce_unsigned(json, u64::MAX, "UInt64")?))
             },
-            (SchemaType::Int8, serde_json::Value::Number(n)) => {
-                if let Some(i) = n.as_i64() {
-                    if i >= i8::MIN as i64 && i <= i8::MAX as i64 {
-                        Ok(HtlvValue::I8(i as i8))
-                    } else {
-                        Err(Error::SchemaError(format!("Value {} is out of range for Int8", i)))
-                    }
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to Int8", n)))
-                }
+            (SchemaType::Int8, json) => {
+                let coercion = &self.config.coercion;
+                Ok(HtlvValue::I8(coercion.coerce_signed(json, i8::MIN as i64, i8::MAX as i64, "Int8")? as i8))
             },
-            (SchemaType::Int16, serde_json::Value::Number(n)) => {
-                if let Some(i) = n.as_i64() {
-                    if i >= i16::MIN as i64 && i <= i16::MAX as i64 {
-                        Ok(HtlvValue::I16(i as i16))
-                    } else {
-                        Err(Error::SchemaError(format!("Value {} is out of range for Int16", i)))
-                    }
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to Int16", n)))
-                }
+            (SchemaType::Int16, json) => {
+                let coercion = &self.config.coercion;
+                Ok(HtlvValue::I16(coercion.coerce_signed(json, i16::MIN as i64, i16::MAX as i64, "Int16")? as i16))
             },
-            (SchemaType::Int32, serde_json::Value::Number(n)) => {
-                if let Some(i) = n.as_i64() {
-                    if i >= i32::MIN as i64 && i <= i32::MAX as i64 {
-                        Ok(HtlvValue::I32(i as i32))
-                    } else {
-                        Err(Error::SchemaError(format!("Value {} is out of range for Int32", i)))
-                    }
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to Int32", n)))
-                }
+            (SchemaType::Int32, json) => {
+                let coercion = &self.config.coercion;
+                Ok(HtlvValue::I32(coercion.coerce_signed(json, i32::MIN as i64, i32::MAX as i64, "Int32")? as i32))
             },
-            (SchemaType::Int64, serde_json::Value::Number(n)) => {
-                if let Some(i) = n.as_i64() {
-                    Ok(HtlvValue::I64(i))
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to Int64", n)))
-                }
+            (SchemaType::Int64, json) => {
+                Ok(HtlvValue::I64(self.config.coercion.coerce_signed(json, i64::MIN, i64::MAX, "Int64")?))
             },
-            (SchemaType::Float32, serde_json::Value::Number(n)) => {
-                if let Some(f) = n.as_f64() {
-                    // Check if the value is within the range of f32
-                    if f.abs() <= f32::MAX as f64 && f.abs() >= f32::MIN_POSITIVE as f64 {
-                        Ok(HtlvValue::F32(f as f32))
-                    } else {
-                        Err(Error::SchemaError(format!("Value {} is out of range for Float32", f)))
-                    }
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to Float32", n)))
-                }
+            (SchemaType::Float32, json) => {
+                Ok(HtlvValue::F32(self.config.coercion.coerce_f32(json, "Float32")?))
             },
-            (SchemaType::Float64, serde_json::Value::Number(n)) => {
-                if let Some(f) = n.as_f64() {
-                    Ok(HtlvValue::F64(f))
-                } else {
-                    Err(Error::SchemaError(format!("Cannot convert {} to Float64", n)))
-                }
+            (SchemaType::Float64, json) => {
+                Ok(HtlvValue::F64(self.config.coercion.coerce_f64(json, "Float64")?))
             },
             
             // String and binary types
@@ -239,16 +203,45 @@ impl SchemaMapper {
             (SchemaType::Object(fields), serde_json::Value::Object(obj)) => {
                 let mut items = Vec::new();
                 
-                // Create a map of field names to field definitions for quick lookup
-                let field_map: HashMap<&str, &SchemaField> = fields
-                    .iter()
-                    .map(|field| (field.name.as_str(), field))
-                    .collect();
+                // Create maps of field names and aliases to field definitions for quick lookup,
+                // the second one keyed by names normalized across naming conventions
+                let mut field_map: HashMap<&str, &SchemaField> = HashMap::new();
+                let mut normalized_map: HashMap<String, &SchemaField> = HashMap::new();
+                for field in fields {
+                    for name in std::iter::once(&field.name).chain(&field.aliases) {
+                        field_map.insert(name.as_str(), field);
+                        if self.config.match_naming_conventions {
+                            normalized_map.insert(normalize_name(name), field);
+                        }
+                    }
+                }
+                
+                // JSON key that provided each field, by tag
+                let mut matched: HashMap<u64, &str> = HashMap::new();
                 
                 // Convert each field in the JSON object
                 for (key, value) in obj {
-                    if let Some(field) = field_map.get(key.as_str()) {
-                        let htlv_value = self.json_to_htlv(&field.field_type, value)?;
+                    let field = field_map.get(key.as_str()).copied().or_else(|| {
+                        if self.config.match_naming_conventions {
+                            normalized_map.get(&normalize_name(key)).copied()
+                        } else {
+                            None
+                        }
+                    });
+                    
+                    if let Some(field) = field {
+                        if let Some(other) = matched.insert(field.tag, key) {
+                            return Err(Error::SchemaError(format!(
+                                "Field '{}' is given twice, as '{}' and '{}'", field.name, other, key
+                            )));
+                        }
+                        
+                        let action = self.config.policy.as_ref().map_or(PolicyAction::Allow, |policy| policy.action_for(field));
+                        let htlv_value = match action {
+                            PolicyAction::Allow => self.json_to_htlv(&field.field_type, value)?,
+                            PolicyAction::Redact => redacted_value(&field.field_type, &self.definitions)?,
+                            PolicyAction::Drop => continue,
+                        };
                         items.push(HtlvItem {
                             tag: field.tag,
                             value: htlv_value,
@@ -273,7 +266,7 @@ impl SchemaMapper {
                 
                 // Add default values for missing required fields
                 for field in fields {
-                    if field.required && !obj.contains_key(&field.name) {
+                    if field.required && !matched.contains_key(&field.tag) {
                         if let Some(default) = &field.default_value {
                             items.push(HtlvItem {
                                 tag: field.tag,
@@ -295,8 +288,11 @@ impl SchemaMapper {
             
             // Union type
             (SchemaType::Union(types), json) => {
-                // Try each possible type in the union
-                for t in types {
+                // Try each possible type in the union, preferring the types that
+                // accept the JSON value without coercion
+                let (native, coerced): (Vec<&SchemaType>, Vec<&SchemaType>) =
+                    types.iter().partition(|t| accepts_natively(t, json));
+                for t in native.into_iter().chain(coerced) {
                     if let Ok(value) = self.json_to_htlv(t, json) {
                         return Ok(value);
                     }
@@ -338,6 +334,12 @@ impl SchemaMapper {
                 Ok(HtlvValue::Object(items))
             },
             
+            // Named type
+            (SchemaType::Ref(name), json) => match self.definitions.get(name) {
+                Some(definition) => self.json_to_htlv(definition, json),
+                None => Err(Error::SchemaError(format!("Unknown type reference '{}'", name))),
+            },
+            
             // Type mismatch
             (expected, actual) => Err(Error::SchemaError(format!(
                 "Type mismatch: expected {:?}, got {:?}", expected, actual
@@ -345,6 +347,101 @@ impl SchemaMapper {
         }
     }
     
+    /// Converts an HTLV value to a JSON value based on the schema type, the
+    /// inverse of `json_to_htlv`
+    ///
+    /// Object fields are named after the schema; fields the schema does not
+    /// describe are kept under their tag number if `preserve_unknown_fields` is
+    /// set. Binary values become base64 strings and non-finite floats `null`.
+    pub fn htlv_to_json(
+        &self,
+        schema_type: &SchemaType,
+        value: &HtlvValue,
+    ) -> Result<serde_json::Value> {
+        use serde_json::Value;
+        
+        // Wire format v1 decodes multi-byte numbers as batches; a batch of
+        // one is the scalar itself
+        let value = if schema_type.is_numeric() { value.as_scalar() } else { value };
+        match (schema_type, value) {
+            (SchemaType::Null, HtlvValue::Null) => Ok(Value::Null),
+            (SchemaType::Boolean, HtlvValue::Bool(b)) => Ok(Value::Bool(*b)),
+            
+            // Numbers of any width, as long as the schema type is numeric
+            (schema_type, value) if schema_type.is_numeric() && value_to_json(value).is_number() => {
+                Ok(value_to_json(value))
+            },
+            (schema_type, HtlvValue::F32(_) | HtlvValue::F64(_)) if schema_type.is_float() => Ok(Value::Null),
+            
+            (SchemaType::String, HtlvValue::String(s)) => match std::str::from_utf8(s) {
+                Ok(s) => Ok(Value::String(s.to_string())),
+                Err(e) => Err(Error::SchemaError(format!("Invalid UTF-8 in string value: {}", e))),
+            },
+            (SchemaType::Binary, HtlvValue::Bytes(b)) => Ok(Value::String(base64::encode(b))),
+            
+            (SchemaType::Array(elem_type), HtlvValue::Array(items)) => items
+                .iter()
+                .map(|item| self.htlv_to_json(elem_type, &item.value))
+                .collect::<Result<Vec<_>>>()
+                .map(Value::Array),
+            
+            (SchemaType::Object(fields), HtlvValue::Object(items)) => {
+                let mut obj = serde_json::Map::new();
+                for item in items {
+                    match fields.iter().find(|field| field.tag == item.tag) {
+                        Some(field) => {
+                            obj.insert(field.name.clone(), self.htlv_to_json(&field.field_type, &item.value)?);
+                        }
+                        None if self.config.preserve_unknown_fields => {
+                            obj.insert(item.tag.to_string(), value_to_json(&item.value));
+                        }
+                        None => {}
+                    }
+                }
+                Ok(Value::Object(obj))
+            },
+            
+            (SchemaType::Map(key_type, value_type), HtlvValue::Object(entries)) => {
+                let mut obj = serde_json::Map::new();
+                for entry in entries {
+                    let (key, value) = match &entry.value {
+                        HtlvValue::Object(pair) => (
+                            pair.iter().find(|i| i.tag == 0),
+                            pair.iter().find(|i| i.tag == 1),
+                        ),
+                        _ => (None, None),
+                    };
+                    let (Some(key), Some(value)) = (key, value) else {
+                        return Err(Error::SchemaError("Map entry must be an object with key and value fields".to_string()));
+                    };
+                    let key = match self.htlv_to_json(key_type, &key.value)? {
+                        Value::String(key) => key,
+                        key => key.to_string(),
+                    };
+                    obj.insert(key, self.htlv_to_json(value_type, &value.value)?);
+                }
+                Ok(Value::Object(obj))
+            },
+            
+            // The first type in the union that accepts the value
+            (SchemaType::Union(types), value) => types
+                .iter()
+                .find_map(|t| self.htlv_to_json(t, value).ok())
+                .ok_or_else(|| Error::SchemaError(format!(
+                    "HTLV value of type {:?} does not match any type in union", value.value_type()
+                ))),
+            
+            (SchemaType::Ref(name), value) => match self.definitions.get(name) {
+                Some(definition) => self.htlv_to_json(definition, value),
+                None => Err(Error::SchemaError(format!("Unknown type reference '{}'", name))),
+            },
+            
+            (expected, actual) => Err(Error::SchemaError(format!(
+                "Type mismatch: expected {:?}, got HTLV {:?}", expected, actual.value_type()
+            ))),
+        }
+    }
+    
     /// Infers a schema type from a JSON value
     fn infer_schema_type(&self, json: &serde_json::Value) -> SchemaType {
         match json {
@@ -393,3 +490,121 @@ impl SchemaMapper {
         }
     }
 }
+
+/// Converts an item to JSON with field names, map keys and value types taken
+/// from the schema, as `SchemaMapper::htlv_to_json` does for its root type
+///
+/// The result converts back to the same item with `SchemaMapper::json_to_htlv`:
+/// binary values become base64 strings and maps, objects of tag-0/tag-1 entries
+/// in HTLV, become JSON objects. Fields unknown to the schema are left out.
+pub fn htlv_to_json(item: &HtlvItem, schema: &Schema) -> Result<serde_json::Value> {
+    let mut mapper = SchemaMapper::new();
+    mapper.set_definitions(schema);
+    mapper.htlv_to_json(&schema.root_type, &item.value)
+}
+
+/// Converts an item to JSON without a schema, for debugging payloads
+///
+/// Like `value_to_json`, objects are keyed by tag number, except that objects
+/// whose fields all look like map entries, objects of a tag-0 key and a tag-1
+/// value, become JSON objects keyed by the entry keys. This is a best effort:
+/// the result does not tell numeric types apart and cannot be converted back
+/// without a schema.
+pub fn htlv_to_json_schemaless(item: &HtlvItem) -> serde_json::Value {
+    schemaless_value_to_json(&item.value)
+}
+
+fn schemaless_value_to_json(value: &HtlvValue) -> serde_json::Value {
+    use serde_json::Value;
+    
+    match value {
+        HtlvValue::Array(items) => Value::Array(items.iter().map(|item| schemaless_value_to_json(&item.value)).collect()),
+        HtlvValue::Object(items) => match map_entries(items) {
+            Some(entries) => Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let key = match schemaless_value_to_json(key) {
+                            Value::String(key) => key,
+                            key => key.to_string(),
+                        };
+                        (key, schemaless_value_to_json(value))
+                    })
+                    .collect()
+            ),
+            None => Value::Object(
+                items.iter().map(|item| (item.tag.to_string(), schemaless_value_to_json(&item.value))).collect()
+            ),
+        },
+        value => value_to_json(value),
+    }
+}
+
+/// Returns the keys and values of the fields if they are all map entries
+fn map_entries(items: &[HtlvItem]) -> Option<Vec<(&HtlvValue, &HtlvValue)>> {
+    if items.is_empty() {
+        return None;
+    }
+    items
+        .iter()
+        .map(|item| match &item.value {
+            HtlvValue::Object(pair) if pair.len() == 2 => Some((
+                &pair.iter().find(|i| i.tag == 0)?.value,
+                &pair.iter().find(|i| i.tag == 1)?.value,
+            )),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Converts an HTLV value to JSON without a schema: objects are keyed by tag
+/// number, binary values become base64 strings and non-finite floats `null`;
+/// external references become objects with their locator, length and hex hash
+pub fn value_to_json(value: &HtlvValue) -> serde_json::Value {
+    use serde_json::Value;
+    
+    match value {
+        HtlvValue::Null => Value::Null,
+        HtlvValue::Bool(b) => Value::Bool(*b),
+        HtlvValue::U8(v) => Value::from(*v),
+        HtlvValue::U16(v) => Value::from(*v),
+        HtlvValue::U32(v) => Value::from(*v),
+        HtlvValue::U64(v) => Value::from(*v),
+        HtlvValue::I8(v) => Value::from(*v),
+        HtlvValue::I16(v) => Value::from(*v),
+        HtlvValue::I32(v) => Value::from(*v),
+        HtlvValue::I64(v) => Value::from(*v),
+        HtlvValue::F32(v) => serde_json::Number::from_f64(*v as f64).map_or(Value::Null, Value::Number),
+        HtlvValue::F64(v) => serde_json::Number::from_f64(*v).map_or(Value::Null, Value::Number),
+        HtlvValue::String(s) => Value::String(String::from_utf8_lossy(s).into_owned()),
+        HtlvValue::Bytes(b) | HtlvValue::Extension(_, b) => Value::String(base64::encode(b)),
+        HtlvValue::Array(items) => Value::Array(items.iter().map(|item| value_to_json(&item.value)).collect()),
+        HtlvValue::Object(items) => Value::Object(
+            items.iter().map(|item| (item.tag.to_string(), value_to_json(&item.value))).collect()
+        ),
+        HtlvValue::ExternalRef { locator, length, hash } => serde_json::json!({
+            "locator": locator,
+            "length": length,
+            "hash": hex::encode(hash),
+        }),
+    }
+}
+
+/// Normalizes a field name so that snake_case, camelCase, PascalCase and
+/// kebab-case spellings of the same name are equal
+fn normalize_name(name: &str) -> String {
+    name.chars()
+        .filter(|c| *c != '_' && *c != '-')
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+/// Returns true if a JSON value maps to the schema type without numeric coercion
+fn accepts_natively(schema_type: &SchemaType, json: &serde_json::Value) -> bool {
+    match json {
+        serde_json::Value::Number(n) if n.is_f64() => schema_type.is_float(),
+        serde_json::Value::Number(_) => schema_type.is_integer(),
+        serde_json::Value::String(_) => matches!(schema_type, SchemaType::String | SchemaType::Binary),
+        _ => !schema_type.is_numeric(),
+    }
+}
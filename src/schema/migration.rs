@@ -0,0 +1,272 @@
+// Data migration between versions of a Tonitru schema
+//
+// During a rolling upgrade, producers of two schema versions share the wire.
+// A `SchemaMigrator` rewrites items of the old version into items of the new
+// one, so consumers only handle the version they were built for. Fields are
+// carried over by tag, and `MigrationRules` cover what the tags do not say:
+//
+// - renames: a field of the new version takes its value from a differently
+//   tagged field of the old version
+// - defaults: the value of a field the old version does not have
+// - conversions: functions turning values of a changed type into the new type
+//
+// Numeric widenings (and any numeric change that keeps the value exact) are
+// applied without a rule, under the `CoercionRules` of the migrator. Fields
+// the new version does not declare are dropped. Paths are the dotted field
+// names used by `diff`, nested objects and arrays of objects included.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::internal::error::{Error, Result};
+use crate::schema::coercion::CoercionRules;
+use crate::schema::diff::describe_type;
+use crate::schema::mapper::{value_to_json, MapperConfig, SchemaMapper};
+use crate::schema::types::{Schema, SchemaField, SchemaRegistry, SchemaType, SchemaVersion};
+use crate::schema::utils::field_path;
+
+/// Converts a value of the old type of a field into its new type
+pub type ValueConverter = Arc<dyn Fn(&HtlvValue) -> Result<HtlvValue> + Send + Sync>;
+
+/// Rules for the changes between two schema versions that tags do not cover
+#[derive(Clone, Default)]
+pub struct MigrationRules {
+    /// Old field path, by the path of the new field taking its value
+    renames: HashMap<String, String>,
+    /// Values of fields missing from old items, by new field path
+    defaults: HashMap<String, HtlvValue>,
+    /// Conversions of changed types, by new field path
+    converters: HashMap<String, ValueConverter>,
+    /// Rules for numeric conversions without a converter
+    coercion: CoercionRules,
+}
+
+impl fmt::Debug for MigrationRules {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut converters: Vec<&String> = self.converters.keys().collect();
+        converters.sort();
+        f.debug_struct("MigrationRules")
+            .field("renames", &self.renames)
+            .field("defaults", &self.defaults)
+            .field("converters", &converters)
+            .field("coercion", &self.coercion)
+            .finish()
+    }
+}
+
+impl MigrationRules {
+    /// Creates empty rules: fields are carried over by tag and numbers are
+    /// only converted exactly
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the rules with the field at `new_path` taking the value of the
+    /// field at `old_path`; both fields must be in the same object
+    pub fn rename(mut self, old_path: &str, new_path: &str) -> Self {
+        self.renames.insert(new_path.to_string(), old_path.to_string());
+        self
+    }
+
+    /// Returns the rules with a value for the field at `path` when old items
+    /// lack it; takes precedence over the default of the schema
+    pub fn default_value(mut self, path: &str, value: HtlvValue) -> Self {
+        self.defaults.insert(path.to_string(), value);
+        self
+    }
+
+    /// Returns the rules with a conversion for the values of the field at `path`
+    pub fn convert(mut self, path: &str, converter: impl Fn(&HtlvValue) -> Result<HtlvValue> + Send + Sync + 'static) -> Self {
+        self.converters.insert(path.to_string(), Arc::new(converter));
+        self
+    }
+
+    /// Returns the rules with the coercion rules for numeric conversions,
+    /// e.g. `CoercionRules::lenient()` to allow narrowing
+    pub fn coercion(mut self, coercion: CoercionRules) -> Self {
+        self.coercion = coercion;
+        self
+    }
+}
+
+/// Rewrites items of one schema version into items of another
+#[derive(Debug)]
+pub struct SchemaMigrator {
+    from: Arc<Schema>,
+    to: Arc<Schema>,
+    rules: MigrationRules,
+    /// Numeric conversions, under the coercion rules
+    mapper: SchemaMapper,
+}
+
+impl SchemaMigrator {
+    /// Creates a migrator between two registered versions of a schema
+    pub fn new(
+        registry: &SchemaRegistry,
+        id: &str,
+        from: &SchemaVersion,
+        to: &SchemaVersion,
+        rules: MigrationRules,
+    ) -> Result<Self> {
+        let version = |version: &SchemaVersion| {
+            registry.get_schema_version(id, version).ok_or_else(|| {
+                Error::SchemaError(format!("Schema '{}' version {} is not registered", id, version))
+            })
+        };
+        Self::between(version(from)?, version(to)?, rules)
+    }
+
+    /// Creates a migrator from one schema to another; fails if a rule names a
+    /// field neither schema declares
+    pub fn between(from: Arc<Schema>, to: Arc<Schema>, rules: MigrationRules) -> Result<Self> {
+        for (new_path, old_path) in &rules.renames {
+            if find_field(&from, old_path).is_none() {
+                return Err(Error::SchemaError(format!("Renamed field '{}' is not in version {}", old_path, from.version)));
+            }
+            let parent = |path: &str| path.rsplit_once('.').map(|(parent, _)| parent.to_string());
+            if parent(old_path) != parent(new_path) {
+                return Err(Error::SchemaError(format!("Fields '{}' and '{}' are not in the same object", old_path, new_path)));
+            }
+        }
+        let paths = rules.renames.keys().chain(rules.defaults.keys()).chain(rules.converters.keys());
+        for path in paths {
+            if find_field(&to, path).is_none() {
+                return Err(Error::SchemaError(format!("Field '{}' is not in version {}", path, to.version)));
+            }
+        }
+
+        let mapper = SchemaMapper::with_config(MapperConfig { coercion: rules.coercion, ..MapperConfig::default() });
+        Ok(SchemaMigrator { from, to, rules, mapper })
+    }
+
+    /// Returns the schema items are migrated from
+    pub fn from_schema(&self) -> &Arc<Schema> {
+        &self.from
+    }
+
+    /// Returns the schema items are migrated to
+    pub fn to_schema(&self) -> &Arc<Schema> {
+        &self.to
+    }
+
+    /// Rewrites an item of the old version into an item of the new version
+    pub fn migrate(&self, item: &HtlvItem) -> Result<HtlvItem> {
+        let value = self.migrate_value(&item.value, &self.from.root_type, &self.to.root_type, "")?;
+        Ok(HtlvItem::new(item.tag, value))
+    }
+
+    fn migrate_value(&self, value: &HtlvValue, old_type: &SchemaType, new_type: &SchemaType, path: &str) -> Result<HtlvValue> {
+        if let Some(converter) = self.rules.converters.get(path) {
+            return converter(value);
+        }
+        let old_type = resolve(old_type, &self.from)?;
+        let new_type = resolve(new_type, &self.to)?;
+
+        match (value, old_type, new_type) {
+            (HtlvValue::Object(items), SchemaType::Object(old_fields), SchemaType::Object(new_fields)) => {
+                self.migrate_object(items, old_fields, new_fields, path).map(HtlvValue::Object)
+            }
+            (HtlvValue::Array(items), SchemaType::Array(old_element), SchemaType::Array(new_element)) => items
+                .iter()
+                .map(|item| Ok(HtlvItem::new(item.tag, self.migrate_value(&item.value, old_element, new_element, path)?)))
+                .collect::<Result<Vec<_>>>()
+                .map(HtlvValue::Array),
+            _ if old_type == new_type => Ok(value.clone()),
+            (_, _, new_type) if new_type.is_numeric() && is_number(value) => {
+                self.mapper.json_to_htlv(new_type, &value_to_json(value)).map_err(|e| {
+                    Error::SchemaError(format!("Cannot migrate field '{}' to {}: {}", path, describe_type(new_type), e))
+                })
+            }
+            (_, old_type, new_type) => Err(Error::SchemaError(format!(
+                "No migration rule for field '{}' changing from {} to {}",
+                path,
+                describe_type(old_type),
+                describe_type(new_type)
+            ))),
+        }
+    }
+
+    fn migrate_object(&self, items: &[HtlvItem], old_fields: &[SchemaField], new_fields: &[SchemaField], prefix: &str) -> Result<Vec<HtlvItem>> {
+        // Old fields renamed to another field only feed that field
+        let renamed_away: Vec<&str> = new_fields
+            .iter()
+            .filter_map(|field| self.rules.renames.get(&field_path(prefix, &field.name)))
+            .map(|old_path| old_path.rsplit('.').next().unwrap_or(old_path))
+            .collect();
+
+        let mut migrated = Vec::with_capacity(new_fields.len());
+        for new_field in new_fields {
+            let path = field_path(prefix, &new_field.name);
+            let old_field = match self.rules.renames.get(&path) {
+                Some(old_path) => {
+                    let old_name = old_path.rsplit('.').next().unwrap_or(old_path);
+                    old_fields.iter().find(|field| field.name == old_name)
+                }
+                None => old_fields
+                    .iter()
+                    .find(|field| field.tag == new_field.tag && !renamed_away.contains(&field.name.as_str())),
+            };
+            let old_item = old_field.and_then(|old_field| {
+                items.iter().find(|item| item.tag == old_field.tag).map(|item| (old_field, item))
+            });
+
+            let value = match old_item {
+                Some((old_field, item)) => self.migrate_value(&item.value, &old_field.field_type, &new_field.field_type, &path)?,
+                None => match self.rules.defaults.get(&path).or(new_field.default_value.as_ref()) {
+                    Some(default) => default.clone(),
+                    None if new_field.required => {
+                        return Err(Error::SchemaError(format!(
+                            "Required field '{}' has no value in version {} and no default",
+                            path, self.from.version
+                        )));
+                    }
+                    None => continue,
+                },
+            };
+            migrated.push(HtlvItem::new(new_field.tag, value));
+        }
+        Ok(migrated)
+    }
+}
+
+/// Follows a type reference to its definition in the schema
+fn resolve<'a>(schema_type: &'a SchemaType, schema: &'a Schema) -> Result<&'a SchemaType> {
+    match schema_type {
+        SchemaType::Ref(name) => schema
+            .definitions
+            .get(name)
+            .ok_or_else(|| Error::SchemaError(format!("Unknown type reference '{}'", name))),
+        schema_type => Ok(schema_type),
+    }
+}
+
+/// Returns the field at a dotted path of a schema
+fn find_field<'a>(schema: &'a Schema, path: &str) -> Option<&'a SchemaField> {
+    let mut schema_type = &schema.root_type;
+    let mut field = None;
+    for name in path.split('.') {
+        let fields = match resolve(schema_type, schema).ok()? {
+            SchemaType::Object(fields) => fields,
+            SchemaType::Array(element) => match resolve(element, schema).ok()? {
+                SchemaType::Object(fields) => fields,
+                _ => return None,
+            },
+            _ => return None,
+        };
+        let found = fields.iter().find(|candidate| candidate.name == name)?;
+        schema_type = &found.field_type;
+        field = Some(found);
+    }
+    field
+}
+
+fn is_number(value: &HtlvValue) -> bool {
+    matches!(
+        value,
+        HtlvValue::U8(_) | HtlvValue::U16(_) | HtlvValue::U32(_) | HtlvValue::U64(_)
+            | HtlvValue::I8(_) | HtlvValue::I16(_) | HtlvValue::I32(_) | HtlvValue::I64(_)
+            | HtlvValue::F32(_) | HtlvValue::F64(_)
+    )
+}
@@ -8,14 +8,37 @@
 // 3. Schema to HTLV structure mapping rules
 // 4. JSON-like Schema parser
 // 5. Type inference logic
+// 6. Schema usage analytics from decoded traffic
+// 7. Schema linting for wire-format anti-patterns
+// 8. Field lifecycle (deprecation and removal) checks between versions
+// 9. Security labels and field access policies
+// 10. Numeric type coercion rules for mapping third-party JSON
+// 11. Structural diffs between schema versions
+// 12. Precompiled validation plans for high-throughput validation
+// 13. Sampled validation with violation rates for hot paths
+// 14. Schema-aware decoding into documents with named fields
+// 15. Protobuf (.proto) schema import
+// 16. Compatibility checks classifying changes between versions
+// 17. Data migration of items between schema versions
 
 // Re-export public types and functions
 pub use self::types::{Schema, SchemaType, SchemaField, SchemaOptions};
 pub use self::defaults::DefaultValueStrategy;
-pub use self::mapper::SchemaMapper;
+pub use self::mapper::{SchemaMapper, htlv_to_json, htlv_to_json_schemaless};
 pub use self::parser::SchemaParser;
 pub use self::inference::SchemaInference;
-pub use self::validator::SchemaValidator;
+pub use self::validator::{SchemaValidator, ValidationWarning};
+pub use self::usage::{SchemaUsageCollector, SchemaUsageReport};
+pub use self::lint::{SchemaLinter, LintReport, LintSeverity};
+pub use self::lifecycle::{check_lifecycle, LifecycleIssue};
+pub use self::policy::{PolicyEngine, Clearance, SecurityLabel};
+pub use self::coercion::CoercionRules;
+pub use self::diff::{diff, SchemaDiff, FieldChange};
+pub use self::compiled::CompiledSchema;
+pub use self::sampling::{SamplingValidator, SamplingStats};
+pub use self::decoder::{SchemaDecoder, DecodedDocument};
+pub use self::compatibility::{check_compatibility, ChangeLevel, CompatibilityReport};
+pub use self::migration::{SchemaMigrator, MigrationRules};
 
 // Sub-modules
 pub mod types;
@@ -24,6 +47,22 @@ pub mod mapper;
 pub mod parser;
 pub mod inference;
 pub mod validator;
+pub mod usage;
+pub mod lint;
+pub mod lifecycle;
+pub mod policy;
+pub mod coercion;
+pub mod diff;
+pub mod compiled;
+pub mod sampling;
+pub mod decoder;
+pub mod proto;
+pub mod compatibility;
+pub mod migration;
 
 // Internal module for shared utilities
-mod utils;
\ No newline at end of file
+mod utils;
+
+// Schema fixtures for unit tests
+#[cfg(test)]
+pub(crate) mod testing;
@@ -9,21 +9,47 @@ use serde_json::Value;
 
 use crate::internal::error::{Error, Result};
 use crate::codec::types::HtlvValue;
-use crate::schema::types::{Schema, SchemaType, SchemaField, SchemaOptions, SchemaVersion};
+use crate::schema::types::{Schema, SchemaType, SchemaField, SchemaOptions, SchemaVersion, SchemaRegistry, version_key};
+use crate::schema::policy::SecurityLabel;
+use crate::schema::proto;
+
+/// Prefix of references to the local definitions of a schema
+const LOCAL_REF_PREFIX: &str = "#/definitions/";
+
+/// Prefix of references to other schemas by fingerprint
+const FINGERPRINT_REF_PREFIX: &str = "fingerprint:";
 
 /// Parser for JSON-like Schema definitions
+///
+/// Types can be given inline or by `$ref`:
+/// - `#/definitions/<name>` refers to an entry of the `definitions` object of
+///   the schema being parsed; definitions are inlined, except those referring
+///   to themselves, which become named types (`SchemaType::Ref`)
+/// - `fingerprint:<hex>` refers to a referenced schema by fingerprint
+/// - any other value refers to a referenced schema by ID
 #[derive(Debug, Default)]
 pub struct SchemaParser {
     /// Custom type mappings (JSON schema type name -> Tonitru schema type)
     custom_type_mappings: HashMap<String, SchemaType>,
+    /// Root types of other schemas, by ID and by `fingerprint:<hex>`
+    referenced_types: HashMap<String, SchemaType>,
+}
+
+/// State of the parsing of one schema
+#[derive(Debug, Default)]
+struct ParseContext {
+    /// Local definitions of the schema being parsed
+    definitions: serde_json::Map<String, Value>,
+    /// Local definitions being resolved, to detect reference cycles
+    resolving: Vec<String>,
+    /// Local definitions that refer to themselves, kept as named types
+    recursive_types: HashMap<String, Option<SchemaType>>,
 }
 
 impl SchemaParser {
     /// Creates a new schema parser
     pub fn new() -> Self {
-        Self {
-            custom_type_mappings: HashMap::new(),
-        }
+        Self::default()
     }
     
     /// Adds a custom type mapping
@@ -31,6 +57,38 @@ impl SchemaParser {
         self.custom_type_mappings.insert(json_type.to_string(), schema_type);
     }
     
+    /// Makes a schema available to `$ref` by ID and by fingerprint
+    pub fn add_referenced_schema(&mut self, schema: &Schema) {
+        self.referenced_types.insert(
+            format!("{}{}", FINGERPRINT_REF_PREFIX, schema.fingerprint()),
+            schema.root_type.clone(),
+        );
+        self.referenced_types.insert(schema.id.clone(), schema.root_type.clone());
+    }
+    
+    /// Makes all schemas of a registry available to `$ref`; references by ID
+    /// resolve to the latest version
+    pub fn add_registry(&mut self, registry: &SchemaRegistry) {
+        for schema in registry.iter() {
+            self.referenced_types
+                .entry(format!("{}{}", FINGERPRINT_REF_PREFIX, schema.fingerprint()))
+                .or_insert_with(|| schema.root_type.clone());
+            self.referenced_types
+                .entry(schema.id.clone())
+                .or_insert_with(|| schema.root_type.clone());
+        }
+    }
+    
+    /// Parses a proto3 `.proto` file into a Tonitru Schema
+    ///
+    /// The first top-level message becomes the root type and field numbers
+    /// become HTLV tags. Custom type mappings apply to protobuf type names,
+    /// and types from imported files resolve to referenced schemas by their
+    /// fully qualified name.
+    pub fn parse_proto(&self, source: &str) -> Result<Schema> {
+        proto::parse_proto(source, &self.custom_type_mappings, &self.referenced_types)
+    }
+    
     /// Parses a JSON schema definition into a Tonitru Schema
     pub fn parse_schema(&self, json: &Value) -> Result<Schema> {
         // Validate that the input is an object
@@ -39,6 +97,24 @@ impl SchemaParser {
             _ => return Err(Error::SchemaError("Schema must be a JSON object".to_string())),
         };
         
+        // Local definitions are only visible while parsing this schema
+        let definitions = match obj.get("definitions") {
+            Some(Value::Object(definitions)) => definitions.clone(),
+            Some(_) => return Err(Error::SchemaError("Schema definitions must be an object".to_string())),
+            None => serde_json::Map::new(),
+        };
+        let mut cx = ParseContext { definitions, ..ParseContext::default() };
+        let mut schema = self.parse_schema_object(obj, &mut cx)?;
+        for (name, definition) in cx.recursive_types {
+            if let Some(definition) = definition {
+                schema.definitions.insert(name, definition);
+            }
+        }
+        schema.check_definitions()?;
+        Ok(schema)
+    }
+    
+    fn parse_schema_object(&self, obj: &serde_json::Map<String, Value>, cx: &mut ParseContext) -> Result<Schema> {
         // Extract required fields
         let id = self.get_string_field(obj, "id")?;
         let name = self.get_string_field(obj, "name")?;
@@ -53,10 +129,10 @@ impl SchemaParser {
         
         // Parse root type
         let root_type = if let Some(type_value) = obj.get("type") {
-            self.parse_type(type_value, obj)?
+            self.parse_type(type_value, obj, cx)?
         } else if let Some(properties) = obj.get("properties") {
             // If no type is specified but properties are present, assume it's an object
-            self.parse_object_type(properties)?
+            self.parse_object_type(properties, cx)?
         } else {
             return Err(Error::SchemaError("Schema must specify a type or properties".to_string()));
         };
@@ -122,7 +198,7 @@ impl SchemaParser {
     }
     
     /// Parses a type definition into a SchemaType
-    fn parse_type(&self, type_value: &Value, schema_obj: &serde_json::Map<String, Value>) -> Result<SchemaType> {
+    fn parse_type(&self, type_value: &Value, schema_obj: &serde_json::Map<String, Value>, cx: &mut ParseContext) -> Result<SchemaType> {
         match type_value {
             Value::String(type_name) => {
                 // Check for custom type mapping
@@ -178,7 +254,7 @@ impl SchemaParser {
                     "array" => {
                         // Parse array items type
                         if let Some(items) = schema_obj.get("items") {
-                            let item_type = self.parse_type(items, schema_obj)?;
+                            let item_type = self.parse_type(items, schema_obj, cx)?;
                             Ok(SchemaType::Array(Box::new(item_type)))
                         } else {
                             Err(Error::SchemaError("Array schema must specify 'items'".to_string()))
@@ -187,7 +263,7 @@ impl SchemaParser {
                     "object" => {
                         // Parse object properties
                         if let Some(properties) = schema_obj.get("properties") {
-                            self.parse_object_type(properties)
+                            self.parse_object_type(properties, cx)
                         } else {
                             // Empty object
                             Ok(SchemaType::Object(Vec::new()))
@@ -200,19 +276,21 @@ impl SchemaParser {
                 // Union type (multiple possible types)
                 let mut union_types = Vec::new();
                 for t in types {
-                    let schema_type = self.parse_type(t, schema_obj)?;
+                    let schema_type = self.parse_type(t, schema_obj, cx)?;
                     union_types.push(schema_type);
                 }
                 Ok(SchemaType::Union(union_types))
             },
             Value::Object(obj) => {
                 // Complex type definition
-                if let Some(Value::String(type_name)) = obj.get("type") {
+                if let Some(reference) = obj.get("$ref") {
+                    self.resolve_ref(reference, cx)
+                } else if let Some(Value::String(type_name)) = obj.get("type") {
                     match type_name.as_str() {
                         "array" => {
                             // Parse array items type
                             if let Some(items) = obj.get("items") {
-                                let item_type = self.parse_type(items, obj)?;
+                                let item_type = self.parse_type(items, obj, cx)?;
                                 Ok(SchemaType::Array(Box::new(item_type)))
                             } else {
                                 Err(Error::SchemaError("Array schema must specify 'items'".to_string()))
@@ -221,7 +299,7 @@ impl SchemaParser {
                         "object" => {
                             // Parse object properties
                             if let Some(properties) = obj.get("properties") {
-                                self.parse_object_type(properties)
+                                self.parse_object_type(properties, cx)
                             } else {
                                 // Empty object
                                 Ok(SchemaType::Object(Vec::new()))
@@ -230,25 +308,28 @@ impl SchemaParser {
                         "map" => {
                             // Parse map key and value types
                             let key_type = if let Some(keys) = obj.get("keys") {
-                                self.parse_type(keys, obj)?
+                                self.parse_type(keys, obj, cx)?
                             } else {
                                 // Default to string keys
                                 SchemaType::String
                             };
                             
                             let value_type = if let Some(values) = obj.get("values") {
-                                self.parse_type(values, obj)?
+                                self.parse_type(values, obj, cx)?
                             } else {
                                 return Err(Error::SchemaError("Map schema must specify 'values'".to_string()));
                             };
                             
                             Ok(SchemaType::Map(Box::new(key_type), Box::new(value_type)))
                         },
-                        _ => self.parse_type(&Value::String(type_name.clone()), obj),
+                        _ => self.parse_type(&Value::String(type_name.clone()), obj, cx),
                     }
+                } else if let Some(types @ Value::Array(_)) = obj.get("type") {
+                    // Union of the listed types
+                    self.parse_type(types, obj, cx)
                 } else {
                     // Assume it's an object with inline properties
-                    self.parse_object_type(type_value)
+                    self.parse_object_type(type_value, cx)
                 }
             },
             _ => Err(Error::SchemaError(format!("Invalid type definition: {:?}", type_value))),
@@ -256,7 +337,7 @@ impl SchemaParser {
     }
     
     /// Parses an object type definition
-    fn parse_object_type(&self, properties: &Value) -> Result<SchemaType> {
+    fn parse_object_type(&self, properties: &Value, cx: &mut ParseContext) -> Result<SchemaType> {
         let props = match properties {
             Value::Object(obj) => obj,
             _ => return Err(Error::SchemaError("Properties must be an object".to_string())),
@@ -271,8 +352,10 @@ impl SchemaParser {
             };
             
             // Parse field type
-            let field_type = if let Some(type_value) = prop_obj.get("type") {
-                self.parse_type(type_value, prop_obj)?
+            let field_type = if let Some(reference) = prop_obj.get("$ref") {
+                self.resolve_ref(reference, cx)?
+            } else if let Some(type_value) = prop_obj.get("type") {
+                self.parse_type(type_value, prop_obj, cx)?
             } else {
                 return Err(Error::SchemaError(format!("Property '{}' must specify a type", name)));
             };
@@ -309,6 +392,19 @@ impl SchemaParser {
                 None
             };
             
+            // Parse aliases
+            let mut aliases = Vec::new();
+            if let Some(Value::Array(alias_values)) = prop_obj.get("aliases") {
+                for alias in alias_values {
+                    match alias {
+                        Value::String(alias) if alias != name => aliases.push(alias.clone()),
+                        _ => {
+                            return Err(Error::SchemaError(format!("Invalid alias for property '{}': {:?}", name, alias)));
+                        }
+                    }
+                }
+            }
+            
             // Parse description
             let description = if let Some(Value::String(desc)) = prop_obj.get("description") {
                 Some(desc.clone())
@@ -369,10 +465,44 @@ impl SchemaParser {
                     }
                 }
             }
+
+            // Parse security labels
+            if let Some(Value::Array(labels)) = prop_obj.get("labels") {
+                for label in labels {
+                    if let Value::String(label) = label {
+                        options.labels.push(SecurityLabel::parse(label));
+                    } else {
+                        return Err(Error::SchemaError(format!("Invalid label for property '{}': {:?}", name, label)));
+                    }
+                }
+            }
+
+            // Parse lifecycle attributes
+            if let Some(Value::Bool(deprecated)) = prop_obj.get("deprecated") {
+                options.deprecated = *deprecated;
+            }
+
+            if let Some(since_version) = prop_obj.get("sinceVersion") {
+                options.since_version = Some(self.parse_version(since_version)?);
+            }
+
+            if let Some(removed_in) = prop_obj.get("removedIn") {
+                options.removed_in = Some(self.parse_version(removed_in)?);
+            }
+
+            if let (Some(since), Some(removed)) = (&options.since_version, &options.removed_in) {
+                if version_key(removed) <= version_key(since) {
+                    return Err(Error::SchemaError(format!(
+                        "Property '{}' is removed in version {} before it is introduced in version {}",
+                        name, removed, since
+                    )));
+                }
+            }
             
             // Create the field
             let field = SchemaField {
                 name: name.clone(),
+                aliases,
                 tag,
                 field_type,
                 required,
@@ -384,9 +514,66 @@ impl SchemaParser {
             fields.push(field);
         }
         
+        // Names and aliases must each identify a single field
+        let mut names: HashMap<&str, &str> = HashMap::new();
+        for field in &fields {
+            for name in std::iter::once(&field.name).chain(&field.aliases) {
+                if let Some(other) = names.insert(name, &field.name) {
+                    if other != field.name {
+                        return Err(Error::SchemaError(format!(
+                            "Name '{}' is used by both properties '{}' and '{}'", name, other, field.name
+                        )));
+                    }
+                }
+            }
+        }
+        
         Ok(SchemaType::Object(fields))
     }
     
+    /// Resolves a `$ref` to a local definition or a referenced schema
+    fn resolve_ref(&self, reference: &Value, cx: &mut ParseContext) -> Result<SchemaType> {
+        let reference = match reference {
+            Value::String(reference) => reference,
+            _ => return Err(Error::SchemaError(format!("Invalid $ref: {:?}, expected a string", reference))),
+        };
+        
+        let name = match reference.strip_prefix(LOCAL_REF_PREFIX) {
+            Some(name) => name,
+            None => {
+                return self.referenced_types.get(reference).cloned().ok_or_else(|| {
+                    Error::SchemaError(format!("Unknown schema reference '{}'", reference))
+                });
+            }
+        };
+        
+        let definition = cx.definitions.get(name).cloned().ok_or_else(|| {
+            Error::SchemaError(format!("Unknown definition '{}'", name))
+        })?;
+        
+        // A definition referring to itself becomes a named type of the schema
+        if cx.recursive_types.contains_key(name) || cx.resolving.iter().any(|resolving| resolving == name) {
+            cx.recursive_types.entry(name.to_string()).or_insert(None);
+            return Ok(SchemaType::Ref(name.to_string()));
+        }
+        
+        cx.resolving.push(name.to_string());
+        let result = match &definition {
+            Value::Object(definition_obj) => self.parse_type(&definition, definition_obj, cx),
+            _ => self.parse_type(&definition, &serde_json::Map::new(), cx),
+        };
+        cx.resolving.pop();
+        
+        let schema_type = result?;
+        match cx.recursive_types.get_mut(name) {
+            Some(named_type) => {
+                *named_type = Some(schema_type);
+                Ok(SchemaType::Ref(name.to_string()))
+            },
+            None => Ok(schema_type),
+        }
+    }
+    
     /// Helper to get a string field from a JSON object
     fn get_string_field(&self, obj: &serde_json::Map<String, Value>, field: &str) -> Result<String> {
         match obj.get(field) {
@@ -0,0 +1,333 @@
+// Field access policies for Tonitru schemas
+//
+// Schema fields carry security labels (e.g. "pii", "secret"). A `PolicyEngine`
+// evaluates those labels against the clearance of a caller and decides, per
+// field, whether the value is passed through, redacted (replaced by the zero
+// value of its type, so the result still matches the schema) or dropped. The
+// same labels decide which fields are subject to field-level encryption.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::internal::error::{Error, Result};
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::schema::types::{Schema, SchemaType, SchemaField};
+use crate::schema::defaults::DefaultValueStrategy;
+
+/// Security label attached to a schema field
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SecurityLabel {
+    /// Visible to every caller
+    Public,
+    /// Visible inside the organization
+    Internal,
+    /// Personally identifiable information
+    Pii,
+    /// Credentials, keys and similar secrets
+    Secret,
+    /// Application-defined label
+    Custom(String),
+}
+
+impl SecurityLabel {
+    /// Parses a label name; unknown names become custom labels
+    pub fn parse(name: &str) -> Self {
+        match name.to_ascii_lowercase().as_str() {
+            "public" => SecurityLabel::Public,
+            "internal" => SecurityLabel::Internal,
+            "pii" => SecurityLabel::Pii,
+            "secret" => SecurityLabel::Secret,
+            _ => SecurityLabel::Custom(name.to_string()),
+        }
+    }
+
+    /// Returns the name of the label
+    pub fn name(&self) -> &str {
+        match self {
+            SecurityLabel::Public => "public",
+            SecurityLabel::Internal => "internal",
+            SecurityLabel::Pii => "pii",
+            SecurityLabel::Secret => "secret",
+            SecurityLabel::Custom(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for SecurityLabel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// The set of labels a caller is allowed to see
+///
+/// `Public` fields are always visible, whatever the clearance.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Clearance {
+    labels: HashSet<SecurityLabel>,
+}
+
+impl Clearance {
+    /// Creates a clearance for public fields only
+    pub fn public() -> Self {
+        Self::default()
+    }
+
+    /// Returns the clearance with an additional label granted
+    pub fn with(mut self, label: SecurityLabel) -> Self {
+        self.grant(label);
+        self
+    }
+
+    /// Grants a label
+    pub fn grant(&mut self, label: SecurityLabel) {
+        self.labels.insert(label);
+    }
+
+    /// Returns true if fields with the label are visible
+    pub fn allows(&self, label: &SecurityLabel) -> bool {
+        *label == SecurityLabel::Public || self.labels.contains(label)
+    }
+}
+
+/// What happens to a field the caller is not cleared for
+///
+/// Ordered from the least to the most restrictive; a field with several
+/// labels gets the most restrictive action.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum PolicyAction {
+    /// The value is passed through
+    Allow,
+    /// The value is replaced by the zero value of its type
+    Redact,
+    /// The field is removed
+    Drop,
+}
+
+/// Evaluates field security labels against the clearance of a caller
+#[derive(Debug, Clone)]
+pub struct PolicyEngine {
+    clearance: Clearance,
+    /// Actions for labels the caller is not cleared for
+    actions: HashMap<SecurityLabel, PolicyAction>,
+    /// Action for labels without an entry in `actions`
+    default_action: PolicyAction,
+    /// Labels whose fields are encrypted on the wire
+    encrypted_labels: HashSet<SecurityLabel>,
+}
+
+impl PolicyEngine {
+    /// Creates a policy engine for a caller
+    ///
+    /// By default, `Secret` fields are dropped and other fields the caller is
+    /// not cleared for are redacted; `Pii` and `Secret` fields are encrypted.
+    pub fn new(clearance: Clearance) -> Self {
+        let mut actions = HashMap::new();
+        actions.insert(SecurityLabel::Secret, PolicyAction::Drop);
+
+        let mut encrypted_labels = HashSet::new();
+        encrypted_labels.insert(SecurityLabel::Pii);
+        encrypted_labels.insert(SecurityLabel::Secret);
+
+        Self {
+            clearance,
+            actions,
+            default_action: PolicyAction::Redact,
+            encrypted_labels,
+        }
+    }
+
+    /// Sets the action for fields with a label the caller is not cleared for
+    pub fn set_action(&mut self, label: SecurityLabel, action: PolicyAction) {
+        self.actions.insert(label, action);
+    }
+
+    /// Sets the action for labels without a specific action
+    pub fn set_default_action(&mut self, action: PolicyAction) {
+        self.default_action = action;
+    }
+
+    /// Sets whether fields with a label are encrypted on the wire
+    pub fn set_encrypted(&mut self, label: SecurityLabel, encrypted: bool) {
+        if encrypted {
+            self.encrypted_labels.insert(label);
+        } else {
+            self.encrypted_labels.remove(&label);
+        }
+    }
+
+    /// Returns the clearance of the caller
+    pub fn clearance(&self) -> &Clearance {
+        &self.clearance
+    }
+
+    /// Returns the action to take for a field
+    pub fn action_for(&self, field: &SchemaField) -> PolicyAction {
+        field
+            .options
+            .labels
+            .iter()
+            .filter(|label| !self.clearance.allows(label))
+            .map(|label| *self.actions.get(label).unwrap_or(&self.default_action))
+            .max()
+            .unwrap_or(PolicyAction::Allow)
+    }
+
+    /// Returns true if a field must be encrypted, either because the schema says
+    /// so or because of one of its labels
+    pub fn requires_encryption(&self, field: &SchemaField) -> bool {
+        field.options.encrypt || field.options.labels.iter().any(|label| self.encrypted_labels.contains(label))
+    }
+
+    /// Returns the tags of the fields that must be encrypted, e.g. to build a
+    /// field encryption policy
+    pub fn encrypted_tags(&self, schema: &Schema) -> Vec<u64> {
+        let mut tags = Vec::new();
+        collect_encrypted_tags(self, &schema.root_type, &mut tags);
+
+        let mut names: Vec<&String> = schema.definitions.keys().collect();
+        names.sort();
+        for name in names {
+            collect_encrypted_tags(self, &schema.definitions[name], &mut tags);
+        }
+        tags.sort_unstable();
+        tags.dedup();
+        tags
+    }
+
+    /// Returns a copy of a decoded item with the fields the caller is not
+    /// cleared for redacted or dropped
+    pub fn apply(&self, schema: &Schema, item: &HtlvItem) -> Result<HtlvItem> {
+        Ok(HtlvItem::new(item.tag, self.filter_value(&schema.root_type, &item.value, &schema.definitions)?))
+    }
+
+    /// Applies the policy to a value of the given schema type
+    ///
+    /// The type must not contain references to named types; use `apply` to
+    /// resolve them through the definitions of a schema.
+    pub fn apply_value(&self, schema_type: &SchemaType, value: &HtlvValue) -> Result<HtlvValue> {
+        self.filter_value(schema_type, value, &HashMap::new())
+    }
+
+    fn filter_value(
+        &self,
+        schema_type: &SchemaType,
+        value: &HtlvValue,
+        definitions: &HashMap<String, SchemaType>,
+    ) -> Result<HtlvValue> {
+        match (schema_type, value) {
+            (SchemaType::Object(fields), HtlvValue::Object(items)) => {
+                let mut filtered = Vec::with_capacity(items.len());
+                for item in items {
+                    match fields.iter().find(|field| field.tag == item.tag) {
+                        Some(field) => {
+                            if let Some(value) = self.apply_field(field, &item.value, definitions)? {
+                                filtered.push(HtlvItem::new(item.tag, value));
+                            }
+                        }
+                        // Fields unknown to the schema carry no labels
+                        None => filtered.push(item.clone()),
+                    }
+                }
+                Ok(HtlvValue::Object(filtered))
+            }
+            (SchemaType::Array(element_type), HtlvValue::Array(items)) => {
+                let mut filtered = Vec::with_capacity(items.len());
+                for item in items {
+                    filtered.push(HtlvItem::new(item.tag, self.filter_value(element_type, &item.value, definitions)?));
+                }
+                Ok(HtlvValue::Array(filtered))
+            }
+            (SchemaType::Ref(name), value) => match definitions.get(name) {
+                Some(definition) => self.filter_value(definition, value, definitions),
+                None => Err(Error::SchemaError(format!("Unknown type reference '{}'", name))),
+            },
+            _ => Ok(value.clone()),
+        }
+    }
+
+    /// Applies the policy to the value of a field; returns `None` if the field is dropped
+    fn apply_field(
+        &self,
+        field: &SchemaField,
+        value: &HtlvValue,
+        definitions: &HashMap<String, SchemaType>,
+    ) -> Result<Option<HtlvValue>> {
+        match self.action_for(field) {
+            PolicyAction::Allow => Ok(Some(self.filter_value(&field.field_type, value, definitions)?)),
+            PolicyAction::Redact => Ok(Some(redacted_value(&field.field_type, definitions)?)),
+            PolicyAction::Drop => Ok(None),
+        }
+    }
+}
+
+/// Returns the value replacing a redacted field of the given type: the zero
+/// value of scalars, and empty containers. Type references are resolved
+/// through the definitions.
+pub(crate) fn redacted_value(schema_type: &SchemaType, definitions: &HashMap<String, SchemaType>) -> Result<HtlvValue> {
+    let mut resolved = schema_type;
+    // A chain of references longer than the definitions is a cycle
+    for _ in 0..=definitions.len() {
+        match resolved {
+            SchemaType::Ref(name) => {
+                resolved = definitions.get(name)
+                    .ok_or_else(|| Error::SchemaError(format!("Unknown type reference '{}'", name)))?;
+            }
+            _ => return DefaultValueStrategy::None.apply_defaults(resolved, None),
+        }
+    }
+    Err(Error::SchemaError("Type references form a cycle without a type".to_string()))
+}
+
+fn collect_encrypted_tags(engine: &PolicyEngine, schema_type: &SchemaType, tags: &mut Vec<u64>) {
+    match schema_type {
+        SchemaType::Object(fields) => {
+            for field in fields {
+                if engine.requires_encryption(field) {
+                    tags.push(field.tag);
+                }
+                collect_encrypted_tags(engine, &field.field_type, tags);
+            }
+        }
+        SchemaType::Array(element_type) => collect_encrypted_tags(engine, element_type, tags),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::schema::testing::{self, field};
+    use crate::schema::types::SchemaOptions;
+
+    #[test]
+    fn test_redacts_labelled_recursive_field() {
+        // A tree node whose children are labelled
+        let pii = SchemaOptions { labels: vec![SecurityLabel::Pii], ..Default::default() };
+        let node = SchemaType::Object(vec![
+            field("name", 1, SchemaType::String, SchemaOptions::default()),
+            field("child", 2, SchemaType::Ref("Node".to_string()), pii),
+        ]);
+        let mut schema = testing::schema("tree", "Tree", SchemaType::Ref("Node".to_string()));
+        schema.definitions.insert("Node".to_string(), node);
+
+        let leaf = HtlvItem::new(0, HtlvValue::Object(vec![HtlvItem::new(1, HtlvValue::String("leaf".into()))]));
+        let root = HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String("root".into())),
+            HtlvItem::new(2, leaf.value.clone()),
+        ]));
+
+        let redacted = PolicyEngine::new(Clearance::public()).apply(&schema, &root).unwrap();
+        assert_eq!(redacted, HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(1, HtlvValue::String("root".into())),
+            HtlvItem::new(2, HtlvValue::Object(Vec::new())),
+        ])));
+        let cleared = PolicyEngine::new(Clearance::public().with(SecurityLabel::Pii));
+        assert_eq!(cleared.apply(&schema, &root).unwrap(), root);
+
+        // A reference to itself has no value to redact to
+        let mut looping = HashMap::new();
+        looping.insert("Loop".to_string(), SchemaType::Ref("Loop".to_string()));
+        assert!(redacted_value(&SchemaType::Ref("Loop".to_string()), &looping).is_err());
+    }
+}
@@ -0,0 +1,574 @@
+// Protobuf schema import for Tonitru
+//
+// This module reads the proto3 subset of the protobuf language into Tonitru
+// schemas, so services that already maintain .proto files need not restate
+// them in the JSON-like schema language. Field numbers become HTLV tags and
+// protobuf types map to schema types:
+//
+//   double, float                         Float64, Float32
+//   int32, sint32, sfixed32, enums        Int32
+//   int64, sint64, sfixed64               Int64
+//   uint32, fixed32 / uint64, fixed64     UInt32 / UInt64
+//   bool, string, bytes                   Boolean, String, Binary
+//   messages                              Object, inlined unless recursive
+//   repeated T, map<K, V>                 Array(T), Map(K, V)
+//
+// The first top-level message of the file is the root type of the schema.
+// Nested messages and enums, `oneof` groups, `reserved` ranges and the
+// `deprecated` field option are understood; other options, imports and
+// services are skipped. Types from imported files resolve through the custom
+// type mappings and the referenced schemas of the `SchemaParser`.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::internal::error::{Error, Result};
+use crate::schema::types::{Schema, SchemaField, SchemaOptions, SchemaType, SchemaVersion};
+
+/// Largest field number protobuf allows
+const MAX_FIELD_NUMBER: u64 = (1 << 29) - 1;
+
+/// Metadata key of the protobuf package of an imported schema
+pub const PROTO_PACKAGE_METADATA: &str = "proto_package";
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    Int(u64),
+    Str(String),
+    Symbol(char),
+}
+
+/// Splits a .proto file into tokens with their line numbers
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    let mut line = 1;
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => line += 1,
+            c if c.is_whitespace() => {}
+            '/' if chars.peek() == Some(&'/') => {
+                while chars.next_if(|c| *c != '\n').is_some() {}
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                chars.next();
+                let mut previous = ' ';
+                loop {
+                    match chars.next() {
+                        Some('/') if previous == '*' => break,
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            previous = c;
+                        }
+                        None => return Err(proto_error(line, "Unterminated comment")),
+                    }
+                }
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some(end) if end == c => break,
+                        Some('\\') => value.extend(chars.next()),
+                        Some('\n') | None => return Err(proto_error(line, "Unterminated string")),
+                        Some(c) => value.push(c),
+                    }
+                }
+                tokens.push((Token::Str(value), line));
+            }
+            c if c.is_ascii_digit() => {
+                let mut literal = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_ascii_alphanumeric() || *c == '.') {
+                    literal.push(c);
+                }
+                let value = match literal.strip_prefix("0x").or_else(|| literal.strip_prefix("0X")) {
+                    Some(hex) => u64::from_str_radix(hex, 16).ok(),
+                    None => literal.parse().ok(),
+                };
+                // Float literals only appear in option values, which are skipped
+                tokens.push((value.map_or(Token::Ident(literal), Token::Int), line));
+            }
+            c if c.is_alphabetic() || c == '_' || c == '.' => {
+                let mut ident = c.to_string();
+                while let Some(c) = chars.next_if(|c| c.is_alphanumeric() || *c == '_' || *c == '.') {
+                    ident.push(c);
+                }
+                tokens.push((Token::Ident(ident), line));
+            }
+            c => tokens.push((Token::Symbol(c), line)),
+        }
+    }
+    Ok(tokens)
+}
+
+fn proto_error(line: usize, message: &str) -> Error {
+    Error::SchemaError(format!("Proto line {}: {}", line, message))
+}
+
+/// How a field holds values of its type
+#[derive(Debug)]
+enum Label {
+    Single(String),
+    Repeated(String),
+    Map(String, String),
+}
+
+#[derive(Debug)]
+struct ProtoField {
+    name: String,
+    number: u64,
+    label: Label,
+    deprecated: bool,
+    line: usize,
+}
+
+#[derive(Debug)]
+struct ProtoMessage {
+    fields: Vec<ProtoField>,
+}
+
+/// Declarations of a .proto file, by fully qualified name
+#[derive(Debug, Default)]
+struct ProtoFile {
+    package: Option<String>,
+    messages: HashMap<String, ProtoMessage>,
+    enums: HashSet<String>,
+    top_level: Vec<String>,
+}
+
+struct ProtoParser {
+    tokens: Vec<(Token, usize)>,
+    position: usize,
+    file: ProtoFile,
+}
+
+impl ProtoParser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.position).or(self.tokens.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn error(&self, message: &str) -> Error {
+        proto_error(self.line(), message)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position).map(|(token, _)| token)
+    }
+
+    fn next(&mut self) -> Result<Token> {
+        let token = self.peek().cloned().ok_or_else(|| self.error("Unexpected end of file"))?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, symbol: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(symbol)) {
+            self.position += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, symbol: char) -> Result<()> {
+        if self.eat(symbol) {
+            Ok(())
+        } else {
+            Err(self.error(&format!("Expected '{}'", symbol)))
+        }
+    }
+
+    fn ident(&mut self) -> Result<String> {
+        match self.next()? {
+            Token::Ident(ident) => Ok(ident),
+            token => {
+                self.position -= 1;
+                Err(self.error(&format!("Expected a name, found {:?}", token)))
+            }
+        }
+    }
+
+    fn int(&mut self) -> Result<u64> {
+        match self.next()? {
+            Token::Int(value) => Ok(value),
+            token => {
+                self.position -= 1;
+                Err(self.error(&format!("Expected a number, found {:?}", token)))
+            }
+        }
+    }
+
+    /// Skips tokens up to and including the `;` ending a statement, or the
+    /// block it opens
+    fn skip_statement(&mut self) -> Result<()> {
+        loop {
+            match self.next()? {
+                Token::Symbol(';') => return Ok(()),
+                Token::Symbol('{') => {
+                    self.position -= 1;
+                    return self.skip_block();
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// Skips a `{ ... }` block, nested blocks included
+    fn skip_block(&mut self) -> Result<()> {
+        self.expect('{')?;
+        let mut depth = 1;
+        while depth > 0 {
+            match self.next()? {
+                Token::Symbol('{') => depth += 1,
+                Token::Symbol('}') => depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    fn parse_file(&mut self) -> Result<()> {
+        while let Some(token) = self.peek().cloned() {
+            let Token::Ident(keyword) = token else {
+                if self.eat(';') {
+                    continue;
+                }
+                return Err(self.error(&format!("Unexpected {:?}", token)));
+            };
+            match keyword.as_str() {
+                "syntax" | "edition" => {
+                    self.position += 1;
+                    self.expect('=')?;
+                    let syntax = self.next()?;
+                    if keyword == "edition" || syntax != Token::Str("proto3".to_string()) {
+                        return Err(self.error("Only proto3 files are supported"));
+                    }
+                    self.expect(';')?;
+                }
+                "package" => {
+                    self.position += 1;
+                    self.file.package = Some(self.ident()?);
+                    self.expect(';')?;
+                }
+                "message" => {
+                    self.position += 1;
+                    let scope = self.file.package.clone().unwrap_or_default();
+                    let name = self.parse_message(&scope)?;
+                    self.file.top_level.push(name);
+                }
+                "enum" => {
+                    self.position += 1;
+                    let scope = self.file.package.clone().unwrap_or_default();
+                    self.parse_enum(&scope)?;
+                }
+                // Imports resolve through the parser's referenced schemas
+                "import" | "option" | "service" | "extend" => self.skip_statement()?,
+                _ => return Err(self.error(&format!("Unexpected '{}'", keyword))),
+            }
+        }
+        Ok(())
+    }
+
+    /// Parses a message after its keyword, returning its qualified name
+    fn parse_message(&mut self, scope: &str) -> Result<String> {
+        let full_name = qualify(scope, &self.ident()?);
+        self.expect('{')?;
+        let mut fields = Vec::new();
+        let mut reserved_numbers = Vec::new();
+        let mut reserved_names = Vec::new();
+        while !self.eat('}') {
+            let Token::Ident(keyword) = self.next()? else {
+                self.position -= 1;
+                if self.eat(';') {
+                    continue;
+                }
+                return Err(self.error("Expected a field or declaration"));
+            };
+            match keyword.as_str() {
+                "message" => {
+                    self.parse_message(&full_name)?;
+                }
+                "enum" => self.parse_enum(&full_name)?,
+                "oneof" => {
+                    self.ident()?;
+                    self.expect('{')?;
+                    while !self.eat('}') {
+                        if self.peek() == Some(&Token::Ident("option".to_string())) {
+                            self.skip_statement()?;
+                            continue;
+                        }
+                        let type_name = self.ident()?;
+                        fields.push(self.parse_field(Label::Single(type_name))?);
+                    }
+                }
+                "reserved" => self.parse_reserved(&mut reserved_numbers, &mut reserved_names)?,
+                "option" | "extensions" | "extend" => {
+                    self.position -= 1;
+                    self.skip_statement()?;
+                }
+                "repeated" => {
+                    let type_name = self.ident()?;
+                    fields.push(self.parse_field(Label::Repeated(type_name))?);
+                }
+                "optional" => {
+                    let type_name = self.ident()?;
+                    fields.push(self.parse_field(Label::Single(type_name))?);
+                }
+                "required" => return Err(self.error("'required' fields are not part of proto3")),
+                "map" => {
+                    self.expect('<')?;
+                    let key = self.ident()?;
+                    self.expect(',')?;
+                    let value = self.ident()?;
+                    self.expect('>')?;
+                    fields.push(self.parse_field(Label::Map(key, value))?);
+                }
+                _ => fields.push(self.parse_field(Label::Single(keyword))?),
+            }
+        }
+
+        let mut numbers = HashMap::new();
+        let mut names = HashSet::new();
+        for field in &fields {
+            let error = |message: String| proto_error(field.line, &message);
+            if field.number == 0 || field.number > MAX_FIELD_NUMBER {
+                return Err(error(format!("Field number {} of '{}' is out of range", field.number, field.name)));
+            }
+            if let Some(other) = numbers.insert(field.number, &field.name) {
+                return Err(error(format!(
+                    "Fields '{}' and '{}' of '{}' share number {}",
+                    other, field.name, full_name, field.number
+                )));
+            }
+            if !names.insert(&field.name) {
+                return Err(error(format!("Field '{}' is declared twice in '{}'", field.name, full_name)));
+            }
+            if reserved_numbers.iter().any(|(low, high)| (*low..=*high).contains(&field.number)) {
+                return Err(error(format!("Field '{}' uses reserved number {}", field.name, field.number)));
+            }
+            if reserved_names.contains(&field.name) {
+                return Err(error(format!("Field name '{}' is reserved", field.name)));
+            }
+        }
+        self.file.messages.insert(full_name.clone(), ProtoMessage { fields });
+        Ok(full_name)
+    }
+
+    /// Parses `name = number [options];` after the type of a field
+    fn parse_field(&mut self, label: Label) -> Result<ProtoField> {
+        let line = self.line();
+        let name = self.ident()?;
+        self.expect('=')?;
+        let number = self.int()?;
+        let mut deprecated = false;
+        if self.eat('[') {
+            loop {
+                let option = self.ident()?;
+                self.expect('=')?;
+                let value = self.next()?;
+                if option == "deprecated" {
+                    deprecated = value == Token::Ident("true".to_string());
+                }
+                if value == Token::Symbol('{') {
+                    self.position -= 1;
+                    self.skip_block()?;
+                }
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            self.expect(']')?;
+        }
+        self.expect(';')?;
+        Ok(ProtoField { name, number, label, deprecated, line })
+    }
+
+    /// Parses `reserved 2, 9 to 11, 40 to max;` or `reserved "foo", "bar";`
+    fn parse_reserved(&mut self, numbers: &mut Vec<(u64, u64)>, names: &mut Vec<String>) -> Result<()> {
+        loop {
+            match self.next()? {
+                Token::Str(name) => names.push(name),
+                Token::Int(low) => {
+                    let high = if self.peek() == Some(&Token::Ident("to".to_string())) {
+                        self.position += 1;
+                        match self.next()? {
+                            Token::Ident(max) if max == "max" => MAX_FIELD_NUMBER,
+                            Token::Int(high) => high,
+                            _ => return Err(self.error("Expected the end of a reserved range")),
+                        }
+                    } else {
+                        low
+                    };
+                    numbers.push((low, high));
+                }
+                _ => return Err(self.error("Expected a reserved number or name")),
+            }
+            if !self.eat(',') {
+                return self.expect(';');
+            }
+        }
+    }
+
+    /// Records an enum after its keyword; its values are not needed
+    fn parse_enum(&mut self, scope: &str) -> Result<()> {
+        let full_name = qualify(scope, &self.ident()?);
+        self.skip_block()?;
+        self.file.enums.insert(full_name);
+        Ok(())
+    }
+}
+
+/// Returns the qualified name of a declaration in a scope
+fn qualify(scope: &str, name: &str) -> String {
+    if scope.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", scope, name)
+    }
+}
+
+/// Returns the schema type of a protobuf scalar type
+fn scalar_type(name: &str) -> Option<SchemaType> {
+    Some(match name {
+        "double" => SchemaType::Float64,
+        "float" => SchemaType::Float32,
+        "int32" | "sint32" | "sfixed32" => SchemaType::Int32,
+        "int64" | "sint64" | "sfixed64" => SchemaType::Int64,
+        "uint32" | "fixed32" => SchemaType::UInt32,
+        "uint64" | "fixed64" => SchemaType::UInt64,
+        "bool" => SchemaType::Boolean,
+        "string" => SchemaType::String,
+        "bytes" => SchemaType::Binary,
+        _ => return None,
+    })
+}
+
+/// Turns the declarations of a file into schema types
+struct Resolver<'a> {
+    file: &'a ProtoFile,
+    custom_types: &'a HashMap<String, SchemaType>,
+    referenced_types: &'a HashMap<String, SchemaType>,
+    /// Messages being resolved, to detect recursion
+    resolving: Vec<String>,
+    /// Messages that refer to themselves, kept as named types
+    recursive_types: HashMap<String, Option<SchemaType>>,
+}
+
+impl Resolver<'_> {
+    fn message_type(&mut self, full_name: &str) -> Result<SchemaType> {
+        if self.recursive_types.contains_key(full_name) || self.resolving.iter().any(|name| name == full_name) {
+            self.recursive_types.entry(full_name.to_string()).or_insert(None);
+            return Ok(SchemaType::Ref(full_name.to_string()));
+        }
+
+        let message = &self.file.messages[full_name];
+        self.resolving.push(full_name.to_string());
+        let mut fields = Vec::with_capacity(message.fields.len());
+        for field in &message.fields {
+            let field_type = match &field.label {
+                Label::Single(type_name) => self.field_type(type_name, full_name, field.line)?,
+                Label::Repeated(type_name) => {
+                    SchemaType::Array(Box::new(self.field_type(type_name, full_name, field.line)?))
+                }
+                Label::Map(key, value) => SchemaType::Map(
+                    Box::new(self.field_type(key, full_name, field.line)?),
+                    Box::new(self.field_type(value, full_name, field.line)?),
+                ),
+            };
+            fields.push(SchemaField {
+                name: field.name.clone(),
+                aliases: Vec::new(),
+                tag: field.number,
+                field_type,
+                required: false,
+                default_value: None,
+                description: None,
+                options: SchemaOptions { deprecated: field.deprecated, ..SchemaOptions::default() },
+            });
+        }
+        self.resolving.pop();
+
+        let schema_type = SchemaType::Object(fields);
+        match self.recursive_types.get_mut(full_name) {
+            Some(named_type) => {
+                *named_type = Some(schema_type);
+                Ok(SchemaType::Ref(full_name.to_string()))
+            }
+            None => Ok(schema_type),
+        }
+    }
+
+    /// Resolves a type name used in a message, following protobuf scoping:
+    /// the innermost enclosing scope declaring the name wins
+    fn field_type(&mut self, type_name: &str, scope: &str, line: usize) -> Result<SchemaType> {
+        if let Some(scalar) = scalar_type(type_name) {
+            return Ok(scalar);
+        }
+        if let Some(custom) = self.custom_types.get(type_name) {
+            return Ok(custom.clone());
+        }
+
+        let candidates: Vec<String> = match type_name.strip_prefix('.') {
+            Some(absolute) => vec![absolute.to_string()],
+            None => {
+                let mut scope = Some(scope);
+                let mut candidates = Vec::new();
+                while let Some(current) = scope {
+                    candidates.push(qualify(current, type_name));
+                    scope = current.rsplit_once('.').map(|(outer, _)| outer).or((!current.is_empty()).then_some(""));
+                }
+                candidates
+            }
+        };
+        for candidate in &candidates {
+            if self.file.messages.contains_key(candidate) {
+                return self.message_type(candidate);
+            }
+            if self.file.enums.contains(candidate) {
+                return Ok(SchemaType::Int32);
+            }
+        }
+        self.referenced_types
+            .get(type_name.trim_start_matches('.'))
+            .cloned()
+            .ok_or_else(|| proto_error(line, &format!("Unknown type '{}'", type_name)))
+    }
+}
+
+/// Parses a proto3 file into a schema whose root is its first top-level
+/// message
+pub(crate) fn parse_proto(
+    source: &str,
+    custom_types: &HashMap<String, SchemaType>,
+    referenced_types: &HashMap<String, SchemaType>,
+) -> Result<Schema> {
+    let mut parser = ProtoParser { tokens: tokenize(source)?, position: 0, file: ProtoFile::default() };
+    parser.parse_file()?;
+    let file = parser.file;
+    let root = file.top_level.first().ok_or_else(|| {
+        Error::SchemaError("Proto file declares no message".to_string())
+    })?;
+
+    let mut resolver = Resolver {
+        file: &file,
+        custom_types,
+        referenced_types,
+        resolving: Vec::new(),
+        recursive_types: HashMap::new(),
+    };
+    let root_type = resolver.message_type(root)?;
+    let short_name = root.rsplit('.').next().unwrap_or(root);
+    let mut schema = Schema::new(root.clone(), short_name.to_string(), SchemaVersion::new(1, 0, 0), root_type);
+    if let Some(package) = &file.package {
+        schema.metadata.insert(PROTO_PACKAGE_METADATA.to_string(), package.clone());
+    }
+    for (name, definition) in resolver.recursive_types {
+        if let Some(definition) = definition {
+            schema.definitions.insert(name, definition);
+        }
+    }
+    schema.check_definitions()?;
+    Ok(schema)
+}
@@ -0,0 +1,159 @@
+// Sampled validation for hot paths
+//
+// Validating every packet of a high-throughput stream is often too expensive,
+// while not validating at all lets malformed producers go unnoticed. A
+// `SamplingValidator` validates one packet in N and keeps violation counts, so
+// a service can watch violation rates at a fraction of the cost. The first
+// packet after a schema version change is always validated, as a new version
+// is the most likely moment for producers and consumers to disagree.
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::internal::error::Result;
+use crate::codec::types::HtlvItem;
+use crate::schema::compiled::CompiledSchema;
+use crate::schema::types::SchemaVersion;
+
+/// Outcome of offering a packet to a `SamplingValidator`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleOutcome {
+    /// The packet was validated and conforms to the schema
+    Valid,
+    /// The packet was not selected for validation
+    Skipped,
+}
+
+/// Counters of a `SamplingValidator`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SamplingStats {
+    /// Packets offered for validation
+    pub seen: u64,
+    /// Packets actually validated, including forced validations
+    pub validated: u64,
+    /// Validations forced by a schema version change
+    pub forced: u64,
+    /// Validated packets that did not conform to their schema
+    pub violations: u64,
+}
+
+impl SamplingStats {
+    /// Returns the fraction of validated packets that violated their schema
+    pub fn violation_rate(&self) -> f64 {
+        if self.validated == 0 {
+            0.0
+        } else {
+            self.violations as f64 / self.validated as f64
+        }
+    }
+
+    /// Returns the estimated number of violating packets among all packets
+    /// seen, extrapolated from the sample
+    pub fn estimated_violations(&self) -> f64 {
+        self.violation_rate() * self.seen as f64
+    }
+}
+
+impl fmt::Display for SamplingStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} packets seen, {} validated ({} forced), {} violations ({:.2}%)",
+            self.seen,
+            self.validated,
+            self.forced,
+            self.violations,
+            self.violation_rate() * 100.0
+        )
+    }
+}
+
+/// Validates a sample of the packets of a stream
+#[derive(Debug)]
+pub struct SamplingValidator {
+    /// Validate one packet in this many
+    sample_every: u64,
+    /// Packets since the last sampled one
+    countdown: u64,
+    /// Last schema version seen per schema ID
+    versions: HashMap<String, SchemaVersion>,
+    stats: SamplingStats,
+}
+
+impl SamplingValidator {
+    /// Creates a validator that validates one packet in `sample_every`; 0 and 1
+    /// both validate every packet
+    pub fn new(sample_every: u64) -> Self {
+        Self {
+            sample_every: sample_every.max(1),
+            countdown: 0,
+            versions: HashMap::new(),
+            stats: SamplingStats::default(),
+        }
+    }
+
+    /// Returns the sampling interval
+    pub fn sample_every(&self) -> u64 {
+        self.sample_every
+    }
+
+    /// Changes the sampling interval; the next packet is validated
+    pub fn set_sample_every(&mut self, sample_every: u64) {
+        self.sample_every = sample_every.max(1);
+        self.countdown = 0;
+    }
+
+    /// Offers a packet for validation
+    ///
+    /// Returns the validation error if the packet was selected and does not
+    /// conform to the schema.
+    pub fn check(&mut self, schema: &CompiledSchema, item: &HtlvItem) -> Result<SampleOutcome> {
+        self.stats.seen += 1;
+
+        let version_changed = self.note_version(schema);
+        let sampled = self.countdown == 0;
+        self.countdown = if sampled { self.sample_every - 1 } else { self.countdown - 1 };
+        if !sampled && !version_changed {
+            return Ok(SampleOutcome::Skipped);
+        }
+
+        self.stats.validated += 1;
+        if version_changed && !sampled {
+            self.stats.forced += 1;
+        }
+        match schema.validate(item) {
+            Ok(()) => Ok(SampleOutcome::Valid),
+            Err(e) => {
+                self.stats.violations += 1;
+                Err(e)
+            }
+        }
+    }
+
+    /// Returns the counters since creation or the last reset
+    pub fn stats(&self) -> &SamplingStats {
+        &self.stats
+    }
+
+    /// Returns the counters and starts counting from zero
+    pub fn take_stats(&mut self) -> SamplingStats {
+        std::mem::take(&mut self.stats)
+    }
+
+    /// Records the schema version of a packet; returns true if it differs from
+    /// the last version seen for the schema ID, or if the ID is new
+    fn note_version(&mut self, schema: &CompiledSchema) -> bool {
+        let schema = schema.schema();
+        match self.versions.get_mut(&schema.id) {
+            Some(version) if *version == schema.version => false,
+            Some(version) => {
+                *version = schema.version.clone();
+                true
+            }
+            None => {
+                self.versions.insert(schema.id.clone(), schema.version.clone());
+                true
+            }
+        }
+    }
+}
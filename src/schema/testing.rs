@@ -0,0 +1,25 @@
+// Schema fixtures shared by unit tests
+//
+// Tests across the crate build small schemas by hand; these helpers keep the
+// boilerplate of `SchemaField` and `Schema` out of each of them.
+
+use crate::schema::types::{Schema, SchemaField, SchemaOptions, SchemaType, SchemaVersion};
+
+/// Returns an optional field without a default value
+pub(crate) fn field(name: &str, tag: u64, field_type: SchemaType, options: SchemaOptions) -> SchemaField {
+    SchemaField {
+        name: name.to_string(),
+        aliases: Vec::new(),
+        tag,
+        field_type,
+        required: false,
+        default_value: None,
+        description: None,
+        options,
+    }
+}
+
+/// Returns version 1.0.0 of a schema without type definitions
+pub(crate) fn schema(id: &str, name: &str, root_type: SchemaType) -> Schema {
+    Schema::new(id.to_string(), name.to_string(), SchemaVersion::new(1, 0, 0), root_type)
+}
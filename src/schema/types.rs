@@ -9,6 +9,11 @@ use std::sync::Arc;
 
 use crate::internal::error::{Error, Result};
 use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::schema::lifecycle::{check_lifecycle, LifecycleIssue};
+use crate::schema::diff::{diff, SchemaDiff};
+use crate::schema::compatibility::{check_compatibility, CompatibilityReport};
+use crate::schema::compiled::CompiledSchema;
+use crate::schema::policy::SecurityLabel;
 
 /// Represents a schema version
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -84,6 +89,9 @@ pub enum SchemaType {
     Map(Box<SchemaType>, Box<SchemaType>),
     /// Union of multiple possible types
     Union(Vec<SchemaType>),
+    /// Reference to a named type in the definitions of the schema, which lets
+    /// types refer to themselves (trees, linked structures)
+    Ref(String),
 }
 
 impl SchemaType {
@@ -115,12 +123,21 @@ impl SchemaType {
     pub fn is_complex(&self) -> bool {
         matches!(
             self,
-            SchemaType::Array(_) | SchemaType::Object(_) | SchemaType::Map(_, _) | SchemaType::Union(_)
+            SchemaType::Array(_) | SchemaType::Object(_) | SchemaType::Map(_, _) | SchemaType::Union(_) | SchemaType::Ref(_)
         )
     }
     
     /// Validates that a given HtlvValue matches this schema type
+    ///
+    /// Type references cannot be resolved without the schema definitions; use
+    /// `validate_value_with` or `Schema::validate` for types containing them.
     pub fn validate_value(&self, value: &HtlvValue) -> Result<()> {
+        self.validate_value_with(value, &HashMap::new())
+    }
+    
+    /// Validates that a given HtlvValue matches this schema type, resolving type
+    /// references with the given definitions
+    pub fn validate_value_with(&self, value: &HtlvValue, definitions: &HashMap<String, SchemaType>) -> Result<()> {
         match (self, value) {
             (SchemaType::Null, HtlvValue::Null) => Ok(()),
             (SchemaType::Boolean, HtlvValue::Bool(_)) => Ok(()),
@@ -139,7 +156,7 @@ impl SchemaType {
             (SchemaType::Array(elem_type), HtlvValue::Array(items)) => {
                 // Validate each array element
                 for item in items {
-                    elem_type.validate_value(&item.value)?;
+                    elem_type.validate_value_with(&item.value, definitions)?;
                 }
                 Ok(())
             },
@@ -156,7 +173,7 @@ impl SchemaType {
                 // Validate each object field
                 for item in items {
                     if let Some(field) = field_map.get(&item.tag) {
-                        field.field_type.validate_value(&item.value)?;
+                        field.field_type.validate_value_with(&item.value, definitions)?;
                         seen_fields.insert(field.tag, true);
                     } else {
                         // Unknown field
@@ -180,7 +197,7 @@ impl SchemaType {
             (SchemaType::Union(types), value) => {
                 // Try each possible type
                 for t in types {
-                    if t.validate_value(value).is_ok() {
+                    if t.validate_value_with(value, definitions).is_ok() {
                         return Ok(());
                     }
                 }
@@ -190,6 +207,10 @@ impl SchemaType {
                     "Value does not match any type in union: {:?}", value
                 )))
             },
+            (SchemaType::Ref(name), value) => match definitions.get(name) {
+                Some(definition) => definition.validate_value_with(value, definitions),
+                None => Err(Error::SchemaError(format!("Unknown type reference '{}'", name))),
+            },
             // Map type validation would go here
             (SchemaType::Map(_, _), _) => {
                 // TODO: Implement Map validation
@@ -208,6 +229,8 @@ impl SchemaType {
 pub struct SchemaField {
     /// Field name
     pub name: String,
+    /// Alternative names accepted when mapping from JSON
+    pub aliases: Vec<String>,
     /// Field tag (used in HTLV encoding)
     pub tag: u64,
     /// Field type
@@ -222,6 +245,41 @@ pub struct SchemaField {
     pub options: SchemaOptions,
 }
 
+impl SchemaField {
+    /// Returns true if the given name is the name or one of the aliases of the field
+    pub fn has_name(&self, name: &str) -> bool {
+        self.name == name || self.aliases.iter().any(|alias| alias == name)
+    }
+    
+    /// Returns true if the field is marked as deprecated
+    pub fn is_deprecated(&self) -> bool {
+        self.options.deprecated
+    }
+    
+    /// Returns true if the field has been removed as of the given schema version
+    pub fn is_removed_in(&self, version: &SchemaVersion) -> bool {
+        self.options
+            .removed_in
+            .as_ref()
+            .is_some_and(|removed_in| version_key(version) >= version_key(removed_in))
+    }
+    
+    /// Returns true if the field is part of the given schema version, i.e. it
+    /// has been introduced and not yet removed
+    pub fn is_active_in(&self, version: &SchemaVersion) -> bool {
+        let introduced = self.options
+            .since_version
+            .as_ref()
+            .is_none_or(|since| version_key(version) >= version_key(since));
+        introduced && !self.is_removed_in(version)
+    }
+}
+
+/// Returns a key ordering schema versions
+pub(crate) fn version_key(version: &SchemaVersion) -> (u32, u32, u32) {
+    (version.major, version.minor, version.patch)
+}
+
 /// Additional options for schema fields
 #[derive(Debug, Clone, PartialEq, Default)]
 pub struct SchemaOptions {
@@ -243,6 +301,14 @@ pub struct SchemaOptions {
     pub max_length: Option<usize>,
     /// Custom options
     pub custom: HashMap<String, String>,
+    /// Whether the field is deprecated and should no longer be sent
+    pub deprecated: bool,
+    /// Schema version that introduced the field
+    pub since_version: Option<SchemaVersion>,
+    /// Schema version in which the field is (or will be) removed
+    pub removed_in: Option<SchemaVersion>,
+    /// Security labels, evaluated by the `PolicyEngine`
+    pub labels: Vec<SecurityLabel>,
 }
 
 /// Represents a complete schema definition
@@ -260,6 +326,8 @@ pub struct Schema {
     pub description: Option<String>,
     /// Additional schema metadata
     pub metadata: HashMap<String, String>,
+    /// Named types, referred to by `SchemaType::Ref`
+    pub definitions: HashMap<String, SchemaType>,
 }
 
 impl Schema {
@@ -277,12 +345,154 @@ impl Schema {
             root_type,
             description: None,
             metadata: HashMap::new(),
+            definitions: HashMap::new(),
         }
     }
     
     /// Validates that a given HtlvItem matches this schema
     pub fn validate(&self, item: &HtlvItem) -> Result<()> {
-        self.root_type.validate_value(&item.value)
+        self.root_type.validate_value_with(&item.value, &self.definitions)
+    }
+    
+    /// Checks that every type reference names a definition, and that no
+    /// definition refers to itself without an object, array or map in between,
+    /// which no finite value could match
+    pub fn check_definitions(&self) -> Result<()> {
+        let mut references = Vec::new();
+        collect_references(&self.root_type, &mut references);
+        for definition in self.definitions.values() {
+            collect_references(definition, &mut references);
+        }
+        if let Some(unknown) = references.iter().find(|name| !self.definitions.contains_key(**name)) {
+            return Err(Error::SchemaError(format!("Unknown type reference '{}'", unknown)));
+        }
+        
+        for (name, definition) in &self.definitions {
+            if self.reaches_without_nesting(definition, name, &mut Vec::new()) {
+                return Err(Error::SchemaError(format!(
+                    "Type '{}' refers to itself without nesting", name
+                )));
+            }
+        }
+        Ok(())
+    }
+    
+    /// Returns true if the type reaches a reference to `target` through unions
+    /// and references only
+    fn reaches_without_nesting<'a>(&'a self, schema_type: &'a SchemaType, target: &str, visited: &mut Vec<&'a str>) -> bool {
+        match schema_type {
+            SchemaType::Ref(name) => {
+                if name == target {
+                    return true;
+                }
+                if visited.contains(&name.as_str()) {
+                    return false;
+                }
+                visited.push(name);
+                self.definitions
+                    .get(name)
+                    .is_some_and(|definition| self.reaches_without_nesting(definition, target, visited))
+            },
+            SchemaType::Union(types) => types.iter().any(|t| self.reaches_without_nesting(t, target, visited)),
+            _ => false,
+        }
+    }
+    
+    /// Returns the fingerprint of the schema structure as a hex string
+    ///
+    /// The fingerprint is a BLAKE3 hash of the field names, tags, types and
+    /// required flags; the ID, version, descriptions and options are not part
+    /// of it, so structurally identical schemas share a fingerprint.
+    pub fn fingerprint(&self) -> String {
+        let mut canonical = Vec::new();
+        write_canonical_type(&self.root_type, &mut canonical);
+        
+        let mut names: Vec<&String> = self.definitions.keys().collect();
+        names.sort();
+        for name in names {
+            write_canonical_name(name, &mut canonical);
+            write_canonical_type(&self.definitions[name], &mut canonical);
+        }
+        blake3::hash(&canonical).to_hex().to_string()
+    }
+}
+
+/// Writes a deterministic encoding of a schema type, used for fingerprints
+fn write_canonical_type(schema_type: &SchemaType, out: &mut Vec<u8>) {
+    let code: u8 = match schema_type {
+        SchemaType::Null => 0,
+        SchemaType::Boolean => 1,
+        SchemaType::UInt8 => 2,
+        SchemaType::UInt16 => 3,
+        SchemaType::UInt32 => 4,
+        SchemaType::UInt64 => 5,
+        SchemaType::Int8 => 6,
+        SchemaType::Int16 => 7,
+        SchemaType::Int32 => 8,
+        SchemaType::Int64 => 9,
+        SchemaType::Float32 => 10,
+        SchemaType::Float64 => 11,
+        SchemaType::Binary => 12,
+        SchemaType::String => 13,
+        SchemaType::Array(_) => 14,
+        SchemaType::Object(_) => 15,
+        SchemaType::Map(_, _) => 16,
+        SchemaType::Union(_) => 17,
+        SchemaType::Ref(_) => 18,
+    };
+    out.push(code);
+    
+    match schema_type {
+        SchemaType::Array(element_type) => write_canonical_type(element_type, out),
+        SchemaType::Object(fields) => {
+            out.extend_from_slice(&(fields.len() as u64).to_le_bytes());
+            for field in fields {
+                write_canonical_name(&field.name, out);
+                out.extend_from_slice(&field.tag.to_le_bytes());
+                out.push(field.required as u8);
+                write_canonical_type(&field.field_type, out);
+            }
+        },
+        SchemaType::Map(key_type, value_type) => {
+            write_canonical_type(key_type, out);
+            write_canonical_type(value_type, out);
+        },
+        SchemaType::Union(types) => {
+            out.extend_from_slice(&(types.len() as u64).to_le_bytes());
+            for t in types {
+                write_canonical_type(t, out);
+            }
+        },
+        SchemaType::Ref(name) => write_canonical_name(name, out),
+        _ => {},
+    }
+}
+
+fn write_canonical_name(name: &str, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(name.len() as u64).to_le_bytes());
+    out.extend_from_slice(name.as_bytes());
+}
+
+/// Collects the names of the type references in a type
+fn collect_references<'a>(schema_type: &'a SchemaType, references: &mut Vec<&'a str>) {
+    match schema_type {
+        SchemaType::Ref(name) => references.push(name),
+        SchemaType::Array(element_type) => collect_references(element_type, references),
+        SchemaType::Object(fields) => {
+            for field in fields {
+                collect_references(&field.field_type, references);
+            }
+        },
+        SchemaType::Map(key_type, value_type) => {
+            collect_references(key_type, references);
+            collect_references(value_type, references);
+        },
+        SchemaType::Union(types) => {
+            for t in types {
+                collect_references(t, references);
+            }
+        },
+        _ => {},
     }
 }
 
@@ -293,6 +503,8 @@ pub struct SchemaRegistry {
     schemas: HashMap<String, Arc<Schema>>,
     /// Map of schema IDs to schema versions
     versions: HashMap<String, Vec<(SchemaVersion, Arc<Schema>)>>,
+    /// Map of schema IDs to the validation plan of the latest version
+    compiled: HashMap<String, Arc<CompiledSchema>>,
 }
 
 impl SchemaRegistry {
@@ -301,17 +513,23 @@ impl SchemaRegistry {
         Self {
             schemas: HashMap::new(),
             versions: HashMap::new(),
+            compiled: HashMap::new(),
         }
     }
     
     /// Registers a schema
+    ///
+    /// The schema is compiled into a validation plan, so registration fails
+    /// if a pattern constraint is not a valid regular expression.
     pub fn register_schema(&mut self, schema: Schema) -> Result<()> {
         let schema_id = schema.id.clone();
         let schema_version = schema.version.clone();
         let schema_arc = Arc::new(schema);
+        let compiled = CompiledSchema::compile(schema_arc.clone())?;
         
         // Store the latest version
         self.schemas.insert(schema_id.clone(), schema_arc.clone());
+        self.compiled.insert(schema_id.clone(), Arc::new(compiled));
         
         // Store in version history
         let versions = self.versions.entry(schema_id).or_insert_with(Vec::new);
@@ -332,6 +550,43 @@ impl SchemaRegistry {
         self.schemas.get(id).cloned()
     }
     
+    /// Gets the validation plan of a schema by ID (latest version)
+    pub fn get_compiled(&self, id: &str) -> Option<Arc<CompiledSchema>> {
+        self.compiled.get(id).cloned()
+    }
+    
+    /// Checks the field lifecycle of a schema against the latest registered
+    /// version with the same ID; returns no issues if there is none
+    pub fn check_lifecycle(&self, schema: &Schema) -> Vec<LifecycleIssue> {
+        match self.schemas.get(&schema.id) {
+            Some(previous) => check_lifecycle(previous, schema),
+            None => Vec::new(),
+        }
+    }
+    
+    /// Computes the changes from the latest registered version with the same
+    /// ID to a schema; returns `None` if there is no such version
+    pub fn diff(&self, schema: &Schema) -> Option<SchemaDiff> {
+        self.schemas.get(&schema.id).map(|previous| diff(previous, schema))
+    }
+    
+    /// Classifies the structural changes from an old to a new version of a
+    /// schema by the version bump they call for; unlike
+    /// `SchemaVersion::is_compatible_with`, this looks at the fields
+    pub fn check_compatibility(&self, old: &Schema, new: &Schema) -> CompatibilityReport {
+        check_compatibility(old, new)
+    }
+    
+    /// Gets a schema by fingerprint (see `Schema::fingerprint`)
+    pub fn get_schema_by_fingerprint(&self, fingerprint: &str) -> Option<Arc<Schema>> {
+        self.iter().find(|schema| schema.fingerprint() == fingerprint).cloned()
+    }
+    
+    /// Iterates over all registered schema versions, latest version first for each ID
+    pub fn iter(&self) -> impl Iterator<Item = &Arc<Schema>> {
+        self.versions.values().flat_map(|versions| versions.iter().map(|(_, schema)| schema))
+    }
+    
     /// Gets a schema by ID and version
     pub fn get_schema_version(&self, id: &str, version: &SchemaVersion) -> Option<Arc<Schema>> {
         if let Some(versions) = self.versions.get(id) {
@@ -0,0 +1,269 @@
+// Schema usage analytics for Tonitru
+//
+// This module provides an optional collector that records which schema fields
+// are actually present in decoded packets and which ones the application reads.
+// The resulting report drives schema pruning (fields that are never sent or
+// read) and required-field decisions (optional fields that are always sent).
+
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::internal::error::{Error, Result};
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::schema::types::{Schema, SchemaType, SchemaField};
+
+/// Usage counters for a single schema field
+#[derive(Debug, Clone, PartialEq)]
+pub struct FieldUsage {
+    /// Dotted path of the field names from the root object (e.g. "user.address.city")
+    pub path: String,
+    /// Field tag
+    pub tag: u64,
+    /// Whether the schema declares the field as required
+    pub required: bool,
+    /// Number of times the enclosing object was seen
+    pub parent_seen: u64,
+    /// Number of times the field was present in the enclosing object
+    pub present: u64,
+    /// Number of times the application reported reading the field
+    pub accessed: u64,
+}
+
+impl FieldUsage {
+    /// Returns the fraction of enclosing objects that contained the field
+    pub fn presence_ratio(&self) -> f64 {
+        if self.parent_seen == 0 {
+            0.0
+        } else {
+            self.present as f64 / self.parent_seen as f64
+        }
+    }
+}
+
+/// Report produced by a `SchemaUsageCollector`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SchemaUsageReport {
+    /// ID of the schema the report is about
+    pub schema_id: String,
+    /// Number of packets recorded
+    pub packets: u64,
+    /// Usage of each schema field, in schema order
+    pub fields: Vec<FieldUsage>,
+    /// Tags found in objects that are not declared by the schema, with their counts
+    pub unknown_tags: Vec<(u64, u64)>,
+}
+
+impl SchemaUsageReport {
+    /// Returns the fields that were never present nor read, which are candidates for pruning
+    pub fn unused_fields(&self) -> Vec<&FieldUsage> {
+        self.fields
+            .iter()
+            .filter(|field| field.present == 0 && field.accessed == 0)
+            .collect()
+    }
+
+    /// Returns the optional fields that were present every time their enclosing object
+    /// was seen, which are candidates for becoming required
+    pub fn always_present_optional_fields(&self) -> Vec<&FieldUsage> {
+        self.fields
+            .iter()
+            .filter(|field| !field.required && field.parent_seen > 0 && field.present == field.parent_seen)
+            .collect()
+    }
+
+    /// Returns the required fields that were missing at least once
+    pub fn missing_required_fields(&self) -> Vec<&FieldUsage> {
+        self.fields
+            .iter()
+            .filter(|field| field.required && field.present < field.parent_seen)
+            .collect()
+    }
+}
+
+impl fmt::Display for SchemaUsageReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Schema '{}': {} packets", self.schema_id, self.packets)?;
+        for field in &self.fields {
+            writeln!(
+                f,
+                "  {} (tag {}{}): present {}/{} ({:.1}%), accessed {}",
+                field.path,
+                field.tag,
+                if field.required { ", required" } else { "" },
+                field.present,
+                field.parent_seen,
+                field.presence_ratio() * 100.0,
+                field.accessed
+            )?;
+        }
+        for (tag, count) in &self.unknown_tags {
+            writeln!(f, "  unknown tag {}: {}", tag, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The fields of an object type, with the shapes of nested objects
+#[derive(Debug)]
+struct ObjectShape {
+    /// (tag, index into the usage counters, shape of the nested object if any)
+    fields: Vec<(u64, usize, Option<ObjectShape>)>,
+}
+
+/// Collects schema usage statistics from decoded packets
+#[derive(Debug)]
+pub struct SchemaUsageCollector {
+    schema_id: String,
+    root: Option<ObjectShape>,
+    fields: Vec<FieldUsage>,
+    field_index: HashMap<String, usize>,
+    unknown_tags: HashMap<u64, u64>,
+    packets: u64,
+}
+
+impl SchemaUsageCollector {
+    /// Creates a collector for the fields of a schema
+    ///
+    /// Fields are tracked for the root object and for objects nested in object
+    /// fields or used as array elements.
+    pub fn new(schema: &Schema) -> Self {
+        let mut fields = Vec::new();
+        let root = match &schema.root_type {
+            SchemaType::Object(schema_fields) => Some(build_shape(schema_fields, "", &mut fields)),
+            _ => None,
+        };
+        let field_index = fields
+            .iter()
+            .enumerate()
+            .map(|(index, field)| (field.path.clone(), index))
+            .collect();
+
+        Self {
+            schema_id: schema.id.clone(),
+            root,
+            fields,
+            field_index,
+            unknown_tags: HashMap::new(),
+            packets: 0,
+        }
+    }
+
+    /// Records the fields present in a decoded packet
+    pub fn record(&mut self, item: &HtlvItem) {
+        self.packets += 1;
+        if let (Some(shape), HtlvValue::Object(items)) = (&self.root, &item.value) {
+            record_object(shape, items, &mut self.fields, &mut self.unknown_tags);
+        }
+    }
+
+    /// Records that the application read the field at the given dotted path
+    pub fn record_access(&mut self, path: &str) -> Result<()> {
+        let index = *self.field_index.get(path).ok_or_else(|| {
+            Error::SchemaError(format!("Unknown field path '{}' in schema '{}'", path, self.schema_id))
+        })?;
+        self.fields[index].accessed += 1;
+        Ok(())
+    }
+
+    /// Returns the number of packets recorded
+    pub fn packets(&self) -> u64 {
+        self.packets
+    }
+
+    /// Produces a report of the usage collected so far
+    pub fn report(&self) -> SchemaUsageReport {
+        let mut unknown_tags: Vec<(u64, u64)> = self.unknown_tags.iter().map(|(&tag, &count)| (tag, count)).collect();
+        unknown_tags.sort_unstable();
+
+        SchemaUsageReport {
+            schema_id: self.schema_id.clone(),
+            packets: self.packets,
+            fields: self.fields.clone(),
+            unknown_tags,
+        }
+    }
+
+    /// Resets all counters
+    pub fn reset(&mut self) {
+        for field in &mut self.fields {
+            field.parent_seen = 0;
+            field.present = 0;
+            field.accessed = 0;
+        }
+        self.unknown_tags.clear();
+        self.packets = 0;
+    }
+}
+
+/// Builds the shape of an object type, registering usage counters for its fields
+fn build_shape(schema_fields: &[SchemaField], prefix: &str, fields: &mut Vec<FieldUsage>) -> ObjectShape {
+    let mut shape = ObjectShape { fields: Vec::new() };
+
+    for field in schema_fields {
+        let path = if prefix.is_empty() {
+            field.name.clone()
+        } else {
+            format!("{}.{}", prefix, field.name)
+        };
+
+        let index = fields.len();
+        fields.push(FieldUsage {
+            path: path.clone(),
+            tag: field.tag,
+            required: field.required,
+            parent_seen: 0,
+            present: 0,
+            accessed: 0,
+        });
+
+        let nested = match &field.field_type {
+            SchemaType::Object(nested_fields) => Some(build_shape(nested_fields, &path, fields)),
+            SchemaType::Array(element_type) => match element_type.as_ref() {
+                SchemaType::Object(nested_fields) => Some(build_shape(nested_fields, &path, fields)),
+                _ => None,
+            },
+            _ => None,
+        };
+        shape.fields.push((field.tag, index, nested));
+    }
+
+    shape
+}
+
+/// Records the presence of the fields of an object
+fn record_object(
+    shape: &ObjectShape,
+    items: &[HtlvItem],
+    fields: &mut [FieldUsage],
+    unknown_tags: &mut HashMap<u64, u64>,
+) {
+    for (tag, index, nested) in &shape.fields {
+        fields[*index].parent_seen += 1;
+
+        let item = match items.iter().find(|item| item.tag == *tag) {
+            Some(item) => item,
+            None => continue,
+        };
+        fields[*index].present += 1;
+
+        match (nested, &item.value) {
+            (Some(nested), HtlvValue::Object(sub_items)) => {
+                record_object(nested, sub_items, fields, unknown_tags);
+            }
+            (Some(nested), HtlvValue::Array(elements)) => {
+                for element in elements {
+                    if let HtlvValue::Object(sub_items) = &element.value {
+                        record_object(nested, sub_items, fields, unknown_tags);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    for item in items {
+        if !shape.fields.iter().any(|(tag, _, _)| *tag == item.tag) {
+            *unknown_tags.entry(item.tag).or_insert(0) += 1;
+        }
+    }
+}
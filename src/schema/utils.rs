@@ -20,6 +20,16 @@ pub fn generate_tag_from_name(name: &str) -> u64 {
     hasher.finish()
 }
 
+/// Returns the dotted path of the field `name` of the object at `prefix`,
+/// which is empty for the root object
+pub(crate) fn field_path(prefix: &str, name: &str) -> String {
+    if prefix.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}.{}", prefix, name)
+    }
+}
+
 /// Checks if a numeric value is within the valid range for a given schema type
 ///
 /// This function is used to validate that numeric values are within the
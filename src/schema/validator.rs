@@ -4,10 +4,16 @@
 // ensuring that data conforms to the defined schema.
 
 use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use regex::Regex;
 
 use crate::internal::error::{Error, Result};
+use crate::internal::diagnostics::{DiagnosticKind, DiagnosticSource, Diagnostics};
 use crate::codec::types::{HtlvItem, HtlvValue};
-use crate::schema::types::{Schema, SchemaType, SchemaField};
+use crate::schema::compiled::CompiledSchema;
+use crate::schema::types::{Schema, SchemaType, SchemaField, SchemaVersion};
 
 /// Configuration for schema validation
 #[derive(Debug, Clone)]
@@ -36,6 +42,56 @@ impl Default for ValidatorConfig {
     }
 }
 
+/// Kind of non-fatal finding reported during validation
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationWarningKind {
+    /// A deprecated field is still being sent
+    DeprecatedField,
+    /// A field is present although it was removed in the given version
+    RemovedField(SchemaVersion),
+    /// A field is present although it is only introduced in the given version
+    FieldNotYetIntroduced(SchemaVersion),
+}
+
+/// A non-fatal finding reported during validation
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationWarning {
+    /// Name of the field the warning is about
+    pub field: String,
+    /// Tag of the field the warning is about
+    pub tag: u64,
+    /// What was found
+    pub kind: ValidationWarningKind,
+}
+
+impl fmt::Display for ValidationWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ValidationWarningKind::DeprecatedField => {
+                write!(f, "Field '{}' (tag {}) is deprecated", self.field, self.tag)
+            }
+            ValidationWarningKind::RemovedField(version) => {
+                write!(f, "Field '{}' (tag {}) was removed in version {}", self.field, self.tag, version)
+            }
+            ValidationWarningKind::FieldNotYetIntroduced(version) => {
+                write!(f, "Field '{}' (tag {}) is only introduced in version {}", self.field, self.tag, version)
+            }
+        }
+    }
+}
+
+/// State threaded through a validation pass
+struct ValidationState<'a> {
+    /// Version of the schema being validated against, if known
+    version: Option<&'a SchemaVersion>,
+    /// Named types of the schema, if known
+    definitions: Option<&'a HashMap<String, SchemaType>>,
+    /// Type references being expanded, with the depth they were expanded at
+    expanding: Vec<(&'a str, usize)>,
+    /// Warnings collected so far
+    warnings: Vec<ValidationWarning>,
+}
+
 /// Schema validator
 #[derive(Debug)]
 pub struct SchemaValidator {
@@ -57,7 +113,55 @@ impl SchemaValidator {
     
     /// Validates an HTLV item against a schema
     pub fn validate(&self, schema: &Schema, item: &HtlvItem) -> Result<()> {
-        self.validate_value(&schema.root_type, &item.value, 0)
+        self.validate_with_warnings(schema, item).map(|_| ())
+    }
+    
+    /// Validates an HTLV item against a schema, returning the non-fatal findings
+    /// such as deprecated or removed fields that are still being sent
+    pub fn validate_with_warnings(&self, schema: &Schema, item: &HtlvItem) -> Result<Vec<ValidationWarning>> {
+        let mut state = ValidationState {
+            version: Some(&schema.version),
+            definitions: Some(&schema.definitions),
+            expanding: Vec::new(),
+            warnings: Vec::new(),
+        };
+        self.check_value(&schema.root_type, &item.value, 0, &mut state)?;
+        Ok(state.warnings)
+    }
+
+    /// Validates an HTLV item against a schema like `validate`, reporting the
+    /// non-fatal findings to the diagnostics sink
+    pub fn validate_with_diagnostics(&self, schema: &Schema, item: &HtlvItem, diagnostics: &Diagnostics) -> Result<()> {
+        for warning in self.validate_with_warnings(schema, item)? {
+            let kind = match warning.kind {
+                ValidationWarningKind::DeprecatedField => DiagnosticKind::DeprecatedField,
+                ValidationWarningKind::RemovedField(_) | ValidationWarningKind::FieldNotYetIntroduced(_) => {
+                    DiagnosticKind::FieldOutsideLifecycle
+                }
+            };
+            diagnostics.report(DiagnosticSource::Validate, kind, warning.to_string());
+        }
+        Ok(())
+    }
+    
+    /// Validates a batch of HTLV items against a schema, returning one result
+    /// per item in order
+    ///
+    /// The schema is compiled once for the whole batch instead of being walked
+    /// for every item. Fails without validating any item if the schema does not
+    /// compile, for instance because of an invalid pattern constraint.
+    pub fn validate_batch(&self, schema: &Schema, items: &[HtlvItem]) -> Result<Vec<Result<()>>> {
+        Ok(self.compile(schema)?.validate_batch(items))
+    }
+
+    /// Validates a batch like `validate_batch`, splitting the items over up to
+    /// `threads` threads
+    pub fn validate_batch_parallel(&self, schema: &Schema, items: &[HtlvItem], threads: usize) -> Result<Vec<Result<()>>> {
+        Ok(self.compile(schema)?.validate_batch_parallel(items, threads))
+    }
+
+    fn compile(&self, schema: &Schema) -> Result<CompiledSchema> {
+        CompiledSchema::compile_with_config(Arc::new(schema.clone()), self.config.clone())
     }
     
     /// Validates an HTLV value against a schema type
@@ -66,6 +170,22 @@ impl SchemaValidator {
         schema_type: &SchemaType,
         value: &HtlvValue,
         depth: usize,
+    ) -> Result<()> {
+        let mut state = ValidationState {
+            version: None,
+            definitions: None,
+            expanding: Vec::new(),
+            warnings: Vec::new(),
+        };
+        self.check_value(schema_type, value, depth, &mut state)
+    }
+    
+    fn check_value<'a>(
+        &self,
+        schema_type: &'a SchemaType,
+        value: &HtlvValue,
+        depth: usize,
+        state: &mut ValidationState<'a>,
     ) -> Result<()> {
         // Check nesting depth
         if depth > self.config.max_nesting_depth {
@@ -95,14 +215,14 @@ impl SchemaValidator {
             // Array type
             (SchemaType::Array(elem_type), HtlvValue::Array(items)) => {
                 for item in items {
-                    self.validate_value(elem_type, &item.value, depth + 1)?;
+                    self.check_value(elem_type, &item.value, depth + 1, state)?;
                 }
                 Ok(())
             },
             
             // Object type
             (SchemaType::Object(fields), HtlvValue::Object(items)) => {
-                self.validate_object(fields, items, depth)
+                self.validate_object(fields, items, depth, state)
             },
             
             // Map type
@@ -118,14 +238,14 @@ impl SchemaValidator {
                         
                         // Validate key (tag 0)
                         if let Some(key_item) = entry.iter().find(|i| i.tag == 0) {
-                            self.validate_value(key_type, &key_item.value, depth + 1)?;
+                            self.check_value(key_type, &key_item.value, depth + 1, state)?;
                         } else {
                             return Err(Error::SchemaError("Map entry missing key field (tag 0)".to_string()));
                         }
                         
                         // Validate value (tag 1)
                         if let Some(val_item) = entry.iter().find(|i| i.tag == 1) {
-                            self.validate_value(value_type, &val_item.value, depth + 1)?;
+                            self.check_value(value_type, &val_item.value, depth + 1, state)?;
                         } else {
                             return Err(Error::SchemaError("Map entry missing value field (tag 1)".to_string()));
                         }
@@ -140,11 +260,13 @@ impl SchemaValidator {
             
             // Union type
             (SchemaType::Union(types), value) => {
-                // Try each possible type
+                // Try each possible type, dropping the warnings of the variants that did not match
+                let warning_count = state.warnings.len();
                 for t in types {
-                    if self.validate_value(t, value, depth).is_ok() {
+                    if self.check_value(t, value, depth, state).is_ok() {
                         return Ok(());
                     }
+                    state.warnings.truncate(warning_count);
                 }
                 
                 // No matching type found
@@ -153,6 +275,26 @@ impl SchemaValidator {
                 )))
             },
             
+            // Named type, possibly recursive
+            (SchemaType::Ref(name), value) => {
+                let definition = state
+                    .definitions
+                    .and_then(|definitions| definitions.get(name))
+                    .ok_or_else(|| Error::SchemaError(format!("Unknown type reference '{}'", name)))?;
+                
+                // Expanding the same reference again without going one level
+                // deeper into the value would never terminate
+                if state.expanding.contains(&(name.as_str(), depth)) {
+                    return Err(Error::SchemaError(format!(
+                        "Type '{}' refers to itself without nesting", name
+                    )));
+                }
+                state.expanding.push((name, depth));
+                let result = self.check_value(definition, value, depth, state);
+                state.expanding.pop();
+                result
+            },
+            
             // Type mismatch
             (expected, actual) => Err(Error::SchemaError(format!(
                 "Type mismatch: expected {:?}, got {:?}", expected, actual
@@ -161,11 +303,12 @@ impl SchemaValidator {
     }
     
     /// Validates an object against a schema object type
-    fn validate_object(
+    fn validate_object<'a>(
         &self,
-        fields: &[SchemaField],
+        fields: &'a [SchemaField],
         items: &[HtlvItem],
         depth: usize,
+        state: &mut ValidationState<'a>,
     ) -> Result<()> {
         // Create a map of field tags to field definitions for quick lookup
         let field_map: HashMap<u64, &SchemaField> = fields
@@ -179,7 +322,8 @@ impl SchemaValidator {
         // Validate each object field
         for item in items {
             if let Some(field) = field_map.get(&item.tag) {
-                self.validate_value(&field.field_type, &item.value, depth + 1)?;
+                self.check_value(&field.field_type, &item.value, depth + 1, state)?;
+                check_lifecycle(field, state);
                 
                 // If validating constraints, check field-specific constraints
                 if self.config.validate_constraints {
@@ -198,7 +342,9 @@ impl SchemaValidator {
         // Check that all required fields are present
         if self.config.validate_required {
             for field in fields {
-                if field.required && !seen_fields.contains_key(&field.tag) {
+                // Fields outside the lifecycle window of the schema version are never required
+                let active = state.version.is_none_or(|version| field.is_active_in(version));
+                if field.required && active && !seen_fields.contains_key(&field.tag) {
                     return Err(Error::SchemaError(format!(
                         "Required field '{}' (tag {}) is missing", field.name, field.tag
                     )));
@@ -213,81 +359,110 @@ impl SchemaValidator {
     fn validate_constraints(&self, field: &SchemaField, value: &HtlvValue) -> Result<()> {
         let options = &field.options;
         
-        // Validate min/max value constraints for numeric types
-        if let (Some(min_value), HtlvValue::U8(v)) = (&options.min_value, value) {
-            if let HtlvValue::U8(min) = min_value {
-                if v < min {
-                    return Err(Error::SchemaError(format!(
-                        "Field '{}' value {} is less than minimum {}", field.name, v, min
-                    )));
-                }
-            }
+        if let Some(min_value) = &options.min_value {
+            check_min_value(&field.name, min_value, value)?;
         }
         
-        // Similar checks for other numeric types...
-        // (Omitted for brevity, but would follow the same pattern)
+        check_length(&field.name, options.min_length, options.max_length, value)?;
         
-        // Validate min/max length constraints for string, binary, array types
-        if let (Some(min_length), HtlvValue::String(s)) = (options.min_length, value) {
-            if s.len() < min_length {
-                return Err(Error::SchemaError(format!(
-                    "Field '{}' string length {} is less than minimum {}",
-                    field.name, s.len(), min_length
-                )));
-            }
+        // The pattern is compiled on every call; `CompiledSchema` compiles it once
+        if let Some(pattern) = &options.pattern {
+            check_pattern(&field.name, &compile_pattern(&field.name, pattern)?, value)?;
         }
         
-        if let (Some(max_length), HtlvValue::String(s)) = (options.max_length, value) {
-            if s.len() > max_length {
-                return Err(Error::SchemaError(format!(
-                    "Field '{}' string length {} is greater than maximum {}",
-                    field.name, s.len(), max_length
-                )));
-            }
+        Ok(())
+    }
+}
+
+/// Checks the minimum value constraint of a field
+pub(crate) fn check_min_value(name: &str, min_value: &HtlvValue, value: &HtlvValue) -> Result<()> {
+    if let (HtlvValue::U8(min), HtlvValue::U8(v)) = (min_value, value) {
+        if v < min {
+            return Err(Error::SchemaError(format!(
+                "Field '{}' value {} is less than minimum {}", name, v, min
+            )));
         }
-        
-        if let (Some(min_length), HtlvValue::Bytes(b)) = (options.min_length, value) {
-            if b.len() < min_length {
-                return Err(Error::SchemaError(format!(
-                    "Field '{}' binary length {} is less than minimum {}",
-                    field.name, b.len(), min_length
-                )));
-            }
+    }
+    
+    // Similar checks for other numeric types...
+    // (Omitted for brevity, but would follow the same pattern)
+    Ok(())
+}
+
+/// Checks the min/max length constraints of a string, binary or array field
+pub(crate) fn check_length(name: &str, min_length: Option<usize>, max_length: Option<usize>, value: &HtlvValue) -> Result<()> {
+    let (kind, length) = match value {
+        HtlvValue::String(s) => ("string", s.len()),
+        HtlvValue::Bytes(b) => ("binary", b.len()),
+        HtlvValue::Array(arr) => ("array", arr.len()),
+        _ => return Ok(()),
+    };
+    
+    if let Some(min_length) = min_length {
+        if length < min_length {
+            return Err(Error::SchemaError(format!(
+                "Field '{}' {} length {} is less than minimum {}",
+                name, kind, length, min_length
+            )));
         }
-        
-        if let (Some(max_length), HtlvValue::Bytes(b)) = (options.max_length, value) {
-            if b.len() > max_length {
-                return Err(Error::SchemaError(format!(
-                    "Field '{}' binary length {} is greater than maximum {}",
-                    field.name, b.len(), max_length
-                )));
-            }
+    }
+    
+    if let Some(max_length) = max_length {
+        if length > max_length {
+            return Err(Error::SchemaError(format!(
+                "Field '{}' {} length {} is greater than maximum {}",
+                name, kind, length, max_length
+            )));
         }
-        
-        if let (Some(min_length), HtlvValue::Array(arr)) = (options.min_length, value) {
-            if arr.len() < min_length {
-                return Err(Error::SchemaError(format!(
-                    "Field '{}' array length {} is less than minimum {}",
-                    field.name, arr.len(), min_length
-                )));
-            }
+    }
+    
+    Ok(())
+}
+
+/// Compiles the pattern constraint of a field
+pub(crate) fn compile_pattern(name: &str, pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).map_err(|e| Error::SchemaError(format!(
+        "Field '{}' has an invalid pattern '{}': {}", name, pattern, e
+    )))
+}
+
+/// Checks the pattern constraint of a string field; like JSON Schema, the
+/// pattern may match anywhere in the string unless it is anchored
+pub(crate) fn check_pattern(name: &str, pattern: &Regex, value: &HtlvValue) -> Result<()> {
+    if let HtlvValue::String(s) = value {
+        let s = std::str::from_utf8(s).map_err(|_| Error::SchemaError(format!(
+            "Field '{}' value is not valid UTF-8", name
+        )))?;
+        if !pattern.is_match(s) {
+            return Err(Error::SchemaError(format!(
+                "Field '{}' value does not match pattern '{}'", name, pattern
+            )));
         }
-        
-        if let (Some(max_length), HtlvValue::Array(arr)) = (options.max_length, value) {
-            if arr.len() > max_length {
-                return Err(Error::SchemaError(format!(
-                    "Field '{}' array length {} is greater than maximum {}",
-                    field.name, arr.len(), max_length
-                )));
-            }
+    }
+    Ok(())
+}
+
+/// Records lifecycle warnings for a field present in the data
+fn check_lifecycle(field: &SchemaField, state: &mut ValidationState<'_>) {
+    if let Some(kind) = lifecycle_warning(field, state.version) {
+        state.warnings.push(ValidationWarning {
+            field: field.name.clone(),
+            tag: field.tag,
+            kind,
+        });
+    }
+}
+
+/// Returns the lifecycle warning for a field present in data of the given schema version
+pub(crate) fn lifecycle_warning(field: &SchemaField, version: Option<&SchemaVersion>) -> Option<ValidationWarningKind> {
+    match version {
+        Some(version) if field.is_removed_in(version) => {
+            field.options.removed_in.clone().map(ValidationWarningKind::RemovedField)
         }
-        
-        // Validate pattern constraint for string types
-        if let (Some(pattern), HtlvValue::String(s)) = (&options.pattern, value) {
-            // TODO: Implement regex pattern validation
-            // For now, just skip this validation
+        Some(version) if !field.is_active_in(version) => {
+            field.options.since_version.clone().map(ValidationWarningKind::FieldNotYetIntroduced)
         }
-        
-        Ok(())
+        _ if field.is_deprecated() => Some(ValidationWarningKind::DeprecatedField),
+        _ => None,
     }
 }
@@ -0,0 +1,310 @@
+// Deserializer reading Rust types from HtlvValue trees
+//
+// Strings and bytes are borrowed from the tree. Numbers decoded from the wire
+// arrive as a one-element batch Array; wherever a number is expected such an
+// Array is unwrapped, so values survive a round trip through HTLV bytes.
+
+use ::serde::de::{self, DeserializeSeed, IntoDeserializer, Visitor};
+use ::serde::forward_to_deserialize_any;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::internal::error::{Error, Result};
+use super::member_name;
+
+/// Deserializer reading any `Deserialize` type from an `HtlvValue`.
+#[derive(Debug, Clone, Copy)]
+pub struct Deserializer<'de> {
+    value: &'de HtlvValue,
+}
+
+impl<'de> Deserializer<'de> {
+    /// Creates a deserializer over a value.
+    pub fn new(value: &'de HtlvValue) -> Self {
+        Deserializer { value }
+    }
+
+    /// Returns the number held by a one-element batch Array, or the value itself.
+    fn scalar(self) -> Self {
+        Deserializer::new(self.value.as_scalar())
+    }
+
+    fn invalid_type(&self, expected: &dyn de::Expected) -> Error {
+        de::Error::invalid_type(de::Unexpected::Other(&format!("{:?}", self.value.value_type())), expected)
+    }
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            HtlvValue::Null => visitor.visit_unit(),
+            HtlvValue::Bool(v) => visitor.visit_bool(*v),
+            HtlvValue::U8(v) => visitor.visit_u8(*v),
+            HtlvValue::U16(v) => visitor.visit_u16(*v),
+            HtlvValue::U32(v) => visitor.visit_u32(*v),
+            HtlvValue::U64(v) => visitor.visit_u64(*v),
+            HtlvValue::I8(v) => visitor.visit_i8(*v),
+            HtlvValue::I16(v) => visitor.visit_i16(*v),
+            HtlvValue::I32(v) => visitor.visit_i32(*v),
+            HtlvValue::I64(v) => visitor.visit_i64(*v),
+            HtlvValue::F32(v) => visitor.visit_f32(*v),
+            HtlvValue::F64(v) => visitor.visit_f64(*v),
+            HtlvValue::Bytes(v) | HtlvValue::Extension(_, v) => visitor.visit_borrowed_bytes(v),
+            HtlvValue::String(v) => match std::str::from_utf8(v) {
+                Ok(s) => visitor.visit_borrowed_str(s),
+                Err(e) => Err(Error::CodecError(format!("Invalid UTF-8 sequence for String value: {}", e))),
+            },
+            HtlvValue::Array(items) => visitor.visit_seq(SeqAccess { items: items.iter() }),
+            HtlvValue::Object(items) => visitor.visit_map(TagMapAccess { items: items.iter(), value: None }),
+            HtlvValue::ExternalRef { locator, .. } => Err(Error::CodecError(format!(
+                "External reference '{}' must be resolved before deserializing", locator
+            ))),
+        }
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        self.scalar().deserialize_any(visitor)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            HtlvValue::Null => visitor.visit_none(),
+            _ => visitor.visit_some(self),
+        }
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(self, _name: &'static str, visitor: V) -> Result<V::Value> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        match self.value {
+            HtlvValue::Object(items) => visitor.visit_map(TagMapAccess { items: items.iter(), value: None }),
+            HtlvValue::Array(pairs) => visitor.visit_map(PairMapAccess { pairs: pairs.iter(), value: None }),
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.value {
+            HtlvValue::Object(items) => visitor.visit_map(StructAccess { items: items.iter(), fields, value: None }),
+            _ => self.deserialize_any(visitor),
+        }
+    }
+
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value> {
+        match self.value {
+            HtlvValue::Object(items) if items.len() == 1 => {
+                let item = &items[0];
+                let variant = member_name(variants, item.tag)
+                    .ok_or_else(|| Error::CodecError(format!("Unknown variant tag {}", item.tag)))?;
+                visitor.visit_enum(EnumAccess { variant, value: &item.value })
+            }
+            _ => Err(self.invalid_type(&visitor)),
+        }
+    }
+
+    fn deserialize_ignored_any<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        visitor.visit_unit()
+    }
+
+    forward_to_deserialize_any! {
+        i128 u128 char str string bytes byte_buf unit unit_struct
+        seq tuple tuple_struct identifier
+    }
+}
+
+/// Yields the values of Array items as sequence elements.
+struct SeqAccess<'de> {
+    items: std::slice::Iter<'de, HtlvItem>,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqAccess<'de> {
+    type Error = Error;
+
+    fn next_element_seed<T: DeserializeSeed<'de>>(&mut self, seed: T) -> Result<Option<T::Value>> {
+        self.items.next().map(|item| seed.deserialize(Deserializer::new(&item.value))).transpose()
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// Yields Object items as map entries keyed by their tags.
+struct TagMapAccess<'de> {
+    items: std::slice::Iter<'de, HtlvItem>,
+    value: Option<&'de HtlvValue>,
+}
+
+impl<'de> de::MapAccess<'de> for TagMapAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some(item) = self.items.next() else { return Ok(None) };
+        self.value = Some(&item.value);
+        seed.deserialize(IntoDeserializer::<Error>::into_deserializer(item.tag)).map(Some)
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.value.take()
+            .ok_or_else(|| Error::CodecError("Map value requested before its key".to_string()))?;
+        seed.deserialize(Deserializer::new(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// Yields an Array of `[key, value]` Arrays as map entries.
+struct PairMapAccess<'de> {
+    pairs: std::slice::Iter<'de, HtlvItem>,
+    value: Option<&'de HtlvValue>,
+}
+
+impl<'de> de::MapAccess<'de> for PairMapAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some(pair) = self.pairs.next() else { return Ok(None) };
+        match &pair.value {
+            HtlvValue::Array(entry) if entry.len() == 2 => {
+                self.value = Some(&entry[1].value);
+                seed.deserialize(Deserializer::new(&entry[0].value)).map(Some)
+            }
+            _ => Err(Error::CodecError("Map entry is not a [key, value] Array".to_string())),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.value.take()
+            .ok_or_else(|| Error::CodecError("Map value requested before its key".to_string()))?;
+        seed.deserialize(Deserializer::new(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.pairs.len())
+    }
+}
+
+/// Yields Object items as struct fields, naming each by its tag.
+struct StructAccess<'de> {
+    items: std::slice::Iter<'de, HtlvItem>,
+    fields: &'static [&'static str],
+    value: Option<&'de HtlvValue>,
+}
+
+impl<'de> de::MapAccess<'de> for StructAccess<'de> {
+    type Error = Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(&mut self, seed: K) -> Result<Option<K::Value>> {
+        let Some(item) = self.items.next() else { return Ok(None) };
+        self.value = Some(&item.value);
+        match member_name(self.fields, item.tag) {
+            Some(field) => seed.deserialize(IntoDeserializer::<Error>::into_deserializer(field)).map(Some),
+            // Unknown tags are passed on as their number, which matches no field name
+            None => seed.deserialize(IntoDeserializer::<Error>::into_deserializer(item.tag.to_string())).map(Some),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(&mut self, seed: V) -> Result<V::Value> {
+        let value = self.value.take()
+            .ok_or_else(|| Error::CodecError("Field value requested before its key".to_string()))?;
+        seed.deserialize(Deserializer::new(value))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.items.len())
+    }
+}
+
+/// Gives access to the variant and the value of an enum.
+struct EnumAccess<'de> {
+    variant: &'static str,
+    value: &'de HtlvValue,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumAccess<'de> {
+    type Error = Error;
+    type Variant = Deserializer<'de>;
+
+    fn variant_seed<V: DeserializeSeed<'de>>(self, seed: V) -> Result<(V::Value, Deserializer<'de>)> {
+        let variant = seed.deserialize(IntoDeserializer::<Error>::into_deserializer(self.variant))?;
+        Ok((variant, Deserializer::new(self.value)))
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for Deserializer<'de> {
+    type Error = Error;
+
+    fn unit_variant(self) -> Result<()> {
+        match self.value {
+            HtlvValue::Null => Ok(()),
+            _ => Err(self.invalid_type(&"a unit variant")),
+        }
+    }
+
+    fn newtype_variant_seed<T: DeserializeSeed<'de>>(self, seed: T) -> Result<T::Value> {
+        seed.deserialize(self)
+    }
+
+    fn tuple_variant<V: Visitor<'de>>(self, _len: usize, visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_seq(self, visitor)
+    }
+
+    fn struct_variant<V: Visitor<'de>>(self, fields: &'static [&'static str], visitor: V) -> Result<V::Value> {
+        de::Deserializer::deserialize_struct(self, "", fields, visitor)
+    }
+}
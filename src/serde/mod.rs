@@ -0,0 +1,184 @@
+// Serde integration for HTLV
+//
+// Any type implementing `serde::Serialize` can be turned into an `HtlvValue`
+// tree or straight into HTLV bytes, and back through `serde::Deserialize`,
+// without hand-building `HtlvItem` trees.
+//
+// Mapping of the serde data model:
+// - Structs become Objects. Each field gets the tag given by its name when the
+//   name is a number (`#[serde(rename = "7")]`), otherwise its position in
+//   the struct starting at 1. Numeric names keep tags stable when fields are
+//   reordered or added in between.
+// - Enum variants become an Object with a single child, tagged by the same
+//   rule from the variant name or index; unit variants carry a Null child.
+// - Sequences and tuples become Arrays of items with tag 0.
+// - Maps with unsigned integer keys become Objects keyed by tag; any other map
+//   becomes an Array of `[key, value]` Arrays.
+// - `None`, `()` and unit structs become Null; newtypes are transparent.
+
+pub mod de; // HtlvValue -> Rust types
+pub mod ser; // Rust types -> HtlvValue
+mod value; // Serialize/Deserialize for HtlvValue
+
+use ::serde::de::DeserializeOwned;
+use ::serde::{Deserialize, Serialize};
+
+use crate::codec::decode::decode_item;
+use crate::codec::encode::encode_item;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::internal::error::{Error, Result};
+
+pub use de::Deserializer;
+pub use ser::Serializer;
+
+/// Tag of the root item written by `to_htlv`
+pub const ROOT_TAG: u64 = 0;
+
+/// Converts a value into an `HtlvValue` tree.
+pub fn to_value<T: Serialize + ?Sized>(value: &T) -> Result<HtlvValue> {
+    value.serialize(Serializer)
+}
+
+/// Converts an `HtlvValue` tree into a value; strings and bytes may be borrowed from it.
+pub fn from_value<'de, T: Deserialize<'de>>(value: &'de HtlvValue) -> Result<T> {
+    T::deserialize(Deserializer::new(value))
+}
+
+/// Encodes a value as a single HTLV item with tag `ROOT_TAG`.
+pub fn to_htlv<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>> {
+    encode_item(&HtlvItem::new(ROOT_TAG, to_value(value)?))
+}
+
+/// Decodes a value from the first HTLV item in `data`.
+pub fn from_htlv<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+    let (item, _) = decode_item(data)?;
+    from_value(&item.value)
+}
+
+/// Returns the tag of a struct field or enum variant: its name if that is a
+/// number, otherwise its position starting at 1.
+pub(crate) fn member_tag(name: &str, index: usize) -> u64 {
+    name.parse().unwrap_or(index as u64 + 1)
+}
+
+/// Returns the struct field or enum variant a tag refers to, by the rule of `member_tag`.
+pub(crate) fn member_name(members: &'static [&'static str], tag: u64) -> Option<&'static str> {
+    if let Some(name) = members.iter().find(|name| name.parse() == Ok(tag)) {
+        return Some(name);
+    }
+    let index = usize::try_from(tag.checked_sub(1)?).ok()?;
+    members.get(index).copied().filter(|name| name.parse::<u64>().is_err())
+}
+
+impl ::serde::ser::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::CodecError(msg.to_string())
+    }
+}
+
+impl ::serde::de::Error for Error {
+    fn custom<T: std::fmt::Display>(msg: T) -> Self {
+        Error::CodecError(msg.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::{BTreeMap, HashMap};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Shape {
+        Empty,
+        Circle(f64),
+        Rect { width: u32, height: u32 },
+        #[serde(rename = "9")]
+        Line(i16, i16),
+    }
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Record {
+        id: u64,
+        name: String,
+        #[serde(rename = "20")]
+        score: i32,
+        tags: Vec<String>,
+        ratios: Vec<f32>,
+        parent: Option<u32>,
+        shapes: Vec<Shape>,
+        counts: BTreeMap<u16, u8>,
+        labels: HashMap<String, bool>,
+        pair: (u8, String),
+    }
+
+    fn record() -> Record {
+        Record {
+            id: 42,
+            name: "Ada".to_string(),
+            score: -7,
+            tags: vec!["a".to_string(), "bc".to_string()],
+            ratios: vec![0.5],
+            parent: Some(3),
+            shapes: vec![
+                Shape::Empty,
+                Shape::Circle(1.5),
+                Shape::Rect { width: 2, height: 3 },
+                Shape::Line(-1, 1),
+            ],
+            counts: BTreeMap::from([(1, 2), (300, 4)]),
+            labels: HashMap::from([("x".to_string(), true)]),
+            pair: (9, "nine".to_string()),
+        }
+    }
+
+    #[test]
+    fn test_struct_fields_are_tagged_by_position_or_numeric_name() {
+        let value = to_value(&record()).unwrap();
+        let HtlvValue::Object(fields) = &value else { panic!("Expected an object") };
+        let tags: Vec<u64> = fields.iter().map(|item| item.tag).collect();
+        assert_eq!(tags, vec![1, 2, 20, 4, 5, 6, 7, 8, 9, 10]);
+        assert_eq!(fields[0].value, HtlvValue::U64(42));
+
+        let HtlvValue::Array(shapes) = &fields[6].value else { panic!("Expected an array") };
+        let HtlvValue::Object(line) = &shapes[3].value else { panic!("Expected an object") };
+        assert_eq!(line[0].tag, 9);
+    }
+
+    #[test]
+    fn test_round_trip_through_value_and_bytes() {
+        let original = record();
+        let value = to_value(&original).unwrap();
+        assert_eq!(from_value::<Record>(&value).unwrap(), original);
+
+        let encoded = to_htlv(&original).unwrap();
+        assert_eq!(from_htlv::<Record>(&encoded).unwrap(), original);
+        assert_eq!(from_htlv::<u64>(&to_htlv(&7u64).unwrap()).unwrap(), 7);
+    }
+
+    #[test]
+    fn test_htlv_value_round_trips_through_serde() {
+        let value = to_value(&record()).unwrap();
+        let back: HtlvValue = from_value(&value).unwrap();
+        assert_eq!(back, value);
+        assert_eq!(to_value(&back).unwrap(), value);
+
+        let json = serde_json::to_string(&HtlvValue::Object(vec![HtlvItem::new(3, HtlvValue::Bool(true))])).unwrap();
+        assert_eq!(json, r#"{"3":true}"#);
+        let parsed: HtlvValue = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, HtlvValue::Object(vec![HtlvItem::new(3, HtlvValue::Bool(true))]));
+    }
+
+    #[test]
+    fn test_errors() {
+        assert!(from_value::<u8>(&HtlvValue::U32(300)).is_err());
+        assert!(from_value::<String>(&HtlvValue::Bool(true)).is_err());
+
+        #[derive(Serialize)]
+        struct Clash {
+            first: u8,
+            #[serde(rename = "1")]
+            second: u8,
+        }
+        assert!(to_value(&Clash { first: 1, second: 2 }).is_err());
+    }
+}
@@ -0,0 +1,332 @@
+// Serializer producing HtlvValue trees
+//
+// See the module documentation of `crate::serde` for how the serde data model
+// maps onto HTLV values.
+
+use ::serde::ser::{self, Serialize};
+use bytes::Bytes;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::internal::error::{Error, Result};
+use super::member_tag;
+
+/// Serializer turning any `Serialize` value into an `HtlvValue`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Serializer;
+
+/// Wraps a value into the single-child Object representing an enum variant.
+fn variant(name: &str, index: u32, value: HtlvValue) -> HtlvValue {
+    HtlvValue::Object(vec![HtlvItem::new(member_tag(name, index as usize), value)])
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = HtlvValue;
+    type Error = Error;
+    type SerializeSeq = SeqSerializer;
+    type SerializeTuple = SeqSerializer;
+    type SerializeTupleStruct = SeqSerializer;
+    type SerializeTupleVariant = VariantSerializer<SeqSerializer>;
+    type SerializeMap = MapSerializer;
+    type SerializeStruct = StructSerializer;
+    type SerializeStructVariant = VariantSerializer<StructSerializer>;
+
+    fn serialize_bool(self, v: bool) -> Result<HtlvValue> {
+        Ok(HtlvValue::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<HtlvValue> {
+        Ok(HtlvValue::I8(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<HtlvValue> {
+        Ok(HtlvValue::I16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<HtlvValue> {
+        Ok(HtlvValue::I32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<HtlvValue> {
+        Ok(HtlvValue::I64(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<HtlvValue> {
+        Ok(HtlvValue::U8(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<HtlvValue> {
+        Ok(HtlvValue::U16(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<HtlvValue> {
+        Ok(HtlvValue::U32(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<HtlvValue> {
+        Ok(HtlvValue::U64(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<HtlvValue> {
+        Ok(HtlvValue::F32(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<HtlvValue> {
+        Ok(HtlvValue::F64(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<HtlvValue> {
+        self.serialize_str(v.encode_utf8(&mut [0u8; 4]))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<HtlvValue> {
+        Ok(HtlvValue::String(Bytes::copy_from_slice(v.as_bytes())))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<HtlvValue> {
+        Ok(HtlvValue::Bytes(Bytes::copy_from_slice(v)))
+    }
+
+    fn serialize_none(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Null)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<HtlvValue> {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Null)
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<HtlvValue> {
+        Ok(HtlvValue::Null)
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, index: u32, variant_name: &'static str) -> Result<HtlvValue> {
+        Ok(variant(variant_name, index, HtlvValue::Null))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(self, _name: &'static str, value: &T) -> Result<HtlvValue> {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant_name: &'static str,
+        value: &T,
+    ) -> Result<HtlvValue> {
+        Ok(variant(variant_name, index, value.serialize(self)?))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<SeqSerializer> {
+        Ok(SeqSerializer { items: Vec::with_capacity(len.unwrap_or(0)) })
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<SeqSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> Result<SeqSerializer> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant_name: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<SeqSerializer>> {
+        Ok(VariantSerializer { tag: member_tag(variant_name, index as usize), inner: self.serialize_seq(Some(len))? })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<MapSerializer> {
+        Ok(MapSerializer { entries: Vec::with_capacity(len.unwrap_or(0)), key: None })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> Result<StructSerializer> {
+        Ok(StructSerializer { items: Vec::with_capacity(len), index: 0 })
+    }
+
+    fn serialize_struct_variant(
+        self,
+        _name: &'static str,
+        index: u32,
+        variant_name: &'static str,
+        len: usize,
+    ) -> Result<VariantSerializer<StructSerializer>> {
+        Ok(VariantSerializer { tag: member_tag(variant_name, index as usize), inner: self.serialize_struct(variant_name, len)? })
+    }
+}
+
+/// Collects sequence and tuple elements into an Array.
+#[derive(Debug)]
+pub struct SeqSerializer {
+    items: Vec<HtlvItem>,
+}
+
+impl SeqSerializer {
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.items.push(HtlvItem::new(0, value.serialize(Serializer)?));
+        Ok(())
+    }
+}
+
+impl ser::SerializeSeq for SeqSerializer {
+    type Ok = HtlvValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Array(self.items))
+    }
+}
+
+impl ser::SerializeTuple for SeqSerializer {
+    type Ok = HtlvValue;
+    type Error = Error;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Array(self.items))
+    }
+}
+
+impl ser::SerializeTupleStruct for SeqSerializer {
+    type Ok = HtlvValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Array(self.items))
+    }
+}
+
+/// Collects map entries into an Object when all keys are unsigned integers,
+/// or into an Array of `[key, value]` Arrays otherwise.
+#[derive(Debug)]
+pub struct MapSerializer {
+    entries: Vec<(HtlvValue, HtlvValue)>,
+    key: Option<HtlvValue>,
+}
+
+/// Returns the tag a map key stands for, if it is an unsigned integer.
+fn key_tag(key: &HtlvValue) -> Option<u64> {
+    match key {
+        HtlvValue::U8(v) => Some(*v as u64),
+        HtlvValue::U16(v) => Some(*v as u64),
+        HtlvValue::U32(v) => Some(*v as u64),
+        HtlvValue::U64(v) => Some(*v),
+        _ => None,
+    }
+}
+
+impl ser::SerializeMap for MapSerializer {
+    type Ok = HtlvValue;
+    type Error = Error;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<()> {
+        self.key = Some(key.serialize(Serializer)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        let key = self.key.take()
+            .ok_or_else(|| Error::CodecError("Map value serialized before its key".to_string()))?;
+        self.entries.push((key, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<HtlvValue> {
+        if self.entries.iter().all(|(key, _)| key_tag(key).is_some()) {
+            let items = self.entries.into_iter()
+                .filter_map(|(key, value)| key_tag(&key).map(|tag| HtlvItem::new(tag, value)))
+                .collect();
+            return Ok(HtlvValue::Object(items));
+        }
+        let pairs = self.entries.into_iter()
+            .map(|(key, value)| HtlvItem::new(0, HtlvValue::Array(vec![HtlvItem::new(0, key), HtlvItem::new(0, value)])))
+            .collect();
+        Ok(HtlvValue::Array(pairs))
+    }
+}
+
+/// Collects struct fields into an Object, tagging each field by `member_tag`.
+#[derive(Debug)]
+pub struct StructSerializer {
+    items: Vec<HtlvItem>,
+    index: usize,
+}
+
+impl ser::SerializeStruct for StructSerializer {
+    type Ok = HtlvValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        let tag = member_tag(key, self.index);
+        self.index += 1;
+        if self.items.iter().any(|item| item.tag == tag) {
+            return Err(Error::CodecError(format!("Field '{}' maps to tag {} which is already in use", key, tag)));
+        }
+        self.items.push(HtlvItem::new(tag, value.serialize(Serializer)?));
+        Ok(())
+    }
+
+    fn skip_field(&mut self, _key: &'static str) -> Result<()> {
+        // Skipped fields keep their position so that later fields keep their tags
+        self.index += 1;
+        Ok(())
+    }
+
+    fn end(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Object(self.items))
+    }
+}
+
+/// Wraps the value of a tuple or struct variant into a single-child Object.
+#[derive(Debug)]
+pub struct VariantSerializer<S> {
+    tag: u64,
+    inner: S,
+}
+
+impl ser::SerializeTupleVariant for VariantSerializer<SeqSerializer> {
+    type Ok = HtlvValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<()> {
+        self.inner.push(value)
+    }
+
+    fn end(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Object(vec![HtlvItem::new(self.tag, HtlvValue::Array(self.inner.items))]))
+    }
+}
+
+impl ser::SerializeStructVariant for VariantSerializer<StructSerializer> {
+    type Ok = HtlvValue;
+    type Error = Error;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, key: &'static str, value: &T) -> Result<()> {
+        ser::SerializeStruct::serialize_field(&mut self.inner, key, value)
+    }
+
+    fn skip_field(&mut self, key: &'static str) -> Result<()> {
+        ser::SerializeStruct::skip_field(&mut self.inner, key)
+    }
+
+    fn end(self) -> Result<HtlvValue> {
+        Ok(HtlvValue::Object(vec![HtlvItem::new(self.tag, ser::SerializeStruct::end(self.inner)?)]))
+    }
+}
@@ -0,0 +1,203 @@
+// Serialize and Deserialize for HtlvValue
+//
+// An HtlvValue serializes as its natural serde counterpart: numbers keep their
+// width, Strings that are valid UTF-8 become strings, Arrays become sequences
+// and Objects become maps keyed by tag. Deserializing accepts any
+// self-describing format; map keys must be tags, as numbers or numeric strings.
+// Extension values serialize as a (type byte, payload) tuple and come back as
+// an Array, since serde formats have no notion of HTLV extension types.
+// External references likewise serialize as a (locator, length, hash) tuple.
+
+use std::fmt;
+
+use ::serde::de::{self, Deserialize, MapAccess, SeqAccess, Visitor};
+use ::serde::ser::{Serialize, SerializeMap, SerializeSeq, SerializeTuple};
+use bytes::Bytes;
+
+use crate::codec::types::{HtlvItem, HtlvValue};
+
+impl Serialize for HtlvValue {
+    fn serialize<S: ::serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            HtlvValue::Null => serializer.serialize_unit(),
+            HtlvValue::Bool(v) => serializer.serialize_bool(*v),
+            HtlvValue::U8(v) => serializer.serialize_u8(*v),
+            HtlvValue::U16(v) => serializer.serialize_u16(*v),
+            HtlvValue::U32(v) => serializer.serialize_u32(*v),
+            HtlvValue::U64(v) => serializer.serialize_u64(*v),
+            HtlvValue::I8(v) => serializer.serialize_i8(*v),
+            HtlvValue::I16(v) => serializer.serialize_i16(*v),
+            HtlvValue::I32(v) => serializer.serialize_i32(*v),
+            HtlvValue::I64(v) => serializer.serialize_i64(*v),
+            HtlvValue::F32(v) => serializer.serialize_f32(*v),
+            HtlvValue::F64(v) => serializer.serialize_f64(*v),
+            HtlvValue::Bytes(v) => serializer.serialize_bytes(v),
+            HtlvValue::String(v) => match std::str::from_utf8(v) {
+                Ok(s) => serializer.serialize_str(s),
+                Err(_) => serializer.serialize_bytes(v),
+            },
+            HtlvValue::Array(items) => {
+                let mut seq = serializer.serialize_seq(Some(items.len()))?;
+                for item in items {
+                    seq.serialize_element(&item.value)?;
+                }
+                seq.end()
+            }
+            HtlvValue::Object(items) => {
+                let mut map = serializer.serialize_map(Some(items.len()))?;
+                for item in items {
+                    map.serialize_entry(&item.tag, &item.value)?;
+                }
+                map.end()
+            }
+            HtlvValue::Extension(type_byte, v) => {
+                let mut tuple = serializer.serialize_tuple(2)?;
+                tuple.serialize_element(type_byte)?;
+                tuple.serialize_element(&HtlvValue::Bytes(v.clone()))?;
+                tuple.end()
+            }
+            HtlvValue::ExternalRef { locator, length, hash } => {
+                let mut tuple = serializer.serialize_tuple(3)?;
+                tuple.serialize_element(locator)?;
+                tuple.serialize_element(length)?;
+                tuple.serialize_element(&HtlvValue::Bytes(Bytes::copy_from_slice(hash)))?;
+                tuple.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for HtlvValue {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+struct ValueVisitor;
+
+impl<'de> Visitor<'de> for ValueVisitor {
+    type Value = HtlvValue;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("an HTLV value")
+    }
+
+    fn visit_bool<E: de::Error>(self, v: bool) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::Bool(v))
+    }
+
+    fn visit_i8<E: de::Error>(self, v: i8) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::I8(v))
+    }
+
+    fn visit_i16<E: de::Error>(self, v: i16) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::I16(v))
+    }
+
+    fn visit_i32<E: de::Error>(self, v: i32) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::I32(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::I64(v))
+    }
+
+    fn visit_u8<E: de::Error>(self, v: u8) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::U8(v))
+    }
+
+    fn visit_u16<E: de::Error>(self, v: u16) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::U16(v))
+    }
+
+    fn visit_u32<E: de::Error>(self, v: u32) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::U32(v))
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::U64(v))
+    }
+
+    fn visit_f32<E: de::Error>(self, v: f32) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::F32(v))
+    }
+
+    fn visit_f64<E: de::Error>(self, v: f64) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::F64(v))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::String(Bytes::copy_from_slice(v.as_bytes())))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::String(Bytes::from(v)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::Bytes(Bytes::copy_from_slice(v)))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::Bytes(Bytes::from(v)))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::Null)
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<HtlvValue, E> {
+        Ok(HtlvValue::Null)
+    }
+
+    fn visit_some<D: ::serde::Deserializer<'de>>(self, deserializer: D) -> Result<HtlvValue, D::Error> {
+        HtlvValue::deserialize(deserializer)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<HtlvValue, A::Error> {
+        let mut items = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(value) = seq.next_element()? {
+            items.push(HtlvItem::new(0, value));
+        }
+        Ok(HtlvValue::Array(items))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<HtlvValue, A::Error> {
+        let mut items = Vec::with_capacity(map.size_hint().unwrap_or(0));
+        while let Some((Tag(tag), value)) = map.next_entry()? {
+            items.push(HtlvItem::new(tag, value));
+        }
+        Ok(HtlvValue::Object(items))
+    }
+}
+
+/// An Object key: a tag given as a number or a numeric string.
+struct Tag(u64);
+
+impl<'de> Deserialize<'de> for Tag {
+    fn deserialize<D: ::serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(TagVisitor)
+    }
+}
+
+struct TagVisitor;
+
+impl<'de> Visitor<'de> for TagVisitor {
+    type Value = Tag;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a tag")
+    }
+
+    fn visit_u64<E: de::Error>(self, v: u64) -> Result<Tag, E> {
+        Ok(Tag(v))
+    }
+
+    fn visit_i64<E: de::Error>(self, v: i64) -> Result<Tag, E> {
+        u64::try_from(v).map(Tag).map_err(|_| E::invalid_value(de::Unexpected::Signed(v), &self))
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Tag, E> {
+        v.parse().map(Tag).map_err(|_| E::invalid_value(de::Unexpected::Str(v), &self))
+    }
+}
@@ -0,0 +1,378 @@
+// Materialized views over streamed packets
+//
+// A `View` keeps the latest state of a set of documents, keyed by a field of
+// their schema, from packets as they arrive: a packet whose key is new inserts
+// a document, one whose key is known patches it. Secondary indexes on other
+// top-level fields are kept in step. The state lives in an RCU cell, so
+// readers take consistent snapshots without locking while packets are
+// applied; each update publishes a new copy of the state, which makes
+// `apply_batch` much cheaper than applying packets one at a time.
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::Arc;
+
+use crate::internal::error::{Error, Result};
+use crate::internal::packet::Packet;
+use crate::codec::encode::encode_item;
+use crate::codec::rcu::Rcu;
+use crate::codec::types::{HtlvItem, HtlvValue};
+use crate::schema::types::{Schema, SchemaType};
+
+/// Configuration of a materialized view
+#[derive(Debug, Clone, PartialEq)]
+pub struct ViewConfig {
+    /// Tag of the top-level field holding the document key
+    pub key_tag: u64,
+
+    /// Tags of the top-level fields with a secondary index
+    pub index_tags: Vec<u64>,
+
+    /// Schema IDs of the packets applied; empty for all schemas
+    pub schema_ids: Vec<u64>,
+
+    /// Tag of a boolean field that, when true, deletes the document
+    pub delete_tag: Option<u64>,
+}
+
+impl ViewConfig {
+    /// Creates a configuration keyed by the field with the given tag
+    pub fn new(key_tag: u64) -> Self {
+        Self {
+            key_tag,
+            index_tags: Vec::new(),
+            schema_ids: Vec::new(),
+            delete_tag: None,
+        }
+    }
+}
+
+/// What applying an item did to a view
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViewChange {
+    /// A new document was inserted
+    Inserted,
+    /// An existing document was patched
+    Patched,
+    /// A document was deleted
+    Deleted,
+    /// Nothing changed, e.g. a delete for an unknown key
+    Unchanged,
+}
+
+/// A consistent snapshot of the documents of a view
+#[derive(Debug, Clone, Default)]
+pub struct ViewState {
+    documents: BTreeMap<Vec<u8>, Arc<HtlvItem>>,
+    indexes: HashMap<u64, BTreeMap<Vec<u8>, BTreeSet<Vec<u8>>>>,
+    version: u64,
+}
+
+impl ViewState {
+    /// Returns the document with the given key
+    pub fn get(&self, key: &HtlvValue) -> Option<Arc<HtlvItem>> {
+        self.documents.get(&value_key(key)?).cloned()
+    }
+
+    /// Returns the documents whose indexed field with the given tag holds
+    /// `value`, in key order
+    ///
+    /// Fails if the field is not indexed.
+    pub fn find(&self, tag: u64, value: &HtlvValue) -> Result<Vec<Arc<HtlvItem>>> {
+        let index = self.indexes.get(&tag)
+            .ok_or_else(|| Error::IndexError(format!("Tag {} is not indexed in the view", tag)))?;
+        let Some(keys) = value_key(value).and_then(|value| index.get(&value)) else {
+            return Ok(Vec::new());
+        };
+        Ok(keys.iter().map(|key| self.documents[key].clone()).collect())
+    }
+
+    /// Returns the number of documents
+    pub fn len(&self) -> usize {
+        self.documents.len()
+    }
+
+    /// Returns true if the view holds no documents
+    pub fn is_empty(&self) -> bool {
+        self.documents.is_empty()
+    }
+
+    /// Returns the number of updates published before this snapshot
+    pub fn version(&self) -> u64 {
+        self.version
+    }
+
+    /// Iterates over the documents in key order
+    pub fn documents(&self) -> impl Iterator<Item = &Arc<HtlvItem>> {
+        self.documents.values()
+    }
+
+    fn index(&mut self, key: &[u8], document: &HtlvItem, tags: &[u64], add: bool) {
+        for field in fields(document) {
+            if !tags.contains(&field.tag) {
+                continue;
+            }
+            let Some(value) = value_key(&field.value) else { continue };
+            let entries = self.indexes.entry(field.tag).or_default();
+            if add {
+                entries.entry(value).or_default().insert(key.to_vec());
+            } else if let Some(keys) = entries.get_mut(&value) {
+                keys.remove(key);
+                if keys.is_empty() {
+                    entries.remove(&value);
+                }
+            }
+        }
+    }
+}
+
+/// A queryable latest-state cache maintained from packets
+pub struct View {
+    config: ViewConfig,
+    state: Rcu<ViewState>,
+}
+
+impl std::fmt::Debug for View {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("View")
+            .field("config", &self.config)
+            .field("documents", &self.state.read().len())
+            .finish()
+    }
+}
+
+impl View {
+    /// Creates an empty view
+    pub fn new(config: ViewConfig) -> Self {
+        let mut state = ViewState::default();
+        for tag in &config.index_tags {
+            state.indexes.insert(*tag, BTreeMap::new());
+        }
+        Self { config, state: Rcu::new(state) }
+    }
+
+    /// Creates an empty view of the documents of an object schema, keyed by
+    /// the field with the given name and indexing the fields marked `index`
+    pub fn for_schema(schema: &Schema, key_field: &str) -> Result<Self> {
+        let SchemaType::Object(schema_fields) = &schema.root_type else {
+            return Err(Error::SchemaError(format!("Schema '{}' does not describe objects", schema.id)));
+        };
+        let key = schema_fields.iter().find(|field| field.has_name(key_field))
+            .ok_or_else(|| Error::SchemaError(format!("Schema '{}' has no field '{}'", schema.id, key_field)))?;
+        let mut config = ViewConfig::new(key.tag);
+        config.index_tags = schema_fields.iter()
+            .filter(|field| field.options.index && field.tag != key.tag)
+            .map(|field| field.tag)
+            .collect();
+        Ok(Self::new(config))
+    }
+
+    /// Returns the configuration of the view
+    pub fn config(&self) -> &ViewConfig {
+        &self.config
+    }
+
+    /// Returns a snapshot of the current state; later updates do not affect it
+    pub fn snapshot(&self) -> Arc<ViewState> {
+        self.state.read()
+    }
+
+    /// Applies a packet; packets of other schemas are ignored
+    pub fn apply(&self, packet: &Packet) -> Result<ViewChange> {
+        self.apply_batch(std::slice::from_ref(packet)).map(|changes| changes[0])
+    }
+
+    /// Applies packets in order and publishes the result as one update
+    ///
+    /// If a packet cannot be applied, nothing is published.
+    pub fn apply_batch(&self, packets: &[Packet]) -> Result<Vec<ViewChange>> {
+        let mut state = (*self.state.read()).clone();
+        let mut changes = Vec::with_capacity(packets.len());
+        for packet in packets {
+            if !self.config.schema_ids.is_empty() && !self.config.schema_ids.contains(&packet.header.schema_id) {
+                changes.push(ViewChange::Unchanged);
+                continue;
+            }
+            let item = crate::archive::packet_item(packet)
+                .ok_or_else(|| Error::CodecError("Cannot decode the body of a view packet".to_string()))?;
+            changes.push(self.merge(&mut state, &item)?);
+        }
+        if changes.iter().any(|change| *change != ViewChange::Unchanged) {
+            state.version += 1;
+            self.state.update(state);
+        }
+        Ok(changes)
+    }
+
+    /// Applies a decoded item, e.g. one delivered by a tail subscription
+    pub fn apply_item(&self, item: &HtlvItem) -> Result<ViewChange> {
+        let mut state = (*self.state.read()).clone();
+        let change = self.merge(&mut state, item)?;
+        if change != ViewChange::Unchanged {
+            state.version += 1;
+            self.state.update(state);
+        }
+        Ok(change)
+    }
+
+    /// Inserts, patches or deletes the document an item refers to
+    fn merge(&self, state: &mut ViewState, item: &HtlvItem) -> Result<ViewChange> {
+        let HtlvValue::Object(patch) = &item.value else {
+            return Err(Error::IndexError("View items must be objects".to_string()));
+        };
+        let key = patch.iter()
+            .find(|field| field.tag == self.config.key_tag)
+            .and_then(|field| value_key(&field.value))
+            .ok_or_else(|| Error::IndexError(format!("View item has no scalar key field {}", self.config.key_tag)))?;
+
+        let old = state.documents.remove(&key);
+        if let Some(old) = &old {
+            state.index(&key, old, &self.config.index_tags, false);
+        }
+
+        let deleted = self.config.delete_tag.is_some_and(|tag| {
+            patch.iter().any(|field| field.tag == tag && field.value.as_scalar() == &HtlvValue::Bool(true))
+        });
+        if deleted {
+            return Ok(if old.is_some() { ViewChange::Deleted } else { ViewChange::Unchanged });
+        }
+
+        // Fields of the patch replace those of the document; null removes them
+        let mut document: Vec<HtlvItem> = old.as_deref().map(|old| fields(old).to_vec()).unwrap_or_default();
+        for field in patch {
+            document.retain(|existing| existing.tag != field.tag);
+            if field.value != HtlvValue::Null {
+                document.push(field.clone());
+            }
+        }
+        document.sort_by_key(|field| field.tag);
+
+        let document = HtlvItem::new(item.tag, HtlvValue::Object(document));
+        state.index(&key, &document, &self.config.index_tags, true);
+        state.documents.insert(key, Arc::new(document));
+        Ok(if old.is_some() { ViewChange::Patched } else { ViewChange::Inserted })
+    }
+}
+
+fn fields(document: &HtlvItem) -> &[HtlvItem] {
+    match &document.value {
+        HtlvValue::Object(fields) => fields,
+        _ => &[],
+    }
+}
+
+/// Returns the lookup key of a scalar value, or `None` for floats and complex
+/// values; integers of any width and sign compare by value
+fn value_key(value: &HtlvValue) -> Option<Vec<u8>> {
+    let value = match value.as_scalar() {
+        HtlvValue::U8(v) => HtlvValue::U64(*v as u64),
+        HtlvValue::U16(v) => HtlvValue::U64(*v as u64),
+        HtlvValue::U32(v) => HtlvValue::U64(*v as u64),
+        HtlvValue::I8(v) => signed_value(*v as i64),
+        HtlvValue::I16(v) => signed_value(*v as i64),
+        HtlvValue::I32(v) => signed_value(*v as i64),
+        HtlvValue::I64(v) => signed_value(*v),
+        HtlvValue::F32(_) | HtlvValue::F64(_) | HtlvValue::Array(_) | HtlvValue::Object(_) => return None,
+        value => value.clone(),
+    };
+    encode_item(&HtlvItem::new(0, value)).ok()
+}
+
+fn signed_value(value: i64) -> HtlvValue {
+    match u64::try_from(value) {
+        Ok(unsigned) => HtlvValue::U64(unsigned),
+        Err(_) => HtlvValue::I64(value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::internal::packet::{DataBody, MetadataHeader};
+    use crate::schema::parser::SchemaParser;
+
+    const SCHEMA: &str = r#"{
+        "id": "accounts", "name": "Accounts", "version": "1.0.0", "type": "object",
+        "properties": {
+            "id": {"type": "integer"},
+            "plan": {"type": "string", "index": true},
+            "deleted": {"type": "boolean"}
+        }
+    }"#;
+
+    fn packet(fields: Vec<HtlvItem>) -> Packet {
+        let item = HtlvItem::new(0, HtlvValue::Object(fields));
+        let header = MetadataHeader { schema_id: 3, timestamp: 0, shard_id: 0, flow_flags: 0, body_type: 0, key_id: None };
+        Packet::build_packet(header, DataBody::Raw(encode_item(&item).unwrap())).unwrap()
+    }
+
+    fn account(id: u32, plan: &str) -> Packet {
+        packet(vec![
+            HtlvItem::new(1, HtlvValue::U32(id)),
+            HtlvItem::new(2, HtlvValue::String(plan.to_string().into())),
+        ])
+    }
+
+    fn ids(documents: &[Arc<HtlvItem>]) -> Vec<HtlvValue> {
+        documents.iter().map(|document| fields(document)[0].value.as_scalar().clone()).collect()
+    }
+
+    #[test]
+    fn test_view_inserts_patches_and_deletes() {
+        let mut config = ViewConfig::new(1);
+        config.index_tags = vec![2];
+        config.delete_tag = Some(3);
+        let view = View::new(config);
+
+        let changes = view.apply_batch(&[account(1, "free"), account(2, "pro"), account(3, "free")]).unwrap();
+        assert_eq!(changes, vec![ViewChange::Inserted; 3]);
+        let before = view.snapshot();
+
+        // Upgrade account 1: the index follows the patch
+        assert_eq!(view.apply(&account(1, "pro")).unwrap(), ViewChange::Patched);
+        let state = view.snapshot();
+        assert_eq!(ids(&state.find(2, &HtlvValue::String("pro".into())).unwrap()), vec![HtlvValue::U32(1), HtlvValue::U32(2)]);
+        assert_eq!(ids(&state.find(2, &HtlvValue::String("free".into())).unwrap()), vec![HtlvValue::U32(3)]);
+        assert!(state.find(1, &HtlvValue::U32(1)).is_err());
+
+        // Keys match whatever the integer width
+        let document = state.get(&HtlvValue::I64(1)).unwrap();
+        assert_eq!(fields(&document).len(), 2);
+
+        // Null removes a field, the delete flag removes the document
+        view.apply(&packet(vec![HtlvItem::new(1, HtlvValue::U32(2)), HtlvItem::new(2, HtlvValue::Null)])).unwrap();
+        assert_eq!(fields(&view.snapshot().get(&HtlvValue::U8(2)).unwrap()).len(), 1);
+        let delete = packet(vec![HtlvItem::new(1, HtlvValue::U32(3)), HtlvItem::new(3, HtlvValue::Bool(true))]);
+        assert_eq!(view.apply(&delete).unwrap(), ViewChange::Deleted);
+        assert_eq!(view.apply(&delete).unwrap(), ViewChange::Unchanged);
+
+        let state = view.snapshot();
+        assert_eq!((state.len(), state.version()), (2, 4));
+        assert!(state.find(2, &HtlvValue::String("free".into())).unwrap().is_empty());
+
+        // Older snapshots are unaffected
+        assert_eq!((before.len(), before.version()), (3, 1));
+        assert_eq!(ids(&before.find(2, &HtlvValue::String("free".into())).unwrap()), vec![HtlvValue::U32(1), HtlvValue::U32(3)]);
+    }
+
+    #[test]
+    fn test_view_for_schema() {
+        let json: serde_json::Value = serde_json::from_str(SCHEMA).unwrap();
+        let schema = SchemaParser::new().parse_schema(&json).unwrap();
+        let view = View::for_schema(&schema, "id").unwrap();
+        assert!(View::for_schema(&schema, "missing").is_err());
+
+        let key_tag = view.config().key_tag;
+        let plan_tag = view.config().index_tags[0];
+        let item = HtlvItem::new(0, HtlvValue::Object(vec![
+            HtlvItem::new(key_tag, HtlvValue::U64(7)),
+            HtlvItem::new(plan_tag, HtlvValue::String("team".into())),
+        ]));
+        assert_eq!(view.apply_item(&item).unwrap(), ViewChange::Inserted);
+        assert_eq!(view.snapshot().find(plan_tag, &HtlvValue::String("team".into())).unwrap().len(), 1);
+
+        // Items without a key leave the view as it was
+        let keyless = HtlvItem::new(0, HtlvValue::Object(vec![HtlvItem::new(plan_tag, HtlvValue::Null)]));
+        assert!(view.apply_item(&keyless).is_err());
+        assert_eq!(view.snapshot().version(), 1);
+    }
+}
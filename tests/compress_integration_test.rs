@@ -26,6 +26,7 @@ fn test_compression_with_packet() {
             shard_id: 10,
             flow_flags: 0,
             body_type: 0, // Will be set by build_packet
+            key_id: None,
         };
 
         // Set compression strategy in header
@@ -91,8 +92,9 @@ fn test_sharded_compression_with_large_data() {
 /// Tests the incremental compression with sequential data.
 #[test]
 fn test_incremental_compression_with_sequential_data() {
-    // Create an incremental compressor
+    // Create incremental compressors for both ends of the stream
     let mut compressor = IncrementalCompressor::default();
+    let mut decompressor = IncrementalCompressor::default();
     let context_id = 1;
 
     // Create a sequence of related data
@@ -114,7 +116,7 @@ fn test_incremental_compression_with_sequential_data() {
     // Decompress each part
     let mut decompressed_parts = Vec::new();
     for compressed in &compressed_parts {
-        let decompressed = compressor.decompress_with_context(compressed, context_id).unwrap();
+        let decompressed = decompressor.decompress_with_context(compressed, context_id).unwrap();
         decompressed_parts.push(decompressed);
     }
 
@@ -141,8 +143,9 @@ fn test_combined_sharded_and_incremental_compression() {
     // Compress the data into shards
     let shards = sharded_compressor.compress_to_shards(&original_data).unwrap();
 
-    // Create an incremental compressor
+    // Create incremental compressors for both ends of the stream
     let mut incremental_compressor = IncrementalCompressor::default();
+    let mut incremental_decompressor = IncrementalCompressor::default();
     let context_id = 1;
 
     // Compress each shard incrementally
@@ -155,7 +158,7 @@ fn test_combined_sharded_and_incremental_compression() {
     // Decompress each shard incrementally
     let mut decompressed_shards = Vec::new();
     for compressed in &compressed_shards {
-        let decompressed = incremental_compressor.decompress_with_context(compressed, context_id).unwrap();
+        let decompressed = incremental_decompressor.decompress_with_context(compressed, context_id).unwrap();
         decompressed_shards.push(decompressed);
     }
 
@@ -0,0 +1,26 @@
+// Loopback run of the interop examples
+//
+// Runs the interop server and the conformance suite of the interop client
+// against each other over a loopback socket, end to end through framing,
+// schema synchronization, compression, encryption and fragmentation.
+
+#[path = "../examples/interop/mod.rs"]
+mod interop;
+
+use tokio::net::TcpListener;
+
+#[tokio::test]
+async fn test_interop_client_and_server_on_loopback() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap().to_string();
+    let mut server = interop::InteropServer::new(listener, interop::server_schemas().unwrap());
+
+    // The suite connects twice: once for a new session, once to resume it. A
+    // failed check panics here rather than leave the server waiting.
+    let (served, ()) = tokio::join!(server.serve(Some(2)), async { interop::run_conformance(&addr).await.unwrap() });
+    served.unwrap();
+
+    // The server kept the schema it received from the client
+    let ids: Vec<_> = server.schemas().infos().into_iter().map(|info| info.id).collect();
+    assert!(ids.contains(&"interop.commands".to_string()), "{:?}", ids);
+}
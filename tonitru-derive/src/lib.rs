@@ -0,0 +1,136 @@
+// Derive macros for Tonitru Encode/Decode
+//
+// `#[derive(Encode)]` and `#[derive(Decode)]` map a struct with named fields
+// to an HTLV Object: each field becomes an item tagged by its
+// `#[htlv(tag = N)]` attribute, or by its position starting at 1 when the
+// attribute is absent. Field values are converted with
+// `tonitru::codec::convert::{ToHtlvValue, FromHtlvValue}`, which the derives
+// also implement for the struct so derived structs can be nested.
+//
+// These macros are re-exported by `tonitru` when its `derive` feature is
+// enabled; use them through `tonitru::codec::{Encode, Decode}`.
+
+use proc_macro::TokenStream;
+use proc_macro2::TokenStream as TokenStream2;
+use quote::quote;
+use syn::{parse_macro_input, Data, DeriveInput, Fields, Ident, LitInt};
+
+/// Derives `Encode` and `ToHtlvValue` for a struct with named fields.
+#[proc_macro_derive(Encode, attributes(htlv))]
+pub fn derive_encode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_encode(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// Derives `Decode` and `FromHtlvValue` for a struct with named fields.
+#[proc_macro_derive(Decode, attributes(htlv))]
+pub fn derive_decode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand_decode(&input).unwrap_or_else(syn::Error::into_compile_error).into()
+}
+
+/// A struct field and the tag it is encoded under
+struct TaggedField {
+    ident: Ident,
+    tag: u64,
+}
+
+/// Collects the fields of the struct with their tags, rejecting duplicate tags.
+fn tagged_fields(input: &DeriveInput) -> syn::Result<Vec<TaggedField>> {
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => return Err(syn::Error::new_spanned(&input.ident, "HTLV derives require a struct with named fields")),
+        },
+        _ => return Err(syn::Error::new_spanned(&input.ident, "HTLV derives only support structs")),
+    };
+
+    let mut tagged: Vec<TaggedField> = Vec::with_capacity(fields.len());
+    for (index, field) in fields.iter().enumerate() {
+        let mut tag = index as u64 + 1;
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("htlv")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("tag") {
+                    tag = meta.value()?.parse::<LitInt>()?.base10_parse()?;
+                    Ok(())
+                } else {
+                    Err(meta.error("unsupported htlv attribute, expected `tag = N`"))
+                }
+            })?;
+        }
+        let ident = field.ident.clone().expect("named field");
+        if let Some(other) = tagged.iter().find(|other| other.tag == tag) {
+            return Err(syn::Error::new_spanned(
+                &ident,
+                format!("tag {} is already used by field `{}`", tag, other.ident),
+            ));
+        }
+        tagged.push(TaggedField { ident, tag });
+    }
+    Ok(tagged)
+}
+
+fn expand_encode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = tagged_fields(input)?;
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let items = fields.iter().map(|TaggedField { ident, tag }| {
+        quote! {
+            ::tonitru::codec::types::HtlvItem::new(
+                #tag,
+                ::tonitru::codec::convert::ToHtlvValue::to_htlv_value(&self.#ident)?,
+            )
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::tonitru::codec::convert::ToHtlvValue for #name #ty_generics #where_clause {
+            fn to_htlv_value(&self) -> ::tonitru::internal::error::Result<::tonitru::codec::types::HtlvValue> {
+                Ok(::tonitru::codec::types::HtlvValue::Object(vec![#(#items),*]))
+            }
+        }
+
+        impl #impl_generics ::tonitru::codec::Encode for #name #ty_generics #where_clause {
+            fn encode(&self, buf: &mut ::tonitru::__bytes::BytesMut) -> ::tonitru::internal::error::Result<()> {
+                let item = ::tonitru::codec::types::HtlvItem::new(
+                    0,
+                    ::tonitru::codec::convert::ToHtlvValue::to_htlv_value(self)?,
+                );
+                buf.extend_from_slice(&::tonitru::codec::encode::encode_item(&item)?);
+                Ok(())
+            }
+        }
+    })
+}
+
+fn expand_decode(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let fields = tagged_fields(input)?;
+    let name = &input.ident;
+    let name_str = name.to_string();
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let inits = fields.iter().map(|TaggedField { ident, tag }| {
+        let field_name = ident.to_string();
+        quote! {
+            #ident: ::tonitru::codec::convert::field(items, #tag, #field_name)?
+        }
+    });
+
+    Ok(quote! {
+        impl #impl_generics ::tonitru::codec::convert::FromHtlvValue for #name #ty_generics #where_clause {
+            fn from_htlv_value(
+                value: &::tonitru::codec::types::HtlvValue,
+            ) -> ::tonitru::internal::error::Result<Self> {
+                let items = ::tonitru::codec::convert::object_items(value, #name_str)?;
+                Ok(#name { #(#inits),* })
+            }
+        }
+
+        impl #impl_generics ::tonitru::codec::Decode for #name #ty_generics #where_clause {
+            fn decode(data: &[u8]) -> ::tonitru::internal::error::Result<(Self, usize)> {
+                let (item, bytes_read) = ::tonitru::codec::decode::decode_item(data)?;
+                let value = <Self as ::tonitru::codec::convert::FromHtlvValue>::from_htlv_value(&item.value)?;
+                Ok((value, bytes_read))
+            }
+        }
+    })
+}
@@ -1,5 +1,303 @@
 // Tonitru CLI tool entry point
 
+mod parquet_export;
+
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter};
+use std::process;
+use tonitru::archive::{export_jsonl, import_jsonl, repair_file, ArchiveReader, ArchiveWriter, RecordConverter, SegmentKey};
+use tonitru::codec::stats::DocumentStats;
+use tonitru::codec::wire::WireFormat;
+use tonitru::internal::packet::{DataBody, Packet};
+use tonitru::{detect, ContentKind};
+use tonitru::schema::{check_lifecycle, diff, Schema, SchemaLinter, SchemaParser};
+
+fn print_usage() {
+    eprintln!("Usage: tonitru-cli inspect <file>");
+    eprintln!("       tonitru-cli lint <schema.json>");
+    eprintln!("       tonitru-cli diff <old.json> <new.json> [--json]");
+    eprintln!("       tonitru-cli archive pack <out.tna> [--schema <id>=<schema.json>]... <packet>...");
+    eprintln!("       tonitru-cli archive list <archive.tna>");
+    eprintln!("       tonitru-cli archive export <archive.tna> --format jsonl|parquet [--output <file>]");
+    eprintln!("       tonitru-cli archive import <records.jsonl> <out.tna> [--schema <id>=<schema.json>]...");
+    eprintln!("       tonitru-cli archive repair <damaged.tna> <out.tna>");
+}
+
+/// Prints the content kind of a file, and document statistics when it holds
+/// encoded HTLV items, directly or in a raw packet body.
+fn inspect(path: &str) -> Result<(), String> {
+    let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let kind = detect(&data);
+    println!("Content: {:?}", kind);
+
+    let items = match kind {
+        ContentKind::Archive => return list_archive(path),
+        ContentKind::Item(WireFormat::V1) | ContentKind::ItemStream(_) => data,
+        ContentKind::Packet(_) => {
+            let packet = Packet::parse_packet(&data).map_err(|e| format!("Failed to inspect {}: {}", path, e))?;
+            match packet.body {
+                DataBody::Raw(body) => body,
+                _ => return Ok(()),
+            }
+        }
+        _ => return Ok(()),
+    };
+    let stats = DocumentStats::from_encoded(&items).map_err(|e| format!("Failed to inspect {}: {}", path, e))?;
+    println!("{}", stats);
+    Ok(())
+}
+
+/// Reads and parses a JSON schema file.
+fn read_schema(path: &str) -> Result<Schema, String> {
+    let text = fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let json: serde_json::Value = serde_json::from_str(&text).map_err(|e| format!("Failed to parse {}: {}", path, e))?;
+    SchemaParser::new()
+        .parse_schema(&json)
+        .map_err(|e| format!("Invalid schema {}: {}", path, e))
+}
+
+/// Lints a JSON schema file. Fails if any finding has error severity.
+fn lint(path: &str) -> Result<(), String> {
+    let schema = read_schema(path)?;
+    let report = SchemaLinter::new().lint(&schema);
+    print!("{}", report);
+    if report.has_errors() {
+        return Err(format!("{} has lint errors", path));
+    }
+    Ok(())
+}
+
+/// Prints the changes between two versions of a JSON schema, followed by the
+/// lifecycle issues that would reject the new version. Fails if any issue is
+/// breaking.
+fn diff_schemas(old_path: &str, new_path: &str, as_json: bool) -> Result<(), String> {
+    let old = read_schema(old_path)?;
+    let new = read_schema(new_path)?;
+
+    let changes = diff(&old, &new);
+    let issues = check_lifecycle(&old, &new);
+    if as_json {
+        let mut json = changes.to_json();
+        json["lifecycle"] = issues
+            .iter()
+            .map(|issue| {
+                serde_json::json!({
+                    "path": issue.path,
+                    "tag": issue.tag,
+                    "breaking": issue.breaking,
+                    "message": issue.message,
+                })
+            })
+            .collect();
+        let text = serde_json::to_string_pretty(&json).map_err(|e| format!("Failed to render diff: {}", e))?;
+        println!("{}", text);
+    } else {
+        print!("{}", changes);
+        for issue in &issues {
+            println!("{}", issue);
+        }
+    }
+
+    if issues.iter().any(|issue| issue.breaking) {
+        return Err(format!("{} breaks the lifecycle of {}", new_path, old_path));
+    }
+    Ok(())
+}
+
+/// Reads an `<id>=<schema.json>` argument into the schema ID, the schema
+/// document and the parsed schema.
+fn read_schema_arg(spec: &str) -> Result<(u64, Vec<u8>, Schema), String> {
+    let (id, path) = spec.split_once('=').ok_or_else(|| format!("Invalid schema argument: {}", spec))?;
+    let id: u64 = id.parse().map_err(|_| format!("Invalid schema ID: {}", id))?;
+    let schema = read_schema(path)?;
+    let document = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    Ok((id, document, schema))
+}
+
+/// Splits `--schema <id>=<schema.json>` options from the other arguments.
+fn split_schema_args(args: &[String]) -> Result<(Vec<(u64, Vec<u8>, Schema)>, Vec<&String>), String> {
+    let mut args = args.iter();
+    let mut schemas = Vec::new();
+    let mut rest = Vec::new();
+    while let Some(arg) = args.next() {
+        if arg == "--schema" {
+            let spec = args.next().ok_or("--schema needs an <id>=<schema.json> argument")?;
+            schemas.push(read_schema_arg(spec)?);
+        } else {
+            rest.push(arg);
+        }
+    }
+    Ok((schemas, rest))
+}
+
+/// Packs packet files into an archive, together with the given schemas.
+fn pack(out_path: &str, args: &[String]) -> Result<(), String> {
+    let (schemas, packet_paths) = split_schema_args(args)?;
+    let file = File::create(out_path).map_err(|e| format!("Failed to create {}: {}", out_path, e))?;
+    let mut writer = ArchiveWriter::new(BufWriter::new(file));
+    for (id, document, _) in schemas {
+        writer.add_schema(id, document).map_err(|e| e.to_string())?;
+    }
+
+    for path in &packet_paths {
+        let data = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+        let packet = Packet::parse_packet(&data).map_err(|e| format!("{} is not a packet: {}", path, e))?;
+        writer.append(&packet).map_err(|e| format!("Failed to pack {}: {}", path, e))?;
+    }
+    writer.finish().map_err(|e| format!("Failed to write {}: {}", out_path, e))?;
+    println!("Packed {} packets into {}", packet_paths.len(), out_path);
+    Ok(())
+}
+
+/// Prints the schemas and table of contents of an archive.
+fn list_archive(path: &str) -> Result<(), String> {
+    let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let reader = ArchiveReader::open(file).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+
+    for schema in reader.schemas() {
+        match schema.parse() {
+            Ok(parsed) => println!("schema {}: {} {}", schema.schema_id, parsed.id, parsed.version),
+            Err(e) => println!("schema {}: invalid ({})", schema.schema_id, e),
+        }
+    }
+    if !reader.indexed_tags().is_empty() {
+        println!("indexed tags: {:?}", reader.indexed_tags());
+    }
+    for (i, segment) in reader.segments().iter().enumerate() {
+        println!(
+            "segment {}: {} packets, {} bytes at {}, timestamps {}..={}, schemas {:?}, bloom filter {} bytes{}{}",
+            i,
+            segment.packet_count,
+            segment.length,
+            segment.offset,
+            segment.min_timestamp,
+            segment.max_timestamp,
+            segment.schema_ids,
+            segment.bloom.size(),
+            if segment.fully_indexed { "" } else { " (partial)" },
+            match &segment.key {
+                Some(SegmentKey::Wrapped { master_key_id, .. }) => format!(", encrypted with key {}", master_key_id),
+                Some(SegmentKey::Shredded) => ", shredded".to_string(),
+                None => String::new(),
+            }
+        );
+    }
+    println!("{} packets in {} segments", reader.packet_count(), reader.segments().len());
+    Ok(())
+}
+
+/// Exports the packets of an archive as JSON Lines or Parquet. JSON Lines go
+/// to standard output unless an output file is given.
+fn export(path: &str, args: &[String]) -> Result<(), String> {
+    let (format, output) = match args {
+        [flag, format] if flag == "--format" => (format, None),
+        [flag, format, out_flag, output] if flag == "--format" && out_flag == "--output" => (format, Some(output)),
+        _ => return Err("Usage: tonitru-cli archive export <archive.tna> --format jsonl|parquet [--output <file>]".to_string()),
+    };
+    let file = File::open(path).map_err(|e| format!("Failed to read {}: {}", path, e))?;
+    let mut reader = ArchiveReader::open(BufReader::new(file)).map_err(|e| format!("Failed to open {}: {}", path, e))?;
+
+    match (format.as_str(), output) {
+        ("jsonl", Some(output)) => {
+            let out = File::create(output).map_err(|e| format!("Failed to create {}: {}", output, e))?;
+            let report = export_jsonl(&mut reader, BufWriter::new(out)).map_err(|e| format!("Failed to export {}: {}", path, e))?;
+            eprintln!("Exported {} packets, skipped {} unreadable", report.exported, report.skipped);
+        }
+        ("jsonl", None) => {
+            let report = export_jsonl(&mut reader, io::stdout().lock()).map_err(|e| format!("Failed to export {}: {}", path, e))?;
+            eprintln!("Exported {} packets, skipped {} unreadable", report.exported, report.skipped);
+        }
+        ("parquet", Some(output)) => {
+            let converter = RecordConverter::new(reader.schemas());
+            let mut records = Vec::new();
+            let mut skipped = 0;
+            for packet in reader.read_all().map_err(|e| format!("Failed to read {}: {}", path, e))? {
+                match converter.to_record(&packet).map_err(|e| format!("Failed to export {}: {}", path, e))? {
+                    Some(record) => records.push(record),
+                    None => skipped += 1,
+                }
+            }
+            // Per-field columns need a single schema for all packets
+            let mut schema_ids: Vec<u64> = reader.segments().iter().flat_map(|s| s.schema_ids.iter().copied()).collect();
+            schema_ids.sort_unstable();
+            schema_ids.dedup();
+            let schema = match schema_ids.as_slice() {
+                [id] => converter.schema(*id),
+                _ => None,
+            };
+            parquet_export::write_parquet(output, &records, schema)?;
+            eprintln!("Exported {} packets, skipped {} unreadable", records.len(), skipped);
+        }
+        ("parquet", None) => return Err("Parquet export needs --output <file>".to_string()),
+        (format, _) => return Err(format!("Unknown export format: {}", format)),
+    }
+    Ok(())
+}
+
+/// Imports JSON Lines records into a new archive. Every record's schema ID
+/// needs a schema.
+fn import(input_path: &str, out_path: &str, args: &[String]) -> Result<(), String> {
+    let (schemas, rest) = split_schema_args(args)?;
+    if let Some(arg) = rest.first() {
+        return Err(format!("Unexpected argument: {}", arg));
+    }
+    let input = File::open(input_path).map_err(|e| format!("Failed to read {}: {}", input_path, e))?;
+    let file = File::create(out_path).map_err(|e| format!("Failed to create {}: {}", out_path, e))?;
+    let mut writer = ArchiveWriter::new(BufWriter::new(file));
+    let mut converter = RecordConverter::default();
+    for (id, document, schema) in schemas {
+        writer.add_schema(id, document).map_err(|e| e.to_string())?;
+        converter.add_schema(id, schema);
+    }
+
+    let imported = import_jsonl(BufReader::new(input), &converter, &mut writer)
+        .map_err(|e| format!("Failed to import {}: {}", input_path, e))?;
+    writer.finish().map_err(|e| format!("Failed to write {}: {}", out_path, e))?;
+    println!("Imported {} packets into {}", imported, out_path);
+    Ok(())
+}
+
+/// Recovers what is left of a damaged archive into a new archive.
+fn repair(input: &str, out_path: &str) -> Result<(), String> {
+    let report = repair_file(input, out_path).map_err(|e| format!("Failed to repair {}: {}", input, e))?;
+    if !report.index_intact {
+        println!("Index missing or damaged, rebuilt from the recovered packets");
+    }
+    for region in &report.unrecoverable {
+        println!("unrecoverable: {} bytes at {}", region.length, region.offset);
+    }
+    println!(
+        "Recovered {} packets and {} schemas into {}, {} bytes lost",
+        report.packets_recovered,
+        report.schemas_recovered,
+        out_path,
+        report.bytes_lost()
+    );
+    Ok(())
+}
+
 fn main() {
-    println!("Tonitru CLI tool");
-}
\ No newline at end of file
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.as_slice() {
+        [command, path] if command == "inspect" => inspect(path),
+        [command, path] if command == "lint" => lint(path),
+        [command, old, new] if command == "diff" => diff_schemas(old, new, false),
+        [command, old, new, flag] if command == "diff" && flag == "--json" => diff_schemas(old, new, true),
+        [command, sub, out, rest @ ..] if command == "archive" && sub == "pack" => pack(out, rest),
+        [command, sub, path] if command == "archive" && sub == "list" => list_archive(path),
+        [command, sub, path, rest @ ..] if command == "archive" && sub == "export" => export(path, rest),
+        [command, sub, input, out, rest @ ..] if command == "archive" && sub == "import" => import(input, out, rest),
+        [command, sub, input, out] if command == "archive" && sub == "repair" => repair(input, out),
+        _ => {
+            println!("Tonitru CLI tool");
+            print_usage();
+            return;
+        }
+    };
+
+    if let Err(e) = result {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,184 @@
+// Parquet export of archive records
+//
+// Header fields always get their own columns. When every packet uses the same
+// object schema, each top-level field of the schema gets a column too;
+// otherwise the item goes into a single `data` column as JSON text. Nested
+// values in field columns are also stored as JSON text.
+
+use std::fs::File;
+use std::sync::Arc;
+
+use parquet::basic::{ConvertedType, Repetition, Type as PhysicalType};
+use parquet::data_type::{BoolType, ByteArray, ByteArrayType, DoubleType, Int64Type};
+use parquet::file::properties::WriterProperties;
+use parquet::file::writer::SerializedFileWriter;
+use parquet::schema::types::Type;
+use serde_json::Value;
+use tonitru::schema::{Schema, SchemaType};
+
+/// Header fields of a record, in column order
+const HEADER_COLUMNS: [&str; 3] = ["schema_id", "timestamp", "shard_id"];
+
+/// Physical representation of a column
+#[derive(Debug, Clone, Copy)]
+enum ColumnKind {
+    Int64,
+    Double,
+    Boolean,
+    Text,
+    Json,
+}
+
+impl ColumnKind {
+    fn for_type(schema_type: &SchemaType) -> Self {
+        match schema_type {
+            SchemaType::Boolean => ColumnKind::Boolean,
+            SchemaType::String => ColumnKind::Text,
+            t if t.is_integer() => ColumnKind::Int64,
+            t if t.is_float() => ColumnKind::Double,
+            _ => ColumnKind::Json,
+        }
+    }
+}
+
+/// Where a column takes its values from
+#[derive(Debug, Clone, Copy)]
+enum Source {
+    Header,
+    Field,
+    Data,
+}
+
+#[derive(Debug)]
+struct Column {
+    name: String,
+    source: Source,
+    kind: ColumnKind,
+}
+
+impl Column {
+    fn value<'a>(&self, record: &'a Value) -> Option<&'a Value> {
+        let value = match self.source {
+            Source::Header => record.get(&self.name),
+            Source::Field => record.get("data").and_then(|data| data.get(&self.name)),
+            Source::Data => record.get("data"),
+        };
+        value.filter(|value| !value.is_null())
+    }
+
+    fn parquet_type(&self) -> Result<Type, String> {
+        let (physical, converted) = match self.kind {
+            ColumnKind::Int64 => (PhysicalType::INT64, ConvertedType::NONE),
+            ColumnKind::Double => (PhysicalType::DOUBLE, ConvertedType::NONE),
+            ColumnKind::Boolean => (PhysicalType::BOOLEAN, ConvertedType::NONE),
+            ColumnKind::Text | ColumnKind::Json => (PhysicalType::BYTE_ARRAY, ConvertedType::UTF8),
+        };
+        let repetition = match self.source {
+            Source::Header => Repetition::REQUIRED,
+            _ => Repetition::OPTIONAL,
+        };
+        Type::primitive_type_builder(&self.name, physical)
+            .with_repetition(repetition)
+            .with_converted_type(converted)
+            .build()
+            .map_err(|e| format!("Invalid column {}: {}", self.name, e))
+    }
+}
+
+/// Picks the columns for records of the given schema, if all records share one
+fn columns(schema: Option<&Schema>) -> Vec<Column> {
+    let mut columns: Vec<Column> = HEADER_COLUMNS
+        .iter()
+        .map(|name| Column { name: name.to_string(), source: Source::Header, kind: ColumnKind::Int64 })
+        .collect();
+    match schema.map(|schema| &schema.root_type) {
+        Some(SchemaType::Object(fields)) => columns.extend(fields.iter().map(|field| Column {
+            name: field.name.clone(),
+            source: Source::Field,
+            kind: ColumnKind::for_type(&field.field_type),
+        })),
+        _ => columns.push(Column { name: "data".to_string(), source: Source::Data, kind: ColumnKind::Json }),
+    }
+    columns
+}
+
+/// Writes records to a Parquet file; `schema` is the schema shared by all
+/// records, if any
+pub fn write_parquet(path: &str, records: &[Value], schema: Option<&Schema>) -> Result<(), String> {
+    let columns = columns(schema);
+    let fields = columns
+        .iter()
+        .map(|column| column.parquet_type().map(Arc::new))
+        .collect::<Result<Vec<_>, _>>()?;
+    let message = Type::group_type_builder("record")
+        .with_fields(fields)
+        .build()
+        .map_err(|e| format!("Invalid Parquet schema: {}", e))?;
+
+    let file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    let properties = Arc::new(WriterProperties::builder().build());
+    let mut writer = SerializedFileWriter::new(file, Arc::new(message), properties)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+
+    let mut row_group = writer.next_row_group().map_err(|e| e.to_string())?;
+    for column in &columns {
+        let mut column_writer = row_group
+            .next_column()
+            .map_err(|e| e.to_string())?
+            .ok_or("Parquet writer has fewer columns than the schema")?;
+
+        let mut levels = Vec::with_capacity(records.len());
+        let mut present = Vec::with_capacity(records.len());
+        for record in records {
+            let value = column.value(record);
+            levels.push(value.is_some() as i16);
+            present.extend(value);
+        }
+        if matches!(column.source, Source::Header) && present.len() != records.len() {
+            return Err(format!("Some records lack the {} field", column.name));
+        }
+        let levels = match column.source {
+            Source::Header => None,
+            _ => Some(levels.as_slice()),
+        };
+
+        let written = match column.kind {
+            ColumnKind::Int64 => {
+                // UInt64 values above i64::MAX do not fit a signed column
+                let values: Vec<i64> = present
+                    .iter()
+                    .map(|v| v.as_i64().ok_or_else(|| mismatch(column, v)))
+                    .collect::<Result<_, _>>()?;
+                column_writer.typed::<Int64Type>().write_batch(&values, levels, None)
+            }
+            ColumnKind::Double => {
+                let values: Vec<f64> = present.iter().map(|v| v.as_f64().ok_or_else(|| mismatch(column, v))).collect::<Result<_, _>>()?;
+                column_writer.typed::<DoubleType>().write_batch(&values, levels, None)
+            }
+            ColumnKind::Boolean => {
+                let values: Vec<bool> = present.iter().map(|v| v.as_bool().ok_or_else(|| mismatch(column, v))).collect::<Result<_, _>>()?;
+                column_writer.typed::<BoolType>().write_batch(&values, levels, None)
+            }
+            ColumnKind::Text => {
+                let values: Vec<ByteArray> = present
+                    .iter()
+                    .map(|v| v.as_str().map(ByteArray::from).ok_or_else(|| mismatch(column, v)))
+                    .collect::<Result<_, _>>()?;
+                column_writer.typed::<ByteArrayType>().write_batch(&values, levels, None)
+            }
+            ColumnKind::Json => {
+                let values: Vec<ByteArray> = present.iter().map(|v| ByteArray::from(v.to_string().into_bytes())).collect();
+                column_writer.typed::<ByteArrayType>().write_batch(&values, levels, None)
+            }
+        };
+        written.map_err(|e| format!("Failed to write column {}: {}", column.name, e))?;
+        column_writer.close().map_err(|e| e.to_string())?;
+    }
+    row_group.close().map_err(|e| e.to_string())?;
+    writer.close().map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(())
+}
+
+fn mismatch(column: &Column, value: &Value) -> String {
+    format!("Value {} does not fit column {} ({:?})", value, column.name, column.kind)
+}